@@ -9,11 +9,9 @@
 //! The new event loop design makes this type of application very easy to make.
 
 use libremarkable::appctx::ApplicationContext;
-use libremarkable::framebuffer::common::{
-    color, display_temp, dither_mode, waveform_mode, DRAWING_QUANT_BIT,
-};
-use libremarkable::framebuffer::PartialRefreshMode;
+use libremarkable::framebuffer::common::color;
 use libremarkable::framebuffer::{FramebufferDraw, FramebufferRefresh};
+use libremarkable::framebuffer::{PartialRefreshMode, RefreshProfile};
 use libremarkable::input::{InputEvent, WacomEvent, WacomPen};
 
 fn main() {
@@ -70,16 +68,13 @@ fn main() {
                         radcolor.1,
                     );
 
-                    fb.partial_refresh(
+                    // The ink profile uses DU mode, which only supports black and
+                    // white colors. See the documentation of the different waveform
+                    // modes for more information
+                    fb.partial_refresh_with(
                         &region,
                         PartialRefreshMode::Async,
-                        // DU mode only supports black and white colors.
-                        // See the documentation of the different waveform modes
-                        // for more information
-                        waveform_mode::WAVEFORM_MODE_DU,
-                        display_temp::TEMP_USE_REMARKABLE_DRAW,
-                        dither_mode::EPDC_FLAG_EXP1,
-                        DRAWING_QUANT_BIT,
+                        &RefreshProfile::INK,
                         false,
                     );
                 }
@@ -0,0 +1,135 @@
+//! Several apps sharing the display through the experimental compositor:
+//!
+//! ```text
+//! compositor server &       # owns the display and input
+//! compositor sketch left &  # a drawing pad on each half of the screen
+//! compositor sketch right &
+//! compositor clock &        # a clock above both
+//! ```
+
+use std::time::Duration;
+
+use libremarkable::cgmath::Point2;
+use libremarkable::compositor::{Compositor, Layer, Surface, DEFAULT_SOCKET};
+use libremarkable::framebuffer::common::*;
+use libremarkable::framebuffer::core::Framebuffer;
+use libremarkable::framebuffer::{FramebufferDraw, FramebufferRefresh, PartialRefreshMode};
+use libremarkable::input::{GPIOEvent, InputEvent, PhysicalButton, WacomEvent};
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["server"] => server(),
+        ["clock"] => clock(),
+        ["sketch", side @ ("left" | "right")] => sketch(*side == "left"),
+        _ => eprintln!("Usage: compositor server|clock|sketch <left|right>"),
+    }
+}
+
+fn server() {
+    let mut fb = Framebuffer::new();
+    fb.clear();
+    fb.full_refresh(
+        waveform_mode::WAVEFORM_MODE_INIT,
+        display_temp::TEMP_USE_AMBIENT,
+        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        0,
+        true,
+    );
+    let mut compositor = Compositor::new(fb, DEFAULT_SOCKET).unwrap();
+    compositor.start_input().unwrap();
+    compositor.run();
+}
+
+fn refresh(fb: &Framebuffer, rect: &mxcfb_rect, waveform_mode: waveform_mode) {
+    fb.partial_refresh(
+        rect,
+        PartialRefreshMode::Async,
+        waveform_mode,
+        display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        0,
+        false,
+    );
+}
+
+fn clock() {
+    let rect = mxcfb_rect {
+        top: 20,
+        left: DISPLAYWIDTH as u32 - 300,
+        width: 280,
+        height: 90,
+    };
+    let mut surface = Surface::connect(DEFAULT_SOCKET, rect, Layer::Overlay).unwrap();
+    let fb = &mut surface.framebuffer;
+    loop {
+        fb.clear();
+        let time = chrono::Local::now().format("%H:%M").to_string();
+        fb.draw_rect(Point2 { x: 0, y: 0 }, (280, 90).into(), 2, color::BLACK);
+        fb.draw_text(
+            Point2 { x: 40.0, y: 70.0 },
+            &time,
+            64.0,
+            color::BLACK,
+            false,
+        );
+        let whole = mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: rect.width,
+            height: rect.height,
+        };
+        refresh(fb, &whole, waveform_mode::WAVEFORM_MODE_GC16_FAST);
+        std::thread::sleep(Duration::from_secs(30));
+    }
+}
+
+fn sketch(left: bool) {
+    let half = DISPLAYWIDTH as u32 / 2;
+    let rect = mxcfb_rect {
+        top: 0,
+        left: if left { 0 } else { half },
+        width: half,
+        height: DISPLAYHEIGHT as u32,
+    };
+    let mut surface = Surface::connect(DEFAULT_SOCKET, rect, Layer::Normal).unwrap();
+    let fb = &mut surface.framebuffer;
+    fb.draw_rect(
+        Point2 { x: 0, y: 0 },
+        (rect.width, rect.height).into(),
+        3,
+        color::BLACK,
+    );
+    let whole = mxcfb_rect {
+        top: 0,
+        left: 0,
+        width: rect.width,
+        height: rect.height,
+    };
+    refresh(fb, &whole, waveform_mode::WAVEFORM_MODE_GC16_FAST);
+
+    for event in surface.events.iter() {
+        match event {
+            InputEvent::WacomEvent {
+                event: WacomEvent::Draw { position, .. },
+            } => {
+                let dot = fb.fill_circle(position.cast().unwrap(), 3, color::BLACK);
+                refresh(fb, &dot, waveform_mode::WAVEFORM_MODE_DU);
+            }
+            // Closes the surface, uncovering what's below
+            InputEvent::GPIO {
+                event:
+                    GPIOEvent::Press {
+                        button: PhysicalButton::MIDDLE,
+                    },
+            } => break,
+            _ => {}
+        }
+    }
+}
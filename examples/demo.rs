@@ -3,7 +3,9 @@ use libremarkable::framebuffer::cgmath::EuclideanSpace;
 use libremarkable::framebuffer::common::*;
 use libremarkable::framebuffer::storage;
 use libremarkable::framebuffer::PartialRefreshMode;
-use libremarkable::framebuffer::{FramebufferDraw, FramebufferIO, FramebufferRefresh};
+use libremarkable::framebuffer::{
+    FramebufferDraw, FramebufferIO, FramebufferRefresh, ImageImportOptions,
+};
 use libremarkable::image::GenericImage;
 use libremarkable::input::{InputDevice, InputEvent};
 use libremarkable::ui_extensions::element::{
@@ -206,6 +208,10 @@ fn on_zoom_out(app: &mut appctx::ApplicationContext<'_>, _element: UIElementHand
             framebuffer.draw_image(
                 new_image.as_rgb8().unwrap(),
                 CANVAS_REGION.top_left().cast().unwrap(),
+                ImageImportOptions {
+                    dither: dither_algorithm::NONE,
+                    ..Default::default()
+                },
             );
             framebuffer.partial_refresh(
                 &CANVAS_REGION,
@@ -240,6 +246,10 @@ fn on_blur_canvas(app: &mut appctx::ApplicationContext<'_>, _element: UIElementH
             framebuffer.draw_image(
                 dynamic.as_rgb8().unwrap(),
                 CANVAS_REGION.top_left().cast().unwrap(),
+                ImageImportOptions {
+                    dither: dither_algorithm::NONE,
+                    ..Default::default()
+                },
             );
             framebuffer.partial_refresh(
                 &CANVAS_REGION,
@@ -427,6 +437,7 @@ fn draw_color_test_rgb(app: &mut appctx::ApplicationContext<'_>, _element: UIEle
     fb.draw_image(
         img_rgb565.as_rgb8().unwrap(),
         CANVAS_REGION.top_left().cast().unwrap(),
+        ImageImportOptions::default(),
     );
     fb.partial_refresh(
         &CANVAS_REGION,
@@ -491,9 +502,7 @@ fn loop_update_topbar(app: &mut appctx::ApplicationContext<'_>, millis: u64) {
 fn on_wacom_input(app: &mut appctx::ApplicationContext<'_>, input: input::WacomEvent) {
     match input {
         input::WacomEvent::Draw {
-            position,
-            pressure,
-            tilt: _,
+            position, pressure, ..
         } => {
             let mut wacom_stack = WACOM_HISTORY.lock().unwrap();
 
@@ -589,7 +598,7 @@ fn on_wacom_input(app: &mut appctx::ApplicationContext<'_>, input: input::WacomE
         input::WacomEvent::Hover {
             position: _,
             distance,
-            tilt: _,
+            ..
         } => {
             // If the pen is hovering, don't record its coordinates as the origin of the next line
             if distance > 1 {
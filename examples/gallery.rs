@@ -0,0 +1,519 @@
+//! A launcher for the other examples, plus a few pages to check widgets, input and
+//! refresh modes by hand on a device.
+//!
+//! The examples are expected next to this binary, as `cargo build --examples` puts
+//! them. While one is running, the middle button ends it and returns to the gallery.
+
+use libremarkable::appctx::ApplicationContext;
+use libremarkable::framebuffer::cgmath::{Point2, Vector2};
+use libremarkable::framebuffer::common::*;
+use libremarkable::framebuffer::{FramebufferDraw, FramebufferRefresh, PartialRefreshMode};
+use libremarkable::image;
+use libremarkable::input::{
+    GPIOEvent, InputDevice, InputEvent, MultitouchEvent, PhysicalButton, WacomEvent,
+};
+use libremarkable::stroke::scratch::ScratchOutDetector;
+use libremarkable::ui_extensions::element::{
+    UIConstraintRefresh, UIElement, UIElementHandle, UIElementWrapper,
+};
+
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// Examples that can be started, with a short description
+const EXAMPLES: &[(&str, &str)] = &[
+    ("demo", "Drawing, UI elements, storage and more"),
+    ("basic_draw", "Minimal pen drawing with eraser"),
+    ("input", "Prints raw input events to stderr"),
+    ("live", "Serves the screen over HTTP on :8000"),
+];
+
+/// Refresh modes compared on the refresh page
+const WAVEFORMS: &[(&str, waveform_mode)] = &[
+    ("DU", waveform_mode::WAVEFORM_MODE_DU),
+    ("GC16", waveform_mode::WAVEFORM_MODE_GC16),
+    ("GC16_FAST", waveform_mode::WAVEFORM_MODE_GC16_FAST),
+    ("GL16_FAST", waveform_mode::WAVEFORM_MODE_GL16_FAST),
+    ("DU4", waveform_mode::WAVEFORM_MODE_DU4),
+    ("REAGL", waveform_mode::WAVEFORM_MODE_REAGL),
+];
+
+/// Elements shown on the widgets page
+const WIDGETS: &[&str] = &["widgetText", "widgetRegion", "widgetImage", "widgetTaps"];
+
+static WIDGET_TAPS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Page {
+    Menu,
+    Refresh,
+    Input,
+    Widgets,
+}
+
+#[derive(Copy, Clone)]
+enum Target {
+    Example(&'static str),
+    Page(Page),
+    Back,
+}
+
+struct Gallery {
+    page: Page,
+    /// Tappable areas of the current page
+    targets: Vec<(mxcfb_rect, Target)>,
+    /// Which of the bars on the refresh page are inverted
+    inverted: Vec<bool>,
+    /// Where the last event is described on the input page
+    status: mxcfb_rect,
+}
+
+fn main() {
+    env_logger::init();
+
+    let mut app = ApplicationContext::default();
+    app.set_scratch_out_detector(Some(ScratchOutDetector::default()));
+    let mut gallery = Gallery {
+        page: Page::Menu,
+        targets: Vec::new(),
+        inverted: vec![false; WAVEFORMS.len()],
+        status: mxcfb_rect::invalid(),
+    };
+    gallery.show(&mut app, Page::Menu);
+
+    app.start_event_loop(true, true, true, move |app, event| {
+        gallery.on_event(app, event)
+    });
+}
+
+impl Gallery {
+    fn show(&mut self, app: &mut ApplicationContext<'_>, page: Page) {
+        if self.page == Page::Widgets {
+            remove_widgets(app);
+        }
+        self.page = page;
+        self.targets.clear();
+        app.clear(true);
+
+        match page {
+            Page::Menu => self.show_menu(app),
+            Page::Refresh => self.show_refresh(app),
+            Page::Input => self.show_input(app),
+            Page::Widgets => show_widgets(app),
+        }
+        if page != Page::Menu {
+            let back = text(app, (40.0, 100.0), 50.0, "< Back", true);
+            self.targets.push((back, Target::Back));
+        }
+    }
+
+    fn show_menu(&mut self, app: &mut ApplicationContext<'_>) {
+        text(app, (120.0, 150.0), 80.0, "libremarkable gallery", false);
+
+        let mut y = 300.0;
+        for &(name, description) in EXAMPLES {
+            let available = example_path(name).exists();
+            let label = match available {
+                true => name.to_owned(),
+                false => format!("{} (not built)", name),
+            };
+            let rect = text(app, (120.0, y), 55.0, &label, available);
+            text(app, (120.0, y + 65.0), 35.0, description, false);
+            if available {
+                self.targets.push((rect, Target::Example(name)));
+            }
+            y += 150.0;
+        }
+
+        y += 50.0;
+        for (label, page) in [
+            ("Refresh modes", Page::Refresh),
+            ("Input", Page::Input),
+            ("Widgets", Page::Widgets),
+        ] {
+            let rect = text(app, (120.0, y), 55.0, label, true);
+            self.targets.push((rect, Target::Page(page)));
+            y += 120.0;
+        }
+    }
+
+    fn show_refresh(&mut self, app: &mut ApplicationContext<'_>) {
+        text(
+            app,
+            (40.0, 220.0),
+            40.0,
+            "Tap a bar to invert it with that waveform",
+            false,
+        );
+        for (row, &(name, _)) in WAVEFORMS.iter().enumerate() {
+            text(
+                app,
+                (40.0, bar_rect(row).top as f32 + 70.0),
+                45.0,
+                name,
+                false,
+            );
+            self.draw_bar(app, row);
+        }
+    }
+
+    /// Draws the gray scale bar of `row` and refreshes it with the row's waveform
+    fn draw_bar(&mut self, app: &mut ApplicationContext<'_>, row: usize) {
+        let fb = app.get_framebuffer_ref();
+        let rect = bar_rect(row);
+        let steps = 8;
+        let step_width = rect.width / steps;
+        for step in 0..steps {
+            let level = match self.inverted[row] {
+                false => step * 255 / (steps - 1),
+                true => 255 - step * 255 / (steps - 1),
+            };
+            fb.fill_rect(
+                Point2 {
+                    x: (rect.left + step * step_width) as i32,
+                    y: rect.top as i32,
+                },
+                Vector2 {
+                    x: step_width,
+                    y: rect.height,
+                },
+                color::GRAY(level as u8),
+            );
+        }
+
+        let start = Instant::now();
+        fb.partial_refresh(
+            &rect,
+            PartialRefreshMode::Wait,
+            WAVEFORMS[row].1,
+            display_temp::TEMP_USE_AMBIENT,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        );
+        let elapsed = start.elapsed();
+
+        let label = Point2 {
+            x: 40,
+            y: rect.top as i32 + 90,
+        };
+        fb.fill_rect(label, Vector2 { x: 280, y: 50 }, color::WHITE);
+        text(
+            app,
+            (40.0, label.y as f32 + 40.0),
+            35.0,
+            &format!("{} ms", elapsed.as_millis()),
+            false,
+        );
+    }
+
+    fn show_input(&mut self, app: &mut ApplicationContext<'_>) {
+        text(
+            app,
+            (40.0, 220.0),
+            40.0,
+            "Draw, scratch out with the pen, touch or press buttons",
+            false,
+        );
+        self.status = mxcfb_rect {
+            top: 260,
+            left: 40,
+            width: 1324,
+            height: 60,
+        };
+    }
+
+    fn on_event(&mut self, app: &mut ApplicationContext<'_>, event: InputEvent) {
+        match event {
+            InputEvent::GPIO {
+                event:
+                    GPIOEvent::Press {
+                        button: PhysicalButton::MIDDLE,
+                    },
+            } if self.page != Page::Menu => {
+                self.show(app, Page::Menu);
+                return;
+            }
+            InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Press { finger },
+            } => {
+                let pos = finger.pos.cast().unwrap();
+                let target = self
+                    .targets
+                    .iter()
+                    .find(|(rect, _)| rect.contains_point(&pos))
+                    .map(|&(_, target)| target);
+                match target {
+                    Some(Target::Back) => return self.show(app, Page::Menu),
+                    Some(Target::Page(page)) => return self.show(app, page),
+                    Some(Target::Example(name)) => return self.launch(app, name),
+                    None => {}
+                }
+                if self.page == Page::Refresh {
+                    if let Some(row) =
+                        (0..WAVEFORMS.len()).find(|&row| bar_rect(row).contains_point(&pos))
+                    {
+                        self.inverted[row] = !self.inverted[row];
+                        self.draw_bar(app, row);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if self.page == Page::Input {
+            self.on_input_event(app, event);
+        }
+    }
+
+    fn on_input_event(&mut self, app: &mut ApplicationContext<'_>, event: InputEvent) {
+        let fb = app.get_framebuffer_ref();
+        let status = match event {
+            InputEvent::WacomEvent {
+                event:
+                    WacomEvent::Draw {
+                        position, pressure, ..
+                    },
+            } => {
+                if position.y > self.status.top as f32 + self.status.height as f32 {
+                    let rad = 1 + u32::from(pressure) * 6 / 4096;
+                    let rect = fb.fill_circle(position.cast().unwrap(), rad, color::BLACK);
+                    fb.partial_refresh(
+                        &rect,
+                        PartialRefreshMode::Async,
+                        waveform_mode::WAVEFORM_MODE_DU,
+                        display_temp::TEMP_USE_REMARKABLE_DRAW,
+                        dither_mode::EPDC_FLAG_EXP1,
+                        DRAWING_QUANT_BIT,
+                        false,
+                    );
+                }
+                return;
+            }
+            InputEvent::WacomEvent {
+                event: WacomEvent::Hover { .. } | WacomEvent::Unknown,
+            } => return,
+            InputEvent::WacomEvent {
+                event: WacomEvent::InstrumentChange { pen, state },
+            } => format!("Pen {:?}: {}", pen, state),
+            InputEvent::MultitouchEvent { event } => match event {
+                MultitouchEvent::Press { finger } | MultitouchEvent::Release { finger } => {
+                    let pos = finger.pos;
+                    let rect = fb.draw_circle(pos.cast().unwrap(), 30, color::BLACK);
+                    fb.partial_refresh(
+                        &rect.expand(1),
+                        PartialRefreshMode::Async,
+                        waveform_mode::WAVEFORM_MODE_DU,
+                        display_temp::TEMP_USE_REMARKABLE_DRAW,
+                        dither_mode::EPDC_FLAG_EXP1,
+                        0,
+                        false,
+                    );
+                    format!("Finger {}: {:?}", finger.tracking_id, event)
+                }
+                _ => return,
+            },
+            InputEvent::GPIO { event } => format!("Button: {:?}", event),
+            InputEvent::EraseRegion { position, size } => {
+                let rect = mxcfb_rect::from(position, size);
+                fb.fill_rect(position.cast().unwrap(), size, color::WHITE);
+                fb.partial_refresh(
+                    &rect,
+                    PartialRefreshMode::Async,
+                    waveform_mode::WAVEFORM_MODE_DU,
+                    display_temp::TEMP_USE_REMARKABLE_DRAW,
+                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                    0,
+                    false,
+                );
+                format!("Scratched out {}x{} at {:?}", size.x, size.y, position)
+            }
+            InputEvent::Recognition { .. }
+            | InputEvent::Keyboard { .. }
+            | InputEvent::Scroll { .. }
+            | InputEvent::Gesture { .. }
+            | InputEvent::SmoothedDraw { .. }
+            | InputEvent::RefreshHang { .. }
+            | InputEvent::GhostingCleared { .. }
+            | InputEvent::Notification { .. }
+            | InputEvent::Unknown {} => format!("{:?}", event),
+        };
+
+        fb.fill_rect(
+            self.status.top_left().cast().unwrap(),
+            self.status.size(),
+            color::WHITE,
+        );
+        text(
+            app,
+            (self.status.left as f32, self.status.top as f32 + 45.0),
+            35.0,
+            &status,
+            false,
+        );
+    }
+
+    /// Runs the example `name` until it exits or the middle button is pressed
+    fn launch(&mut self, app: &mut ApplicationContext<'_>, name: &str) {
+        // Leave the screen and pen/touch input to the example. Buttons stay active to
+        // notice the middle button, the example sees them as well.
+        app.deactivate_input_device(InputDevice::Wacom);
+        app.deactivate_input_device(InputDevice::Multitouch);
+        app.clear(true);
+
+        match Command::new(example_path(name)).spawn() {
+            Ok(child) => supervise(app, child),
+            Err(err) => eprintln!("Failed to start {}: {}", name, err),
+        }
+
+        // Events that were queued before the devices were stopped
+        while app.event_receiver().try_recv().is_ok() {}
+        app.activate_input_device(InputDevice::Wacom);
+        app.activate_input_device(InputDevice::Multitouch);
+        self.show(app, Page::Menu);
+    }
+}
+
+fn supervise(app: &mut ApplicationContext<'_>, mut child: Child) {
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                eprintln!("Example exited with {}", status);
+                return;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                eprintln!("Failed to wait for the example: {}", err);
+                return;
+            }
+        }
+        if let Ok(InputEvent::GPIO {
+            event: GPIOEvent::Press {
+                button: PhysicalButton::MIDDLE,
+            },
+        }) = app
+            .event_receiver()
+            .recv_timeout(Duration::from_millis(100))
+        {
+            // Not every example exits on its own
+            let _ = child.kill();
+            let _ = child.wait();
+            return;
+        }
+    }
+}
+
+fn show_widgets(app: &mut ApplicationContext<'_>) {
+    let widget = |position: (i32, i32), inner| UIElementWrapper {
+        position: position.into(),
+        refresh: UIConstraintRefresh::Refresh,
+        onclick: Some(on_widget_tap),
+        inner,
+        ..Default::default()
+    };
+    app.add_element(
+        WIDGETS[0],
+        widget(
+            (120, 350),
+            UIElement::Text {
+                text: "A text element, tap it".to_owned(),
+                scale: 50.0,
+                foreground: color::BLACK,
+                border_px: 3,
+            },
+        ),
+    );
+    app.add_element(
+        WIDGETS[1],
+        widget(
+            (120, 500),
+            UIElement::Region {
+                size: Vector2 { x: 400, y: 250 },
+                border_color: color::BLACK,
+                border_px: 5,
+            },
+        ),
+    );
+    app.add_element(
+        WIDGETS[2],
+        widget(
+            (700, 500),
+            UIElement::Image {
+                img: image::load_from_memory(include_bytes!("../assets/rustlang.png")).unwrap(),
+            },
+        ),
+    );
+    app.add_element(
+        WIDGETS[3],
+        UIElementWrapper {
+            position: (120, 1000).into(),
+            inner: taps_text(WIDGET_TAPS.load(Ordering::Relaxed)),
+            ..Default::default()
+        },
+    );
+    app.draw_elements();
+}
+
+fn on_widget_tap(app: &mut ApplicationContext<'_>, _: UIElementHandle) {
+    let taps = WIDGET_TAPS.fetch_add(1, Ordering::Relaxed) + 1;
+    if let Some(counter) = app.get_element_by_name(WIDGETS[3]) {
+        let mut counter = counter.write();
+        counter.inner = taps_text(taps);
+        counter.draw(app, &None);
+    }
+}
+
+fn taps_text(taps: u32) -> UIElement {
+    UIElement::Text {
+        text: format!("Widgets tapped {} times", taps),
+        scale: 40.0,
+        foreground: color::BLACK,
+        border_px: 0,
+    }
+}
+
+fn remove_widgets(app: &mut ApplicationContext<'_>) {
+    for name in WIDGETS {
+        if let Some(element) = app.get_element_by_name(name) {
+            if let Some(rect) = element.read().last_drawn_rect {
+                app.remove_active_region_at_point(rect.top as u16, rect.left as u16);
+            }
+        }
+    }
+    app.remove_elements();
+}
+
+/// Draws `text` with its baseline starting at `pos`, optionally framed like a button
+fn text(
+    app: &mut ApplicationContext<'_>,
+    pos: (f32, f32),
+    scale: f32,
+    text: &str,
+    framed: bool,
+) -> mxcfb_rect {
+    app.display_text(
+        pos.into(),
+        color::BLACK,
+        scale,
+        if framed { 3 } else { 0 },
+        12,
+        text,
+        UIConstraintRefresh::Refresh,
+    )
+}
+
+fn bar_rect(row: usize) -> mxcfb_rect {
+    mxcfb_rect {
+        top: 300 + row as u32 * 220,
+        left: 360,
+        width: 960,
+        height: 180,
+    }
+}
+
+fn example_path(name: &str) -> PathBuf {
+    let exe = std::env::current_exe().unwrap_or_default();
+    exe.with_file_name(name)
+}
@@ -22,9 +22,16 @@ fn main() {
 
     // Send all input events to input_rx
     let (input_tx, input_rx) = channel::<InputEvent>();
-    EvDevContext::new(InputDevice::GPIO, input_tx.clone()).start();
-    EvDevContext::new(InputDevice::Multitouch, input_tx.clone()).start();
-    EvDevContext::new(InputDevice::Wacom, input_tx).start();
+    for device in [
+        InputDevice::GPIO,
+        InputDevice::Multitouch,
+        InputDevice::Wacom,
+    ] {
+        if let Err(err) = EvDevContext::new(device, input_tx.clone()).start() {
+            eprintln!("Failed to open {:?}: {}", device, err);
+        }
+    }
+    drop(input_tx);
 
     // Output measurement of start time
     eprintln!("Opened input devices in {:?}", start.elapsed().unwrap());
@@ -1,29 +1,72 @@
+#[cfg(feature = "framebuffer-text-drawing")]
+use std::cell::Cell;
 #[cfg(feature = "hlua")]
 use std::cell::UnsafeCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 #[cfg(not(feature = "hlua"))]
 use std::marker::PhantomData;
 use std::ops::DerefMut;
+#[cfg(feature = "framebuffer-text-drawing")]
+use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::RwLock;
+use std::time::Instant;
 
 use aabb_quadtree::{geom, ItemId, QuadTree};
-#[cfg(feature = "hlua")]
+use evdev::Key;
 use log::warn;
 
+use crate::clipboard::{Clipboard, ClipboardError};
 use crate::framebuffer::cgmath;
 use crate::framebuffer::common::*;
 use crate::framebuffer::core;
+use crate::framebuffer::ghosting::GhostingPolicy;
+use crate::framebuffer::profile::RefreshHint;
+use crate::framebuffer::rotation::ScreenRotation;
+use crate::framebuffer::watchdog::{Recovery, RefreshWatchdog};
 use crate::framebuffer::FramebufferDraw;
 use crate::framebuffer::FramebufferRefresh;
 use crate::framebuffer::PartialRefreshMode;
 use crate::input::ev;
-use crate::input::MultitouchEvent;
-use crate::input::{InputDevice, InputEvent};
+use crate::input::gestures::GestureRecognizer;
+use crate::input::keyboard::{self, Keyboard, KeyboardLayout};
+use crate::input::multitouch::PalmRejection;
+use crate::input::scroll::ScrollRecognizer;
+use crate::input::smoothing::StrokeFilter;
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::input::GestureEvent;
+use crate::input::{InputDevice, InputError, InputEvent, KeyboardEvent};
+use crate::input::{MultitouchEvent, WacomEvent};
+use crate::notification::NotificationCenter;
+use crate::router::InputRouter;
+#[cfg(feature = "stroke")]
+use crate::ui_extensions::canvas::CanvasWidget;
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::ui_extensions::dialog::{self, DialogResult, DialogSpec, ShownDialog};
 use crate::ui_extensions::element::{
-    ActiveRegionFunction, ActiveRegionHandler, UIConstraintRefresh, UIElementHandle,
-    UIElementWrapper,
+    ActiveRegionFunction, ActiveRegionHandler, Control, ControlEvent, ControlKind,
+    UIConstraintRefresh, UIElement, UIElementHandle, UIElementWrapper,
 };
+use crate::ui_extensions::layout::LayoutNode;
+use crate::ui_extensions::list_view::{ListEvent, ListView};
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::ui_extensions::menu::{Menu, MenuBar, MenuItem, ShownMenu};
+use crate::ui_extensions::navigator::{Navigator, Screen, ScreenFunction};
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::ui_extensions::overlay::OverlayKind;
+use crate::ui_extensions::overlay::{OverlayId, OverlayManager};
+use crate::ui_extensions::scroll_view::ScrollView;
+use crate::ui_extensions::slider::{Slider, Stepper};
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::ui_extensions::text_input::{TextInput, TextInputEvent};
+
+#[cfg(feature = "stroke")]
+use crate::recognition::{Recognition, Recognizer};
+#[cfg(feature = "stroke")]
+use crate::stroke::scratch::ScratchOutDetector;
+#[cfg(feature = "stroke")]
+use crate::stroke::{Stroke, StrokeBuilder};
 
 #[cfg(feature = "hlua")]
 use hlua::Lua;
@@ -55,13 +98,51 @@ pub struct ApplicationContext<'a> {
 
     active_regions: QuadTree<ActiveRegionHandler>,
     ui_elements: HashMap<String, UIElementHandle>,
+    clipboard: Clipboard,
+    notifications: Option<NotificationCenter>,
+    scroll: Option<ScrollRecognizer>,
+    gestures: Option<GestureRecognizer>,
+    palm_rejection: Option<PalmRejection>,
+    stroke_filter: Option<StrokeFilter>,
+    router: InputRouter,
+    /// The name of the element keyboard input goes to
+    focus: Option<String>,
+    layout: Option<LayoutNode>,
+    overlays: OverlayManager,
+    /// Those of the overlays that are dialogs
+    #[cfg(feature = "framebuffer-text-drawing")]
+    dialogs: Vec<ShownDialog>,
+    /// The one of the overlays that is a menu
+    #[cfg(feature = "framebuffer-text-drawing")]
+    menu: Option<ShownMenu>,
+    /// Opened by long presses on the elements they are named after
+    #[cfg(feature = "framebuffer-text-drawing")]
+    context_menus: HashMap<String, Menu>,
+    navigator: Navigator,
+    /// The tracking id of the touch last checked for an active region under it
+    last_gesture_id: i32,
+
+    #[cfg(feature = "stroke")]
+    recognizer: Option<std::sync::Arc<std::sync::Mutex<Box<dyn Recognizer>>>>,
+    #[cfg(feature = "stroke")]
+    recognition_requests: u64,
+    #[cfg(feature = "stroke")]
+    scratch_out: Option<(ScratchOutDetector, StrokeBuilder)>,
 }
 
 impl Default for ApplicationContext<'static> {
     fn default() -> ApplicationContext<'static> {
-        let framebuffer = Box::new(core::Framebuffer::new());
+        ApplicationContext::with_framebuffer(core::Framebuffer::new())
+    }
+}
+
+impl ApplicationContext<'static> {
+    /// Creates the context around an already opened framebuffer
+    pub fn with_framebuffer(framebuffer: core::Framebuffer) -> ApplicationContext<'static> {
+        let framebuffer = Box::new(framebuffer);
         let yres = framebuffer.var_screen_info.yres;
         let xres = framebuffer.var_screen_info.xres;
+        let side = xres.max(yres);
 
         let (input_tx, input_rx) = std::sync::mpsc::channel();
         #[allow(unused_mut)] // Some features require this to be mut, some not
@@ -80,13 +161,31 @@ impl Default for ApplicationContext<'static> {
             input_rx,
             input_tx,
             ui_elements: HashMap::new(),
-            active_regions: QuadTree::default(geom::Rect::from_points(
-                &geom::Point { x: 0.0, y: 0.0 },
-                &geom::Point {
-                    x: xres as f32,
-                    y: yres as f32,
-                },
-            )),
+            clipboard: Clipboard::default(),
+            notifications: None,
+            scroll: None,
+            gestures: None,
+            palm_rejection: None,
+            stroke_filter: None,
+            router: InputRouter::new(),
+            focus: None,
+            layout: None,
+            overlays: OverlayManager::new(),
+            #[cfg(feature = "framebuffer-text-drawing")]
+            dialogs: Vec::new(),
+            #[cfg(feature = "framebuffer-text-drawing")]
+            menu: None,
+            #[cfg(feature = "framebuffer-text-drawing")]
+            context_menus: HashMap::new(),
+            navigator: Navigator::default(),
+            last_gesture_id: -1,
+            active_regions: active_regions(side),
+            #[cfg(feature = "stroke")]
+            recognizer: None,
+            #[cfg(feature = "stroke")]
+            recognition_requests: 0,
+            #[cfg(feature = "stroke")]
+            scratch_out: None,
         };
 
         // Enable all std lib
@@ -109,6 +208,7 @@ impl Default for ApplicationContext<'static> {
             nms.set("refresh", hlua::function6(luaext::lua_refresh));
 
             // Draws text with rusttype
+            #[cfg(feature = "framebuffer-text-drawing")]
             nms.set("draw_text", hlua::function5(luaext::lua_draw_text));
 
             // Sets the pixel to the u8 color value, does no refresh. Refresh done explicitly via calling `refresh`
@@ -117,6 +217,11 @@ impl Default for ApplicationContext<'static> {
 
         res
     }
+
+    /// Creates a context without a display, see `core::Framebuffer::headless`
+    pub fn headless(width: u32, height: u32) -> ApplicationContext<'static> {
+        ApplicationContext::with_framebuffer(core::Framebuffer::headless(width, height))
+    }
 }
 
 impl<'a> ApplicationContext<'a> {
@@ -141,161 +246,1521 @@ impl<'a> ApplicationContext<'a> {
         }
     }
 
-    pub fn get_dimensions(&self) -> (u32, u32) {
-        (self.yres, self.xres)
+    pub fn get_dimensions(&self) -> (u32, u32) {
+        (self.yres, self.xres)
+    }
+
+    #[cfg(feature = "hlua")]
+    pub fn execute_lua(&mut self, code: &str) {
+        let lua = self.get_lua_ref();
+        if let Err(e) = lua.execute::<hlua::AnyLuaValue>(code) {
+            warn!("Error in Lua Context: {:?}", e);
+        }
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    #[allow(clippy::too_many_arguments)]
+    pub fn display_text(
+        &mut self,
+        position: cgmath::Point2<f32>,
+        c: color,
+        scale: f32,
+        border_px: u32,
+        border_padding: u32,
+        text: &str,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let mut draw_area: mxcfb_rect = framebuffer.draw_text(position, text, scale, c, false);
+
+        // Draw the border if border_px is set to a non-default value
+        if border_px > 0 {
+            draw_area = draw_area.expand(border_padding);
+            framebuffer.draw_rect(
+                draw_area.top_left().cast().unwrap(),
+                draw_area.size(),
+                border_px,
+                c,
+            );
+        }
+
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh(&draw_area, PartialRefreshMode::Async)
+            }
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area.expand(border_px)
+    }
+
+    pub fn display_rect(
+        &mut self,
+        position: cgmath::Point2<i32>,
+        size: cgmath::Vector2<u32>,
+        border_px: u32,
+        border_color: color,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+
+        framebuffer.draw_rect(position, size, border_px, border_color);
+        let draw_area = mxcfb_rect::from(position.cast().unwrap(), size);
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh(&draw_area, PartialRefreshMode::Async)
+            }
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn display_text_input(
+        &mut self,
+        input: &mut TextInput,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = input.draw(framebuffer, position);
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh(&draw_area, PartialRefreshMode::Async)
+            }
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    pub fn display_scroll_view(
+        &mut self,
+        view: &mut ScrollView,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = mxcfb_rect::from(position.cast().unwrap(), view.size);
+        framebuffer.fill_rect(position, view.size, color::WHITE);
+        for (name, content_position) in view.children() {
+            let element = match self.ui_elements.get(name) {
+                Some(element) => element.clone(),
+                None => continue,
+            };
+            let visible = {
+                let mut child = element.write();
+                // The view was cleared already and is refreshed as a whole
+                if let (Some(old), Some(_)) = (child.last_drawn_rect.take(), child.onclick) {
+                    self.remove_active_region_at_point(old.top as u16, old.left as u16);
+                }
+                child.position = view.child_position(draw_area, *content_position);
+                child.clip = Some(draw_area);
+                child.refresh = UIConstraintRefresh::NoRefresh;
+                child.natural_size().is_none_or(|size| {
+                    let rect = mxcfb_rect {
+                        left: child.position.x.max(0) as u32,
+                        top: child.position.y.max(0) as u32,
+                        width: size.x,
+                        height: size.y,
+                    };
+                    rect.intersection(&draw_area).is_some()
+                })
+            };
+            // Those scrolled out of view are skipped
+            if visible {
+                self.draw_element(name);
+            }
+        }
+        view.drawn(draw_area);
+
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh(&draw_area, PartialRefreshMode::Async)
+            }
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    pub fn display_list_view(
+        &mut self,
+        list: &mut ListView,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = mxcfb_rect::from(position.cast().unwrap(), list.size());
+        framebuffer.fill_rect(position, list.size(), color::WHITE);
+        for row in list.rows_in_view(draw_area) {
+            row.draw(self, &None);
+        }
+        list.drawn(draw_area);
+
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh(&draw_area, PartialRefreshMode::Async)
+            }
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    /// Draws `control`, refreshing it with DU to show presses and checks quickly
+    pub fn display_control(
+        &mut self,
+        control: &Control,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = control.draw(framebuffer, position);
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
+                .refresh_auto(
+                    &draw_area,
+                    RefreshHint::UiMonochrome,
+                    PartialRefreshMode::Async,
+                ),
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    /// Draws `slider`, refreshing it with DU to follow drags quickly
+    pub fn display_slider(
+        &mut self,
+        slider: &mut Slider,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = slider.draw(framebuffer, position);
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
+                .refresh_auto(
+                    &draw_area,
+                    RefreshHint::UiMonochrome,
+                    PartialRefreshMode::Async,
+                ),
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    /// Draws `bar`, with the title of the menu it has open inverted
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn display_menu_bar(
+        &mut self,
+        bar: &MenuBar,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = bar.draw(framebuffer, position);
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
+                .refresh_auto(
+                    &draw_area,
+                    RefreshHint::UiMonochrome,
+                    PartialRefreshMode::Async,
+                ),
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    /// Draws `stepper`, refreshing it with DU to show presses quickly
+    pub fn display_stepper(
+        &mut self,
+        stepper: &mut Stepper,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = stepper.draw(framebuffer, position);
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
+                .refresh_auto(
+                    &draw_area,
+                    RefreshHint::UiMonochrome,
+                    PartialRefreshMode::Async,
+                ),
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    /// Draws `canvas` with all its strokes
+    #[cfg(feature = "stroke")]
+    pub fn display_canvas(
+        &mut self,
+        canvas: &mut CanvasWidget,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = canvas.draw(framebuffer, position);
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh_auto(&draw_area, RefreshHint::Image, PartialRefreshMode::Async)
+            }
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    #[cfg(feature = "image")]
+    pub fn display_image(
+        &mut self,
+        img: &image::DynamicImage,
+        position: cgmath::Point2<i32>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let options = crate::framebuffer::ImageImportOptions::default();
+        let draw_area = match img {
+            image::DynamicImage::ImageRgb8(ref rgb) => {
+                framebuffer.draw_image(rgb, position, options)
+            }
+            other => framebuffer.draw_image(&other.to_rgb8(), position, options),
+        };
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh(&draw_area, PartialRefreshMode::Async)
+            }
+            _ => return draw_area,
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        draw_area
+    }
+
+    /// The clipboard shared with other apps, `Clipboard::default` unless set
+    pub fn clipboard(&self) -> &Clipboard {
+        &self.clipboard
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: Clipboard) {
+        self.clipboard = clipboard;
+    }
+
+    /// Reads the keyboard at `path`, e.g. `/dev/input/event3`, passing what `keyboard`
+    /// makes of its keys to the callback of the event loop as `InputEvent::Keyboard`
+    pub fn start_keyboard(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        keyboard: Keyboard,
+    ) -> Result<(), InputError> {
+        keyboard::start(path, keyboard, self.input_tx.clone())
+    }
+
+    /// Reads every keyboard attached, like the Type Folio, typing with `layout`. Returns
+    /// the devices read, which may be none.
+    pub fn start_keyboards(&self, layout: KeyboardLayout) -> Vec<std::path::PathBuf> {
+        keyboard::find()
+            .into_iter()
+            .filter(
+                |path| match self.start_keyboard(path, Keyboard::new(layout.clone())) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        warn!("Unable to read the keyboard at {}: {}", path.display(), err);
+                        false
+                    }
+                },
+            )
+            .collect()
+    }
+
+    /// Accepts notifications from background services at `socket`. Every one posted or
+    /// withdrawn is passed to the callback of the event loop as an
+    /// `InputEvent::Notification`; answer them with `notifications()`.
+    pub fn listen_for_notifications(
+        &mut self,
+        socket: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<()> {
+        let input_tx = self.input_tx.clone();
+        let center = NotificationCenter::listen(socket, move |event| {
+            // The receiver is gone if the context was dropped in the meantime
+            let _ = input_tx.send(InputEvent::Notification { event });
+        })?;
+        self.notifications = Some(center);
+        Ok(())
+    }
+
+    /// Delivers events to the callback of the event loop, like the input devices
+    #[cfg(feature = "simulator")]
+    pub(crate) fn input_sender(&self) -> std::sync::mpsc::Sender<InputEvent> {
+        self.input_tx.clone()
+    }
+
+    /// Where notifications are received, if `listen_for_notifications` was called
+    pub fn notifications(&self) -> Option<&NotificationCenter> {
+        self.notifications.as_ref()
+    }
+
+    /// Copies `rect` of the screen to the clipboard
+    pub fn copy_region(&mut self, rect: mxcfb_rect) -> Result<(), ClipboardError> {
+        self.clipboard.copy_region(&self.framebuffer, rect)
+    }
+
+    /// Draws the image on the clipboard with its top left at `position`. Returns the
+    /// area it covers, or `None` if there is no image on the clipboard.
+    pub fn paste_region(
+        &mut self,
+        position: cgmath::Point2<u32>,
+        refresh: UIConstraintRefresh,
+    ) -> Result<Option<mxcfb_rect>, ClipboardError> {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = match self.clipboard.paste_region(framebuffer, position)? {
+            Some(draw_area) => draw_area,
+            None => return Ok(None),
+        };
+        let marker = match refresh {
+            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => {
+                framebuffer.refresh(&draw_area, PartialRefreshMode::Async)
+            }
+            _ => return Ok(Some(draw_area)),
+        };
+
+        if let UIConstraintRefresh::RefreshAndWait = refresh {
+            framebuffer.wait_refresh_complete(marker);
+        }
+        Ok(Some(draw_area))
+    }
+
+    pub fn add_element(
+        &mut self,
+        name: &str,
+        element: UIElementWrapper,
+    ) -> Option<UIElementHandle> {
+        if self.ui_elements.contains_key(name) {
+            return None;
+        }
+
+        let elem = UIElementHandle::new(element);
+        self.ui_elements.insert(name.to_owned(), elem.clone());
+        Some(elem)
+    }
+
+    pub fn remove_element(&mut self, name: &str) -> bool {
+        if self.focus.as_deref() == Some(name) {
+            self.focus = None;
+        }
+        self.ui_elements.remove(name).is_some()
+    }
+
+    pub fn remove_elements(&mut self) {
+        self.focus = None;
+        self.ui_elements.clear();
+    }
+
+    /// The whole display, as it is rotated
+    pub fn screen_rect(&self) -> mxcfb_rect {
+        let (height, width) = self.get_dimensions();
+        mxcfb_rect {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        }
+    }
+
+    /// Shows `spec` over everything else until one of its buttons is tapped, keeping
+    /// input from the app meanwhile. The index of the button goes to the `on_result`
+    /// handler of `spec`, and completes the `DialogResult` returned.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn show_dialog(&mut self, spec: DialogSpec) -> DialogResult {
+        let framebuffer = self.get_framebuffer_ref();
+        let layout = spec.layout(framebuffer, self.screen_rect());
+        let result = DialogResult::default();
+        let on_result = spec.on_result;
+        let (rect, buttons) = (layout.rect, layout.buttons.clone());
+        let overlay = self
+            .overlays
+            .show(framebuffer, OverlayKind::Modal, rect, move |fb, _| {
+                spec.draw(fb, &layout)
+            });
+        self.dialogs.push(ShownDialog {
+            overlay,
+            rect,
+            buttons,
+            on_result,
+            result: result.clone(),
+        });
+        result
+    }
+
+    /// Shows `text` near the bottom of the display for `duration`
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn show_toast(&mut self, text: &str, duration: std::time::Duration) -> OverlayId {
+        const SIZE: f32 = 32.0;
+        let framebuffer = self.get_framebuffer_ref();
+        let rect = dialog::toast_rect(framebuffer, text, SIZE, self.screen_rect());
+        let text = text.to_owned();
+        self.overlays.show(
+            framebuffer,
+            OverlayKind::Toast { duration },
+            rect,
+            move |fb, rect| dialog::draw_toast(fb, &text, SIZE, rect),
+        )
+    }
+
+    /// Takes the dialog, menu or toast `id` off the display before its time, restoring what
+    /// it covered. A dialog closed this way has no result. Returns whether it was
+    /// shown.
+    pub fn dismiss_overlay(&mut self, id: OverlayId) -> bool {
+        #[cfg(feature = "framebuffer-text-drawing")]
+        self.dialogs.retain(|dialog| dialog.overlay != id);
+        #[cfg(feature = "framebuffer-text-drawing")]
+        if self.menu.as_ref().is_some_and(|shown| shown.overlay == id) {
+            self.close_menus();
+            return true;
+        }
+        let framebuffer = self.get_framebuffer_ref();
+        self.overlays.dismiss(framebuffer, id)
+    }
+
+    /// Shows `menu` next to `anchor`, e.g. below the button opening it, placed to stay
+    /// on the display. Any other menu closes. It closes itself when one of its items is
+    /// picked, or on a tap anywhere else.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn show_menu(&mut self, menu: Menu, anchor: mxcfb_rect) -> OverlayId {
+        self.close_menus();
+        self.show_menu_from(menu, anchor, None)
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn show_menu_from(
+        &mut self,
+        menu: Menu,
+        anchor: mxcfb_rect,
+        bar: Option<(String, usize)>,
+    ) -> OverlayId {
+        let framebuffer = self.get_framebuffer_ref();
+        let layout = menu.layout(framebuffer, anchor, self.screen_rect());
+        let highlighted = Rc::new(Cell::new(None));
+        let (shown, drawn, lit) = (menu.clone(), layout.clone(), highlighted.clone());
+        let overlay =
+            self.overlays
+                .show(framebuffer, OverlayKind::Menu, layout.rect, move |fb, _| {
+                    shown.draw(fb, &drawn, lit.get())
+                });
+        self.menu = Some(ShownMenu {
+            overlay,
+            menu,
+            layout,
+            highlighted,
+            bar,
+        });
+        overlay
+    }
+
+    /// Opens the menu `index` of the `UIElement::MenuBar` element `name` below its
+    /// title, closing any other menu. Returns whether there is such a menu.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn open_menu(&mut self, name: &str, index: usize) -> bool {
+        self.close_menus();
+        let handle = match self.ui_elements.get(name) {
+            Some(handle) => handle.clone(),
+            None => return false,
+        };
+        let (menu, anchor) = {
+            let mut element = handle.write();
+            let rect = match element.last_drawn_rect {
+                Some(rect) => rect,
+                None => return false,
+            };
+            match element.inner {
+                UIElement::MenuBar { ref mut bar } if index < bar.menus.len() => {
+                    let framebuffer = self.get_framebuffer_ref();
+                    let anchor = bar.title_rects(framebuffer, rect)[index];
+                    bar.set_open(Some(index));
+                    (bar.menus[index].1.clone(), anchor)
+                }
+                _ => return false,
+            }
+        };
+        self.draw_element(name);
+        self.show_menu_from(menu, anchor, Some((name.to_owned(), index)));
+        true
+    }
+
+    /// The menu shown, if any, to be taken off with `dismiss_overlay`
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn shown_menu(&self) -> Option<OverlayId> {
+        self.menu.as_ref().map(|shown| shown.overlay)
+    }
+
+    /// Closes the menu shown, if any, restoring what it covered
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn close_menus(&mut self) {
+        let shown = match self.menu.take() {
+            Some(shown) => shown,
+            None => return,
+        };
+        let framebuffer = self.get_framebuffer_ref();
+        self.overlays.dismiss(framebuffer, shown.overlay);
+        if let Some((name, _)) = shown.bar {
+            if let Some(handle) = self.ui_elements.get(&name) {
+                if let UIElement::MenuBar { ref mut bar } = handle.write().inner {
+                    bar.set_open(None);
+                }
+            }
+            self.draw_element(&name);
+        }
+    }
+
+    /// Opens `menu` where the element `name` is long pressed, or stops doing so with
+    /// `None`. The long presses are those of the `GestureRecognizer` set with
+    /// `set_gesture_recognizer`.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn set_context_menu(&mut self, name: &str, menu: Option<Menu>) {
+        match menu {
+            Some(menu) => self.context_menus.insert(name.to_owned(), menu),
+            None => self.context_menus.remove(name),
+        };
+    }
+
+    /// Opens the context menu of the element at `pos`. Returns whether there is one.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn open_context_menu(&mut self, pos: cgmath::Point2<u32>) -> bool {
+        let menu = self.context_menus.iter().find_map(|(name, menu)| {
+            let rect = self.ui_elements.get(name)?.read().last_drawn_rect?;
+            rect.contains_point(&pos).then(|| menu.clone())
+        });
+        match menu {
+            Some(menu) => {
+                let anchor = mxcfb_rect::from(pos, (0, 0).into());
+                self.show_menu(menu, anchor);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The `UIElement::MenuBar` element with a title at `pos`, and the index of its menu
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn menu_title_at(&mut self, pos: cgmath::Point2<u32>) -> Option<(String, usize)> {
+        let framebuffer = self.get_framebuffer_ref();
+        self.ui_elements.iter().find_map(|(name, handle)| {
+            let element = handle.read();
+            match (&element.inner, element.last_drawn_rect) {
+                (UIElement::MenuBar { bar }, Some(rect)) if rect.contains_point(&pos) => bar
+                    .title_rects(framebuffer, rect)
+                    .iter()
+                    .position(|title| title.contains_point(&pos))
+                    .map(|index| (name.clone(), index)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Highlights the item `index` of the menu shown, or none
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn highlight_menu_item(&mut self, index: Option<usize>) {
+        let shown = match self.menu {
+            Some(ref shown) if shown.highlighted.get() != index => shown,
+            _ => return,
+        };
+        shown.highlighted.set(index);
+        let overlay = shown.overlay;
+        let framebuffer = self.get_framebuffer_ref();
+        self.overlays.redraw(framebuffer, overlay);
+    }
+
+    /// Closes the menu shown and calls the handler of its item `index`, unless that is
+    /// disabled
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn pick_menu_item(&mut self, index: usize) {
+        let item = self
+            .menu
+            .as_ref()
+            .and_then(|shown| shown.menu.items.get(index).cloned());
+        if let Some(MenuItem::Action {
+            enabled: true,
+            on_select,
+            ..
+        }) = item
+        {
+            self.close_menus();
+            if let Some(on_select) = on_select {
+                on_select(self);
+            }
+        }
+    }
+
+    /// Opens context menus on long presses and the menus of menu bars on taps, and
+    /// passes input to the menu shown, which keeps it from the app. Returns whether
+    /// `event` was for the menus.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn handle_menus(&mut self, event: &InputEvent) -> bool {
+        match event {
+            InputEvent::Gesture {
+                event: GestureEvent::LongPress { position },
+            } => match position.cast() {
+                Some(pos) => self.open_context_menu(pos),
+                None => false,
+            },
+            InputEvent::Keyboard { event } if self.menu.is_some() => {
+                self.navigate_menu(event);
+                true
+            }
+            InputEvent::MultitouchEvent { event } => self.touch_menus(event),
+            _ => false,
+        }
+    }
+
+    #[cfg(not(feature = "framebuffer-text-drawing"))]
+    fn handle_menus(&mut self, _: &InputEvent) -> bool {
+        false
+    }
+
+    /// Highlights the item under the finger, and picks it once the finger lifts there.
+    /// A press anywhere else closes the menu, or opens another one of its menu bar.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn touch_menus(&mut self, event: &MultitouchEvent) -> bool {
+        let pos = match event.finger() {
+            Some(finger) => finger.pos.cast().unwrap(),
+            None => return self.menu.is_some(),
+        };
+        let shown = match self.menu {
+            Some(ref shown) => shown,
+            None => {
+                let title = match event {
+                    MultitouchEvent::Press { .. } => self.menu_title_at(pos),
+                    _ => None,
+                };
+                return title.is_some_and(|(name, index)| self.open_menu(&name, index));
+            }
+        };
+        let item = shown
+            .layout
+            .item_at(pos)
+            .filter(|&index| shown.menu.items[index].is_enabled());
+        match (event, item) {
+            (MultitouchEvent::Press { .. }, _) if !shown.layout.rect.contains_point(&pos) => {
+                let bar = shown.bar.clone();
+                let title = self.menu_title_at(pos);
+                self.close_menus();
+                // Tapping the title of the menu shown closes it too
+                if let Some((name, index)) = title.filter(|title| Some(title) != bar.as_ref()) {
+                    self.open_menu(&name, index);
+                }
+            }
+            (MultitouchEvent::Release { .. }, Some(index)) => self.pick_menu_item(index),
+            (MultitouchEvent::Release { .. }, None) => self.highlight_menu_item(None),
+            _ => self.highlight_menu_item(item),
+        }
+        true
+    }
+
+    /// Moves the highlight of the menu shown with the arrow keys, left and right going
+    /// to the next menu of its menu bar. Enter and space pick the item highlighted,
+    /// escape closes the menu.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn navigate_menu(&mut self, event: &KeyboardEvent) {
+        let key = match *event {
+            KeyboardEvent::Press { key, .. } => Key::new(key),
+            _ => return,
+        };
+        let (next, highlighted, bar) = match self.menu {
+            Some(ref shown) => {
+                let highlighted = shown.highlighted.get();
+                let down = shown.menu.next_enabled(highlighted, true);
+                let up = shown.menu.next_enabled(highlighted, false);
+                ((up, down), highlighted, shown.bar.clone())
+            }
+            None => return,
+        };
+        match key {
+            Key::KEY_UP => self.highlight_menu_item(next.0),
+            Key::KEY_DOWN => self.highlight_menu_item(next.1),
+            Key::KEY_LEFT | Key::KEY_RIGHT => {
+                let (name, index) = match bar {
+                    Some(bar) => bar,
+                    None => return,
+                };
+                let count = match self.ui_elements.get(&name).map(|h| h.read()) {
+                    Some(element) => match element.inner {
+                        UIElement::MenuBar { ref bar } => bar.menus.len(),
+                        _ => return,
+                    },
+                    None => return,
+                };
+                let index = match key {
+                    Key::KEY_RIGHT => (index + 1) % count,
+                    _ => (index + count - 1) % count,
+                };
+                if self.open_menu(&name, index) {
+                    let first = self
+                        .menu
+                        .as_ref()
+                        .and_then(|m| m.menu.next_enabled(None, true));
+                    self.highlight_menu_item(first);
+                }
+            }
+            Key::KEY_ENTER | Key::KEY_SPACE => {
+                if let Some(index) = highlighted {
+                    self.pick_menu_item(index);
+                }
+            }
+            Key::KEY_ESC => self.close_menus(),
+            _ => {}
+        }
+    }
+
+    /// Where the app is among its screens, see `push_screen` and `switch_tab`
+    pub fn navigator(&self) -> &Navigator {
+        &self.navigator
+    }
+
+    /// Shows a new screen `name` over the one shown, which is kept as it is to go back
+    /// to with `pop_screen`. `build` adds the elements and regions of the new screen.
+    pub fn push_screen(&mut self, name: &str, build: ScreenFunction) {
+        let below = self.take_screen();
+        self.navigator.below.push(below);
+        self.navigator.current = Some(name.to_owned());
+        build(self);
+        self.redraw_screen();
+    }
+
+    /// Goes back to the screen below the one shown, dropping that along with its
+    /// elements and regions. Returns false if there is none.
+    pub fn pop_screen(&mut self) -> bool {
+        let below = match self.navigator.below.pop() {
+            Some(below) => below,
+            None => return false,
+        };
+        self.take_screen();
+        self.restore_screen(below);
+        self.redraw_screen();
+        true
+    }
+
+    /// Shows the tab `name` as it was left, with the screens pushed on it, keeping
+    /// those of the tab shown to switch back to. `build` adds the elements and regions
+    /// of the tab when it is shown for the first time. The screens shown before any tab
+    /// was switched to are dropped.
+    pub fn switch_tab(&mut self, name: &str, build: ScreenFunction) {
+        if self.navigator.tab.as_deref() == Some(name) {
+            return;
+        }
+        let mut screens = std::mem::take(&mut self.navigator.below);
+        screens.push(self.take_screen());
+        if let Some(tab) = self.navigator.tab.replace(name.to_owned()) {
+            self.navigator.tabs.insert(tab, screens);
+        }
+        match self.navigator.tabs.remove(name) {
+            Some(mut screens) => {
+                let top = screens.pop().expect("tabs keep at least one screen");
+                self.navigator.below = screens;
+                self.restore_screen(top);
+            }
+            None => {
+                self.navigator.current = Some(name.to_owned());
+                build(self);
+            }
+        }
+        self.redraw_screen();
+    }
+
+    /// Takes away what the screen shown is made of, leaving an empty one
+    fn take_screen(&mut self) -> Screen {
+        #[cfg(feature = "framebuffer-text-drawing")]
+        self.close_menus();
+        let side = self.xres.max(self.yres);
+        Screen {
+            name: self.navigator.current.take(),
+            ui_elements: std::mem::take(&mut self.ui_elements),
+            active_regions: std::mem::replace(&mut self.active_regions, active_regions(side)),
+            router: std::mem::replace(&mut self.router, InputRouter::new()),
+            focus: self.focus.take(),
+            layout: self.layout.take(),
+            #[cfg(feature = "framebuffer-text-drawing")]
+            context_menus: std::mem::take(&mut self.context_menus),
+        }
+    }
+
+    fn restore_screen(&mut self, screen: Screen) {
+        self.navigator.current = screen.name;
+        self.ui_elements = screen.ui_elements;
+        self.active_regions = screen.active_regions;
+        self.router = screen.router;
+        self.focus = screen.focus;
+        self.layout = screen.layout;
+        #[cfg(feature = "framebuffer-text-drawing")]
+        {
+            self.context_menus = screen.context_menus;
+        }
+        // The display may have turned while it was away
+        self.relayout();
+    }
+
+    /// Draws the screen shown on a blank display and refreshes that as a whole, with
+    /// the overlays still shown over it
+    fn redraw_screen(&mut self) {
+        let framebuffer = self.get_framebuffer_ref();
+        framebuffer.clear();
+        let children = self.scroll_children();
+        let elements: Vec<_> = self
+            .ui_elements
+            .iter()
+            .filter(|(name, _)| !children.contains(*name))
+            .map(|(_, element)| element.clone())
+            .collect();
+        for element in elements {
+            let handler = element.read().onclick.map(|handler| ActiveRegionHandler {
+                handler,
+                element: element.clone(),
+            });
+            // One refresh of everything below instead of one per element
+            let mut wrapper = element.write();
+            let refresh = std::mem::replace(&mut wrapper.refresh, UIConstraintRefresh::NoRefresh);
+            wrapper.draw(self, &handler);
+            wrapper.refresh = refresh;
+        }
+        self.overlays.repaint(framebuffer);
+        framebuffer.refresh_auto(
+            &self.screen_rect(),
+            RefreshHint::FullQuality,
+            PartialRefreshMode::Async,
+        );
+    }
+
+    /// Dismisses the toasts shown long enough at `now`
+    fn expire_toasts(&mut self, now: Instant) {
+        let framebuffer = self.get_framebuffer_ref();
+        self.overlays.expire(framebuffer, now);
+    }
+
+    /// Closes the dialogs tapped, and keeps input from the app while one is shown.
+    /// Returns whether `event` was for the overlays.
+    fn handle_overlays(&mut self, event: &InputEvent) -> bool {
+        if self.overlays.is_empty() {
+            return false;
+        }
+        self.expire_toasts(Instant::now());
+        let (pos, released) = match event {
+            InputEvent::MultitouchEvent { event } => match (event, event.finger()) {
+                (MultitouchEvent::Release { .. }, Some(finger)) => (finger.pos.cast(), true),
+                (_, Some(finger)) => (finger.pos.cast(), false),
+                _ => return false,
+            },
+            InputEvent::WacomEvent {
+                event: WacomEvent::Draw { position, .. } | WacomEvent::Hover { position, .. },
+            } => (position.cast(), false),
+            _ => return false,
+        };
+        let pos = match pos {
+            Some(pos) => pos,
+            None => return self.overlays.blocks(cgmath::Point2 { x: 0, y: 0 }),
+        };
+        #[cfg(feature = "framebuffer-text-drawing")]
+        if let Some(at) = self
+            .dialogs
+            .iter()
+            .position(|dialog| dialog.rect.contains_point(&pos))
+        {
+            let tapped = released
+                .then(|| {
+                    let buttons = &self.dialogs[at].buttons;
+                    buttons
+                        .iter()
+                        .position(|button| button.contains_point(&pos))
+                })
+                .flatten();
+            if let Some(index) = tapped {
+                let dialog = self.dialogs.remove(at);
+                let framebuffer = self.get_framebuffer_ref();
+                self.overlays.dismiss(framebuffer, dialog.overlay);
+                dialog.result.complete(index);
+                if let Some(on_result) = dialog.on_result {
+                    on_result(self, index);
+                }
+            }
+            return true;
+        }
+        #[cfg(not(feature = "framebuffer-text-drawing"))]
+        let _ = released;
+        self.overlays.blocks(pos)
+    }
+
+    pub fn draw_element(&mut self, name: &str) -> bool {
+        let appref = self.upgrade_ref();
+        match self.ui_elements.get(name) {
+            None => false,
+            Some(element) => {
+                let handler = element.read().onclick.map(|handler| ActiveRegionHandler {
+                    handler,
+                    element: element.clone(),
+                });
+                element.write().draw(appref, &handler);
+                true
+            }
+        }
+    }
+
+    /// Types `event` into the `UIElement::TextInput` element `name`, redrawing it and
+    /// calling its `on_change` or `on_submit` handler. Returns whether the event did
+    /// anything, which it doesn't for other elements.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn type_into(&mut self, name: &str, event: &KeyboardEvent) -> bool {
+        let element = match self.ui_elements.get(name) {
+            Some(element) => element.clone(),
+            None => return false,
+        };
+        let (result, handler) = match element.write().inner {
+            UIElement::TextInput { ref mut input } => {
+                let result = input.handle_key(event);
+                let handler = match result {
+                    Some(TextInputEvent::Changed) => input.on_change,
+                    Some(TextInputEvent::Submitted) => input.on_submit,
+                    _ => None,
+                };
+                (result, handler)
+            }
+            _ => return false,
+        };
+        if result.is_none() {
+            return false;
+        }
+        if result != Some(TextInputEvent::Submitted) {
+            self.draw_element(name);
+        }
+        if let Some(handler) = handler {
+            handler(self, element);
+        }
+        true
+    }
+
+    /// Calls `f` with the `UIElement::Canvas` element `name`, e.g. to undo or load
+    /// strokes, then draws what it changed again and calls its `on_change` handler if
+    /// its strokes changed. Returns what `f` returns, or `None` for other elements.
+    #[cfg(feature = "stroke")]
+    pub fn update_canvas<R>(
+        &mut self,
+        name: &str,
+        f: impl FnOnce(&mut CanvasWidget) -> R,
+    ) -> Option<R> {
+        let element = self.ui_elements.get(name)?.clone();
+        let result = match element.write().inner {
+            UIElement::Canvas { ref mut canvas } => f(canvas),
+            _ => return None,
+        };
+        self.redraw_canvas(element);
+        Some(result)
+    }
+
+    /// Draws the strokes of the canvas `element` again where they changed, and calls
+    /// its `on_change` handler if they did
+    #[cfg(feature = "stroke")]
+    fn redraw_canvas(&mut self, element: UIElementHandle) {
+        let (drawn, on_change) = {
+            let mut element = element.write();
+            let position = element.position;
+            let shown = element.last_drawn_rect.is_some();
+            match element.inner {
+                UIElement::Canvas { ref mut canvas } => {
+                    let drawn = match shown {
+                        true => canvas.draw_damage(self.get_framebuffer_ref(), position),
+                        false => None,
+                    };
+                    // Quickly while the pen is moving, e.g. showing a selection dragged
+                    let hint = match canvas.is_capturing() {
+                        true => RefreshHint::Ink,
+                        false => RefreshHint::Image,
+                    };
+                    let changed = canvas.on_change.filter(|_| canvas.take_changed());
+                    (drawn.zip(Some(hint)), changed)
+                }
+                _ => return,
+            }
+        };
+        if let Some((rect, hint)) = drawn {
+            self.get_framebuffer_ref()
+                .refresh_auto(&rect, hint, PartialRefreshMode::Async);
+        }
+        if let Some(on_change) = on_change {
+            on_change(self, element);
+        }
+    }
+
+    /// Passes pen input to every canvas, refreshing the ink it draws as quickly as
+    /// possible, then what it finished or erased properly
+    #[cfg(feature = "stroke")]
+    fn update_canvases(&mut self, event: &InputEvent) {
+        let event = match event {
+            InputEvent::WacomEvent { event } => event,
+            _ => return,
+        };
+        let names: Vec<_> = self.ui_elements.keys().cloned().collect();
+        for name in names {
+            let handle = match self.ui_elements.get(&name) {
+                Some(handle) => handle.clone(),
+                None => continue,
+            };
+            let inked = {
+                let mut element = handle.write();
+                let rect = match element.last_drawn_rect {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+                match element.inner {
+                    UIElement::Canvas { ref mut canvas } => {
+                        canvas.handle_wacom(self.get_framebuffer_ref(), event, rect)
+                    }
+                    _ => continue,
+                }
+            };
+            if let Some(rect) = inked {
+                self.get_framebuffer_ref().refresh_auto(
+                    &rect,
+                    RefreshHint::Ink,
+                    PartialRefreshMode::Async,
+                );
+            }
+            self.redraw_canvas(handle);
+        }
+    }
+
+    /// Places the elements on the screen with `layout`, see `ui_extensions::layout`,
+    /// from now on, or leaves them where they are
+    pub fn set_layout(&mut self, layout: Option<LayoutNode>) {
+        self.layout = layout;
+        self.relayout();
+    }
+
+    /// Places the elements again, e.g. after their content changed. They still need to
+    /// be drawn.
+    pub fn relayout(&mut self) {
+        let layout = match self.layout {
+            Some(ref layout) => layout,
+            None => return,
+        };
+        let screen = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: self.xres,
+            height: self.yres,
+        };
+        let rects = layout.compute(screen, |name| {
+            self.ui_elements
+                .get(name)
+                .and_then(|element| element.read().natural_size())
+        });
+        for (name, rect) in rects {
+            if let Some(element) = self.ui_elements.get(&name) {
+                element.write().place(rect);
+            }
+        }
+    }
+
+    /// Shows the element `child` at `position` on the content of the
+    /// `UIElement::ScrollView` element `view`. It is drawn along with the view from now
+    /// on, without refreshing on its own. Returns false if there is no such view.
+    pub fn add_scroll_child(
+        &mut self,
+        view: &str,
+        child: &str,
+        position: cgmath::Point2<i32>,
+    ) -> bool {
+        match self.ui_elements.get(view) {
+            Some(element) => match element.write().inner {
+                UIElement::ScrollView { ref mut view } => {
+                    view.add_child(child, position);
+                    true
+                }
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// The names of the elements shown by scroll views
+    fn scroll_children(&self) -> HashSet<String> {
+        let mut children = HashSet::new();
+        for element in self.ui_elements.values() {
+            if let UIElement::ScrollView { ref view } = element.read().inner {
+                children.extend(view.children().iter().map(|(name, _)| name.clone()));
+            }
+        }
+        children
+    }
+
+    /// Calls `f` with every scroll view, redrawing those it returns true for
+    fn update_scroll_views(&mut self, mut f: impl FnMut(&mut ScrollView) -> bool) {
+        let names: Vec<_> = self.ui_elements.keys().cloned().collect();
+        for name in names {
+            let redraw = match self.ui_elements[&name].write().inner {
+                UIElement::ScrollView { ref mut view } => f(view),
+                _ => false,
+            };
+            if redraw {
+                self.draw_element(&name);
+            }
+        }
+    }
+
+    /// When a scroll view coasting has to move next
+    fn next_scroll_view_momentum(&self) -> Option<Instant> {
+        self.ui_elements
+            .values()
+            .filter_map(|element| match element.read().inner {
+                UIElement::ScrollView { ref view } => view.next_momentum(),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Calls `f` with every list view, redrawing those it returns true for, then
+    /// calls the handlers of the rows selected
+    fn update_list_views(&mut self, mut f: impl FnMut(&mut ListView) -> bool) {
+        let names: Vec<_> = self.ui_elements.keys().cloned().collect();
+        for name in names {
+            // An earlier handler may have removed it
+            let handle = match self.ui_elements.get(&name) {
+                Some(handle) => handle.clone(),
+                None => continue,
+            };
+            let (redraw, selected) = match handle.write().inner {
+                UIElement::ListView { ref mut list } => {
+                    let redraw = f(list);
+                    let selected: Vec<_> = list
+                        .take_events()
+                        .into_iter()
+                        .filter_map(|event| match event {
+                            ListEvent::Tap(index) => list.on_select.map(|h| (h, index)),
+                            ListEvent::LongPress(index) => list.on_long_press.map(|h| (h, index)),
+                        })
+                        .collect();
+                    (redraw, selected)
+                }
+                _ => continue,
+            };
+            if redraw {
+                self.draw_element(&name);
+            }
+            for (handler, index) in selected {
+                handler(self, handle.clone(), index);
+            }
+        }
+    }
+
+    /// When a list view has to coast or report a long press next
+    fn next_list_view_wake(&self) -> Option<Instant> {
+        self.ui_elements
+            .values()
+            .filter_map(|element| match element.read().inner {
+                UIElement::ListView { ref list } => [list.next_momentum(), list.next_long_press()]
+                    .into_iter()
+                    .flatten()
+                    .min(),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Passes a touch to every `UIElement::Control`, redrawing those it pressed or
+    /// released and calling their handlers
+    fn update_controls(&mut self, event: &MultitouchEvent) {
+        let names: Vec<_> = self.ui_elements.keys().cloned().collect();
+        for name in names {
+            let handle = match self.ui_elements.get(&name) {
+                Some(handle) => handle.clone(),
+                None => continue,
+            };
+            let (update, control) = {
+                let mut element = handle.write();
+                let rect = match element.last_drawn_rect {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+                match element.inner {
+                    UIElement::Control { ref mut control } => {
+                        match control.handle_multitouch(event, rect) {
+                            Some(update) => (update, control.clone()),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                }
+            };
+            self.draw_element(&name);
+            match update {
+                ControlEvent::Activated if control.kind == ControlKind::Button => {
+                    if let Some(on_press) = control.on_press {
+                        on_press(self, handle);
+                    }
+                }
+                ControlEvent::Changed(checked) => {
+                    if let (true, Some(group)) = (checked, &control.group) {
+                        self.uncheck_radio_group(group, &name);
+                    }
+                    if let Some(on_change) = control.on_change {
+                        on_change(self, handle, checked);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Passes pen and touch input to every slider and stepper, redrawing those it
+    /// moved and calling the handlers of those whose value changed
+    fn update_value_elements(&mut self, event: &InputEvent) {
+        let now = Instant::now();
+        let names: Vec<_> = self.ui_elements.keys().cloned().collect();
+        for name in names {
+            let handle = match self.ui_elements.get(&name) {
+                Some(handle) => handle.clone(),
+                None => continue,
+            };
+            let (redraw, shown) = {
+                let mut element = handle.write();
+                let rect = match element.last_drawn_rect {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+                match (&mut element.inner, event) {
+                    (UIElement::Slider { slider }, InputEvent::MultitouchEvent { event }) => {
+                        (slider.handle_multitouch(event, rect, now), slider.shown())
+                    }
+                    (UIElement::Slider { slider }, InputEvent::WacomEvent { event }) => {
+                        (slider.handle_wacom(event, rect, now), slider.shown())
+                    }
+                    (UIElement::Stepper { stepper }, InputEvent::MultitouchEvent { event }) => {
+                        (stepper.handle_multitouch(event, rect), stepper.shown())
+                    }
+                    _ => continue,
+                }
+            };
+            if !redraw {
+                continue;
+            }
+            self.draw_element(&name);
+            let changed = match handle.read().inner {
+                UIElement::Slider { ref slider } => slider.on_change.zip(Some(slider.value())),
+                UIElement::Stepper { ref stepper } => stepper.on_change.zip(Some(stepper.value())),
+                _ => None,
+            };
+            if let Some((on_change, value)) = changed.filter(|(_, value)| *value != shown) {
+                on_change(self, handle, value);
+            }
+        }
     }
 
-    #[cfg(feature = "hlua")]
-    pub fn execute_lua(&mut self, code: &str) {
-        let lua = self.get_lua_ref();
-        if let Err(e) = lua.execute::<hlua::AnyLuaValue>(code) {
-            warn!("Error in Lua Context: {:?}", e);
+    /// Unchecks the radio buttons of `group` other than `checked`
+    fn uncheck_radio_group(&mut self, group: &str, checked: &str) {
+        let names: Vec<_> = self.ui_elements.keys().cloned().collect();
+        for name in names.iter().filter(|name| *name != checked) {
+            let handle = match self.ui_elements.get(name) {
+                Some(handle) => handle.clone(),
+                None => continue,
+            };
+            let on_change = match handle.write().inner {
+                UIElement::Control { ref mut control }
+                    if control.kind == ControlKind::Radio
+                        && control.checked
+                        && control.group.as_deref() == Some(group) =>
+                {
+                    control.checked = false;
+                    control.on_change
+                }
+                _ => continue,
+            };
+            self.draw_element(name);
+            if let Some(on_change) = on_change {
+                on_change(self, handle, false);
+            }
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn display_text(
-        &mut self,
-        position: cgmath::Point2<f32>,
-        c: color,
-        scale: f32,
-        border_px: u32,
-        border_padding: u32,
-        text: &str,
-        refresh: UIConstraintRefresh,
-    ) -> mxcfb_rect {
-        let framebuffer = self.get_framebuffer_ref();
-        let mut draw_area: mxcfb_rect = framebuffer.draw_text(position, text, scale, c, false);
+    /// The name of the element with the focus
+    pub fn focus(&self) -> Option<&str> {
+        self.focus.as_deref()
+    }
 
-        // Draw the border if border_px is set to a non-default value
-        if border_px > 0 {
-            draw_area = draw_area.expand(border_padding);
-            framebuffer.draw_rect(
-                draw_area.top_left().cast().unwrap(),
-                draw_area.size(),
-                border_px,
-                c,
-            );
+    /// Moves the focus to the focusable element `name`, or nowhere, calling the
+    /// `onblur` handler of the element losing it and the `onfocus` handler of the one
+    /// getting it. Returns false, leaving the focus as it is, if there is no such
+    /// element.
+    pub fn set_focus(&mut self, name: Option<&str>) -> bool {
+        if let Some(name) = name {
+            match self.ui_elements.get(name) {
+                Some(element) if element.read().is_focusable() => {}
+                _ => return false,
+            }
         }
-
-        let marker = match refresh {
-            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
-                ),
-            _ => return draw_area,
-        };
-
-        if let UIConstraintRefresh::RefreshAndWait = refresh {
-            framebuffer.wait_refresh_complete(marker);
+        if self.focus.as_deref() == name {
+            return true;
         }
-        draw_area.expand(border_px)
-    }
-
-    pub fn display_rect(
-        &mut self,
-        position: cgmath::Point2<i32>,
-        size: cgmath::Vector2<u32>,
-        border_px: u32,
-        border_color: color,
-        refresh: UIConstraintRefresh,
-    ) -> mxcfb_rect {
-        let framebuffer = self.get_framebuffer_ref();
-
-        framebuffer.draw_rect(position, size, border_px, border_color);
-        let draw_area = mxcfb_rect::from(position.cast().unwrap(), size);
-        let marker = match refresh {
-            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
-                ),
-            _ => return draw_area,
-        };
-
-        if let UIConstraintRefresh::RefreshAndWait = refresh {
-            framebuffer.wait_refresh_complete(marker);
+        if let Some(old) = self.focus.take() {
+            self.notify_focus(&old, false);
         }
-        draw_area
+        if let Some(name) = name {
+            self.focus = Some(name.to_owned());
+            self.notify_focus(name, true);
+        }
+        true
     }
 
-    #[cfg(feature = "image")]
-    pub fn display_image(
-        &mut self,
-        img: &image::DynamicImage,
-        position: cgmath::Point2<i32>,
-        refresh: UIConstraintRefresh,
-    ) -> mxcfb_rect {
-        let framebuffer = self.get_framebuffer_ref();
-        let draw_area = match img {
-            image::DynamicImage::ImageRgb8(ref rgb) => framebuffer.draw_image(rgb, position),
-            other => framebuffer.draw_image(&other.to_rgb8(), position),
+    fn notify_focus(&mut self, name: &str, focused: bool) {
+        let element = match self.ui_elements.get(name) {
+            Some(element) => element.clone(),
+            None => return,
         };
-        let marker = match refresh {
-            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
-                ),
-            _ => return draw_area,
+        #[cfg(feature = "framebuffer-text-drawing")]
+        if let UIElement::TextInput { ref mut input } = element.write().inner {
+            input.set_focused(focused);
+        }
+        #[cfg(feature = "framebuffer-text-drawing")]
+        if matches!(element.read().inner, UIElement::TextInput { .. }) {
+            self.draw_element(name);
+        }
+        let handler = match focused {
+            true => element.read().onfocus,
+            false => element.read().onblur,
         };
-
-        if let UIConstraintRefresh::RefreshAndWait = refresh {
-            framebuffer.wait_refresh_complete(marker);
+        if let Some(handler) = handler {
+            handler(self, element);
         }
-        draw_area
     }
 
-    pub fn add_element(
-        &mut self,
-        name: &str,
-        element: UIElementWrapper,
-    ) -> Option<UIElementHandle> {
-        if self.ui_elements.contains_key(name) {
-            return None;
-        }
+    /// The focusable elements in tab order, from the top left to the bottom right
+    fn tab_order(&self) -> Vec<String> {
+        let mut elements: Vec<_> = self
+            .ui_elements
+            .iter()
+            .filter_map(|(name, element)| {
+                let element = element.read();
+                element
+                    .is_focusable()
+                    .then(|| (element.position.y, element.position.x, name.clone()))
+            })
+            .collect();
+        elements.sort_unstable();
+        elements.into_iter().map(|(_, _, name)| name).collect()
+    }
 
-        let elem = UIElementHandle::new(element);
-        self.ui_elements.insert(name.to_owned(), elem.clone());
-        Some(elem)
+    /// Moves the focus to the next focusable element in tab order, or the previous one
+    /// unless `forward`, wrapping around. Returns whether there is one.
+    pub fn focus_next(&mut self, forward: bool) -> bool {
+        let order = self.tab_order();
+        if order.is_empty() {
+            return false;
+        }
+        let current = self
+            .focus
+            .as_ref()
+            .and_then(|focus| order.iter().position(|name| name == focus));
+        let next = match (current, forward) {
+            (Some(i), true) => (i + 1) % order.len(),
+            (Some(i), false) => (i + order.len() - 1) % order.len(),
+            (None, true) => 0,
+            (None, false) => order.len() - 1,
+        };
+        self.set_focus(Some(&order[next]))
     }
 
-    pub fn remove_element(&mut self, name: &str) -> bool {
-        self.ui_elements.remove(name).is_some()
+    /// Moves the focus to the focusable element drawn at `pos`, if there is one, placing
+    /// the cursor of a `TextInput` there. Returns whether there is one.
+    fn focus_at(&mut self, pos: cgmath::Point2<u32>) -> bool {
+        let name = self.ui_elements.iter().find_map(|(name, element)| {
+            let element = element.read();
+            let rect = element.last_drawn_rect?;
+            (element.is_focusable() && rect.contains_point(&pos)).then(|| name.clone())
+        });
+        let name = match name {
+            Some(name) => name,
+            None => return false,
+        };
+        self.set_focus(Some(&name));
+        #[cfg(feature = "framebuffer-text-drawing")]
+        {
+            let framebuffer = self.get_framebuffer_ref();
+            let handle = self.ui_elements[&name].clone();
+            let element = &mut *handle.write();
+            if let (UIElement::TextInput { ref mut input }, Some(rect)) =
+                (&mut element.inner, element.last_drawn_rect)
+            {
+                input.place_cursor(framebuffer, pos.x as f32 - rect.left as f32);
+            }
+        }
+        #[cfg(feature = "framebuffer-text-drawing")]
+        self.draw_element(&name);
+        true
     }
 
-    pub fn remove_elements(&mut self) {
-        self.ui_elements.clear();
+    /// Passes `event` to the element with the focus, typing it into a `TextInput`. Tab
+    /// and shift+tab move the focus along the tab order. Returns whether the event did
+    /// anything. The event loop does this for hardware keyboards, the events of an
+    /// `OnScreenKeyboard` can be passed here.
+    pub fn type_into_focus(&mut self, event: &KeyboardEvent) -> bool {
+        if let KeyboardEvent::Press { key, modifiers, .. } = *event {
+            if key == Key::KEY_TAB.code() && self.focus.is_some() {
+                return self.focus_next(!modifiers.shift);
+            }
+        }
+        match self.focus.clone() {
+            #[cfg(feature = "framebuffer-text-drawing")]
+            Some(name) => self.type_into(&name, event),
+            _ => false,
+        }
     }
 
-    pub fn draw_element(&mut self, name: &str) -> bool {
-        let appref = self.upgrade_ref();
-        match self.ui_elements.get(name) {
-            None => false,
-            Some(element) => {
-                let handler = element.read().onclick.map(|handler| ActiveRegionHandler {
-                    handler,
-                    element: element.clone(),
-                });
-                element.write().draw(appref, &handler);
-                true
+    /// Moves the focus on taps and types keyboard input into the focused element.
+    /// Returns whether `event` was used up.
+    fn handle_focus(&mut self, event: &InputEvent) -> bool {
+        match event {
+            InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Press { finger },
+            } => {
+                self.focus_at(finger.pos.cast().unwrap());
+                false
             }
+            InputEvent::Keyboard { event } => self.type_into_focus(event),
+            _ => false,
         }
     }
 
@@ -305,7 +1770,14 @@ impl<'a> ApplicationContext<'a> {
 
     pub fn draw_elements(&mut self) {
         start_bench!(stopwatch, draw_elements);
-        let mut elems: Vec<_> = self.ui_elements.values().cloned().collect();
+        // Scroll views draw their children themselves
+        let children = self.scroll_children();
+        let mut elems: Vec<_> = self
+            .ui_elements
+            .iter()
+            .filter(|(name, _)| !children.contains(*name))
+            .map(|(_, element)| element.clone())
+            .collect();
 
         for element in &mut elems {
             let handler = element.read().onclick.map(|handler| ActiveRegionHandler {
@@ -343,10 +1815,7 @@ impl<'a> ApplicationContext<'a> {
 
     pub fn clear(&mut self, deep: bool) {
         let framebuffer = self.get_framebuffer_ref();
-        let (yres, xres) = (
-            framebuffer.var_screen_info.yres,
-            framebuffer.var_screen_info.xres,
-        );
+        let (yres, xres) = (self.yres, self.xres);
         framebuffer.clear();
 
         if deep {
@@ -420,9 +1889,20 @@ impl<'a> ApplicationContext<'a> {
     /// Returns true if the device is now enabled. If it was enabled prior
     /// to calling this function, this function will return `true`.
     pub fn activate_input_device(&mut self, t: InputDevice) -> bool {
-        // Return true if already enabled
+        match self.try_activate_input_device(t) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Failed to activate {:?}: {}", t, err);
+                false
+            }
+        }
+    }
+
+    /// Like `activate_input_device`, returning why the device couldn't be enabled
+    pub fn try_activate_input_device(&mut self, t: InputDevice) -> Result<(), InputError> {
+        // Nothing to do if already enabled
         if self.is_input_device_active(t) {
-            return true;
+            return Ok(());
         }
 
         // Now we know it isn't active, let's create and spawn
@@ -431,17 +1911,13 @@ impl<'a> ApplicationContext<'a> {
             InputDevice::Wacom => self.wacom_ctx.write().unwrap(),
             InputDevice::Multitouch => self.touch_ctx.write().unwrap(),
             InputDevice::GPIO => self.button_ctx.write().unwrap(),
-            _ => return false,
+            _ => return Err(InputError::Unknown(t)),
         };
 
-        *dev = Some(ev::EvDevContext::new(t, self.input_tx.clone()));
-        match dev.as_mut() {
-            Some(ref mut device) => {
-                device.start();
-                true
-            }
-            None => false,
-        }
+        let mut device = ev::EvDevContext::new(t, self.input_tx.clone());
+        device.start()?;
+        *dev = Some(device);
+        Ok(())
     }
 
     /// Returns true if the given `InputDevice` is active, as in
@@ -460,6 +1936,222 @@ impl<'a> ApplicationContext<'a> {
         }
     }
 
+    /// Sets the engine used by `recognize`
+    #[cfg(feature = "stroke")]
+    pub fn set_recognizer<R: Recognizer + 'static>(&mut self, recognizer: R) {
+        self.recognizer = Some(std::sync::Arc::new(std::sync::Mutex::new(Box::new(
+            recognizer,
+        ))));
+    }
+
+    /// Recognizes the handwriting in `strokes` in the background. The result is
+    /// delivered to the event loop as `InputEvent::Recognition`, carrying the returned
+    /// request id. Returns `None` if no recognizer is set.
+    #[cfg(feature = "stroke")]
+    pub fn recognize(&mut self, strokes: Vec<Stroke>) -> Option<u64> {
+        let recognizer = self.recognizer.clone()?;
+        self.recognition_requests += 1;
+        let request = self.recognition_requests;
+        let input_tx = self.input_tx.clone();
+        std::thread::spawn(move || {
+            // Requests are handled one at a time, in order
+            let result = recognizer.lock().unwrap().recognize(&strokes);
+            let result = Recognition { request, result };
+            // The receiver is gone if the context was dropped in the meantime
+            let _ = input_tx.send(InputEvent::Recognition { result });
+        });
+        Some(request)
+    }
+
+    /// Enables detection of the scratch-out gesture with `detector`, or disables it.
+    /// After the pen event finishing a scratch-out, the event loop passes an
+    /// `InputEvent::EraseRegion` with the scratched out area to the callback.
+    #[cfg(feature = "stroke")]
+    pub fn set_scratch_out_detector(&mut self, detector: Option<ScratchOutDetector>) {
+        self.scratch_out =
+            detector.map(|detector| (detector, StrokeBuilder::new(1.0, color::BLACK)));
+    }
+
+    /// Follows the pen for scratch-out detection
+    #[cfg(feature = "stroke")]
+    fn detect_scratch_out(&mut self, event: &InputEvent) -> Option<InputEvent> {
+        let (detector, builder) = self.scratch_out.as_mut()?;
+        let stroke = match event {
+            InputEvent::WacomEvent { event } => builder.handle_wacom_event(event)?,
+            _ => return None,
+        };
+        let rect = detector.detect(&stroke)?;
+        Some(InputEvent::EraseRegion {
+            position: rect.top_left(),
+            size: rect.size(),
+        })
+    }
+
+    /// Gives up on refreshes not completing within `timeout`, attempting `recovery`.
+    /// Every such hang is passed to the callback of the event loop as
+    /// `InputEvent::RefreshHang`.
+    pub fn watch_refreshes(&mut self, timeout: std::time::Duration, recovery: Recovery) {
+        let input_tx = self.input_tx.clone();
+        let watchdog = RefreshWatchdog::new(timeout)
+            .recovery(recovery)
+            .on_hang(move |hang| {
+                let _ = input_tx.send(InputEvent::RefreshHang {
+                    marker: hang.marker,
+                    waited: hang.waited,
+                    recovered: hang.recovered,
+                });
+            });
+        self.get_framebuffer_ref()
+            .set_refresh_watchdog(Some(watchdog));
+    }
+
+    /// Clears ghosting with full refreshes as `policy` says, or stops. Each of them is
+    /// passed to the event loop's callback as `InputEvent::GhostingCleared`, instead
+    /// of the policy's own callback, to redraw what shouldn't stay after the flash.
+    pub fn set_ghosting_policy(&mut self, policy: Option<GhostingPolicy>) {
+        let input_tx = self.input_tx.clone();
+        let policy = policy.map(|policy| {
+            policy.on_full_refresh(move |marker| {
+                let _ = input_tx.send(InputEvent::GhostingCleared { marker });
+            })
+        });
+        self.get_framebuffer_ref().set_ghosting_policy(policy);
+    }
+
+    /// Turns the display and the input from the wacom digitizer and the touchscreen,
+    /// see `framebuffer::rotation`. `get_dimensions` is the size of the turned display
+    /// from now on.
+    pub fn set_screen_rotation(&mut self, rotation: ScreenRotation) {
+        let framebuffer = self.get_framebuffer_ref();
+        framebuffer.set_rotation(rotation);
+        let size = framebuffer.screen_size();
+        self.xres = size.x;
+        self.yres = size.y;
+        self.relayout();
+    }
+
+    pub fn screen_rotation(&self) -> ScreenRotation {
+        self.framebuffer.rotation()
+    }
+
+    /// `event` with its positions on the turned display, see `set_screen_rotation`
+    fn rotate_event(&self, event: InputEvent) -> InputEvent {
+        let rotation = self.framebuffer.rotation();
+        if rotation == ScreenRotation::Rot0 {
+            return event;
+        }
+        let panel = self.framebuffer.panel_size();
+        let rotate = |pos: cgmath::Point2<f32>| rotation.from_panel(pos, panel);
+        match event {
+            InputEvent::WacomEvent { mut event } => {
+                match &mut event {
+                    WacomEvent::Hover { position, .. } | WacomEvent::Draw { position, .. } => {
+                        *position = rotate(*position);
+                    }
+                    _ => {}
+                }
+                InputEvent::WacomEvent { event }
+            }
+            InputEvent::MultitouchEvent { mut event } => {
+                match &mut event {
+                    MultitouchEvent::Press { finger }
+                    | MultitouchEvent::Release { finger }
+                    | MultitouchEvent::Move { finger } => {
+                        finger.pos = rotate(finger.pos.cast().unwrap()).cast().unwrap();
+                    }
+                    MultitouchEvent::Unknown => {}
+                }
+                InputEvent::MultitouchEvent { event }
+            }
+            event => event,
+        }
+    }
+
+    /// Enables two finger scrolling with `recognizer`, or disables it. After the
+    /// multitouch events of a two finger drag, the event loop passes the resulting
+    /// `InputEvent::Scroll`s to the callback, followed by those of its momentum.
+    pub fn set_scroll_recognizer(&mut self, recognizer: Option<ScrollRecognizer>) {
+        self.scroll = recognizer;
+    }
+
+    /// Follows the fingers for scrolling
+    fn recognize_scroll(&mut self, event: &InputEvent) -> Vec<InputEvent> {
+        match (self.scroll.as_mut(), event) {
+            (Some(scroll), InputEvent::MultitouchEvent { event }) => scroll
+                .handle(event, std::time::Instant::now())
+                .into_iter()
+                .map(|event| InputEvent::Scroll { event })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The regions pen and touch input is passed to instead of the callback of the
+    /// event loop, see `router`
+    pub fn input_router(&mut self) -> &mut InputRouter {
+        &mut self.router
+    }
+
+    /// Enables smoothing the pen's strokes with `filter`, or disables it. Every
+    /// `WacomEvent::Draw` passed to the callback is followed by an
+    /// `InputEvent::SmoothedDraw` with both its own and the smoothed position.
+    pub fn set_stroke_filter(&mut self, filter: Option<StrokeFilter>) {
+        self.stroke_filter = filter;
+    }
+
+    /// Follows the pen for smoothing
+    fn smooth_stroke(&mut self, event: &InputEvent) -> Option<InputEvent> {
+        match (self.stroke_filter.as_mut(), event) {
+            (Some(filter), InputEvent::WacomEvent { event }) => filter
+                .filter(event, std::time::Instant::now())
+                .map(|point| InputEvent::SmoothedDraw { point }),
+            _ => None,
+        }
+    }
+
+    /// Enables ignoring touches while the pen is near the display with `palm_rejection`,
+    /// or disables it. Rejected multitouch events don't reach active regions, the
+    /// recognizers or the callback.
+    pub fn set_palm_rejection(&mut self, palm_rejection: Option<PalmRejection>) {
+        self.palm_rejection = palm_rejection;
+    }
+
+    /// Follows the pen, returning whether `event` is a touch to ignore
+    fn reject_palm(&mut self, event: &InputEvent) -> bool {
+        let palm = match self.palm_rejection.as_mut() {
+            Some(palm) => palm,
+            None => return false,
+        };
+        let now = std::time::Instant::now();
+        match event {
+            InputEvent::WacomEvent { event } => {
+                palm.pen(event, now);
+                false
+            }
+            InputEvent::MultitouchEvent { event } => !palm.allows(event, now),
+            _ => false,
+        }
+    }
+
+    /// Enables recognizing taps, swipes and pinches with `recognizer`, or disables
+    /// it. The event loop passes the resulting `InputEvent::Gesture`s to the callback
+    /// after the multitouch events they follow, and long presses as they happen.
+    pub fn set_gesture_recognizer(&mut self, recognizer: Option<GestureRecognizer>) {
+        self.gestures = recognizer;
+    }
+
+    /// Follows the fingers for gestures
+    fn recognize_gestures(&mut self, event: &InputEvent) -> Vec<InputEvent> {
+        match (self.gestures.as_mut(), event) {
+            (Some(gestures), InputEvent::MultitouchEvent { event }) => gestures
+                .handle(event, std::time::Instant::now())
+                .into_iter()
+                .map(|event| InputEvent::Gesture { event })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn event_receiver(&self) -> &std::sync::mpsc::Receiver<InputEvent> {
         &self.input_rx
     }
@@ -471,8 +2163,6 @@ impl<'a> ApplicationContext<'a> {
         activate_buttons: bool,
         mut callback: F,
     ) {
-        let appref = self.upgrade_ref();
-
         if activate_wacom {
             self.activate_input_device(InputDevice::Wacom);
         }
@@ -486,51 +2176,135 @@ impl<'a> ApplicationContext<'a> {
         // Now we consume the input events
         self.running.store(true, Ordering::Relaxed);
 
-        let mut last_active_region_gesture_id: i32 = -1;
         while self.running.load(Ordering::Relaxed) {
-            let event = self.input_rx.recv();
-            match event {
-                Err(e) => eprintln!("Error in input event consumer: {e}"),
-                Ok(event) => {
-                    if let InputEvent::MultitouchEvent {
-                        event: MultitouchEvent::Press { finger } | MultitouchEvent::Move { finger },
-                    } = event
-                    {
-                        // Check for and notify clickable active regions for multitouch events
-                        let gseq = finger.tracking_id;
-                        if last_active_region_gesture_id != gseq {
-                            if let Some((h, _)) =
-                                self.find_active_region(finger.pos.y, finger.pos.x)
-                            {
-                                (h.handler)(appref, h.element.clone());
+            // Wake up for the momentum of a scroll or a long press, if there is one
+            let momentum = self
+                .scroll
+                .as_ref()
+                .and_then(ScrollRecognizer::next_momentum);
+            let long_press = self
+                .gestures
+                .as_ref()
+                .and_then(GestureRecognizer::next_long_press);
+            let coasting = self.next_scroll_view_momentum();
+            let listing = self.next_list_view_wake();
+            let toast = self.overlays.next_expiry();
+            let wake = [momentum, long_press, coasting, listing, toast]
+                .into_iter()
+                .flatten()
+                .min();
+            if let Some(at) = wake {
+                let timeout = at.saturating_duration_since(Instant::now());
+                match self.input_rx.recv_timeout(timeout) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        let scroll = match momentum {
+                            Some(momentum) if momentum <= at => {
+                                self.scroll.as_mut().and_then(|scroll| scroll.momentum(at))
+                            }
+                            _ => None,
+                        };
+                        if let Some(event) = scroll {
+                            callback(self.upgrade_ref(), InputEvent::Scroll { event });
+                        }
+                        let gesture = self
+                            .gestures
+                            .as_mut()
+                            .and_then(|gestures| gestures.long_press(at));
+                        if let Some(event) = gesture {
+                            let event = InputEvent::Gesture { event };
+                            if !self.handle_menus(&event) {
+                                callback(self.upgrade_ref(), event);
                             }
-                            last_active_region_gesture_id = gseq;
                         }
+                        self.update_scroll_views(|view| {
+                            view.next_momentum().is_some_and(|next| next <= at) && view.momentum(at)
+                        });
+                        self.expire_toasts(at);
+                        self.update_list_views(|list| {
+                            list.long_press(at);
+                            list.next_momentum().is_some_and(|next| next <= at) && list.momentum(at)
+                        });
                     }
-
-                    callback(appref, event);
+                    Err(e) => eprintln!("Error in input event consumer: {e}"),
+                    Ok(event) => self.dispatch_event(event, &mut callback),
                 }
+                continue;
+            }
+            let event = self.input_rx.recv();
+            match event {
+                Err(e) => eprintln!("Error in input event consumer: {e}"),
+                Ok(event) => self.dispatch_event(event, &mut callback),
             };
         }
     }
 
-    pub fn handle_event(&mut self, event: InputEvent) {
+    /// Passes `event` through overlays, menus, active regions, focus and the elements
+    /// following input, then to its `InputRouter` handler or `callback`, followed by
+    /// the events recognized from it
+    pub(crate) fn dispatch_event<F: FnMut(&mut ApplicationContext<'_>, InputEvent)>(
+        &mut self,
+        event: InputEvent,
+        callback: &mut F,
+    ) {
+        let event = self.rotate_event(event);
+        if self.reject_palm(&event) {
+            return;
+        }
+        if self.handle_overlays(&event) || self.handle_menus(&event) {
+            return;
+        }
         let appref = self.upgrade_ref();
-
-        // Now we consume the input events
-        self.running.store(true, Ordering::Relaxed);
-
-        if self.running.load(Ordering::Relaxed) {
-            if let InputEvent::MultitouchEvent {
-                event: MultitouchEvent::Press { finger } | MultitouchEvent::Move { finger },
-            } = event
-            {
-                // Check for and notify clickable active regions for multitouch events
+        if let InputEvent::MultitouchEvent {
+            event: MultitouchEvent::Press { finger } | MultitouchEvent::Move { finger },
+        } = event
+        {
+            // Check for and notify clickable active regions for multitouch events
+            let gseq = finger.tracking_id;
+            if self.last_gesture_id != gseq {
+                self.last_gesture_id = gseq;
                 if let Some((h, _)) = self.find_active_region(finger.pos.y, finger.pos.x) {
                     (h.handler)(appref, h.element.clone());
                 }
             }
         }
+
+        if self.handle_focus(&event) {
+            return;
+        }
+        self.update_value_elements(&event);
+        #[cfg(feature = "stroke")]
+        self.update_canvases(&event);
+        if let InputEvent::MultitouchEvent { ref event } = event {
+            let now = Instant::now();
+            self.update_scroll_views(|view| view.handle_multitouch(event, now));
+            self.update_list_views(|list| list.handle_multitouch(event, now));
+            self.update_controls(event);
+        }
+        #[cfg(feature = "stroke")]
+        let erase = self.detect_scratch_out(&event);
+        let scroll = self.recognize_scroll(&event);
+        let gestures = self.recognize_gestures(&event);
+        let smoothed = self.smooth_stroke(&event);
+        match self.router.handler(&event) {
+            Some(handler) => (handler.lock().unwrap())(appref, event),
+            None => callback(appref, event),
+        }
+        for event in smoothed.into_iter().chain(scroll).chain(gestures) {
+            if !self.handle_menus(&event) {
+                callback(self.upgrade_ref(), event);
+            }
+        }
+        #[cfg(feature = "stroke")]
+        if let Some(erase) = erase {
+            callback(self.upgrade_ref(), erase);
+        }
+    }
+
+    /// Handles `event` like `start_event_loop` does, for apps reading input
+    /// themselves. What isn't taken by an element or routed by the `InputRouter` is
+    /// dropped, as are the events recognized from it.
+    pub fn handle_event(&mut self, event: InputEvent) {
+        self.dispatch_event(event, &mut |_, _| {});
     }
 
     pub fn find_active_region(&self, y: u16, x: u16) -> Option<(&ActiveRegionHandler, ItemId)> {
@@ -575,3 +2349,12 @@ impl<'a> ApplicationContext<'a> {
         );
     }
 }
+
+/// Holding the active regions of either orientation, see `set_screen_rotation`
+fn active_regions(side: u32) -> QuadTree<ActiveRegionHandler> {
+    let side = side as f32;
+    QuadTree::default(geom::Rect::from_points(
+        &geom::Point { x: 0.0, y: 0.0 },
+        &geom::Point { x: side, y: side },
+    ))
+}
@@ -0,0 +1,289 @@
+//! Controls the screen and input devices from the command line, e.g. over SSH:
+//!
+//! ```text
+//! remarkable-ctl text 100 200 40 "Hello world"
+//! remarkable-ctl image logo.png 100 400
+//! remarkable-ctl refresh full
+//! remarkable-ctl screenshot screen.png
+//! remarkable-ctl tap 700 900
+//! ```
+//!
+//! Every command only uses the crate's public API.
+
+use std::error::Error;
+use std::io::Write;
+use std::thread::sleep;
+use std::time::Duration;
+
+use libremarkable::cgmath::{Point2, Vector2};
+use libremarkable::framebuffer::common::*;
+use libremarkable::framebuffer::core::Framebuffer;
+use libremarkable::framebuffer::{
+    decode, FramebufferDraw, FramebufferRefresh, ImageImportOptions, PartialRefreshMode,
+};
+use libremarkable::input::{ecodes, raw_position, scan::SCANNED, InputDevice};
+
+use libremarkable::evdev::{EventType, InputEvent};
+
+const USAGE: &str = "\
+Usage: remarkable-ctl <command> [args...]
+
+Drawing (refreshes the changed region):
+  text <x> <y> <size> <text...>      Draw text with its top left at x,y
+  image <path> <x> <y>               Draw an image file at x,y
+  rect <x> <y> <width> <height>      Fill a black rectangle
+  clear                              Clear the screen and do a full refresh
+
+Display:
+  refresh full                       Full refresh of the whole screen
+  refresh <x> <y> <width> <height>   Partial refresh of a region
+  screenshot [path]                  Save the screen as PNG (stdout if no path)
+
+Input injection (seen by every app reading the devices):
+  tap <x> <y>                        Touch at x,y
+  swipe <x1> <y1> <x2> <y2> [ms]     Move a finger from x1,y1 to x2,y2
+  pen <x1> <y1> <x2> <y2> [ms]       Draw a pen stroke from x1,y1 to x2,y2
+  button <left|middle|right|power>   Press and release a button
+";
+
+/// Interval between the reports of injected motions
+const REPORT_INTERVAL: Duration = Duration::from_millis(10);
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        print!("{}", USAGE);
+        return;
+    }
+    if let Err(err) = run(&args[0], &args[1..]) {
+        eprintln!("remarkable-ctl {}: {}", args[0], err);
+        std::process::exit(1);
+    }
+}
+
+fn run(command: &str, args: &[String]) -> Result<()> {
+    match command {
+        "text" => {
+            let [x, y, size] = numbers::<f32, 3>(args.get(..3).ok_or("missing position")?)?;
+            let text = args[3..].join(" ");
+            let mut fb = Framebuffer::try_new()?;
+            let rect = fb.draw_text(Point2 { x, y }, &text, size, color::BLACK, false);
+            refresh(&fb, &rect);
+        }
+        "image" => {
+            let path = args.first().ok_or("missing path")?;
+            let [x, y] = numbers::<i32, 2>(&args[1..])?;
+            let img = decode::load(path)?;
+            let mut fb = Framebuffer::try_new()?;
+            let rect = fb.draw_image(&img, Point2 { x, y }, ImageImportOptions::default());
+            refresh(&fb, &rect);
+        }
+        "rect" => {
+            let [left, top, width, height] = numbers::<u32, 4>(args)?;
+            let mut fb = Framebuffer::try_new()?;
+            fb.fill_rect(
+                Point2 { x: left, y: top }
+                    .cast()
+                    .ok_or("position out of range")?,
+                Vector2 {
+                    x: width,
+                    y: height,
+                },
+                color::BLACK,
+            );
+            refresh(
+                &fb,
+                &mxcfb_rect {
+                    top,
+                    left,
+                    width,
+                    height,
+                },
+            );
+        }
+        "clear" => {
+            let mut fb = Framebuffer::try_new()?;
+            fb.clear();
+            full_refresh(&fb);
+        }
+        "refresh" => {
+            let fb = Framebuffer::try_new()?;
+            match args {
+                [full] if full == "full" => full_refresh(&fb),
+                _ => {
+                    let [left, top, width, height] = numbers::<u32, 4>(args)?;
+                    refresh(
+                        &fb,
+                        &mxcfb_rect {
+                            top,
+                            left,
+                            width,
+                            height,
+                        },
+                    );
+                }
+            }
+        }
+        "screenshot" => {
+            let png = Framebuffer::try_new()?.export_png()?;
+            match args.first() {
+                Some(path) => std::fs::write(path, png)?,
+                None => std::io::stdout().write_all(&png)?,
+            }
+        }
+        "tap" => {
+            let [x, y] = numbers::<f32, 2>(args)?;
+            touch(Point2 { x, y }, Point2 { x, y }, Duration::from_millis(50))?;
+        }
+        "swipe" => {
+            let (from, to, duration) = motion(args)?;
+            touch(from, to, duration)?;
+        }
+        "pen" => {
+            let (from, to, duration) = motion(args)?;
+            pen(from, to, duration)?;
+        }
+        "button" => {
+            let code = match args.first().map(String::as_str) {
+                Some("left") => ecodes::KEY_LEFT,
+                Some("middle") | Some("home") => ecodes::KEY_HOME,
+                Some("right") => ecodes::KEY_RIGHT,
+                Some("power") => ecodes::KEY_POWER,
+                _ => return Err("expected left, middle, right or power".into()),
+            };
+            let mut dev = SCANNED.get_device(InputDevice::GPIO)?;
+            dev.send_events(&[key(code, 1), syn()])?;
+            sleep(Duration::from_millis(50));
+            dev.send_events(&[key(code, 0), syn()])?;
+        }
+        _ => return Err(format!("unknown command, see --help\n\n{}", USAGE).into()),
+    }
+    Ok(())
+}
+
+/// Parses exactly `N` numbers
+fn numbers<T: std::str::FromStr, const N: usize>(args: &[String]) -> Result<[T; N]>
+where
+    T::Err: Error + 'static,
+{
+    if args.len() != N {
+        return Err(format!("expected {} numbers, got {}", N, args.len()).into());
+    }
+    let parsed = args
+        .iter()
+        .map(|arg| arg.parse::<T>())
+        .collect::<std::result::Result<Vec<T>, _>>()?;
+    Ok(parsed.try_into().unwrap_or_else(|_| unreachable!()))
+}
+
+/// Start, end and duration of the arguments of `swipe` and `pen`
+fn motion(args: &[String]) -> Result<(Point2<f32>, Point2<f32>, Duration)> {
+    let [x1, y1, x2, y2] = numbers::<f32, 4>(args.get(..4).ok_or("missing coordinates")?)?;
+    let [ms] = match args.get(4..) {
+        Some([]) | None => [300],
+        Some(rest) => numbers::<u64, 1>(rest)?,
+    };
+    Ok((
+        Point2 { x: x1, y: y1 },
+        Point2 { x: x2, y: y2 },
+        Duration::from_millis(ms),
+    ))
+}
+
+fn refresh(fb: &Framebuffer, rect: &mxcfb_rect) {
+    fb.partial_refresh(
+        rect,
+        PartialRefreshMode::Wait,
+        waveform_mode::WAVEFORM_MODE_GC16_FAST,
+        display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        0,
+        false,
+    );
+}
+
+fn full_refresh(fb: &Framebuffer) {
+    fb.full_refresh(
+        waveform_mode::WAVEFORM_MODE_INIT,
+        display_temp::TEMP_USE_AMBIENT,
+        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        0,
+        true,
+    );
+}
+
+fn syn() -> InputEvent {
+    InputEvent::new(EventType::SYNCHRONIZATION, ecodes::SYN_REPORT, 0)
+}
+
+fn key(code: u16, value: i32) -> InputEvent {
+    InputEvent::new(EventType::KEY, code, value)
+}
+
+fn abs(code: u16, value: i32) -> InputEvent {
+    InputEvent::new(EventType::ABSOLUTE, code, value)
+}
+
+/// Positions along the line from `from` to `to`, one per report
+fn path(
+    from: Point2<f32>,
+    to: Point2<f32>,
+    duration: Duration,
+) -> impl Iterator<Item = Point2<f32>> {
+    let steps = (duration.as_millis() / REPORT_INTERVAL.as_millis()).max(1) as u32;
+    (0..=steps).map(move |step| {
+        let t = step as f32 / steps as f32;
+        from + (to - from) * t
+    })
+}
+
+fn touch(from: Point2<f32>, to: Point2<f32>, duration: Duration) -> Result<()> {
+    let mut dev = SCANNED.get_device(InputDevice::Multitouch)?;
+    // Tracking ids only need to differ from the ones of real fingers in use
+    let tracking_id = 0x7ff0 + (std::process::id() % 0x10) as i32;
+    dev.send_events(&[
+        abs(ecodes::ABS_MT_SLOT, 0),
+        abs(ecodes::ABS_MT_TRACKING_ID, tracking_id),
+    ])?;
+    for (i, pos) in path(from, to, duration).enumerate() {
+        let raw = raw_position(InputDevice::Multitouch, pos).unwrap();
+        let mut events = vec![
+            abs(ecodes::ABS_MT_POSITION_X, raw.x),
+            abs(ecodes::ABS_MT_POSITION_Y, raw.y),
+        ];
+        if i == 0 {
+            events.push(abs(ecodes::ABS_MT_PRESSURE, 100));
+        }
+        events.push(syn());
+        dev.send_events(&events)?;
+        sleep(REPORT_INTERVAL);
+    }
+    dev.send_events(&[abs(ecodes::ABS_MT_TRACKING_ID, -1), syn()])?;
+    Ok(())
+}
+
+fn pen(from: Point2<f32>, to: Point2<f32>, duration: Duration) -> Result<()> {
+    let mut dev = SCANNED.get_device(InputDevice::Wacom)?;
+    dev.send_events(&[key(ecodes::BTN_TOOL_PEN, 1), syn()])?;
+    for (i, pos) in path(from, to, duration).enumerate() {
+        let raw = raw_position(InputDevice::Wacom, pos).unwrap();
+        let mut events = vec![abs(ecodes::ABS_X, raw.x), abs(ecodes::ABS_Y, raw.y)];
+        if i == 0 {
+            events.push(key(ecodes::BTN_TOUCH, 1));
+        }
+        events.push(abs(ecodes::ABS_PRESSURE, 2000));
+        events.push(syn());
+        dev.send_events(&events)?;
+        sleep(REPORT_INTERVAL);
+    }
+    dev.send_events(&[
+        abs(ecodes::ABS_PRESSURE, 0),
+        key(ecodes::BTN_TOUCH, 0),
+        syn(),
+        key(ecodes::BTN_TOOL_PEN, 0),
+        syn(),
+    ])?;
+    Ok(())
+}
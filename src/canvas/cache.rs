@@ -0,0 +1,255 @@
+//! Cache of rendered chunks.
+//!
+//! Stroking the content of a busy chunk can mean drawing thousands of segments.
+//! Once a chunk has been rendered fully on screen, its pixels are kept so that
+//! showing it again (after panning away and back) is a single blit. Entries are
+//! keyed by zoom level and evicted least recently used first once the cache
+//! exceeds its memory budget.
+//!
+//! The cache doesn't know when the canvas changes: call `invalidate` with the
+//! chunks of every drawing operation applied to it.
+
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::canvas::protocol::{ChunkCoordinates, DrawMessage, CHUNK_SIZE};
+use crate::canvas::surface::{draw_message_with, ChunkedCanvas};
+use crate::canvas::viewport::Viewport;
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::{FramebufferDraw, FramebufferIO};
+
+struct Entry {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    last_used: u64,
+}
+
+pub struct ChunkCache {
+    entries: HashMap<(ChunkCoordinates, u32), Entry>,
+    used: usize,
+    clock: u64,
+    /// Maximum number of bytes of pixel data kept
+    pub budget: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for ChunkCache {
+    fn default() -> Self {
+        ChunkCache::new(16 * 1024 * 1024)
+    }
+}
+
+/// Whether `rect`, the screen region of `chunk`, shows all of it
+fn is_complete(viewport: &Viewport, chunk: ChunkCoordinates, rect: &mxcfb_rect) -> bool {
+    let tl = viewport.to_screen(chunk.origin());
+    let size = viewport.scale(CHUNK_SIZE) as f32;
+    !(rect.left as f32 > tl.x.floor()
+        || rect.top as f32 > tl.y.floor()
+        || ((rect.left + rect.width) as f32) < (tl.x + size).ceil()
+        || ((rect.top + rect.height) as f32) < (tl.y + size).ceil())
+}
+
+impl ChunkCache {
+    pub fn new(budget: usize) -> ChunkCache {
+        ChunkCache {
+            entries: HashMap::new(),
+            used: 0,
+            clock: 0,
+            budget,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Bytes of pixel data currently cached
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of chunks blitted from and rendered into the cache so far
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    /// Drops the cached pixels of `chunks` at all zoom levels
+    pub fn invalidate<'a>(&mut self, chunks: impl IntoIterator<Item = &'a ChunkCoordinates>) {
+        for chunk in chunks {
+            let used = &mut self.used;
+            self.entries.retain(|(c, _), entry| {
+                if c == chunk {
+                    *used -= entry.pixels.len();
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    /// Drops the cached pixels of the chunks touched by `message`
+    pub fn invalidate_message(&mut self, message: &DrawMessage) {
+        self.invalidate(&message.chunks());
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+    }
+
+    fn get(&mut self, key: (ChunkCoordinates, u32), rect: &mxcfb_rect) -> Option<&[u8]> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&key)?;
+        // Sub-pixel offsets can make the same chunk a pixel larger or smaller
+        if entry.width != rect.width || entry.height != rect.height {
+            return None;
+        }
+        entry.last_used = self.clock;
+        Some(&entry.pixels)
+    }
+
+    fn insert(&mut self, key: (ChunkCoordinates, u32), rect: &mxcfb_rect, pixels: Vec<u8>) {
+        if pixels.len() > self.budget {
+            return;
+        }
+        self.clock += 1;
+        self.used += pixels.len();
+        let entry = Entry {
+            width: rect.width,
+            height: rect.height,
+            pixels,
+            last_used: self.clock,
+        };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.used -= old.pixels.len();
+        }
+        while self.used > self.budget {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            match oldest.and_then(|key| self.entries.remove(&key)) {
+                Some(entry) => self.used -= entry.pixels.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Renders all chunks visible in `viewport`, blitting cached ones and calling
+    /// `draw_chunk` for the others. `draw_chunk` gets a viewport restricted to the
+    /// chunk, which has already been cleared. Doesn't refresh the display.
+    pub fn render_with<F, D>(
+        &mut self,
+        fb: &mut F,
+        viewport: &Viewport,
+        mut draw_chunk: D,
+    ) -> mxcfb_rect
+    where
+        F: FramebufferIO + FramebufferDraw + ?Sized,
+        D: FnMut(&mut F, ChunkCoordinates, &Viewport),
+    {
+        let zoom = viewport.zoom.to_bits();
+        for chunk in viewport.visible_chunks() {
+            let rect = match viewport.chunk_rect(chunk) {
+                Some(rect) => rect,
+                None => continue,
+            };
+            let complete = is_complete(viewport, chunk, &rect);
+            if complete {
+                if let Some(pixels) = self.get((chunk, zoom), &rect) {
+                    match fb.restore_region(rect, pixels) {
+                        Ok(_) => {
+                            self.hits += 1;
+                            continue;
+                        }
+                        Err(err) => warn!("Failed to blit cached chunk: {}", err),
+                    }
+                }
+            }
+
+            self.misses += 1;
+            fb.fill_rect(
+                cgmath::Point2 {
+                    x: rect.left as i32,
+                    y: rect.top as i32,
+                },
+                rect.size(),
+                color::WHITE,
+            );
+            draw_chunk(fb, chunk, &viewport.restricted_to(rect));
+            if complete {
+                match fb.dump_region(rect) {
+                    Ok(pixels) => self.insert((chunk, zoom), &rect, pixels),
+                    Err(err) => warn!("Failed to cache chunk: {}", err),
+                }
+            }
+        }
+        viewport.screen
+    }
+
+    /// Like `ChunkedCanvas::render`, reusing cached chunks. Paths that are still
+    /// being drawn are never cached and drawn on top.
+    pub fn render<F: FramebufferIO + FramebufferDraw + ?Sized>(
+        &mut self,
+        fb: &mut F,
+        viewport: &Viewport,
+        canvas: &ChunkedCanvas,
+    ) -> mxcfb_rect {
+        let damage = self.render_with(fb, viewport, |fb, chunk, restricted| {
+            for item in canvas.items_in(std::iter::once(&chunk)) {
+                draw_message_with(fb, item, restricted, &canvas.palette);
+            }
+        });
+        for path in canvas.live_paths() {
+            draw_message_with(
+                fb,
+                &DrawMessage::Path(path.clone()),
+                viewport,
+                &canvas.palette,
+            );
+        }
+        damage
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let rect = mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: 10,
+            height: 10,
+        };
+        let key = |x| (ChunkCoordinates::new(x, 0), 1.0f32.to_bits());
+        let mut cache = ChunkCache::new(300);
+        cache.insert(key(0), &rect, vec![0; 100]);
+        cache.insert(key(1), &rect, vec![1; 100]);
+        cache.insert(key(2), &rect, vec![2; 100]);
+        // Touching 0 makes 1 the least recently used
+        assert!(cache.get(key(0), &rect).is_some());
+        cache.insert(key(3), &rect, vec![3; 100]);
+        assert_eq!(cache.used(), 300);
+        assert!(cache.get(key(1), &rect).is_none());
+        assert!(cache.get(key(0), &rect).is_some());
+
+        cache.invalidate(&[ChunkCoordinates::new(0, 0)]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.used(), 200);
+    }
+}
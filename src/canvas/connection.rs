@@ -0,0 +1,434 @@
+//! Keeps a connection to a canvas server (or peer) alive.
+//!
+//! The `ConnectionManager` is independent of the actual transport: anything that can
+//! send and receive `Message`s implements `Transport`, and a `Connector` knows how to
+//! establish a new one. While offline, outgoing messages are queued. Once the
+//! connection comes back (retrying with exponential backoff), the subscription is
+//! re-sent followed by the queued messages.
+//!
+//! `StreamTransport` implements the transport over any byte stream (a non-blocking
+//! `TcpStream`, a Unix socket...) using length prefixed postcard frames of at most
+//! `MAX_FRAME_SIZE` bytes.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::canvas::protocol::{self, CodecError, Message};
+
+/// Largest frame `StreamTransport` accepts, in bytes. Larger ones fail the connection
+/// rather than buffering whatever length a peer claims.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Differentiate between the reasons why a connection can fail.
+#[derive(Debug)]
+pub enum ConnectionError {
+    IOError(io::Error),
+    Codec(CodecError),
+    /// The other side closed the connection
+    Closed,
+    /// A frame of this size exceeded `MAX_FRAME_SIZE`
+    FrameTooLarge(usize),
+}
+
+impl From<io::Error> for ConnectionError {
+    fn from(err: io::Error) -> Self {
+        ConnectionError::IOError(err)
+    }
+}
+
+impl From<CodecError> for ConnectionError {
+    fn from(err: CodecError) -> Self {
+        ConnectionError::Codec(err)
+    }
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionError::IOError(err) => err.fmt(f),
+            ConnectionError::Codec(err) => err.fmt(f),
+            ConnectionError::Closed => write!(f, "Connection closed"),
+            ConnectionError::FrameTooLarge(len) => write!(f, "Frame of {} bytes too large", len),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// An established connection
+pub trait Transport {
+    fn send(&mut self, message: &Message) -> Result<(), ConnectionError>;
+    /// Returns the next received message without blocking, or `None` if there is none
+    fn try_recv(&mut self) -> Result<Option<Message>, ConnectionError>;
+}
+
+/// Establishes connections
+pub trait Connector {
+    type Transport: Transport;
+    fn connect(&mut self) -> Result<Self::Transport, ConnectionError>;
+}
+
+impl<T: Transport, F: FnMut() -> Result<T, ConnectionError>> Connector for F {
+    type Transport = T;
+    fn connect(&mut self) -> Result<T, ConnectionError> {
+        self()
+    }
+}
+
+/// Exponentially growing delay between reconnection attempts
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: u32,
+    current: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            factor: 2,
+            current: initial,
+        }
+    }
+
+    /// The delay to wait before the next attempt. Every call doubles the following one.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * self.factor).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+pub struct ConnectionManager<C: Connector> {
+    connector: C,
+    transport: Option<C::Transport>,
+    queue: VecDeque<Message>,
+    /// Maximum number of queued messages. Once full, the oldest ones are dropped.
+    pub max_queue: usize,
+    pub backoff: Backoff,
+    retry_at: Option<Instant>,
+    subscription: Option<Message>,
+}
+
+impl<C: Connector> ConnectionManager<C> {
+    /// Creates a disconnected manager. The first connection attempt is made by the
+    /// first call to `poll`.
+    pub fn new(connector: C) -> ConnectionManager<C> {
+        ConnectionManager {
+            connector,
+            transport: None,
+            queue: VecDeque::new(),
+            max_queue: 10_000,
+            backoff: Backoff::default(),
+            retry_at: None,
+            subscription: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Number of messages waiting for the connection to come back
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// When the next reconnection attempt will be made, if disconnected
+    pub fn retry_at(&self) -> Option<Instant> {
+        self.retry_at
+    }
+
+    /// Sets the subscription that is sent whenever a connection is established, and
+    /// sends it right away if connected.
+    pub fn set_subscription(&mut self, subscribe: Message) {
+        // Not queued when sending fails, reconnecting sends it anyway
+        if let Some(transport) = self.transport.as_mut() {
+            if let Err(err) = transport.send(&subscribe) {
+                self.disconnect(Instant::now(), &err);
+            }
+        }
+        self.subscription = Some(subscribe);
+    }
+
+    fn enqueue(&mut self, message: Message) {
+        if self.queue.len() >= self.max_queue {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(message);
+    }
+
+    fn disconnect(&mut self, now: Instant, err: &ConnectionError) {
+        if self.transport.take().is_some() {
+            warn!("Lost connection: {}", err);
+        }
+        self.retry_at = Some(now + self.backoff.next_delay());
+    }
+
+    /// Sends `message`, or queues it if currently offline
+    pub fn send(&mut self, message: Message) {
+        let result = match self.transport.as_mut() {
+            Some(transport) => transport.send(&message),
+            None => return self.enqueue(message),
+        };
+        if let Err(err) = result {
+            self.enqueue(message);
+            self.disconnect(Instant::now(), &err);
+        }
+    }
+
+    /// (Re)connects if needed and due, and returns the messages received since the
+    /// last call. Call this regularly, e.g. from your event loop.
+    pub fn poll(&mut self, now: Instant) -> Vec<Message> {
+        if self.transport.is_none() && self.retry_at.is_none_or(|at| now >= at) {
+            self.reconnect(now);
+        }
+
+        let mut received = Vec::new();
+        while let Some(transport) = self.transport.as_mut() {
+            match transport.try_recv() {
+                Ok(Some(message)) => received.push(message),
+                Ok(None) => break,
+                Err(err) => self.disconnect(now, &err),
+            }
+        }
+        received
+    }
+
+    fn reconnect(&mut self, now: Instant) {
+        let mut transport = match self.connector.connect() {
+            Ok(transport) => transport,
+            Err(err) => {
+                debug!("Failed to connect: {}", err);
+                self.retry_at = Some(now + self.backoff.next_delay());
+                return;
+            }
+        };
+        let result = self
+            .subscription
+            .iter()
+            .chain(self.queue.iter())
+            .enumerate()
+            .try_for_each(|(i, message)| transport.send(message).map_err(|err| (i, err)));
+        if let Err((failed_at, err)) = result {
+            // Keep what hasn't been sent yet for the next attempt
+            let sent_from_queue =
+                failed_at.saturating_sub(usize::from(self.subscription.is_some()));
+            self.queue.drain(..sent_from_queue);
+            self.disconnect(now, &err);
+            return;
+        }
+        self.queue.clear();
+        self.backoff.reset();
+        self.retry_at = None;
+        self.transport = Some(transport);
+    }
+}
+
+/// `Transport` over a byte stream. Each message is a postcard encoded frame prefixed
+/// with its length as a little endian `u32`.
+///
+/// `try_recv` only avoids blocking if the stream itself is non-blocking (see
+/// `TcpStream::set_nonblocking`).
+pub struct StreamTransport<S: Read + Write> {
+    stream: S,
+    buffer: Vec<u8>,
+}
+
+impl<S: Read + Write> StreamTransport<S> {
+    pub fn new(stream: S) -> StreamTransport<S> {
+        StreamTransport {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Takes a complete frame out of the buffer if there is one
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, ConnectionError> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes([
+            self.buffer[0],
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+        ]) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(ConnectionError::FrameTooLarge(len));
+        }
+        if self.buffer.len() < 4 + len {
+            return Ok(None);
+        }
+        let frame = self.buffer[4..4 + len].to_vec();
+        self.buffer.drain(..4 + len);
+        Ok(Some(frame))
+    }
+}
+
+impl<S: Read + Write> Transport for StreamTransport<S> {
+    fn send(&mut self, message: &Message) -> Result<(), ConnectionError> {
+        let frame = protocol::to_postcard(message)?;
+        self.stream.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Message>, ConnectionError> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(Some(protocol::from_postcard(&frame)?));
+            }
+            let mut chunk = [0u8; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ConnectionError::Closed),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::protocol::{ChunkCoordinates, Color, Dot, DrawMessage, Point, Subscription};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records sent messages; fails once `broken` is set
+    #[derive(Default)]
+    struct Server {
+        up: bool,
+        broken: bool,
+        received: Vec<Message>,
+    }
+
+    struct MockTransport(Rc<RefCell<Server>>);
+
+    impl Transport for MockTransport {
+        fn send(&mut self, message: &Message) -> Result<(), ConnectionError> {
+            let mut server = self.0.borrow_mut();
+            if server.broken {
+                return Err(ConnectionError::Closed);
+            }
+            server.received.push(message.clone());
+            Ok(())
+        }
+
+        fn try_recv(&mut self) -> Result<Option<Message>, ConnectionError> {
+            match self.0.borrow().broken {
+                true => Err(ConnectionError::Closed),
+                false => Ok(None),
+            }
+        }
+    }
+
+    fn dot(x: i32) -> Message {
+        Message::Draw(DrawMessage::Dot(Dot {
+            position: Point::new(x, 0),
+            size: 1,
+            color: Color::BLACK,
+        }))
+    }
+
+    #[test]
+    fn test_queue_and_replay() {
+        let server = Rc::new(RefCell::new(Server::default()));
+        let handle = server.clone();
+        let mut conn = ConnectionManager::new(move || {
+            if handle.borrow().up {
+                handle.borrow_mut().broken = false;
+                Ok(MockTransport(handle.clone()))
+            } else {
+                Err(ConnectionError::Closed)
+            }
+        });
+        conn.backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(3));
+        let subscribe = Message::Subscribe(Subscription::default());
+        conn.set_subscription(subscribe.clone());
+
+        let t0 = Instant::now();
+        conn.send(dot(1));
+        conn.poll(t0);
+        assert!(!conn.is_connected());
+        assert_eq!(conn.retry_at(), Some(t0 + Duration::from_secs(1)));
+        conn.poll(t0 + Duration::from_secs(1));
+        assert_eq!(conn.retry_at(), Some(t0 + Duration::from_secs(3)));
+
+        server.borrow_mut().up = true;
+        conn.send(dot(2));
+        // Not due yet
+        conn.poll(t0 + Duration::from_secs(2));
+        assert!(!conn.is_connected());
+        conn.poll(t0 + Duration::from_secs(3));
+        assert!(conn.is_connected());
+        assert_eq!(conn.queued(), 0);
+        assert_eq!(
+            server.borrow().received,
+            vec![subscribe.clone(), dot(1), dot(2)]
+        );
+
+        // Dropping the connection queues messages until the next reconnect
+        server.borrow_mut().broken = true;
+        server.borrow_mut().received.clear();
+        conn.send(dot(3));
+        assert!(!conn.is_connected());
+        assert_eq!(conn.queued(), 1);
+        conn.poll(Instant::now() + Duration::from_secs(1));
+        assert_eq!(server.borrow().received, vec![subscribe, dot(3)]);
+
+        // A subscription that fails to send is only sent once on reconnecting
+        server.borrow_mut().broken = true;
+        server.borrow_mut().received.clear();
+        let resubscribe = Message::Subscribe(Subscription::new([ChunkCoordinates::new(1, 2)]));
+        conn.set_subscription(resubscribe.clone());
+        assert!(!conn.is_connected());
+        assert_eq!(conn.queued(), 0);
+        conn.poll(Instant::now() + Duration::from_secs(1));
+        assert_eq!(server.borrow().received, vec![resubscribe]);
+    }
+
+    #[test]
+    fn test_stream_transport_framing() {
+        let mut wire = Vec::new();
+        let mut sender = StreamTransport::new(io::Cursor::new(&mut wire));
+        sender.send(&dot(1)).unwrap();
+        sender.send(&dot(2)).unwrap();
+
+        let mut receiver = StreamTransport::new(io::Cursor::new(wire));
+        assert_eq!(receiver.try_recv().unwrap(), Some(dot(1)));
+        assert_eq!(receiver.try_recv().unwrap(), Some(dot(2)));
+        assert!(matches!(receiver.try_recv(), Err(ConnectionError::Closed)));
+
+        let wire = (MAX_FRAME_SIZE as u32 + 1).to_le_bytes();
+        let mut receiver = StreamTransport::new(io::Cursor::new(wire));
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(ConnectionError::FrameTooLarge(len)) if len == MAX_FRAME_SIZE + 1
+        ));
+    }
+}
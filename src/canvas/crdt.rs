@@ -0,0 +1,470 @@
+//! Operation based CRDT for shared canvases.
+//!
+//! Every change to the canvas is an `Op` identified by an `OpId` made of a Lamport
+//! timestamp and the id of the client that created it. Drawings form a grow-only set
+//! ordered by their ids, erasures are tombstones referring to the drawing they remove.
+//! Applying the same set of operations in any order, any number of times, yields the
+//! same canvas on every client.
+//!
+//! Strokes that are still being drawn (`PathStep`s) are not part of the log: they are
+//! ephemeral previews and only the finished `Path` is recorded.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use log::warn;
+
+use crate::canvas::protocol::{ChunkCoordinates, DrawMessage, Op, OpId, Operation, Point};
+
+#[cfg(feature = "canvas")]
+use crate::canvas::palette::Palette;
+#[cfg(feature = "canvas")]
+use crate::canvas::surface::draw_message_with;
+#[cfg(feature = "canvas")]
+use crate::canvas::viewport::Viewport;
+#[cfg(feature = "canvas")]
+use crate::framebuffer::cgmath;
+#[cfg(feature = "canvas")]
+use crate::framebuffer::common::{color, mxcfb_rect};
+#[cfg(feature = "canvas")]
+use crate::framebuffer::FramebufferDraw;
+
+/// The highest Lamport timestamp an operation may have. Every operation only advances
+/// the clocks of honest peers by one, so they never get near it. Operations beyond it,
+/// local ones included, are rejected by every peer alike, so logs still converge
+/// rather than letting them run the clock into overflow.
+pub const MAX_LAMPORT: u64 = 1 << 48;
+
+/// What applying an operation changed, so the display can be updated incrementally
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Nothing visible changed (duplicate operation, erasure of an unknown drawing...)
+    None,
+    /// A drawing was added on top of everything else and can simply be drawn
+    DrawnOnTop(OpId),
+    /// The content of these chunks changed in a way that requires redrawing them
+    Redraw(BTreeSet<ChunkCoordinates>),
+}
+
+pub struct OpLog {
+    client: u64,
+    clock: u64,
+    ops: BTreeMap<OpId, Operation>,
+    /// Drawings that have been erased, possibly before the drawing itself arrived
+    tombstones: BTreeSet<OpId>,
+    /// Visible drawings by chunk
+    index: HashMap<ChunkCoordinates, BTreeSet<OpId>>,
+    /// Highest id of a visible drawing
+    top: Option<OpId>,
+    /// Colors used when rendering
+    #[cfg(feature = "canvas")]
+    pub palette: Palette,
+}
+
+impl OpLog {
+    /// Creates an empty log for the client `client`. Client ids have to be unique
+    /// among all peers sharing the canvas.
+    pub fn new(client: u64) -> OpLog {
+        OpLog {
+            client,
+            clock: 0,
+            ops: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
+            index: HashMap::new(),
+            top: None,
+            #[cfg(feature = "canvas")]
+            palette: Palette::default(),
+        }
+    }
+
+    pub fn client(&self) -> u64 {
+        self.client
+    }
+
+    /// Number of operations in the log
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn contains(&self, id: &OpId) -> bool {
+        self.ops.contains_key(id)
+    }
+
+    /// All operations, e.g. to bring a new peer up to date
+    pub fn ops(&self) -> impl Iterator<Item = Op> + '_ {
+        self.ops.iter().map(|(id, operation)| Op {
+            id: *id,
+            operation: operation.clone(),
+        })
+    }
+
+    /// Operations with a Lamport timestamp greater than `lamport`
+    pub fn ops_since(&self, lamport: u64) -> impl Iterator<Item = Op> + '_ {
+        self.ops().filter(move |op| op.id.lamport > lamport)
+    }
+
+    /// The drawing added by `id`, if it is visible
+    pub fn drawing(&self, id: &OpId) -> Option<&DrawMessage> {
+        if self.tombstones.contains(id) {
+            return None;
+        }
+        match self.ops.get(id) {
+            Some(Operation::Draw(message)) => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Visible drawings, bottom-most first
+    pub fn drawings(&self) -> impl Iterator<Item = (OpId, &DrawMessage)> {
+        self.ops.iter().filter_map(move |(id, op)| match op {
+            Operation::Draw(message) if !self.tombstones.contains(id) => Some((*id, message)),
+            _ => None,
+        })
+    }
+
+    /// Visible drawings touching any of `chunks`, bottom-most first
+    pub fn drawings_in<'a>(
+        &'a self,
+        chunks: impl IntoIterator<Item = &'a ChunkCoordinates>,
+    ) -> impl Iterator<Item = (OpId, &'a DrawMessage)> {
+        let ids: BTreeSet<OpId> = chunks
+            .into_iter()
+            .filter_map(|c| self.index.get(c))
+            .flatten()
+            .copied()
+            .collect();
+        ids.into_iter()
+            .filter_map(move |id| self.drawing(&id).map(|m| (id, m)))
+    }
+
+    fn next_id(&mut self) -> OpId {
+        self.clock = self.clock.saturating_add(1);
+        OpId {
+            lamport: self.clock,
+            client: self.client,
+        }
+    }
+
+    /// Records a drawing made locally. Returns the operation to send to peers along
+    /// with the resulting change.
+    pub fn draw(&mut self, message: DrawMessage) -> (Op, Change) {
+        let op = Op {
+            id: self.next_id(),
+            operation: Operation::Draw(message),
+        };
+        let change = self.apply(op.clone());
+        (op, change)
+    }
+
+    /// Erases the drawing `target` locally
+    pub fn erase(&mut self, target: OpId) -> (Op, Change) {
+        let op = Op {
+            id: self.next_id(),
+            operation: Operation::Erase(target),
+        };
+        let change = self.apply(op.clone());
+        (op, change)
+    }
+
+    /// Applies a local or remote operation. Applying an operation that is already in
+    /// the log, or one with a timestamp beyond `MAX_LAMPORT`, is a no-op.
+    pub fn apply(&mut self, op: Op) -> Change {
+        if self.ops.contains_key(&op.id) {
+            return Change::None;
+        }
+        if op.id.lamport > MAX_LAMPORT {
+            warn!(
+                "Rejecting operation {:?}, beyond the highest timestamp",
+                op.id
+            );
+            return Change::None;
+        }
+        self.clock = self.clock.max(op.id.lamport);
+        let id = op.id;
+        self.ops.insert(id, op.operation.clone());
+
+        match op.operation {
+            Operation::Draw(message) => {
+                if self.tombstones.contains(&id) {
+                    return Change::None;
+                }
+                let chunks = message.chunks();
+                for chunk in &chunks {
+                    self.index.entry(*chunk).or_default().insert(id);
+                }
+                if self.top < Some(id) {
+                    self.top = Some(id);
+                    Change::DrawnOnTop(id)
+                } else {
+                    Change::Redraw(chunks)
+                }
+            }
+            Operation::Erase(target) => {
+                if !self.tombstones.insert(target) {
+                    return Change::None;
+                }
+                let chunks = match self.ops.get(&target) {
+                    Some(Operation::Draw(message)) => message.chunks(),
+                    _ => return Change::None,
+                };
+                for chunk in &chunks {
+                    if let Some(ids) = self.index.get_mut(chunk) {
+                        ids.remove(&target);
+                    }
+                }
+                if self.top == Some(target) {
+                    self.top = self.drawings().last().map(|(id, _)| id);
+                }
+                Change::Redraw(chunks)
+            }
+        }
+    }
+
+    /// Merges the changes of several operations applied together into one
+    pub fn combine_changes(&self, changes: impl IntoIterator<Item = Change>) -> Change {
+        let changes: Vec<Change> = changes.into_iter().filter(|c| *c != Change::None).collect();
+        if changes.len() <= 1 {
+            return changes.into_iter().next().unwrap_or(Change::None);
+        }
+        let mut chunks = BTreeSet::new();
+        for change in changes {
+            match change {
+                Change::None => {}
+                Change::DrawnOnTop(id) => {
+                    if let Some(message) = self.drawing(&id) {
+                        chunks.extend(message.chunks());
+                    }
+                }
+                Change::Redraw(c) => chunks.extend(c),
+            }
+        }
+        Change::Redraw(chunks)
+    }
+
+    /// Topmost visible drawing passing within `radius` of `point`, e.g. to find
+    /// what an eraser touches
+    pub fn hit_test(&self, point: Point, radius: u32) -> Option<OpId> {
+        let chunk = ChunkCoordinates::containing(point);
+        let mut around = Vec::with_capacity(9);
+        for x in chunk.x - 1..=chunk.x + 1 {
+            for y in chunk.y - 1..=chunk.y + 1 {
+                around.push(ChunkCoordinates { x, y });
+            }
+        }
+        self.drawings_in(&around)
+            .filter(|(_, m)| distance(m, point) <= radius as f32)
+            .map(|(id, _)| id)
+            .last()
+    }
+
+    /// Draws every visible drawing through `viewport` after clearing it
+    #[cfg(feature = "canvas")]
+    pub fn render<F: FramebufferDraw + ?Sized>(
+        &self,
+        fb: &mut F,
+        viewport: &Viewport,
+    ) -> mxcfb_rect {
+        let screen = viewport.screen;
+        fb.fill_rect(
+            cgmath::Point2 {
+                x: screen.left as i32,
+                y: screen.top as i32,
+            },
+            screen.size(),
+            color::WHITE,
+        );
+        let visible = viewport.visible_chunks();
+        for (_, message) in self.drawings_in(&visible) {
+            draw_message_with(fb, message, viewport, &self.palette);
+        }
+        screen
+    }
+
+    /// Updates the display for a `Change` returned by `apply`, only touching the
+    /// affected chunks. Returns the region that needs to be refreshed.
+    #[cfg(feature = "canvas")]
+    pub fn render_change<F: FramebufferDraw + ?Sized>(
+        &self,
+        fb: &mut F,
+        change: &Change,
+        viewport: &Viewport,
+    ) -> mxcfb_rect {
+        match change {
+            Change::None => mxcfb_rect::invalid(),
+            Change::DrawnOnTop(id) => match self.drawing(id) {
+                Some(message) => draw_message_with(fb, message, viewport, &self.palette),
+                None => mxcfb_rect::invalid(),
+            },
+            Change::Redraw(chunks) => {
+                let mut damage = mxcfb_rect::invalid();
+                for chunk in chunks {
+                    let rect = match viewport.chunk_rect(*chunk) {
+                        Some(rect) => rect,
+                        None => continue,
+                    };
+                    let restricted = viewport.restricted_to(rect);
+                    fb.fill_rect(
+                        cgmath::Point2 {
+                            x: rect.left as i32,
+                            y: rect.top as i32,
+                        },
+                        rect.size(),
+                        color::WHITE,
+                    );
+                    for (_, message) in self.drawings_in(std::iter::once(chunk)) {
+                        draw_message_with(fb, message, &restricted, &self.palette);
+                    }
+                    damage = damage.merge_rect(&rect);
+                }
+                damage
+            }
+        }
+    }
+}
+
+/// Distance between `p` and the closest point of segment `a`-`b`
+fn segment_distance(a: Point, b: Point, p: Point) -> f32 {
+    let (ax, ay, bx, by) = (a.x as f32, a.y as f32, b.x as f32, b.y as f32);
+    let (px, py) = (p.x as f32, p.y as f32);
+    let (dx, dy) = (bx - ax, by - ay);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        (((px - ax) * dx + (py - ay) * dy) / len2).clamp(0.0, 1.0)
+    };
+    let (cx, cy) = (ax + t * dx, ay + t * dy);
+    ((px - cx).powi(2) + (py - cy).powi(2)).sqrt()
+}
+
+/// Distance between `p` and the outline of a drawing
+fn distance(message: &DrawMessage, p: Point) -> f32 {
+    match message {
+        DrawMessage::Dot(dot) => {
+            (segment_distance(dot.position, dot.position, p) - dot.size as f32 / 2.0).max(0.0)
+        }
+        DrawMessage::Line(line) => {
+            (segment_distance(line.start, line.end, p) - line.width as f32 / 2.0).max(0.0)
+        }
+        DrawMessage::Path(path) => match path.points.len() {
+            0 => f32::MAX,
+            1 => segment_distance(path.points[0], path.points[0], p),
+            _ => path
+                .points
+                .windows(2)
+                .map(|w| segment_distance(w[0], w[1], p))
+                .fold(f32::MAX, f32::min),
+        },
+        DrawMessage::PathStep(step) => segment_distance(step.point, step.point, p),
+        DrawMessage::Composite(composite) => composite
+            .items
+            .iter()
+            .map(|m| distance(m, p))
+            .fold(f32::MAX, f32::min),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::protocol::{Color, Dot};
+
+    fn dot(x: i32) -> DrawMessage {
+        DrawMessage::Dot(Dot {
+            position: Point::new(x, 0),
+            size: 4,
+            color: Color::BLACK,
+        })
+    }
+
+    #[test]
+    fn test_concurrent_ops_converge() {
+        let mut a = OpLog::new(1);
+        let mut b = OpLog::new(2);
+
+        let (a1, change) = a.draw(dot(10));
+        assert_eq!(change, Change::DrawnOnTop(a1.id));
+        let (b1, _) = b.draw(dot(20));
+        // b erases a's drawing before a's drawing reached... anyone else
+        let (b2, _) = b.erase(a1.id);
+        let (a2, _) = a.draw(dot(30));
+
+        // Deliver in different orders, with duplicates
+        for op in [b2.clone(), b1.clone(), a2.clone(), b1.clone()] {
+            a.apply(op);
+        }
+        for op in [a2, a1.clone(), b2] {
+            b.apply(op);
+        }
+        let a_state: Vec<_> = a.drawings().collect();
+        let b_state: Vec<_> = b.drawings().collect();
+        assert_eq!(a_state, b_state);
+        assert_eq!(a_state.len(), 2);
+        assert!(a.drawing(&a1.id).is_none());
+
+        // The drawing with the lower timestamp ends up below
+        assert_eq!(a_state[0].0, b1.id);
+        assert_eq!(a.hit_test(Point::new(31, 1), 2), Some(a_state[1].0));
+        assert_eq!(a.hit_test(Point::new(100, 100), 2), None);
+    }
+
+    #[test]
+    fn test_clock_jump() {
+        let mut log = OpLog::new(1);
+        let (first, _) = log.draw(dot(10));
+        let bogus = Op {
+            id: OpId {
+                lamport: u64::MAX,
+                client: 2,
+            },
+            operation: Operation::Draw(dot(20)),
+        };
+        assert_eq!(log.apply(bogus.clone()), Change::None);
+        assert!(!log.contains(&bogus.id));
+
+        // Far ahead, but within the bound. Any peer rejects or accepts the same, no
+        // matter its own clock.
+        let ahead = Op {
+            id: OpId {
+                lamport: MAX_LAMPORT,
+                client: 2,
+            },
+            operation: Operation::Draw(dot(20)),
+        };
+        let mut fresh = OpLog::new(3);
+        assert_eq!(fresh.apply(bogus), Change::None);
+        assert_eq!(fresh.apply(ahead.clone()), Change::DrawnOnTop(ahead.id));
+        assert_eq!(log.apply(ahead.clone()), Change::DrawnOnTop(ahead.id));
+        assert!(log.contains(&first.id));
+
+        // Past it, local drawings are dropped just like they are by peers
+        let (next, change) = log.draw(dot(30));
+        assert_eq!(next.id.lamport, MAX_LAMPORT + 1);
+        assert_eq!(change, Change::None);
+        assert!(!log.contains(&next.id));
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut log = OpLog::new(1);
+        let (first, _) = log.draw(dot(10));
+        let late = Op {
+            id: OpId {
+                lamport: 0,
+                client: 9,
+            },
+            operation: Operation::Draw(dot(12)),
+        };
+        assert!(matches!(log.apply(late.clone()), Change::Redraw(_)));
+        assert_eq!(log.apply(late), Change::None);
+        let (_, change) = log.erase(first.id);
+        assert_eq!(
+            change,
+            Change::Redraw([ChunkCoordinates::new(0, -1), ChunkCoordinates::new(0, 0)].into())
+        );
+        assert_eq!(log.ops_since(1).count(), 1);
+    }
+}
@@ -0,0 +1,259 @@
+//! Cursors of remote participants.
+//!
+//! Cursors are drawn like sprites: before a cursor is drawn, the framebuffer content
+//! underneath is saved, and `hide` puts it back. The usual cycle for every batch of
+//! incoming messages is `hide`, apply the drawing operations, `show`, then refresh
+//! the union of the returned regions.
+
+use std::collections::BTreeMap;
+
+use log::warn;
+
+use crate::canvas::protocol::{Cursor, PathStep, PathStepAction, Point};
+use crate::canvas::viewport::Viewport;
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::{FramebufferDraw, FramebufferIO};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteCursor {
+    pub name: String,
+    pub position: Point,
+    /// Whether the participant is currently drawing a path
+    pub drawing: bool,
+}
+
+/// Framebuffer content covered by a drawn cursor
+struct Sprite {
+    rect: mxcfb_rect,
+    background: Vec<u8>,
+}
+
+pub struct RemoteCursors {
+    cursors: BTreeMap<u64, RemoteCursor>,
+    sprites: Vec<Sprite>,
+    /// Radius of the cursor's circle in pixels
+    pub radius: u32,
+    /// Size of the name label, 0 to hide names
+    pub label_size: f32,
+    pub color: color,
+}
+
+impl Default for RemoteCursors {
+    fn default() -> Self {
+        RemoteCursors::new()
+    }
+}
+
+impl RemoteCursors {
+    pub fn new() -> RemoteCursors {
+        RemoteCursors {
+            cursors: BTreeMap::new(),
+            sprites: Vec::new(),
+            radius: 10,
+            label_size: 24.0,
+            color: color::GRAY(0x80),
+        }
+    }
+
+    pub fn cursors(&self) -> impl Iterator<Item = (&u64, &RemoteCursor)> {
+        self.cursors.iter()
+    }
+
+    /// Whether any cursor is currently drawn on the framebuffer
+    pub fn is_shown(&self) -> bool {
+        !self.sprites.is_empty()
+    }
+
+    /// Updates a cursor from a `Message::Cursor`
+    pub fn handle_cursor(&mut self, cursor: &Cursor) {
+        match cursor.position {
+            Some(position) => {
+                let entry = self
+                    .cursors
+                    .entry(cursor.client)
+                    .or_insert_with(|| RemoteCursor {
+                        name: cursor.name.clone(),
+                        position,
+                        drawing: false,
+                    });
+                entry.name.clone_from(&cursor.name);
+                entry.position = position;
+            }
+            None => {
+                self.cursors.remove(&cursor.client);
+            }
+        }
+    }
+
+    /// Moves the cursor of `client` along with a path it is drawing
+    pub fn handle_path_step(&mut self, client: u64, step: &PathStep) {
+        if let Some(cursor) = self.cursors.get_mut(&client) {
+            cursor.position = step.point;
+            cursor.drawing = step.action != PathStepAction::End;
+        }
+    }
+
+    pub fn remove(&mut self, client: u64) {
+        self.cursors.remove(&client);
+    }
+
+    /// Restores the framebuffer content below all drawn cursors. Returns the region
+    /// that changed.
+    pub fn hide<F: FramebufferIO + ?Sized>(&mut self, fb: &mut F) -> mxcfb_rect {
+        let mut damage = mxcfb_rect::invalid();
+        // Reverse order so that overlapping sprites restore the original content
+        while let Some(sprite) = self.sprites.pop() {
+            if let Err(err) = fb.restore_region(sprite.rect, &sprite.background) {
+                warn!("Failed to restore below cursor: {}", err);
+            }
+            damage = damage.merge_rect(&sprite.rect);
+        }
+        damage
+    }
+
+    /// Area covered by a cursor at `center`, including its label
+    #[allow(unused_variables)]
+    fn sprite_rect<F: FramebufferDraw + ?Sized>(
+        &self,
+        fb: &mut F,
+        center: cgmath::Point2<i32>,
+        name: &str,
+    ) -> mxcfb_rect {
+        let r = self.radius as i32 + 1;
+        let circle = mxcfb_rect {
+            left: (center.x - r).max(0) as u32,
+            top: (center.y - r).max(0) as u32,
+            width: 2 * r as u32 + 1,
+            height: 2 * r as u32 + 1,
+        };
+        #[cfg(feature = "framebuffer-text-drawing")]
+        if self.label_size > 0.0 && !name.is_empty() {
+            let label = fb.draw_text(
+                self.label_position(center),
+                name,
+                self.label_size,
+                self.color,
+                true,
+            );
+            return circle.merge_rect(&label);
+        }
+        circle
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn label_position(&self, center: cgmath::Point2<i32>) -> cgmath::Point2<f32> {
+        cgmath::Point2 {
+            x: (center.x + self.radius as i32 + 4) as f32,
+            y: (center.y + self.radius as i32) as f32,
+        }
+    }
+
+    /// Draws all cursors visible in `viewport`, saving what they cover first. Call
+    /// `hide` before changing anything below them. Returns the region that changed.
+    pub fn show<F: FramebufferIO + FramebufferDraw + ?Sized>(
+        &mut self,
+        fb: &mut F,
+        viewport: &Viewport,
+    ) -> mxcfb_rect {
+        let mut damage = self.hide(fb);
+        let screen = viewport.screen;
+        for cursor in self.cursors.values() {
+            let p = viewport.to_screen(cursor.position);
+            let center = cgmath::Point2 {
+                x: p.x.round() as i32,
+                y: p.y.round() as i32,
+            };
+            let rect = self.sprite_rect(fb, center, &cursor.name);
+            // Only cursors that fit entirely on the canvas are shown so that they never
+            // cover anything outside of it
+            if !screen.contains_rect(&rect) {
+                continue;
+            }
+            let background = match fb.dump_region(rect) {
+                Ok(background) => background,
+                Err(err) => {
+                    warn!("Failed to save below cursor: {}", err);
+                    continue;
+                }
+            };
+            self.sprites.push(Sprite { rect, background });
+
+            fb.draw_circle(center, self.radius, self.color);
+            if cursor.drawing {
+                fb.fill_circle(center, self.radius / 3, self.color);
+            }
+            #[cfg(feature = "framebuffer-text-drawing")]
+            if self.label_size > 0.0 {
+                fb.draw_text(
+                    self.label_position(center),
+                    &cursor.name,
+                    self.label_size,
+                    self.color,
+                    false,
+                );
+            }
+            damage = damage.merge_rect(&rect);
+        }
+        damage
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::core::Framebuffer;
+    use crate::test_util::rect;
+
+    fn cursor(client: u64, x: i32, y: i32) -> Cursor {
+        Cursor {
+            client,
+            name: format!("Peer {}", client),
+            position: Some(Point { x, y }),
+        }
+    }
+
+    fn striped() -> Framebuffer {
+        let mut fb = Framebuffer::headless(300, 200);
+        for x in (0..300).step_by(7) {
+            fb.fill_rect((x, 0).into(), (3, 200).into(), color::BLACK);
+        }
+        fb
+    }
+
+    #[test]
+    fn test_show_and_hide() {
+        // Something below the cursors for them to cover
+        let (mut fb, reference) = (striped(), striped());
+        let screen = rect(0, 0, 300, 200);
+        let viewport = Viewport::new(screen);
+        let mut cursors = RemoteCursors::new();
+
+        // Overlapping cursors, restored in the reverse order they were drawn
+        cursors.handle_cursor(&cursor(1, 40, 40));
+        cursors.handle_cursor(&cursor(2, 45, 45));
+        let shown = cursors.show(&mut fb, &viewport);
+        assert!(cursors.is_shown());
+        assert!(shown.contains_point(&(40, 40).into()));
+        assert_ne!(fb.dump_region(shown), reference.dump_region(shown));
+
+        // Moving a cursor puts back what it covered where it was
+        cursors.handle_cursor(&cursor(1, 200, 120));
+        cursors.remove(2);
+        let moved = cursors.show(&mut fb, &viewport);
+        assert!(moved.contains_rect(&shown));
+        assert_eq!(fb.dump_region(shown), reference.dump_region(shown));
+        assert_ne!(fb.dump_region(screen), reference.dump_region(screen));
+
+        let hidden = cursors.hide(&mut fb);
+        assert!(!cursors.is_shown());
+        assert!(hidden.contains_point(&(200, 120).into()));
+        assert_eq!(fb.dump_region(screen), reference.dump_region(screen));
+
+        // Cursors that don't fit on the canvas aren't drawn
+        cursors.handle_cursor(&cursor(1, 299, 199));
+        cursors.show(&mut fb, &viewport);
+        assert!(!cursors.is_shown());
+        assert_eq!(fb.dump_region(screen), reference.dump_region(screen));
+    }
+}
@@ -0,0 +1,198 @@
+//! Compact encoding of point sequences.
+//!
+//! Consecutive points of a stroke are close to each other, so instead of absolute
+//! coordinates each point is stored as the difference to the previous one, zigzag
+//! encoded into a LEB128 style variable length integer. Most deltas fit in a single
+//! byte, versus two to three bytes per coordinate for absolute values.
+//!
+//! The `points` and `widths` modules plug this into serde for binary formats
+//! (`#[serde(with = "...")]`) while human readable formats such as JSON keep the
+//! plain representation.
+
+use std::fmt;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::canvas::protocol::Point;
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = bytes.split_first()?;
+        *bytes = rest;
+        v |= u64::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Delta encodes a sequence of signed values made of interleaved components (e.g.
+/// `x, y, x, y...` with a `stride` of 2). Each component is delta encoded separately.
+fn encode_values(values: impl Iterator<Item = i64>, stride: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut previous = vec![0i64; stride];
+    for (i, v) in values.enumerate() {
+        let prev = &mut previous[i % stride];
+        write_varint(&mut out, zigzag(v.wrapping_sub(*prev)));
+        *prev = v;
+    }
+    out
+}
+
+fn decode_values(mut bytes: &[u8], stride: usize) -> Option<Vec<i64>> {
+    let mut values = Vec::new();
+    let mut previous = vec![0i64; stride];
+    while !bytes.is_empty() {
+        let prev = &mut previous[values.len() % stride];
+        *prev = prev.wrapping_add(unzigzag(read_varint(&mut bytes)?));
+        values.push(*prev);
+    }
+    Some(values)
+}
+
+/// Delta encodes `points`
+pub fn encode_points(points: &[Point]) -> Vec<u8> {
+    encode_values(
+        points.iter().flat_map(|p| [i64::from(p.x), i64::from(p.y)]),
+        2,
+    )
+}
+
+/// Decodes points encoded with `encode_points`. Returns `None` if the data is
+/// truncated or otherwise malformed.
+pub fn decode_points(bytes: &[u8]) -> Option<Vec<Point>> {
+    let values = decode_values(bytes, 2)?;
+    if values.len() % 2 != 0 {
+        return None;
+    }
+    values
+        .chunks(2)
+        .map(|c| {
+            Some(Point {
+                x: i32::try_from(c[0]).ok()?,
+                y: i32::try_from(c[1]).ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Delta encodes `widths`
+pub fn encode_widths(widths: &[u32]) -> Vec<u8> {
+    encode_values(widths.iter().map(|w| i64::from(*w)), 1)
+}
+
+/// Decodes widths encoded with `encode_widths`
+pub fn decode_widths(bytes: &[u8]) -> Option<Vec<u32>> {
+    decode_values(bytes, 1)?
+        .into_iter()
+        .map(|v| u32::try_from(v).ok())
+        .collect()
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "delta encoded bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+}
+
+/// Serde adapter for `Vec<Point>`
+pub mod points {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(points: &[Point], s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            points.serialize(s)
+        } else {
+            s.serialize_bytes(&encode_points(points))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Point>, D::Error> {
+        if d.is_human_readable() {
+            Vec::<Point>::deserialize(d)
+        } else {
+            let bytes = d.deserialize_bytes(BytesVisitor)?;
+            decode_points(&bytes).ok_or_else(|| de::Error::custom("malformed delta encoded points"))
+        }
+    }
+}
+
+/// Serde adapter for `Vec<u32>` stroke widths
+pub mod widths {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(widths: &[u32], s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            widths.serialize(s)
+        } else {
+            s.serialize_bytes(&encode_widths(widths))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u32>, D::Error> {
+        if d.is_human_readable() {
+            Vec::<u32>::deserialize(d)
+        } else {
+            let bytes = d.deserialize_bytes(BytesVisitor)?;
+            decode_widths(&bytes).ok_or_else(|| de::Error::custom("malformed delta encoded widths"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let points: Vec<Point> = (0..100)
+            .map(|i| Point::new(1000 + i * 2, -500 - (i % 7)))
+            .chain([
+                Point::new(i32::MIN, i32::MAX),
+                Point::new(i32::MAX, i32::MIN),
+            ])
+            .collect();
+        let encoded = encode_points(&points);
+        assert_eq!(decode_points(&encoded), Some(points));
+        // The first point takes 2 bytes per coordinate, small deltas take one
+        assert!(encoded.len() < 2 * 102 + 30);
+
+        assert_eq!(
+            decode_widths(&encode_widths(&[3, 4, 4, 2])),
+            Some(vec![3, 4, 4, 2])
+        );
+        assert_eq!(decode_points(&[0x80]), None);
+        assert_eq!(decode_points(&[0x02]), None);
+    }
+}
@@ -0,0 +1,40 @@
+//! Building blocks for applications that share a canvas between several devices.
+//!
+//! The canvas is an unbounded plane split into square chunks of `protocol::CHUNK_SIZE`
+//! pixels. Clients subscribe to the chunks they are displaying and exchange drawing
+//! operations using the messages in `protocol`.
+
+/// Reusing the pixels of previously rendered chunks
+#[cfg(feature = "canvas")]
+pub mod cache;
+/// Reconnecting, buffering connection to a canvas server
+pub mod connection;
+/// Operation log that lets concurrent edits from several clients converge
+pub mod crdt;
+/// Showing where other participants are
+#[cfg(feature = "canvas")]
+pub mod cursors;
+/// Delta encoding of point sequences used by the binary protocol
+pub mod delta;
+/// Mapping colors onto grayscale panels
+#[cfg(feature = "canvas")]
+pub mod palette;
+/// Versioned wire format shared by canvas clients and servers
+pub mod protocol;
+/// Recording drawing sessions and playing them back
+#[cfg(feature = "canvas")]
+pub mod replay;
+/// Saving the canvas to disk and loading it back chunk by chunk
+#[cfg(feature = "canvas")]
+pub mod store;
+/// Keeping the chunk subscription in sync with the viewport
+#[cfg(feature = "canvas")]
+pub mod subscription;
+/// Storage and rendering of the canvas content
+#[cfg(feature = "canvas")]
+pub mod surface;
+/// Undo and redo of local changes to a shared canvas
+pub mod undo;
+/// Mapping between canvas and screen coordinates, plus pan and zoom gestures
+#[cfg(feature = "canvas")]
+pub mod viewport;
@@ -0,0 +1,142 @@
+//! Showing colored drawings on grayscale panels.
+//!
+//! Peers with color displays (or other apps) may draw in any `Color`. A `Palette`
+//! decides what that becomes on an e-ink panel: a gray level derived from the
+//! color, an explicit level for specific colors, and optionally a dash pattern so
+//! that colors which map to similar grays remain distinguishable.
+
+use std::collections::HashMap;
+
+use crate::canvas::protocol::Color;
+use crate::framebuffer::common::color;
+
+/// How colors without an explicit level are turned into gray
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum GrayPolicy {
+    /// Pass colors to the framebuffer unchanged
+    #[default]
+    Preserve,
+    /// The gray of the same luminance
+    Luminance,
+    /// Black if the luminance is below the threshold, white otherwise
+    Threshold(u8),
+    /// Always black
+    Black,
+}
+
+/// Stroke pattern, lengths are in screen pixels
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Pattern {
+    #[default]
+    Solid,
+    Dashed {
+        on: u32,
+        off: u32,
+    },
+    Dotted {
+        spacing: u32,
+    },
+}
+
+impl Pattern {
+    /// Lengths of the drawn and skipped parts of one period, `None` for solid lines
+    pub fn dashes(self) -> Option<(f32, f32)> {
+        match self {
+            Pattern::Solid => None,
+            Pattern::Dashed { on, off } => Some((on.max(1) as f32, off as f32)),
+            Pattern::Dotted { spacing } => Some((1.0, spacing.max(1) as f32)),
+        }
+    }
+}
+
+/// What a drawing ends up being drawn with
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pen {
+    pub color: color,
+    pub pattern: Pattern,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Palette {
+    pub policy: GrayPolicy,
+    /// Gray levels (0 is black, 255 white) for specific colors, overriding `policy`
+    pub levels: HashMap<Color, u8>,
+    /// Patterns to draw specific colors with
+    pub patterns: HashMap<Color, Pattern>,
+}
+
+/// Perceived brightness of `c` (ITU-R BT.601 weights), 0 is black
+pub fn luminance(c: Color) -> u8 {
+    ((299 * u32::from(c.r) + 587 * u32::from(c.g) + 114 * u32::from(c.b)) / 1000) as u8
+}
+
+impl Palette {
+    /// A palette for grayscale panels, showing red, green and blue with distinct
+    /// gray levels and patterns
+    pub fn grayscale() -> Palette {
+        let mut palette = Palette {
+            policy: GrayPolicy::Luminance,
+            ..Palette::default()
+        };
+        for (c, level, pattern) in [
+            (
+                Color::rgb(255, 0, 0),
+                0x50,
+                Pattern::Dashed { on: 12, off: 6 },
+            ),
+            (Color::rgb(0, 255, 0), 0x90, Pattern::Solid),
+            (Color::rgb(0, 0, 255), 0x30, Pattern::Dotted { spacing: 6 }),
+        ] {
+            palette.levels.insert(c, level);
+            palette.patterns.insert(c, pattern);
+        }
+        palette
+    }
+
+    /// The framebuffer color `c` is drawn with
+    pub fn color(&self, c: Color) -> color {
+        let gray = |level: u8| color::RGB(level, level, level);
+        if let Some(level) = self.levels.get(&c) {
+            return gray(*level);
+        }
+        match self.policy {
+            GrayPolicy::Preserve => c.into(),
+            GrayPolicy::Luminance => gray(luminance(c)),
+            GrayPolicy::Threshold(threshold) if luminance(c) < threshold => color::BLACK,
+            GrayPolicy::Threshold(_) => color::WHITE,
+            GrayPolicy::Black => color::BLACK,
+        }
+    }
+
+    pub fn pen(&self, c: Color) -> Pen {
+        Pen {
+            color: self.color(c),
+            pattern: self.patterns.get(&c).copied().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        let red = Color::rgb(255, 0, 0);
+        let yellow = Color::rgb(255, 255, 0);
+        let mut palette = Palette::default();
+        assert_eq!(palette.color(red), color::RGB(255, 0, 0));
+
+        palette.policy = GrayPolicy::Threshold(128);
+        assert_eq!(palette.color(red), color::BLACK);
+        assert_eq!(palette.color(yellow), color::WHITE);
+
+        palette.policy = GrayPolicy::Luminance;
+        assert_eq!(palette.color(yellow), color::RGB(225, 225, 225));
+
+        let palette = Palette::grayscale();
+        assert_eq!(palette.color(red), color::RGB(0x50, 0x50, 0x50));
+        assert_eq!(palette.pen(red).pattern, Pattern::Dashed { on: 12, off: 6 });
+        assert_eq!(palette.pen(Color::BLACK).color, color::RGB(0, 0, 0));
+    }
+}
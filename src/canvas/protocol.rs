@@ -0,0 +1,717 @@
+//! Wire format for collaborative canvases.
+//!
+//! Every message is wrapped in an `Envelope` carrying the `PROTOCOL_VERSION` it was
+//! encoded with. Two encodings are provided: a compact binary one based on
+//! `postcard` for device to device traffic, and JSON for debugging and web clients.
+//!
+//! In the binary encoding the points and widths of `Path`s are delta encoded (see
+//! `canvas::delta`), JSON keeps them as plain lists.
+//!
+//! Compatibility rules: new message variants and fields may only be appended (which
+//! bumps `PROTOCOL_VERSION`). Decoders reject messages from a newer version than they
+//! know about, or older than `MIN_PROTOCOL_VERSION`, with
+//! `CodecError::UnsupportedVersion`.
+
+use std::cell::Cell;
+use std::collections::BTreeSet;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[cfg(feature = "framebuffer-types")]
+use crate::framebuffer::common::color;
+
+/// Version of the protocol implemented by this module
+pub const PROTOCOL_VERSION: u16 = 5;
+
+/// Oldest version that can still be decoded. Version 4 changed the binary encoding
+/// of `Path`.
+pub const MIN_PROTOCOL_VERSION: u16 = 4;
+
+/// Width and height of a canvas chunk in pixels
+pub const CHUNK_SIZE: u32 = 256;
+
+/// How far from the origin drawing operations may reach in either direction, in
+/// pixels. Decoding rejects messages going further.
+pub const MAX_COORDINATE: i32 = 1 << 20;
+
+/// Largest width of lines and paths and size of dots, in pixels. Decoding rejects
+/// messages with larger ones.
+pub const MAX_WIDTH: u32 = 1024;
+
+/// How deeply `Composite`s may be nested in one another. Decoding rejects messages
+/// nesting them deeper, before they overflow the stack.
+pub const MAX_NESTING: usize = 32;
+
+/// Position of a chunk on the canvas, in chunks
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkCoordinates {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl ChunkCoordinates {
+    pub fn new(x: i32, y: i32) -> ChunkCoordinates {
+        ChunkCoordinates { x, y }
+    }
+
+    /// The chunk `point` falls into
+    pub fn containing(point: Point) -> ChunkCoordinates {
+        ChunkCoordinates {
+            x: point.x.div_euclid(CHUNK_SIZE as i32),
+            y: point.y.div_euclid(CHUNK_SIZE as i32),
+        }
+    }
+
+    /// Canvas position of the chunk's top left pixel
+    pub fn origin(&self) -> Point {
+        Point {
+            x: self.x * CHUNK_SIZE as i32,
+            y: self.y * CHUNK_SIZE as i32,
+        }
+    }
+}
+
+/// A position on the canvas, in pixels
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Point {
+    pub fn new(x: i32, y: i32) -> Point {
+        Point { x, y }
+    }
+}
+
+/// An 8 bit per channel RGB color
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+    pub const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    pub fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+}
+
+#[cfg(feature = "framebuffer-types")]
+impl From<color> for Color {
+    fn from(c: color) -> Self {
+        let [r, g, b] = c.to_rgb8();
+        Color { r, g, b }
+    }
+}
+
+#[cfg(feature = "framebuffer-types")]
+impl From<Color> for color {
+    fn from(c: Color) -> Self {
+        color::RGB(c.r, c.g, c.b)
+    }
+}
+
+/// A filled circle
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Dot {
+    pub position: Point,
+    /// Diameter in pixels
+    pub size: u32,
+    pub color: Color,
+}
+
+/// A straight line
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub start: Point,
+    pub end: Point,
+    pub width: u32,
+    pub color: Color,
+}
+
+/// A finished polyline with a width per point
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    /// Identifies the path among the ones created by the same client
+    pub id: u64,
+    #[serde(with = "crate::canvas::delta::points")]
+    pub points: Vec<Point>,
+    /// Width at each of the `points`
+    #[serde(with = "crate::canvas::delta::widths")]
+    pub widths: Vec<u32>,
+    pub color: Color,
+}
+
+/// What a `PathStep` does to the path it refers to
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PathStepAction {
+    /// Begins a new path at the step's point
+    Start,
+    /// Extends the path to the step's point
+    Continue,
+    /// Ends the path. The step's point is the last point of the path.
+    End,
+}
+
+/// Incremental update to a `Path` that is still being drawn. Lets peers follow a
+/// stroke live instead of waiting for the pen to be lifted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PathStep {
+    pub path_id: u64,
+    pub action: PathStepAction,
+    pub point: Point,
+    pub width: u32,
+    pub color: Color,
+}
+
+/// Several drawing operations that have to be applied together
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Composite {
+    pub items: Vec<DrawMessage>,
+}
+
+thread_local! {
+    /// Of the `Composite`s being decoded on this thread
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+impl<'de> Deserialize<'de> for Composite {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "Composite")]
+        struct Items {
+            items: Vec<DrawMessage>,
+        }
+
+        let depth = NESTING.with(Cell::get);
+        if depth == MAX_NESTING {
+            return Err(D::Error::custom("composites nested too deeply"));
+        }
+        NESTING.with(|nesting| nesting.set(depth + 1));
+        let items = Items::deserialize(deserializer);
+        NESTING.with(|nesting| nesting.set(depth));
+        Ok(Composite {
+            items: items?.items,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum DrawMessage {
+    Dot(Dot),
+    Line(Line),
+    Path(Path),
+    PathStep(PathStep),
+    Composite(Composite),
+}
+
+impl DrawMessage {
+    /// The chunks touched by this drawing operation. Coordinates and widths beyond
+    /// `MAX_COORDINATE` and `MAX_WIDTH` are clamped to them.
+    pub fn chunks(&self) -> BTreeSet<ChunkCoordinates> {
+        let mut chunks = BTreeSet::new();
+        self.collect_chunks(&mut chunks);
+        chunks
+    }
+
+    fn collect_chunks(&self, chunks: &mut BTreeSet<ChunkCoordinates>) {
+        match self {
+            DrawMessage::Dot(dot) => add_segment(chunks, dot.position, dot.position, dot.size),
+            DrawMessage::Line(line) => add_segment(chunks, line.start, line.end, line.width),
+            DrawMessage::Path(path) => {
+                let width = |i: usize| path.widths.get(i).copied().unwrap_or(1);
+                if let [point] = path.points[..] {
+                    add_segment(chunks, point, point, width(0));
+                }
+                for (i, pair) in path.points.windows(2).enumerate() {
+                    let width = width(i).max(width(i + 1));
+                    add_segment(chunks, pair[0], pair[1], width);
+                }
+            }
+            DrawMessage::PathStep(step) => add_segment(chunks, step.point, step.point, step.width),
+            DrawMessage::Composite(composite) => {
+                for item in &composite.items {
+                    item.collect_chunks(chunks);
+                }
+            }
+        }
+    }
+
+    /// Whether all coordinates are within `MAX_COORDINATE` and all widths within
+    /// `MAX_WIDTH`
+    pub fn is_in_range(&self) -> bool {
+        let point = |p: &Point| {
+            p.x.unsigned_abs() <= MAX_COORDINATE as u32
+                && p.y.unsigned_abs() <= MAX_COORDINATE as u32
+        };
+        match self {
+            DrawMessage::Dot(dot) => point(&dot.position) && dot.size <= MAX_WIDTH,
+            DrawMessage::Line(line) => {
+                point(&line.start) && point(&line.end) && line.width <= MAX_WIDTH
+            }
+            DrawMessage::Path(path) => {
+                path.points.iter().all(point) && path.widths.iter().all(|w| *w <= MAX_WIDTH)
+            }
+            DrawMessage::PathStep(step) => point(&step.point) && step.width <= MAX_WIDTH,
+            DrawMessage::Composite(composite) => composite.items.iter().all(Self::is_in_range),
+        }
+    }
+}
+
+/// Adds the chunks overlapped by the line from `start` to `end`, `width` wide with
+/// square ends, going along it a column of chunks at a time
+fn add_segment(chunks: &mut BTreeSet<ChunkCoordinates>, start: Point, end: Point, width: u32) {
+    let clamp = |p: Point| Point {
+        x: p.x.clamp(-MAX_COORDINATE, MAX_COORDINATE),
+        y: p.y.clamp(-MAX_COORDINATE, MAX_COORDINATE),
+    };
+    let (a, b) = match start.x <= end.x {
+        true => (clamp(start), clamp(end)),
+        false => (clamp(end), clamp(start)),
+    };
+    let r = (width.min(MAX_WIDTH) / 2) as i32;
+    let size = CHUNK_SIZE as i32;
+    // Where the line is at `x`
+    let y_at = |x: i32| match b.x - a.x {
+        0 => f64::from(a.y),
+        dx => f64::from(a.y) + f64::from(b.y - a.y) * f64::from(x - a.x) / f64::from(dx),
+    };
+    let from = ChunkCoordinates::containing(Point::new(a.x.saturating_sub(r), 0));
+    let to = ChunkCoordinates::containing(Point::new(b.x.saturating_add(r), 0));
+    for x in from.x..=to.x {
+        // The part of the line within reach of this column
+        let left = (x * size).saturating_sub(r).max(a.x);
+        let right = ((x + 1) * size - 1).saturating_add(r).min(b.x);
+        let (top, bottom) = match b.x - a.x {
+            0 => (a.y.min(b.y), a.y.max(b.y)),
+            _ => {
+                let (y0, y1) = (y_at(left), y_at(right));
+                (y0.min(y1).floor() as i32, y0.max(y1).ceil() as i32)
+            }
+        };
+        let from = ChunkCoordinates::containing(Point::new(0, top.saturating_sub(r)));
+        let to = ChunkCoordinates::containing(Point::new(0, bottom.saturating_add(r)));
+        for y in from.y..=to.y {
+            chunks.insert(ChunkCoordinates { x, y });
+        }
+    }
+}
+
+/// The set of chunks a client wants to receive updates for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Subscription {
+    pub chunks: BTreeSet<ChunkCoordinates>,
+}
+
+impl Subscription {
+    pub fn new(chunks: impl IntoIterator<Item = ChunkCoordinates>) -> Subscription {
+        Subscription {
+            chunks: chunks.into_iter().collect(),
+        }
+    }
+
+    pub fn contains(&self, chunk: &ChunkCoordinates) -> bool {
+        self.chunks.contains(chunk)
+    }
+
+    /// Chunks `other` is subscribed to but `self` isn't
+    pub fn missing_from_self(&self, other: &Subscription) -> BTreeSet<ChunkCoordinates> {
+        other.chunks.difference(&self.chunks).copied().collect()
+    }
+
+    /// Chunks `self` is subscribed to but `other` isn't
+    pub fn missing_from_other(&self, other: &Subscription) -> BTreeSet<ChunkCoordinates> {
+        self.chunks.difference(&other.chunks).copied().collect()
+    }
+
+    /// Applies the changes of a `Message::UpdateSubscription`
+    pub fn update(
+        &mut self,
+        subscribed: &BTreeSet<ChunkCoordinates>,
+        unsubscribed: &BTreeSet<ChunkCoordinates>,
+    ) {
+        self.chunks.extend(subscribed);
+        for chunk in unsubscribed {
+            self.chunks.remove(chunk);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Replaces the sender's current subscription
+    Subscribe(Subscription),
+    Draw(DrawMessage),
+    /// Adds and removes chunks from the sender's current subscription (since version 2)
+    UpdateSubscription {
+        subscribed: BTreeSet<ChunkCoordinates>,
+        unsubscribed: BTreeSet<ChunkCoordinates>,
+    },
+    /// An entry of the shared operation log, see `canvas::crdt` (since version 3)
+    Op(Op),
+    /// Where another participant currently is (since version 5)
+    Cursor(Cursor),
+}
+
+/// Position of a participant's pen, shared so others can see where they are drawing
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub client: u64,
+    /// Display name of the participant
+    pub name: String,
+    /// `None` once the pen left the canvas
+    pub position: Option<Point>,
+}
+
+/// Globally unique identifier of an `Op`. The ordering (Lamport timestamp first,
+/// client id to break ties) is the order in which operations are stacked.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OpId {
+    pub lamport: u64,
+    pub client: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Adds a drawing to the canvas
+    Draw(DrawMessage),
+    /// Removes the drawing added by the given operation
+    Erase(OpId),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Op {
+    pub id: OpId,
+    pub operation: Operation,
+}
+
+/// A `Message` along with the protocol version it was encoded with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub version: u16,
+    pub message: Message,
+}
+
+impl Envelope {
+    /// Wraps `message` with the current `PROTOCOL_VERSION`
+    pub fn new(message: Message) -> Envelope {
+        Envelope {
+            version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+}
+
+/// Differentiate between the reasons why encoding or decoding a message can fail.
+#[derive(Debug)]
+pub enum CodecError {
+    /// The message was encoded with a protocol version this crate doesn't understand
+    UnsupportedVersion(u16),
+    /// The message draws further out or wider than `MAX_COORDINATE` and `MAX_WIDTH`
+    OutOfRange,
+    Postcard(postcard::Error),
+    Json(serde_json::Error),
+}
+
+impl From<postcard::Error> for CodecError {
+    fn from(err: postcard::Error) -> Self {
+        CodecError::Postcard(err)
+    }
+}
+
+impl From<serde_json::Error> for CodecError {
+    fn from(err: serde_json::Error) -> Self {
+        CodecError::Json(err)
+    }
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported canvas protocol version {}", version)
+            }
+            CodecError::OutOfRange => write!(f, "Canvas drawing out of range"),
+            CodecError::Postcard(err) => err.fmt(f),
+            CodecError::Json(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub(crate) fn check_version(version: u16) -> Result<(), CodecError> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        Err(CodecError::UnsupportedVersion(version))
+    } else {
+        Ok(())
+    }
+}
+
+/// Rejects messages drawing out of range, which would take unbounded work to index
+fn check_range(message: Message) -> Result<Message, CodecError> {
+    let in_range = match message {
+        Message::Draw(ref draw)
+        | Message::Op(Op {
+            operation: Operation::Draw(ref draw),
+            ..
+        }) => draw.is_in_range(),
+        _ => true,
+    };
+    match in_range {
+        true => Ok(message),
+        false => Err(CodecError::OutOfRange),
+    }
+}
+
+/// Encodes `message` with postcard
+pub fn to_postcard(message: &Message) -> Result<Vec<u8>, CodecError> {
+    Ok(postcard::to_allocvec(&Envelope::new(message.clone()))?)
+}
+
+/// Decodes a message encoded with `to_postcard`
+pub fn from_postcard(bytes: &[u8]) -> Result<Message, CodecError> {
+    // The version is checked before the rest so that messages of newer versions
+    // aren't reported as garbage
+    let (version, _) = postcard::take_from_bytes::<u16>(bytes)?;
+    check_version(version)?;
+    check_range(postcard::from_bytes::<Envelope>(bytes)?.message)
+}
+
+/// Encodes `message` as JSON
+pub fn to_json(message: &Message) -> Result<String, CodecError> {
+    Ok(serde_json::to_string(&Envelope::new(message.clone()))?)
+}
+
+/// Decodes a message encoded with `to_json`
+pub fn from_json(json: &str) -> Result<Message, CodecError> {
+    #[derive(Deserialize)]
+    struct Version {
+        version: u16,
+    }
+    check_version(serde_json::from_str::<Version>(json)?.version)?;
+    check_range(serde_json::from_str::<Envelope>(json)?.message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_messages() -> Vec<Message> {
+        let path = Path {
+            id: 42,
+            points: vec![Point::new(-5, 3), Point::new(300, 12), Point::new(310, 600)],
+            widths: vec![2, 3, 4],
+            color: Color::rgb(10, 20, 30),
+        };
+        vec![
+            Message::UpdateSubscription {
+                subscribed: [ChunkCoordinates::new(3, 4)].into_iter().collect(),
+                unsubscribed: BTreeSet::new(),
+            },
+            Message::Op(Op {
+                id: OpId {
+                    lamport: 12,
+                    client: 3,
+                },
+                operation: Operation::Erase(OpId {
+                    lamport: 2,
+                    client: 1,
+                }),
+            }),
+            Message::Cursor(Cursor {
+                client: 3,
+                name: "Ada".to_owned(),
+                position: Some(Point::new(-4, 8)),
+            }),
+            Message::Subscribe(Subscription::new(vec![
+                ChunkCoordinates::new(0, 0),
+                ChunkCoordinates::new(-1, 2),
+            ])),
+            Message::Draw(DrawMessage::Dot(Dot {
+                position: Point::new(1, 2),
+                size: 5,
+                color: Color::BLACK,
+            })),
+            Message::Draw(DrawMessage::Composite(Composite {
+                items: vec![
+                    DrawMessage::Path(path),
+                    DrawMessage::Line(Line {
+                        start: Point::new(0, 0),
+                        end: Point::new(-1000, 1000),
+                        width: 1,
+                        color: Color::WHITE,
+                    }),
+                    DrawMessage::PathStep(PathStep {
+                        path_id: 7,
+                        action: PathStepAction::End,
+                        point: Point::new(9, 9),
+                        width: 2,
+                        color: Color::BLACK,
+                    }),
+                ],
+            })),
+        ]
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        for message in sample_messages() {
+            let bytes = to_postcard(&message).unwrap();
+            assert_eq!(from_postcard(&bytes).unwrap(), message);
+            let json = to_json(&message).unwrap();
+            assert_eq!(from_json(&json).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_versions_are_rejected() {
+        let message = sample_messages().remove(0);
+        let mut envelope = Envelope::new(message);
+        envelope.version = PROTOCOL_VERSION + 1;
+
+        let bytes = postcard::to_allocvec(&envelope).unwrap();
+        assert!(matches!(
+            from_postcard(&bytes),
+            Err(CodecError::UnsupportedVersion(v)) if v == PROTOCOL_VERSION + 1
+        ));
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert!(matches!(
+            from_json(&json),
+            Err(CodecError::UnsupportedVersion(_))
+        ));
+
+        envelope.version = MIN_PROTOCOL_VERSION - 1;
+        let bytes = postcard::to_allocvec(&envelope).unwrap();
+        assert!(matches!(
+            from_postcard(&bytes),
+            Err(CodecError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_subscription_diff_and_chunks() {
+        let a = Subscription::new(vec![
+            ChunkCoordinates::new(0, 0),
+            ChunkCoordinates::new(1, 0),
+        ]);
+        let b = Subscription::new(vec![
+            ChunkCoordinates::new(1, 0),
+            ChunkCoordinates::new(2, 0),
+        ]);
+        assert_eq!(
+            a.missing_from_self(&b).into_iter().collect::<Vec<_>>(),
+            vec![ChunkCoordinates::new(2, 0)]
+        );
+        assert_eq!(
+            a.missing_from_other(&b).into_iter().collect::<Vec<_>>(),
+            vec![ChunkCoordinates::new(0, 0)]
+        );
+
+        let line = DrawMessage::Line(Line {
+            start: Point::new(-1, 10),
+            end: Point::new(CHUNK_SIZE as i32 + 1, 10),
+            width: 1,
+            color: Color::BLACK,
+        });
+        assert_eq!(
+            line.chunks().into_iter().collect::<Vec<_>>(),
+            vec![
+                ChunkCoordinates::new(-1, 0),
+                ChunkCoordinates::new(0, 0),
+                ChunkCoordinates::new(1, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hostile_messages() {
+        let line = Line {
+            start: Point::new(i32::MIN / 2, i32::MIN / 2),
+            end: Point::new(i32::MAX / 2, i32::MAX / 2),
+            width: u32::MAX,
+            color: Color::BLACK,
+        };
+        let path = Path {
+            id: 1,
+            points: vec![Point::new(i32::MIN, 0), Point::new(i32::MAX, 0)],
+            widths: vec![u32::MAX, 0],
+            color: Color::BLACK,
+        };
+        for message in [DrawMessage::Line(line), DrawMessage::Path(path)] {
+            assert!(!message.is_in_range());
+            let bytes = to_postcard(&Message::Draw(message.clone())).unwrap();
+            assert!(matches!(from_postcard(&bytes), Err(CodecError::OutOfRange)));
+            let json = to_json(&Message::Draw(message.clone())).unwrap();
+            assert!(matches!(from_json(&json), Err(CodecError::OutOfRange)));
+            // Clamped, and along the line rather than all over its bounding box
+            let chunks = message.chunks().len() as i64;
+            let across = 2 * i64::from(MAX_COORDINATE) / i64::from(CHUNK_SIZE) + 1;
+            assert!(chunks < across * 16);
+        }
+    }
+
+    #[test]
+    fn test_nesting() {
+        fn nested(depth: usize) -> Vec<u8> {
+            let empty = Message::Draw(DrawMessage::Composite(Composite::default()));
+            let bytes = to_postcard(&empty).unwrap();
+            // Ends with the variant of the composite and its number of items
+            let (envelope, innermost) = bytes.split_at(bytes.len() - 2);
+            let composite = innermost[0];
+            let mut bytes = envelope.to_vec();
+            for _ in 1..depth {
+                bytes.extend_from_slice(&[composite, 1]);
+            }
+            bytes.extend_from_slice(innermost);
+            bytes
+        }
+
+        assert!(from_postcard(&nested(MAX_NESTING)).is_ok());
+        for depth in [MAX_NESTING + 1, 1_000_000] {
+            assert!(matches!(
+                from_postcard(&nested(depth)),
+                Err(CodecError::Postcard(_))
+            ));
+        }
+        // Composites nested too deeply are rejected without affecting later messages
+        assert!(from_postcard(&nested(MAX_NESTING)).is_ok());
+    }
+
+    #[test]
+    fn test_path_chunks() {
+        // Wide enough to reach into the chunks above and below
+        let path = DrawMessage::Path(Path {
+            id: 1,
+            points: vec![Point::new(30, 0), Point::new(100, 0), Point::new(300, 0)],
+            widths: vec![1, 40, 1],
+            color: Color::BLACK,
+        });
+        assert_eq!(
+            path.chunks().into_iter().collect::<Vec<_>>(),
+            vec![
+                ChunkCoordinates::new(0, -1),
+                ChunkCoordinates::new(0, 0),
+                ChunkCoordinates::new(1, -1),
+                ChunkCoordinates::new(1, 0),
+            ]
+        );
+        let diagonal = DrawMessage::Line(Line {
+            start: Point::new(0, 0),
+            end: Point::new(CHUNK_SIZE as i32 * 4 - 1, CHUNK_SIZE as i32 * 4 - 1),
+            width: 1,
+            color: Color::BLACK,
+        });
+        assert_eq!(diagonal.chunks().len(), 4);
+    }
+}
@@ -0,0 +1,356 @@
+//! Recording drawing sessions and playing them back.
+//!
+//! A `Recorder` timestamps every drawing operation it is given (local or received
+//! from other participants). The resulting `Recording` can be saved, and a `Replayer`
+//! draws it again onto the framebuffer at any speed, e.g. for tutorials or to
+//! reproduce synchronization problems step by step.
+
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::palette::Palette;
+use crate::canvas::protocol::{self, DrawMessage, PathStepAction, Point};
+use crate::canvas::store::StoreError;
+use crate::canvas::surface::draw_message_with;
+use crate::canvas::viewport::Viewport;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::FramebufferDraw;
+
+#[cfg(feature = "framebuffer")]
+use crate::framebuffer::common::{display_temp, dither_mode, waveform_mode};
+#[cfg(feature = "framebuffer")]
+use crate::framebuffer::core;
+#[cfg(feature = "framebuffer")]
+use crate::framebuffer::{FramebufferRefresh, PartialRefreshMode};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    /// Time since the start of the recording
+    pub at: Duration,
+    /// Client that drew the message
+    pub client: u64,
+    pub message: DrawMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    version: u16,
+    /// Recorded messages, ordered by time
+    pub messages: Vec<RecordedMessage>,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Recording {
+            version: protocol::PROTOCOL_VERSION,
+            messages: Vec::new(),
+        }
+    }
+}
+
+impl Recording {
+    /// Time of the last message
+    pub fn duration(&self) -> Duration {
+        self.messages.last().map(|m| m.at).unwrap_or_default()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StoreError> {
+        fs::write(path, postcard::to_allocvec(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Recording, StoreError> {
+        let bytes = fs::read(path)?;
+        let (version, _) = postcard::take_from_bytes::<u16>(&bytes)?;
+        protocol::check_version(version)?;
+        Ok(postcard::from_bytes(&bytes)?)
+    }
+}
+
+pub struct Recorder {
+    start: Instant,
+    recording: Recording,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder::new()
+    }
+}
+
+impl Recorder {
+    /// Starts a recording now
+    pub fn new() -> Recorder {
+        Recorder {
+            start: Instant::now(),
+            recording: Recording::default(),
+        }
+    }
+
+    /// Records `message` drawn by `client` just now
+    pub fn record(&mut self, client: u64, message: &DrawMessage) {
+        self.record_at(self.start.elapsed(), client, message);
+    }
+
+    /// Records `message` at `at` after the start. Times before the previous message
+    /// are moved up to it to keep the recording ordered.
+    pub fn record_at(&mut self, at: Duration, client: u64, message: &DrawMessage) {
+        let at = at.max(self.recording.duration());
+        self.recording.messages.push(RecordedMessage {
+            at,
+            client,
+            message: message.clone(),
+        });
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+/// Plays a `Recording` back.
+///
+/// Like the animation `Player`, the replayer is clocked by the caller: pass the
+/// wall clock time elapsed since the last call to `advance` or `render`.
+pub struct Replayer {
+    recording: Recording,
+    position: usize,
+    time: Duration,
+    speed: f32,
+    /// Pauses between messages longer than this are shortened to it
+    pub max_gap: Option<Duration>,
+    /// Last point of every path that is being drawn step by step
+    path_ends: HashMap<(u64, u64), Point>,
+    /// Colors used when rendering
+    pub palette: Palette,
+}
+
+impl Replayer {
+    pub fn new(recording: Recording) -> Replayer {
+        Replayer {
+            recording,
+            position: 0,
+            time: Duration::ZERO,
+            speed: 1.0,
+            max_gap: None,
+            path_ends: HashMap::new(),
+            palette: Palette::default(),
+        }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Playback speed, 2.0 plays twice as fast and 0.0 pauses
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Sets the playback speed. Negative, infinite and NaN speeds pause.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = match speed.is_finite() && speed > 0.0 {
+            true => speed,
+            false => 0.0,
+        };
+    }
+
+    /// Whether all messages have been played
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.recording.messages.len()
+    }
+
+    /// Goes back to the start of the recording
+    pub fn rewind(&mut self) {
+        self.position = 0;
+        self.time = Duration::ZERO;
+        self.path_ends.clear();
+    }
+
+    /// Wall clock time until the next message is due, `None` if finished or paused
+    pub fn time_until_next(&self) -> Option<Duration> {
+        let next = self.recording.messages.get(self.position)?;
+        if self.speed <= 0.0 {
+            return None;
+        }
+        let mut wait = next.at.saturating_sub(self.time);
+        if let Some(max_gap) = self.max_gap {
+            wait = wait.min(max_gap);
+        }
+        // Waits too long to represent at tiny speeds are as good as forever
+        Some(
+            Duration::try_from_secs_f64(wait.as_secs_f64() / f64::from(self.speed))
+                .unwrap_or(Duration::MAX),
+        )
+    }
+
+    /// Moves the playback position forward by `elapsed` wall clock time and returns
+    /// the range of messages that became due
+    pub fn advance(&mut self, elapsed: Duration) -> Range<usize> {
+        let start = self.position;
+        if self.speed <= 0.0 {
+            return start..start;
+        }
+        let scaled = Duration::try_from_secs_f64(elapsed.as_secs_f64() * f64::from(self.speed))
+            .unwrap_or(Duration::MAX);
+        let mut time = self.time.saturating_add(scaled);
+        let messages = &self.recording.messages;
+        while let Some(next) = messages.get(self.position) {
+            if let Some(max_gap) = self.max_gap {
+                // Skip the part of the pause exceeding the maximum
+                let previous = match self.position {
+                    0 => Duration::ZERO,
+                    i => messages[i - 1].at,
+                };
+                if next.at.saturating_sub(previous) > max_gap
+                    && time >= previous.saturating_add(max_gap)
+                {
+                    time = time.saturating_add(next.at - previous - max_gap);
+                }
+            }
+            if next.at > time {
+                break;
+            }
+            self.position += 1;
+        }
+        self.time = time;
+        start..self.position
+    }
+
+    /// Advances by `elapsed` and draws the messages that became due. Paths sent step
+    /// by step are drawn segment by segment. Doesn't refresh the display.
+    pub fn render<F: FramebufferDraw + ?Sized>(
+        &mut self,
+        fb: &mut F,
+        viewport: &Viewport,
+        elapsed: Duration,
+    ) -> mxcfb_rect {
+        let mut damage = mxcfb_rect::invalid();
+        for i in self.advance(elapsed) {
+            let recorded = &self.recording.messages[i];
+            let rect = match &recorded.message {
+                DrawMessage::PathStep(step) => {
+                    let key = (recorded.client, step.path_id);
+                    let previous = match step.action {
+                        PathStepAction::Start => None,
+                        _ => self.path_ends.get(&key).copied(),
+                    };
+                    match step.action {
+                        PathStepAction::End => self.path_ends.remove(&key),
+                        _ => self.path_ends.insert(key, step.point),
+                    };
+                    let segment = DrawMessage::Line(protocol::Line {
+                        start: previous.unwrap_or(step.point),
+                        end: step.point,
+                        width: step.width,
+                        color: step.color,
+                    });
+                    draw_message_with(fb, &segment, viewport, &self.palette)
+                }
+                message => draw_message_with(fb, message, viewport, &self.palette),
+            };
+            damage = damage.merge_rect(&rect);
+        }
+        damage
+    }
+
+    /// Plays the rest of the recording onto `viewport`, blocking until it is
+    /// finished (or forever if paused). Intermediate updates use `DU`, the whole
+    /// viewport gets a `GC16` refresh at the end.
+    #[cfg(feature = "framebuffer")]
+    pub fn play(&mut self, fb: &mut core::Framebuffer, viewport: &Viewport) {
+        let mut last = Instant::now();
+        while !self.is_finished() {
+            std::thread::sleep(self.time_until_next().unwrap_or(Duration::from_millis(100)));
+            let now = Instant::now();
+            let damage = self.render(fb, viewport, now - last);
+            last = now;
+            if damage.width > 0 && damage.height > 0 {
+                fb.partial_refresh(
+                    &damage,
+                    PartialRefreshMode::Async,
+                    waveform_mode::WAVEFORM_MODE_DU,
+                    display_temp::TEMP_USE_REMARKABLE_DRAW,
+                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                    0,
+                    false,
+                );
+            }
+        }
+        let marker = fb.partial_refresh(
+            &viewport.screen,
+            PartialRefreshMode::Async,
+            waveform_mode::WAVEFORM_MODE_GC16,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        );
+        fb.wait_refresh_complete(marker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::protocol::{Color, Dot};
+
+    fn dot(x: i32) -> DrawMessage {
+        DrawMessage::Dot(Dot {
+            position: Point::new(x, 0),
+            size: 2,
+            color: Color::BLACK,
+        })
+    }
+
+    #[test]
+    fn test_replay_speed_and_gaps() {
+        let mut recorder = Recorder::new();
+        for (ms, x) in [(0, 0), (100, 1), (200, 2), (10_200, 3)] {
+            recorder.record_at(Duration::from_millis(ms), 1, &dot(x));
+        }
+        let recording = recorder.finish();
+        let bytes = postcard::to_allocvec(&recording).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<Recording>(&bytes).unwrap(),
+            recording
+        );
+
+        let mut replayer = Replayer::new(recording);
+        for paused in [-1.0, f32::NAN, f32::INFINITY] {
+            replayer.set_speed(paused);
+            assert_eq!(replayer.speed(), 0.0);
+            assert_eq!(replayer.time_until_next(), None);
+            assert_eq!(replayer.advance(Duration::from_secs(1)), 0..0);
+        }
+        replayer.set_speed(2.0);
+        assert_eq!(replayer.advance(Duration::ZERO), 0..1);
+        assert_eq!(replayer.time_until_next(), Some(Duration::from_millis(50)));
+        assert_eq!(replayer.advance(Duration::from_millis(50)), 1..2);
+        assert_eq!(replayer.advance(Duration::from_millis(50)), 2..3);
+
+        // The 10s pause is cut down to 1s (0.5s at double speed)
+        replayer.max_gap = Some(Duration::from_secs(1));
+        assert_eq!(replayer.time_until_next(), Some(Duration::from_millis(500)));
+        assert_eq!(replayer.advance(Duration::from_millis(400)), 3..3);
+        assert_eq!(replayer.advance(Duration::from_millis(100)), 3..4);
+        assert!(replayer.is_finished());
+
+        // Extreme speeds saturate instead of overflowing
+        replayer.rewind();
+        replayer.advance(Duration::ZERO);
+        replayer.set_speed(f32::MIN_POSITIVE);
+        assert_eq!(replayer.time_until_next(), Some(Duration::MAX));
+        replayer.set_speed(f32::MAX);
+        assert_eq!(replayer.advance(Duration::from_secs(1)), 1..4);
+    }
+}
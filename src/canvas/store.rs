@@ -0,0 +1,270 @@
+//! Persisting the canvas content on the device.
+//!
+//! Every chunk is stored in its own file (`<x>_<y>.chunk` in the store's directory)
+//! holding the postcard encoded drawing operations that touch it. Operations spanning
+//! several chunks are written to each of them and only loaded once. Chunks are loaded
+//! lazily, when they first become visible, so that opening a large canvas doesn't
+//! have to read all of it.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::protocol::{self, ChunkCoordinates, CodecError, DrawMessage};
+use crate::canvas::surface::ChunkedCanvas;
+use crate::canvas::viewport::Viewport;
+
+/// Differentiate between the reasons why loading or saving canvas data can fail.
+#[derive(Debug)]
+pub enum StoreError {
+    IOError(io::Error),
+    Codec(CodecError),
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::IOError(err)
+    }
+}
+
+impl From<CodecError> for StoreError {
+    fn from(err: CodecError) -> Self {
+        StoreError::Codec(err)
+    }
+}
+
+impl From<postcard::Error> for StoreError {
+    fn from(err: postcard::Error) -> Self {
+        StoreError::Codec(CodecError::Postcard(err))
+    }
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::IOError(err) => err.fmt(f),
+            StoreError::Codec(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Content of a chunk file. Versioned like the wire protocol, so that stored
+/// canvases are readable as long as the messages are.
+#[derive(Serialize, Deserialize)]
+struct ChunkFile {
+    version: u16,
+    items: Vec<DrawMessage>,
+}
+
+pub struct ChunkStore {
+    dir: PathBuf,
+    loaded: BTreeSet<ChunkCoordinates>,
+    dirty: BTreeSet<ChunkCoordinates>,
+}
+
+impl ChunkStore {
+    /// Opens the store in `dir`, creating the directory if needed. Nothing is loaded
+    /// until `load_visible` or `load` is called.
+    pub fn open(dir: impl AsRef<Path>) -> Result<ChunkStore, StoreError> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(ChunkStore {
+            dir: dir.as_ref().to_path_buf(),
+            loaded: BTreeSet::new(),
+            dirty: BTreeSet::new(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn chunk_path(&self, chunk: ChunkCoordinates) -> PathBuf {
+        self.dir.join(format!("{}_{}.chunk", chunk.x, chunk.y))
+    }
+
+    /// Chunks that have a file in the store
+    pub fn stored_chunks(&self) -> Result<BTreeSet<ChunkCoordinates>, StoreError> {
+        let mut chunks = BTreeSet::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let coordinates = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".chunk"))
+                .and_then(|n| n.split_once('_'))
+                .and_then(|(x, y)| Some(ChunkCoordinates::new(x.parse().ok()?, y.parse().ok()?)));
+            chunks.extend(coordinates);
+        }
+        Ok(chunks)
+    }
+
+    pub fn is_loaded(&self, chunk: &ChunkCoordinates) -> bool {
+        self.loaded.contains(chunk)
+    }
+
+    /// Loads `chunk` into `canvas` unless it has been loaded already
+    pub fn load(
+        &mut self,
+        canvas: &mut ChunkedCanvas,
+        chunk: ChunkCoordinates,
+    ) -> Result<(), StoreError> {
+        if self.loaded.contains(&chunk) {
+            return Ok(());
+        }
+        let bytes = match fs::read(self.chunk_path(chunk)) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.loaded.insert(chunk);
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (version, _) = postcard::take_from_bytes::<u16>(&bytes)?;
+        protocol::check_version(version)?;
+        let file: ChunkFile = postcard::from_bytes(&bytes)?;
+        for item in file.items {
+            // Operations also touching an already loaded chunk came with that one
+            let duplicate = item
+                .chunks()
+                .iter()
+                .any(|c| *c != chunk && self.loaded.contains(c));
+            if !duplicate {
+                canvas.apply(item);
+            }
+        }
+        self.loaded.insert(chunk);
+        Ok(())
+    }
+
+    /// Loads all chunks visible in `viewport` that haven't been loaded yet. Returns
+    /// the newly loaded ones, which need to be rendered. Chunks that fail to load
+    /// are logged and skipped, and will be retried on the next call.
+    pub fn load_visible(
+        &mut self,
+        canvas: &mut ChunkedCanvas,
+        viewport: &Viewport,
+    ) -> BTreeSet<ChunkCoordinates> {
+        let mut loaded = BTreeSet::new();
+        for chunk in viewport.visible_chunks() {
+            if self.loaded.contains(&chunk) {
+                continue;
+            }
+            match self.load(canvas, chunk) {
+                Ok(()) => {
+                    loaded.insert(chunk);
+                }
+                Err(err) => warn!("Failed to load chunk {:?}: {}", chunk, err),
+            }
+        }
+        loaded
+    }
+
+    /// Marks the chunks touched by `message` as modified. Call this for every
+    /// message applied to the canvas that should be persisted.
+    pub fn record(&mut self, message: &DrawMessage) {
+        self.dirty.extend(message.chunks());
+    }
+
+    /// Whether there are modifications that haven't been saved yet
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Writes all modified chunks to disk. Modified chunks that haven't been loaded
+    /// yet are loaded first so that their stored content is kept.
+    pub fn save(&mut self, canvas: &mut ChunkedCanvas) -> Result<(), StoreError> {
+        while let Some(chunk) = self.dirty.pop_first() {
+            let result = self
+                .load(canvas, chunk)
+                .and_then(|_| self.write_chunk(canvas, chunk));
+            if let Err(err) = result {
+                self.dirty.insert(chunk);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_chunk(
+        &self,
+        canvas: &ChunkedCanvas,
+        chunk: ChunkCoordinates,
+    ) -> Result<(), StoreError> {
+        let path = self.chunk_path(chunk);
+        let items: Vec<DrawMessage> = canvas.items_in(&[chunk]).cloned().collect();
+        if items.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+        let bytes = postcard::to_allocvec(&ChunkFile {
+            version: protocol::PROTOCOL_VERSION,
+            items,
+        })?;
+        // Write to a temporary file first so that a crash never leaves a truncated chunk
+        let tmp = path.with_extension("chunk.tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::protocol::{Color, Line, Point, CHUNK_SIZE};
+    use crate::framebuffer::common::mxcfb_rect;
+
+    #[test]
+    fn test_save_and_lazy_load() {
+        let dir = std::env::temp_dir().join(format!("libremarkable-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let size = CHUNK_SIZE as i32;
+        // Crosses from chunk (0, 0) into (1, 0)
+        let line = DrawMessage::Line(Line {
+            start: Point::new(10, 10),
+            end: Point::new(size + 10, 10),
+            width: 2,
+            color: Color::BLACK,
+        });
+        let far = DrawMessage::Line(Line {
+            start: Point::new(10 * size + 20, 10),
+            end: Point::new(10 * size + 25, 10),
+            width: 2,
+            color: Color::BLACK,
+        });
+
+        let mut canvas = ChunkedCanvas::new();
+        let mut store = ChunkStore::open(&dir).unwrap();
+        for message in [line.clone(), far.clone()] {
+            store.record(&message);
+            canvas.apply(message);
+        }
+        store.save(&mut canvas).unwrap();
+        assert!(!store.is_dirty());
+        assert_eq!(store.stored_chunks().unwrap().len(), 3);
+
+        let mut canvas = ChunkedCanvas::new();
+        let mut store = ChunkStore::open(&dir).unwrap();
+        let mut viewport = Viewport::new(mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: 2 * CHUNK_SIZE - 1,
+            height: CHUNK_SIZE - 1,
+        });
+        assert_eq!(store.load_visible(&mut canvas, &viewport).len(), 2);
+        // Loaded once even though it's stored in two chunks
+        assert_eq!(canvas.items(), std::slice::from_ref(&line));
+
+        viewport.offset.x = 10.0 * size as f32;
+        store.load_visible(&mut canvas, &viewport);
+        assert_eq!(canvas.items(), &[line, far]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
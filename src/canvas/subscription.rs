@@ -0,0 +1,150 @@
+use std::collections::BTreeSet;
+
+use crate::canvas::protocol::{ChunkCoordinates, Message, Subscription};
+use crate::canvas::viewport::Viewport;
+
+/// Changes to make to the subscription after the viewport moved
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SubscriptionUpdate {
+    pub subscribed: BTreeSet<ChunkCoordinates>,
+    pub unsubscribed: BTreeSet<ChunkCoordinates>,
+}
+
+impl SubscriptionUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.subscribed.is_empty() && self.unsubscribed.is_empty()
+    }
+
+    /// The message announcing this update to the server
+    pub fn to_message(&self) -> Message {
+        Message::UpdateSubscription {
+            subscribed: self.subscribed.clone(),
+            unsubscribed: self.unsubscribed.clone(),
+        }
+    }
+}
+
+/// Derives the chunks a client should be subscribed to from its `Viewport`.
+///
+/// Besides the visible chunks, `prefetch` chunks around them are subscribed to so
+/// that panning doesn't immediately show empty chunks. Chunks are only dropped once
+/// they are `prefetch + hysteresis` chunks away from the viewport, which avoids
+/// subscribing and unsubscribing the same chunks repeatedly while panning back and
+/// forth over a chunk boundary.
+pub struct SubscriptionManager {
+    current: Subscription,
+    pub prefetch: i32,
+    pub hysteresis: i32,
+}
+
+impl Default for SubscriptionManager {
+    fn default() -> Self {
+        SubscriptionManager::new()
+    }
+}
+
+impl SubscriptionManager {
+    pub fn new() -> SubscriptionManager {
+        SubscriptionManager {
+            current: Subscription::default(),
+            prefetch: 1,
+            hysteresis: 1,
+        }
+    }
+
+    /// The subscription as it was last sent
+    pub fn current(&self) -> &Subscription {
+        &self.current
+    }
+
+    /// Forgets the current subscription, e.g. after reconnecting to a server that
+    /// doesn't know about it anymore. The next `update` will subscribe to everything.
+    pub fn reset(&mut self) {
+        self.current = Subscription::default();
+    }
+
+    /// The full subscription for (re)connecting
+    pub fn subscribe_message(&self) -> Message {
+        Message::Subscribe(self.current.clone())
+    }
+
+    fn around(viewport: &Viewport, margin: i32) -> Subscription {
+        let visible = viewport.visible_chunks();
+        let (first, last) = match (visible.iter().next(), visible.iter().next_back()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Subscription::default(),
+        };
+        // `visible_chunks` is a rectangle, so its extremes are the corners
+        let mut chunks = BTreeSet::new();
+        for x in first.x - margin..=last.x + margin {
+            for y in first.y - margin..=last.y + margin {
+                chunks.insert(ChunkCoordinates { x, y });
+            }
+        }
+        Subscription { chunks }
+    }
+
+    /// Recomputes the subscription for `viewport`. Returns the changes that need to
+    /// be sent, or `None` if the subscription didn't change.
+    pub fn update(&mut self, viewport: &Viewport) -> Option<SubscriptionUpdate> {
+        let wanted = Self::around(viewport, self.prefetch);
+        let keep = Self::around(viewport, self.prefetch + self.hysteresis.max(0));
+
+        let update = SubscriptionUpdate {
+            subscribed: self.current.missing_from_self(&wanted),
+            unsubscribed: self.current.missing_from_other(&keep),
+        };
+        if update.is_empty() {
+            return None;
+        }
+        self.current
+            .update(&update.subscribed, &update.unsubscribed);
+        Some(update)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::protocol::CHUNK_SIZE;
+    use crate::framebuffer::cgmath;
+    use crate::framebuffer::common::mxcfb_rect;
+
+    #[test]
+    fn test_subscription_follows_viewport() {
+        let mut vp = Viewport::new(mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: CHUNK_SIZE - 1,
+            height: CHUNK_SIZE - 1,
+        });
+        let mut manager = SubscriptionManager::new();
+
+        let first = manager.update(&vp).unwrap();
+        assert_eq!(first.subscribed.len(), 9);
+        assert!(first.unsubscribed.is_empty());
+        assert_eq!(manager.update(&vp), None);
+
+        // Moving one chunk to the right subscribes a new column but keeps the old
+        // one around because of the hysteresis
+        vp.pan(cgmath::Vector2 {
+            x: -(CHUNK_SIZE as f32),
+            y: 0.0,
+        });
+        let update = manager.update(&vp).unwrap();
+        assert_eq!(update.subscribed.len(), 3);
+        assert!(update.unsubscribed.is_empty());
+
+        vp.pan(cgmath::Vector2 {
+            x: -(CHUNK_SIZE as f32),
+            y: 0.0,
+        });
+        let update = manager.update(&vp).unwrap();
+        assert_eq!(update.subscribed.len(), 3);
+        assert!(update
+            .unsubscribed
+            .iter()
+            .all(|c| c.x == -1 && (-1..=1).contains(&c.y)));
+        assert_eq!(manager.current().chunks.len(), 12);
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::canvas::palette::{Palette, Pen};
+use crate::canvas::protocol::{
+    ChunkCoordinates, Composite, DrawMessage, Path, PathStep, PathStepAction, Point,
+};
+use crate::canvas::viewport::Viewport;
+use crate::framebuffer::cgmath;
+use crate::framebuffer::cgmath::InnerSpace;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::FramebufferDraw;
+
+/// An unbounded drawing surface made up of chunks.
+///
+/// Drawing operations are kept in the order they were applied and indexed by the
+/// chunks they touch, so that rendering a viewport only has to look at the content
+/// of the visible chunks.
+#[derive(Default)]
+pub struct ChunkedCanvas {
+    items: Vec<DrawMessage>,
+    index: HashMap<ChunkCoordinates, Vec<usize>>,
+    /// Paths that are being streamed through `PathStep`s
+    live_paths: HashMap<u64, Path>,
+    /// Colors used when rendering
+    pub palette: Palette,
+}
+
+impl ChunkedCanvas {
+    pub fn new() -> ChunkedCanvas {
+        ChunkedCanvas::default()
+    }
+
+    /// All finished drawing operations in the order they were applied
+    pub fn items(&self) -> &[DrawMessage] {
+        &self.items
+    }
+
+    /// Paths that have been started but not ended yet
+    pub fn live_paths(&self) -> impl Iterator<Item = &Path> {
+        self.live_paths.values()
+    }
+
+    /// Chunks that have any content
+    pub fn chunks(&self) -> impl Iterator<Item = &ChunkCoordinates> {
+        self.index.keys()
+    }
+
+    /// Adds a drawing operation to the canvas. `PathStep`s are accumulated and turned
+    /// into a `Path` once their `End` step arrives.
+    pub fn apply(&mut self, message: DrawMessage) {
+        match message {
+            DrawMessage::PathStep(step) => self.apply_step(step),
+            message => self.insert(message),
+        }
+    }
+
+    fn apply_step(&mut self, step: PathStep) {
+        if step.action == PathStepAction::Start {
+            self.live_paths.remove(&step.path_id);
+        }
+        let path = self.live_paths.entry(step.path_id).or_insert_with(|| Path {
+            id: step.path_id,
+            points: Vec::new(),
+            widths: Vec::new(),
+            color: step.color,
+        });
+        path.points.push(step.point);
+        path.widths.push(step.width);
+        if step.action == PathStepAction::End {
+            if let Some(path) = self.live_paths.remove(&step.path_id) {
+                self.insert(DrawMessage::Path(path));
+            }
+        }
+    }
+
+    fn insert(&mut self, message: DrawMessage) {
+        let i = self.items.len();
+        for chunk in message.chunks() {
+            self.index.entry(chunk).or_default().push(i);
+        }
+        self.items.push(message);
+    }
+
+    /// Finished drawing operations touching any of `chunks`, in the order they were
+    /// applied
+    pub fn items_in<'a>(
+        &'a self,
+        chunks: impl IntoIterator<Item = &'a ChunkCoordinates>,
+    ) -> impl Iterator<Item = &'a DrawMessage> {
+        let indices: BTreeSet<usize> = chunks
+            .into_iter()
+            .filter_map(|c| self.index.get(c))
+            .flatten()
+            .copied()
+            .collect();
+        indices.into_iter().map(move |i| &self.items[i])
+    }
+
+    /// Clears the viewport's screen region and draws the content of all visible
+    /// chunks into it. Doesn't refresh the display.
+    pub fn render<F: FramebufferDraw + ?Sized>(
+        &self,
+        fb: &mut F,
+        viewport: &Viewport,
+    ) -> mxcfb_rect {
+        let screen = viewport.screen;
+        fb.fill_rect(
+            cgmath::Point2 {
+                x: screen.left as i32,
+                y: screen.top as i32,
+            },
+            screen.size(),
+            color::WHITE,
+        );
+        let visible = viewport.visible_chunks();
+        for item in self.items_in(&visible) {
+            draw_message_with(fb, item, viewport, &self.palette);
+        }
+        for path in self.live_paths.values() {
+            draw_message_with(
+                fb,
+                &DrawMessage::Path(path.clone()),
+                viewport,
+                &self.palette,
+            );
+        }
+        screen
+    }
+}
+
+/// Clips the segment `a`-`b` to `rect` (Liang-Barsky). Returns `None` if the
+/// segment lies completely outside.
+fn clip_segment(
+    a: cgmath::Point2<f32>,
+    b: cgmath::Point2<f32>,
+    rect: (f32, f32, f32, f32),
+) -> Option<(cgmath::Point2<f32>, cgmath::Point2<f32>)> {
+    let (left, top, right, bottom) = rect;
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, q) in [
+        (-dx, a.x - left),
+        (dx, right - a.x),
+        (-dy, a.y - top),
+        (dy, bottom - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let r = q / p;
+            if p < 0.0 {
+                t0 = t0.max(r);
+            } else {
+                t1 = t1.min(r);
+            }
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some((
+        cgmath::Point2 {
+            x: a.x + t0 * dx,
+            y: a.y + t0 * dy,
+        },
+        cgmath::Point2 {
+            x: a.x + t1 * dx,
+            y: a.y + t1 * dy,
+        },
+    ))
+}
+
+/// Draws a line in canvas coordinates, clipped so that it stays within the
+/// viewport's screen region. `phase` is the position within the pen's pattern at
+/// `start`, it is advanced to the one at `end` so that paths continue their pattern.
+fn draw_segment<F: FramebufferDraw + ?Sized>(
+    fb: &mut F,
+    viewport: &Viewport,
+    start: Point,
+    end: Point,
+    width: u32,
+    pen: Pen,
+    phase: &mut f32,
+) -> mxcfb_rect {
+    let width = viewport.scale(width);
+    let inset = (width / 2) as f32;
+    let s = viewport.screen;
+    let rect = (
+        s.left as f32 + inset,
+        s.top as f32 + inset,
+        (s.left + s.width) as f32 - 1.0 - inset,
+        (s.top + s.height) as f32 - 1.0 - inset,
+    );
+    let (from, to) = (viewport.to_screen(start), viewport.to_screen(end));
+    let length = (to - from).magnitude();
+    let start_phase = *phase;
+    *phase += length;
+    let (a, b) = match clip_segment(from, to, rect) {
+        Some(clipped) => clipped,
+        None => return mxcfb_rect::invalid(),
+    };
+    let round = |p: cgmath::Point2<f32>| cgmath::Point2 {
+        x: p.x.round() as i32,
+        y: p.y.round() as i32,
+    };
+    let (on, off) = match pen.pattern.dashes() {
+        Some(dashes) if length > 0.0 => dashes,
+        _ => return fb.draw_line(round(a), round(b), width, pen.color),
+    };
+    // Walk the clipped part one dash at a time, positions relative to `from`
+    let direction = (to - from) / length;
+    let (clip_start, clip_end) = ((a - from).magnitude(), (b - from).magnitude());
+    let period = on + off;
+    let mut d = clip_start - (start_phase + clip_start) % period;
+    let mut drawn = mxcfb_rect::invalid();
+    while d < clip_end {
+        let dash_start = d.max(clip_start);
+        let dash_end = (d + on).min(clip_end);
+        if dash_end >= dash_start {
+            drawn = drawn.merge_rect(&fb.draw_line(
+                round(from + direction * dash_start),
+                round(from + direction * dash_end),
+                width,
+                pen.color,
+            ));
+        }
+        d += period;
+    }
+    drawn
+}
+
+/// Draws a single drawing operation as seen through `viewport`. Useful to render
+/// operations received from peers incrementally instead of redrawing the whole
+/// viewport. Doesn't refresh the display.
+pub fn draw_message<F: FramebufferDraw + ?Sized>(
+    fb: &mut F,
+    message: &DrawMessage,
+    viewport: &Viewport,
+) -> mxcfb_rect {
+    draw_message_with(fb, message, viewport, &Palette::default())
+}
+
+/// Like `draw_message`, mapping colors through `palette`
+pub fn draw_message_with<F: FramebufferDraw + ?Sized>(
+    fb: &mut F,
+    message: &DrawMessage,
+    viewport: &Viewport,
+    palette: &Palette,
+) -> mxcfb_rect {
+    match message {
+        DrawMessage::Dot(dot) => {
+            let radius = viewport.scale(dot.size) / 2;
+            let center = viewport.to_screen(dot.position);
+            let s = viewport.screen;
+            let inside = center.x >= (s.left + radius) as f32
+                && center.y >= (s.top + radius) as f32
+                && center.x < (s.left + s.width).saturating_sub(radius) as f32
+                && center.y < (s.top + s.height).saturating_sub(radius) as f32;
+            if !inside {
+                // Partially visible dots are drawn as clipped horizontal spans
+                let rect = (
+                    s.left as f32,
+                    s.top as f32,
+                    (s.left + s.width) as f32 - 1.0,
+                    (s.top + s.height) as f32 - 1.0,
+                );
+                let r = radius.max(1) as f32;
+                let mut drawn = mxcfb_rect::invalid();
+                for dy in -(r as i32)..=(r as i32) {
+                    let half = (r * r - (dy * dy) as f32).sqrt();
+                    let y = center.y.round() + dy as f32;
+                    let a = cgmath::Point2 {
+                        x: center.x - half,
+                        y,
+                    };
+                    let b = cgmath::Point2 {
+                        x: center.x + half,
+                        y,
+                    };
+                    if let Some((a, b)) = clip_segment(a, b, rect) {
+                        drawn = drawn.merge_rect(&fb.draw_line(
+                            cgmath::Point2 {
+                                x: a.x.round() as i32,
+                                y: a.y as i32,
+                            },
+                            cgmath::Point2 {
+                                x: b.x.round() as i32,
+                                y: b.y as i32,
+                            },
+                            1,
+                            palette.color(dot.color),
+                        ));
+                    }
+                }
+                return drawn;
+            }
+            fb.fill_circle(
+                cgmath::Point2 {
+                    x: center.x.round() as i32,
+                    y: center.y.round() as i32,
+                },
+                radius.max(1),
+                palette.color(dot.color),
+            )
+        }
+        DrawMessage::Line(line) => draw_segment(
+            fb,
+            viewport,
+            line.start,
+            line.end,
+            line.width,
+            palette.pen(line.color),
+            &mut 0.0,
+        ),
+        DrawMessage::Path(path) => {
+            let mut rect = mxcfb_rect::invalid();
+            let pen = palette.pen(path.color);
+            let mut phase = 0.0;
+            for (i, pair) in path.points.windows(2).enumerate() {
+                let width = path.widths.get(i + 1).copied().unwrap_or(1);
+                rect = rect.merge_rect(&draw_segment(
+                    fb, viewport, pair[0], pair[1], width, pen, &mut phase,
+                ));
+            }
+            rect
+        }
+        DrawMessage::PathStep(_) => mxcfb_rect::invalid(),
+        DrawMessage::Composite(Composite { items }) => {
+            items.iter().fold(mxcfb_rect::invalid(), |rect, item| {
+                rect.merge_rect(&draw_message_with(fb, item, viewport, palette))
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::protocol::{Color, Dot, CHUNK_SIZE};
+
+    #[test]
+    fn test_chunk_index() {
+        let mut canvas = ChunkedCanvas::new();
+        let dot = |x, y| {
+            DrawMessage::Dot(Dot {
+                position: Point::new(x, y),
+                size: 2,
+                color: Color::BLACK,
+            })
+        };
+        canvas.apply(dot(10, 10));
+        canvas.apply(dot(CHUNK_SIZE as i32 * 3, 10));
+        for (action, x) in [
+            (PathStepAction::Start, 5),
+            (PathStepAction::Continue, 6),
+            (PathStepAction::End, 7),
+        ] {
+            assert!(canvas.items().len() == 2);
+            canvas.apply(DrawMessage::PathStep(PathStep {
+                path_id: 1,
+                action,
+                point: Point::new(x, 20),
+                width: 1,
+                color: Color::BLACK,
+            }));
+        }
+        assert_eq!(canvas.items().len(), 3);
+        assert_eq!(canvas.live_paths().count(), 0);
+
+        let origin = [ChunkCoordinates::new(0, 0)];
+        let visible: Vec<_> = canvas.items_in(&origin).collect();
+        assert_eq!(visible.len(), 2);
+        assert_eq!(visible[0], &dot(10, 10));
+    }
+
+    #[test]
+    fn test_clip_segment() {
+        let rect = (0.0, 0.0, 10.0, 10.0);
+        let p = |x, y| cgmath::Point2 { x, y };
+        assert_eq!(
+            clip_segment(p(-5.0, 5.0), p(15.0, 5.0), rect),
+            Some((p(0.0, 5.0), p(10.0, 5.0)))
+        );
+        assert_eq!(clip_segment(p(-5.0, -5.0), p(-1.0, 20.0), rect), None);
+    }
+}
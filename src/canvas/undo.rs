@@ -0,0 +1,222 @@
+//! Undo and redo on top of the operation log.
+//!
+//! Operations in the log are never removed, so undoing is done by applying the
+//! inverse operation: an undone drawing is erased, and an undone erasure draws the
+//! erased content again as a new drawing, whose id then replaces the erased one
+//! throughout the stack. The resulting operations are regular
+//! ops that have to be sent to peers like any other. Only the local client's
+//! actions are undone; changes made by others in the meantime are left alone.
+
+use crate::canvas::crdt::{Change, OpLog};
+use crate::canvas::protocol::{DrawMessage, Op, OpId};
+
+/// A group of operations that is undone and redone as a whole
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Drew(Vec<OpId>),
+    /// Erased drawings along with the ids they had
+    Erased(Vec<(OpId, DrawMessage)>),
+}
+
+/// What an undo, redo or recorded action did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Applied {
+    /// Operations to send to peers
+    pub ops: Vec<Op>,
+    /// The change to render, see `OpLog::render_change`
+    pub change: Change,
+}
+
+pub struct UndoStack {
+    undo: Vec<Action>,
+    redo: Vec<Action>,
+    /// Maximum number of actions that can be undone
+    pub limit: usize,
+}
+
+impl Default for UndoStack {
+    fn default() -> Self {
+        UndoStack::new()
+    }
+}
+
+impl UndoStack {
+    pub fn new() -> UndoStack {
+        UndoStack {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            limit: 100,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    fn push(&mut self, action: Action) {
+        self.undo.push(action);
+        if self.undo.len() > self.limit {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Draws `messages` as one undoable action, e.g. a stroke or a paste
+    pub fn draw(&mut self, log: &mut OpLog, messages: Vec<DrawMessage>) -> Applied {
+        let mut ops = Vec::new();
+        let mut changes = Vec::new();
+        for message in messages {
+            let (op, change) = log.draw(message);
+            ops.push(op);
+            changes.push(change);
+        }
+        self.redo.clear();
+        self.push(Action::Drew(ops.iter().map(|op| op.id).collect()));
+        Applied {
+            change: log.combine_changes(changes),
+            ops,
+        }
+    }
+
+    /// Erases `targets` as one undoable action. Drawings that aren't visible are
+    /// skipped.
+    pub fn erase(&mut self, log: &mut OpLog, targets: &[OpId]) -> Applied {
+        let (action, applied) = self.revert(log, Action::Drew(targets.to_vec()));
+        self.redo.clear();
+        self.push(action);
+        applied
+    }
+
+    /// Reverts the last action
+    pub fn undo(&mut self, log: &mut OpLog) -> Option<Applied> {
+        let action = self.undo.pop()?;
+        let (inverse, applied) = self.revert(log, action);
+        self.redo.push(inverse);
+        Some(applied)
+    }
+
+    /// Reapplies the last undone action
+    pub fn redo(&mut self, log: &mut OpLog) -> Option<Applied> {
+        let action = self.redo.pop()?;
+        let (inverse, applied) = self.revert(log, action);
+        self.push(inverse);
+        Some(applied)
+    }
+
+    /// Replaces `old` by `new` in all actions
+    fn remap(&mut self, old: OpId, new: OpId) {
+        for action in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            match action {
+                Action::Drew(ids) => ids
+                    .iter_mut()
+                    .filter(|id| **id == old)
+                    .for_each(|id| *id = new),
+                Action::Erased(items) => items
+                    .iter_mut()
+                    .filter(|(id, _)| *id == old)
+                    .for_each(|(id, _)| *id = new),
+            }
+        }
+    }
+
+    /// Reverts `action`, returning the action that reverts that again
+    fn revert(&mut self, log: &mut OpLog, action: Action) -> (Action, Applied) {
+        let mut ops = Vec::new();
+        let mut changes = Vec::new();
+        let inverse = match action {
+            Action::Drew(ids) => {
+                let mut erased = Vec::new();
+                for id in ids {
+                    let message = match log.drawing(&id) {
+                        Some(message) => message.clone(),
+                        // Already erased, possibly by someone else
+                        None => continue,
+                    };
+                    let (op, change) = log.erase(id);
+                    erased.push((id, message));
+                    ops.push(op);
+                    changes.push(change);
+                }
+                Action::Erased(erased)
+            }
+            Action::Erased(items) => {
+                let mut drawn = Vec::new();
+                for (old, message) in items {
+                    let (op, change) = log.draw(message);
+                    self.remap(old, op.id);
+                    drawn.push(op.id);
+                    ops.push(op);
+                    changes.push(change);
+                }
+                Action::Drew(drawn)
+            }
+        };
+        let change = log.combine_changes(changes);
+        (inverse, Applied { ops, change })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::protocol::{Color, Dot, Point};
+
+    fn dot(x: i32) -> DrawMessage {
+        DrawMessage::Dot(Dot {
+            position: Point::new(x, 0),
+            size: 2,
+            color: Color::BLACK,
+        })
+    }
+
+    fn visible(log: &OpLog) -> Vec<DrawMessage> {
+        log.drawings().map(|(_, m)| m.clone()).collect()
+    }
+
+    #[test]
+    fn test_undo_redo_converges_with_peer() {
+        let mut log = OpLog::new(1);
+        let mut peer = OpLog::new(2);
+        let mut stack = UndoStack::new();
+        let sync = |ops: Vec<Op>, peer: &mut OpLog| {
+            for op in ops {
+                peer.apply(op);
+            }
+        };
+
+        let stroke = stack.draw(&mut log, vec![dot(1)]);
+        sync(stroke.ops, &mut peer);
+        let paste = stack.draw(&mut log, vec![dot(2), dot(3)]);
+        assert!(matches!(paste.change, Change::Redraw(_)));
+        let pasted: Vec<OpId> = paste.ops.iter().map(|op| op.id).collect();
+        sync(paste.ops, &mut peer);
+        let erase = stack.erase(&mut log, &pasted[..1]);
+        sync(erase.ops, &mut peer);
+        assert_eq!(visible(&log), vec![dot(1), dot(3)]);
+
+        // Undo the erase, then the paste
+        let undo = stack.undo(&mut log).unwrap();
+        sync(undo.ops, &mut peer);
+        assert_eq!(visible(&log), vec![dot(1), dot(3), dot(2)]);
+        let undo = stack.undo(&mut log).unwrap();
+        sync(undo.ops, &mut peer);
+        assert_eq!(visible(&log), vec![dot(1)]);
+
+        let redo = stack.redo(&mut log).unwrap();
+        sync(redo.ops, &mut peer);
+        assert_eq!(visible(&log), vec![dot(1), dot(2), dot(3)]);
+        assert_eq!(visible(&peer), visible(&log));
+
+        // A new action drops what could still be redone
+        stack.draw(&mut log, vec![dot(4)]);
+        assert!(!stack.can_redo());
+    }
+}
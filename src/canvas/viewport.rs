@@ -0,0 +1,247 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::canvas::protocol::{ChunkCoordinates, Point, CHUNK_SIZE};
+use crate::framebuffer::cgmath;
+use crate::framebuffer::cgmath::InnerSpace;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::input::MultitouchEvent;
+
+/// The part of the canvas that is shown on screen.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    /// Canvas position displayed at the top left corner of `screen`
+    pub offset: cgmath::Point2<f32>,
+    /// Screen pixels per canvas pixel
+    pub zoom: f32,
+    /// Region of the framebuffer the canvas is displayed in
+    pub screen: mxcfb_rect,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+}
+
+impl Viewport {
+    /// A viewport showing the canvas origin at 1:1 scale in `screen`
+    pub fn new(screen: mxcfb_rect) -> Viewport {
+        Viewport {
+            offset: cgmath::Point2 { x: 0.0, y: 0.0 },
+            zoom: 1.0,
+            screen,
+            min_zoom: 0.25,
+            max_zoom: 4.0,
+        }
+    }
+
+    /// Converts a canvas position into framebuffer coordinates
+    pub fn to_screen(&self, p: Point) -> cgmath::Point2<f32> {
+        cgmath::Point2 {
+            x: self.screen.left as f32 + (p.x as f32 - self.offset.x) * self.zoom,
+            y: self.screen.top as f32 + (p.y as f32 - self.offset.y) * self.zoom,
+        }
+    }
+
+    /// Converts framebuffer coordinates into a canvas position
+    pub fn to_canvas(&self, p: cgmath::Point2<f32>) -> Point {
+        Point {
+            x: (self.offset.x + (p.x - self.screen.left as f32) / self.zoom).round() as i32,
+            y: (self.offset.y + (p.y - self.screen.top as f32) / self.zoom).round() as i32,
+        }
+    }
+
+    /// Scales a canvas length (e.g. a stroke width) to screen pixels
+    pub fn scale(&self, length: u32) -> u32 {
+        ((length as f32 * self.zoom).round() as u32).max(1)
+    }
+
+    /// Moves the content by `delta` screen pixels
+    pub fn pan(&mut self, delta: cgmath::Vector2<f32>) {
+        self.offset.x -= delta.x / self.zoom;
+        self.offset.y -= delta.y / self.zoom;
+    }
+
+    /// Multiplies the zoom by `factor` (clamped to `min_zoom..=max_zoom`), keeping
+    /// the content under the screen position `anchor` in place.
+    pub fn zoom_around(&mut self, anchor: cgmath::Point2<f32>, factor: f32) {
+        let zoom = (self.zoom * factor).clamp(self.min_zoom, self.max_zoom);
+        let rel_x = anchor.x - self.screen.left as f32;
+        let rel_y = anchor.y - self.screen.top as f32;
+        self.offset.x += rel_x / self.zoom - rel_x / zoom;
+        self.offset.y += rel_y / self.zoom - rel_y / zoom;
+        self.zoom = zoom;
+    }
+
+    /// Top left and bottom right canvas positions that are visible
+    pub fn visible_bounds(&self) -> (Point, Point) {
+        let top_left = Point {
+            x: self.offset.x.floor() as i32,
+            y: self.offset.y.floor() as i32,
+        };
+        let bottom_right = Point {
+            x: (self.offset.x + self.screen.width as f32 / self.zoom).ceil() as i32,
+            y: (self.offset.y + self.screen.height as f32 / self.zoom).ceil() as i32,
+        };
+        (top_left, bottom_right)
+    }
+
+    /// Chunks that are at least partially visible
+    pub fn visible_chunks(&self) -> BTreeSet<ChunkCoordinates> {
+        let (top_left, bottom_right) = self.visible_bounds();
+        let from = ChunkCoordinates::containing(top_left);
+        let to = ChunkCoordinates::containing(bottom_right);
+        let mut chunks = BTreeSet::new();
+        for x in from.x..=to.x {
+            for y in from.y..=to.y {
+                chunks.insert(ChunkCoordinates { x, y });
+            }
+        }
+        chunks
+    }
+
+    /// The same mapping, limited to the part of the screen covered by `rect`.
+    /// Drawing through the returned viewport doesn't touch anything outside `rect`.
+    pub fn restricted_to(&self, rect: mxcfb_rect) -> Viewport {
+        Viewport {
+            offset: cgmath::Point2 {
+                x: self.offset.x + (rect.left as f32 - self.screen.left as f32) / self.zoom,
+                y: self.offset.y + (rect.top as f32 - self.screen.top as f32) / self.zoom,
+            },
+            screen: rect,
+            ..*self
+        }
+    }
+
+    /// Region of the framebuffer covered by `chunk`, clamped to `screen`. Returns
+    /// `None` if the chunk isn't visible.
+    pub fn chunk_rect(&self, chunk: ChunkCoordinates) -> Option<mxcfb_rect> {
+        let tl = self.to_screen(chunk.origin());
+        let br = self.to_screen(Point {
+            x: chunk.origin().x + CHUNK_SIZE as i32,
+            y: chunk.origin().y + CHUNK_SIZE as i32,
+        });
+        let left = tl.x.floor().max(self.screen.left as f32) as u32;
+        let top = tl.y.floor().max(self.screen.top as f32) as u32;
+        let right =
+            br.x.ceil()
+                .min((self.screen.left + self.screen.width) as f32) as u32;
+        let bottom =
+            br.y.ceil()
+                .min((self.screen.top + self.screen.height) as f32) as u32;
+        if right <= left || bottom <= top {
+            return None;
+        }
+        Some(mxcfb_rect {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+}
+
+/// Turns finger input into pan (one or two fingers) and pinch zoom (two fingers)
+/// operations on a `Viewport`.
+#[derive(Default)]
+pub struct PanZoomGesture {
+    fingers: HashMap<i32, cgmath::Point2<f32>>,
+}
+
+impl PanZoomGesture {
+    pub fn new() -> PanZoomGesture {
+        PanZoomGesture::default()
+    }
+
+    /// Whether any finger is currently down
+    pub fn is_active(&self) -> bool {
+        !self.fingers.is_empty()
+    }
+
+    /// Centroid and spread of the first two fingers
+    fn anchor(&self) -> Option<(cgmath::Point2<f32>, f32)> {
+        let mut ids: Vec<_> = self.fingers.keys().copied().collect();
+        ids.sort_unstable();
+        match ids[..] {
+            [] => None,
+            [a] => Some((self.fingers[&a], 0.0)),
+            [a, b, ..] => {
+                let (pa, pb) = (self.fingers[&a], self.fingers[&b]);
+                Some((
+                    cgmath::Point2 {
+                        x: (pa.x + pb.x) / 2.0,
+                        y: (pa.y + pb.y) / 2.0,
+                    },
+                    (pb - pa).magnitude(),
+                ))
+            }
+        }
+    }
+
+    /// Updates `viewport` according to `event`. Returns true if the viewport changed
+    /// and the canvas needs to be redrawn.
+    pub fn handle_multitouch(&mut self, viewport: &mut Viewport, event: &MultitouchEvent) -> bool {
+        let finger = match event.finger() {
+            Some(f) => *f,
+            None => return false,
+        };
+        let pos = cgmath::Point2 {
+            x: f32::from(finger.pos.x),
+            y: f32::from(finger.pos.y),
+        };
+        match event {
+            MultitouchEvent::Press { .. } => {
+                self.fingers.insert(finger.tracking_id, pos);
+                false
+            }
+            MultitouchEvent::Release { .. } => {
+                self.fingers.remove(&finger.tracking_id);
+                false
+            }
+            MultitouchEvent::Move { .. } => {
+                let before = self.anchor();
+                self.fingers.insert(finger.tracking_id, pos);
+                let (before, after) = match (before, self.anchor()) {
+                    (Some(b), Some(a)) => (b, a),
+                    _ => return false,
+                };
+                let previous = *viewport;
+                viewport.pan(after.0 - before.0);
+                if before.1 > 0.0 && after.1 > 0.0 {
+                    viewport.zoom_around(after.0, after.1 / before.1);
+                }
+                *viewport != previous
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_viewport_mapping() {
+        let mut vp = Viewport::new(mxcfb_rect {
+            left: 100,
+            top: 0,
+            width: 512,
+            height: 512,
+        });
+        assert_eq!(
+            vp.to_canvas(vp.to_screen(Point::new(30, 40))),
+            Point::new(30, 40)
+        );
+        assert_eq!(vp.visible_chunks().len(), 9);
+
+        // Zooming keeps the point under the anchor in place
+        let anchor = cgmath::Point2 { x: 200.0, y: 100.0 };
+        let under = vp.to_canvas(anchor);
+        vp.zoom_around(anchor, 2.0);
+        assert_eq!(vp.zoom, 2.0);
+        assert_eq!(vp.to_canvas(anchor), under);
+
+        vp.pan(cgmath::Vector2 { x: 20.0, y: 0.0 });
+        assert_eq!(vp.to_canvas(anchor), Point::new(under.x - 10, under.y));
+
+        vp.zoom_around(anchor, 100.0);
+        assert_eq!(vp.zoom, vp.max_zoom);
+    }
+}
@@ -0,0 +1,499 @@
+//! A clipboard shared by the apps built on this crate.
+//!
+//! By default the content lives in a file, so it survives the app that copied it and
+//! any app of the same user can paste it later. The file is in `$XDG_RUNTIME_DIR`, or
+//! else in a directory in `/tmp` that only the user can access. Alternatively a `ClipboardServer` keeps it in memory
+//! and apps reach it over a Unix socket.
+//!
+//! Images are kept in the framebuffer's pixel format, as returned by
+//! `FramebufferIO::dump_region`, so copying and pasting regions of the screen is
+//! lossless.
+
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+
+use crate::cgmath;
+
+/// Where `Clipboard::default` keeps the content
+pub fn default_file() -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(format!("/tmp/libremarkable-{}", unsafe { libc::geteuid() })),
+    };
+    dir.join("libremarkable-clipboard")
+}
+
+/// Creates `dir` if needed, and makes sure no other user can access it, as it is in
+/// a directory anyone can write to
+fn private_dir(dir: &Path) -> io::Result<()> {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => return Err(err),
+        _ => {}
+    }
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != unsafe { libc::geteuid() }
+        || metadata.mode() & 0o077 != 0
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the clipboard directory is accessible by others",
+        ));
+    }
+    Ok(())
+}
+
+/// Larger content is rejected
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+const OP_COPY: u8 = 1;
+const OP_PASTE: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClipboardContent {
+    Text(String),
+    /// Pixels in the framebuffer's format of `bits_per_pixel`, row by row
+    Image {
+        size: cgmath::Vector2<u32>,
+        bits_per_pixel: u32,
+        pixels: Vec<u8>,
+    },
+}
+
+/// Why copying or pasting failed
+#[derive(Debug, thiserror::Error)]
+pub enum ClipboardError {
+    #[error("Failed to access the clipboard")]
+    Io(#[from] io::Error),
+    #[error("Malformed clipboard content: {0}")]
+    Malformed(&'static str),
+}
+
+impl ClipboardContent {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            ClipboardContent::Text(text) => [&[1u8][..], text.as_bytes()].concat(),
+            ClipboardContent::Image {
+                size,
+                bits_per_pixel,
+                pixels,
+            } => {
+                let mut out = vec![2u8];
+                out.extend_from_slice(&size.x.to_le_bytes());
+                out.extend_from_slice(&size.y.to_le_bytes());
+                out.extend_from_slice(&bits_per_pixel.to_le_bytes());
+                out.extend_from_slice(pixels);
+                out
+            }
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<ClipboardContent, ClipboardError> {
+        match data.split_first() {
+            Some((1, text)) => String::from_utf8(text.to_vec())
+                .map(ClipboardContent::Text)
+                .map_err(|_| ClipboardError::Malformed("text is not UTF-8")),
+            Some((2, image)) if image.len() >= 12 => {
+                let word = |i: usize| u32::from_le_bytes(image[i..i + 4].try_into().unwrap());
+                let size = cgmath::Vector2 {
+                    x: word(0),
+                    y: word(4),
+                };
+                let bits_per_pixel = word(8);
+                if bits_per_pixel != 16 && bits_per_pixel != 32 {
+                    return Err(ClipboardError::Malformed("pixel format"));
+                }
+                let pixels = image[12..].to_vec();
+                let len = u64::from(size.x) * u64::from(size.y) * u64::from(bits_per_pixel / 8);
+                if pixels.len() as u64 != len {
+                    return Err(ClipboardError::Malformed("image size"));
+                }
+                Ok(ClipboardContent::Image {
+                    size,
+                    bits_per_pixel,
+                    pixels,
+                })
+            }
+            _ => Err(ClipboardError::Malformed("unknown kind of content")),
+        }
+    }
+}
+
+enum Backend {
+    /// With whether the directory of the file is made private before using it
+    File(PathBuf, bool),
+    Socket(PathBuf),
+}
+
+pub struct Clipboard {
+    backend: Backend,
+}
+
+impl Default for Clipboard {
+    /// A clipboard kept in `default_file`
+    fn default() -> Clipboard {
+        Clipboard {
+            backend: Backend::File(default_file(), true),
+        }
+    }
+}
+
+impl Clipboard {
+    /// A clipboard kept in the file at `path`
+    pub fn file(path: impl AsRef<Path>) -> Clipboard {
+        Clipboard {
+            backend: Backend::File(path.as_ref().to_path_buf(), false),
+        }
+    }
+
+    /// The clipboard of the `ClipboardServer` listening at `socket`
+    pub fn socket(socket: impl AsRef<Path>) -> Clipboard {
+        Clipboard {
+            backend: Backend::Socket(socket.as_ref().to_path_buf()),
+        }
+    }
+
+    /// Replaces the content
+    pub fn copy(&self, content: &ClipboardContent) -> Result<(), ClipboardError> {
+        let data = content.encode();
+        match &self.backend {
+            Backend::File(path, private) => {
+                if let (true, Some(dir)) = (private, path.parent()) {
+                    private_dir(dir)?;
+                }
+                // Written next to it and renamed, so pasting never sees half of it. A
+                // new file, so nothing left there redirects the write.
+                let partial = path.with_extension(format!("{}.partial", std::process::id()));
+                let _ = std::fs::remove_file(&partial);
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&partial)?;
+                file.write_all(&data)?;
+                drop(file);
+                std::fs::rename(&partial, path)?;
+            }
+            Backend::Socket(socket) => {
+                let mut stream = UnixStream::connect(socket)?;
+                stream.write_all(&[OP_COPY])?;
+                stream.write_all(&data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The content, if anything was copied
+    pub fn paste(&self) -> Result<Option<ClipboardContent>, ClipboardError> {
+        let data = match &self.backend {
+            Backend::File(path, private) => {
+                if let (true, Some(dir)) = (private, path.parent()) {
+                    private_dir(dir)?;
+                }
+                match std::fs::read(path) {
+                    Ok(data) => data,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Backend::Socket(socket) => {
+                let mut stream = UnixStream::connect(socket)?;
+                stream.write_all(&[OP_PASTE])?;
+                stream.shutdown(std::net::Shutdown::Write)?;
+                let mut data = Vec::new();
+                stream.take(MAX_SIZE).read_to_end(&mut data)?;
+                data
+            }
+        };
+        match data.is_empty() {
+            true => Ok(None),
+            false => ClipboardContent::decode(&data).map(Some),
+        }
+    }
+
+    pub fn copy_text(&self, text: &str) -> Result<(), ClipboardError> {
+        self.copy(&ClipboardContent::Text(text.to_owned()))
+    }
+
+    /// The text on the clipboard, `None` if there is none or an image
+    pub fn paste_text(&self) -> Result<Option<String>, ClipboardError> {
+        match self.paste()? {
+            Some(ClipboardContent::Text(text)) => Ok(Some(text)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "framebuffer")]
+mod region {
+    use super::{Clipboard, ClipboardContent, ClipboardError};
+    use crate::framebuffer::cgmath;
+    use crate::framebuffer::common::{color, mxcfb_rect};
+    use crate::framebuffer::core::Framebuffer;
+    use crate::framebuffer::FramebufferIO;
+
+    impl Clipboard {
+        /// Copies the pixels of `rect` as an image
+        pub fn copy_region(
+            &self,
+            fb: &Framebuffer,
+            rect: mxcfb_rect,
+        ) -> Result<(), ClipboardError> {
+            let pixels = fb
+                .dump_region(rect)
+                .map_err(|_| ClipboardError::Malformed("region outside of the framebuffer"))?;
+            self.copy(&ClipboardContent::Image {
+                size: cgmath::Vector2 {
+                    x: rect.width,
+                    y: rect.height,
+                },
+                bits_per_pixel: fb.var_screen_info.bits_per_pixel,
+                pixels,
+            })
+        }
+
+        /// Draws the image on the clipboard with its top left at `pos`, cut off at the
+        /// edges of `fb`. Returns the area drawn, which still needs to be refreshed.
+        pub fn paste_region(
+            &self,
+            fb: &mut Framebuffer,
+            pos: cgmath::Point2<u32>,
+        ) -> Result<Option<mxcfb_rect>, ClipboardError> {
+            let (size, bits_per_pixel, pixels) = match self.paste()? {
+                Some(ClipboardContent::Image {
+                    size,
+                    bits_per_pixel,
+                    pixels,
+                }) => (size, bits_per_pixel, pixels),
+                _ => return Ok(None),
+            };
+            let screen = fb.screen_size();
+            let width = size.x.min(screen.x.saturating_sub(pos.x));
+            let height = size.y.min(screen.y.saturating_sub(pos.y));
+            if width == 0 || height == 0 {
+                return Ok(None);
+            }
+            let rect = mxcfb_rect {
+                left: pos.x,
+                top: pos.y,
+                width,
+                height,
+            };
+            let bytespp = (bits_per_pixel / 8) as usize;
+            let rows = pixels
+                .chunks_exact(size.x as usize * bytespp)
+                .take(height as usize)
+                .map(|row| &row[..width as usize * bytespp]);
+            let visible: Vec<u8> = if bits_per_pixel == fb.var_screen_info.bits_per_pixel {
+                rows.flatten().copied().collect()
+            } else {
+                // Copied from a framebuffer with the other format
+                rows.flat_map(|row| row.chunks_exact(bytespp))
+                    .flat_map(|pixel| {
+                        let pixel = match *pixel {
+                            [b, g, r, _] => color::RGB(r, g, b),
+                            [c1, c2] => color::NATIVE_COMPONENTS(c1, c2),
+                            _ => color::WHITE,
+                        };
+                        match fb.is_color() {
+                            true => pixel.to_xrgb8888().to_vec(),
+                            false => pixel.as_native().to_vec(),
+                        }
+                    })
+                    .collect()
+            };
+            fb.restore_region(rect, &visible)
+                .map_err(|_| ClipboardError::Malformed("region outside of the framebuffer"))?;
+            Ok(Some(rect))
+        }
+    }
+}
+
+/// Keeps the clipboard in memory for the apps connecting to its socket
+pub struct ClipboardServer {
+    socket: PathBuf,
+}
+
+impl ClipboardServer {
+    /// Starts serving at `socket` on a background thread
+    pub fn serve(socket: impl AsRef<Path>) -> io::Result<ClipboardServer> {
+        let socket = socket.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&socket)?;
+        let content = Arc::new(Mutex::new(Vec::new()));
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| serve_request(stream, &content));
+                if let Err(err) = result {
+                    warn!("Failed to serve a clipboard request: {}", err);
+                }
+            }
+        });
+        Ok(ClipboardServer { socket })
+    }
+}
+
+impl Drop for ClipboardServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+fn serve_request(mut stream: UnixStream, content: &Mutex<Vec<u8>>) -> io::Result<()> {
+    let mut op = [0u8];
+    stream.read_exact(&mut op)?;
+    match op[0] {
+        OP_COPY => {
+            let mut data = Vec::new();
+            (&mut stream).take(MAX_SIZE).read_to_end(&mut data)?;
+            // Checked here so pasting never fails on what another app copied
+            match ClipboardContent::decode(&data) {
+                Ok(_) => *content.lock().unwrap() = data,
+                Err(err) => warn!("Ignoring clipboard content: {}", err),
+            }
+        }
+        OP_PASTE => {
+            let data = content.lock().unwrap().clone();
+            stream.write_all(&data)?;
+        }
+        _ => warn!("Unknown clipboard request {}", op[0]),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("clipboard-test-{}-{}", std::process::id(), name))
+    }
+
+    fn round_trip(clipboard: &Clipboard) {
+        assert_eq!(clipboard.paste().unwrap(), None);
+        clipboard.copy_text("hello").unwrap();
+        assert_eq!(clipboard.paste_text().unwrap().as_deref(), Some("hello"));
+
+        let image = ClipboardContent::Image {
+            size: cgmath::Vector2 { x: 2, y: 1 },
+            bits_per_pixel: 16,
+            pixels: vec![1, 2, 3, 4],
+        };
+        clipboard.copy(&image).unwrap();
+        assert_eq!(clipboard.paste().unwrap(), Some(image));
+        assert_eq!(clipboard.paste_text().unwrap(), None);
+    }
+
+    #[test]
+    fn test_file_clipboard() {
+        let path = temp_path("file");
+        round_trip(&Clipboard::file(&path));
+        std::fs::write(&path, [2, 1]).unwrap();
+        assert!(Clipboard::file(&path).paste().is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // Not written through whatever is left where the partial file goes
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        let target = temp_path("target");
+        std::os::unix::fs::symlink(&target, &partial).unwrap();
+        Clipboard::file(&path).copy_text("hello").unwrap();
+        assert!(!target.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_path("private");
+        let clipboard = Clipboard {
+            backend: Backend::File(dir.join("clipboard"), true),
+        };
+        round_trip(&clipboard);
+        let metadata = std::fs::metadata(&dir).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o700);
+        assert_eq!(
+            std::fs::metadata(dir.join("clipboard")).unwrap().mode() & 0o777,
+            0o600
+        );
+
+        // Left accessible by others, e.g. by someone else creating it first
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(matches!(clipboard.paste(), Err(ClipboardError::Io(_))));
+        assert!(clipboard.copy_text("hello").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(default_file().ends_with("libremarkable-clipboard"));
+    }
+
+    #[test]
+    fn test_socket_clipboard() {
+        let socket = temp_path("socket");
+        let _server = ClipboardServer::serve(&socket).unwrap();
+        round_trip(&Clipboard::socket(&socket));
+    }
+
+    #[cfg(feature = "framebuffer")]
+    #[test]
+    fn test_region() {
+        use crate::framebuffer::common::{color, mxcfb_rect};
+        use crate::framebuffer::core::Framebuffer;
+        use crate::framebuffer::rotation::ScreenRotation;
+        use crate::framebuffer::FramebufferIO;
+
+        let path = temp_path("region");
+        let clipboard = Clipboard::file(&path);
+        let mut fb = Framebuffer::headless(20, 20);
+        fb.write_pixel((1, 1).into(), color::BLACK);
+        let rect = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 4,
+            height: 4,
+        };
+        clipboard.copy_region(&fb, rect).unwrap();
+
+        // Cut off at the bottom right corner
+        let pasted = clipboard
+            .paste_region(&mut fb, (17, 18).into())
+            .unwrap()
+            .unwrap();
+        assert_eq!((pasted.width, pasted.height), (3, 2));
+        assert_eq!(
+            fb.read_pixel((18, 19).into()).as_native(),
+            color::BLACK.as_native()
+        );
+
+        // Between framebuffers of different formats
+        let mut color_fb = Framebuffer::headless_color(20, 20);
+        clipboard
+            .paste_region(&mut color_fb, (0, 0).into())
+            .unwrap();
+        assert_eq!(color_fb.read_pixel((1, 1).into()), color::RGB(0, 0, 0));
+        color_fb.write_pixel((2, 2).into(), color::RGB(0, 0, 0xff));
+        clipboard.copy_region(&color_fb, rect).unwrap();
+        clipboard.paste_region(&mut fb, (0, 0).into()).unwrap();
+        assert_eq!(
+            fb.read_pixel((2, 2).into()).as_native(),
+            color::BLUE.as_native()
+        );
+
+        // Cut off at the edges of the rotated screen, not those of the panel
+        let mut rotated = Framebuffer::headless(20, 30);
+        rotated.set_rotation(ScreenRotation::Rot90);
+        let pasted = clipboard
+            .paste_region(&mut rotated, (27, 17).into())
+            .unwrap()
+            .unwrap();
+        assert_eq!((pasted.width, pasted.height), (3, 3));
+        assert_eq!(
+            rotated.read_pixel((29, 19).into()).as_native(),
+            color::BLUE.as_native()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs::OpenOptions;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+
+use log::warn;
+use memmap2::MmapOptions;
+
+use super::protocol::{read_message, write_message, ClientMessage, ServerMessage};
+use super::{CompositorError, Layer};
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core::{Framebuffer, FramebufferUpdate};
+use crate::framebuffer::mxcfb::mxcfb_update_data;
+use crate::input::InputEvent;
+
+/// A client's connection to the compositor, which refreshes are sent over
+pub struct Connection {
+    stream: UnixStream,
+}
+
+impl Connection {
+    pub(crate) fn send_damage(&self, update: &mxcfb_update_data) -> bool {
+        let damage = ClientMessage::Damage {
+            region: update.update_region,
+            waveform_mode: update.waveform_mode,
+            full: update.update_mode
+                == crate::framebuffer::common::update_mode::UPDATE_MODE_FULL as u32,
+        };
+        match write_message(&self.stream, &damage.encode()) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Failed to send damage to the compositor: {}", err);
+                false
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // The thread reading input holds on to the socket as well
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// A rectangle of the screen owned by this app
+pub struct Surface {
+    pub id: u32,
+    /// The part of the screen covered, (0, 0) of `framebuffer` is at its top left
+    pub rect: mxcfb_rect,
+    /// Drawing into this and refreshing it updates the surface on the screen
+    pub framebuffer: Framebuffer,
+    /// The input over the surface, in surface coordinates. Disconnects when the
+    /// compositor exits.
+    pub events: Receiver<InputEvent>,
+}
+
+impl Surface {
+    /// Asks the compositor listening at `socket` for a surface covering `rect`
+    pub fn connect(
+        socket: impl AsRef<Path>,
+        rect: mxcfb_rect,
+        layer: Layer,
+    ) -> Result<Surface, CompositorError> {
+        let mut stream = UnixStream::connect(socket)?;
+        write_message(&stream, &ClientMessage::Create { rect, layer }.encode())?;
+        let (id, rect, bits_per_pixel, path) =
+            match ServerMessage::decode(&read_message(&mut stream)?)? {
+                ServerMessage::Created {
+                    id,
+                    rect,
+                    bits_per_pixel: bits_per_pixel @ (16 | 32),
+                    path,
+                } => (id, rect, bits_per_pixel, path),
+                other => return Err(CompositorError::Protocol(format!("{:?}", other))),
+            };
+
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let frame = MmapOptions::new()
+            .len(rect.width as usize * rect.height as usize * (bits_per_pixel / 8) as usize)
+            .map_raw(&file)?;
+
+        let (tx, events) = channel();
+        let mut reader = stream.try_clone()?;
+        std::thread::spawn(move || loop {
+            let message = read_message(&mut reader).and_then(|m| ServerMessage::decode(&m));
+            match message {
+                Ok(ServerMessage::Input { event }) => {
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                }
+                Ok(other) => warn!("Unexpected message from the compositor: {:?}", other),
+                Err(_) => break,
+            }
+        });
+
+        let connection = FramebufferUpdate::Compositor(Connection { stream });
+        Ok(Surface {
+            id,
+            rect,
+            framebuffer: Framebuffer::with_memory(
+                frame,
+                rect.width,
+                rect.height,
+                bits_per_pixel,
+                connection,
+            ),
+            events,
+        })
+    }
+}
@@ -0,0 +1,47 @@
+//! An experimental compositor, letting several apps share the display.
+//!
+//! The `Compositor` server owns the real framebuffer and the input devices. Every
+//! client gets a `Surface`, a rectangle of the screen backed by shared memory, which
+//! it draws into with the usual `Framebuffer` API. Refreshing a region of the surface
+//! makes the server composite it onto the display, and input over a surface is
+//! delivered to its client in surface coordinates. This allows e.g. two apps side
+//! by side, or a clock overlaid on top of another app:
+//!
+//! ```no_run
+//! use libremarkable::compositor::{Layer, Surface, DEFAULT_SOCKET};
+//! use libremarkable::framebuffer::common::mxcfb_rect;
+//!
+//! let rect = mxcfb_rect { top: 20, left: 1100, width: 280, height: 80 };
+//! let mut clock = Surface::connect(DEFAULT_SOCKET, rect, Layer::Overlay).unwrap();
+//! // Draw into clock.framebuffer and refresh it as usual
+//! ```
+
+mod client;
+pub mod protocol;
+mod server;
+
+pub use self::client::{Connection, Surface};
+pub use self::server::Compositor;
+
+/// Where the compositor listens by default
+pub const DEFAULT_SOCKET: &str = "/run/libremarkable-compositor.sock";
+
+/// Stacking of surfaces. Surfaces on the same layer stack in the order they were
+/// created.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    /// Apps, which receive the input over them
+    Normal,
+    /// Always above the normal surfaces. Input passes through to the surfaces below,
+    /// e.g. for a clock or status bar.
+    Overlay,
+}
+
+/// Why talking to the compositor, or to one of its clients, failed
+#[derive(Debug, thiserror::Error)]
+pub enum CompositorError {
+    #[error("Compositor connection failed")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed compositor message: {0}")]
+    Protocol(String),
+}
@@ -0,0 +1,497 @@
+//! Messages between the compositor and its clients.
+//!
+//! Every message is sent over the Unix socket as a little endian `u32` length
+//! followed by that many bytes, the first of which tells the kind of message.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use super::{CompositorError, Layer};
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::input::{
+    Finger, GPIOEvent, InputEvent, MultitouchEvent, PhysicalButton, StylusButtons, WacomEvent,
+    WacomPen, WacomTool,
+};
+
+/// Messages larger than this are rejected rather than buffered
+const MAX_MESSAGE_LEN: u32 = 64 * 1024;
+
+/// Sent by clients
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    /// Asks for a surface covering `rect` of the screen. Only one per connection.
+    Create { rect: mxcfb_rect, layer: Layer },
+    /// The client drew into `region` of its surface, to be refreshed with
+    /// `waveform_mode`
+    Damage {
+        region: mxcfb_rect,
+        waveform_mode: u32,
+        full: bool,
+    },
+}
+
+/// Sent by the compositor
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    /// The surface was created. Its pixels are in the file at `path`, in the format
+    /// of the display with `bits_per_pixel`, and `rect` is the part of the screen it
+    /// covers, which may be smaller than requested.
+    Created {
+        id: u32,
+        rect: mxcfb_rect,
+        bits_per_pixel: u32,
+        path: PathBuf,
+    },
+    /// Input over the surface, in surface coordinates
+    Input { event: InputEvent },
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Encoder::default();
+        match self {
+            ClientMessage::Create { rect, layer } => {
+                out.u8(1);
+                out.rect(rect);
+                out.u8(*layer as u8);
+            }
+            ClientMessage::Damage {
+                region,
+                waveform_mode,
+                full,
+            } => {
+                out.u8(2);
+                out.rect(region);
+                out.u32(*waveform_mode);
+                out.u8(u8::from(*full));
+            }
+        }
+        out.0
+    }
+
+    pub fn decode(data: &[u8]) -> Result<ClientMessage, CompositorError> {
+        let mut data = Decoder(data);
+        let message = match data.u8()? {
+            1 => ClientMessage::Create {
+                rect: data.rect()?,
+                layer: match data.u8()? {
+                    0 => Layer::Normal,
+                    1 => Layer::Overlay,
+                    other => return Err(malformed(format!("unknown layer {}", other))),
+                },
+            },
+            2 => ClientMessage::Damage {
+                region: data.rect()?,
+                waveform_mode: data.u32()?,
+                full: data.u8()? != 0,
+            },
+            other => return Err(malformed(format!("unknown client message {}", other))),
+        };
+        data.finish(message)
+    }
+}
+
+impl ServerMessage {
+    /// `None` for input events that aren't sent to clients
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let mut out = Encoder::default();
+        match self {
+            ServerMessage::Created {
+                id,
+                rect,
+                bits_per_pixel,
+                path,
+            } => {
+                out.u8(1);
+                out.u32(*id);
+                out.rect(rect);
+                out.u32(*bits_per_pixel);
+                out.0.extend_from_slice(path.to_str()?.as_bytes());
+            }
+            ServerMessage::Input { event } => {
+                out.u8(2);
+                encode_input(&mut out, event)?;
+            }
+        }
+        Some(out.0)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<ServerMessage, CompositorError> {
+        let mut data = Decoder(data);
+        let message = match data.u8()? {
+            1 => ServerMessage::Created {
+                id: data.u32()?,
+                rect: data.rect()?,
+                bits_per_pixel: data.u32()?,
+                path: PathBuf::from(
+                    String::from_utf8(data.rest().to_vec())
+                        .map_err(|_| malformed("surface path".to_owned()))?,
+                ),
+            },
+            2 => ServerMessage::Input {
+                event: decode_input(&mut data)?,
+            },
+            other => return Err(malformed(format!("unknown server message {}", other))),
+        };
+        data.finish(message)
+    }
+}
+
+/// Writes one message
+pub fn write_message(mut stream: impl Write, message: &[u8]) -> std::io::Result<()> {
+    // One write, so messages of several threads can't interleave
+    let mut frame = Vec::with_capacity(4 + message.len());
+    frame.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    frame.extend_from_slice(message);
+    stream.write_all(&frame)
+}
+
+/// Reads one message, blocking until it's complete
+pub fn read_message(mut stream: impl Read) -> Result<Vec<u8>, CompositorError> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        return Err(malformed(format!("message of {} bytes", len)));
+    }
+    let mut message = vec![0u8; len as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn malformed(what: String) -> CompositorError {
+    CompositorError::Protocol(what)
+}
+
+fn encode_input(out: &mut Encoder, event: &InputEvent) -> Option<()> {
+    match event {
+        InputEvent::WacomEvent { event } => match event {
+            WacomEvent::InstrumentChange { pen, state } => {
+                out.u8(1);
+                out.u16(*pen as u16);
+                out.u8(u8::from(*state));
+            }
+            WacomEvent::Hover {
+                position,
+                distance,
+                tilt,
+                tool,
+                buttons,
+            } => {
+                out.u8(2);
+                out.point(position);
+                out.u16(*distance);
+                out.u16(tilt.x);
+                out.u16(tilt.y);
+                out.tool(*tool, *buttons);
+            }
+            WacomEvent::Draw {
+                position,
+                pressure,
+                tilt,
+                tool,
+                buttons,
+            } => {
+                out.u8(3);
+                out.point(position);
+                out.u16(*pressure);
+                out.u16(tilt.x);
+                out.u16(tilt.y);
+                out.tool(*tool, *buttons);
+            }
+            WacomEvent::Unknown => return None,
+        },
+        InputEvent::MultitouchEvent { event } => {
+            let (kind, finger) = match event {
+                MultitouchEvent::Press { finger } => (4, finger),
+                MultitouchEvent::Release { finger } => (5, finger),
+                MultitouchEvent::Move { finger } => (6, finger),
+                MultitouchEvent::Unknown => return None,
+            };
+            out.u8(kind);
+            out.u32(finger.tracking_id as u32);
+            out.u16(finger.pos.x);
+            out.u16(finger.pos.y);
+            out.u16(finger.pressure);
+            out.u16(finger.size);
+        }
+        InputEvent::GPIO { event } => {
+            let (kind, button) = match event {
+                GPIOEvent::Press { button } => (7, button),
+                GPIOEvent::Unpress { button } => (8, button),
+                GPIOEvent::Unknown => return None,
+            };
+            out.u8(kind);
+            out.u8(match button {
+                PhysicalButton::LEFT => 0,
+                PhysicalButton::MIDDLE => 1,
+                PhysicalButton::RIGHT => 2,
+                PhysicalButton::POWER => 3,
+                PhysicalButton::WAKEUP => 4,
+            });
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+fn decode_input(data: &mut Decoder) -> Result<InputEvent, CompositorError> {
+    let kind = data.u8()?;
+    let event = match kind {
+        1 => WacomEvent::InstrumentChange {
+            pen: WacomPen::from_code(data.u16()?).ok_or_else(|| malformed("pen".to_owned()))?,
+            state: data.u8()? != 0,
+        },
+        2 => {
+            let (position, distance, tilt) = (data.point()?, data.u16()?, data.tilt()?);
+            let (tool, buttons) = data.tool()?;
+            WacomEvent::Hover {
+                position,
+                distance,
+                tilt,
+                tool,
+                buttons,
+            }
+        }
+        3 => {
+            let (position, pressure, tilt) = (data.point()?, data.u16()?, data.tilt()?);
+            let (tool, buttons) = data.tool()?;
+            WacomEvent::Draw {
+                position,
+                pressure,
+                tilt,
+                tool,
+                buttons,
+            }
+        }
+        4..=6 => {
+            let tracking_id = data.u32()? as i32;
+            let pos = cgmath::Point2 {
+                x: data.u16()?,
+                y: data.u16()?,
+            };
+            let mut finger = Finger::new(tracking_id, pos, kind != 5);
+            finger.pressure = data.u16()?;
+            finger.size = data.u16()?;
+            let event = match kind {
+                4 => MultitouchEvent::Press { finger },
+                5 => MultitouchEvent::Release { finger },
+                _ => MultitouchEvent::Move { finger },
+            };
+            return Ok(InputEvent::MultitouchEvent { event });
+        }
+        7 | 8 => {
+            let button = match data.u8()? {
+                0 => PhysicalButton::LEFT,
+                1 => PhysicalButton::MIDDLE,
+                2 => PhysicalButton::RIGHT,
+                3 => PhysicalButton::POWER,
+                4 => PhysicalButton::WAKEUP,
+                other => return Err(malformed(format!("unknown button {}", other))),
+            };
+            let event = match kind {
+                7 => GPIOEvent::Press { button },
+                _ => GPIOEvent::Unpress { button },
+            };
+            return Ok(InputEvent::GPIO { event });
+        }
+        other => return Err(malformed(format!("unknown input event {}", other))),
+    };
+    Ok(InputEvent::WacomEvent { event })
+}
+
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn rect(&mut self, rect: &mxcfb_rect) {
+        self.u32(rect.left);
+        self.u32(rect.top);
+        self.u32(rect.width);
+        self.u32(rect.height);
+    }
+
+    fn point(&mut self, point: &cgmath::Point2<f32>) {
+        self.u32(point.x.to_bits());
+        self.u32(point.y.to_bits());
+    }
+
+    /// One bit each for the eraser and the two buttons
+    fn tool(&mut self, tool: WacomTool, buttons: StylusButtons) {
+        self.u8(u8::from(tool == WacomTool::Eraser)
+            | u8::from(buttons.primary) << 1
+            | u8::from(buttons.secondary) << 2);
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], CompositorError> {
+        if self.0.len() < N {
+            return Err(malformed("message too short".to_owned()));
+        }
+        let (value, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(value.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, CompositorError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, CompositorError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, CompositorError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn rect(&mut self) -> Result<mxcfb_rect, CompositorError> {
+        Ok(mxcfb_rect {
+            left: self.u32()?,
+            top: self.u32()?,
+            width: self.u32()?,
+            height: self.u32()?,
+        })
+    }
+
+    fn point(&mut self) -> Result<cgmath::Point2<f32>, CompositorError> {
+        Ok(cgmath::Point2 {
+            x: f32::from_bits(self.u32()?),
+            y: f32::from_bits(self.u32()?),
+        })
+    }
+
+    fn tilt(&mut self) -> Result<cgmath::Vector2<u16>, CompositorError> {
+        Ok(cgmath::Vector2 {
+            x: self.u16()?,
+            y: self.u16()?,
+        })
+    }
+
+    fn tool(&mut self) -> Result<(WacomTool, StylusButtons), CompositorError> {
+        let bits = self.u8()?;
+        let tool = match bits & 1 {
+            0 => WacomTool::Pen,
+            _ => WacomTool::Eraser,
+        };
+        let buttons = StylusButtons {
+            primary: bits & 2 != 0,
+            secondary: bits & 4 != 0,
+        };
+        Ok((tool, buttons))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.0)
+    }
+
+    /// `message`, if all of the data was used for it
+    fn finish<T>(self, message: T) -> Result<T, CompositorError> {
+        match self.0.is_empty() {
+            true => Ok(message),
+            false => Err(malformed("trailing data".to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let rect = mxcfb_rect {
+            left: 10,
+            top: 20,
+            width: 300,
+            height: 400,
+        };
+        for message in [
+            ClientMessage::Create {
+                rect,
+                layer: Layer::Overlay,
+            },
+            ClientMessage::Damage {
+                region: rect,
+                waveform_mode: 3,
+                full: true,
+            },
+        ] {
+            assert_eq!(ClientMessage::decode(&message.encode()).unwrap(), message);
+        }
+
+        let events = [
+            InputEvent::WacomEvent {
+                event: WacomEvent::InstrumentChange {
+                    pen: WacomPen::ToolRubber,
+                    state: true,
+                },
+            },
+            InputEvent::WacomEvent {
+                event: WacomEvent::Draw {
+                    position: cgmath::Point2 { x: 1.5, y: 700.25 },
+                    pressure: 2000,
+                    tilt: cgmath::Vector2 { x: 10, y: 65000 },
+                    tool: WacomTool::Eraser,
+                    buttons: StylusButtons {
+                        primary: false,
+                        secondary: true,
+                    },
+                },
+            },
+            InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Release {
+                    finger: Finger::new(-1, cgmath::Point2 { x: 5, y: 6 }, false),
+                },
+            },
+            InputEvent::GPIO {
+                event: GPIOEvent::Press {
+                    button: PhysicalButton::MIDDLE,
+                },
+            },
+        ];
+        let created = ServerMessage::Created {
+            id: 7,
+            rect,
+            bits_per_pixel: 32,
+            path: PathBuf::from("/dev/shm/surface"),
+        };
+        let inputs = events.map(|event| ServerMessage::Input { event });
+        for message in std::iter::once(created).chain(inputs) {
+            let encoded = message.encode().unwrap();
+            assert_eq!(ServerMessage::decode(&encoded).unwrap(), message);
+        }
+        assert!(ServerMessage::Input {
+            event: InputEvent::Unknown {}
+        }
+        .encode()
+        .is_none());
+
+        // Truncated and oversized messages
+        let encoded = ClientMessage::Create {
+            rect,
+            layer: Layer::Normal,
+        }
+        .encode();
+        assert!(ClientMessage::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(ClientMessage::decode(&[encoded.as_slice(), &[0]].concat()).is_err());
+        let mut stream = std::io::Cursor::new(u32::MAX.to_le_bytes());
+        assert!(read_message(&mut stream).is_err());
+    }
+}
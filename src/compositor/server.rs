@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use log::warn;
+use memmap2::{MmapOptions, MmapRaw};
+
+use super::protocol::{read_message, write_message, ClientMessage, ServerMessage};
+use super::{CompositorError, Layer};
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{display_temp, dither_mode, mxcfb_rect, waveform_mode};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::{FramebufferIO, FramebufferRefresh, PartialRefreshMode};
+use crate::input::ev::EvDevContext;
+use crate::input::{GPIOEvent, InputDevice, InputError, InputEvent, MultitouchEvent, WacomEvent};
+
+/// How long sending input may block on a client that doesn't read it
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+enum Event {
+    Connected(UnixStream),
+    Message(u32, ClientMessage),
+    Disconnected(u32),
+    Input(InputEvent),
+}
+
+struct ServerSurface {
+    id: u32,
+    rect: mxcfb_rect,
+    layer: Layer,
+    path: PathBuf,
+    pixels: MmapRaw,
+}
+
+/// Owns the display and input devices, and composites the surfaces of its clients
+/// onto it
+pub struct Compositor {
+    framebuffer: Framebuffer,
+    socket: PathBuf,
+    /// Where the files backing the surfaces are created, `/dev/shm` by default
+    pub surface_dir: PathBuf,
+    tx: Sender<Event>,
+    events: Receiver<Event>,
+    clients: HashMap<u32, UnixStream>,
+    /// Bottom to top
+    surfaces: Vec<ServerSurface>,
+    next_id: u32,
+    /// The surface the pen is over, or drawing on
+    pen: Option<u32>,
+    pen_down: bool,
+    /// The surfaces the fingers went down on, by tracking id
+    fingers: HashMap<i32, u32>,
+    /// The surface the buttons go to, the one last touched
+    focus: Option<u32>,
+    input: Vec<EvDevContext>,
+}
+
+impl Compositor {
+    /// Listens for clients at `socket`, compositing them onto `framebuffer`
+    pub fn new(
+        framebuffer: Framebuffer,
+        socket: impl AsRef<Path>,
+    ) -> Result<Compositor, CompositorError> {
+        let socket = socket.as_ref().to_path_buf();
+        // Left behind by a previous run that didn't exit cleanly
+        if let Ok(metadata) = std::fs::metadata(&socket) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(&socket)?;
+            }
+        }
+        let listener = UnixListener::bind(&socket)?;
+
+        let (tx, events) = channel();
+        let connections = tx.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if connections.send(Event::Connected(stream)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => warn!("Failed to accept a compositor client: {}", err),
+                }
+            }
+        });
+
+        Ok(Compositor {
+            framebuffer,
+            socket,
+            surface_dir: PathBuf::from("/dev/shm"),
+            tx,
+            events,
+            clients: HashMap::new(),
+            surfaces: Vec::new(),
+            next_id: 1,
+            pen: None,
+            pen_down: false,
+            fingers: HashMap::new(),
+            focus: None,
+            input: Vec::new(),
+        })
+    }
+
+    /// The display, as composited so far
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Starts reading the input devices, to route their events to the clients
+    pub fn start_input(&mut self) -> Result<(), InputError> {
+        let (input_tx, input_rx) = channel();
+        for device in [
+            InputDevice::Wacom,
+            InputDevice::Multitouch,
+            InputDevice::GPIO,
+        ] {
+            let mut context = EvDevContext::new(device, input_tx.clone());
+            context.start()?;
+            self.input.push(context);
+        }
+        let tx = self.tx.clone();
+        std::thread::spawn(move || {
+            for event in input_rx {
+                if tx.send(Event::Input(event)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Serves the clients forever
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.dispatch(None);
+        }
+    }
+
+    /// Handles the next connection, client message or input event, waiting up to
+    /// `timeout` for one. Returns whether there was one.
+    pub fn dispatch(&mut self, timeout: Option<Duration>) -> bool {
+        let event = match timeout {
+            Some(timeout) => match self.events.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(_) => return false,
+            },
+            // Can't disconnect, `self.tx` is a sender
+            None => self.events.recv().unwrap(),
+        };
+        match event {
+            Event::Connected(stream) => self.connect(stream),
+            Event::Message(id, message) => self.handle(id, message),
+            Event::Disconnected(id) => self.disconnect(id),
+            Event::Input(event) => self.route_input(event),
+        }
+        true
+    }
+
+    /// Sends `event` to the surface it is over, or the focused one for buttons
+    pub fn route_input(&mut self, event: InputEvent) {
+        let target = match event {
+            InputEvent::WacomEvent { event } => match event {
+                WacomEvent::Draw { position, .. } => {
+                    // Keeps drawing on the surface the pen went down on
+                    if !self.pen_down {
+                        self.pen_down = true;
+                        self.pen = self.surface_at(position);
+                        self.focus = self.pen.or(self.focus);
+                    }
+                    self.pen
+                }
+                WacomEvent::Hover { position, .. } => {
+                    self.pen_down = false;
+                    self.pen = self.surface_at(position);
+                    self.pen
+                }
+                WacomEvent::InstrumentChange { .. } => self.pen,
+                WacomEvent::Unknown => None,
+            },
+            InputEvent::MultitouchEvent { event } => match event {
+                MultitouchEvent::Press { finger } => {
+                    let target = self.surface_at(finger.pos.cast().unwrap());
+                    if let Some(id) = target {
+                        self.fingers.insert(finger.tracking_id, id);
+                        self.focus = Some(id);
+                    }
+                    target
+                }
+                MultitouchEvent::Move { finger } => self.fingers.get(&finger.tracking_id).copied(),
+                MultitouchEvent::Release { finger } => self.fingers.remove(&finger.tracking_id),
+                MultitouchEvent::Unknown => None,
+            },
+            InputEvent::GPIO { event } if event != GPIOEvent::Unknown => self.focus.or_else(|| {
+                let mut normal = self.surfaces.iter().filter(|s| s.layer == Layer::Normal);
+                normal.next_back().map(|s| s.id)
+            }),
+            _ => None,
+        };
+
+        let surface = match target.and_then(|id| self.surfaces.iter().find(|s| s.id == id)) {
+            Some(surface) => surface,
+            None => return,
+        };
+        let message = ServerMessage::Input {
+            event: to_surface(event, &surface.rect),
+        };
+        if let (Some(stream), Some(message)) = (self.clients.get(&surface.id), message.encode()) {
+            // Failures are noticed by the reading side
+            let _ = write_message(stream, &message);
+        }
+    }
+
+    fn connect(&mut self, stream: UnixStream) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(err) => return warn!("Failed to set up a compositor client: {}", err),
+        };
+        let _ = stream.set_write_timeout(Some(SEND_TIMEOUT));
+        self.clients.insert(id, stream);
+
+        let tx = self.tx.clone();
+        std::thread::spawn(move || loop {
+            let message = read_message(&mut reader).and_then(|m| ClientMessage::decode(&m));
+            let event = match message {
+                Ok(message) => Event::Message(id, message),
+                Err(err) => {
+                    if let CompositorError::Protocol(_) = err {
+                        warn!("Disconnecting compositor client {}: {}", id, err);
+                    }
+                    Event::Disconnected(id)
+                }
+            };
+            let disconnected = matches!(event, Event::Disconnected(_));
+            if tx.send(event).is_err() || disconnected {
+                break;
+            }
+        });
+    }
+
+    fn handle(&mut self, id: u32, message: ClientMessage) {
+        match message {
+            ClientMessage::Create { rect, layer } => {
+                if let Err(err) = self.create(id, rect, layer) {
+                    warn!("Failed to create a surface for client {}: {}", id, err);
+                    self.drop_client(id);
+                }
+            }
+            ClientMessage::Damage {
+                region,
+                waveform_mode,
+                full,
+            } => {
+                let rect = match self.surfaces.iter().find(|s| s.id == id) {
+                    Some(surface) => surface.rect,
+                    None => return self.drop_client(id),
+                };
+                let size = mxcfb_rect {
+                    left: 0,
+                    top: 0,
+                    width: rect.width,
+                    height: rect.height,
+                };
+                if let Some(region) = region.intersection(&size) {
+                    let on_screen = mxcfb_rect {
+                        left: rect.left + region.left,
+                        top: rect.top + region.top,
+                        ..region
+                    };
+                    self.compose(&on_screen);
+                    self.refresh(
+                        &on_screen,
+                        waveform_mode::from_raw(waveform_mode)
+                            .unwrap_or(waveform_mode::WAVEFORM_MODE_GC16_FAST),
+                        full,
+                    );
+                }
+            }
+        }
+    }
+
+    fn create(&mut self, id: u32, rect: mxcfb_rect, layer: Layer) -> Result<(), CompositorError> {
+        if self.surfaces.iter().any(|s| s.id == id) {
+            return Err(CompositorError::Protocol("second surface".to_owned()));
+        }
+        let rect = rect
+            .intersection(&self.screen())
+            .ok_or_else(|| CompositorError::Protocol("surface off screen".to_owned()))?;
+
+        let path = self.surface_dir.join(format!(
+            "libremarkable-surface-{}-{}",
+            std::process::id(),
+            id
+        ));
+        let len = (rect.width * rect.height * self.bytespp()) as usize;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len(len as u64)?;
+        let pixels = MmapOptions::new().len(len).map_raw(&file)?;
+        // White like a cleared display
+        unsafe { std::ptr::write_bytes(pixels.as_mut_ptr(), 0xff, len) };
+
+        // Above the other surfaces of its layer, below those of higher layers
+        let index = self.surfaces.partition_point(|s| s.layer <= layer);
+        self.surfaces.insert(
+            index,
+            ServerSurface {
+                id,
+                rect,
+                layer,
+                path: path.clone(),
+                pixels,
+            },
+        );
+        if layer == Layer::Normal {
+            self.focus = Some(id);
+        }
+
+        if let Some(stream) = self.clients.get(&id) {
+            let created = ServerMessage::Created {
+                id,
+                rect,
+                bits_per_pixel: self.framebuffer.var_screen_info.bits_per_pixel,
+                path,
+            };
+            write_message(stream, &created.encode().unwrap())?;
+        }
+        self.compose(&rect);
+        self.refresh(&rect, waveform_mode::WAVEFORM_MODE_GC16_FAST, false);
+        Ok(())
+    }
+
+    fn drop_client(&mut self, id: u32) {
+        if let Some(stream) = self.clients.get(&id) {
+            // The reading side notices and reports the disconnect
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    fn disconnect(&mut self, id: u32) {
+        self.clients.remove(&id);
+        self.fingers.retain(|_, surface| *surface != id);
+        if self.pen == Some(id) {
+            self.pen = None;
+        }
+        if self.focus == Some(id) {
+            self.focus = None;
+        }
+        if let Some(index) = self.surfaces.iter().position(|s| s.id == id) {
+            let surface = self.surfaces.remove(index);
+            let _ = std::fs::remove_file(&surface.path);
+            self.compose(&surface.rect);
+            self.refresh(&surface.rect, waveform_mode::WAVEFORM_MODE_GC16_FAST, false);
+        }
+    }
+
+    /// Of the display, which the surfaces share
+    fn bytespp(&self) -> u32 {
+        self.framebuffer.var_screen_info.bits_per_pixel / 8
+    }
+
+    fn screen(&self) -> mxcfb_rect {
+        mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: self.framebuffer.var_screen_info.xres,
+            height: self.framebuffer.var_screen_info.yres,
+        }
+    }
+
+    /// The topmost surface at `position` that takes input
+    fn surface_at(&self, position: cgmath::Point2<f32>) -> Option<u32> {
+        if position.x < 0.0 || position.y < 0.0 {
+            return None;
+        }
+        let point = cgmath::Point2 {
+            x: position.x as u32,
+            y: position.y as u32,
+        };
+        self.surfaces
+            .iter()
+            .rev()
+            .filter(|s| s.layer == Layer::Normal)
+            .find(|s| {
+                point.x >= s.rect.left
+                    && point.y >= s.rect.top
+                    && point.x < s.rect.left + s.rect.width
+                    && point.y < s.rect.top + s.rect.height
+            })
+            .map(|s| s.id)
+    }
+
+    /// Copies what is visible of the surfaces in `rect` onto the display. Parts not
+    /// covered by any surface are white.
+    fn compose(&mut self, rect: &mxcfb_rect) {
+        let rect = match rect.intersection(&self.screen()) {
+            Some(rect) => rect,
+            None => return,
+        };
+        let bytespp = self.bytespp() as usize;
+        let mut pixels = vec![0xffu8; rect.width as usize * rect.height as usize * bytespp];
+        for surface in &self.surfaces {
+            let overlap = match rect.intersection(&surface.rect) {
+                Some(overlap) => overlap,
+                None => continue,
+            };
+            let row = overlap.width as usize * bytespp;
+            for y in overlap.top..overlap.top + overlap.height {
+                let from = ((y - surface.rect.top) * surface.rect.width + overlap.left
+                    - surface.rect.left) as usize
+                    * bytespp;
+                let to =
+                    ((y - rect.top) * rect.width + overlap.left - rect.left) as usize * bytespp;
+                // The client may be drawing into the surface at the same time, which at
+                // worst shows a partially drawn row until its next refresh
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        surface.pixels.as_ptr().add(from),
+                        pixels.as_mut_ptr().add(to),
+                        row,
+                    );
+                }
+            }
+        }
+        if let Err(err) = self.framebuffer.restore_region(rect, &pixels) {
+            warn!("Failed to composite {:?}: {}", rect, err);
+        }
+    }
+
+    fn refresh(&self, rect: &mxcfb_rect, waveform_mode: waveform_mode, full: bool) {
+        self.framebuffer.partial_refresh(
+            rect,
+            PartialRefreshMode::Async,
+            waveform_mode,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            full,
+        );
+    }
+}
+
+impl Drop for Compositor {
+    fn drop(&mut self) {
+        for context in &mut self.input {
+            context.stop();
+        }
+        for surface in &self.surfaces {
+            let _ = std::fs::remove_file(&surface.path);
+        }
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+/// `event` relative to the top left of `rect`
+fn to_surface(event: InputEvent, rect: &mxcfb_rect) -> InputEvent {
+    let offset = cgmath::Vector2 {
+        x: rect.left as f32,
+        y: rect.top as f32,
+    };
+    let finger = |mut finger: crate::input::Finger| {
+        finger.pos.x = finger.pos.x.saturating_sub(rect.left as u16);
+        finger.pos.y = finger.pos.y.saturating_sub(rect.top as u16);
+        finger
+    };
+    match event {
+        InputEvent::WacomEvent { mut event } => {
+            if let WacomEvent::Hover { position, .. } | WacomEvent::Draw { position, .. } =
+                &mut event
+            {
+                *position -= offset;
+            }
+            InputEvent::WacomEvent { event }
+        }
+        InputEvent::MultitouchEvent { event } => InputEvent::MultitouchEvent {
+            event: match event {
+                MultitouchEvent::Press { finger: f } => {
+                    MultitouchEvent::Press { finger: finger(f) }
+                }
+                MultitouchEvent::Move { finger: f } => MultitouchEvent::Move { finger: finger(f) },
+                MultitouchEvent::Release { finger: f } => {
+                    MultitouchEvent::Release { finger: finger(f) }
+                }
+                other => other,
+            },
+        },
+        other => other,
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::compositor::Surface;
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::FramebufferDraw;
+    use crate::input::Finger;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn connect(
+        compositor: &mut Compositor,
+        socket: &Path,
+        rect: mxcfb_rect,
+        layer: Layer,
+    ) -> Surface {
+        let socket = socket.to_path_buf();
+        let client = std::thread::spawn(move || Surface::connect(socket, rect, layer));
+        // The connection and the request for the surface
+        assert!(compositor.dispatch(Some(TIMEOUT)));
+        assert!(compositor.dispatch(Some(TIMEOUT)));
+        client.join().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_compositing() {
+        let dir = std::env::temp_dir().join(format!("compositor-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("compositor.sock");
+        let mut compositor = Compositor::new(Framebuffer::headless(100, 100), &socket).unwrap();
+        compositor.surface_dir = dir.clone();
+
+        let left_half = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 50,
+            height: 100,
+        };
+        let mut app = connect(&mut compositor, &socket, left_half, Layer::Normal);
+        // Only the part on the screen
+        let overlay_rect = mxcfb_rect {
+            left: 40,
+            top: 90,
+            width: 100,
+            height: 100,
+        };
+        let overlay = connect(&mut compositor, &socket, overlay_rect, Layer::Overlay);
+        assert_eq!(overlay.rect.width, 60);
+        assert_eq!(overlay.rect.height, 10);
+
+        app.framebuffer
+            .fill_rect((0, 0).into(), (50, 100).into(), color::BLACK);
+        app.framebuffer.full_refresh(
+            waveform_mode::WAVEFORM_MODE_GC16,
+            display_temp::TEMP_USE_AMBIENT,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        );
+        assert!(compositor.dispatch(Some(TIMEOUT)));
+        let fb = compositor.framebuffer();
+        assert_eq!(
+            fb.read_pixel((10, 10).into()).as_native(),
+            color::BLACK.as_native()
+        );
+        assert_eq!(
+            fb.read_pixel((60, 10).into()).as_native(),
+            color::WHITE.as_native()
+        );
+        // The overlay is still white and on top
+        assert_eq!(
+            fb.read_pixel((45, 95).into()).as_native(),
+            color::WHITE.as_native()
+        );
+        let refresh = fb.take_refreshes().pop().unwrap();
+        assert_eq!(refresh.update_region, left_half);
+
+        // Input goes to the app below the overlay, in its coordinates
+        compositor.route_input(InputEvent::MultitouchEvent {
+            event: MultitouchEvent::Press {
+                finger: Finger::new(1, (45, 95).into(), true),
+            },
+        });
+        let event = app.events.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(
+            event,
+            InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Press {
+                    finger: Finger::new(1, (45, 95).into(), true),
+                },
+            }
+        );
+        compositor.route_input(InputEvent::WacomEvent {
+            event: WacomEvent::Hover {
+                position: (80.0, 50.0).into(),
+                distance: 10,
+                tilt: (0, 0).into(),
+                tool: Default::default(),
+                buttons: Default::default(),
+            },
+        });
+        assert!(overlay.events.try_recv().is_err());
+
+        // Closing the app uncovers the screen below
+        drop(app);
+        assert!(compositor.dispatch(Some(TIMEOUT)));
+        assert_eq!(
+            compositor
+                .framebuffer()
+                .read_pixel((10, 10).into())
+                .as_native(),
+            color::WHITE.as_native()
+        );
+
+        drop(overlay);
+        drop(compositor);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_color_surface() {
+        let dir = std::env::temp_dir().join(format!("compositor-color-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("compositor.sock");
+        let display = Framebuffer::headless_color(40, 40);
+        let mut compositor = Compositor::new(display, &socket).unwrap();
+        compositor.surface_dir = dir.clone();
+
+        let rect = mxcfb_rect {
+            left: 10,
+            top: 10,
+            width: 20,
+            height: 20,
+        };
+        let mut app = connect(&mut compositor, &socket, rect, Layer::Normal);
+        assert!(app.framebuffer.is_color());
+        let red = color::RGB(200, 30, 10);
+        app.framebuffer
+            .fill_rect((0, 0).into(), (20, 20).into(), red);
+        app.framebuffer.full_refresh(
+            waveform_mode::WAVEFORM_MODE_GC16,
+            display_temp::TEMP_USE_AMBIENT,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        );
+        assert!(compositor.dispatch(Some(TIMEOUT)));
+        let fb = compositor.framebuffer();
+        assert_eq!(fb.read_pixel((29, 29).into()), red);
+        assert_eq!(fb.read_pixel((30, 30).into()), color::RGB(255, 255, 255));
+
+        drop(app);
+        drop(compositor);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
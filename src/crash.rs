@@ -0,0 +1,251 @@
+//! Reporting panics in the field.
+//!
+//! Once installed, a `CrashHandler` turns a panic anywhere in the app into a report
+//! appended to a log file, with the backtrace and, with the `trace-io` feature, the
+//! recent input reads and refreshes. If the panic is on the main thread, it then
+//! replaces the app on the display with a screen saying it crashed and where the
+//! report is, and runs the command given to `run`.
+//!
+//! The handler runs before the panic unwinds, so it can't tell whether it will be
+//! caught with `catch_unwind`, and leaves ending the process to the panic by default.
+//! With `exit`, it ends the process on a panic of any thread, which closes the input
+//! devices and the framebuffer for whatever runs next instead of leaving a half dead
+//! app holding them until a reboot.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::error;
+
+use crate::framebuffer::cgmath::{Point2, Vector2};
+use crate::framebuffer::common::{color, display_temp, dither_mode, waveform_mode};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::{FramebufferDraw, FramebufferRefresh};
+
+/// Exit code of a process ended by the handler, the same as of an unhandled panic
+pub const EXIT_CODE: i32 = 101;
+
+/// Reports panics, see `install`
+#[derive(Clone, Debug)]
+pub struct CrashHandler {
+    log: PathBuf,
+    message: String,
+    screen: bool,
+    exit: bool,
+    command: Option<String>,
+}
+
+impl CrashHandler {
+    /// Appends reports to the file at `log`
+    pub fn new(log: impl AsRef<Path>) -> CrashHandler {
+        CrashHandler {
+            log: log.as_ref().to_path_buf(),
+            message: "Hold the power button to restart the device.".to_owned(),
+            screen: true,
+            exit: false,
+            command: None,
+        }
+    }
+
+    /// What the user can do now, shown on the crash screen
+    pub fn message(mut self, message: impl Into<String>) -> CrashHandler {
+        self.message = message.into();
+        self
+    }
+
+    /// Whether to draw the crash screen
+    pub fn screen(mut self, screen: bool) -> CrashHandler {
+        self.screen = screen;
+        self
+    }
+
+    /// Whether to end the process once a panic is reported, handling panics of threads
+    /// other than the main thread like those of the main thread, even if they would be
+    /// caught. Off by default.
+    pub fn exit(mut self, exit: bool) -> CrashHandler {
+        self.exit = exit;
+        self
+    }
+
+    /// Runs `command` with `sh` once the crash screen is up, e.g.
+    /// `sleep 10; systemctl start xochitl` to get back to the stock UI
+    pub fn run(mut self, command: impl Into<String>) -> CrashHandler {
+        self.command = Some(command.into());
+        self
+    }
+
+    /// Handles all panics from now on, after the previously installed panic hook
+    pub fn install(self) {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+
+            let message = match info.payload().downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => match info.payload().downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => "Box<dyn Any>".to_owned(),
+                },
+            };
+            let location = info
+                .location()
+                .map(|l| format!("{}:{}", l.file(), l.line()))
+                .unwrap_or_default();
+            self.handle(&message, &location);
+        }));
+    }
+
+    fn handle(&self, message: &str, location: &str) {
+        let report = report(message, location);
+        if let Err(err) = self.write(&report) {
+            error!(
+                "Failed to write the crash report to {:?}: {}",
+                self.log, err
+            );
+        }
+
+        // Panics of other threads may well be caught, or the app may carry on without
+        // the thread
+        if !self.exit && std::thread::current().name() != Some("main") {
+            return;
+        }
+
+        if self.screen {
+            match Framebuffer::try_new() {
+                Ok(mut fb) => {
+                    draw_screen(&mut fb, message, &self.message, &self.log);
+                    fb.full_refresh(
+                        waveform_mode::WAVEFORM_MODE_GC16,
+                        display_temp::TEMP_USE_AMBIENT,
+                        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                        0,
+                        true,
+                    );
+                }
+                Err(err) => error!(
+                    "Failed to open the framebuffer for the crash screen: {}",
+                    err
+                ),
+            }
+        }
+
+        if let Some(command) = &self.command {
+            if let Err(err) = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .spawn()
+            {
+                error!("Failed to run {:?} after the crash: {}", command, err);
+            }
+        }
+        if self.exit {
+            std::process::exit(EXIT_CODE);
+        }
+    }
+
+    fn write(&self, report: &str) -> std::io::Result<()> {
+        if let Some(dir) = self.log.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log)?;
+        log.write_all(report.as_bytes())?;
+        log.sync_all()
+    }
+}
+
+/// The report of a panic with `message` at `location` on the current thread
+fn report(message: &str, location: &str) -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let thread = std::thread::current();
+    let mut report = format!(
+        "Crashed at {}s since the epoch\nThread '{}' panicked at {}:\n{}\n\nBacktrace:\n{}\n",
+        since_epoch.as_secs(),
+        thread.name().unwrap_or("<unnamed>"),
+        location,
+        message,
+        std::backtrace::Backtrace::force_capture()
+    );
+    #[cfg(feature = "trace-io")]
+    {
+        report.push_str("\nRecent input and refreshes:\n");
+        for line in crate::trace::recent() {
+            report.push_str(&line);
+            report.push('\n');
+        }
+    }
+    report.push('\n');
+    report
+}
+
+/// Draws the crash screen, without refreshing
+fn draw_screen(fb: &mut Framebuffer, panic: &str, message: &str, log: &Path) {
+    let (width, height) = (fb.var_screen_info.xres, fb.var_screen_info.yres);
+    fb.fill_rect(
+        Point2 { x: 0, y: 0 },
+        Vector2 {
+            x: width,
+            y: height,
+        },
+        color::WHITE,
+    );
+    // Only what fits on a line, the report has the rest
+    let panic: String = panic
+        .lines()
+        .next()
+        .unwrap_or("")
+        .chars()
+        .take(60)
+        .collect();
+    let details = format!("Details are in {}", log.display());
+    let lines = [
+        ("The app crashed", 80.0, 0.0),
+        (panic.as_str(), 30.0, 0.0),
+        (message, 35.0, 40.0),
+        (details.as_str(), 30.0, 0.0),
+    ];
+    let margin = width as f32 / 12.0;
+    let mut y = height as f32 / 3.0;
+    for (text, size, gap) in lines {
+        y += gap;
+        fb.draw_text(Point2 { x: margin, y }, text, size, color::BLACK, false);
+        y += size * 1.5;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::FramebufferIO;
+
+    #[test]
+    fn test_crash_report() {
+        let report = report("index out of bounds", "src/main.rs:12");
+        assert!(report.contains("panicked at src/main.rs:12:\nindex out of bounds"));
+        assert!(report.contains("test_crash_report"), "{}", report);
+
+        let dir = std::env::temp_dir().join(format!("libremarkable-crash-{}", std::process::id()));
+        let handler = CrashHandler::new(dir.join("crash.log"));
+        handler.write("first\n").unwrap();
+        handler.write("second\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("crash.log")).unwrap(),
+            "first\nsecond\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut fb = Framebuffer::headless(600, 800);
+        draw_screen(&mut fb, "oops", "Restart", Path::new("/tmp/crash.log"));
+        let dark = (0..800)
+            .flat_map(|y| (0..600).map(move |x| Point2 { x, y }))
+            .filter(|p| fb.read_pixel(*p).to_rgb8()[0] < 128)
+            .count();
+        assert!(dark > 500, "{}", dark);
+    }
+}
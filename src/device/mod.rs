@@ -8,6 +8,8 @@ pub mod rotate;
 pub enum Model {
     Gen1,
     Gen2,
+    /// The reMarkable Paper Pro, with a color display
+    PaperPro,
 }
 
 impl std::fmt::Display for Model {
@@ -15,6 +17,7 @@ impl std::fmt::Display for Model {
         match *self {
             Model::Gen1 => write!(f, "reMarkable 1"),
             Model::Gen2 => write!(f, "reMarkable 2"),
+            Model::PaperPro => write!(f, "reMarkable Paper Pro"),
         }
     }
 }
@@ -30,6 +33,8 @@ impl Model {
         // https://github.com/Eeems/oxide/issues/48#issuecomment-698223552
         } else if machine_name == "reMarkable 2.0" {
             Ok(Model::Gen2)
+        } else if machine_name == "reMarkable Ferrari" {
+            Ok(Model::PaperPro)
         } else {
             Err(ErrorKind::UnknownVersion(machine_name.to_owned()))
         }
@@ -43,13 +48,29 @@ impl Model {
     /// internal swtfb_client will be used. This enables the use
     /// of musl builds. But the rm2fb server must still be installed.
     ///
+    /// The Paper Pro doesn't come with a framebuffer device, one has to be provided,
+    /// e.g. by a display server, with 32 bits per pixel.
+    ///
     /// TODO: Use proper path (needs breaking change for FramebufferBase::from_path() !)
     pub fn framebuffer_path(&self) -> &'static str {
         match self {
-            Model::Gen1 => "/dev/fb0",
+            Model::Gen1 | Model::PaperPro => "/dev/fb0",
             Model::Gen2 => "/dev/shm/swtfb.01",
         }
     }
+
+    /// Pixels per inch of the display
+    pub fn dpi(&self) -> f32 {
+        match self {
+            Model::Gen1 | Model::Gen2 => 226.0,
+            Model::PaperPro => 229.0,
+        }
+    }
+
+    /// Whether the display shows colors, instead of shades of gray
+    pub fn has_color_display(&self) -> bool {
+        *self == Model::PaperPro
+    }
 }
 
 pub static CURRENT_DEVICE: Lazy<Device> = Lazy::new(Device::new);
@@ -80,6 +101,8 @@ impl std::fmt::Display for ErrorKind {
     }
 }
 
+impl std::error::Error for ErrorKind {}
+
 /// Mainly information regarding both models
 pub struct Device {
     pub model: Model,
@@ -98,6 +121,18 @@ pub struct InputDevicePlacement {
     pub invert_y: bool,
 }
 
+impl InputDevicePlacement {
+    /// The placement of the Wacom digitizer, which is the same on every model, so it
+    /// is known without detecting the device
+    pub(crate) fn wacom() -> InputDevicePlacement {
+        InputDevicePlacement {
+            rotation: InputDeviceRotation::Rot270,
+            invert_x: false,
+            invert_y: false,
+        }
+    }
+}
+
 impl Device {
     fn new() -> Self {
         let model = Model::current_model()
@@ -113,7 +148,8 @@ impl Device {
                 invert_x: false,
                 invert_y: false,
             },
-            Model::Gen2 => InputDevicePlacement {
+            // Not known to differ from the rM 2
+            Model::Gen2 | Model::PaperPro => InputDevicePlacement {
                 rotation: InputDeviceRotation::Rot180,
                 invert_x: true,
                 invert_y: false,
@@ -122,19 +158,14 @@ impl Device {
     }
 
     pub fn get_wacom_placement(&self) -> InputDevicePlacement {
-        // The Wacom digitizer on Gen1 and Gen2 is placed the same
-        InputDevicePlacement {
-            rotation: InputDeviceRotation::Rot270,
-            invert_x: false,
-            invert_y: false,
-        }
+        InputDevicePlacement::wacom()
     }
 
     /// Name of the battery as found in /sys/class/power_supply
     pub fn get_internal_battery_name(&self) -> &str {
         match self.model {
             Model::Gen1 => "bq27441-0",
-            Model::Gen2 => "max77818_battery",
+            Model::Gen2 | Model::PaperPro => "max77818_battery",
         }
     }
 
@@ -34,13 +34,13 @@ impl InputDeviceRotation {
             CoordinatePart::X(x) => match self {
                 InputDeviceRotation::Rot0 => CoordinatePart::X(x),
                 InputDeviceRotation::Rot90 => CoordinatePart::Y(x),
-                InputDeviceRotation::Rot180 => CoordinatePart::X(size.x - x),
-                InputDeviceRotation::Rot270 => CoordinatePart::Y(size.x - x),
+                InputDeviceRotation::Rot180 => CoordinatePart::X(size.x.saturating_sub(x)),
+                InputDeviceRotation::Rot270 => CoordinatePart::Y(size.x.saturating_sub(x)),
             },
             CoordinatePart::Y(y) => match self {
                 InputDeviceRotation::Rot0 => CoordinatePart::Y(y),
-                InputDeviceRotation::Rot90 => CoordinatePart::X(size.y - y),
-                InputDeviceRotation::Rot180 => CoordinatePart::Y(size.y - y),
+                InputDeviceRotation::Rot90 => CoordinatePart::X(size.y.saturating_sub(y)),
+                InputDeviceRotation::Rot180 => CoordinatePart::Y(size.y.saturating_sub(y)),
                 InputDeviceRotation::Rot270 => CoordinatePart::X(y),
             },
         }
@@ -66,6 +66,17 @@ impl InputDeviceRotation {
         unreachable!()
     }
 
+    /// Same as `rotate_part` but for a direction, like the tilt of the pen, which has no
+    /// origin to move.
+    pub fn rotate_vector(&self, v: Vector2<f32>) -> Vector2<f32> {
+        match self {
+            InputDeviceRotation::Rot0 => v,
+            InputDeviceRotation::Rot90 => Vector2 { x: -v.y, y: v.x },
+            InputDeviceRotation::Rot180 => Vector2 { x: -v.x, y: -v.y },
+            InputDeviceRotation::Rot270 => Vector2 { x: v.y, y: -v.x },
+        }
+    }
+
     /// Whether based on the original rotation, width and height should be swapped.
     pub fn should_swap_size_axes(&self) -> bool {
         match self {
@@ -104,5 +115,10 @@ mod test {
             Point2 { x: 200, y: 100 }
         );
         assert_eq!(Rot270.rotate_point(&point, &size), Point2 { x: 0, y: 200 });
+
+        // Pointing along the device's x axis, like the points above moving to x = 1
+        let along_x = Vector2 { x: 1.0, y: 0.0 };
+        assert_eq!(Rot90.rotate_vector(along_x), Vector2 { x: 0.0, y: 1.0 });
+        assert_eq!(Rot270.rotate_vector(along_x), Vector2 { x: 0.0, y: -1.0 });
     }
 }
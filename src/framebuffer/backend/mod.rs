@@ -0,0 +1,149 @@
+//! Displays other than the built in ones.
+//!
+//! `FramebufferUpdate` covers the displays this crate knows how to refresh: the mxcfb
+//! device, shared memory, a compositor or none at all. Anything else that can show
+//! what was drawn, like the rm2fb server of the reMarkable 2, the display of a device
+//! not supported yet, a screen on another machine or a window on the desktop,
+//! implements `FramebufferBackend`. `Framebuffer::with_backend` draws into memory and
+//! passes the refreshes on to it, so apps work with it like with any other
+//! framebuffer.
+
+/// The rm2fb server of the reMarkable 2
+pub mod rm2fb;
+
+use std::sync::Arc;
+
+use memmap2::{MmapOptions, MmapRaw};
+
+use crate::device::Model;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core::{self, FramebufferUpdate};
+use crate::framebuffer::mxcfb::mxcfb_update_data;
+
+/// The pixels of a framebuffer in its native format, row by row
+pub struct Pixels<'a> {
+    pub data: &'a [u8],
+    /// Bytes from the start of one row to the next
+    pub line_length: u32,
+    pub bits_per_pixel: u32,
+}
+
+impl Pixels<'_> {
+    /// The pixels of `rect` row by row, like `FramebufferIO::dump_region` without
+    /// rotation
+    pub fn region(&self, rect: mxcfb_rect) -> Vec<u8> {
+        let bytespp = (self.bits_per_pixel / 8) as usize;
+        let row_length = rect.width as usize * bytespp;
+        let mut region = Vec::with_capacity(row_length * rect.height as usize);
+        for y in rect.top..rect.top + rect.height {
+            let start = y as usize * self.line_length as usize + rect.left as usize * bytespp;
+            region.extend_from_slice(&self.data[start..start + row_length]);
+        }
+        region
+    }
+}
+
+/// Shows what is drawn on a framebuffer, see the module documentation
+pub trait FramebufferBackend: Send + Sync {
+    /// Shows `update.update_region` of `pixels`, which is in the orientation of the
+    /// panel whatever `Framebuffer::rotation` is. Returns whether the refresh was
+    /// sent.
+    fn send_update(&self, pixels: &Pixels<'_>, update: &mxcfb_update_data) -> bool;
+
+    /// Blocks until the refresh with `marker` is shown. Returns the result of its
+    /// collision test, see `FramebufferRefresh::wait_refresh_complete`.
+    fn wait_for_update(&self, _marker: u32) -> u32 {
+        0
+    }
+
+    /// The device whose display this is, if it is one
+    fn model(&self) -> Option<Model> {
+        None
+    }
+}
+
+impl core::Framebuffer {
+    /// A framebuffer of `width` by `height` pixels in the display's pixel format,
+    /// refreshed by `backend`
+    pub fn with_backend(
+        width: u32,
+        height: u32,
+        backend: impl FramebufferBackend + 'static,
+    ) -> core::Framebuffer {
+        let mut mem_map = MmapOptions::new()
+            .len((width * height * 2) as usize)
+            .map_anon()
+            .expect("Unable to allocate framebuffer");
+        mem_map.fill(0xff);
+        core::Framebuffer::with_memory(
+            MmapRaw::from(mem_map),
+            width,
+            height,
+            16,
+            FramebufferUpdate::Backend(Arc::new(backend)),
+        )
+    }
+
+    /// What was drawn, see `FramebufferBackend::send_update`
+    pub(crate) fn pixels(&self) -> Pixels<'_> {
+        Pixels {
+            data: unsafe { std::slice::from_raw_parts(self.frame.as_ptr(), self.frame.len()) },
+            line_length: self.fix_screen_info.line_length,
+            bits_per_pixel: self.var_screen_info.bits_per_pixel,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::{Point2, Vector2};
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::{FramebufferDraw, PartialRefreshMode};
+    use std::sync::Mutex;
+
+    type Shown = Arc<Mutex<Vec<(mxcfb_rect, Vec<u8>)>>>;
+
+    /// Keeps the pixels of every refresh
+    #[derive(Default)]
+    struct Remote {
+        shown: Shown,
+    }
+
+    impl FramebufferBackend for Remote {
+        fn send_update(&self, pixels: &Pixels<'_>, update: &mxcfb_update_data) -> bool {
+            let rect = update.update_region;
+            self.shown.lock().unwrap().push((rect, pixels.region(rect)));
+            true
+        }
+
+        fn model(&self) -> Option<Model> {
+            Some(Model::Gen2)
+        }
+    }
+
+    #[test]
+    fn test_backend() {
+        let remote = Remote::default();
+        let shown = remote.shown.clone();
+        let mut fb = core::Framebuffer::with_backend(30, 20, remote);
+        assert_eq!(fb.model(), Some(Model::Gen2));
+
+        fb.fill_rect(Point2 { x: 10, y: 5 }, Vector2 { x: 2, y: 1 }, color::BLACK);
+        let rect = mxcfb_rect {
+            left: 9,
+            top: 5,
+            width: 4,
+            height: 1,
+        };
+        let marker = fb.refresh(&rect, PartialRefreshMode::Wait);
+        assert_eq!(marker, 0);
+        let (white, black) = (color::WHITE.as_native(), color::BLACK.as_native());
+        assert_eq!(
+            *shown.lock().unwrap(),
+            [(rect, [white, black, black, white].concat())]
+        );
+        // Only headless framebuffers record them
+        assert!(fb.take_refreshes().is_empty());
+    }
+}
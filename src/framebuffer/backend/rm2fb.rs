@@ -0,0 +1,152 @@
+//! The display of the reMarkable 2, through the rm2fb server.
+//!
+//! The reMarkable 2 has no mxcfb device whose ioctls refresh the display, it is
+//! driven by the [rm2fb server](https://github.com/ddvk/remarkable2-framebuffer)
+//! instead. `Rm2fb` speaks its protocol natively: refreshed regions are copied into
+//! the shared memory of the server, and the refreshes are sent through its message
+//! queue, so no client shim has to be preloaded. `Framebuffer::new` uses it on the
+//! reMarkable 2, see `Framebuffer::rm2fb`.
+
+use std::path::Path;
+use std::ptr;
+
+use memmap2::MmapRaw;
+
+use crate::device::Model;
+use crate::framebuffer::backend::{FramebufferBackend, Pixels};
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::error::FramebufferError;
+use crate::framebuffer::mxcfb::mxcfb_update_data;
+use crate::framebuffer::swtfb_client::{self, SwtfbClient};
+
+/// Shows what is drawn through the rm2fb server, see the module documentation
+pub struct Rm2fb {
+    client: SwtfbClient,
+    /// Shared with the server
+    buffer: MmapRaw,
+}
+
+impl Rm2fb {
+    /// Connects to the rm2fb server whose shared memory is at `path`
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Rm2fb, FramebufferError> {
+        let client = SwtfbClient::try_new(path)?;
+        let buffer = client.open_buffer().map_err(FramebufferError::Map)?;
+        Ok(Rm2fb { client, buffer })
+    }
+
+    /// The size of the display in pixels
+    pub fn size() -> (u32, u32) {
+        (swtfb_client::WIDTH as u32, swtfb_client::HEIGHT as u32)
+    }
+
+    /// Copies what the server currently shows into `frame`, as much as fits
+    pub fn read_frame(&self, frame: &mut [u8]) {
+        let len = frame.len().min(self.buffer.len());
+        // See `copy_region` on accessing the buffer
+        unsafe {
+            ptr::copy_nonoverlapping(self.buffer.as_ptr(), frame.as_mut_ptr(), len);
+        }
+    }
+}
+
+/// Copies `rect` of `pixels` into `buffer`, which holds a frame in the format of the
+/// rm2fb server. Returns false if `pixels` are in another format or too small.
+fn copy_region(pixels: &Pixels<'_>, rect: mxcfb_rect, buffer: &MmapRaw) -> bool {
+    const BYTESPP: usize = 2;
+    if pixels.bits_per_pixel as usize != BYTESPP * 8 {
+        return false;
+    }
+    let (width, height) = Rm2fb::size();
+    let right = rect.left.saturating_add(rect.width).min(width);
+    let bottom = rect.top.saturating_add(rect.height).min(height);
+    if rect.left >= right || rect.top >= bottom {
+        return true;
+    }
+    let line_length = width as usize * BYTESPP;
+    let row_length = (right - rect.left) as usize * BYTESPP;
+    for y in rect.top as usize..bottom as usize {
+        let from = y * pixels.line_length as usize + rect.left as usize * BYTESPP;
+        let row = match pixels.data.get(from..from + row_length) {
+            Some(row) => row,
+            None => return false,
+        };
+        let to = y * line_length + rect.left as usize * BYTESPP;
+        // The server reads the buffer while it is written, so it's only ever accessed
+        // through raw pointers. The row is within the buffer, as the rect was clipped
+        // to the display.
+        unsafe {
+            ptr::copy_nonoverlapping(row.as_ptr(), buffer.as_mut_ptr().add(to), row_length);
+        }
+    }
+    true
+}
+
+impl FramebufferBackend for Rm2fb {
+    fn send_update(&self, pixels: &Pixels<'_>, update: &mxcfb_update_data) -> bool {
+        copy_region(pixels, update.update_region, &self.buffer)
+            && self.client.send_mxcfb_update(update)
+    }
+
+    fn wait_for_update(&self, _marker: u32) -> u32 {
+        self.client.wait_for_update_complete();
+        // The server doesn't report collisions
+        0
+    }
+
+    fn model(&self) -> Option<Model> {
+        Some(Model::Gen2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use memmap2::MmapOptions;
+
+    #[test]
+    fn test_copy_region() {
+        let (width, height) = Rm2fb::size();
+        let buffer = MmapRaw::from(
+            MmapOptions::new()
+                .len(swtfb_client::BUF_SIZE as usize)
+                .map_anon()
+                .unwrap(),
+        );
+        let data = vec![7; (width * height * 2) as usize];
+        let pixels = Pixels {
+            data: &data,
+            line_length: width * 2,
+            bits_per_pixel: 16,
+        };
+        let shown = |offset: usize| unsafe { *buffer.as_ptr().add(offset) };
+
+        // Clipped to the display
+        let rect = mxcfb_rect {
+            left: width - 2,
+            top: height - 1,
+            width: 10,
+            height: u32::MAX,
+        };
+        assert!(copy_region(&pixels, rect, &buffer));
+        let last = (width * height * 2) as usize;
+        assert_eq!(shown(last - 1), 7);
+        assert_eq!(shown(last - 4), 7);
+        assert_eq!(shown(last - 5), 0);
+        assert_eq!(shown(last - width as usize * 2 - 1), 0);
+
+        // Too few pixels
+        let rect = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 1,
+            height: 1,
+        };
+        let pixels = Pixels {
+            data: &[],
+            line_length: width * 2,
+            bits_per_pixel: 16,
+        };
+        assert!(!copy_region(&pixels, rect, &buffer));
+        assert_eq!(shown(0), 0);
+    }
+}
@@ -0,0 +1,321 @@
+//! Rendering pen strokes with brushes.
+//!
+//! A `Brush` stamps its tip along a stroke, shaped by the pressure and tilt of the pen
+//! at every point. `Framebuffer::draw_stroke` collects the stamps first and then
+//! blends each pixel once, with the most any stamp covered it, so the stamps don't
+//! pile up into dark spots where they overlap.
+
+use std::collections::HashMap;
+
+use crate::framebuffer::cgmath::{InnerSpace, Point2, Vector2};
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core;
+use crate::framebuffer::FramebufferIO;
+use crate::stroke::StrokePoint;
+
+/// Largest tilt reported by the digitizer in either direction
+pub const MAX_TILT: f32 = 9000.0;
+
+/// The tip of a pen
+pub trait Brush {
+    /// Width of the tip at full pressure
+    fn size(&self) -> f32;
+    fn color(&self) -> color;
+    /// Distance between stamps along the stroke, relative to `size`
+    fn spacing(&self) -> f32 {
+        0.1
+    }
+    /// Calls `plot` with how much of every pixel, from 0.0 to 1.0, the tip covers at
+    /// `point`
+    fn stamp(&self, point: &StrokePoint, plot: &mut dyn FnMut(Point2<i32>, f32));
+}
+
+/// Calls `plot` with the pixels around `center` within `reach` that `shape` covers,
+/// given the pixel and its center relative to `center`
+fn around(
+    center: Point2<f32>,
+    reach: f32,
+    shape: &dyn Fn(Point2<i32>, Vector2<f32>) -> f32,
+    plot: &mut dyn FnMut(Point2<i32>, f32),
+) {
+    let reach = reach + 1.0;
+    for y in (center.y - reach).floor() as i32..=(center.y + reach).ceil() as i32 {
+        for x in (center.x - reach).floor() as i32..=(center.x + reach).ceil() as i32 {
+            let pixel = Point2 { x, y };
+            let offset = Vector2 {
+                x: x as f32 + 0.5 - center.x,
+                y: y as f32 + 0.5 - center.y,
+            };
+            let covered = shape(pixel, offset);
+            if covered > 0.0 {
+                plot(pixel, covered.min(1.0));
+            }
+        }
+    }
+}
+
+/// A round tip growing with the pressure, like a ballpoint or fineliner
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RoundBrush {
+    pub size: f32,
+    pub color: color,
+}
+
+impl Brush for RoundBrush {
+    fn size(&self) -> f32 {
+        self.size
+    }
+
+    fn color(&self) -> color {
+        self.color
+    }
+
+    fn stamp(&self, point: &StrokePoint, plot: &mut dyn FnMut(Point2<i32>, f32)) {
+        let radius = (self.size * point.pressure.clamp(0.0, 1.0) / 2.0).max(0.5);
+        around(
+            point.position,
+            radius,
+            &|_, offset| radius + 0.5 - offset.magnitude(),
+            plot,
+        );
+    }
+}
+
+/// A flat tip held at a fixed angle, like a calligraphy marker, drawing thin lines
+/// along and wide lines across it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChiselBrush {
+    /// Length of the tip at full pressure
+    pub size: f32,
+    pub thickness: f32,
+    /// Of the tip, clockwise from the x axis in radians
+    pub angle: f32,
+    pub color: color,
+}
+
+impl Brush for ChiselBrush {
+    fn size(&self) -> f32 {
+        self.size
+    }
+
+    fn color(&self) -> color {
+        self.color
+    }
+
+    fn spacing(&self) -> f32 {
+        // Along the tip a stamp is only as wide as its thickness
+        (self.thickness / self.size).min(1.0) / 2.0
+    }
+
+    fn stamp(&self, point: &StrokePoint, plot: &mut dyn FnMut(Point2<i32>, f32)) {
+        let half_length = (self.size * point.pressure.clamp(0.0, 1.0) / 2.0).max(0.5);
+        let half_thickness = (self.thickness / 2.0).max(0.5);
+        let (sin, cos) = self.angle.sin_cos();
+        around(
+            point.position,
+            half_length.max(half_thickness),
+            &|_, offset| {
+                let along = offset.x * cos + offset.y * sin;
+                let across = -offset.x * sin + offset.y * cos;
+                (half_length + 0.5 - along.abs()).min(half_thickness + 0.5 - across.abs())
+            },
+            plot,
+        );
+    }
+}
+
+/// A round tip marking the paper through a texture, like a pencil on grainy paper.
+/// The texture is repeated over the whole display, so the grain stays in place
+/// along the stroke. Pressing harder covers more of the grain, tilting the pen
+/// widens the tip for shading.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TexturedBrush {
+    pub size: f32,
+    pub color: color,
+    /// How much of every pixel of the texture can be covered, from 0 to 255, in rows
+    texture: Vec<u8>,
+    texture_width: usize,
+}
+
+impl TexturedBrush {
+    /// With a texture `texture_width` pixels wide, see `texture`
+    pub fn new(size: f32, color: color, texture: Vec<u8>, texture_width: usize) -> TexturedBrush {
+        assert!(
+            texture_width > 0 && !texture.is_empty() && texture.len().is_multiple_of(texture_width),
+            "The texture must be a whole number of rows of texture_width"
+        );
+        TexturedBrush {
+            size,
+            color,
+            texture,
+            texture_width,
+        }
+    }
+
+    /// A graphite pencil on paper with a random grain
+    pub fn pencil(size: f32, color: color) -> TexturedBrush {
+        const SIDE: usize = 64;
+        // xorshift, the same grain every time
+        let mut state: u32 = 0x9e37_79b9;
+        let texture = (0..SIDE * SIDE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                96 + (state % 160) as u8
+            })
+            .collect();
+        TexturedBrush::new(size, color, texture, SIDE)
+    }
+
+    fn grain(&self, pixel: Point2<i32>) -> f32 {
+        let height = self.texture.len() / self.texture_width;
+        let x = pixel.x.rem_euclid(self.texture_width as i32) as usize;
+        let y = pixel.y.rem_euclid(height as i32) as usize;
+        f32::from(self.texture[y * self.texture_width + x]) / 255.0
+    }
+}
+
+impl Brush for TexturedBrush {
+    fn size(&self) -> f32 {
+        self.size
+    }
+
+    fn color(&self) -> color {
+        self.color
+    }
+
+    fn stamp(&self, point: &StrokePoint, plot: &mut dyn FnMut(Point2<i32>, f32)) {
+        let pressure = point.pressure.clamp(0.0, 1.0);
+        let tilt = (point.tilt.magnitude() / MAX_TILT).min(1.0);
+        let radius = (self.size * (0.5 + pressure / 2.0) * (1.0 + tilt) / 2.0).max(0.5);
+        // Light touches only catch the top of the grain
+        let depth = 0.25 + pressure * 0.75;
+        around(
+            point.position,
+            radius,
+            &|pixel, offset| {
+                let edge = (radius + 0.5 - offset.magnitude()).clamp(0.0, 1.0);
+                edge * (self.grain(pixel) + depth - 1.0).clamp(0.0, 1.0) / depth
+            },
+            plot,
+        );
+    }
+}
+
+impl core::Framebuffer {
+    /// Draws the stroke through `points` with `brush`, without refreshing. Returns the
+    /// area to refresh.
+    pub fn draw_stroke(&mut self, points: &[StrokePoint], brush: &dyn Brush) -> mxcfb_rect {
+        let mut coverage: HashMap<(i32, i32), f32> = HashMap::new();
+        let mut plot = |p: Point2<i32>, covered: f32| {
+            let pixel = coverage.entry((p.x, p.y)).or_insert(0.0);
+            *pixel = pixel.max(covered);
+        };
+        let step = (brush.size() * brush.spacing()).max(0.5);
+        for (i, point) in points.iter().enumerate() {
+            let next = match points.get(i + 1) {
+                Some(next) => next,
+                None => {
+                    brush.stamp(point, &mut plot);
+                    break;
+                }
+            };
+            // Stamps in between, with the pressure and tilt changing evenly
+            let stamps = ((next.position - point.position).magnitude() / step)
+                .ceil()
+                .max(1.0) as usize;
+            for s in 0..stamps {
+                let t = s as f32 / stamps as f32;
+                let between = StrokePoint {
+                    position: point.position + (next.position - point.position) * t,
+                    pressure: point.pressure + (next.pressure - point.pressure) * t,
+                    tilt: point.tilt + (next.tilt - point.tilt) * t,
+                };
+                brush.stamp(&between, &mut plot);
+            }
+        }
+
+        let (width, height) = (self.var_screen_info.xres, self.var_screen_info.yres);
+        let rgb = brush.color().to_rgb8();
+        let mut drawn = mxcfb_rect::invalid();
+        for ((x, y), covered) in coverage {
+            if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+                continue;
+            }
+            let pos = Point2 {
+                x: x as u32,
+                y: y as u32,
+            };
+            let under = self.read_pixel(pos).to_rgb8();
+            let blend = |c: usize| {
+                (f32::from(under[c]) * (1.0 - covered) + f32::from(rgb[c]) * covered).round() as u8
+            };
+            self.write_pixel(Point2 { x, y }, color::RGB(blend(0), blend(1), blend(2)));
+            drawn = drawn.merge_rect(&mxcfb_rect {
+                left: pos.x,
+                top: pos.y,
+                width: 1,
+                height: 1,
+            });
+        }
+        drawn
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn darkness(fb: &core::Framebuffer, x: u32, y: u32) -> u8 {
+        255 - fb.read_pixel(Point2 { x, y }).to_rgb8()[1]
+    }
+
+    #[test]
+    fn test_brushes() {
+        let points = [
+            StrokePoint {
+                pressure: 0.2,
+                ..StrokePoint::new(20.0, 20.5)
+            },
+            StrokePoint::new(100.0, 20.5),
+        ];
+
+        let mut fb = core::Framebuffer::headless(200, 200);
+        let round = RoundBrush {
+            size: 10.0,
+            color: color::BLACK,
+        };
+        let rect = fb.draw_stroke(&points, &round);
+        assert!(rect.top <= 16 && rect.top + rect.height >= 25, "{:?}", rect);
+        // Wider with more pressure, evenly dark along the middle
+        assert_eq!(darkness(&fb, 24, 17), 0);
+        assert!(darkness(&fb, 96, 17) > 200);
+        assert!((30..90).all(|x| darkness(&fb, x, 20) > 250));
+
+        let mut fb = core::Framebuffer::headless(200, 200);
+        let chisel = ChiselBrush {
+            size: 20.0,
+            thickness: 2.0,
+            angle: std::f32::consts::FRAC_PI_2,
+            color: color::BLACK,
+        };
+        let across = [StrokePoint::new(20.0, 50.5), StrokePoint::new(100.0, 50.5)];
+        let along = [
+            StrokePoint::new(150.5, 20.0),
+            StrokePoint::new(150.5, 100.0),
+        ];
+        fb.draw_stroke(&across, &chisel);
+        fb.draw_stroke(&along, &chisel);
+        assert!(darkness(&fb, 60, 42) > 200 && darkness(&fb, 60, 58) > 200);
+        assert_eq!(darkness(&fb, 153, 60), 0);
+
+        let mut fb = core::Framebuffer::headless(200, 200);
+        let pencil = TexturedBrush::pencil(8.0, color::BLACK);
+        fb.draw_stroke(&points, &pencil);
+        let shades: Vec<u8> = (60..100).map(|x| darkness(&fb, x, 20)).collect();
+        // Grainy, not solid
+        assert!(shades.iter().any(|s| *s > 100), "{:?}", shades);
+        assert!(shades.iter().any(|s| *s < 200), "{:?}", shades);
+    }
+}
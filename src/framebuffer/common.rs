@@ -47,6 +47,7 @@ pub enum color {
     #[default]
     WHITE,
     NATIVE_COMPONENTS(u8, u8),
+    /// Shown as a shade of gray on the grayscale displays of the rM 1 and 2
     RGB(u8, u8, u8),
     GRAY(u8),
 }
@@ -88,6 +89,20 @@ impl color {
         }
     }
 
+    /// As the 32 bit pixels of color displays, blue first
+    pub fn to_xrgb8888(self) -> [u8; 4] {
+        let [r8, g8, b8] = match self {
+            color::RGB(r8, g8, b8) => [r8, g8, b8],
+            color::GRAY(level) => [255 - level; 3],
+            c => c.to_rgb8(),
+        };
+        [b8, g8, r8, 0xff]
+    }
+
+    pub fn from_xrgb8888(c: [u8; 4]) -> color {
+        color::RGB(c[2], c[1], c[0])
+    }
+
     #[inline]
     fn rgb_to_native(r8: u8, g8: u8, b8: u8) -> [u8; 2] {
         // Split out to avoid making as_native appear recursive
@@ -108,6 +123,18 @@ impl color {
     }
 }
 
+#[test]
+fn xrgb8888_conversions() {
+    assert_eq!(color::RGB(10, 20, 30).to_xrgb8888(), [30, 20, 10, 0xff]);
+    assert_eq!(color::GRAY(0x80).to_xrgb8888(), [0x7f, 0x7f, 0x7f, 0xff]);
+    assert_eq!(color::WHITE.to_xrgb8888(), [0xff; 4]);
+    assert_eq!(color::BLACK.to_xrgb8888(), [0, 0, 0, 0xff]);
+    assert_eq!(
+        color::from_xrgb8888([30, 20, 10, 0xff]),
+        color::RGB(10, 20, 30)
+    );
+}
+
 #[test]
 fn rgb565_conversions() {
     // Ensure that min and max values are transformed faithfully
@@ -265,6 +292,23 @@ impl mxcfb_rect {
         }
     }
 
+    /// The overlap of both, if any
+    pub fn intersection(&self, rect: &mxcfb_rect) -> Option<mxcfb_rect> {
+        let left = self.left.max(rect.left);
+        let top = self.top.max(rect.top);
+        let right = (self.left + self.width).min(rect.left + rect.width);
+        let bottom = (self.top + self.height).min(rect.top + rect.height);
+        if right <= left || bottom <= top {
+            return None;
+        }
+        Some(mxcfb_rect {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
     pub fn expand(&self, margin: u32) -> mxcfb_rect {
         mxcfb_rect {
             left: if self.left > margin {
@@ -352,6 +396,18 @@ pub enum dither_mode {
     EPDC_FLAG_EXP8 = 0x7ed3_d2c0,
 }
 
+/// How images are brought down to the 16 grays of the display when drawn
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum dither_algorithm {
+    /// The closest gray, leaving bands in gradients
+    NONE,
+    /// Spreading the difference to the closest gray over the next pixels, best for photos
+    #[default]
+    FLOYD_STEINBERG,
+    /// A fixed pattern per gray, fast and without the noise of `FLOYD_STEINBERG`
+    ORDERED,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum waveform_mode {
     /// (Recommended) Screen goes to white
@@ -403,6 +459,30 @@ pub enum waveform_mode {
     WAVEFORM_MODE_AUTO = 257,
 }
 
+impl waveform_mode {
+    /// The mode with the value `raw`, as in `mxcfb_update_data::waveform_mode`
+    pub fn from_raw(raw: u32) -> Option<waveform_mode> {
+        use waveform_mode::*;
+        [
+            WAVEFORM_MODE_INIT,
+            WAVEFORM_MODE_GLR16,
+            WAVEFORM_MODE_GLD16,
+            WAVEFORM_MODE_DU,
+            WAVEFORM_MODE_GC16,
+            WAVEFORM_MODE_GC16_FAST,
+            WAVEFORM_MODE_GL16_FAST,
+            WAVEFORM_MODE_DU4,
+            WAVEFORM_MODE_REAGL,
+            WAVEFORM_MODE_REAGLD,
+            WAVEFORM_MODE_GL4,
+            WAVEFORM_MODE_GL16_INV,
+            WAVEFORM_MODE_AUTO,
+        ]
+        .into_iter()
+        .find(|mode| *mode as u32 == raw)
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum display_temp {
     /// Seems to have the best draw latency. Perhaps the rule of thumb here is the lower the faster.
@@ -0,0 +1,212 @@
+//! Awaiting refreshes instead of blocking on them.
+//!
+//! Waiting for a refresh with `PartialRefreshMode::Wait` blocks the calling thread
+//! until the display controller is done, which stalls every other task of an async
+//! runtime on it. `Framebuffer::partial_refresh_async` sends the refresh right away
+//! and returns a `RefreshCompletion` to await instead. The waits happen one after
+//! another on a thread of their own, so this works with any runtime.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core::{self, FramebufferUpdate};
+use crate::framebuffer::{FramebufferRefresh, PartialRefreshMode, RefreshProfile};
+
+type Wait = Box<dyn FnOnce() + Send>;
+
+/// Runs the waits of all completions, started by the first
+static WAITER: Mutex<Option<mpsc::Sender<Wait>>> = Mutex::new(None);
+
+/// Runs `wait` on the waiter thread
+fn start(wait: Wait) {
+    let mut waiter = WAITER.lock().unwrap();
+    let mut wait = wait;
+    loop {
+        let waits = waiter.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel::<Wait>();
+            std::thread::spawn(move || rx.into_iter().for_each(|wait| wait()));
+            tx
+        });
+        match waits.send(wait) {
+            Ok(()) => return,
+            // The thread is gone after a wait panicked, so another one is started
+            Err(mpsc::SendError(unsent)) => {
+                wait = unsent;
+                *waiter = None;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    completed: bool,
+    waker: Option<Waker>,
+}
+
+/// Completes once the refresh with `marker` is shown on the display
+pub struct RefreshCompletion {
+    marker: u32,
+    state: Arc<Mutex<State>>,
+}
+
+impl RefreshCompletion {
+    /// Already completed, for refreshes nothing waits on
+    fn completed(marker: u32) -> RefreshCompletion {
+        RefreshCompletion {
+            marker,
+            state: Arc::new(Mutex::new(State {
+                completed: true,
+                waker: None,
+            })),
+        }
+    }
+
+    /// Completes when `wait` returns, which is called on the waiter thread
+    fn waiting(marker: u32, wait: impl FnOnce() + Send + 'static) -> RefreshCompletion {
+        let state = Arc::new(Mutex::new(State::default()));
+        let waited = state.clone();
+        start(Box::new(move || {
+            wait();
+            let mut state = waited.lock().unwrap();
+            state.completed = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }));
+        RefreshCompletion { marker, state }
+    }
+
+    pub fn marker(&self) -> u32 {
+        self.marker
+    }
+}
+
+impl Future for RefreshCompletion {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.completed {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl core::Framebuffer {
+    /// Refreshes `region` with the settings of `profile` like `partial_refresh_with`
+    /// in `PartialRefreshMode::Async`, returning a future that completes with the
+    /// refresh. The refresh watchdog doesn't apply to it.
+    pub fn partial_refresh_async(
+        &self,
+        region: &mxcfb_rect,
+        profile: &RefreshProfile,
+        force_full_refresh: bool,
+    ) -> RefreshCompletion {
+        let marker = self.partial_refresh_with(
+            region,
+            PartialRefreshMode::Async,
+            profile,
+            force_full_refresh,
+        );
+        match &self.framebuffer_update {
+            FramebufferUpdate::Ioctl(device) => match device.try_clone() {
+                Ok(device) => RefreshCompletion::waiting(marker, move || {
+                    crate::framebuffer::refresh::wait_for_update(&device, marker);
+                }),
+                Err(_) => {
+                    // Blocking rather than claiming a refresh is done that isn't
+                    self.wait_refresh_complete(marker);
+                    RefreshCompletion::completed(marker)
+                }
+            },
+            FramebufferUpdate::Backend(backend) => {
+                let backend = backend.clone();
+                RefreshCompletion::waiting(marker, move || {
+                    backend.wait_for_update(marker);
+                })
+            }
+            // Nothing to wait for, see `wait_refresh_complete`
+            _ => RefreshCompletion::completed(marker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::task::Wake;
+    use std::thread::ThreadId;
+
+    /// Signals every wake
+    struct Notify(Mutex<mpsc::Sender<()>>);
+
+    impl Wake for Notify {
+        fn wake(self: Arc<Self>) {
+            let _ = self.0.lock().unwrap().send(());
+        }
+    }
+
+    fn poll(completion: &mut RefreshCompletion, waker: &Waker) -> Poll<()> {
+        Pin::new(completion).poll(&mut Context::from_waker(waker))
+    }
+
+    /// Completes with `marker` once `done` is signalled, recording the thread it
+    /// waited on
+    fn waiting(
+        marker: u32,
+        done: mpsc::Receiver<()>,
+        threads: &Arc<Mutex<Vec<ThreadId>>>,
+    ) -> RefreshCompletion {
+        let threads = threads.clone();
+        RefreshCompletion::waiting(marker, move || {
+            threads.lock().unwrap().push(std::thread::current().id());
+            let _ = done.recv();
+        })
+    }
+
+    #[test]
+    fn test_refresh_completion() {
+        let (notify, woken) = mpsc::channel();
+        let waker = Waker::from(Arc::new(Notify(Mutex::new(notify))));
+
+        let fb = core::Framebuffer::headless(100, 100);
+        let region = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 10,
+            height: 10,
+        };
+        let mut completion = fb.partial_refresh_async(&region, &RefreshProfile::UI, false);
+        assert_eq!(poll(&mut completion, &waker), Poll::Ready(()));
+        assert_eq!(fb.take_refreshes()[0].update_marker, completion.marker());
+
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let (done, signal) = mpsc::channel();
+        let mut first = waiting(7, signal, &threads);
+        let (done_next, signal) = mpsc::channel();
+        let mut next = waiting(8, signal, &threads);
+        assert_eq!(poll(&mut first, &waker), Poll::Pending);
+        assert_eq!(poll(&mut next, &waker), Poll::Pending);
+
+        done.send(()).unwrap();
+        woken.recv().unwrap();
+        assert_eq!(poll(&mut first, &waker), Poll::Ready(()));
+        assert_eq!(poll(&mut next, &waker), Poll::Pending);
+
+        done_next.send(()).unwrap();
+        woken.recv().unwrap();
+        assert_eq!(poll(&mut next, &waker), Poll::Ready(()));
+
+        // Both waited on the same thread
+        let threads = threads.lock().unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0], threads[1]);
+    }
+}
@@ -1,25 +1,52 @@
 use libc::ioctl;
+use log::warn;
 use memmap2::{MmapOptions, MmapRaw};
 
 use std::fs::{File, OpenOptions};
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, Mutex};
 
-use crate::device;
 use crate::device::Model;
 use crate::framebuffer;
+use crate::framebuffer::backend::rm2fb::Rm2fb;
+use crate::framebuffer::backend::FramebufferBackend;
 use crate::framebuffer::common::{
-    FBIOGET_FSCREENINFO, FBIOGET_VSCREENINFO, FBIOPUT_VSCREENINFO, MXCFB_DISABLE_EPDC_ACCESS,
-    MXCFB_ENABLE_EPDC_ACCESS, MXCFB_SET_AUTO_UPDATE_MODE, MXCFB_SET_UPDATE_SCHEME,
+    mxcfb_rect, FBIOGET_FSCREENINFO, FBIOGET_VSCREENINFO, FBIOPUT_VSCREENINFO,
+    MXCFB_DISABLE_EPDC_ACCESS, MXCFB_ENABLE_EPDC_ACCESS, MXCFB_SET_AUTO_UPDATE_MODE,
+    MXCFB_SET_UPDATE_SCHEME,
 };
+use crate::framebuffer::error::FramebufferError;
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::framebuffer::fonts::{FontCollection, GlyphCache, DEFAULT_FONTS};
+use crate::framebuffer::ghosting::GhostingPolicy;
+use crate::framebuffer::mxcfb::mxcfb_update_data;
+use crate::framebuffer::rotation::ScreenRotation;
 use crate::framebuffer::screeninfo::{FixScreeninfo, VarScreeninfo};
-use crate::framebuffer::swtfb_client::SwtfbClient;
-use crate::framebuffer::FramebufferBase;
+use crate::framebuffer::shm::ShmClient;
+use crate::framebuffer::watchdog::RefreshWatchdog;
+use crate::framebuffer::{
+    BlendMode, FramebufferBase, FramebufferRefresh, PartialRefreshMode, RefreshHint, RefreshProfile,
+};
+
+/// Whether the rm2fb client shim, which implements the framebuffer ioctls on the
+/// reMarkable 2, is preloaded into this process
+fn rm2fb_shim_loaded() -> bool {
+    std::env::var("LD_PRELOAD").is_ok_and(|preload| preload.contains("rm2fb"))
+}
 
 pub enum FramebufferUpdate {
     Ioctl(File),
-    Swtfb(SwtfbClient),
+    /// A buffer in shared memory, see `framebuffer::shm`
+    Shm(ShmClient),
+    /// No display attached. Refreshes complete immediately and are only recorded.
+    Headless(Mutex<Vec<mxcfb_update_data>>),
+    /// A surface of a compositor, which is sent the refreshed regions
+    #[cfg(feature = "compositor")]
+    Compositor(crate::compositor::Connection),
+    /// Any other display, see `framebuffer::backend`
+    Backend(Arc<dyn FramebufferBackend>),
 }
 
 /// Framebuffer struct containing the state (latest update marker etc.)
@@ -33,6 +60,18 @@ pub struct Framebuffer {
     pub var_screen_info: VarScreeninfo,
     pub fix_screen_info: FixScreeninfo,
     pub framebuffer_update: FramebufferUpdate,
+    pub(crate) watchdog: Option<RefreshWatchdog>,
+    pub(crate) ghosting: Option<GhostingPolicy>,
+    refresh_profile: RefreshProfile,
+    /// Drawing is clipped to the last one, see `push_clip`
+    clip: Vec<mxcfb_rect>,
+    pub(crate) blend_mode: BlendMode,
+    rotation: ScreenRotation,
+    /// The builtin font if not set
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fonts: Option<FontCollection>,
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub(crate) glyph_cache: GlyphCache,
 }
 
 unsafe impl Send for Framebuffer {}
@@ -46,15 +85,22 @@ impl Default for Framebuffer {
 
 impl Framebuffer {
     /// Create a new framebuffer instance, autodetecting the correct update method.
+    ///
+    /// Panics if the framebuffer can't be set up, see `try_new` to handle that instead.
     pub fn new() -> Framebuffer {
-        let device = &*device::CURRENT_DEVICE;
-        match device.model {
-            Model::Gen1 => Framebuffer::device(device.get_framebuffer_path()),
+        Framebuffer::try_new().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `new`, returning an error when the framebuffer can't be set up
+    pub fn try_new() -> Result<Framebuffer, FramebufferError> {
+        let model = Model::current_model()?;
+        match model {
+            Model::Gen1 | Model::PaperPro => Framebuffer::try_device(model.framebuffer_path()),
             Model::Gen2 => {
                 // Auto-select old method still if env LIBREMARKABLE_FB_DISFAVOR_INTERNAL_RM2FB is set affirmatively
                 match std::env::var("LIBREMARKABLE_FB_DISFAVOR_INTERNAL_RM2FB").as_deref() {
-                    Ok("1") => Framebuffer::device(device.get_framebuffer_path()),
-                    _ => Framebuffer::rm2fb(device.get_framebuffer_path()),
+                    Ok("1") => Framebuffer::try_device(model.framebuffer_path()),
+                    _ => Framebuffer::try_rm2fb(model.framebuffer_path()),
                 }
             }
         }
@@ -67,21 +113,297 @@ impl Framebuffer {
     /// shim on RM2. `new` is generally preferred, though existing apps may
     /// wish to use this method to avoid some risk of changing behaviour.
     pub fn device(path: impl AsRef<Path>) -> Framebuffer {
+        Framebuffer::try_device(path).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `device`, returning an error when the device can't be set up
+    pub fn try_device(path: impl AsRef<Path>) -> Result<Framebuffer, FramebufferError> {
+        let model = Model::current_model().ok();
+        if model == Some(Model::Gen2) && !rm2fb_shim_loaded() {
+            warn!(
+                "The ioctls of {} do nothing on the reMarkable 2 without the rm2fb client \
+                 shim, consider `Framebuffer::new` to use the builtin rm2fb client",
+                path.as_ref().display()
+            );
+        }
         let device = OpenOptions::new()
             .read(true)
             .write(true)
-            .open(path)
-            .unwrap();
-        Framebuffer::build(FramebufferUpdate::Ioctl(device))
+            .open(path.as_ref())
+            .map_err(|source| FramebufferError::Open {
+                path: path.as_ref().to_path_buf(),
+                source,
+            })?;
+        Framebuffer::build(device, model)
     }
 
     /// Uses the [rm2fb interface](https://github.com/ddvk/remarkable2-framebuffer)
-    /// ufor framebuffer metadata.
+    /// for framebuffer metadata.
+    ///
+    /// The shared memory and message queue of the rm2fb server are used directly, see
+    /// `framebuffer::backend::rm2fb`, so only the server has to be installed and no
+    /// client shim has to be preloaded. `new` does this on the reMarkable 2.
     ///
     /// This will not work at all on RM1; consider using `new` to autodetect
     /// the right interface for the current hardware.
     pub fn rm2fb(path: impl AsRef<Path>) -> Framebuffer {
-        Framebuffer::build(FramebufferUpdate::Swtfb(SwtfbClient::new(path)))
+        Framebuffer::try_rm2fb(path).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `rm2fb`, returning an error when the rm2fb server can't be used
+    pub fn try_rm2fb(path: impl AsRef<Path>) -> Result<Framebuffer, FramebufferError> {
+        let backend = Rm2fb::try_new(path)?;
+        let (width, height) = Rm2fb::size();
+        let mut shown = vec![0; (width * height * 2) as usize];
+        backend.read_frame(&mut shown);
+        let fb = Framebuffer::with_backend(width, height, backend);
+        // Drawing starts out on what is on the display, like on the other devices
+        unsafe {
+            std::ptr::copy_nonoverlapping(shown.as_ptr(), fb.frame.as_mut_ptr(), shown.len());
+        }
+        Ok(fb)
+    }
+
+    /// Draws into a buffer shared with the process owning the display, which is
+    /// sent the refreshed regions, see `framebuffer::shm`. Panics if the server at
+    /// `socket` can't be reached, see `try_shm` to handle that instead.
+    pub fn shm(socket: impl AsRef<Path>) -> Framebuffer {
+        Framebuffer::try_shm(socket).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `shm`, returning an error when the server can't be reached
+    pub fn try_shm(socket: impl AsRef<Path>) -> Result<Framebuffer, FramebufferError> {
+        let (client, width, height, frame) =
+            ShmClient::connect(socket).map_err(FramebufferError::Shm)?;
+        Ok(Framebuffer::with_memory(
+            frame,
+            width,
+            height,
+            16,
+            FramebufferUpdate::Shm(client),
+        ))
+    }
+
+    /// Creates a framebuffer backed by plain memory instead of a device, with the
+    /// same pixel format as the real one. Drawing works as usual and refreshes do
+    /// nothing, which makes it usable off-device, e.g. in tests.
+    pub fn headless(width: u32, height: u32) -> Framebuffer {
+        let mut mem_map = MmapOptions::new()
+            .len((width * height * 2) as usize)
+            .map_anon()
+            .expect("Unable to allocate headless framebuffer");
+        // Start out white like a cleared display
+        mem_map.fill(0xff);
+        Framebuffer::with_memory(
+            MmapRaw::from(mem_map),
+            width,
+            height,
+            16,
+            FramebufferUpdate::Headless(Mutex::new(Vec::new())),
+        )
+    }
+
+    /// Like `headless`, in the 32 bit color format of the Paper Pro, see
+    /// `color::to_xrgb8888`
+    pub fn headless_color(width: u32, height: u32) -> Framebuffer {
+        let mut mem_map = MmapOptions::new()
+            .len((width * height * 4) as usize)
+            .map_anon()
+            .expect("Unable to allocate headless framebuffer");
+        mem_map.fill(0xff);
+        Framebuffer::with_memory(
+            MmapRaw::from(mem_map),
+            width,
+            height,
+            32,
+            FramebufferUpdate::Headless(Mutex::new(Vec::new())),
+        )
+    }
+
+    /// A framebuffer of `width` by `height` pixels stored in `frame`, in the display's
+    /// pixel format for a `bits_per_pixel` of 16, or that of color displays for 32
+    pub(crate) fn with_memory(
+        frame: MmapRaw,
+        width: u32,
+        height: u32,
+        bits_per_pixel: u32,
+        framebuffer_update: FramebufferUpdate,
+    ) -> Framebuffer {
+        let bytespp = bits_per_pixel / 8;
+        let var_screen_info = VarScreeninfo {
+            xres: width,
+            yres: height,
+            xres_virtual: width,
+            yres_virtual: height,
+            // Physical size, unknown like on the device
+            width: 0xffff_ffff,
+            height: 0xffff_ffff,
+            bits_per_pixel,
+            ..Default::default()
+        };
+        let fix_screen_info = FixScreeninfo {
+            line_length: width * bytespp,
+            smem_len: width * height * bytespp,
+            ..Default::default()
+        };
+        Framebuffer {
+            marker: AtomicU32::new(1),
+            frame,
+            var_screen_info,
+            fix_screen_info,
+            framebuffer_update,
+            watchdog: None,
+            ghosting: None,
+            refresh_profile: RefreshProfile::default(),
+            clip: Vec::new(),
+            blend_mode: BlendMode::Opaque,
+            rotation: ScreenRotation::Rot0,
+            #[cfg(feature = "framebuffer-text-drawing")]
+            fonts: None,
+            #[cfg(feature = "framebuffer-text-drawing")]
+            glyph_cache: GlyphCache::default(),
+        }
+    }
+
+    /// The profile `refresh` uses, `RefreshProfile::UI` unless changed
+    pub fn refresh_profile(&self) -> RefreshProfile {
+        self.refresh_profile
+    }
+
+    pub fn set_refresh_profile(&mut self, profile: RefreshProfile) {
+        self.refresh_profile = profile;
+    }
+
+    /// Refreshes `region` with the default profile, see `set_refresh_profile`
+    pub fn refresh(&self, region: &mxcfb_rect, mode: PartialRefreshMode) -> u32 {
+        self.partial_refresh_with(region, mode, &self.refresh_profile, false)
+    }
+
+    /// Refreshes `region` with the settings for `hint` on this display, see
+    /// `RefreshHint`
+    pub fn refresh_auto(
+        &self,
+        region: &mxcfb_rect,
+        hint: RefreshHint,
+        mode: PartialRefreshMode,
+    ) -> u32 {
+        let profile = hint.profile(self.model().unwrap_or(Model::Gen1));
+        self.partial_refresh_with(region, mode, &profile, hint.is_full())
+    }
+
+    /// The device whose display this is, `None` when there is none or it's unknown
+    pub fn model(&self) -> Option<Model> {
+        match &self.framebuffer_update {
+            FramebufferUpdate::Ioctl(_) => Model::current_model().ok(),
+            FramebufferUpdate::Backend(backend) => backend.model(),
+            _ => None,
+        }
+    }
+
+    /// Whether colors are drawn as they are, rather than reduced to the RGB565 format
+    /// of the grayscale displays, which show them as shades of gray
+    pub fn is_color(&self) -> bool {
+        self.var_screen_info.bits_per_pixel == 32
+    }
+
+    /// Clips all drawing to `rect`, within the current clip rectangle, until `pop_clip`
+    pub fn push_clip(&mut self, rect: mxcfb_rect) {
+        let rect = match self.clip.last() {
+            // Nothing is drawn if they don't overlap
+            Some(clip) => clip.intersection(&rect).unwrap_or_default(),
+            None => rect,
+        };
+        self.clip.push(rect);
+    }
+
+    /// Goes back to the clip rectangle before the last `push_clip`, returning the one
+    /// removed
+    pub fn pop_clip(&mut self) -> Option<mxcfb_rect> {
+        self.clip.pop()
+    }
+
+    /// The rectangle drawing is clipped to, if any
+    pub fn clip(&self) -> Option<mxcfb_rect> {
+        self.clip.last().copied()
+    }
+
+    /// Whether `pos` is outside the clip rectangle, if there is one
+    pub(crate) fn is_clipped(&self, pos: framebuffer::cgmath::Point2<i32>) -> bool {
+        match self.clip.last() {
+            Some(clip) => {
+                pos.x < clip.left as i32
+                    || pos.y < clip.top as i32
+                    || pos.x >= (clip.left + clip.width) as i32
+                    || pos.y >= (clip.top + clip.height) as i32
+            }
+            None => false,
+        }
+    }
+
+    /// How everything drawn from now on combines with what is already there, e.g.
+    /// `BlendMode::Darken` for highlighter strokes that don't cover the ink below. In
+    /// the other modes besides `Opaque`, pixels drawn over more than once in a single
+    /// call, like where the stamps of a thick line overlap, blend more than once.
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Draws text with `fonts` from now on, e.g. with fallbacks for other scripts
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn set_fonts(&mut self, fonts: FontCollection) {
+        self.fonts = Some(fonts);
+        self.glyph_cache.clear();
+    }
+
+    /// The fonts text is drawn with, only the builtin one unless changed
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn fonts(&self) -> &FontCollection {
+        self.fonts.as_ref().unwrap_or(&DEFAULT_FONTS)
+    }
+
+    /// Turns everything drawn, read and refreshed from now on, see
+    /// `framebuffer::rotation`. Clip rectangles are in the coordinates of the rotation
+    /// they are used with.
+    pub fn set_rotation(&mut self, rotation: ScreenRotation) {
+        self.rotation = rotation;
+    }
+
+    pub fn rotation(&self) -> ScreenRotation {
+        self.rotation
+    }
+
+    /// Frees the glyphs kept to draw the same text faster again, up to
+    /// `fonts::GLYPH_CACHE_CAPACITY` of them
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub fn clear_glyph_cache(&mut self) {
+        self.glyph_cache.clear();
+    }
+
+    /// Gives up on waiting for refreshes that don't complete in time, see
+    /// `framebuffer::watchdog`. Only waits on the device itself can hang.
+    pub fn set_refresh_watchdog(&mut self, watchdog: Option<RefreshWatchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    /// Follows partial refreshes with full ones to clear ghosting, see
+    /// `framebuffer::ghosting`
+    pub fn set_ghosting_policy(&mut self, policy: Option<GhostingPolicy>) {
+        self.ghosting = policy;
+    }
+
+    /// Takes the refreshes requested from a headless framebuffer since the last call,
+    /// in the order they were made. Always empty for other framebuffers.
+    pub fn take_refreshes(&self) -> Vec<mxcfb_update_data> {
+        match &self.framebuffer_update {
+            FramebufferUpdate::Headless(refreshes) => {
+                std::mem::take(&mut refreshes.lock().unwrap())
+            }
+            _ => Vec::new(),
+        }
     }
 
     #[deprecated = "Use `new` to autodetect the right update method based on your device version, or `device` or `rm2fb` to choose one explicitly."]
@@ -93,54 +415,55 @@ impl Framebuffer {
         }
     }
 
-    fn build(framebuffer_update: FramebufferUpdate) -> Framebuffer {
-        let mut var_screen_info = match &framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => Framebuffer::get_var_screeninfo(device),
-            FramebufferUpdate::Swtfb(c) => c.get_var_screeninfo(),
-        };
-        var_screen_info.xres = 1404;
-        var_screen_info.yres = 1872;
-        var_screen_info.rotate = 1;
-        var_screen_info.width = 0xffff_ffff;
-        var_screen_info.height = 0xffff_ffff;
-        var_screen_info.pixclock = 6250;
-        var_screen_info.left_margin = 32;
-        var_screen_info.right_margin = 326;
-        var_screen_info.upper_margin = 4;
-        var_screen_info.lower_margin = 12;
-        var_screen_info.hsync_len = 44;
-        var_screen_info.vsync_len = 1;
-        var_screen_info.sync = 0;
-        var_screen_info.vmode = 0; // FB_VMODE_NONINTERLACED
-        var_screen_info.accel_flags = 0;
-
-        let fix_screen_info = match &framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                Framebuffer::put_var_screeninfo(device, &mut var_screen_info);
-                Framebuffer::get_fix_screeninfo(device)
+    fn build(device: File, model: Option<Model>) -> Result<Framebuffer, FramebufferError> {
+        let mut var_screen_info = Framebuffer::get_var_screeninfo(&device)?;
+        // The Paper Pro keeps the mode it comes up in, its own resolution in 32 bit color
+        if model != Some(Model::PaperPro) {
+            var_screen_info.xres = 1404;
+            var_screen_info.yres = 1872;
+            var_screen_info.rotate = 1;
+            var_screen_info.width = 0xffff_ffff;
+            var_screen_info.height = 0xffff_ffff;
+            var_screen_info.pixclock = 6250;
+            var_screen_info.left_margin = 32;
+            var_screen_info.right_margin = 326;
+            var_screen_info.upper_margin = 4;
+            var_screen_info.lower_margin = 12;
+            var_screen_info.hsync_len = 44;
+            var_screen_info.vsync_len = 1;
+            var_screen_info.sync = 0;
+            var_screen_info.vmode = 0; // FB_VMODE_NONINTERLACED
+            var_screen_info.accel_flags = 0;
+
+            if !Framebuffer::put_var_screeninfo(&device, &mut var_screen_info) {
+                warn!("FBIOPUT_VSCREENINFO failed, continuing with the current mode");
             }
-            FramebufferUpdate::Swtfb(c) => c.get_fix_screeninfo(),
-        };
+        }
+        let fix_screen_info = Framebuffer::get_fix_screeninfo(&device)?;
 
         let frame_length = (fix_screen_info.line_length * var_screen_info.yres) as usize;
+        let mem_map = MmapOptions::new()
+            .len(frame_length)
+            .map_raw(&device)
+            .map_err(FramebufferError::Map)?;
 
-        let mem_map = match &framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => MmapOptions::new()
-                .len(frame_length)
-                .map_raw(device)
-                .expect("Unable to map provided path"),
-            FramebufferUpdate::Swtfb(swtfb_client) => swtfb_client
-                .open_buffer()
-                .expect("Failed to open swtfb shared buffer"),
-        };
-
-        Framebuffer {
+        Ok(Framebuffer {
             marker: AtomicU32::new(1),
             frame: mem_map,
             var_screen_info,
             fix_screen_info,
-            framebuffer_update,
-        }
+            framebuffer_update: FramebufferUpdate::Ioctl(device),
+            watchdog: None,
+            ghosting: None,
+            refresh_profile: RefreshProfile::default(),
+            clip: Vec::new(),
+            blend_mode: BlendMode::Opaque,
+            rotation: ScreenRotation::Rot0,
+            #[cfg(feature = "framebuffer-text-drawing")]
+            fonts: None,
+            #[cfg(feature = "framebuffer-text-drawing")]
+            glyph_cache: GlyphCache::default(),
+        })
     }
 }
 
@@ -157,7 +480,11 @@ impl framebuffer::FramebufferBase for Framebuffer {
                     libc::ioctl(device.as_raw_fd(), request);
                 };
             }
-            FramebufferUpdate::Swtfb(_) => {}
+            FramebufferUpdate::Shm(_)
+            | FramebufferUpdate::Headless(_)
+            | FramebufferUpdate::Backend(_) => {}
+            #[cfg(feature = "compositor")]
+            FramebufferUpdate::Compositor(_) => {}
         }
     }
 
@@ -173,7 +500,11 @@ impl framebuffer::FramebufferBase for Framebuffer {
                     );
                 };
             }
-            FramebufferUpdate::Swtfb(_) => {}
+            FramebufferUpdate::Shm(_)
+            | FramebufferUpdate::Headless(_)
+            | FramebufferUpdate::Backend(_) => {}
+            #[cfg(feature = "compositor")]
+            FramebufferUpdate::Compositor(_) => {}
         }
     }
 
@@ -189,22 +520,30 @@ impl framebuffer::FramebufferBase for Framebuffer {
                     );
                 };
             }
-            FramebufferUpdate::Swtfb(_) => {}
+            FramebufferUpdate::Shm(_)
+            | FramebufferUpdate::Headless(_)
+            | FramebufferUpdate::Backend(_) => {}
+            #[cfg(feature = "compositor")]
+            FramebufferUpdate::Compositor(_) => {}
         }
     }
 
-    fn get_fix_screeninfo(device: &File) -> FixScreeninfo {
+    fn get_fix_screeninfo(device: &File) -> Result<FixScreeninfo, FramebufferError> {
         let mut info: FixScreeninfo = Default::default();
         let result = unsafe { ioctl(device.as_raw_fd(), FBIOGET_FSCREENINFO, &mut info) };
-        assert!(result == 0, "FBIOGET_FSCREENINFO failed");
-        info
+        if result != 0 {
+            return Err(FramebufferError::ioctl("FBIOGET_FSCREENINFO"));
+        }
+        Ok(info)
     }
 
-    fn get_var_screeninfo(device: &File) -> VarScreeninfo {
+    fn get_var_screeninfo(device: &File) -> Result<VarScreeninfo, FramebufferError> {
         let mut info: VarScreeninfo = Default::default();
         let result = unsafe { ioctl(device.as_raw_fd(), FBIOGET_VSCREENINFO, &mut info) };
-        assert!(result == 0, "FBIOGET_VSCREENINFO failed");
-        info
+        if result != 0 {
+            return Err(FramebufferError::ioctl("FBIOGET_VSCREENINFO"));
+        }
+        Ok(info)
     }
 
     fn put_var_screeninfo(device: &std::fs::File, var_screen_info: &mut VarScreeninfo) -> bool {
@@ -217,7 +556,365 @@ impl framebuffer::FramebufferBase for Framebuffer {
             FramebufferUpdate::Ioctl(device) => {
                 Self::put_var_screeninfo(device, &mut self.var_screen_info)
             }
-            FramebufferUpdate::Swtfb(_) => true,
+            FramebufferUpdate::Shm(_)
+            | FramebufferUpdate::Headless(_)
+            | FramebufferUpdate::Backend(_) => true,
+            #[cfg(feature = "compositor")]
+            FramebufferUpdate::Compositor(_) => true,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-text-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::{Point2, Vector2};
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::{FramebufferDraw, FramebufferIO};
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_image_transformed() {
+        use crate::framebuffer::cgmath::{Deg, Matrix3};
+        use crate::framebuffer::Interpolation;
+
+        // Black on the left, white on the right
+        let img = image::RgbImage::from_fn(20, 10, |x, _| {
+            if x < 10 {
+                image::Rgb([0, 0, 0])
+            } else {
+                image::Rgb([255, 255, 255])
+            }
+        });
+        let dark = |fb: &Framebuffer, x, y| fb.read_pixel(Point2 { x, y }).to_rgb8()[1] < 128;
+
+        // Turned a quarter clockwise around its top left corner at (50, 50) and doubled
+        let transform = Matrix3::from_translation(Vector2 { x: 50.0, y: 50.0 })
+            * Matrix3::from_angle_z(Deg(90.0))
+            * Matrix3::from_scale(2.0);
+        let mut fb = Framebuffer::headless(100, 100);
+        fb.fill_rect(
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 100, y: 100 },
+            color::GRAY(0x80),
+        );
+        let rect = fb.draw_image_transformed(&img, transform, Interpolation::Nearest);
+        assert_eq!(
+            rect,
+            mxcfb_rect {
+                left: 30,
+                top: 50,
+                width: 20,
+                height: 40
+            }
+        );
+        assert!(dark(&fb, 40, 55) && !dark(&fb, 40, 85));
+        // Outside of it untouched
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 55, y: 60 }),
+            color::from_native(color::GRAY(0x80).as_native())
+        );
+
+        let mut fb = Framebuffer::headless(100, 100);
+        let zoom = Matrix3::from_scale(4.0);
+        fb.draw_image_transformed(&img, zoom, Interpolation::Bilinear);
+        let green = |x| fb.read_pixel(Point2 { x, y: 20 }).to_rgb8()[1];
+        // A smooth ramp across the edge instead of a step
+        assert!(green(30) < 10 && green(50) > 245);
+        assert!(green(39) > 20 && green(39) < 235, "{}", green(39));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_image_dithering() {
+        use crate::framebuffer::common::dither_algorithm;
+        use crate::framebuffer::ImageImportOptions;
+
+        let gray = |g| image::RgbImage::from_pixel(32, 32, image::Rgb([g, g, g]));
+        let draw = |img: &image::RgbImage, options: ImageImportOptions| {
+            let mut fb = Framebuffer::headless(40, 40);
+            fb.draw_image(img, Point2 { x: 4, y: 4 }, options);
+            let grays: Vec<u8> = (4..36)
+                .flat_map(|y| (4..36).map(move |x| Point2 { x, y }))
+                .map(|p| fb.read_pixel(p).to_rgb8()[1])
+                .collect();
+            let mean = grays.iter().map(|g| f32::from(*g)).sum::<f32>() / grays.len() as f32;
+            let mut levels = grays.clone();
+            levels.sort_unstable();
+            levels.dedup();
+            (mean, levels)
+        };
+
+        // Only the closest gray, a shade off
+        let (mean, levels) = draw(
+            &gray(128),
+            ImageImportOptions {
+                dither: dither_algorithm::NONE,
+                ..Default::default()
+            },
+        );
+        assert_eq!(levels.len(), 1);
+        assert!((mean - 128.0).abs() > 3.0, "{}", mean);
+        // The two closest grays, mixed to look like the original
+        for dither in [dither_algorithm::FLOYD_STEINBERG, dither_algorithm::ORDERED] {
+            let (mean, levels) = draw(
+                &gray(128),
+                ImageImportOptions {
+                    dither,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(levels.len(), 2, "{:?}", dither);
+            assert!((mean - 128.0).abs() < 3.0, "{:?} {}", dither, mean);
         }
+
+        let (mean, _) = draw(
+            &gray(128),
+            ImageImportOptions {
+                gamma: 2.0,
+                ..Default::default()
+            },
+        );
+        assert!((mean - 180.0).abs() < 4.0, "{}", mean);
+        // Light grays turn white
+        let (_, levels) = draw(
+            &gray(160),
+            ImageImportOptions {
+                contrast: 10.0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(levels, [255]);
+    }
+
+    #[test]
+    fn test_text_outline() {
+        use crate::framebuffer::TextStyle;
+
+        let draw = |style| {
+            let mut fb = Framebuffer::headless(300, 100);
+            let rect = fb.draw_text_styled(Point2 { x: 10.0, y: 70.0 }, "HO", 60.0, style, false);
+            (fb, rect)
+        };
+        let dark = |fb: &Framebuffer, x, y| fb.read_pixel(Point2 { x, y }).to_rgb8()[1] < 100;
+        let pixels = || (0..100).flat_map(|y| (0..300).map(move |x| (x, y)));
+
+        let (filled, filled_rect) = draw(TextStyle::Fill(color::BLACK));
+        let (hollow, hollow_rect) = draw(TextStyle::Outline {
+            color: color::BLACK,
+            width: 2,
+        });
+        assert_eq!(hollow_rect.left + 3, filled_rect.left);
+        assert_eq!(hollow_rect.width, filled_rect.width + 6);
+        // Only around the glyphs, which are left empty
+        let outline: Vec<_> = pixels().filter(|&(x, y)| dark(&hollow, x, y)).collect();
+        assert!(outline.len() > 200, "{}", outline.len());
+        assert!(outline.iter().all(|&(x, y)| !dark(&filled, x, y)));
+
+        let (both, _) = draw(TextStyle::FilledOutline {
+            fill: color::BLACK,
+            outline: color::GRAY(0x80),
+            width: 2,
+        });
+        assert!(pixels().all(|(x, y)| !dark(&filled, x, y) || dark(&both, x, y)));
+        let gray = |fb: &Framebuffer, x, y| {
+            let g = fb.read_pixel(Point2 { x, y }).to_rgb8()[1];
+            g > 100 && g < 160
+        };
+        assert!(outline.iter().all(|&(x, y)| gray(&both, x, y)));
+    }
+
+    #[test]
+    fn test_text_spans() {
+        use crate::framebuffer::draw::TextSpan;
+
+        let spans = [
+            TextSpan::new("Plain and ", 30.0),
+            TextSpan::new("bold", 30.0).bold(),
+            TextSpan::new(" text, ", 30.0),
+            TextSpan::new("underlined", 20.0).underline(),
+            TextSpan::new(" and\nbroken", 30.0).strikethrough(),
+        ];
+        let mut fb = Framebuffer::headless(400, 300);
+        let origin = Point2 { x: 10.0, y: 10.0 };
+        let measured = fb.draw_spans(origin, 200.0, &spans, true);
+        let rect = fb.draw_spans(origin, 200.0, &spans, false);
+        assert_eq!(measured, rect);
+        assert!(rect.width <= 200, "{:?}", rect);
+        // Wrapped once and broken once
+        let line = (30.0 * 1.2) as u32;
+        assert!(
+            rect.height > 2 * line && rect.height < 4 * line,
+            "{:?}",
+            rect
+        );
+
+        let dark = |fb: &Framebuffer, x, y| fb.read_pixel(Point2 { x, y }).to_rgb8()[1] < 128;
+        let darkness = |text: TextSpan| {
+            let mut fb = Framebuffer::headless(300, 100);
+            fb.draw_spans(origin, 300.0, &[text], false);
+            (0..100u32)
+                .flat_map(|y| (0..300u32).map(move |x| (x, y)))
+                .filter(|&(x, y)| dark(&fb, x, y))
+                .count()
+        };
+        assert!(
+            darkness(TextSpan::new("word", 30.0).bold())
+                > darkness(TextSpan::new("word", 30.0)) * 5 / 4
+        );
+
+        // A solid line under the whole span, and through it
+        let mut fb = Framebuffer::headless(300, 100);
+        let rect = fb.draw_spans(
+            origin,
+            300.0,
+            &[TextSpan::new("a b", 32.0).underline()],
+            false,
+        );
+        let underline = (rect.top..rect.top + rect.height)
+            .find(|&y| (15..rect.left + rect.width - 5).all(|x| dark(&fb, x, y)));
+        assert!(underline.is_some());
+        let mut fb = Framebuffer::headless(300, 100);
+        let rect = fb.draw_spans(
+            origin,
+            300.0,
+            &[TextSpan::new("a b", 32.0).strikethrough()],
+            false,
+        );
+        let strike = (rect.top..rect.top + rect.height)
+            .find(|&y| (15..rect.left + rect.width - 5).all(|x| dark(&fb, x, y)));
+        assert!(strike.unwrap() < underline.unwrap());
+    }
+
+    #[test]
+    fn test_clip() {
+        let mut fb = Framebuffer::headless(200, 100);
+        let is_black = |fb: &Framebuffer, x, y| fb.read_pixel(Point2 { x, y }).to_rgb8()[0] < 128;
+
+        fb.push_clip(mxcfb_rect {
+            left: 10,
+            top: 10,
+            width: 50,
+            height: 50,
+        });
+        // Nested clips only ever shrink
+        fb.push_clip(mxcfb_rect {
+            left: 40,
+            top: 0,
+            width: 100,
+            height: 100,
+        });
+        assert_eq!(
+            fb.clip(),
+            Some(mxcfb_rect {
+                left: 40,
+                top: 10,
+                width: 20,
+                height: 50,
+            })
+        );
+        fb.fill_rect(
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 200, y: 100 },
+            color::BLACK,
+        );
+        assert!(is_black(&fb, 40, 10) && is_black(&fb, 59, 59));
+        assert!(!is_black(&fb, 39, 10) && !is_black(&fb, 60, 10) && !is_black(&fb, 40, 60));
+
+        fb.pop_clip();
+        fb.clear();
+        assert!(!is_black(&fb, 40, 10));
+        fb.draw_text(
+            Point2 { x: 0.0, y: 40.0 },
+            "Text much too long for its box",
+            30.0,
+            color::BLACK,
+            false,
+        );
+        assert!((0..100).all(|y| !is_black(&fb, 100, y)));
+
+        assert!(fb.pop_clip().is_some());
+        assert_eq!(fb.clip(), None);
+        fb.fill_rect(
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 200, y: 100 },
+            color::BLACK,
+        );
+        assert!(is_black(&fb, 0, 0) && is_black(&fb, 199, 99));
+    }
+
+    #[test]
+    fn test_blend_modes() {
+        // Within what the framebuffer's 16 bit colors can tell apart
+        let gray = |c: color| c.to_rgb8()[1];
+        let near = |c: color, level: u8| (i16::from(gray(c)) - i16::from(level)).abs() <= 4;
+        let mode = BlendMode::SourceOver(128);
+        assert!(near(mode.blend(color::WHITE, color::BLACK), 127));
+        assert!(near(
+            BlendMode::Multiply.blend(color::GRAY(128), color::GRAY(128)),
+            64
+        ));
+        assert!(near(
+            BlendMode::Lighten.blend(color::BLACK, color::GRAY(50)),
+            205
+        ));
+        assert_eq!(
+            gray(BlendMode::Darken.blend(color::BLACK, color::GRAY(50))),
+            0
+        );
+
+        // A highlighter over ink and paper
+        let mut fb = Framebuffer::headless(100, 10);
+        fb.clear();
+        fb.fill_rect(
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 50, y: 10 },
+            color::BLACK,
+        );
+        fb.set_blend_mode(BlendMode::Darken);
+        fb.draw_line(
+            Point2 { x: 0, y: 5 },
+            Point2 { x: 99, y: 5 },
+            6,
+            color::GRAY(80),
+        );
+        fb.set_blend_mode(BlendMode::Opaque);
+        let at = |x| gray(fb.read_pixel(Point2 { x, y: 5 }));
+        assert_eq!(at(20), 0);
+        assert!((170..=182).contains(&at(80)), "{}", at(80));
+    }
+
+    #[test]
+    fn test_color_framebuffer() {
+        let mut fb = Framebuffer::headless_color(20, 10);
+        assert!(fb.is_color() && !Framebuffer::headless(20, 10).is_color());
+        fb.fill_rect(
+            Point2 { x: 2, y: 2 },
+            Vector2 { x: 5, y: 5 },
+            color::RGB(200, 30, 90),
+        );
+        // Not reduced to 16 bits
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 3, y: 3 }),
+            color::RGB(200, 30, 90)
+        );
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 19, y: 9 }),
+            color::RGB(255, 255, 255)
+        );
+        let rect = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 20,
+            height: 10,
+        };
+        let dump = fb.dump_region(rect).unwrap();
+        assert_eq!(dump.len(), 20 * 10 * 4);
+        fb.clear();
+        fb.restore_region(rect, &dump).unwrap();
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 6, y: 6 }),
+            color::RGB(200, 30, 90)
+        );
     }
 }
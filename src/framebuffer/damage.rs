@@ -0,0 +1,137 @@
+//! Refreshing what was drawn, without keeping track of it by hand.
+//!
+//! A `DamageTracker` records the area every draw call through it returns. Areas close
+//! to each other are merged as by `region::decompose`, so a handful of rectangles
+//! cover everything drawn since the last `flush`, which refreshes them. Refreshing a
+//! region twice, or forgetting one, is what makes apps flicker or leave stale pixels
+//! behind.
+
+use std::ops::DerefMut;
+
+use crate::framebuffer::common::{mxcfb_rect, waveform_mode};
+use crate::framebuffer::core;
+use crate::framebuffer::region;
+use crate::framebuffer::{FramebufferRefresh, PartialRefreshMode, RefreshProfile};
+
+/// How many rectangles the damage is kept in at most, see `region::decompose`
+pub const MAX_DAMAGE_RECTS: usize = 4;
+
+/// Records the areas drawn on `F`, a `&mut Framebuffer` or anything else that
+/// dereferences to one, for refreshing them together
+pub struct DamageTracker<F: DerefMut<Target = core::Framebuffer>> {
+    fb: F,
+    damage: Vec<mxcfb_rect>,
+}
+
+impl<F: DerefMut<Target = core::Framebuffer>> DamageTracker<F> {
+    pub fn new(fb: F) -> DamageTracker<F> {
+        DamageTracker {
+            fb,
+            damage: Vec::new(),
+        }
+    }
+
+    /// Draws with `draw`, recording the area it returns, e.g.
+    /// `tracker.draw(|fb| fb.draw_text(pos, "Saved", 30.0, color::BLACK, false))`
+    pub fn draw(&mut self, draw: impl FnOnce(&mut core::Framebuffer) -> mxcfb_rect) -> mxcfb_rect {
+        let rect = draw(&mut self.fb);
+        self.add(rect);
+        rect
+    }
+
+    /// Records `rect` as drawn, for changes not made through `draw`
+    pub fn add(&mut self, rect: mxcfb_rect) {
+        region::add_rect(&mut self.damage, rect, MAX_DAMAGE_RECTS);
+    }
+
+    /// The areas drawn since the last `flush`
+    pub fn damage(&self) -> &[mxcfb_rect] {
+        &self.damage
+    }
+
+    /// Forgets the areas drawn without refreshing them
+    pub fn clear(&mut self) {
+        self.damage.clear();
+    }
+
+    /// Refreshes the areas drawn since the last flush with `waveform`, and otherwise
+    /// the framebuffer's refresh profile. Returns the markers of the refreshes.
+    pub fn flush(&mut self, waveform: waveform_mode, mode: PartialRefreshMode) -> Vec<u32> {
+        let profile = RefreshProfile {
+            waveform_mode: waveform,
+            ..self.fb.refresh_profile()
+        };
+        self.flush_with(&profile, mode)
+    }
+
+    /// Like `flush`, with the settings of `profile`
+    pub fn flush_with(&mut self, profile: &RefreshProfile, mode: PartialRefreshMode) -> Vec<u32> {
+        std::mem::take(&mut self.damage)
+            .iter()
+            .map(|rect| self.fb.partial_refresh_with(rect, mode, profile, false))
+            .collect()
+    }
+
+    pub fn framebuffer(&self) -> &core::Framebuffer {
+        &self.fb
+    }
+
+    /// The framebuffer, to draw on it without recording the area, see `add`
+    pub fn framebuffer_mut(&mut self) -> &mut core::Framebuffer {
+        &mut self.fb
+    }
+
+    pub fn into_inner(self) -> F {
+        self.fb
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::Point2;
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::FramebufferDraw;
+    use crate::test_util::rect;
+
+    #[test]
+    fn test_damage_tracker() {
+        let mut fb = core::Framebuffer::headless(1000, 1000);
+        let mut tracker = DamageTracker::new(&mut fb);
+        tracker.draw(|fb| fb.fill_circle(Point2 { x: 20, y: 20 }, 10, color::BLACK));
+        // Overlapping and contained ones are merged, far apart ones aren't
+        tracker.add(rect(20, 20, 20, 20));
+        tracker.add(rect(12, 12, 2, 2));
+        tracker.add(rect(500, 500, 10, 10));
+        tracker.add(mxcfb_rect::invalid());
+        let mut damage = tracker.damage().to_vec();
+        damage.sort_by_key(|r| r.left);
+        assert_eq!(damage, [rect(10, 10, 30, 30), rect(500, 500, 10, 10)]);
+
+        // Never more than a few
+        for i in 0..10 {
+            tracker.add(rect(i * 90, 900, 5, 5));
+        }
+        assert_eq!(tracker.damage().len(), MAX_DAMAGE_RECTS);
+        let drawn = [
+            rect(10, 10, 30, 30),
+            rect(500, 500, 10, 10),
+            rect(810, 900, 5, 5),
+        ];
+        for drawn in drawn {
+            assert!(tracker.damage().iter().any(|r| r.contains_rect(&drawn)));
+        }
+
+        let markers = tracker.flush(waveform_mode::WAVEFORM_MODE_DU, PartialRefreshMode::Async);
+        assert_eq!(markers.len(), MAX_DAMAGE_RECTS);
+        assert!(tracker.damage().is_empty());
+        assert!(tracker
+            .flush(waveform_mode::WAVEFORM_MODE_DU, PartialRefreshMode::Async)
+            .is_empty());
+        let refreshes = tracker.into_inner().take_refreshes();
+        assert_eq!(refreshes.len(), MAX_DAMAGE_RECTS);
+        assert!(refreshes
+            .iter()
+            .all(|r| r.waveform_mode == waveform_mode::WAVEFORM_MODE_DU as u32));
+    }
+}
@@ -0,0 +1,130 @@
+//! Decoding images for `draw_image`.
+//!
+//! PNG is always supported. JPEG and WebP, e.g. for photos downloaded by an app, are
+//! behind the `image-jpeg` and `image-webp` features. These only gate the helpers
+//! here, the re-exported `image` crate keeps all of its default formats. The
+//! format is told from the data itself, so `decode` and `load` take any of them and
+//! return an image ready to draw, optionally scaled down to fit the display with
+//! `fit`.
+
+use std::io;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::{ImageFormat, RgbImage};
+
+use crate::framebuffer::cgmath::Vector2;
+
+/// Why decoding an image failed
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("Failed to read the image file")]
+    Io(#[from] io::Error),
+    #[error("Not an image in a known format")]
+    UnknownFormat,
+    #[error("Decoding {0:?} images is not enabled")]
+    Unsupported(ImageFormat),
+    #[error("Malformed image")]
+    Image(#[from] image::ImageError),
+}
+
+/// Whether images in `format` can be decoded with the enabled features
+pub fn is_supported(format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::Jpeg => cfg!(feature = "image-jpeg"),
+        ImageFormat::WebP => cfg!(feature = "image-webp"),
+        format => format == ImageFormat::Png,
+    }
+}
+
+fn decode_as(data: &[u8], format: ImageFormat) -> Result<RgbImage, DecodeError> {
+    if !is_supported(format) {
+        return Err(DecodeError::Unsupported(format));
+    }
+    Ok(image::load_from_memory_with_format(data, format)?.to_rgb8())
+}
+
+/// Decodes the PNG, JPEG or WebP image in `data`
+pub fn decode(data: &[u8]) -> Result<RgbImage, DecodeError> {
+    let format = image::guess_format(data).map_err(|_| DecodeError::UnknownFormat)?;
+    decode_as(data, format)
+}
+
+/// Decodes the image in the file at `path`, see `decode`
+pub fn load(path: impl AsRef<Path>) -> Result<RgbImage, DecodeError> {
+    decode(&std::fs::read(path)?)
+}
+
+pub fn decode_png(data: &[u8]) -> Result<RgbImage, DecodeError> {
+    decode_as(data, ImageFormat::Png)
+}
+
+#[cfg(feature = "image-jpeg")]
+pub fn decode_jpeg(data: &[u8]) -> Result<RgbImage, DecodeError> {
+    decode_as(data, ImageFormat::Jpeg)
+}
+
+/// Only lossy WebP images are supported
+#[cfg(feature = "image-webp")]
+pub fn decode_webp(data: &[u8]) -> Result<RgbImage, DecodeError> {
+    decode_as(data, ImageFormat::WebP)
+}
+
+/// `img` scaled down to fit in `size`, keeping its aspect ratio. Images that already
+/// fit are returned as they are.
+pub fn fit(img: RgbImage, size: Vector2<u32>) -> RgbImage {
+    let (width, height) = img.dimensions();
+    if width <= size.x && height <= size.y {
+        return img;
+    }
+    let scale = (size.x as f32 / width as f32).min(size.y as f32 / height as f32);
+    let scaled = |length: u32| ((length as f32 * scale).round() as u32).max(1);
+    image::imageops::resize(&img, scaled(width), scaled(height), FilterType::Triangle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_decode() {
+        let img = RgbImage::from_fn(40, 20, |x, _| Rgb([(x * 6) as u8; 3]));
+        let mut png = Vec::new();
+        image::codecs::png::PngEncoder::new(&mut png)
+            .encode(img.as_raw(), 40, 20, image::ColorType::Rgb8)
+            .unwrap();
+        assert_eq!(decode(&png).unwrap(), img);
+        assert_eq!(decode_png(&png).unwrap(), img);
+
+        #[cfg(feature = "image-jpeg")]
+        {
+            let mut jpeg = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 95)
+                .encode(img.as_raw(), 40, 20, image::ColorType::Rgb8)
+                .unwrap();
+            let decoded = decode(&jpeg).unwrap();
+            assert_eq!(decoded.dimensions(), (40, 20));
+            assert!((i32::from(decoded.get_pixel(30, 10)[0]) - 180).abs() < 8);
+            assert!(matches!(decode_jpeg(&png), Err(DecodeError::Image(_))));
+        }
+        #[cfg(not(feature = "image-jpeg"))]
+        assert!(matches!(
+            decode(b"\xff\xd8\xff\xe0"),
+            Err(DecodeError::Unsupported(ImageFormat::Jpeg))
+        ));
+
+        assert!(matches!(
+            decode(b"not an image"),
+            Err(DecodeError::UnknownFormat)
+        ));
+        assert!(matches!(
+            load("/nonexistent/photo.jpg"),
+            Err(DecodeError::Io(_))
+        ));
+
+        let fitted = fit(img.clone(), Vector2 { x: 10, y: 100 });
+        assert_eq!(fitted.dimensions(), (10, 5));
+        assert_eq!(fit(img.clone(), Vector2 { x: 100, y: 100 }), img);
+    }
+}
@@ -19,15 +19,203 @@ pub static DEFAULT_FONT: Lazy<Font<'static>> = Lazy::new(|| {
         .expect("corrupted font data")
 });
 
+/// `text` ready to be laid out from left to right, see `framebuffer::shaping`
+#[cfg(feature = "framebuffer-text-drawing")]
+fn shaped(text: &str) -> std::borrow::Cow<'_, str> {
+    #[cfg(feature = "text-shaping")]
+    return framebuffer::shaping::shape(text);
+    #[cfg(not(feature = "text-shaping"))]
+    std::borrow::Cow::Borrowed(text)
+}
+
+/// How heavy text is. Bold is made up by widening the glyphs of the font.
+#[cfg(feature = "framebuffer-text-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FontWeight {
+    Regular,
+    Bold,
+}
+
+/// A piece of a paragraph in a single style, see `FramebufferDraw::draw_spans`
+#[cfg(feature = "framebuffer-text-drawing")]
+#[derive(Clone)]
+pub struct TextSpan<'a> {
+    pub text: &'a str,
+    pub font: &'a Font<'static>,
+    pub size: f32,
+    pub weight: FontWeight,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub color: color,
+}
+
+#[cfg(feature = "framebuffer-text-drawing")]
+impl<'a> TextSpan<'a> {
+    /// Regular black text in the default font
+    pub fn new(text: &'a str, size: f32) -> TextSpan<'a> {
+        TextSpan {
+            text,
+            font: &DEFAULT_FONT,
+            size,
+            weight: FontWeight::Regular,
+            underline: false,
+            strikethrough: false,
+            color: color::BLACK,
+        }
+    }
+
+    pub fn font(mut self, font: &'a Font<'static>) -> TextSpan<'a> {
+        self.font = font;
+        self
+    }
+
+    pub fn bold(mut self) -> TextSpan<'a> {
+        self.weight = FontWeight::Bold;
+        self
+    }
+
+    pub fn underline(mut self) -> TextSpan<'a> {
+        self.underline = true;
+        self
+    }
+
+    pub fn strikethrough(mut self) -> TextSpan<'a> {
+        self.strikethrough = true;
+        self
+    }
+
+    pub fn color(mut self, color: color) -> TextSpan<'a> {
+        self.color = color;
+        self
+    }
+
+    /// How much further to the right bold glyphs reach
+    fn emboldening(&self) -> f32 {
+        match self.weight {
+            FontWeight::Regular => 0.0,
+            FontWeight::Bold => (self.size / 24.0).max(1.0).round(),
+        }
+    }
+
+    /// How wide `text` is in the style of the span
+    fn measure(&self, text: &str) -> f32 {
+        let scale = Scale::uniform(self.size);
+        self.font
+            .layout(text, scale, point(0.0, 0.0))
+            .last()
+            .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
+            .unwrap_or(0.0)
+            + self.emboldening()
+    }
+}
+
+/// A word or the space between words of the `span`th span, placed in a paragraph
+#[cfg(feature = "framebuffer-text-drawing")]
+struct PlacedRun<'a> {
+    span: usize,
+    text: &'a str,
+    x: f32,
+    width: f32,
+    line: usize,
+}
+
+/// Breaks `spans` into lines no wider than `width`, except for single words that are.
+/// Returns the runs and the ascent and descent of every line.
+#[cfg(feature = "framebuffer-text-drawing")]
+fn layout_spans<'a>(spans: &[TextSpan<'a>], width: f32) -> (Vec<PlacedRun<'a>>, Vec<(f32, f32)>) {
+    let mut runs = Vec::new();
+    let mut lines = vec![(0.0f32, 0.0f32)];
+    let mut x = 0.0;
+    for (i, span) in spans.iter().enumerate() {
+        let metrics = span.font.v_metrics(Scale::uniform(span.size));
+        let mut rest = span.text;
+        while !rest.is_empty() {
+            // Up to a change between space and not
+            let space = rest.starts_with(char::is_whitespace);
+            let end = rest
+                .find(|c: char| c.is_whitespace() != space || c == '\n')
+                .unwrap_or(rest.len())
+                .max(usize::from(rest.starts_with('\n')));
+            let (text, remaining) = rest.split_at(end);
+            rest = remaining;
+            if text == "\n" {
+                lines.push((0.0, 0.0));
+                x = 0.0;
+                continue;
+            }
+            let run_width = span.measure(text);
+            if !space && x > 0.0 && x + run_width > width {
+                lines.push((0.0, 0.0));
+                x = 0.0;
+                // No space at the start of a wrapped line
+                while runs.last().is_some_and(|r: &PlacedRun| {
+                    r.line == lines.len() - 2 && r.text.trim().is_empty()
+                }) {
+                    runs.pop();
+                }
+            }
+            if space && x == 0.0 {
+                continue;
+            }
+            let line = lines.len() - 1;
+            let (ascent, descent) = &mut lines[line];
+            *ascent = ascent.max(metrics.ascent);
+            *descent = descent.max(-metrics.descent + metrics.line_gap);
+            runs.push(PlacedRun {
+                span: i,
+                text,
+                x,
+                width: run_width,
+                line,
+            });
+            x += run_width;
+        }
+    }
+    // Empty lines as high as the text before them
+    for i in 1..lines.len() {
+        if lines[i] == (0.0, 0.0) {
+            lines[i] = lines[i - 1];
+        }
+    }
+    (runs, lines)
+}
+
 impl framebuffer::FramebufferDraw for core::Framebuffer {
     #[cfg(feature = "image")]
-    fn draw_image(&mut self, img: &RgbImage, pos: Point2<i32>) -> mxcfb_rect {
-        for (x, y, pixel) in img.enumerate_pixels() {
-            let pixel_pos = pos + vec2(x as i32, y as i32);
-            self.write_pixel(
-                pixel_pos.cast().unwrap(),
-                color::RGB(pixel.0[0], pixel.0[1], pixel.0[2]),
-            );
+    fn draw_image(
+        &mut self,
+        img: &RgbImage,
+        pos: Point2<i32>,
+        options: framebuffer::ImageImportOptions,
+    ) -> mxcfb_rect {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let mut grays: Vec<f32> = img.pixels().map(|p| options.gray(p.0)).collect();
+        for y in 0..height {
+            for x in 0..width {
+                let pixel_pos = pos + vec2(x as i32, y as i32);
+                let gray = grays[y * width + x].clamp(0.0, 255.0);
+                let closest = (gray / 17.0).round() * 17.0;
+                let level = closest as u8;
+                let c = match options.dither {
+                    dither_algorithm::NONE => color::RGB(level, level, level),
+                    dither_algorithm::ORDERED => graphics::dither(gray, pixel_pos),
+                    dither_algorithm::FLOYD_STEINBERG => {
+                        let error = gray - closest;
+                        let mut spread = |dx: isize, dy: usize, share: f32| {
+                            let to = x as isize + dx;
+                            if to >= 0 && (to as usize) < width && y + dy < height {
+                                grays[(y + dy) * width + to as usize] += error * share;
+                            }
+                        };
+                        spread(1, 0, 7.0 / 16.0);
+                        spread(-1, 1, 3.0 / 16.0);
+                        spread(0, 1, 5.0 / 16.0);
+                        spread(1, 1, 1.0 / 16.0);
+                        color::RGB(level, level, level)
+                    }
+                };
+                self.write_pixel(pixel_pos.cast().unwrap(), c);
+            }
         }
         mxcfb_rect {
             top: pos.y as u32,
@@ -37,6 +225,93 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         }
     }
 
+    #[cfg(feature = "image")]
+    fn draw_image_transformed(
+        &mut self,
+        img: &RgbImage,
+        transform: Matrix3<f32>,
+        interpolation: framebuffer::Interpolation,
+    ) -> mxcfb_rect {
+        let inverse = match transform.invert() {
+            Some(inverse) if img.width() > 0 && img.height() > 0 => inverse,
+            _ => return mxcfb_rect::invalid(),
+        };
+        let apply = |m: &Matrix3<f32>, x: f32, y: f32| {
+            let p = m * Vector3 { x, y, z: 1.0 };
+            Point2 {
+                x: p.x / p.z,
+                y: p.y / p.z,
+            }
+        };
+
+        // Only the framebuffer pixels the corners of the image enclose
+        let (w, h) = (img.width() as f32, img.height() as f32);
+        let corners =
+            [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)].map(|(x, y)| apply(&transform, x, y));
+        let min =
+            |coord: fn(&Point2<f32>) -> f32| corners.iter().map(coord).fold(f32::MAX, f32::min);
+        let max =
+            |coord: fn(&Point2<f32>) -> f32| corners.iter().map(coord).fold(f32::MIN, f32::max);
+        // Rounding errors of rotations shouldn't reach into the next pixel
+        const SLACK: f32 = 1e-3;
+        let left = (min(|p| p.x) + SLACK).floor().max(0.0);
+        let top = (min(|p| p.y) + SLACK).floor().max(0.0);
+        let right = (max(|p| p.x) - SLACK)
+            .ceil()
+            .min(self.screen_size().x as f32);
+        let bottom = (max(|p| p.y) - SLACK)
+            .ceil()
+            .min(self.screen_size().y as f32);
+        if right <= left || bottom <= top {
+            return mxcfb_rect::invalid();
+        }
+        let (left, top, right, bottom) = (left as u32, top as u32, right as u32, bottom as u32);
+
+        let pixel = |x: i64, y: i64| {
+            let x = x.clamp(0, i64::from(img.width()) - 1) as u32;
+            let y = y.clamp(0, i64::from(img.height()) - 1) as u32;
+            img.get_pixel(x, y).0.map(f32::from)
+        };
+        for y in top..bottom {
+            for x in left..right {
+                let at = apply(&inverse, x as f32 + 0.5, y as f32 + 0.5);
+                if at.x < 0.0 || at.y < 0.0 || at.x >= w || at.y >= h {
+                    continue;
+                }
+                let rgb = match interpolation {
+                    framebuffer::Interpolation::Nearest => pixel(at.x as i64, at.y as i64),
+                    framebuffer::Interpolation::Bilinear => {
+                        // Between the centers of the four closest pixels
+                        let (sx, sy) = (at.x - 0.5, at.y - 0.5);
+                        let (x0, y0) = (sx.floor(), sy.floor());
+                        let (fx, fy) = (sx - x0, sy - y0);
+                        let (x0, y0) = (x0 as i64, y0 as i64);
+                        let (a, b) = (pixel(x0, y0), pixel(x0 + 1, y0));
+                        let (c, d) = (pixel(x0, y0 + 1), pixel(x0 + 1, y0 + 1));
+                        [0, 1, 2].map(|i| {
+                            (a[i] * (1.0 - fx) + b[i] * fx) * (1.0 - fy)
+                                + (c[i] * (1.0 - fx) + d[i] * fx) * fy
+                        })
+                    }
+                };
+                let [r, g, b] = rgb.map(|c| c.round() as u8);
+                self.write_pixel(
+                    Point2 {
+                        x: x as i32,
+                        y: y as i32,
+                    },
+                    color::RGB(r, g, b),
+                );
+            }
+        }
+        mxcfb_rect {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
     fn draw_line(
         &mut self,
         start: Point2<i32>,
@@ -59,6 +334,42 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         graphics::stamp_along_line(stamp, start, end).expand(margin)
     }
 
+    fn draw_line_aa(
+        &mut self,
+        start: Point2<f32>,
+        end: Point2<f32>,
+        width: f32,
+        v: color,
+    ) -> mxcfb_rect {
+        let rgb = v.to_rgb8();
+        let plot = &mut |p: Point2<i32>, coverage: f32| {
+            if coverage >= 1.0 {
+                return self.write_pixel(p, v);
+            }
+            if p.x < 0 || p.y < 0 {
+                return;
+            }
+            let under = self.read_pixel(p.cast().unwrap()).to_rgb8();
+            let blend = |c: usize| {
+                (f32::from(under[c]) * (1.0 - coverage) + f32::from(rgb[c]) * coverage).round()
+                    as u8
+            };
+            self.write_pixel(p, color::RGB(blend(0), blend(1), blend(2)));
+        };
+        graphics::stamp_along_line_aa(plot, start, end, width)
+    }
+
+    fn draw_line_styled(
+        &mut self,
+        start: Point2<i32>,
+        end: Point2<i32>,
+        width: u32,
+        v: color,
+        style: &framebuffer::StrokeStyle,
+    ) -> mxcfb_rect {
+        draw_dashes(self, start, end, width, v, style, 0.0)
+    }
+
     fn draw_polygon(&mut self, points: &[cgmath::Point2<i32>], fill: bool, c: color) -> mxcfb_rect {
         if fill {
             graphics::fill_polygon(&mut |p| self.write_pixel(p, c), points)
@@ -74,6 +385,31 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         }
     }
 
+    fn fill_polygon(
+        &mut self,
+        points: &[cgmath::Point2<i32>],
+        fill: impl Into<framebuffer::Fill>,
+        rule: framebuffer::FillRule,
+    ) -> mxcfb_rect {
+        let fill = fill.into();
+        graphics::fill_polygon_with_rule(
+            &mut |p| self.write_pixel(p, fill.color_at(p)),
+            points,
+            rule,
+        )
+    }
+
+    fn draw_polyline(
+        &mut self,
+        points: &[Point2<f32>],
+        width: f32,
+        join: framebuffer::LineJoin,
+        cap: framebuffer::LineCap,
+        v: color,
+    ) -> mxcfb_rect {
+        graphics::stroke_polyline(&mut |p| self.write_pixel(p, v), points, width, join, cap)
+    }
+
     fn draw_circle(&mut self, pos: cgmath::Point2<i32>, rad: u32, v: color) -> mxcfb_rect {
         for (x, y) in line_drawing::BresenhamCircle::new(pos.x, pos.y, rad as i32) {
             self.write_pixel(Point2 { x, y }, v);
@@ -86,7 +422,51 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         }
     }
 
-    fn fill_circle(&mut self, pos: cgmath::Point2<i32>, rad: u32, v: color) -> mxcfb_rect {
+    fn draw_arc(
+        &mut self,
+        pos: Point2<i32>,
+        rad: u32,
+        start_angle: f32,
+        end_angle: f32,
+        width: u32,
+        v: color,
+    ) -> mxcfb_rect {
+        let half = width.max(1) as f32 / 2.0;
+        graphics::fill_ring_sector(
+            &mut |p| self.write_pixel(p, v),
+            pos,
+            rad as f32 - half,
+            rad as f32 + half,
+            start_angle,
+            end_angle,
+        )
+    }
+
+    fn fill_pie(
+        &mut self,
+        pos: Point2<i32>,
+        rad: u32,
+        start_angle: f32,
+        end_angle: f32,
+        v: color,
+    ) -> mxcfb_rect {
+        graphics::fill_ring_sector(
+            &mut |p| self.write_pixel(p, v),
+            pos,
+            0.0,
+            rad as f32,
+            start_angle,
+            end_angle,
+        )
+    }
+
+    fn fill_circle(
+        &mut self,
+        pos: cgmath::Point2<i32>,
+        rad: u32,
+        fill: impl Into<framebuffer::Fill>,
+    ) -> mxcfb_rect {
+        let fill = fill.into();
         let rad_square = (rad * rad) as i32;
         let search_distance: i32 = (rad + 1) as i32;
         for y in (-search_distance)..search_distance {
@@ -94,7 +474,8 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
             for x in (-search_distance)..search_distance {
                 let x_square = x * x;
                 if x_square + y_square <= rad_square {
-                    self.write_pixel(pos + Vector2 { x, y }, v);
+                    let p = pos + Vector2 { x, y };
+                    self.write_pixel(p, fill.color_at(p));
                 }
             }
         }
@@ -141,6 +522,15 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         )
     }
 
+    fn draw_bezier_variable_width(
+        &mut self,
+        points: &[Point2<f32>],
+        widths: (f32, f32),
+        v: color,
+    ) -> mxcfb_rect {
+        graphics::fill_bezier_ribbon(&mut |p| self.write_pixel(p, v), points, widths)
+    }
+
     #[cfg(feature = "framebuffer-text-drawing")]
     fn draw_text(
         &mut self,
@@ -166,7 +556,7 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         let c3 = f32::from(255 - components[2]);
 
         // Loop through the glyphs in the text, positing each one on a line
-        for glyph in DEFAULT_FONT.layout(text, scale, start) {
+        for (font, glyph) in self.fonts().layout_indexed(&shaped(text), scale, start) {
             if let Some(bounding_box) = glyph.pixel_bounding_box() {
                 // Draw the glyph into the image per-pixel by using the draw closure
                 let bbmax_y = bounding_box.max.y as u32;
@@ -190,19 +580,29 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
                     continue;
                 }
 
-                glyph.draw(|x, y, v| {
+                // Rasterized before if the same glyph was drawn at the same size
+                let bitmap = match self.glyph_cache.rasterize(font, &glyph) {
+                    Some(bitmap) => bitmap,
+                    None => continue,
+                };
+                let origin = Point2 {
+                    x: glyph.position().x.floor() as i32 + bitmap.offset.0,
+                    y: glyph.position().y.floor() as i32 + bitmap.offset.1,
+                };
+                for (i, &v) in bitmap.coverage.iter().enumerate() {
                     self.write_pixel(
-                        Point2 {
-                            x: (x + bounding_box.min.x as u32) as i32,
-                            y: (y + bounding_box.min.y as u32) as i32,
-                        },
+                        origin
+                            + Vector2 {
+                                x: (i % bitmap.width) as i32,
+                                y: (i / bitmap.width) as i32,
+                            },
                         color::RGB(
-                            (255.0 + (c1 - 255.0) * v) as u8,
-                            (255.0 + (c2 - 255.0) * v) as u8,
-                            (255.0 + (c3 - 255.0) * v) as u8,
+                            (255.0 - c1 * v) as u8,
+                            (255.0 - c2 * v) as u8,
+                            (255.0 - c3 * v) as u8,
                         ),
                     )
-                });
+                }
             }
         }
 
@@ -215,6 +615,195 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         }
     }
 
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn draw_spans(
+        &mut self,
+        pos: Point2<f32>,
+        width: f32,
+        spans: &[TextSpan],
+        dryrun: bool,
+    ) -> mxcfb_rect {
+        let (runs, lines) = layout_spans(spans, width);
+        let mut baselines = Vec::with_capacity(lines.len());
+        let mut y = pos.y;
+        for (ascent, descent) in &lines {
+            baselines.push(y + ascent);
+            y += ascent + descent;
+        }
+        let used = runs.iter().map(|r| r.x + r.width).fold(0.0f32, f32::max);
+        let rect = mxcfb_rect {
+            left: pos.x.floor().max(0.0) as u32,
+            top: pos.y.floor().max(0.0) as u32,
+            width: (pos.x + used).ceil().max(0.0) as u32 - pos.x.floor().max(0.0) as u32,
+            height: y.ceil().max(0.0) as u32 - pos.y.floor().max(0.0) as u32,
+        };
+        if dryrun {
+            return rect;
+        }
+
+        for run in &runs {
+            let span = &spans[run.span];
+            let (x, baseline) = (pos.x + run.x, baselines[run.line]);
+            let mut coverage: std::collections::HashMap<(i32, i32), f32> =
+                std::collections::HashMap::new();
+            let bold = span.emboldening() as i32;
+            for glyph in span
+                .font
+                .layout(run.text, Scale::uniform(span.size), point(x, baseline))
+            {
+                if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                    glyph.draw(|gx, gy, v| {
+                        for dx in 0..=bold {
+                            let covered = coverage
+                                .entry((
+                                    gx as i32 + bounding_box.min.x + dx,
+                                    gy as i32 + bounding_box.min.y,
+                                ))
+                                .or_insert(0.0);
+                            *covered = covered.max(v);
+                        }
+                    });
+                }
+            }
+            let rgb = span.color.to_rgb8();
+            for ((px, py), v) in coverage {
+                if px < 0 || py < 0 {
+                    continue;
+                }
+                let under = self
+                    .read_pixel(Point2 {
+                        x: px as u32,
+                        y: py as u32,
+                    })
+                    .to_rgb8();
+                let blend = |c: usize| {
+                    (f32::from(under[c]) * (1.0 - v) + f32::from(rgb[c]) * v).round() as u8
+                };
+                self.write_pixel(
+                    Point2 { x: px, y: py },
+                    color::RGB(blend(0), blend(1), blend(2)),
+                );
+            }
+
+            // Lines through the runs, spaces included, so they don't break between words
+            let thickness = (span.size / 16.0).round().max(1.0);
+            let mut line_at = |offset: f32| {
+                self.fill_rect(
+                    Point2 {
+                        x: x.round() as i32,
+                        y: (baseline + offset).round() as i32,
+                    },
+                    Vector2 {
+                        x: run.width.round() as u32,
+                        y: thickness as u32,
+                    },
+                    span.color,
+                )
+            };
+            if span.underline {
+                line_at(span.size * 0.1);
+            }
+            if span.strikethrough {
+                line_at(-span.size * 0.3);
+            }
+        }
+        rect
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn draw_text_styled(
+        &mut self,
+        pos: Point2<f32>,
+        text: &str,
+        size: f32,
+        style: framebuffer::TextStyle,
+        dryrun: bool,
+    ) -> mxcfb_rect {
+        let (fill, outline, width) = match style {
+            framebuffer::TextStyle::Fill(col) => {
+                return self.draw_text(pos, text, size, col, dryrun)
+            }
+            framebuffer::TextStyle::Outline { color, width } => (None, color, width),
+            framebuffer::TextStyle::FilledOutline {
+                fill,
+                outline,
+                width,
+            } => (Some(fill), outline, width),
+        };
+        let text_rect = self.draw_text(pos, text, size, color::WHITE, true);
+        // The outline goes around the text
+        let pad = width as i32 + 1;
+        let left = text_rect.left as i32 - pad;
+        let top = text_rect.top as i32 - pad;
+        let columns = (text_rect.width as i32 + 2 * pad) as usize;
+        let rows = (text_rect.height as i32 + 2 * pad) as usize;
+        let rect = mxcfb_rect {
+            left: left.max(0) as u32,
+            top: top.max(0) as u32,
+            width: (left + columns as i32 - left.max(0)) as u32,
+            height: (top + rows as i32 - top.max(0)) as u32,
+        };
+        if dryrun {
+            return rect;
+        }
+
+        let mut coverage = vec![0.0f32; columns * rows];
+        let scale = Scale::uniform(size);
+        for glyph in self
+            .fonts()
+            .layout(&shaped(text), scale, point(pos.x, pos.y))
+        {
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                glyph.draw(|x, y, v| {
+                    let x = x as i32 + bounding_box.min.x - left;
+                    let y = y as i32 + bounding_box.min.y - top;
+                    if x >= 0 && y >= 0 && (x as usize) < columns && (y as usize) < rows {
+                        let covered = &mut coverage[y as usize * columns + x as usize];
+                        *covered = covered.max(v);
+                    }
+                });
+            }
+        }
+        let inside = |x: i32, y: i32| {
+            x >= 0
+                && y >= 0
+                && (x as usize) < columns
+                && (y as usize) < rows
+                && coverage[y as usize * columns + x as usize] >= 0.5
+        };
+        let reach = width as i32;
+        let near_inside = |x: i32, y: i32| {
+            (-reach..=reach).any(|dy| {
+                (-reach..=reach)
+                    .any(|dx| dx * dx + dy * dy <= reach * reach && inside(x + dx, y + dy))
+            })
+        };
+
+        let fill = fill.map(color::to_rgb8);
+        for y in 0..rows as i32 {
+            for x in 0..columns as i32 {
+                let p = Point2 {
+                    x: left + x,
+                    y: top + y,
+                };
+                if !inside(x, y) && near_inside(x, y) {
+                    self.write_pixel(p, outline);
+                } else if let Some(rgb) = fill {
+                    // Blended onto white like `draw_text`
+                    let v = coverage[y as usize * columns + x as usize];
+                    if v > 0.0 {
+                        let blend = |c: u8| (255.0 - f32::from(255 - c) * v) as u8;
+                        self.write_pixel(
+                            p,
+                            color::RGB(blend(rgb[0]), blend(rgb[1]), blend(rgb[2])),
+                        );
+                    }
+                }
+            }
+        }
+        rect
+    }
+
     fn draw_rect(&mut self, pos: Point2<i32>, size: Vector2<u32>, border_px: u32, c: color) {
         let top_left = pos;
         let top_right = pos + vec2(size.x as i32, 0);
@@ -234,15 +823,76 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         self.draw_line(bottom_left, bottom_right, border_px, c);
     }
 
-    fn fill_rect(&mut self, pos: Point2<i32>, size: Vector2<u32>, c: color) {
+    fn draw_rect_styled(
+        &mut self,
+        pos: Point2<i32>,
+        size: Vector2<u32>,
+        border_px: u32,
+        c: color,
+        style: &framebuffer::StrokeStyle,
+    ) -> mxcfb_rect {
+        let corners = [
+            pos,
+            pos + vec2(size.x as i32, 0),
+            pos + size.cast().unwrap(),
+            pos + vec2(0, size.y as i32),
+        ];
+        let mut rect = mxcfb_rect::invalid();
+        let mut phase = 0.0;
+        for i in 0..4 {
+            let (start, end) = (corners[i], corners[(i + 1) % 4]);
+            rect = rect.merge_rect(&draw_dashes(self, start, end, border_px, c, style, phase));
+            phase += (end - start).cast::<f32>().unwrap().magnitude();
+        }
+        rect
+    }
+
+    fn draw_rounded_rect(
+        &mut self,
+        pos: Point2<i32>,
+        size: Vector2<u32>,
+        radii: framebuffer::CornerRadii,
+        border_px: u32,
+        c: color,
+    ) -> mxcfb_rect {
+        graphics::fill_rounded_rect(
+            &mut |p| self.write_pixel(p, c),
+            pos,
+            size,
+            radii,
+            Some(border_px.max(1)),
+        )
+    }
+
+    fn fill_rounded_rect(
+        &mut self,
+        pos: Point2<i32>,
+        size: Vector2<u32>,
+        radii: framebuffer::CornerRadii,
+        c: color,
+    ) -> mxcfb_rect {
+        graphics::fill_rounded_rect(&mut |p| self.write_pixel(p, c), pos, size, radii, None)
+    }
+
+    fn fill_rect(
+        &mut self,
+        pos: Point2<i32>,
+        size: Vector2<u32>,
+        fill: impl Into<framebuffer::Fill>,
+    ) {
+        let fill = fill.into();
         for ypos in pos.y..pos.y + size.y as i32 {
             for xpos in pos.x..pos.x + size.x as i32 {
-                self.write_pixel(Point2::new(xpos, ypos), c);
+                let p = Point2::new(xpos, ypos);
+                self.write_pixel(p, fill.color_at(p));
             }
         }
     }
 
     fn clear(&mut self) {
+        if let Some(clip) = self.clip() {
+            return self.fill_rect(clip.top_left().cast().unwrap(), clip.size(), color::WHITE);
+        }
         let h = self.var_screen_info.yres as usize;
         let line_length = self.fix_screen_info.line_length as usize;
         unsafe {
@@ -254,3 +904,59 @@ impl framebuffer::FramebufferDraw for core::Framebuffer {
         }
     }
 }
+
+/// Draws the dashes of `style` on the line from `start` to `end`, with the pattern
+/// starting `phase` pixels in
+fn draw_dashes(
+    fb: &mut core::Framebuffer,
+    start: Point2<i32>,
+    end: Point2<i32>,
+    width: u32,
+    v: color,
+    style: &framebuffer::StrokeStyle,
+    phase: f32,
+) -> mxcfb_rect {
+    use crate::framebuffer::{FillRule, FramebufferDraw, LineCap};
+
+    let (from, to) = (start.cast::<f32>().unwrap(), end.cast::<f32>().unwrap());
+    let length = (to - from).magnitude();
+    let direction = match length > 0.0 {
+        true => (to - from) / length,
+        false => vec2(1.0, 0.0),
+    };
+    let half = width as f32 / 2.0;
+    let normal = vec2(-direction.y, direction.x) * half;
+
+    let mut rect = mxcfb_rect::invalid();
+    for (a, b) in graphics::dash_runs(style.dash, phase, length) {
+        let dash = match (width, style.cap) {
+            // Too thin for the caps to show
+            (0 | 1, _) => fb.draw_line(
+                (from + direction * a).map(f32::round).cast().unwrap(),
+                (from + direction * b).map(f32::round).cast().unwrap(),
+                1,
+                v,
+            ),
+            (_, LineCap::Round) => fb.draw_bezier_variable_width(
+                &[from + direction * a, from + direction * b],
+                (width as f32, width as f32),
+                v,
+            ),
+            (_, cap) => {
+                let extend = match cap {
+                    LineCap::Square => half,
+                    _ => 0.0,
+                };
+                let (a, b) = (
+                    from + direction * (a - extend),
+                    from + direction * (b + extend),
+                );
+                let corners = [a + normal, b + normal, b - normal, a - normal]
+                    .map(|p| p.map(f32::round).cast().unwrap());
+                fb.fill_polygon(&corners, v, FillRule::NonZero)
+            }
+        };
+        rect = rect.merge_rect(&dash);
+    }
+    rect
+}
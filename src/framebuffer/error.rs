@@ -0,0 +1,43 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Why setting up or talking to the framebuffer failed
+#[derive(Debug, Error)]
+pub enum FramebufferError {
+    #[error("Failed to determine the device model: {0}")]
+    Model(#[from] crate::device::ErrorKind),
+    #[error("Failed to open framebuffer {path:?}")]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{request} ioctl failed")]
+    Ioctl {
+        request: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to map the framebuffer memory")]
+    Map(#[source] io::Error),
+    /// rm2fb is only available on the reMarkable 2
+    #[error("rm2fb is not supported on the {0}")]
+    Unsupported(crate::device::Model),
+    #[error("Failed to connect to the rm2fb server")]
+    Swtfb(#[source] io::Error),
+    #[error("Failed to connect to the display server")]
+    Shm(#[source] io::Error),
+}
+
+#[cfg(feature = "framebuffer")]
+impl FramebufferError {
+    /// The last OS error as the failure of `request`
+    pub(crate) fn ioctl(request: &'static str) -> FramebufferError {
+        FramebufferError::Ioctl {
+            request,
+            source: io::Error::last_os_error(),
+        }
+    }
+}
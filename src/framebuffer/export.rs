@@ -0,0 +1,112 @@
+//! Screenshots of the framebuffer as PNG images, e.g. to send to a companion app or
+//! attach to a bug report.
+
+use image::codecs::png::PngEncoder;
+use image::ColorType;
+
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core;
+use crate::framebuffer::golden;
+
+/// Why exporting a screenshot failed
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Failed to read the framebuffer: {0}")]
+    Read(&'static str),
+    #[error("Failed to encode the PNG image")]
+    Encode(#[from] image::ImageError),
+}
+
+impl core::Framebuffer {
+    /// `rect` of the framebuffer as a PNG image
+    pub fn export_region_png(&self, rect: mxcfb_rect) -> Result<Vec<u8>, ExportError> {
+        let img = golden::capture(self, rect).map_err(ExportError::Read)?;
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).encode(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            ColorType::Rgb8,
+        )?;
+        Ok(png)
+    }
+
+    /// The whole framebuffer as a PNG image
+    pub fn export_png(&self) -> Result<Vec<u8>, ExportError> {
+        self.export_region_png(mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: self.var_screen_info.xres,
+            height: self.var_screen_info.yres,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::{Point2, Vector2};
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::FramebufferDraw;
+
+    #[test]
+    fn test_export_png() {
+        let mut fb = core::Framebuffer::headless(60, 40);
+        fb.fill_rect(
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 60, y: 40 },
+            color::WHITE,
+        );
+        fb.fill_rect(
+            Point2 { x: 10, y: 10 },
+            Vector2 { x: 5, y: 5 },
+            color::BLACK,
+        );
+
+        let png = fb.export_png().unwrap();
+        let img = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (60, 40));
+        assert_eq!(img.get_pixel(12, 12).0, [0, 0, 0]);
+        assert_eq!(img.get_pixel(20, 12).0, [255, 255, 255]);
+
+        let rect = mxcfb_rect {
+            top: 8,
+            left: 8,
+            width: 4,
+            height: 3,
+        };
+        let region = image::load_from_memory(&fb.export_region_png(rect).unwrap())
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(region.dimensions(), (4, 3));
+        assert_eq!(region.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(region.get_pixel(3, 2).0, [0, 0, 0]);
+
+        let outside = mxcfb_rect {
+            top: 30,
+            left: 0,
+            width: 60,
+            height: 20,
+        };
+        assert!(matches!(
+            fb.export_region_png(outside),
+            Err(ExportError::Read(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_color_png() {
+        let mut fb = core::Framebuffer::headless_color(30, 20);
+        fb.fill_rect(
+            Point2 { x: 5, y: 5 },
+            Vector2 { x: 5, y: 5 },
+            color::RGB(200, 30, 10),
+        );
+
+        let png = fb.export_png().unwrap();
+        let img = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (30, 20));
+        assert_eq!(img.get_pixel(7, 7).0, [200, 30, 10]);
+        assert_eq!(img.get_pixel(29, 19).0, [255, 255, 255]);
+    }
+}
@@ -0,0 +1,309 @@
+//! Fonts loaded at runtime, with fallbacks for what the first one lacks.
+//!
+//! A `FontCollection` is a chain of fonts, e.g. one for Latin, one for CJK and one
+//! for emoji. Every character is drawn with the first font in the chain that has a
+//! glyph for it, so text in any script renders instead of empty boxes as long as one
+//! of them covers it. Set it on a framebuffer with `Framebuffer::set_fonts` for
+//! `draw_text` and everything built on it, like `ApplicationContext::display_text`.
+//!
+//! The framebuffer keeps the glyphs it rasterized in a `GlyphCache`, so redrawing the
+//! same text, like a menu, doesn't rasterize it all over again.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use rusttype::{point, Font, Point, PositionedGlyph, Scale};
+
+use crate::framebuffer::draw::DEFAULT_FONT;
+
+/// Only the font built into the crate
+pub static DEFAULT_FONTS: Lazy<FontCollection> = Lazy::new(FontCollection::builtin);
+
+/// Why loading a font failed
+#[derive(Debug, thiserror::Error)]
+pub enum FontError {
+    #[error("Failed to read the font file")]
+    Io(#[from] io::Error),
+    #[error("Not a TrueType or OpenType font")]
+    Invalid,
+}
+
+/// How many glyphs the cache holds before it starts over
+pub const GLYPH_CACHE_CAPACITY: usize = 2048;
+
+/// Glyphs closer than this fraction of a pixel to the position within the pixel of a
+/// cached one are drawn like it
+const SUBPIXEL_STEPS: f32 = 64.0;
+
+/// Identifies a rasterized glyph: the font by its position in the collection, the
+/// size and the glyph, and the position within the pixel, which changes how the
+/// edges are covered
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: usize,
+    size: (u32, u32),
+    id: u16,
+    subpixel: (u8, u8),
+}
+
+/// The coverage of the pixels of a rasterized glyph
+#[derive(Debug)]
+pub(crate) struct GlyphBitmap {
+    /// Of the top left pixel, from the pixel the glyph is positioned in
+    pub offset: (i32, i32),
+    pub width: usize,
+    /// From 0.0 to 1.0, in rows
+    pub coverage: Vec<f32>,
+}
+
+/// Glyphs rasterized before, see `Framebuffer::clear_glyph_cache`
+#[derive(Default)]
+pub(crate) struct GlyphCache {
+    glyphs: HashMap<GlyphKey, Arc<GlyphBitmap>>,
+}
+
+impl GlyphCache {
+    /// The bitmap of `glyph` of the `font`th font, rasterized unless it is cached.
+    /// `None` for glyphs without any pixels, like spaces.
+    pub fn rasterize(
+        &mut self,
+        font: usize,
+        glyph: &PositionedGlyph<'static>,
+    ) -> Option<Arc<GlyphBitmap>> {
+        let position = glyph.position();
+        let scale = glyph.scale();
+        let key = GlyphKey {
+            font,
+            size: (scale.x.to_bits(), scale.y.to_bits()),
+            id: glyph.id().0,
+            subpixel: (
+                ((position.x - position.x.floor()) * SUBPIXEL_STEPS) as u8,
+                ((position.y - position.y.floor()) * SUBPIXEL_STEPS) as u8,
+            ),
+        };
+        if let Some(bitmap) = self.glyphs.get(&key) {
+            return Some(bitmap.clone());
+        }
+
+        let bounding_box = glyph.pixel_bounding_box()?;
+        let width = bounding_box.width() as usize;
+        let mut coverage = vec![0.0; width * bounding_box.height() as usize];
+        glyph.draw(|x, y, v| coverage[y as usize * width + x as usize] = v);
+        let bitmap = Arc::new(GlyphBitmap {
+            offset: (
+                bounding_box.min.x - position.x.floor() as i32,
+                bounding_box.min.y - position.y.floor() as i32,
+            ),
+            width,
+            coverage,
+        });
+        if self.glyphs.len() >= GLYPH_CACHE_CAPACITY {
+            self.glyphs.clear();
+        }
+        self.glyphs.insert(key, bitmap.clone());
+        Some(bitmap)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.glyphs.clear();
+    }
+}
+
+/// Fonts tried in order for every character
+#[derive(Clone, Default)]
+pub struct FontCollection {
+    fonts: Vec<Font<'static>>,
+}
+
+impl FontCollection {
+    /// Without any fonts, so nothing is drawn until some are added
+    pub fn new() -> FontCollection {
+        FontCollection::default()
+    }
+
+    /// With the font built into the crate, to add fallbacks to
+    pub fn builtin() -> FontCollection {
+        FontCollection {
+            fonts: vec![DEFAULT_FONT.clone()],
+        }
+    }
+
+    /// Adds `font` to the end of the chain
+    pub fn push(&mut self, font: Font<'static>) {
+        self.fonts.push(font);
+    }
+
+    /// Adds the TrueType or OpenType font in the file at `path` to the end of the chain
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), FontError> {
+        self.load_bytes(std::fs::read(path)?)
+    }
+
+    /// Adds the TrueType or OpenType font in `data` to the end of the chain
+    pub fn load_bytes(&mut self, data: Vec<u8>) -> Result<(), FontError> {
+        self.push(Font::try_from_vec(data).ok_or(FontError::Invalid)?);
+        Ok(())
+    }
+
+    pub fn fonts(&self) -> &[Font<'static>] {
+        &self.fonts
+    }
+
+    /// The position in the chain of the first font with a glyph for `c`, or of the
+    /// first font if none has one
+    fn index_for(&self, c: char) -> Option<usize> {
+        if self.fonts.is_empty() {
+            return None;
+        }
+        Some(
+            self.fonts
+                .iter()
+                .position(|font| font.glyph(c).id().0 != 0)
+                .unwrap_or(0),
+        )
+    }
+
+    /// The font `c` is drawn with
+    pub fn font_for(&self, c: char) -> Option<&Font<'static>> {
+        self.index_for(c).map(|i| &self.fonts[i])
+    }
+
+    /// Lays out `text` on a single line like `Font::layout`, with the baseline starting
+    /// at `start`, every character in its own font
+    pub fn layout(
+        &self,
+        text: &str,
+        scale: Scale,
+        start: Point<f32>,
+    ) -> Vec<PositionedGlyph<'static>> {
+        self.layout_indexed(text, scale, start)
+            .into_iter()
+            .map(|(_, glyph)| glyph)
+            .collect()
+    }
+
+    /// Like `layout`, along with the position in the chain of the font of every glyph
+    pub(crate) fn layout_indexed(
+        &self,
+        text: &str,
+        scale: Scale,
+        start: Point<f32>,
+    ) -> Vec<(usize, PositionedGlyph<'static>)> {
+        let mut glyphs = Vec::new();
+        let mut x = start.x;
+        let mut last = None;
+        for c in text.chars() {
+            let index = match self.index_for(c) {
+                Some(index) => index,
+                None => break,
+            };
+            let font = &self.fonts[index];
+            let glyph = font.glyph(c).scaled(scale);
+            // Only pairs from the same font are kerned
+            if let Some((last_index, last_id)) = last {
+                if last_index == index {
+                    x += font.pair_kerning(scale, last_id, glyph.id());
+                }
+            }
+            last = Some((index, glyph.id()));
+            let advance = glyph.h_metrics().advance_width;
+            glyphs.push((index, glyph.positioned(point(x, start.y))));
+            x += advance;
+        }
+        glyphs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_font_collection() {
+        let scale = Scale::uniform(20.0);
+        assert!(FontCollection::new()
+            .layout("empty", scale, point(0.0, 0.0))
+            .is_empty());
+
+        let mut fonts = FontCollection::builtin();
+        // The same layout as with the font alone, kerning included
+        let alone: Vec<_> = DEFAULT_FONT
+            .layout("AVAV", scale, point(5.0, 30.0))
+            .map(|g| g.position())
+            .collect();
+        let chained: Vec<_> = fonts
+            .layout("AVAV", scale, point(5.0, 30.0))
+            .iter()
+            .map(|g| g.position())
+            .collect();
+        assert_eq!(alone.len(), chained.len());
+        assert!(alone
+            .iter()
+            .zip(&chained)
+            .all(|(a, b)| (a.x - b.x).abs() < 0.01 && a.y == b.y));
+
+        // Nothing has a glyph for it, so it falls back to the first font
+        assert!(fonts.font_for('\u{4e2d}').is_some());
+        assert_eq!(fonts.layout("a\u{4e2d}b", scale, point(0.0, 0.0)).len(), 3);
+
+        assert!(matches!(
+            fonts.load_bytes(b"not a font".to_vec()),
+            Err(FontError::Invalid)
+        ));
+        assert!(matches!(
+            fonts.load("/nonexistent/font.ttf"),
+            Err(FontError::Io(_))
+        ));
+        fonts
+            .load(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/assets/Roboto-Regular.ttf"
+            ))
+            .unwrap();
+        assert_eq!(fonts.fonts().len(), 2);
+    }
+
+    #[test]
+    fn test_glyph_cache() {
+        let fonts = FontCollection::builtin();
+        let mut cache = GlyphCache::default();
+        let scale = Scale::uniform(24.0);
+        let glyphs = fonts.layout_indexed("a b", scale, point(10.3, 40.0));
+        let bitmaps: Vec<_> = glyphs
+            .iter()
+            .map(|(font, glyph)| cache.rasterize(*font, glyph))
+            .collect();
+        // Nothing to draw of the space
+        assert!(bitmaps[0].is_some() && bitmaps[1].is_none() && bitmaps[2].is_some());
+        assert_eq!(cache.len(), 2);
+
+        // The same text elsewhere on whole pixels is all cached
+        let moved = fonts.layout_indexed("a b", scale, point(110.3, 80.0));
+        for ((font, glyph), bitmap) in moved.iter().zip(&bitmaps) {
+            let cached = cache.rasterize(*font, glyph);
+            assert_eq!(cached.is_some(), bitmap.is_some());
+            if let (Some(cached), Some(bitmap)) = (cached, bitmap) {
+                assert!(Arc::ptr_eq(&cached, bitmap));
+            }
+        }
+        assert_eq!(cache.len(), 2);
+
+        // Half a pixel further, or larger, isn't
+        for (font, glyph) in fonts.layout_indexed("a", scale, point(10.8, 40.0)) {
+            cache.rasterize(font, &glyph);
+        }
+        for (font, glyph) in fonts.layout_indexed("a", Scale::uniform(30.0), point(10.3, 40.0)) {
+            cache.rasterize(font, &glyph);
+        }
+        assert_eq!(cache.len(), 4);
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+}
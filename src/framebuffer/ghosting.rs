@@ -0,0 +1,161 @@
+//! Clearing ghosting with periodic full refreshes.
+//!
+//! Every partial refresh leaves a faint trace of what was there before, which adds
+//! up until the display looks smudged. With a `GhostingPolicy` set on a framebuffer,
+//! see `Framebuffer::set_ghosting_policy`, a flashing GC16 refresh of the whole
+//! display follows the partial refresh that crosses its threshold. The callback lets
+//! apps redraw what the flash is not supposed to leave behind, like highlights drawn
+//! with fast waveforms.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// When a full refresh is due
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GhostingThreshold {
+    /// After this many partial refreshes
+    Partials(u32),
+    /// At the first partial refresh once this long passed since the last full one
+    Duration(Duration),
+}
+
+type FullRefreshHandler = Box<dyn Fn(u32) + Send + Sync>;
+
+struct Since {
+    partials: u32,
+    full_refresh: Instant,
+}
+
+/// Issues a full refresh after a threshold of partial ones, see the module
+/// documentation
+pub struct GhostingPolicy {
+    pub threshold: GhostingThreshold,
+    since: Mutex<Since>,
+    on_full_refresh: Option<FullRefreshHandler>,
+}
+
+impl GhostingPolicy {
+    pub fn new(threshold: GhostingThreshold) -> GhostingPolicy {
+        GhostingPolicy {
+            threshold,
+            since: Mutex::new(Since {
+                partials: 0,
+                full_refresh: Instant::now(),
+            }),
+            on_full_refresh: None,
+        }
+    }
+
+    /// A full refresh after every `n` partial refreshes
+    pub fn every_n_partials(n: u32) -> GhostingPolicy {
+        GhostingPolicy::new(GhostingThreshold::Partials(n))
+    }
+
+    /// A full refresh with the first partial refresh after `duration`
+    pub fn every_duration(duration: Duration) -> GhostingPolicy {
+        GhostingPolicy::new(GhostingThreshold::Duration(duration))
+    }
+
+    /// Calls `on_full_refresh` with the marker of every full refresh the policy
+    /// issues, from the thread that refreshed
+    pub fn on_full_refresh(
+        mut self,
+        on_full_refresh: impl Fn(u32) + Send + Sync + 'static,
+    ) -> GhostingPolicy {
+        self.on_full_refresh = Some(Box::new(on_full_refresh));
+        self
+    }
+
+    /// How many partial refreshes were made since the last full one
+    pub fn partials(&self) -> u32 {
+        self.since.lock().unwrap().partials
+    }
+
+    /// Counts a partial refresh. Returns whether a full refresh is due now.
+    pub(crate) fn partial_refreshed(&self) -> bool {
+        let mut since = self.since.lock().unwrap();
+        since.partials += 1;
+        match self.threshold {
+            GhostingThreshold::Partials(n) => since.partials >= n,
+            GhostingThreshold::Duration(duration) => since.full_refresh.elapsed() >= duration,
+        }
+    }
+
+    /// Starts counting again, whoever issued the full refresh
+    pub(crate) fn full_refreshed(&self) {
+        *self.since.lock().unwrap() = Since {
+            partials: 0,
+            full_refresh: Instant::now(),
+        };
+    }
+
+    pub(crate) fn notify(&self, marker: u32) {
+        if let Some(on_full_refresh) = &self.on_full_refresh {
+            on_full_refresh(marker);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::common::{mxcfb_rect, update_mode, waveform_mode};
+    use crate::framebuffer::core::Framebuffer;
+    use crate::framebuffer::{FramebufferRefresh, PartialRefreshMode, RefreshProfile};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_ghosting_policy() {
+        let flashes = Arc::new(Mutex::new(Vec::new()));
+        let reported = flashes.clone();
+        let mut fb = Framebuffer::headless(100, 100);
+        fb.set_ghosting_policy(Some(
+            GhostingPolicy::every_n_partials(3)
+                .on_full_refresh(move |marker| reported.lock().unwrap().push(marker)),
+        ));
+        let region = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 10,
+            height: 10,
+        };
+        let refresh = |fb: &Framebuffer, mode| {
+            fb.partial_refresh_with(&region, mode, &RefreshProfile::INK, false)
+        };
+
+        for _ in 0..7 {
+            refresh(&fb, PartialRefreshMode::Async);
+        }
+        // Collision tests don't count
+        refresh(&fb, PartialRefreshMode::DryRun);
+        let full = |refreshes: &[crate::framebuffer::mxcfb::mxcfb_update_data]| {
+            refreshes
+                .iter()
+                .filter(|r| r.update_mode == update_mode::UPDATE_MODE_FULL as u32)
+                .map(|r| r.update_marker)
+                .collect::<Vec<_>>()
+        };
+        let refreshes = fb.take_refreshes();
+        assert_eq!(refreshes.len(), 10);
+        assert_eq!(
+            refreshes[3].waveform_mode,
+            waveform_mode::WAVEFORM_MODE_GC16 as u32
+        );
+        assert_eq!(full(&refreshes), *flashes.lock().unwrap());
+        assert_eq!(flashes.lock().unwrap().len(), 2);
+
+        // Any full refresh starts counting again
+        refresh(&fb, PartialRefreshMode::Async);
+        fb.full_refresh_with(&RefreshProfile::IMAGE, false);
+        refresh(&fb, PartialRefreshMode::Async);
+        refresh(&fb, PartialRefreshMode::Async);
+        assert_eq!(full(&fb.take_refreshes()).len(), 1);
+        assert_eq!(flashes.lock().unwrap().len(), 2);
+
+        let policy = GhostingPolicy::every_duration(Duration::from_millis(50));
+        assert!(!policy.partial_refreshed());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(policy.partial_refreshed());
+        assert_eq!(policy.partials(), 2);
+    }
+}
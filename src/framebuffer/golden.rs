@@ -0,0 +1,298 @@
+//! Golden image testing.
+//!
+//! Draw into a headless framebuffer (`core::Framebuffer::headless`), then compare a
+//! region of it against a reference PNG with `Golden::assert_matches`. When the
+//! comparison fails, the actual image and a diff (mismatching pixels in red) are
+//! written to the artifacts directory so the failure can be inspected.
+//!
+//! References are (re)generated by running the tests with the environment variable
+//! `LIBREMARKABLE_BLESS=1`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use image::{Rgb, RgbImage};
+use log::warn;
+
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::FramebufferIO;
+
+/// Environment variable that turns comparisons into reference updates
+pub const BLESS_VAR: &str = "LIBREMARKABLE_BLESS";
+
+/// Why a comparison failed
+#[derive(Debug)]
+pub enum GoldenError {
+    /// There is no reference image yet
+    MissingReference(PathBuf),
+    /// The reference and the actual image have different dimensions
+    SizeMismatch((u32, u32), (u32, u32)),
+    /// Too many pixels differ
+    Mismatch {
+        mismatched: usize,
+        total: usize,
+    },
+    Capture(&'static str),
+    Image(image::ImageError),
+}
+
+impl From<image::ImageError> for GoldenError {
+    fn from(err: image::ImageError) -> Self {
+        GoldenError::Image(err)
+    }
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::MissingReference(path) => write!(
+                f,
+                "Missing reference image {}, run with {}=1 to create it",
+                path.display(),
+                BLESS_VAR
+            ),
+            GoldenError::SizeMismatch(expected, actual) => {
+                write!(f, "Expected a {:?} image, got {:?}", expected, actual)
+            }
+            GoldenError::Mismatch { mismatched, total } => {
+                write!(f, "{} of {} pixels differ", mismatched, total)
+            }
+            GoldenError::Capture(err) => write!(f, "Failed to capture the framebuffer: {}", err),
+            GoldenError::Image(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+/// Reads `rect` of the framebuffer into an RGB image
+pub fn capture<F: FramebufferIO + ?Sized>(
+    fb: &F,
+    rect: mxcfb_rect,
+) -> Result<RgbImage, &'static str> {
+    let data = fb.dump_region(rect)?;
+    // One pixel for every one of the rect, in whatever format the framebuffer has
+    let bytespp = data.len() / (rect.width as usize * rect.height as usize);
+    Ok(from_native(
+        rect.width,
+        rect.height,
+        bytespp as u32 * 8,
+        &data,
+    ))
+}
+
+/// Converts pixels in the framebuffer's native format of `bits_per_pixel`, as returned
+/// by `dump_region`, into an RGB image. Missing pixels are white.
+pub fn from_native(width: u32, height: u32, bits_per_pixel: u32, data: &[u8]) -> RgbImage {
+    let bytespp = (bits_per_pixel as usize / 8).max(1);
+    let mut pixels = data.chunks_exact(bytespp).map(|c| match *c {
+        [b, g, r, _] => [r, g, b],
+        [c1, c2] => color::NATIVE_COMPONENTS(c1, c2).to_rgb8(),
+        _ => [0xff; 3],
+    });
+    RgbImage::from_fn(width, height, |_, _| {
+        Rgb(pixels.next().unwrap_or([0xff; 3]))
+    })
+}
+
+/// Compares two images of the same size. Returns the number of pixels that differ
+/// by more than `tolerance` in any channel, along with a diff image.
+pub fn compare(expected: &RgbImage, actual: &RgbImage, tolerance: u8) -> (usize, RgbImage) {
+    let mut mismatched = 0;
+    let diff = RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let differs = (0..3).any(|i| a[i].abs_diff(e[i]) > tolerance);
+        if differs {
+            mismatched += 1;
+            Rgb([0xff, 0, 0])
+        } else {
+            // Faded copy of the image for context
+            let gray = (u16::from(a[0]) + u16::from(a[1]) + u16::from(a[2])) / 3;
+            let faded = (0xff - (0xff - gray) / 4) as u8;
+            Rgb([faded, faded, faded])
+        }
+    });
+    (mismatched, diff)
+}
+
+fn luma(p: &Rgb<u8>) -> i32 {
+    (299 * i32::from(p[0]) + 587 * i32::from(p[1]) + 114 * i32::from(p[2])) / 1000
+}
+
+/// Like `compare`, closer to what is seen on the display: pixels are compared by
+/// brightness, and one that matches a neighbour of its reference pixel only counts as
+/// shifted, as antialiased edges and text often are. In the diff image mismatches are
+/// red, the brighter the more they differ, and shifted pixels blue.
+pub fn perceptual_compare(
+    expected: &RgbImage,
+    actual: &RgbImage,
+    tolerance: u8,
+) -> (usize, RgbImage) {
+    let tolerance = i32::from(tolerance);
+    let mut mismatched = 0;
+    let diff = RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = luma(actual.get_pixel(x, y));
+        let difference = (a - luma(expected.get_pixel(x, y))).abs();
+        if difference <= tolerance {
+            let faded = (0xff - (0xff - a) / 4) as u8;
+            return Rgb([faded, faded, faded]);
+        }
+        let (x, y) = (i64::from(x), i64::from(y));
+        let shifted = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(|&(nx, ny)| {
+                nx >= 0
+                    && ny >= 0
+                    && nx < i64::from(expected.width())
+                    && ny < i64::from(expected.height())
+            })
+            .any(|(nx, ny)| {
+                (a - luma(expected.get_pixel(nx as u32, ny as u32))).abs() <= tolerance
+            });
+        if shifted {
+            Rgb([0x80, 0x80, 0xff])
+        } else {
+            mismatched += 1;
+            Rgb([(0x80 + difference / 2).min(0xff) as u8, 0, 0])
+        }
+    });
+    (mismatched, diff)
+}
+
+/// A directory of reference images and how strictly they are matched
+pub struct Golden {
+    dir: PathBuf,
+    /// Where actual and diff images of failed comparisons are written
+    pub artifacts: PathBuf,
+    /// Per channel difference that is still considered equal
+    pub tolerance: u8,
+    /// Fraction of pixels that may differ
+    pub max_mismatch: f32,
+    /// Compares with `perceptual_compare` instead of `compare`
+    pub perceptual: bool,
+}
+
+impl Golden {
+    /// References are stored as `<name>.png` in `dir`
+    pub fn new(dir: impl AsRef<Path>) -> Golden {
+        Golden {
+            dir: dir.as_ref().to_path_buf(),
+            artifacts: std::env::temp_dir().join("libremarkable-golden"),
+            tolerance: 8,
+            max_mismatch: 0.001,
+            perceptual: false,
+        }
+    }
+
+    pub fn reference_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.png", name))
+    }
+
+    fn bless() -> bool {
+        matches!(std::env::var(BLESS_VAR).as_deref(), Ok("1"))
+    }
+
+    /// Compares `image` against the reference `name`
+    pub fn check_image(&self, name: &str, image: &RgbImage) -> Result<(), GoldenError> {
+        let path = self.reference_path(name);
+        if Golden::bless() {
+            std::fs::create_dir_all(&self.dir).map_err(image::ImageError::IoError)?;
+            image.save(&path)?;
+            return Ok(());
+        }
+        if !path.exists() {
+            self.write_artifacts(name, image, None);
+            return Err(GoldenError::MissingReference(path));
+        }
+        let expected = image::open(&path)?.to_rgb8();
+        if expected.dimensions() != image.dimensions() {
+            self.write_artifacts(name, image, None);
+            return Err(GoldenError::SizeMismatch(
+                expected.dimensions(),
+                image.dimensions(),
+            ));
+        }
+        let (mismatched, diff) = if self.perceptual {
+            perceptual_compare(&expected, image, self.tolerance)
+        } else {
+            compare(&expected, image, self.tolerance)
+        };
+        let total = (image.width() * image.height()) as usize;
+        if mismatched as f32 > total as f32 * self.max_mismatch {
+            self.write_artifacts(name, image, Some(&diff));
+            return Err(GoldenError::Mismatch { mismatched, total });
+        }
+        Ok(())
+    }
+
+    /// Compares `rect` of the framebuffer against the reference `name`
+    pub fn check<F: FramebufferIO + ?Sized>(
+        &self,
+        name: &str,
+        fb: &F,
+        rect: mxcfb_rect,
+    ) -> Result<(), GoldenError> {
+        let image = capture(fb, rect).map_err(GoldenError::Capture)?;
+        self.check_image(name, &image)
+    }
+
+    /// Like `check`, panicking with a description of the failure
+    pub fn assert_matches<F: FramebufferIO + ?Sized>(&self, name: &str, fb: &F, rect: mxcfb_rect) {
+        if let Err(err) = self.check(name, fb, rect) {
+            panic!(
+                "Golden image {} doesn't match: {} (see {})",
+                name,
+                err,
+                self.artifacts.display()
+            );
+        }
+    }
+
+    fn write_artifacts(&self, name: &str, actual: &RgbImage, diff: Option<&RgbImage>) {
+        let result = std::fs::create_dir_all(&self.artifacts)
+            .map_err(image::ImageError::IoError)
+            .and_then(|_| actual.save(self.artifacts.join(format!("{}.actual.png", name))))
+            .and_then(|_| match diff {
+                Some(diff) => diff.save(self.artifacts.join(format!("{}.diff.png", name))),
+                None => Ok(()),
+            });
+        if let Err(err) = result {
+            warn!("Failed to write golden image artifacts: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compare_tolerance() {
+        let expected = RgbImage::from_pixel(4, 4, Rgb([0x80, 0x80, 0x80]));
+        let mut actual = expected.clone();
+        actual.put_pixel(0, 0, Rgb([0x84, 0x80, 0x80]));
+        actual.put_pixel(1, 0, Rgb([0x00, 0x80, 0x80]));
+        let (mismatched, diff) = compare(&expected, &actual, 8);
+        assert_eq!(mismatched, 1);
+        assert_eq!(diff.get_pixel(1, 0), &Rgb([0xff, 0, 0]));
+    }
+
+    #[test]
+    fn test_perceptual_compare() {
+        // A vertical edge
+        let expected = RgbImage::from_fn(6, 4, |x, _| Rgb([if x < 3 { 0 } else { 0xff }; 3]));
+        // Moved a pixel to the right, with a spot in the white
+        let mut actual = RgbImage::from_fn(6, 4, |x, _| Rgb([if x < 4 { 0 } else { 0xff }; 3]));
+        actual.put_pixel(5, 0, Rgb([0, 0, 0]));
+        assert_eq!(compare(&expected, &actual, 8).0, 5);
+        let (mismatched, diff) = perceptual_compare(&expected, &actual, 8);
+        assert_eq!(mismatched, 1);
+        assert_eq!(diff.get_pixel(3, 1), &Rgb([0x80, 0x80, 0xff]));
+        assert_eq!(diff.get_pixel(5, 0), &Rgb([0xff, 0, 0]));
+        // Colors of the same brightness look alike
+        let red = RgbImage::from_pixel(1, 1, Rgb([0xff, 0, 0]));
+        let gray = RgbImage::from_pixel(1, 1, Rgb([76, 76, 76]));
+        assert_eq!(perceptual_compare(&red, &gray, 2).0, 0);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::framebuffer::cgmath::*;
 use crate::framebuffer::common::*;
+use crate::framebuffer::{CornerRadii, FillRule, LineCap, LineJoin};
 
 macro_rules! min {
         ($x: expr) => ($x);
@@ -75,10 +76,95 @@ where
         height: (max_y - min_y) as u32,
     }
 }
+/// Calls `plot` with the coverage, from 0.0 to 1.0, of every pixel touched by the line
+/// from `start` to `end`, `width` wide with round ends. Like in Wu's algorithm, the
+/// coverage falls off linearly with the distance of the pixel center from the edge.
+pub fn stamp_along_line_aa<F>(
+    plot: &mut F,
+    start: Point2<f32>,
+    end: Point2<f32>,
+    width: f32,
+) -> mxcfb_rect
+where
+    F: FnMut(Point2<i32>, f32),
+{
+    let radius = width.max(0.0) / 2.0;
+    let along = end - start;
+    let length2 = along.magnitude2();
+    let coverage = |p: Point2<f32>| {
+        let t = match length2 > 0.0 {
+            true => ((p - start).dot(along) / length2).clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        (radius + 0.5 - (p - (start + along * t)).magnitude()).clamp(0.0, 1.0)
+    };
+
+    // Walk the major axis, covering everything within reach of the line across it
+    let steep = along.y.abs() > along.x.abs();
+    let major_minor = |p: Point2<f32>| match steep {
+        true => (p.y, p.x),
+        false => (p.x, p.y),
+    };
+    let ((s_major, s_minor), (e_major, e_minor)) = (major_minor(start), major_minor(end));
+    let (from, to) = (s_major.min(e_major), s_major.max(e_major));
+    let slope = match e_major != s_major {
+        true => (e_minor - s_minor) / (e_major - s_major),
+        false => 0.0,
+    };
+    let reach = (radius + 1.0) * (1.0 + slope * slope).sqrt();
+
+    let (mut min_x, mut max_x, mut min_y, mut max_y) = (i32::MAX, i32::MIN, i32::MAX, i32::MIN);
+    let first = (from - radius - 1.0).floor() as i32;
+    let last = (to + radius + 1.0).ceil() as i32;
+    for m in first..=last {
+        let center = s_minor + slope * ((m as f32).clamp(from, to) - s_major);
+        for n in (center - reach).floor() as i32..=(center + reach).ceil() as i32 {
+            let p = match steep {
+                true => Point2 { x: n, y: m },
+                false => Point2 { x: m, y: n },
+            };
+            let c = coverage(p.cast().unwrap());
+            if c > 0.0 {
+                plot(p, c);
+                min_x = min!(min_x, p.x);
+                max_x = max!(max_x, p.x);
+                min_y = min!(min_y, p.y);
+                max_y = max!(max_y, p.y);
+            }
+        }
+    }
+
+    if min_x > max_x {
+        return mxcfb_rect::invalid();
+    }
+    let (left, top) = (min_x.max(0), min_y.max(0));
+    mxcfb_rect {
+        top: top as u32,
+        left: left as u32,
+        width: (max_x + 1 - left).max(0) as u32,
+        height: (max_y + 1 - top).max(0) as u32,
+    }
+}
+
 pub fn fill_polygon<F>(write_pixel: &mut F, points: &[Point2<i32>]) -> mxcfb_rect
 where
     F: FnMut(Point2<i32>),
 {
+    fill_polygon_with_rule(write_pixel, points, FillRule::NonZero)
+}
+
+pub fn fill_polygon_with_rule<F>(
+    write_pixel: &mut F,
+    points: &[Point2<i32>],
+    rule: FillRule,
+) -> mxcfb_rect
+where
+    F: FnMut(Point2<i32>),
+{
+    if points.len() < 3 {
+        return mxcfb_rect::invalid();
+    }
+
     // This implementation of polygon rasterisation is based on this article:
     // https://hackernoon.com/computer-graphics-scan-line-polygon-fill-algorithm-3cb47283df6
 
@@ -142,11 +228,15 @@ where
         active_list.sort_unstable_by_key(|p| p.x);
 
         // for every pair of edges on the active list,
-        // apply the winding rule of nonzero
+        // apply the fill rule to the winding count between them
         let mut prev_x = 0;
-        let mut winding_count = 0;
+        let mut winding_count: i32 = 0;
         for edge in active_list.iter() {
-            if winding_count != 0 {
+            let inside = match rule {
+                FillRule::NonZero => winding_count != 0,
+                FillRule::EvenOdd => winding_count % 2 != 0,
+            };
+            if inside {
                 for x in prev_x..edge.x {
                     write_pixel(Point2 { x, y: scanline });
                 }
@@ -203,6 +293,217 @@ where
     }
 }
 
+/// The dashes of the pattern `dash`, see `StrokeStyle::dash`, on a line `length` long,
+/// as their start and end along it. The pattern starts `phase` pixels in, e.g. to
+/// continue it from the previous line.
+pub fn dash_runs(dash: &[u32], phase: f32, length: f32) -> Vec<(f32, f32)> {
+    let period: u32 = dash.iter().sum::<u32>() * if dash.len() % 2 == 1 { 2 } else { 1 };
+    if period == 0 {
+        return vec![(0.0, length)];
+    }
+    let lengths = dash.iter().chain(dash.iter()).take(match dash.len() % 2 {
+        1 => dash.len() * 2,
+        _ => dash.len(),
+    });
+
+    let mut runs = Vec::new();
+    // Where the current period of the pattern started, relative to the line
+    let mut at = -(phase % period as f32);
+    while at < length {
+        for (i, &l) in lengths.clone().enumerate() {
+            let end = at + l as f32;
+            if i % 2 == 0 && end > 0.0 && at < length {
+                runs.push((at.max(0.0), end.min(length)));
+            }
+            at = end;
+        }
+    }
+    runs
+}
+
+/// Fills the part of the ring around `center` between the radii `inner` and `outer`
+/// that lies between the angles `start` and `end`, in radians clockwise from the x
+/// axis. Going from `start` to `end` counterclockwise if `end` is less.
+pub fn fill_ring_sector<F>(
+    write_pixel: &mut F,
+    center: Point2<i32>,
+    inner: f32,
+    outer: f32,
+    start: f32,
+    end: f32,
+) -> mxcfb_rect
+where
+    F: FnMut(Point2<i32>),
+{
+    use std::f32::consts::TAU;
+
+    let (start, sweep) = match end >= start {
+        true => (start, end - start),
+        false => (end, start - end),
+    };
+    let reach = outer.ceil() as i32;
+    let (mut min_x, mut max_x, mut min_y, mut max_y) = (i32::MAX, i32::MIN, i32::MAX, i32::MIN);
+    for y in -reach..=reach {
+        for x in -reach..=reach {
+            let distance = ((x * x + y * y) as f32).sqrt();
+            if distance > outer || distance < inner {
+                continue;
+            }
+            // The center of a pie belongs to every slice
+            let inside = sweep >= TAU
+                || (x == 0 && y == 0)
+                || ((y as f32).atan2(x as f32) - start).rem_euclid(TAU) <= sweep;
+            if inside {
+                let p = center + Vector2 { x, y };
+                write_pixel(p);
+                min_x = min!(min_x, p.x);
+                max_x = max!(max_x, p.x);
+                min_y = min!(min_y, p.y);
+                max_y = max!(max_y, p.y);
+            }
+        }
+    }
+
+    if min_x > max_x {
+        return mxcfb_rect::invalid();
+    }
+    let (left, top) = (min_x.max(0), min_y.max(0));
+    mxcfb_rect {
+        top: top as u32,
+        left: left as u32,
+        width: (max_x + 1 - left).max(0) as u32,
+        height: (max_y + 1 - top).max(0) as u32,
+    }
+}
+
+/// Thresholds for ordered dithering, spreading the pixels that get the brighter of
+/// two grays evenly over every 8x8 block
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// How bright `c` looks, from 0 for black to 255 for white
+pub fn brightness(c: color) -> f32 {
+    let [r, g, b] = c.to_rgb8();
+    0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b)
+}
+
+/// One of the 16 grays of the display for the pixel at `pos`, so that an area of
+/// pixels with `brightness` looks that bright on average
+pub fn dither(brightness: f32, pos: Point2<i32>) -> color {
+    let level = brightness.clamp(0.0, 255.0) / 17.0;
+    let threshold =
+        (f32::from(BAYER[pos.y.rem_euclid(8) as usize][pos.x.rem_euclid(8) as usize]) + 0.5) / 64.0;
+    let level = if level.fract() > threshold {
+        level.ceil()
+    } else {
+        level.floor()
+    };
+    let value = level as u8 * 17;
+    color::RGB(value, value, value)
+}
+
+/// Whether the pixel at `p` lies in the rectangle from `min` to `max` with the corners
+/// rounded off by `radii`: top left, top right, bottom right and bottom left
+fn in_rounded_rect(p: Point2<f32>, min: Point2<f32>, max: Point2<f32>, radii: [f32; 4]) -> bool {
+    if p.x < min.x || p.y < min.y || p.x > max.x || p.y > max.y {
+        return false;
+    }
+    let (left, top) = (p.x < (min.x + max.x) / 2.0, p.y < (min.y + max.y) / 2.0);
+    let (radius, corner) = match (left, top) {
+        (true, true) => (radii[0], min),
+        (false, true) => (radii[1], Point2 { x: max.x, y: min.y }),
+        (false, false) => (radii[2], max),
+        (true, false) => (radii[3], Point2 { x: min.x, y: max.y }),
+    };
+    // The center of the circle rounding off the corner
+    let center = Point2 {
+        x: corner.x + if left { radius } else { -radius },
+        y: corner.y + if top { radius } else { -radius },
+    };
+    let beyond_x = if left { p.x < center.x } else { p.x > center.x };
+    let beyond_y = if top { p.y < center.y } else { p.y > center.y };
+    !(beyond_x && beyond_y) || (p - center).magnitude2() <= radius * radius
+}
+
+/// Fills the rectangle of `size` at `pos` with the corners rounded off by `radii`, or
+/// only a border `border` thick on the inside of it
+pub fn fill_rounded_rect<F>(
+    write_pixel: &mut F,
+    pos: Point2<i32>,
+    size: Vector2<u32>,
+    radii: CornerRadii,
+    border: Option<u32>,
+) -> mxcfb_rect
+where
+    F: FnMut(Point2<i32>),
+{
+    if size.x == 0 || size.y == 0 {
+        return mxcfb_rect::invalid();
+    }
+    // Corners can't be rounder than the shorter side allows
+    let limit = size.x.min(size.y) as f32 / 2.0;
+    let radii = [
+        radii.top_left,
+        radii.top_right,
+        radii.bottom_right,
+        radii.bottom_left,
+    ]
+    .map(|r| (r as f32).min(limit));
+    let min = Point2 { x: 0.0, y: 0.0 };
+    let max = Point2 {
+        x: size.x as f32,
+        y: size.y as f32,
+    };
+    let inner = border.map(|border| {
+        let border = border as f32;
+        (
+            min + Vector2 {
+                x: border,
+                y: border,
+            },
+            max - Vector2 {
+                x: border,
+                y: border,
+            },
+            radii.map(|r| (r - border).max(0.0)),
+        )
+    });
+
+    for y in 0..size.y {
+        for x in 0..size.x {
+            // Pixel centers
+            let p = Point2 {
+                x: x as f32 + 0.5,
+                y: y as f32 + 0.5,
+            };
+            let inside = in_rounded_rect(p, min, max, radii)
+                && !matches!(inner, Some((min, max, radii)) if in_rounded_rect(p, min, max, radii));
+            if inside {
+                write_pixel(
+                    pos + Vector2 {
+                        x: x as i32,
+                        y: y as i32,
+                    },
+                );
+            }
+        }
+    }
+    mxcfb_rect {
+        top: pos.y.max(0) as u32,
+        left: pos.x.max(0) as u32,
+        width: size.x,
+        height: size.y,
+    }
+}
+
 /// Helper function to sample pixels on the bezier curve.
 fn sample_bezier(
     startpt: Point2<f32>,
@@ -302,6 +603,252 @@ where
     }
 }
 
+/// The point at `t` on the bezier curve with the control points `points`, of any
+/// degree, with de Casteljau's algorithm
+fn bezier_point(points: &[Point2<f32>], t: f32) -> Point2<f32> {
+    let mut points = points.to_vec();
+    while points.len() > 1 {
+        for i in 0..points.len() - 1 {
+            points[i] = points[i] + (points[i + 1] - points[i]) * t;
+        }
+        points.pop();
+    }
+    points[0]
+}
+
+/// Fills the ribbon along the bezier curve with the control points `points`, 3 for a
+/// quadratic or 4 for a cubic curve, going from `widths.0` wide at the start to
+/// `widths.1` at the end, with round ends
+pub fn fill_bezier_ribbon<F>(
+    write_pixel: &mut F,
+    points: &[Point2<f32>],
+    widths: (f32, f32),
+) -> mxcfb_rect
+where
+    F: FnMut(Point2<i32>),
+{
+    if points.len() < 2 {
+        return mxcfb_rect::invalid();
+    }
+    // The derivative is a curve of one degree less
+    let degree = (points.len() - 1) as f32;
+    let hodograph: Vec<Point2<f32>> = points
+        .windows(2)
+        .map(|pair| Point2::from_vec((pair[1] - pair[0]) * degree))
+        .collect();
+    let chord = points[points.len() - 1] - points[0];
+
+    // Fine enough that the edges look smooth even on the outside of tight turns
+    let polygon_length: f32 = points.windows(2).map(|p| (p[1] - p[0]).magnitude()).sum();
+    let samples = ((polygon_length + widths.0.max(widths.1)) / 3.0).clamp(8.0, 512.0) as usize;
+
+    let mut left = Vec::with_capacity(samples + 1);
+    let mut right = Vec::with_capacity(samples + 1);
+    let mut spine = Vec::with_capacity(samples + 1);
+    for i in 0..=samples {
+        let t = i as f32 / samples as f32;
+        let pt = bezier_point(points, t);
+        let velocity = bezier_point(&hodograph, t).to_vec();
+        let direction = if velocity.magnitude() > 0.0 {
+            velocity.normalize()
+        } else if chord.magnitude() > 0.0 {
+            chord.normalize()
+        } else {
+            Vector2 { x: 1.0, y: 0.0 }
+        };
+        let half = (widths.0 + (widths.1 - widths.0) * t).max(1.0) / 2.0;
+        let normal = Vector2 {
+            x: -direction.y,
+            y: direction.x,
+        } * half;
+        left.push(pt + normal);
+        right.push(pt - normal);
+        spine.push((pt, direction, half));
+    }
+
+    // Half circles around both ends, from the left to the right when looking along
+    // `direction`
+    let cap = |outline: &mut Vec<Point2<f32>>,
+               (center, direction, half): (Point2<f32>, Vector2<f32>, f32)| {
+        let steps = (half * 2.0).ceil().clamp(4.0, 32.0) as usize;
+        let normal = Vector2 {
+            x: -direction.y,
+            y: direction.x,
+        };
+        for i in 1..steps {
+            let angle = std::f32::consts::PI * i as f32 / steps as f32;
+            let (sin, cos) = angle.sin_cos();
+            outline.push(center + (normal * cos + direction * sin) * half);
+        }
+    };
+    let mut outline = left;
+    cap(&mut outline, spine[samples]);
+    outline.extend(right.into_iter().rev());
+    let (center, direction, half) = spine[0];
+    cap(&mut outline, (center, -direction, half));
+
+    let mut polygon: Vec<Point2<i32>> = Vec::with_capacity(outline.len());
+    for p in outline {
+        let p = Point2 {
+            x: p.x.round() as i32,
+            y: p.y.round() as i32,
+        };
+        if polygon.last() != Some(&p) {
+            polygon.push(p);
+        }
+    }
+    if polygon.len() > 2 {
+        fill_polygon(write_pixel, &polygon)
+    } else {
+        mxcfb_rect::invalid()
+    }
+}
+
+/// How far past the corner a `LineJoin::Miter` may reach, in half widths
+const MITER_LIMIT: f32 = 4.0;
+
+/// Whether `p` lies in the triangle `a`, `b`, `c`, in either winding
+fn in_triangle(p: Point2<f32>, a: Point2<f32>, b: Point2<f32>, c: Point2<f32>) -> bool {
+    let side = |from: Point2<f32>, to: Point2<f32>| (to - from).perp_dot(p - from);
+    let (ab, bc, ca) = (side(a, b), side(b, c), side(c, a));
+    (ab >= 0.0 && bc >= 0.0 && ca >= 0.0) || (ab <= 0.0 && bc <= 0.0 && ca <= 0.0)
+}
+
+/// Calls `write_pixel` once for every pixel covered by the stroke along `points`,
+/// `width` wide, with `join` at the corners and `cap` at the ends
+pub fn stroke_polyline<F>(
+    write_pixel: &mut F,
+    points: &[Point2<f32>],
+    width: f32,
+    join: LineJoin,
+    cap: LineCap,
+) -> mxcfb_rect
+where
+    F: FnMut(Point2<i32>),
+{
+    let mut points = points.to_vec();
+    points.dedup();
+    if points.len() < 2 || width <= 0.0 {
+        return mxcfb_rect::invalid();
+    }
+    let half = width / 2.0;
+
+    // The pixels covered so far, in an area large enough for any join or cap
+    let pad = half * MITER_LIMIT + 1.0;
+    let min = Point2 {
+        x: (points.iter().map(|p| p.x).fold(f32::MAX, f32::min) - pad).floor() as i32,
+        y: (points.iter().map(|p| p.y).fold(f32::MAX, f32::min) - pad).floor() as i32,
+    };
+    let max = Point2 {
+        x: (points.iter().map(|p| p.x).fold(f32::MIN, f32::max) + pad).ceil() as i32,
+        y: (points.iter().map(|p| p.y).fold(f32::MIN, f32::max) + pad).ceil() as i32,
+    };
+    let columns = (max.x - min.x) as usize;
+    let mut covered = vec![false; columns * (max.y - min.y) as usize];
+    // Marks the pixels around `center` within `reach` for which `inside` holds
+    let mut mark = |center: Point2<f32>, reach: f32, inside: &dyn Fn(Point2<f32>) -> bool| {
+        let from_y = ((center.y - reach).floor() as i32).max(min.y);
+        let to_y = ((center.y + reach).ceil() as i32).min(max.y);
+        let from_x = ((center.x - reach).floor() as i32).max(min.x);
+        let to_x = ((center.x + reach).ceil() as i32).min(max.x);
+        for y in from_y..to_y {
+            for x in from_x..to_x {
+                let pixel = Point2 {
+                    x: x as f32 + 0.5,
+                    y: y as f32 + 0.5,
+                };
+                if inside(pixel) {
+                    covered[(y - min.y) as usize * columns + (x - min.x) as usize] = true;
+                }
+            }
+        }
+    };
+
+    let last = points.len() - 2;
+    for (i, segment) in points.windows(2).enumerate() {
+        let (a, b) = (segment[0], segment[1]);
+        let length = (b - a).magnitude();
+        let direction = (b - a) / length;
+        let normal = Vector2 {
+            x: -direction.y,
+            y: direction.x,
+        };
+        let square = |end| {
+            if end && cap == LineCap::Square {
+                half
+            } else {
+                0.0
+            }
+        };
+        let (before, after) = (square(i == 0), square(i == last));
+        let middle = a + (b - a) / 2.0;
+        mark(middle, length / 2.0 + width, &|p| {
+            let along = (p - a).dot(direction);
+            along >= -before && along <= length + after && (p - a).dot(normal).abs() <= half
+        });
+    }
+
+    let round = |p: Point2<f32>, center: Point2<f32>| (p - center).magnitude2() <= half * half;
+    if cap == LineCap::Round {
+        for end in [points[0], points[points.len() - 1]] {
+            mark(end, half, &|p| round(p, end));
+        }
+    }
+
+    for corner in points.windows(3) {
+        let (before, at, after) = (corner[0], corner[1], corner[2]);
+        let (d0, d1) = ((at - before).normalize(), (after - at).normalize());
+        let turn = d0.perp_dot(d1);
+        if turn.abs() < 1e-6 && d0.dot(d1) > 0.0 {
+            // Straight on
+            continue;
+        }
+        // Towards the outside of the corner
+        let side = if turn > 0.0 { -half } else { half };
+        let (n0, n1) = (Vector2 { x: -d0.y, y: d0.x }, Vector2 { x: -d1.y, y: d1.x });
+        let (outer0, outer1) = (at + n0 * side, at + n1 * side);
+        let bisector = n0 + n1;
+        // Of the tip from the corner, in half widths
+        let miter = 2.0 / bisector.magnitude();
+        match join {
+            LineJoin::Round => mark(at, half, &|p| round(p, at)),
+            LineJoin::Miter if miter <= MITER_LIMIT => {
+                let tip = at + bisector * (side * 2.0 / bisector.magnitude2());
+                mark(at, half * miter, &|p| {
+                    in_triangle(p, at, outer0, tip) || in_triangle(p, at, tip, outer1)
+                });
+            }
+            _ => mark(at, half, &|p| in_triangle(p, at, outer0, outer1)),
+        }
+    }
+
+    let (mut top_left, mut bottom_right) = (max, min);
+    for (i, _) in covered.iter().enumerate().filter(|(_, covered)| **covered) {
+        let pixel = Point2 {
+            x: min.x + (i % columns) as i32,
+            y: min.y + (i / columns) as i32,
+        };
+        write_pixel(pixel);
+        top_left = Point2 {
+            x: top_left.x.min(pixel.x),
+            y: top_left.y.min(pixel.y),
+        };
+        bottom_right = Point2 {
+            x: bottom_right.x.max(pixel.x + 1),
+            y: bottom_right.y.max(pixel.y + 1),
+        };
+    }
+    if bottom_right.x <= top_left.x.max(0) || bottom_right.y <= top_left.y.max(0) {
+        return mxcfb_rect::invalid();
+    }
+    mxcfb_rect {
+        left: top_left.x.max(0) as u32,
+        top: top_left.y.max(0) as u32,
+        width: (bottom_right.x - top_left.x.max(0)) as u32,
+        height: (bottom_right.y - top_left.y.max(0)) as u32,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -315,6 +862,338 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_antialiased_line() {
+        let mut plotted = std::collections::HashMap::new();
+        let rect = stamp_along_line_aa(
+            &mut |p, c| {
+                plotted.insert((p.x, p.y), c);
+            },
+            Point2 { x: 10.0, y: 10.0 },
+            Point2 { x: 30.0, y: 15.0 },
+            1.0,
+        );
+        // Fully covered on the line, partially next to it
+        assert_eq!(plotted[&(10, 10)], 1.0);
+        assert_eq!(plotted[&(30, 15)], 1.0);
+        // Halfway between two rows at x = 20
+        let at_20 = [11, 12, 13, 14].map(|y| plotted.get(&(20, y)).copied().unwrap_or(0.0));
+        assert!((at_20[1] - 0.5).abs() < 0.1 && (at_20[2] - 0.5).abs() < 0.1);
+        assert_eq!((at_20[0], at_20[3]), (0.0, 0.0));
+        assert!(plotted.values().all(|&c| c > 0.0 && c <= 1.0));
+        assert_eq!(
+            rect,
+            mxcfb_rect {
+                top: 10,
+                left: 10,
+                width: 21,
+                height: 6
+            }
+        );
+    }
+
+    #[test]
+    fn test_bezier_ribbon() {
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        // A cubic S curve, getting wider
+        let points = [
+            Point2 { x: 100.0, y: 100.0 },
+            Point2 { x: 200.0, y: 0.0 },
+            Point2 { x: 200.0, y: 200.0 },
+            Point2 { x: 300.0, y: 100.0 },
+        ];
+        let rect = fill_bezier_ribbon(&mut |p| mock.write_pixel(p), &points, (4.0, 20.0));
+        let column = |x| {
+            let mut ys: Vec<i32> = mock
+                .pixel_writes
+                .iter()
+                .filter(|p| p.x == x)
+                .map(|p| p.y)
+                .collect();
+            ys.sort_unstable();
+            ys.dedup();
+            ys
+        };
+        // The curve crosses x = 200 at y = 100, halfway and 12px wide
+        let middle = column(200);
+        assert!(
+            middle.contains(&100) && (10..=16).contains(&middle.len()),
+            "{:?}",
+            middle
+        );
+        // Round ends
+        assert!(column(96).len() <= 3 && !column(98).is_empty());
+        assert!(column(308).len() > 5 && column(312).is_empty());
+        assert!(
+            rect.left >= 96 && rect.left + rect.width <= 311,
+            "{:?}",
+            rect
+        );
+
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        let rect = fill_bezier_ribbon(&mut |p| mock.write_pixel(p), &points[..1], (4.0, 4.0));
+        assert!(mock.pixel_writes.is_empty());
+        assert_eq!(rect, mxcfb_rect::invalid());
+    }
+
+    #[test]
+    fn test_fill_rules() {
+        // A pentagram, whose center is wound around twice
+        let star = [
+            Point2 { x: 50, y: 0 },
+            Point2 { x: 80, y: 95 },
+            Point2 { x: 0, y: 35 },
+            Point2 { x: 100, y: 35 },
+            Point2 { x: 20, y: 95 },
+        ];
+        let filled = |rule| {
+            let mut mock = Mock {
+                pixel_writes: &mut Vec::new(),
+            };
+            let rect = fill_polygon_with_rule(&mut |p| mock.write_pixel(p), &star, rule);
+            assert_eq!(
+                rect,
+                mxcfb_rect {
+                    top: 0,
+                    left: 0,
+                    width: 100,
+                    height: 95
+                }
+            );
+            mock.pixel_writes.clone()
+        };
+        let center = Point2 { x: 50, y: 50 };
+        let tip = Point2 { x: 50, y: 20 };
+        let non_zero = filled(FillRule::NonZero);
+        assert!(non_zero.contains(&center) && non_zero.contains(&tip));
+        let even_odd = filled(FillRule::EvenOdd);
+        assert!(!even_odd.contains(&center) && even_odd.contains(&tip));
+
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        let rect =
+            fill_polygon_with_rule(&mut |p| mock.write_pixel(p), &star[..2], FillRule::EvenOdd);
+        assert!(mock.pixel_writes.is_empty());
+        assert_eq!(rect, mxcfb_rect::invalid());
+    }
+
+    #[test]
+    fn test_dash_runs() {
+        assert_eq!(dash_runs(&[], 3.0, 10.0), [(0.0, 10.0)]);
+        assert_eq!(
+            dash_runs(&[4, 2], 0.0, 15.0),
+            [(0.0, 4.0), (6.0, 10.0), (12.0, 15.0)]
+        );
+        // Odd patterns repeat twice over
+        assert_eq!(
+            dash_runs(&[3], 0.0, 14.0),
+            [(0.0, 3.0), (6.0, 9.0), (12.0, 14.0)]
+        );
+        // Continued from a line that ended halfway through a dash
+        assert_eq!(
+            dash_runs(&[4, 2, 1, 2], 11.0, 8.0),
+            [(0.0, 2.0), (4.0, 5.0), (7.0, 8.0)]
+        );
+    }
+
+    #[test]
+    fn test_ring_sector() {
+        use std::f32::consts::PI;
+
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        // The bottom right quarter of a pie
+        let center = Point2 { x: 100, y: 100 };
+        let rect = fill_ring_sector(
+            &mut |p| mock.write_pixel(p),
+            center,
+            -1.0,
+            20.0,
+            0.0,
+            PI / 2.0,
+        );
+        assert!(mock.pixel_writes.iter().all(|p| p.x >= 100 && p.y >= 100));
+        assert!(mock.pixel_writes.contains(&Point2 { x: 110, y: 110 }));
+        assert!(mock.pixel_writes.contains(&center));
+        assert_eq!(
+            rect,
+            mxcfb_rect {
+                top: 100,
+                left: 100,
+                width: 21,
+                height: 21
+            }
+        );
+
+        // An arc 4 wide over the top, drawn counterclockwise
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        fill_ring_sector(&mut |p| mock.write_pixel(p), center, 28.0, 32.0, 0.0, -PI);
+        assert!(mock.pixel_writes.iter().all(|p| p.y <= 100));
+        assert!(mock.pixel_writes.contains(&Point2 { x: 100, y: 70 }));
+        assert!(!mock.pixel_writes.contains(&Point2 { x: 100, y: 75 }));
+    }
+
+    #[test]
+    fn test_dither() {
+        let block = |brightness: f32| {
+            (0..8)
+                .flat_map(|y| (0..8).map(move |x| Point2 { x, y }))
+                .map(|p| dither(brightness, p))
+                .collect::<Vec<_>>()
+        };
+        // The 16 grays themselves aren't dithered
+        assert!(block(0.0).iter().all(|c| *c == color::RGB(0, 0, 0)));
+        assert!(block(136.0).iter().all(|c| *c == color::RGB(136, 136, 136)));
+        // Half way between two of them, half the pixels get either
+        let half = block(136.0 + 8.5);
+        let brighter = half
+            .iter()
+            .filter(|c| **c == color::RGB(153, 153, 153))
+            .count();
+        assert_eq!(brighter, 32);
+        assert!(half
+            .iter()
+            .all(|c| *c == color::RGB(153, 153, 153) || *c == color::RGB(136, 136, 136)));
+        assert!((brightness(color::WHITE) - 255.0).abs() < 1.0);
+
+        use crate::framebuffer::Fill;
+        let gradient = Fill::LinearGradient {
+            from: Point2 { x: 0.0, y: 0.0 },
+            to: Point2 { x: 100.0, y: 0.0 },
+            start: color::BLACK,
+            end: color::WHITE,
+        };
+        assert_eq!(
+            gradient.color_at(Point2 { x: -5, y: 3 }),
+            color::RGB(0, 0, 0)
+        );
+        assert_eq!(
+            gradient.color_at(Point2 { x: 120, y: 3 }),
+            color::RGB(255, 255, 255)
+        );
+        let checkers = Fill::Pattern {
+            bits: [0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55],
+            fg: color::BLACK,
+            bg: color::WHITE,
+        };
+        assert_eq!(checkers.color_at(Point2 { x: 8, y: 0 }), color::BLACK);
+        assert_eq!(checkers.color_at(Point2 { x: -1, y: 0 }), color::WHITE);
+        assert_eq!(checkers.color_at(Point2 { x: -1, y: 1 }), color::BLACK);
+    }
+
+    #[test]
+    fn test_polyline_joins() {
+        // A right angle, turning down at the top right
+        let points = [
+            Point2 { x: 10.0, y: 20.0 },
+            Point2 { x: 40.0, y: 20.0 },
+            Point2 { x: 40.0, y: 50.0 },
+        ];
+        let stroke = |join, cap| {
+            let mut mock = Mock {
+                pixel_writes: &mut Vec::new(),
+            };
+            stroke_polyline(&mut |p| mock.write_pixel(p), &points, 10.0, join, cap);
+            mock.pixel_writes.clone()
+        };
+
+        let miter = stroke(LineJoin::Miter, LineCap::Butt);
+        let mut unique = miter.clone();
+        unique.sort_by_key(|p| (p.y, p.x));
+        unique.dedup();
+        assert_eq!(unique.len(), miter.len());
+        // The outer corner is square, the ends are cut off at the points
+        assert!(miter.contains(&Point2 { x: 44, y: 15 }));
+        assert!(
+            miter.contains(&Point2 { x: 10, y: 20 }) && !miter.contains(&Point2 { x: 9, y: 20 })
+        );
+
+        let bevel = stroke(LineJoin::Bevel, LineCap::Butt);
+        assert!(
+            !bevel.contains(&Point2 { x: 44, y: 15 }) && bevel.contains(&Point2 { x: 41, y: 18 })
+        );
+        let round = stroke(LineJoin::Round, LineCap::Round);
+        assert!(
+            !round.contains(&Point2 { x: 44, y: 15 }) && round.contains(&Point2 { x: 42, y: 17 })
+        );
+        assert!(round.contains(&Point2 { x: 7, y: 20 }));
+        let square = stroke(LineJoin::Round, LineCap::Square);
+        assert!(
+            square.contains(&Point2 { x: 6, y: 16 }) && square.contains(&Point2 { x: 44, y: 54 })
+        );
+
+        // Turning back on itself, the miter would reach too far
+        let hairpin = [
+            Point2 { x: 10.0, y: 20.0 },
+            Point2 { x: 40.0, y: 20.0 },
+            Point2 { x: 10.0, y: 22.0 },
+        ];
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        let rect = stroke_polyline(
+            &mut |p| mock.write_pixel(p),
+            &hairpin,
+            10.0,
+            LineJoin::Miter,
+            LineCap::Butt,
+        );
+        assert!(rect.left + rect.width <= 46, "{:?}", rect);
+    }
+
+    #[test]
+    fn test_rounded_rect() {
+        let radii = CornerRadii {
+            top_left: 10,
+            top_right: 0,
+            bottom_right: 40,
+            bottom_left: 4,
+        };
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        let rect = fill_rounded_rect(
+            &mut |p| mock.write_pixel(p),
+            Point2 { x: 10, y: 10 },
+            Vector2 { x: 60, y: 40 },
+            radii,
+            None,
+        );
+        assert_eq!(
+            rect,
+            mxcfb_rect::from(Point2 { x: 10, y: 10 }, Vector2 { x: 60, y: 40 })
+        );
+        let filled = |x, y| mock.pixel_writes.contains(&Point2 { x, y });
+        // Rounded off, square and rounded as far as the height allows
+        assert!(!filled(10, 10) && filled(13, 13));
+        assert!(filled(69, 10));
+        assert!(!filled(69, 49) && !filled(60, 47) && filled(50, 40));
+        assert!(!filled(10, 49) && filled(11, 48) && filled(10, 45));
+
+        let mut mock = Mock {
+            pixel_writes: &mut Vec::new(),
+        };
+        fill_rounded_rect(
+            &mut |p| mock.write_pixel(p),
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 60, y: 40 },
+            CornerRadii::uniform(8),
+            Some(2),
+        );
+        let drawn = |x, y| mock.pixel_writes.contains(&Point2 { x, y });
+        assert!(drawn(30, 0) && drawn(30, 1) && !drawn(30, 2));
+        assert!(drawn(59, 20) && !drawn(57, 20) && !drawn(0, 0));
+        assert!(drawn(3, 3) && !drawn(5, 5));
+    }
+
     #[test]
     fn test_draw_1px_square_polygon() {
         let mut mock = Mock {
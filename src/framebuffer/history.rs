@@ -0,0 +1,328 @@
+//! Undo and redo on the framebuffer itself.
+//!
+//! Before an app draws into a region it records it in a `History`, which keeps a
+//! compressed copy of what was there. Undoing restores those before-images and
+//! refreshes only the affected area, so apps get undo/redo without keeping their own
+//! journal of drawing operations:
+//!
+//! ```no_run
+//! # use libremarkable::framebuffer::{core::Framebuffer, history::History, FramebufferDraw};
+//! # use libremarkable::framebuffer::common::{color, mxcfb_rect};
+//! let mut fb = Framebuffer::new();
+//! let mut history = History::new(16 * 1024 * 1024);
+//! let area = mxcfb_rect { top: 100, left: 100, width: 200, height: 200 };
+//! history.draw(&mut fb, area, |fb| fb.fill_circle((200, 200).into(), 80, color::BLACK));
+//! history.undo(&mut fb);
+//! ```
+
+use std::collections::VecDeque;
+
+use crate::framebuffer::common::{display_temp, dither_mode, mxcfb_rect, waveform_mode};
+use crate::framebuffer::core;
+use crate::framebuffer::storage::CompressedCanvasState;
+use crate::framebuffer::{FramebufferIO, FramebufferRefresh, PartialRefreshMode};
+
+/// A region and its contents at some point
+struct Snapshot {
+    rect: mxcfb_rect,
+    state: CompressedCanvasState,
+}
+
+impl Snapshot {
+    fn take(fb: &core::Framebuffer, rect: mxcfb_rect) -> Option<Snapshot> {
+        let data = fb.dump_region(rect).ok()?;
+        Some(Snapshot {
+            rect,
+            state: CompressedCanvasState::new(&data, rect.height, rect.width),
+        })
+    }
+
+    fn restore(&self, fb: &mut core::Framebuffer) {
+        // Can't fail, the region was dumped from the same framebuffer
+        let _ = fb.restore_region(self.rect, &self.state.decompress());
+    }
+}
+
+/// The snapshots of one undoable step, in the order they were taken
+#[derive(Default)]
+struct Step {
+    snapshots: Vec<Snapshot>,
+}
+
+impl Step {
+    fn size(&self) -> usize {
+        self.snapshots
+            .iter()
+            .map(|s| s.state.compressed_size())
+            .sum()
+    }
+
+    /// Restores the snapshots and returns the step that reverts this, along with
+    /// the area that changed
+    fn apply(&self, fb: &mut core::Framebuffer) -> (Step, mxcfb_rect) {
+        // Taking the snapshots in reverse order gets every region back to how it was
+        // right before the snapshot was taken, even when they overlap. Their
+        // replacements have to be applied in the opposite order again.
+        let mut reverted = Vec::with_capacity(self.snapshots.len());
+        let mut changed = mxcfb_rect::invalid();
+        for snapshot in self.snapshots.iter().rev() {
+            if let Some(current) = Snapshot::take(fb, snapshot.rect) {
+                reverted.push(current);
+            }
+            snapshot.restore(fb);
+            changed = changed.merge_rect(&snapshot.rect);
+        }
+        (
+            Step {
+                snapshots: reverted,
+            },
+            changed,
+        )
+    }
+}
+
+/// Undo and redo history of framebuffer regions
+pub struct History {
+    undo: VecDeque<Step>,
+    redo: Vec<Step>,
+    /// The step being recorded by `begin` and `end`
+    group: Option<Step>,
+    /// Compressed bytes kept across all steps
+    size: usize,
+    max_size: usize,
+    /// Waveform used to refresh what was undone or redone
+    pub waveform_mode: waveform_mode,
+}
+
+impl History {
+    /// A history keeping up to `max_size` compressed bytes. The oldest steps are
+    /// dropped when it grows beyond that.
+    pub fn new(max_size: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            group: None,
+            size: 0,
+            max_size,
+            waveform_mode: waveform_mode::WAVEFORM_MODE_GC16_FAST,
+        }
+    }
+
+    /// Keeps the current contents of `rect` to return to on undo. Call this before
+    /// drawing into it. The parts of `rect` outside of the screen are ignored.
+    pub fn record(&mut self, fb: &core::Framebuffer, rect: mxcfb_rect) {
+        let rect = clip(fb, rect);
+        let snapshot = match Snapshot::take(fb, rect) {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        self.size += snapshot.state.compressed_size();
+        match self.group {
+            Some(ref mut group) => group.snapshots.push(snapshot),
+            None => self.push(Step {
+                snapshots: vec![snapshot],
+            }),
+        }
+        self.clear_redo();
+    }
+
+    /// Records `rect`, the area that `draw` may change, then runs `draw`
+    pub fn draw<F, R>(&mut self, fb: &mut core::Framebuffer, rect: mxcfb_rect, draw: F) -> R
+    where
+        F: FnOnce(&mut core::Framebuffer) -> R,
+    {
+        self.record(fb, rect);
+        draw(fb)
+    }
+
+    /// Starts a step made of several `record`s, e.g. the segments of a pen stroke
+    pub fn begin(&mut self) {
+        self.end();
+        self.group = Some(Step::default());
+    }
+
+    /// Finishes the step started with `begin`
+    pub fn end(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.snapshots.is_empty() {
+                self.push(group);
+            }
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.group.is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Reverts the last step and refreshes the area it covered, which is returned
+    pub fn undo(&mut self, fb: &mut core::Framebuffer) -> Option<mxcfb_rect> {
+        self.end();
+        let step = self.undo.pop_back()?;
+        let (reverted, changed) = step.apply(fb);
+        self.size = self.size - step.size() + reverted.size();
+        self.redo.push(reverted);
+        self.refresh(fb, &changed);
+        Some(changed)
+    }
+
+    /// Reapplies the last undone step and refreshes the area it covered, which is
+    /// returned
+    pub fn redo(&mut self, fb: &mut core::Framebuffer) -> Option<mxcfb_rect> {
+        self.end();
+        let step = self.redo.pop()?;
+        let (reverted, changed) = step.apply(fb);
+        self.size = self.size - step.size() + reverted.size();
+        self.undo.push_back(reverted);
+        self.refresh(fb, &changed);
+        Some(changed)
+    }
+
+    /// Forgets all steps
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group = None;
+        self.size = 0;
+    }
+
+    /// Compressed bytes currently kept
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn push(&mut self, step: Step) {
+        self.undo.push_back(step);
+        // Always keep the newest step, even if it alone is too large
+        while self.size > self.max_size && self.undo.len() > 1 {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.size -= oldest.size();
+            }
+        }
+    }
+
+    fn clear_redo(&mut self) {
+        for step in self.redo.drain(..) {
+            self.size -= step.size();
+        }
+    }
+
+    fn refresh(&self, fb: &core::Framebuffer, rect: &mxcfb_rect) {
+        fb.partial_refresh(
+            rect,
+            PartialRefreshMode::Async,
+            self.waveform_mode,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        );
+    }
+}
+
+/// The part of `rect` that is on the screen
+fn clip(fb: &core::Framebuffer, rect: mxcfb_rect) -> mxcfb_rect {
+    let size = fb.screen_size();
+    let (xres, yres) = (size.x, size.y);
+    let left = rect.left.min(xres);
+    let top = rect.top.min(yres);
+    mxcfb_rect {
+        left,
+        top,
+        width: rect.width.min(xres - left),
+        height: rect.height.min(yres - top),
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::Point2;
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::FramebufferDraw;
+
+    fn screen(fb: &core::Framebuffer) -> Vec<u8> {
+        fb.dump_region(mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: 64,
+            height: 64,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut fb = core::Framebuffer::headless(64, 64);
+        let mut history = History::new(usize::MAX);
+        let blank = screen(&fb);
+
+        let area = mxcfb_rect {
+            top: 10,
+            left: 10,
+            width: 20,
+            height: 20,
+        };
+        history.draw(&mut fb, area, |fb| {
+            fb.fill_rect(Point2 { x: 10, y: 10 }, (20, 20).into(), color::BLACK)
+        });
+        let square = screen(&fb);
+
+        // A step made of overlapping parts, partially off screen
+        history.begin();
+        for x in [20, 40, 50] {
+            let part = mxcfb_rect {
+                top: 20,
+                left: x,
+                width: 20,
+                height: 20,
+            };
+            history.draw(&mut fb, part, |fb| {
+                fb.fill_rect(
+                    Point2 { x: x as i32, y: 20 },
+                    (20, 20).into(),
+                    color::GRAY(128),
+                )
+            });
+        }
+        history.end();
+        let both = screen(&fb);
+
+        assert!(history.undo(&mut fb).is_some());
+        assert_eq!(screen(&fb), square);
+        assert!(history.undo(&mut fb).is_some());
+        assert_eq!(screen(&fb), blank);
+        assert!(history.undo(&mut fb).is_none());
+
+        assert!(history.redo(&mut fb).is_some());
+        assert_eq!(screen(&fb), square);
+        assert!(history.redo(&mut fb).is_some());
+        assert_eq!(screen(&fb), both);
+        assert!(!history.can_redo());
+
+        // New drawing drops what could be redone
+        history.undo(&mut fb);
+        history.record(&fb, area);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let fb = core::Framebuffer::headless(64, 64);
+        let rect = mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: 64,
+            height: 64,
+        };
+        let mut history = History::new(0);
+        history.record(&fb, rect);
+        history.record(&fb, rect);
+        assert_eq!(history.undo.len(), 1);
+        history.clear();
+        assert_eq!(history.size(), 0);
+    }
+}
@@ -3,6 +3,7 @@ use log::error;
 use crate::framebuffer;
 use crate::framebuffer::cgmath;
 use crate::framebuffer::common;
+use crate::framebuffer::rotation::ScreenRotation;
 
 impl framebuffer::FramebufferIO for framebuffer::core::Framebuffer {
     fn write_frame(&mut self, frame: &[u8]) {
@@ -16,19 +17,30 @@ impl framebuffer::FramebufferIO for framebuffer::core::Framebuffer {
 
     #[inline]
     fn write_pixel(&mut self, pos: cgmath::Point2<i32>, col: framebuffer::common::color) {
-        let w = self.var_screen_info.xres as usize;
-        let h = self.var_screen_info.yres as usize;
+        let size = self.screen_size();
         if pos.y < 0 || pos.x < 0 {
             return;
         }
-        if pos.y as usize >= h || pos.x as usize >= w {
+        if pos.y as u32 >= size.y || pos.x as u32 >= size.x || self.is_clipped(pos) {
             return;
         }
-        let line_length = self.fix_screen_info.line_length as isize;
-        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as isize;
-        let curr_index = pos.y as isize * line_length + pos.x as isize * bytespp;
+        let curr_index = self.pixel_index(pos.cast().unwrap()) as isize;
 
+        let col = match self.blend_mode {
+            framebuffer::BlendMode::Opaque => col,
+            mode => mode.blend(self.read_pixel(pos.cast().unwrap()), col),
+        };
         let begin = self.frame.as_mut_ptr();
+        if self.is_color() {
+            for (i, component) in col.to_xrgb8888().into_iter().enumerate() {
+                unsafe {
+                    begin
+                        .offset(curr_index + i as isize)
+                        .write_volatile(component);
+                }
+            }
+            return;
+        }
         let components = col.as_native();
         unsafe {
             begin.offset(curr_index).write_volatile(components[0]);
@@ -37,17 +49,19 @@ impl framebuffer::FramebufferIO for framebuffer::core::Framebuffer {
     }
 
     fn read_pixel(&self, pos: cgmath::Point2<u32>) -> framebuffer::common::color {
-        let w = self.var_screen_info.xres as usize;
-        let h = self.var_screen_info.yres as usize;
-        if pos.y as usize >= h || pos.x as usize >= w {
+        let size = self.screen_size();
+        if pos.y >= size.y || pos.x >= size.x {
             error!("Attempting to read pixel out of range. Returning a white pixel.");
             return framebuffer::common::color::WHITE;
         }
-        let line_length = self.fix_screen_info.line_length as usize;
-        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
-        let curr_index = pos.y as usize * line_length + pos.x as usize * bytespp;
+        let curr_index = self.pixel_index(pos);
 
         let begin = self.frame.as_mut_ptr();
+        if self.is_color() {
+            let components =
+                [0, 1, 2, 3].map(|i| unsafe { begin.add(curr_index + i).read_volatile() });
+            return framebuffer::common::color::from_xrgb8888(components);
+        }
         let (c1, c2) = unsafe {
             (
                 begin.add(curr_index).read_volatile(),
@@ -68,15 +82,29 @@ impl framebuffer::FramebufferIO for framebuffer::core::Framebuffer {
         if rect.width == 0 || rect.height == 0 {
             return Err("Unable to dump a region with zero height/width");
         }
-        if rect.top + rect.height > self.var_screen_info.height {
+        let size = self.screen_size();
+        if rect.top + rect.height > size.y {
             return Err("Vertically out of bounds");
         }
-        if rect.left + rect.width > self.var_screen_info.width {
+        if rect.left + rect.width > size.x {
             return Err("Horizontally out of bounds");
         }
 
-        let line_length = self.fix_screen_info.line_length;
         let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        if self.rotation() != ScreenRotation::Rot0 {
+            // The rows of the screen aren't those of the framebuffer
+            let mut outbuffer =
+                Vec::with_capacity(rect.height as usize * rect.width as usize * bytespp);
+            for y in rect.top..rect.top + rect.height {
+                for x in rect.left..rect.left + rect.width {
+                    let index = self.pixel_index(cgmath::Point2 { x, y }) as isize;
+                    outbuffer.extend((0..bytespp as isize).map(|i| self.read_offset(index + i)));
+                }
+            }
+            return Ok(outbuffer);
+        }
+
+        let line_length = self.fix_screen_info.line_length;
         let inbuffer = self.frame.as_ptr();
         let mut outbuffer: Vec<u8> =
             Vec::with_capacity(rect.height as usize * rect.width as usize * bytespp);
@@ -108,10 +136,11 @@ impl framebuffer::FramebufferIO for framebuffer::core::Framebuffer {
         if rect.width == 0 || rect.height == 0 {
             return Err("Unable to restore a region with zero height/width");
         }
-        if rect.top + rect.height > self.var_screen_info.height {
+        let size = self.screen_size();
+        if rect.top + rect.height > size.y {
             return Err("Vertically out of bounds");
         }
-        if rect.left + rect.width > self.var_screen_info.width {
+        if rect.left + rect.width > size.x {
             return Err("Horizontally out of bounds");
         }
 
@@ -120,9 +149,23 @@ impl framebuffer::FramebufferIO for framebuffer::core::Framebuffer {
             return Err("Cannot restore region due to mismatched size");
         }
 
+        let outbuffer = self.frame.as_mut_ptr();
+        if self.rotation() != ScreenRotation::Rot0 {
+            let mut pixels = data.chunks_exact(bytespp);
+            for y in rect.top..rect.top + rect.height {
+                for x in rect.left..rect.left + rect.width {
+                    let index = self.pixel_index(cgmath::Point2 { x, y });
+                    let pixel = pixels.next().unwrap();
+                    unsafe {
+                        outbuffer.add(index).copy_from(pixel.as_ptr(), bytespp);
+                    }
+                }
+            }
+            return Ok(data.len() as u32);
+        }
+
         let line_length = self.fix_screen_info.line_length;
         let chunk_size = bytespp * rect.width as usize;
-        let outbuffer = self.frame.as_mut_ptr();
         let inbuffer = data.as_ptr();
         let mut written: u32 = 0;
         for y in 0..rect.height {
@@ -137,3 +180,14 @@ impl framebuffer::FramebufferIO for framebuffer::core::Framebuffer {
         Ok(written)
     }
 }
+
+impl framebuffer::core::Framebuffer {
+    /// Where the pixel at `pos` on the screen starts in the framebuffer, see
+    /// `framebuffer::rotation`
+    fn pixel_index(&self, pos: cgmath::Point2<u32>) -> usize {
+        let pos = self.rotation().to_panel(pos, self.panel_size());
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        pos.y as usize * line_length + pos.x as usize * bytespp
+    }
+}
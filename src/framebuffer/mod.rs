@@ -5,12 +5,64 @@ pub mod screeninfo;
 #[cfg(feature = "framebuffer-storage")]
 pub mod storage;
 
+#[cfg(feature = "framebuffer-storage")]
+pub mod history;
+
 #[cfg(feature = "framebuffer")]
 pub mod io;
 
+pub mod error;
+pub use error::FramebufferError;
+
+pub mod profile;
+pub use profile::{RefreshHint, RefreshProfile};
+
 #[cfg(feature = "framebuffer")]
 pub mod swtfb_client;
 
+#[cfg(feature = "framebuffer")]
+pub mod shm;
+
+/// Comparing framebuffer content against reference images in tests
+#[cfg(all(feature = "framebuffer", feature = "image"))]
+pub mod golden;
+
+/// Framebuffers in memory to draw on before compositing them onto the display
+#[cfg(feature = "framebuffer")]
+pub mod offscreen;
+
+/// Batching the refreshes of what was drawn
+#[cfg(feature = "framebuffer")]
+pub mod damage;
+
+/// Refreshing areas made of many rectangles, like strokes, in a few refreshes
+#[cfg(feature = "framebuffer")]
+pub mod region;
+
+/// Copies of regions to restore later, e.g. under popups
+#[cfg(feature = "framebuffer")]
+pub mod snapshot;
+
+/// Displays implemented outside of this crate
+#[cfg(feature = "framebuffer")]
+pub mod backend;
+#[cfg(feature = "framebuffer")]
+pub use backend::FramebufferBackend;
+
+/// Drawing on the display turned by 90, 180 or 270 degrees
+#[cfg(feature = "framebuffer")]
+pub mod rotation;
+#[cfg(feature = "framebuffer")]
+pub use rotation::ScreenRotation;
+
+/// Screenshots as PNG images
+#[cfg(all(feature = "framebuffer", feature = "image"))]
+pub mod export;
+
+/// Decoding PNG, JPEG and WebP images to draw
+#[cfg(feature = "image")]
+pub mod decode;
+
 pub use cgmath;
 
 pub trait FramebufferIO {
@@ -40,12 +92,263 @@ mod graphics;
 #[cfg(feature = "framebuffer-drawing")]
 pub mod draw;
 
+/// Pen strokes rendered with round, chisel and textured brushes
+#[cfg(all(feature = "framebuffer-drawing", feature = "stroke"))]
+pub mod brush;
+
+/// Loading fonts at runtime and falling back between them
+#[cfg(feature = "framebuffer-text-drawing")]
+pub mod fonts;
+
+/// Ordering right-to-left text and joining Arabic letters for drawing
+#[cfg(feature = "text-shaping")]
+pub mod shaping;
+
+/// Drawing SVG documents, like icons
+#[cfg(feature = "svg")]
+pub mod vector;
+
+/// Text laid out along paths and circle arcs
+#[cfg(feature = "framebuffer-text-drawing")]
+pub mod textpath;
+
+/// Which parts of a self-intersecting polygon are inside, by how often its outline
+/// winds around them
+#[cfg(feature = "framebuffer-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FillRule {
+    /// Wound around at all, counting clockwise and counterclockwise as opposites
+    NonZero,
+    /// Wound around an odd number of times, leaving holes where it overlaps itself
+    EvenOdd,
+}
+
+/// What to fill shapes with. E-ink shows only 16 grays, so gradients are dithered to
+/// those.
+#[cfg(feature = "framebuffer-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Fill {
+    Solid(common::color),
+    /// Going from `start` at `from` to `end` at `to`, and staying the same past either
+    LinearGradient {
+        from: cgmath::Point2<f32>,
+        to: cgmath::Point2<f32>,
+        start: common::color,
+        end: common::color,
+    },
+    /// An 8x8 bitmap repeated from the top left of the framebuffer, with a row per byte
+    /// and its most significant bit on the left. Set bits are `fg`, the others `bg`.
+    Pattern {
+        bits: [u8; 8],
+        fg: common::color,
+        bg: common::color,
+    },
+}
+
+#[cfg(feature = "framebuffer-drawing")]
+impl Fill {
+    /// The color of the pixel at `pos`
+    pub fn color_at(&self, pos: cgmath::Point2<i32>) -> common::color {
+        match *self {
+            Fill::Solid(c) => c,
+            Fill::LinearGradient {
+                from,
+                to,
+                start,
+                end,
+            } => {
+                use cgmath::InnerSpace;
+                let along = to - from;
+                let center = cgmath::Point2 {
+                    x: pos.x as f32 + 0.5,
+                    y: pos.y as f32 + 0.5,
+                };
+                let t = if along.magnitude2() > 0.0 {
+                    ((center - from).dot(along) / along.magnitude2()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let (start, end) = (graphics::brightness(start), graphics::brightness(end));
+                graphics::dither(start + (end - start) * t, pos)
+            }
+            Fill::Pattern { bits, fg, bg } => {
+                let row = bits[pos.y.rem_euclid(8) as usize];
+                if row & (0x80 >> pos.x.rem_euclid(8)) != 0 {
+                    fg
+                } else {
+                    bg
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "framebuffer-drawing")]
+impl From<common::color> for Fill {
+    fn from(c: common::color) -> Fill {
+        Fill::Solid(c)
+    }
+}
+
+/// How the ends of lines, and of their dashes, look
+#[cfg(feature = "framebuffer-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineCap {
+    /// Cut off square at the end
+    Butt,
+    /// Extended by half the width past the end, cut off square
+    Square,
+    /// Rounded off with a half circle past the end
+    Round,
+}
+
+/// How the segments of a polyline meet at its corners
+#[cfg(feature = "framebuffer-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineJoin {
+    /// Extended to a point, or cut off like `Bevel` where that would reach more than 4
+    /// times the width past the corner
+    Miter,
+    /// Rounded off with a circle around the corner
+    Round,
+    /// Cut off straight across the outside of the corner
+    Bevel,
+}
+
+/// How to draw a line besides its width and color
+#[cfg(feature = "framebuffer-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StrokeStyle<'a> {
+    /// Lengths of the dashes and the gaps between them in pixels, starting with a dash
+    /// and repeated along the line. Odd lengths are repeated twice over, so `[4]` is 4
+    /// on, 4 off. Empty for a solid line.
+    pub dash: &'a [u32],
+    pub cap: LineCap,
+}
+
+#[cfg(feature = "framebuffer-drawing")]
+impl<'a> StrokeStyle<'a> {
+    pub const SOLID: StrokeStyle<'static> = StrokeStyle {
+        dash: &[],
+        cap: LineCap::Butt,
+    };
+
+    pub fn dashed(dash: &'a [u32]) -> StrokeStyle<'a> {
+        StrokeStyle {
+            dash,
+            cap: LineCap::Butt,
+        }
+    }
+
+    pub fn cap(mut self, cap: LineCap) -> StrokeStyle<'a> {
+        self.cap = cap;
+        self
+    }
+}
+
+/// The radii of the corners of a rounded rectangle in pixels
+#[cfg(feature = "framebuffer-drawing")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CornerRadii {
+    pub top_left: u32,
+    pub top_right: u32,
+    pub bottom_right: u32,
+    pub bottom_left: u32,
+}
+
+#[cfg(feature = "framebuffer-drawing")]
+impl CornerRadii {
+    /// All corners rounded alike
+    pub fn uniform(radius: u32) -> CornerRadii {
+        CornerRadii {
+            top_left: radius,
+            top_right: radius,
+            bottom_right: radius,
+            bottom_left: radius,
+        }
+    }
+}
+
+/// How to draw text
+#[cfg(feature = "framebuffer-text-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextStyle {
+    /// Filled in, like `draw_text`
+    Fill(common::color),
+    /// Hollow, only outlined by a line `width` thick around the glyphs
+    Outline { color: common::color, width: u32 },
+    /// Filled in with `fill` and outlined around with `outline`
+    FilledOutline {
+        fill: common::color,
+        outline: common::color,
+        width: u32,
+    },
+}
+
+/// How to sample an image drawn scaled or rotated
+#[cfg(feature = "framebuffer-drawing")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The closest pixel, fastest and keeping hard edges
+    Nearest,
+    /// Mixing the four closest pixels, smoother
+    Bilinear,
+}
+
+/// How `FramebufferDraw::draw_image` turns the colors of an image into grays
+#[cfg(feature = "image")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageImportOptions {
+    pub dither: common::dither_algorithm,
+    /// 1.0 keeps the contrast, larger values spread the grays apart from the middle
+    pub contrast: f32,
+    /// 1.0 keeps the grays, larger values brighten the midtones and smaller ones
+    /// darken them
+    pub gamma: f32,
+}
+
+#[cfg(feature = "image")]
+impl Default for ImageImportOptions {
+    fn default() -> ImageImportOptions {
+        ImageImportOptions {
+            dither: common::dither_algorithm::FLOYD_STEINBERG,
+            contrast: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+impl ImageImportOptions {
+    /// The gray, from 0 for black to 255 for white, to draw for a pixel of `rgb`
+    pub fn gray(&self, rgb: [u8; 3]) -> f32 {
+        let [r, g, b] = rgb.map(f32::from);
+        let luma = (0.299 * r + 0.587 * g + 0.114 * b) / 255.0;
+        let contrasted = ((luma - 0.5) * self.contrast + 0.5).clamp(0.0, 1.0);
+        contrasted.powf(1.0 / self.gamma.max(f32::EPSILON)) * 255.0
+    }
+}
+
 #[cfg(feature = "framebuffer-drawing")]
 pub trait FramebufferDraw {
     #[cfg(feature = "image")]
-    /// Draws `img` at `pos` with 1:1 scaling
-    fn draw_image(&mut self, img: &image::RgbImage, pos: cgmath::Point2<i32>)
-        -> common::mxcfb_rect;
+    /// Draws `img` at `pos` with 1:1 scaling, in grays as set by `options`
+    fn draw_image(
+        &mut self,
+        img: &image::RgbImage,
+        pos: cgmath::Point2<i32>,
+        options: ImageImportOptions,
+    ) -> common::mxcfb_rect;
+    #[cfg(feature = "image")]
+    /// Draws `img` with `transform` mapping its pixel coordinates to the framebuffer's,
+    /// e.g. rotated, scaled and moved with
+    /// `Matrix3::from_translation(pos) * Matrix3::from_angle_z(angle) * Matrix3::from_scale(zoom)`
+    fn draw_image_transformed(
+        &mut self,
+        img: &image::RgbImage,
+        transform: cgmath::Matrix3<f32>,
+        interpolation: Interpolation,
+    ) -> common::mxcfb_rect;
     /// Draws a straight line
     fn draw_line(
         &mut self,
@@ -54,6 +357,35 @@ pub trait FramebufferDraw {
         width: u32,
         v: common::color,
     ) -> common::mxcfb_rect;
+    /// Draws a straight line `width` wide with round ends, anti-aliased by blending its
+    /// edges into what is already there
+    fn draw_line_aa(
+        &mut self,
+        start: cgmath::Point2<f32>,
+        end: cgmath::Point2<f32>,
+        width: f32,
+        v: common::color,
+    ) -> common::mxcfb_rect;
+    /// Draws a straight line in `style`, e.g. dashed
+    fn draw_line_styled(
+        &mut self,
+        start: cgmath::Point2<i32>,
+        end: cgmath::Point2<i32>,
+        width: u32,
+        v: common::color,
+        style: &StrokeStyle,
+    ) -> common::mxcfb_rect;
+    /// Draws the lines connecting `points` as a single stroke `width` wide, meeting as
+    /// `join` at the corners, with the ends as `cap`. Every pixel is written once, so
+    /// it blends evenly.
+    fn draw_polyline(
+        &mut self,
+        points: &[cgmath::Point2<f32>],
+        width: f32,
+        join: LineJoin,
+        cap: LineCap,
+        v: common::color,
+    ) -> common::mxcfb_rect;
     /// Draws a circle using Bresenham circle algorithm
     fn draw_circle(
         &mut self,
@@ -61,12 +393,33 @@ pub trait FramebufferDraw {
         rad: u32,
         c: common::color,
     ) -> common::mxcfb_rect;
+    /// Draws the arc `width` wide around `pos` from `start_angle` to `end_angle`, in
+    /// radians clockwise from the x axis, e.g. for a progress indicator. Goes
+    /// counterclockwise if `end_angle` is less.
+    fn draw_arc(
+        &mut self,
+        pos: cgmath::Point2<i32>,
+        rad: u32,
+        start_angle: f32,
+        end_angle: f32,
+        width: u32,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Fills the slice of a circle between `start_angle` and `end_angle`, like `draw_arc`
+    fn fill_pie(
+        &mut self,
+        pos: cgmath::Point2<i32>,
+        rad: u32,
+        start_angle: f32,
+        end_angle: f32,
+        c: common::color,
+    ) -> common::mxcfb_rect;
     /// Fills a circle
     fn fill_circle(
         &mut self,
         pos: cgmath::Point2<i32>,
         rad: u32,
-        c: common::color,
+        fill: impl Into<Fill>,
     ) -> common::mxcfb_rect;
     /// Draws a polygon
     fn draw_polygon(
@@ -75,6 +428,14 @@ pub trait FramebufferDraw {
         fill: bool,
         c: common::color,
     ) -> common::mxcfb_rect;
+    /// Fills the polygon with the corners `points`, with `rule` deciding about the
+    /// parts it overlaps itself
+    fn fill_polygon(
+        &mut self,
+        points: &[cgmath::Point2<i32>],
+        fill: impl Into<Fill>,
+        rule: FillRule,
+    ) -> common::mxcfb_rect;
     /// Draws a bezier curve begining at `startpt`, with control point `ctrlpt`, ending at `endpt` with `color`
     fn draw_bezier(
         &mut self,
@@ -95,6 +456,15 @@ pub trait FramebufferDraw {
         samples: i32,
         v: common::color,
     ) -> common::mxcfb_rect;
+    /// Fills the ribbon along the bezier curve with the control points `points`, 3 for
+    /// a quadratic or 4 for a cubic curve, going from `widths.0` wide at the start to
+    /// `widths.1` at the end, e.g. for a pressure sensitive stroke
+    fn draw_bezier_variable_width(
+        &mut self,
+        points: &[cgmath::Point2<f32>],
+        widths: (f32, f32),
+        v: common::color,
+    ) -> common::mxcfb_rect;
     /// Draws `text` at `pos` with `color` using scale `size`
     #[cfg(feature = "framebuffer-text-drawing")]
     fn draw_text(
@@ -105,6 +475,26 @@ pub trait FramebufferDraw {
         col: common::color,
         dryrun: bool,
     ) -> common::mxcfb_rect;
+    /// Lays out `spans` as a paragraph wrapped at `width`, with `pos` at its top left,
+    /// and draws it. Returns the area it takes, which is all `dryrun` does.
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn draw_spans(
+        &mut self,
+        pos: cgmath::Point2<f32>,
+        width: f32,
+        spans: &[draw::TextSpan],
+        dryrun: bool,
+    ) -> common::mxcfb_rect;
+    /// Draws `text` like `draw_text`, but in `style`, e.g. hollow for headings
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn draw_text_styled(
+        &mut self,
+        pos: cgmath::Point2<f32>,
+        text: &str,
+        size: f32,
+        style: TextStyle,
+        dryrun: bool,
+    ) -> common::mxcfb_rect;
     /// Draws a 1px border rectangle of size `size` at `pos` with `border_px` border thickness
     fn draw_rect(
         &mut self,
@@ -113,9 +503,43 @@ pub trait FramebufferDraw {
         border_px: u32,
         c: common::color,
     );
+    /// Draws a rectangle like `draw_rect` in `style`, e.g. dashed for a selection. The
+    /// dashes continue around the corners, clockwise from the top left.
+    fn draw_rect_styled(
+        &mut self,
+        pos: cgmath::Point2<i32>,
+        size: cgmath::Vector2<u32>,
+        border_px: u32,
+        c: common::color,
+        style: &StrokeStyle,
+    ) -> common::mxcfb_rect;
+    /// Draws the border of a rectangle with rounded corners, `border_px` thick on the
+    /// inside
+    fn draw_rounded_rect(
+        &mut self,
+        pos: cgmath::Point2<i32>,
+        size: cgmath::Vector2<u32>,
+        radii: CornerRadii,
+        border_px: u32,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Fills a rectangle with rounded corners
+    fn fill_rounded_rect(
+        &mut self,
+        pos: cgmath::Point2<i32>,
+        size: cgmath::Vector2<u32>,
+        radii: CornerRadii,
+        c: common::color,
+    ) -> common::mxcfb_rect;
     /// Fills rectangle of size `size` at `pos`
-    fn fill_rect(&mut self, pos: cgmath::Point2<i32>, size: cgmath::Vector2<u32>, c: common::color);
-    /// Clears the framebuffer however does not perform a refresh
+    fn fill_rect(
+        &mut self,
+        pos: cgmath::Point2<i32>,
+        size: cgmath::Vector2<u32>,
+        fill: impl Into<Fill>,
+    );
+    /// Clears the framebuffer, or only the clip rectangle if there is one, however does
+    /// not perform a refresh
     fn clear(&mut self);
 }
 
@@ -129,9 +553,13 @@ pub trait FramebufferBase {
     /// Toggles update scheme
     fn set_update_scheme(&mut self, scheme: u32);
     /// Creates a FixScreeninfo struct and fills it using ioctl
-    fn get_fix_screeninfo(device: &std::fs::File) -> screeninfo::FixScreeninfo;
+    fn get_fix_screeninfo(
+        device: &std::fs::File,
+    ) -> Result<screeninfo::FixScreeninfo, FramebufferError>;
     /// Creates a VarScreeninfo struct and fills it using ioctl
-    fn get_var_screeninfo(device: &std::fs::File) -> screeninfo::VarScreeninfo;
+    fn get_var_screeninfo(
+        device: &std::fs::File,
+    ) -> Result<screeninfo::VarScreeninfo, FramebufferError>;
     /// Makes the proper ioctl call to set the VarScreenInfo.
     /// You must first update the contents of self.var_screen_info
     /// and then call this function.
@@ -143,14 +571,61 @@ pub trait FramebufferBase {
     fn update_var_screeninfo(&mut self) -> bool;
 }
 
+/// How drawn pixels combine with what is already there, see
+/// `Framebuffer::set_blend_mode`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Replaces what is there
+    #[default]
+    Opaque,
+    /// Mixes in the color with an opacity of 0 (none) to 255 (opaque)
+    SourceOver(u8),
+    /// Darkens what is there by the color, like ink on paper
+    Multiply,
+    /// Keeps the lighter of both
+    Lighten,
+    /// Keeps the darker of both. Ink stays black under a light gray, and drawing over
+    /// the same place again doesn't change it, which makes it the one for highlighters.
+    Darken,
+}
+
+impl BlendMode {
+    /// `over` drawn onto `under`
+    pub fn blend(self, under: common::color, over: common::color) -> common::color {
+        let (under, over) = (under.to_rgb8(), over.to_rgb8());
+        let channel = |i: usize| {
+            let (u, o) = (u16::from(under[i]), u16::from(over[i]));
+            (match self {
+                BlendMode::Opaque => o,
+                BlendMode::SourceOver(alpha) => {
+                    let alpha = u16::from(alpha);
+                    (u * (255 - alpha) + o * alpha + 127) / 255
+                }
+                BlendMode::Multiply => (u * o + 127) / 255,
+                BlendMode::Lighten => u.max(o),
+                BlendMode::Darken => u.min(o),
+            }) as u8
+        };
+        common::color::RGB(channel(0), channel(1), channel(2))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PartialRefreshMode {
     DryRun,
     Async,
     Wait,
 }
 
+/// Futures completing with refreshes
+#[cfg(feature = "async")]
+pub mod completion;
+#[cfg(feature = "framebuffer")]
+pub mod ghosting;
 #[cfg(feature = "framebuffer")]
 pub mod refresh;
+#[cfg(feature = "framebuffer")]
+pub mod watchdog;
 pub trait FramebufferRefresh {
     /// Refreshes the entire screen with the provided parameters. If `wait_completion` is
     /// set to true, doesn't return before the refresh has been completed. Returns the marker.
@@ -202,6 +677,36 @@ pub trait FramebufferRefresh {
         force_full_refresh: bool,
     ) -> u32;
 
+    /// Like `full_refresh`, with the settings of `profile`
+    fn full_refresh_with(&self, profile: &RefreshProfile, wait_completion: bool) -> u32 {
+        self.full_refresh(
+            profile.waveform_mode,
+            profile.temperature,
+            profile.dither_mode,
+            profile.quant_bit,
+            wait_completion,
+        )
+    }
+
+    /// Like `partial_refresh`, with the settings of `profile`
+    fn partial_refresh_with(
+        &self,
+        region: &common::mxcfb_rect,
+        mode: PartialRefreshMode,
+        profile: &RefreshProfile,
+        force_full_refresh: bool,
+    ) -> u32 {
+        self.partial_refresh(
+            region,
+            mode,
+            profile.waveform_mode,
+            profile.temperature,
+            profile.dither_mode,
+            profile.quant_bit,
+            force_full_refresh,
+        )
+    }
+
     /// Takes a marker returned by `partial_refresh` and blocks until that
     /// refresh has been reflected on the display.
     /// Returns the collusion_test result which is supposed to be
@@ -0,0 +1,184 @@
+//! Drawing off the display.
+//!
+//! An `OffscreenCanvas` is a framebuffer in memory, drawn on like the display with
+//! everything that draws on a `Framebuffer`, `FramebufferDraw` included. Complex
+//! scenes and widgets can be drawn into one and put on the display at once with
+//! `Framebuffer::composite`, followed by a single partial refresh, so the display
+//! never shows them half drawn. Canvases can also be layers composited onto each
+//! other.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::framebuffer::cgmath::{Point2, Vector2};
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core;
+use crate::framebuffer::{BlendMode, FramebufferIO};
+
+/// A framebuffer in memory to draw on before compositing it, see the module
+/// documentation. Refreshing it does nothing.
+pub struct OffscreenCanvas {
+    fb: core::Framebuffer,
+}
+
+impl OffscreenCanvas {
+    /// `width` by `height` pixels, white
+    pub fn new(width: u32, height: u32) -> OffscreenCanvas {
+        OffscreenCanvas {
+            fb: core::Framebuffer::headless(width, height),
+        }
+    }
+
+    pub fn size(&self) -> Vector2<u32> {
+        Vector2 {
+            x: self.fb.var_screen_info.xres,
+            y: self.fb.var_screen_info.yres,
+        }
+    }
+}
+
+impl Deref for OffscreenCanvas {
+    type Target = core::Framebuffer;
+
+    fn deref(&self) -> &core::Framebuffer {
+        &self.fb
+    }
+}
+
+impl DerefMut for OffscreenCanvas {
+    fn deref_mut(&mut self) -> &mut core::Framebuffer {
+        &mut self.fb
+    }
+}
+
+impl core::Framebuffer {
+    /// Draws `canvas` with its top left corner at `dest`, combined with what is
+    /// already there by `blend_mode` and within the clip rectangle, without
+    /// refreshing. Returns the area to refresh.
+    pub fn composite(
+        &mut self,
+        canvas: &OffscreenCanvas,
+        dest: Point2<i32>,
+        blend_mode: BlendMode,
+    ) -> mxcfb_rect {
+        let size = canvas.size();
+        let mut bounds = (
+            dest.x.max(0),
+            dest.y.max(0),
+            (dest.x + size.x as i32).min(self.var_screen_info.xres as i32),
+            (dest.y + size.y as i32).min(self.var_screen_info.yres as i32),
+        );
+        if let Some(clip) = self.clip() {
+            bounds = (
+                bounds.0.max(clip.left as i32),
+                bounds.1.max(clip.top as i32),
+                bounds.2.min((clip.left + clip.width) as i32),
+                bounds.3.min((clip.top + clip.height) as i32),
+            );
+        }
+        let (left, top, right, bottom) = bounds;
+        if right <= left || bottom <= top {
+            return mxcfb_rect::invalid();
+        }
+        let area = mxcfb_rect {
+            left: left as u32,
+            top: top as u32,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        };
+        let source = mxcfb_rect {
+            left: (left - dest.x) as u32,
+            top: (top - dest.y) as u32,
+            ..area
+        };
+
+        if blend_mode == BlendMode::Opaque {
+            // Copied a row at a time, both are in the same pixel format
+            let copied = canvas
+                .dump_region(source)
+                .and_then(|data| self.restore_region(area, &data));
+            if copied.is_ok() {
+                return area;
+            }
+        }
+        let previous = std::mem::replace(&mut self.blend_mode, blend_mode);
+        for y in 0..area.height {
+            for x in 0..area.width {
+                let c = canvas.read_pixel(Point2 {
+                    x: source.left + x,
+                    y: source.top + y,
+                });
+                self.write_pixel(
+                    Point2 {
+                        x: (area.left + x) as i32,
+                        y: (area.top + y) as i32,
+                    },
+                    c,
+                );
+            }
+        }
+        self.blend_mode = previous;
+        area
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::FramebufferDraw;
+
+    #[test]
+    fn test_composite() {
+        // A black square in the top left quarter of a white layer
+        let mut canvas = OffscreenCanvas::new(20, 10);
+        canvas.fill_rect(Point2 { x: 0, y: 0 }, Vector2 { x: 10, y: 5 }, color::BLACK);
+        assert_eq!(canvas.size(), Vector2 { x: 20, y: 10 });
+
+        let gray = color::GRAY(0x80);
+        let mut fb = core::Framebuffer::headless(50, 50);
+        fb.fill_rect(Point2 { x: 0, y: 0 }, Vector2 { x: 50, y: 50 }, gray);
+        let (black, white, gray) = (
+            color::BLACK.to_rgb8(),
+            color::WHITE.to_rgb8(),
+            gray.to_rgb8(),
+        );
+        let pixel = |fb: &core::Framebuffer, x, y| fb.read_pixel(Point2 { x, y }).to_rgb8();
+
+        // Partly off the display
+        let rect = fb.composite(&canvas, Point2 { x: -5, y: 45 }, BlendMode::Opaque);
+        assert_eq!(
+            rect,
+            mxcfb_rect {
+                left: 0,
+                top: 45,
+                width: 15,
+                height: 5
+            }
+        );
+        assert_eq!(pixel(&fb, 4, 49), black);
+        assert_eq!(pixel(&fb, 5, 49), white);
+        assert_eq!(pixel(&fb, 15, 49), gray);
+        assert!(fb.take_refreshes().is_empty());
+
+        // The white of the layer doesn't cover what is below
+        fb.composite(&canvas, Point2 { x: 10, y: 10 }, BlendMode::Darken);
+        assert_eq!(pixel(&fb, 12, 12), black);
+        assert_eq!(pixel(&fb, 25, 12), gray);
+        assert_eq!(fb.blend_mode(), BlendMode::Opaque);
+
+        fb.push_clip(mxcfb_rect {
+            left: 30,
+            top: 30,
+            width: 5,
+            height: 5,
+        });
+        let rect = fb.composite(&canvas, Point2 { x: 28, y: 28 }, BlendMode::Opaque);
+        assert_eq!(
+            (rect.left, rect.top, rect.width, rect.height),
+            (30, 30, 5, 5)
+        );
+        assert_eq!(pixel(&fb, 29, 29), gray);
+        assert_eq!(pixel(&fb, 30, 30), black);
+        assert_eq!(pixel(&fb, 34, 29), gray);
+    }
+}
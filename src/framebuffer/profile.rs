@@ -0,0 +1,173 @@
+use crate::device::Model;
+use crate::framebuffer::common::{display_temp, dither_mode, waveform_mode, DRAWING_QUANT_BIT};
+
+/// The settings of a refresh besides its region, for use with
+/// `FramebufferRefresh::partial_refresh_with` or as the default of a framebuffer
+#[derive(Copy, Clone, Debug)]
+pub struct RefreshProfile {
+    pub waveform_mode: waveform_mode,
+    pub temperature: display_temp,
+    pub dither_mode: dither_mode,
+    pub quant_bit: i32,
+}
+
+impl RefreshProfile {
+    /// Fastest, black and white only, for pen strokes
+    pub const INK: RefreshProfile = RefreshProfile {
+        waveform_mode: waveform_mode::WAVEFORM_MODE_DU,
+        temperature: display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode: dither_mode::EPDC_FLAG_EXP1,
+        quant_bit: DRAWING_QUANT_BIT,
+    };
+
+    /// Medium fidelity for UI elements, like xochitl's
+    pub const UI: RefreshProfile = RefreshProfile {
+        waveform_mode: waveform_mode::WAVEFORM_MODE_GC16_FAST,
+        temperature: display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode: dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        quant_bit: 0,
+    };
+
+    /// All 16 grays, for images
+    pub const IMAGE: RefreshProfile = RefreshProfile {
+        waveform_mode: waveform_mode::WAVEFORM_MODE_GC16,
+        temperature: display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode: dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        quant_bit: 0,
+    };
+
+    /// Flashes less when turning pages of mostly text on white
+    pub const TEXT_PAGE: RefreshProfile = RefreshProfile {
+        waveform_mode: waveform_mode::WAVEFORM_MODE_GL16_FAST,
+        temperature: display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode: dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        quant_bit: 0,
+    };
+
+    /// The profile called `name`: "ink", "ui", "image" or "text-page"
+    pub fn named(name: &str) -> Option<RefreshProfile> {
+        match name {
+            "ink" => Some(RefreshProfile::INK),
+            "ui" => Some(RefreshProfile::UI),
+            "image" => Some(RefreshProfile::IMAGE),
+            "text-page" => Some(RefreshProfile::TEXT_PAGE),
+            _ => None,
+        }
+    }
+}
+
+impl Default for RefreshProfile {
+    fn default() -> RefreshProfile {
+        RefreshProfile::UI
+    }
+}
+
+/// What a refresh is for, to pick its settings with `Framebuffer::refresh_auto`
+/// without knowing the waveforms of the display
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RefreshHint {
+    /// Pen strokes, as fast as possible
+    Ink,
+    /// Black and white UI, like text and outlines, fast without flashing
+    UiMonochrome,
+    /// Grays, like images and shaded UI
+    Image,
+    /// Everything on the display as it should look, flashing to clear the
+    /// ghosting left behind by faster refreshes
+    FullQuality,
+}
+
+impl RefreshHint {
+    /// The settings for this on the display of `model`. The EPDC of the
+    /// reMarkable 1 dithers pen strokes itself, while the rm2fb server of the
+    /// reMarkable 2 ignores dithering and picks the temperature itself.
+    pub fn profile(self, model: Model) -> RefreshProfile {
+        let profile = match self {
+            RefreshHint::Ink => RefreshProfile::INK,
+            RefreshHint::UiMonochrome => RefreshProfile {
+                waveform_mode: waveform_mode::WAVEFORM_MODE_DU,
+                ..RefreshProfile::UI
+            },
+            RefreshHint::Image => RefreshProfile::IMAGE,
+            RefreshHint::FullQuality => RefreshProfile {
+                temperature: display_temp::TEMP_USE_AMBIENT,
+                ..RefreshProfile::IMAGE
+            },
+        };
+        match model {
+            Model::Gen1 => profile,
+            Model::Gen2 | Model::PaperPro => RefreshProfile {
+                temperature: display_temp::TEMP_USE_AMBIENT,
+                dither_mode: dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                quant_bit: 0,
+                ..profile
+            },
+        }
+    }
+
+    /// Whether refreshes for this flash, see `FramebufferRefresh::partial_refresh`
+    pub fn is_full(self) -> bool {
+        self == RefreshHint::FullQuality
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::common::{mxcfb_rect, update_mode};
+    use crate::framebuffer::core::Framebuffer;
+    use crate::framebuffer::{FramebufferRefresh, PartialRefreshMode};
+
+    #[test]
+    fn test_profiles() {
+        assert!(RefreshProfile::named("paper").is_none());
+        let ink = RefreshProfile::named("ink").unwrap();
+
+        let mut fb = Framebuffer::headless(100, 100);
+        let region = mxcfb_rect {
+            left: 10,
+            top: 10,
+            width: 20,
+            height: 20,
+        };
+        fb.partial_refresh_with(&region, PartialRefreshMode::Async, &ink, false);
+        fb.set_refresh_profile(RefreshProfile::named("text-page").unwrap());
+        fb.refresh(&region, PartialRefreshMode::Async);
+
+        let refreshes = fb.take_refreshes();
+        assert_eq!(refreshes.len(), 2);
+        assert_eq!(
+            refreshes[0].waveform_mode,
+            waveform_mode::WAVEFORM_MODE_DU as u32
+        );
+        assert_eq!(refreshes[0].quant_bit, DRAWING_QUANT_BIT);
+        assert_eq!(
+            refreshes[1].waveform_mode,
+            waveform_mode::WAVEFORM_MODE_GL16_FAST as u32
+        );
+        assert_eq!(refreshes[1].update_region, region);
+
+        // Headless framebuffers refresh like the reMarkable 1
+        fb.refresh_auto(&region, RefreshHint::Ink, PartialRefreshMode::Async);
+        fb.refresh_auto(&region, RefreshHint::FullQuality, PartialRefreshMode::Async);
+        let refreshes = fb.take_refreshes();
+        assert_eq!(refreshes[0].quant_bit, DRAWING_QUANT_BIT);
+        assert_eq!(
+            refreshes[1].waveform_mode,
+            waveform_mode::WAVEFORM_MODE_GC16 as u32
+        );
+        assert_eq!(
+            refreshes[1].update_mode,
+            update_mode::UPDATE_MODE_FULL as u32
+        );
+        assert_eq!(refreshes[1].temp, display_temp::TEMP_USE_AMBIENT as i32);
+
+        let ink = RefreshHint::Ink.profile(Model::Gen2);
+        assert_eq!(
+            ink.waveform_mode as u32,
+            waveform_mode::WAVEFORM_MODE_DU as u32
+        );
+        assert_eq!(ink.quant_bit, 0);
+        assert!(!RefreshHint::UiMonochrome.is_full());
+    }
+}
@@ -1,5 +1,6 @@
+use std::fs::File;
 use std::os::unix::io::AsRawFd;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use log::warn;
 
@@ -7,7 +8,124 @@ use crate::framebuffer;
 use crate::framebuffer::core;
 use crate::framebuffer::core::FramebufferUpdate;
 use crate::framebuffer::mxcfb::*;
-use crate::framebuffer::{common, PartialRefreshMode};
+use crate::framebuffer::watchdog::Recovery;
+use crate::framebuffer::FramebufferBase;
+use crate::framebuffer::{common, FramebufferRefresh, PartialRefreshMode};
+
+static REFRESHES: AtomicU64 = AtomicU64::new(0);
+static REFRESHED_PIXELS: AtomicU64 = AtomicU64::new(0);
+
+/// The refreshes sent by all framebuffers of the process so far, e.g. to estimate
+/// their share of the power usage. Collision tests don't count.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RefreshActivity {
+    pub refreshes: u64,
+    pub pixels: u64,
+}
+
+pub fn activity() -> RefreshActivity {
+    RefreshActivity {
+        refreshes: REFRESHES.load(Ordering::Relaxed),
+        pixels: REFRESHED_PIXELS.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn wait_for_update(device: &File, update_marker: u32) -> u32 {
+    let mut markerdata = mxcfb_update_marker_data {
+        update_marker,
+        collision_test: 0,
+    };
+    if (unsafe {
+        libc::ioctl(
+            device.as_raw_fd(),
+            common::MXCFB_WAIT_FOR_UPDATE_COMPLETE,
+            &mut markerdata,
+        )
+    }) < 0
+    {
+        warn!("WAIT_FOR_UPDATE_COMPLETE failed");
+    }
+    markerdata.collision_test
+}
+
+impl core::Framebuffer {
+    /// Tries to get the display controller going again after a refresh hung
+    fn recover(&self, recovery: Recovery) {
+        if let (Recovery::Reinit, FramebufferUpdate::Ioctl(device)) =
+            (recovery, &self.framebuffer_update)
+        {
+            let mut var_screen_info = self.var_screen_info.clone();
+            if !core::Framebuffer::put_var_screeninfo(device, &mut var_screen_info) {
+                warn!("FBIOPUT_VSCREENINFO failed while recovering from a hung refresh");
+            }
+        }
+        self.full_refresh(
+            common::waveform_mode::WAVEFORM_MODE_GC16,
+            common::display_temp::TEMP_USE_REMARKABLE_DRAW,
+            common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        );
+    }
+
+    fn send_update(&self, update: &mxcfb_update_data) -> bool {
+        let succeeded = match &self.framebuffer_update {
+            FramebufferUpdate::Ioctl(device) => {
+                let pt: *const mxcfb_update_data = update;
+                (unsafe { libc::ioctl(device.as_raw_fd(), common::MXCFB_SEND_UPDATE, pt) }) >= 0
+            }
+            FramebufferUpdate::Shm(shm_client) => shm_client.send_damage(update),
+            FramebufferUpdate::Headless(refreshes) => {
+                refreshes.lock().unwrap().push(*update);
+                true
+            }
+            #[cfg(feature = "compositor")]
+            FramebufferUpdate::Compositor(connection) => connection.send_damage(update),
+            FramebufferUpdate::Backend(backend) => backend.send_update(&self.pixels(), update),
+        };
+        if succeeded && update.flags & common::EPDC_FLAG_TEST_COLLISION == 0 {
+            let region = update.update_region;
+            REFRESHES.fetch_add(1, Ordering::Relaxed);
+            REFRESHED_PIXELS.fetch_add(
+                u64::from(region.width) * u64::from(region.height),
+                Ordering::Relaxed,
+            );
+        }
+        trace_io!(
+            "SEND_UPDATE marker={} mode={} waveform={} temp={} flags={:#x} dither={} quant_bit={} rect={:?} ok={}",
+            update.update_marker,
+            update.update_mode,
+            update.waveform_mode,
+            update.temp,
+            update.flags,
+            update.dither_mode,
+            update.quant_bit,
+            update.update_region,
+            succeeded
+        );
+        succeeded
+    }
+
+    /// Follows a partial refresh with a full one if the ghosting policy says so
+    fn clear_ghosting(&self, mode: &PartialRefreshMode, force_full_refresh: bool) {
+        let ghosting = match (&self.ghosting, mode) {
+            (None, _) | (_, PartialRefreshMode::DryRun) => return,
+            (Some(ghosting), _) => ghosting,
+        };
+        if force_full_refresh {
+            ghosting.full_refreshed();
+        } else if ghosting.partial_refreshed() {
+            let marker = self.full_refresh(
+                common::waveform_mode::WAVEFORM_MODE_GC16,
+                common::display_temp::TEMP_USE_AMBIENT,
+                common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+                false,
+            );
+            ghosting.notify(marker);
+        }
+    }
+}
 
 impl framebuffer::FramebufferRefresh for core::Framebuffer {
     fn full_refresh(
@@ -37,17 +155,14 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
             ..Default::default()
         };
 
-        let update_succeeded = match &self.framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                let pt: *const mxcfb_update_data = &whole;
-                (unsafe { libc::ioctl(device.as_raw_fd(), common::MXCFB_SEND_UPDATE, pt) }) >= 0
-            }
-            FramebufferUpdate::Swtfb(swtfb_client) => swtfb_client.send_mxcfb_update(&whole),
-        };
+        let update_succeeded = self.send_update(&whole);
 
         if !update_succeeded {
             warn!("Sending full_refresh update failed!")
         }
+        if let Some(ghosting) = &self.ghosting {
+            ghosting.full_refreshed();
+        }
 
         if wait_completion {
             self.wait_refresh_complete(whole.update_marker)
@@ -67,11 +182,10 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
         force_full_refresh: bool,
     ) -> u32 {
         let mut update_region = region.to_owned();
+        let screen = self.screen_size();
 
         // No accounting for this, out of bounds, entirely ignored
-        if update_region.left >= self.var_screen_info.xres
-            || update_region.top >= self.var_screen_info.yres
-        {
+        if update_region.left >= screen.x || update_region.top >= screen.y {
             return 0;
         }
 
@@ -84,15 +198,18 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
 
         // Dont try to refresh OOB horizontally
         let max_x = update_region.left + update_region.width;
-        if max_x > self.var_screen_info.xres {
-            update_region.width -= max_x - self.var_screen_info.xres;
+        if max_x > screen.x {
+            update_region.width -= max_x - screen.x;
         }
 
         // Dont try to refresh OOB vertically
         let max_y = update_region.top + update_region.height;
-        if max_y > self.var_screen_info.yres {
-            update_region.height -= max_y - self.var_screen_info.yres;
+        if max_y > screen.y {
+            update_region.height -= max_y - screen.y;
         }
+        let update_region = self
+            .rotation()
+            .rect_to_panel(update_region, self.panel_size());
 
         let update_mode = if force_full_refresh {
             common::update_mode::UPDATE_MODE_FULL as u32
@@ -116,17 +233,12 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
             ..Default::default()
         };
 
-        let update_succeeded = match &self.framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                let pt: *const mxcfb_update_data = &whole;
-                (unsafe { libc::ioctl(device.as_raw_fd(), common::MXCFB_SEND_UPDATE, pt) }) >= 0
-            }
-            FramebufferUpdate::Swtfb(swtfb_client) => swtfb_client.send_mxcfb_update(&whole),
-        };
+        let update_succeeded = self.send_update(&whole);
 
         if !update_succeeded {
             warn!("Sending partial_refresh update failed!")
         }
+        self.clear_ghosting(&mode, force_full_refresh);
 
         match mode {
             PartialRefreshMode::Wait | PartialRefreshMode::DryRun => {
@@ -137,29 +249,30 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
     }
 
     fn wait_refresh_complete(&self, update_marker: u32) -> u32 {
-        match &self.framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                let mut markerdata = mxcfb_update_marker_data {
+        #[cfg(feature = "trace-io")]
+        let started = std::time::Instant::now();
+        let collision = match &self.framebuffer_update {
+            FramebufferUpdate::Ioctl(device) => match (&self.watchdog, device.try_clone()) {
+                (Some(watchdog), Ok(device)) => watchdog.watch(
                     update_marker,
-                    collision_test: 0,
-                };
-                if (unsafe {
-                    libc::ioctl(
-                        device.as_raw_fd(),
-                        common::MXCFB_WAIT_FOR_UPDATE_COMPLETE,
-                        &mut markerdata,
-                    )
-                }) < 0
-                {
-                    warn!("WAIT_FOR_UPDATE_COMPLETE failed");
-                }
-                markerdata.collision_test
-            }
-            FramebufferUpdate::Swtfb(swtfb_client) => {
-                swtfb_client.wait_for_update_complete();
-                // Assume success
-                0
-            }
-        }
+                    move || wait_for_update(&device, update_marker),
+                    |recovery| self.recover(recovery),
+                ),
+                _ => wait_for_update(device, update_marker),
+            },
+            // The display server refreshes asynchronously
+            FramebufferUpdate::Shm(_) | FramebufferUpdate::Headless(_) => 0,
+            // The compositor refreshes asynchronously
+            #[cfg(feature = "compositor")]
+            FramebufferUpdate::Compositor(_) => 0,
+            FramebufferUpdate::Backend(backend) => backend.wait_for_update(update_marker),
+        };
+        trace_io!(
+            "WAIT_FOR_UPDATE_COMPLETE marker={} collision={} waited={:?}",
+            update_marker,
+            collision,
+            started.elapsed()
+        );
+        collision
     }
 }
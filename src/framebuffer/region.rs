@@ -0,0 +1,168 @@
+//! Refreshing areas that aren't rectangles.
+//!
+//! The bounding box of a diagonal stroke across the display covers all of it, while
+//! the stroke itself covers a sliver. `decompose` brings the pieces of such an area
+//! down to a few rectangles covering little more than the area, merging pieces that
+//! overlap or lie next to each other first, and then those whose merge adds the
+//! least. Every refresh has its own overhead, so fewer and slightly larger
+//! rectangles beat exact ones.
+
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core;
+use crate::framebuffer::PartialRefreshMode;
+
+/// How many rectangles `Framebuffer::refresh_rects` refreshes at most
+pub const MAX_REFRESH_RECTS: usize = 8;
+
+/// About as costly as refreshing this many more pixels is a refresh of its own
+const REFRESH_OVERHEAD: u64 = 16 * 16;
+
+fn area(rect: &mxcfb_rect) -> u64 {
+    u64::from(rect.width) * u64::from(rect.height)
+}
+
+/// Adds `rect` to `rects`, merging it with those it costs no more to refresh along
+/// with than on its own, and then the cheapest pairs until there are `max_rects`
+pub(crate) fn add_rect(rects: &mut Vec<mxcfb_rect>, rect: mxcfb_rect, max_rects: usize) {
+    if rect.width == 0 || rect.height == 0 {
+        return;
+    }
+    let mut rect = rect;
+    while let Some(i) = rects.iter().position(|other| {
+        area(&other.merge_rect(&rect)) <= area(other) + area(&rect) + REFRESH_OVERHEAD
+    }) {
+        rect = rect.merge_rect(&rects.swap_remove(i));
+    }
+    rects.push(rect);
+
+    while rects.len() > max_rects.max(1) {
+        let mut cheapest = (0, 1, u64::MAX);
+        for i in 0..rects.len() {
+            for j in i + 1..rects.len() {
+                let (a, b) = (&rects[i], &rects[j]);
+                let extra = area(&a.merge_rect(b)).saturating_sub(area(a) + area(b));
+                if extra < cheapest.2 {
+                    cheapest = (i, j, extra);
+                }
+            }
+        }
+        let merged = rects.swap_remove(cheapest.1);
+        rects[cheapest.0] = rects[cheapest.0].merge_rect(&merged);
+    }
+}
+
+/// At most `max_rects` rectangles covering all of `rects`
+pub fn decompose(rects: impl IntoIterator<Item = mxcfb_rect>, max_rects: usize) -> Vec<mxcfb_rect> {
+    let mut decomposed = Vec::new();
+    for rect in rects {
+        add_rect(&mut decomposed, rect, max_rects);
+    }
+    decomposed
+}
+
+/// At most `max_rects` rectangles covering the cells set in `mask`, a grid `width`
+/// cells wide stored row by row, e.g. one per pixel
+pub fn decompose_mask(mask: &[bool], width: u32, max_rects: usize) -> Vec<mxcfb_rect> {
+    if width == 0 {
+        return Vec::new();
+    }
+    // The runs of set cells in each row, with those continuing the same run of the
+    // row above merged into it
+    let mut done = Vec::new();
+    let mut open: Vec<mxcfb_rect> = Vec::new();
+    for (y, row) in mask.chunks(width as usize).enumerate() {
+        let mut runs = Vec::new();
+        let mut x = 0;
+        while x < row.len() {
+            if !row[x] {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < row.len() && row[x] {
+                x += 1;
+            }
+            runs.push((start as u32, (x - start) as u32));
+        }
+        let mut continued = Vec::with_capacity(runs.len());
+        for (left, run) in runs {
+            let rect = match open.iter().position(|r| r.left == left && r.width == run) {
+                Some(i) => {
+                    let mut rect = open.swap_remove(i);
+                    rect.height += 1;
+                    rect
+                }
+                None => mxcfb_rect {
+                    left,
+                    top: y as u32,
+                    width: run,
+                    height: 1,
+                },
+            };
+            continued.push(rect);
+        }
+        done.append(&mut open);
+        open = continued;
+    }
+    done.append(&mut open);
+    decompose(done, max_rects)
+}
+
+impl core::Framebuffer {
+    /// Refreshes the area covered by `rects` with the default profile, in at most
+    /// `MAX_REFRESH_RECTS` refreshes. Returns their markers.
+    pub fn refresh_rects(&self, rects: &[mxcfb_rect], mode: PartialRefreshMode) -> Vec<u32> {
+        decompose(rects.iter().copied(), MAX_REFRESH_RECTS)
+            .iter()
+            .map(|rect| self.refresh(rect, mode))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::rect;
+
+    #[test]
+    fn test_decompose() {
+        // A diagonal stroke, drawn in 10x10 pieces
+        let stroke: Vec<_> = (0..100).map(|i| rect(i * 10, i * 10, 12, 12)).collect();
+        let rects = decompose(stroke.iter().copied(), MAX_REFRESH_RECTS);
+        assert!(rects.len() <= MAX_REFRESH_RECTS);
+        for piece in &stroke {
+            assert!(rects.iter().any(|r| r.contains_rect(piece)));
+        }
+        // Far less than the bounding box of 1002x1002
+        let covered: u64 = rects.iter().map(area).sum();
+        assert!(covered < 1002 * 1002 / 4, "{}", covered);
+
+        // Side by side pieces become one
+        assert_eq!(
+            decompose([rect(0, 0, 10, 10), rect(10, 0, 10, 10)], 4),
+            [rect(0, 0, 20, 10)]
+        );
+        assert_eq!(decompose([rect(0, 0, 0, 10)], 4), []);
+
+        // An L shape and a dot
+        let mut mask = vec![false; 200 * 100];
+        for y in 0..100 {
+            mask[y * 200] = true;
+            mask[y * 200 + 1] = true;
+        }
+        for x in 0..80 {
+            mask[99 * 200 + x] = true;
+        }
+        mask[20 * 200 + 150] = true;
+        let mut rects = decompose_mask(&mask, 200, 4);
+        rects.sort_by_key(|r| (r.left, r.top));
+        assert_eq!(
+            rects,
+            [rect(0, 0, 2, 99), rect(0, 99, 80, 1), rect(150, 20, 1, 1)]
+        );
+
+        let fb = core::Framebuffer::headless(100, 100);
+        let markers = fb.refresh_rects(&stroke[..5], PartialRefreshMode::Async);
+        assert_eq!(markers.len(), fb.take_refreshes().len());
+    }
+}
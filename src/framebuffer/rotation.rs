@@ -0,0 +1,219 @@
+//! Drawing in landscape, or upside down.
+//!
+//! With a `ScreenRotation` set on a framebuffer, see `Framebuffer::set_rotation`, the
+//! coordinates of everything drawn, read and refreshed are those of the rotated
+//! screen, and `Framebuffer::screen_size` is its size. The framebuffer itself, and
+//! `var_screen_info` with it, stays in the orientation of the panel. Input is rotated
+//! along by `ApplicationContext::set_screen_rotation`.
+
+use crate::framebuffer::cgmath::{Point2, Vector2};
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core;
+
+/// How far the content is turned clockwise from the portrait orientation of the panel
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScreenRotation {
+    #[default]
+    Rot0,
+    /// Landscape, with the top of the content along the right edge of the panel
+    Rot90,
+    Rot180,
+    /// Landscape, with the top of the content along the left edge of the panel
+    Rot270,
+}
+
+impl ScreenRotation {
+    /// Whether width and height trade places
+    pub fn is_landscape(self) -> bool {
+        matches!(self, ScreenRotation::Rot90 | ScreenRotation::Rot270)
+    }
+
+    /// The size of the rotated screen of a panel of `panel` size
+    pub fn rotated_size(self, panel: Vector2<u32>) -> Vector2<u32> {
+        if self.is_landscape() {
+            Vector2 {
+                x: panel.y,
+                y: panel.x,
+            }
+        } else {
+            panel
+        }
+    }
+
+    /// Where the pixel at `pos` on the rotated screen is on a panel of `panel` size
+    pub fn to_panel(self, pos: Point2<u32>, panel: Vector2<u32>) -> Point2<u32> {
+        let (w, h) = (panel.x, panel.y);
+        match self {
+            ScreenRotation::Rot0 => pos,
+            ScreenRotation::Rot90 => Point2 {
+                x: w - 1 - pos.y,
+                y: pos.x,
+            },
+            ScreenRotation::Rot180 => Point2 {
+                x: w - 1 - pos.x,
+                y: h - 1 - pos.y,
+            },
+            ScreenRotation::Rot270 => Point2 {
+                x: pos.y,
+                y: h - 1 - pos.x,
+            },
+        }
+    }
+
+    /// Where `pos` on a panel of `panel` size is on the rotated screen, e.g. for input
+    pub fn from_panel(self, pos: Point2<f32>, panel: Vector2<u32>) -> Point2<f32> {
+        let (w, h) = ((panel.x - 1) as f32, (panel.y - 1) as f32);
+        match self {
+            ScreenRotation::Rot0 => pos,
+            ScreenRotation::Rot90 => Point2 {
+                x: pos.y,
+                y: w - pos.x,
+            },
+            ScreenRotation::Rot180 => Point2 {
+                x: w - pos.x,
+                y: h - pos.y,
+            },
+            ScreenRotation::Rot270 => Point2 {
+                x: h - pos.y,
+                y: pos.x,
+            },
+        }
+    }
+
+    /// Where `rect` on the rotated screen is on a panel of `panel` size. `rect` has
+    /// to be on the screen.
+    pub fn rect_to_panel(self, rect: mxcfb_rect, panel: Vector2<u32>) -> mxcfb_rect {
+        let (w, h) = (panel.x, panel.y);
+        match self {
+            ScreenRotation::Rot0 => rect,
+            ScreenRotation::Rot90 => mxcfb_rect {
+                left: w - rect.top - rect.height,
+                top: rect.left,
+                width: rect.height,
+                height: rect.width,
+            },
+            ScreenRotation::Rot180 => mxcfb_rect {
+                left: w - rect.left - rect.width,
+                top: h - rect.top - rect.height,
+                width: rect.width,
+                height: rect.height,
+            },
+            ScreenRotation::Rot270 => mxcfb_rect {
+                left: rect.top,
+                top: h - rect.left - rect.width,
+                width: rect.height,
+                height: rect.width,
+            },
+        }
+    }
+}
+
+impl core::Framebuffer {
+    /// The size of the panel, whatever the rotation
+    pub fn panel_size(&self) -> Vector2<u32> {
+        Vector2 {
+            x: self.var_screen_info.xres,
+            y: self.var_screen_info.yres,
+        }
+    }
+
+    /// The size of the screen as drawn on, see `set_rotation`
+    pub fn screen_size(&self) -> Vector2<u32> {
+        self.rotation().rotated_size(self.panel_size())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::Point2;
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::{FramebufferIO, PartialRefreshMode};
+
+    #[test]
+    fn test_screen_rotation() {
+        let panel = Vector2 { x: 40, y: 30 };
+        let rotations = [
+            ScreenRotation::Rot0,
+            ScreenRotation::Rot90,
+            ScreenRotation::Rot180,
+            ScreenRotation::Rot270,
+        ];
+        for rotation in rotations {
+            let size = rotation.rotated_size(panel);
+            let rect = mxcfb_rect {
+                left: 3,
+                top: 5,
+                width: 7,
+                height: 2,
+            };
+            let on_panel = rotation.rect_to_panel(rect, panel);
+            for (x, y) in [
+                (3, 5),
+                (9, 5),
+                (3, 6),
+                (9, 6),
+                (0, 0),
+                (size.x - 1, size.y - 1),
+            ] {
+                let pos = rotation.to_panel(Point2 { x, y }, panel);
+                assert!(pos.x < panel.x && pos.y < panel.y, "{:?}", rotation);
+                let back = rotation.from_panel(pos.cast().unwrap(), panel);
+                assert_eq!(back, Point2::new(x as f32, y as f32), "{:?}", rotation);
+                if rect.contains_point(&Point2 { x, y }) {
+                    assert!(on_panel.contains_point(&pos), "{:?}", rotation);
+                }
+            }
+        }
+
+        let mut fb = core::Framebuffer::headless(40, 30);
+        fb.set_rotation(ScreenRotation::Rot90);
+        assert_eq!(fb.screen_size(), Vector2 { x: 30, y: 40 });
+        // The top left of the content is the top right of the panel
+        fb.write_pixel(Point2 { x: 0, y: 0 }, color::BLACK);
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 0, y: 0 }).to_rgb8(),
+            color::BLACK.to_rgb8()
+        );
+        // Out of bounds of the panel, but not of the screen
+        fb.write_pixel(Point2 { x: 0, y: 39 }, color::BLACK);
+        fb.set_rotation(ScreenRotation::Rot0);
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 39, y: 0 }).to_rgb8(),
+            color::BLACK.to_rgb8()
+        );
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 0, y: 0 }).to_rgb8(),
+            color::BLACK.to_rgb8()
+        );
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 0, y: 29 }).to_rgb8(),
+            color::WHITE.to_rgb8()
+        );
+
+        fb.set_rotation(ScreenRotation::Rot270);
+        let region = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 10,
+            height: 100,
+        };
+        let dump = fb.dump_region(mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 30,
+            height: 40,
+        });
+        assert_eq!(dump.map(|d| d.len()), Ok(30 * 40 * 2));
+        fb.refresh(&region, PartialRefreshMode::Async);
+        assert_eq!(
+            fb.take_refreshes()[0].update_region,
+            mxcfb_rect {
+                left: 0,
+                top: 20,
+                width: 40,
+                height: 10,
+            }
+        );
+    }
+}
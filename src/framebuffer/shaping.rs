@@ -0,0 +1,304 @@
+//! Arabic and Hebrew text, shaped and put in display order.
+//!
+//! Text is stored in the order it is read, so a right-to-left word comes out
+//! mirrored when its characters are drawn left to right, and Arabic letters are
+//! drawn apart unless each takes the form joining its neighbours. `shape` turns a
+//! line of text into the characters to draw from left to right instead:
+//!
+//! - Arabic letters are replaced by their initial, medial, final or isolated
+//!   presentation forms, and lam-alef by its ligature. The fonts need glyphs for
+//!   those forms, like DejaVu has, see `fonts::FontCollection` for adding one.
+//! - Runs of right-to-left text are reversed, with numbers and left-to-right words
+//!   in them kept in order, and brackets in them mirrored. The direction of the whole
+//!   line is that of its first letter.
+//!
+//! This covers what is needed for labels and notes with simple bidirectional text,
+//! without the complete Unicode bidirectional algorithm and OpenType shaping.
+
+use std::borrow::Cow;
+
+/// Forms of an Arabic letter: isolated, final, initial and medial, with 0 for those
+/// it doesn't take because it doesn't join the next letter
+const ARABIC_FORMS: [(char, [u32; 4]); 36] = [
+    ('\u{0621}', [0xFE80, 0, 0, 0]),
+    ('\u{0622}', [0xFE81, 0xFE82, 0, 0]),
+    ('\u{0623}', [0xFE83, 0xFE84, 0, 0]),
+    ('\u{0624}', [0xFE85, 0xFE86, 0, 0]),
+    ('\u{0625}', [0xFE87, 0xFE88, 0, 0]),
+    ('\u{0626}', [0xFE89, 0xFE8A, 0xFE8B, 0xFE8C]),
+    ('\u{0627}', [0xFE8D, 0xFE8E, 0, 0]),
+    ('\u{0628}', [0xFE8F, 0xFE90, 0xFE91, 0xFE92]),
+    ('\u{0629}', [0xFE93, 0xFE94, 0, 0]),
+    ('\u{062A}', [0xFE95, 0xFE96, 0xFE97, 0xFE98]),
+    ('\u{062B}', [0xFE99, 0xFE9A, 0xFE9B, 0xFE9C]),
+    ('\u{062C}', [0xFE9D, 0xFE9E, 0xFE9F, 0xFEA0]),
+    ('\u{062D}', [0xFEA1, 0xFEA2, 0xFEA3, 0xFEA4]),
+    ('\u{062E}', [0xFEA5, 0xFEA6, 0xFEA7, 0xFEA8]),
+    ('\u{062F}', [0xFEA9, 0xFEAA, 0, 0]),
+    ('\u{0630}', [0xFEAB, 0xFEAC, 0, 0]),
+    ('\u{0631}', [0xFEAD, 0xFEAE, 0, 0]),
+    ('\u{0632}', [0xFEAF, 0xFEB0, 0, 0]),
+    ('\u{0633}', [0xFEB1, 0xFEB2, 0xFEB3, 0xFEB4]),
+    ('\u{0634}', [0xFEB5, 0xFEB6, 0xFEB7, 0xFEB8]),
+    ('\u{0635}', [0xFEB9, 0xFEBA, 0xFEBB, 0xFEBC]),
+    ('\u{0636}', [0xFEBD, 0xFEBE, 0xFEBF, 0xFEC0]),
+    ('\u{0637}', [0xFEC1, 0xFEC2, 0xFEC3, 0xFEC4]),
+    ('\u{0638}', [0xFEC5, 0xFEC6, 0xFEC7, 0xFEC8]),
+    ('\u{0639}', [0xFEC9, 0xFECA, 0xFECB, 0xFECC]),
+    ('\u{063A}', [0xFECD, 0xFECE, 0xFECF, 0xFED0]),
+    ('\u{0641}', [0xFED1, 0xFED2, 0xFED3, 0xFED4]),
+    ('\u{0642}', [0xFED5, 0xFED6, 0xFED7, 0xFED8]),
+    ('\u{0643}', [0xFED9, 0xFEDA, 0xFEDB, 0xFEDC]),
+    ('\u{0644}', [0xFEDD, 0xFEDE, 0xFEDF, 0xFEE0]),
+    ('\u{0645}', [0xFEE1, 0xFEE2, 0xFEE3, 0xFEE4]),
+    ('\u{0646}', [0xFEE5, 0xFEE6, 0xFEE7, 0xFEE8]),
+    ('\u{0647}', [0xFEE9, 0xFEEA, 0xFEEB, 0xFEEC]),
+    ('\u{0648}', [0xFEED, 0xFEEE, 0, 0]),
+    ('\u{0649}', [0xFEEF, 0xFEF0, 0xFBE8, 0xFBE9]),
+    ('\u{064A}', [0xFEF1, 0xFEF2, 0xFEF3, 0xFEF4]),
+];
+
+const LAM: char = '\u{0644}';
+const TATWEEL: char = '\u{0640}';
+
+/// The ligatures of lam with the alefs, isolated and final
+const LAM_ALEF: [(char, [u32; 2]); 4] = [
+    ('\u{0622}', [0xFEF5, 0xFEF6]),
+    ('\u{0623}', [0xFEF7, 0xFEF8]),
+    ('\u{0625}', [0xFEF9, 0xFEFA]),
+    ('\u{0627}', [0xFEFB, 0xFEFC]),
+];
+
+fn forms(c: char) -> Option<[u32; 4]> {
+    ARABIC_FORMS
+        .iter()
+        .find(|(letter, _)| *letter == c)
+        .map(|(_, forms)| *forms)
+}
+
+/// Marks like vowel signs, which are drawn over or under a letter without breaking
+/// the joining around it
+fn is_transparent(c: char) -> bool {
+    matches!(c, '\u{0610}'..='\u{061A}' | '\u{064B}'..='\u{065F}' | '\u{0670}' | '\u{06D6}'..='\u{06ED}')
+}
+
+/// Whether the letter `c` joins the letter after it
+fn joins_next(c: char) -> bool {
+    c == TATWEEL || forms(c).is_some_and(|forms| forms[2] != 0)
+}
+
+/// Whether the letter `c` joins the letter before it
+fn joins_previous(c: char) -> bool {
+    c == TATWEEL || forms(c).is_some_and(|forms| forms[1] != 0)
+}
+
+/// Replaces the Arabic letters of `text` with their contextual forms, in reading order
+pub fn join_arabic(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let letter_before = |i: usize| chars[..i].iter().rev().find(|c| !is_transparent(**c));
+    let letter_after = |i: usize| chars[i + 1..].iter().find(|c| !is_transparent(**c));
+    let mut shaped = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let joined_before = joins_previous(c) && letter_before(i).is_some_and(|b| joins_next(*b));
+        if c == LAM {
+            let ligature = chars
+                .get(i + 1)
+                .and_then(|alef| LAM_ALEF.iter().find(|(a, _)| a == alef));
+            if let Some((_, ligature)) = ligature {
+                let form = ligature[usize::from(joined_before)];
+                shaped.extend(char::from_u32(form));
+                i += 2;
+                continue;
+            }
+        }
+        match forms(c) {
+            Some(forms) => {
+                let joined_after =
+                    joins_next(c) && letter_after(i).is_some_and(|a| joins_previous(*a));
+                let form = match (joined_before, joined_after) {
+                    (false, false) => forms[0],
+                    (true, false) => forms[1],
+                    (false, true) => forms[2],
+                    (true, true) => forms[3],
+                };
+                shaped.extend(char::from_u32(form));
+            }
+            None => shaped.push(c),
+        }
+        i += 1;
+    }
+    shaped
+}
+
+/// The direction a character gives the text around it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Class {
+    LeftToRight,
+    RightToLeft,
+    Number,
+    Neutral,
+}
+
+fn class(c: char) -> Class {
+    match c {
+        '0'..='9' | '\u{0660}'..='\u{0669}' | '\u{06F0}'..='\u{06F9}' => Class::Number,
+        '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}' => {
+            if is_transparent(c) {
+                Class::Neutral
+            } else {
+                Class::RightToLeft
+            }
+        }
+        c if c.is_alphabetic() => Class::LeftToRight,
+        _ => Class::Neutral,
+    }
+}
+
+fn is_right_to_left(c: char) -> bool {
+    class(c) == Class::RightToLeft
+}
+
+/// Puts the characters of the line `text` in the order they are drawn, from left to
+/// right, see the module documentation
+pub fn reorder(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let classes: Vec<Class> = chars.iter().map(|c| class(*c)).collect();
+    let rtl_line = classes
+        .iter()
+        .find(|c| matches!(c, Class::LeftToRight | Class::RightToLeft))
+        == Some(&Class::RightToLeft);
+    let base = u8::from(rtl_line);
+
+    // Numbers follow the direction of the letter before them, neutrals go with their
+    // neighbours if those agree and with the line otherwise
+    let mut levels = vec![base; chars.len()];
+    let mut last_strong = if rtl_line {
+        Class::RightToLeft
+    } else {
+        Class::LeftToRight
+    };
+    // Which way every character runs for the neutrals around it, numbers after
+    // right-to-left letters run with them
+    let mut direction = vec![None; chars.len()];
+    for (i, class) in classes.iter().enumerate() {
+        let (level, rtl) = match class {
+            Class::LeftToRight => {
+                last_strong = Class::LeftToRight;
+                (2 * base, false)
+            }
+            Class::RightToLeft => {
+                last_strong = Class::RightToLeft;
+                (1, true)
+            }
+            Class::Number => {
+                let after_rtl = last_strong == Class::RightToLeft;
+                (if rtl_line || after_rtl { 2 } else { 0 }, after_rtl)
+            }
+            Class::Neutral => continue,
+        };
+        levels[i] = level;
+        direction[i] = Some(rtl);
+    }
+    let mut i = 0;
+    while i < chars.len() {
+        if classes[i] != Class::Neutral {
+            i += 1;
+            continue;
+        }
+        let end = (i..chars.len())
+            .find(|j| classes[*j] != Class::Neutral)
+            .unwrap_or(chars.len());
+        let before = if i == 0 {
+            Some(rtl_line)
+        } else {
+            direction[i - 1]
+        };
+        // Trailing neutrals go with the line
+        let after = if end == chars.len() {
+            Some(rtl_line)
+        } else {
+            direction[end]
+        };
+        let level = match (before, after) {
+            (Some(true), Some(true)) => 1,
+            (Some(false), Some(false)) => 2 * base,
+            _ => base,
+        };
+        levels[i..end].fill(level);
+        i = end;
+    }
+
+    // Reversed from the highest level down to the lowest right-to-left one
+    let mut order: Vec<usize> = (0..chars.len()).collect();
+    let highest = levels.iter().copied().max().unwrap_or(0);
+    for level in (1..=highest).rev() {
+        let mut i = 0;
+        while i < order.len() {
+            if levels[order[i]] < level {
+                i += 1;
+                continue;
+            }
+            let end = (i..order.len())
+                .find(|j| levels[order[*j]] < level)
+                .unwrap_or(order.len());
+            order[i..end].reverse();
+            i = end;
+        }
+    }
+    order
+        .into_iter()
+        .map(|i| match (levels[i] % 2, chars[i]) {
+            (1, '(') => ')',
+            (1, ')') => '(',
+            (1, '[') => ']',
+            (1, ']') => '[',
+            (1, '{') => '}',
+            (1, '}') => '{',
+            (1, '<') => '>',
+            (1, '>') => '<',
+            (_, c) => c,
+        })
+        .collect()
+}
+
+/// Shapes the Arabic and orders the right-to-left text of every line of `text` for
+/// drawing, see the module documentation. Borrows text without any.
+pub fn shape(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(is_right_to_left) {
+        return Cow::Borrowed(text);
+    }
+    let lines: Vec<String> = text
+        .split('\n')
+        .map(|line| reorder(&join_arabic(line)))
+        .collect();
+    Cow::Owned(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shaping() {
+        assert!(matches!(shape("plain text"), Cow::Borrowed(_)));
+
+        // Hebrew, alone and within and around left-to-right text
+        assert_eq!(shape("שלום"), "םולש");
+        assert_eq!(shape("say שלום now"), "say םולש now");
+        assert_eq!(shape("שלום world!"), "!world םולש");
+        assert_eq!(shape("שלום 123 (עולם)"), "(םלוע) 123 םולש");
+        // Numbers after left-to-right text stay with it
+        assert_eq!(shape("שלום abc 123"), "abc 123 םולש");
+        assert_eq!(shape("א\nב ג"), "א\nג ב");
+
+        // Seen, lam-alef and meem: initial, the final ligature and isolated
+        assert_eq!(join_arabic("سلام"), "\u{FEB3}\u{FEFC}\u{FEE1}");
+        assert_eq!(shape("سلام"), "\u{FEE1}\u{FEFC}\u{FEB3}");
+        // Beh, teh and reh: initial, medial and final, with a vowel sign in between
+        assert_eq!(join_arabic("بتَر"), "\u{FE91}\u{FE98}\u{064E}\u{FEAE}");
+        // Dal doesn't join the letter after it
+        assert_eq!(join_arabic("دب"), "\u{FEA9}\u{FE8F}");
+    }
+}
@@ -0,0 +1,419 @@
+//! Drawing through a process that owns the display.
+//!
+//! Only one process can sensibly drive `/dev/fb0`. Helpers like a status daemon can
+//! instead use `Framebuffer::shm`, which draws into a screen-sized buffer in shared
+//! memory. Refreshing a region of it sends the region as damage over a Unix socket to
+//! the process owning the display, which runs a `ShmServer` and presents the damage
+//! on the real framebuffer whenever it sees fit.
+//!
+//! On connecting, the server sends the width and height of the client's buffer as
+//! little endian `u32`s, with the file descriptor of the buffer attached as
+//! `SCM_RIGHTS`. The buffer is a memfd sealed against shrinking, so a client can't
+//! truncate it under the server. Clients then send every refresh as six little endian
+//! `u32`s: the left, top, width and height of the region, the waveform mode, and
+//! flags, of which bit 0 requests a full update.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+
+use log::warn;
+use memmap2::{MmapOptions, MmapRaw};
+
+use crate::framebuffer::common::{
+    display_temp, dither_mode, mxcfb_rect, update_mode, waveform_mode,
+};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::mxcfb::mxcfb_update_data;
+use crate::framebuffer::{FramebufferIO, FramebufferRefresh, PartialRefreshMode};
+
+/// Where `ShmServer`s are expected to listen
+pub const DEFAULT_SOCKET: &str = "/run/libremarkable-shm.sock";
+
+const FLAG_FULL: u32 = 1;
+
+/// The length in bytes of a buffer of `width` by `height` pixels
+fn buffer_len(width: u32, height: u32) -> io::Result<usize> {
+    width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(2))
+        .map(|len| len as usize)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "buffer size"))
+}
+
+/// Room for the control message carrying one file descriptor, aligned for it
+type Control = [u64; 4];
+
+/// Sends `data` with `fd` attached
+fn send_fd(stream: &UnixStream, data: &[u8], fd: RawFd) -> io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control: Control = [0; 4];
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+        match libc::sendmsg(stream.as_raw_fd(), &msg, 0) {
+            sent if sent < 0 => Err(io::Error::last_os_error()),
+            sent if sent as usize != data.len() => Err(io::ErrorKind::WriteZero.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Receives exactly `data` along with the file descriptor attached to it
+fn recv_fd(stream: &UnixStream, data: &mut [u8]) -> io::Result<File> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let mut control: Control = [0; 4];
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of::<Control>() as _;
+        let received = libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no buffer"));
+        }
+        let file = File::from_raw_fd(std::ptr::read_unaligned(
+            libc::CMSG_DATA(cmsg).cast::<RawFd>(),
+        ));
+        if received as usize != data.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(file)
+    }
+}
+
+/// The client end, sending damage to the server
+pub struct ShmClient {
+    stream: UnixStream,
+}
+
+impl ShmClient {
+    /// Connects to the server at `socket`, returning the client with the size and
+    /// memory of its buffer
+    pub fn connect(socket: impl AsRef<Path>) -> io::Result<(ShmClient, u32, u32, MmapRaw)> {
+        let stream = UnixStream::connect(socket)?;
+        let mut header = [0u8; 8];
+        let file = recv_fd(&stream, &mut header)?;
+        let word = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        let (width, height) = (word(0), word(1));
+
+        let len = buffer_len(width, height)?;
+        if file.metadata()?.len() < len as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "buffer size"));
+        }
+        let frame = MmapOptions::new().len(len).map_raw(&file)?;
+        Ok((ShmClient { stream }, width, height, frame))
+    }
+
+    pub fn send_damage(&self, update: &mxcfb_update_data) -> bool {
+        let region = update.update_region;
+        let full = update.update_mode == update_mode::UPDATE_MODE_FULL as u32;
+        let words = [
+            region.left,
+            region.top,
+            region.width,
+            region.height,
+            update.waveform_mode,
+            if full { FLAG_FULL } else { 0 },
+        ];
+        let message: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        match (&self.stream).write_all(&message) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Failed to send damage to the display server: {}", err);
+                false
+            }
+        }
+    }
+}
+
+/// The memory of a client's buffer, freed once the client and its damage are gone
+struct SharedBuffer {
+    pixels: MmapRaw,
+    width: u32,
+    height: u32,
+}
+
+/// A region a client drew into
+pub struct Damage {
+    /// Tells the clients apart, in the order they connected
+    pub client: u32,
+    /// Where on the screen
+    pub region: mxcfb_rect,
+    pub waveform_mode: waveform_mode,
+    pub full: bool,
+    buffer: Arc<SharedBuffer>,
+}
+
+impl Damage {
+    /// Copies the damaged region onto `fb` and refreshes it
+    pub fn present(&self, fb: &mut Framebuffer) {
+        let buffer = &self.buffer;
+        let right = self
+            .region
+            .left
+            .saturating_add(self.region.width)
+            .min(buffer.width)
+            .min(fb.var_screen_info.xres);
+        let bottom = self
+            .region
+            .top
+            .saturating_add(self.region.height)
+            .min(buffer.height)
+            .min(fb.var_screen_info.yres);
+        if right <= self.region.left || bottom <= self.region.top {
+            return;
+        }
+        let region = mxcfb_rect {
+            width: right - self.region.left,
+            height: bottom - self.region.top,
+            ..self.region
+        };
+
+        let row = region.width as usize * 2;
+        let mut pixels = vec![0u8; row * region.height as usize];
+        for (i, y) in (region.top..region.top + region.height).enumerate() {
+            let from = (y as usize * buffer.width as usize + region.left as usize) * 2;
+            // The client may be drawing at the same time, which at worst shows a
+            // partially drawn row until its next refresh. So the buffer is only read
+            // through raw pointers, never borrowed as a slice.
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    buffer.pixels.as_ptr().add(from),
+                    pixels.as_mut_ptr().add(i * row),
+                    row,
+                );
+            }
+        }
+        if let Err(err) = fb.restore_region(region, &pixels) {
+            return warn!("Failed to present {:?}: {}", region, err);
+        }
+        fb.partial_refresh(
+            &region,
+            PartialRefreshMode::Async,
+            self.waveform_mode,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            self.full,
+        );
+    }
+}
+
+/// The display owning end, giving every client its own buffer
+pub struct ShmServer {
+    socket: PathBuf,
+    /// The damage of all clients, in the order it was reported
+    pub damage: Receiver<Damage>,
+}
+
+impl ShmServer {
+    /// Listens at `socket` for clients, with buffers of `width` by `height` pixels
+    pub fn new(socket: impl AsRef<Path>, width: u32, height: u32) -> io::Result<ShmServer> {
+        let socket = socket.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&socket)?;
+        let (tx, damage) = channel();
+        std::thread::spawn(move || {
+            for (client, stream) in (1..).zip(listener.incoming()) {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept a framebuffer client: {}", err);
+                        continue;
+                    }
+                };
+                let (mut stream, buffer) = match share(stream, width, height) {
+                    Ok(shared) => shared,
+                    Err(err) => {
+                        warn!("Failed to set up framebuffer client {}: {}", client, err);
+                        continue;
+                    }
+                };
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    let mut message = [0u8; 24];
+                    while stream.read_exact(&mut message).is_ok() {
+                        let word = |i: usize| {
+                            u32::from_le_bytes(message[i * 4..i * 4 + 4].try_into().unwrap())
+                        };
+                        let damage = Damage {
+                            client,
+                            region: mxcfb_rect {
+                                left: word(0),
+                                top: word(1),
+                                width: word(2),
+                                height: word(3),
+                            },
+                            waveform_mode: waveform_mode::from_raw(word(4))
+                                .unwrap_or(waveform_mode::WAVEFORM_MODE_GC16_FAST),
+                            full: word(5) & FLAG_FULL != 0,
+                            buffer: buffer.clone(),
+                        };
+                        if tx.send(damage).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(ShmServer { socket, damage })
+    }
+}
+
+impl Drop for ShmServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+/// Creates a white buffer and sends it to the client
+fn share(
+    stream: UnixStream,
+    width: u32,
+    height: u32,
+) -> io::Result<(UnixStream, Arc<SharedBuffer>)> {
+    let len = buffer_len(width, height)?;
+    let fd = unsafe {
+        libc::memfd_create(
+            c"libremarkable-shm".as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    file.set_len(len as u64)?;
+    // The buffer is read through a mapping, which a client truncating it would make
+    // crash the server with SIGBUS
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let buffer = Arc::new(SharedBuffer {
+        pixels: MmapOptions::new().len(len).map_raw(&file)?,
+        width,
+        height,
+    });
+    unsafe { std::ptr::write_bytes(buffer.pixels.as_mut_ptr(), 0xff, len) };
+
+    let mut header = Vec::new();
+    for word in [width, height] {
+        header.extend_from_slice(&word.to_le_bytes());
+    }
+    send_fd(&stream, &header, file.as_raw_fd())?;
+    Ok((stream, buffer))
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::FramebufferDraw;
+    use std::time::Duration;
+
+    #[test]
+    fn test_damage() {
+        let dir = std::env::temp_dir().join(format!("shm-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("shm.sock");
+        let server = ShmServer::new(&socket, 100, 80).unwrap();
+
+        let mut client = Framebuffer::try_shm(&socket).unwrap();
+        assert_eq!(client.var_screen_info.xres, 100);
+        assert_eq!(client.var_screen_info.yres, 80);
+        client.fill_rect((10, 10).into(), (20, 20).into(), color::BLACK);
+        let drawn = mxcfb_rect {
+            left: 10,
+            top: 10,
+            width: 20,
+            height: 20,
+        };
+        client.partial_refresh(
+            &drawn,
+            PartialRefreshMode::Async,
+            waveform_mode::WAVEFORM_MODE_DU,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        );
+
+        let damage = server.damage.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(damage.region, drawn);
+        assert!(!damage.full);
+        let mut display = Framebuffer::headless(100, 80);
+        damage.present(&mut display);
+        assert_eq!(
+            display.read_pixel((15, 15).into()).as_native(),
+            color::BLACK.as_native()
+        );
+        assert_eq!(
+            display.read_pixel((35, 15).into()).as_native(),
+            color::WHITE.as_native()
+        );
+        let refresh = display.take_refreshes().pop().unwrap();
+        assert_eq!(refresh.update_region, drawn);
+        assert_eq!(
+            refresh.waveform_mode,
+            waveform_mode::WAVEFORM_MODE_DU as u32
+        );
+
+        // Regions reaching past the end of the buffer are clipped instead of overflowing
+        let hostile = Damage {
+            region: mxcfb_rect {
+                left: 90,
+                top: 70,
+                width: u32::MAX,
+                height: u32::MAX,
+            },
+            buffer: damage.buffer.clone(),
+            ..damage
+        };
+        hostile.present(&mut display);
+        let refresh = display.take_refreshes().pop().unwrap();
+        assert_eq!(
+            (refresh.update_region.width, refresh.update_region.height),
+            (10, 10)
+        );
+        assert!(buffer_len(u32::MAX, 2).is_err());
+
+        // Clients can't truncate their buffer under the server
+        let stream = UnixStream::connect(&socket).unwrap();
+        let file = recv_fd(&stream, &mut [0; 8]).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 100 * 80 * 2);
+        assert!(file.set_len(0).is_err());
+
+        drop(client);
+        drop(hostile);
+        drop(server);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+//! Copies of regions of the framebuffer to put back later.
+//!
+//! A `RegionSnapshot` holds the raw pixels of a region, copied a row at a time, which
+//! is what undo, popups and transient overlays need to bring back what they covered.
+//! Unlike `FramebufferIO::dump_region` it remembers where it was taken and clips the
+//! region to the display instead of failing.
+
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core;
+use crate::framebuffer::FramebufferIO;
+
+/// The pixels of a region, in the framebuffer's native format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionSnapshot {
+    rect: mxcfb_rect,
+    data: Vec<u8>,
+}
+
+impl RegionSnapshot {
+    /// Where the snapshot was taken, invalid if it is empty
+    pub fn rect(&self) -> mxcfb_rect {
+        self.rect
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl core::Framebuffer {
+    /// Copies the part of `rect` on the display
+    pub fn snapshot_region(&self, rect: mxcfb_rect) -> RegionSnapshot {
+        let size = self.screen_size();
+        let screen = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: size.x,
+            height: size.y,
+        };
+        let empty = RegionSnapshot {
+            rect: mxcfb_rect::invalid(),
+            data: Vec::new(),
+        };
+        let rect = match screen.intersection(&rect) {
+            Some(rect) => rect,
+            None => return empty,
+        };
+        match self.dump_region(rect) {
+            Ok(data) => RegionSnapshot { rect, data },
+            Err(_) => empty,
+        }
+    }
+
+    /// Puts back the pixels of `snapshot` where they were taken, without refreshing.
+    /// Returns the area to refresh, fails for snapshots of framebuffers larger or in
+    /// another pixel format than this one.
+    pub fn restore_snapshot(
+        &mut self,
+        snapshot: &RegionSnapshot,
+    ) -> Result<mxcfb_rect, &'static str> {
+        if snapshot.is_empty() {
+            return Ok(mxcfb_rect::invalid());
+        }
+        self.restore_region(snapshot.rect, &snapshot.data)?;
+        Ok(snapshot.rect)
+    }
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::{Point2, Vector2};
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::FramebufferDraw;
+
+    #[test]
+    fn test_region_snapshot() {
+        let mut fb = core::Framebuffer::headless(60, 40);
+        fb.fill_rect(
+            Point2 { x: 10, y: 10 },
+            Vector2 { x: 5, y: 5 },
+            color::BLACK,
+        );
+        let pixel = |fb: &core::Framebuffer, x, y| fb.read_pixel(Point2 { x, y }).to_rgb8();
+        let (black, gray) = (color::BLACK.to_rgb8(), color::GRAY(0x80).to_rgb8());
+
+        // Clipped to the display
+        let snapshot = fb.snapshot_region(mxcfb_rect {
+            left: 8,
+            top: 8,
+            width: 100,
+            height: 10,
+        });
+        let taken = mxcfb_rect {
+            left: 8,
+            top: 8,
+            width: 52,
+            height: 10,
+        };
+        assert_eq!(snapshot.rect(), taken);
+        assert_eq!(snapshot.data().len(), 52 * 10 * 2);
+
+        // A popup over it, gone again
+        fb.fill_rect(
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 60, y: 40 },
+            color::GRAY(0x80),
+        );
+        assert_eq!(fb.restore_snapshot(&snapshot), Ok(taken));
+        assert_eq!(pixel(&fb, 12, 12), black);
+        assert_eq!(pixel(&fb, 20, 12), color::WHITE.to_rgb8());
+        assert_eq!(pixel(&fb, 5, 5), gray);
+        assert_eq!(pixel(&fb, 20, 20), gray);
+
+        let outside = fb.snapshot_region(mxcfb_rect {
+            left: 70,
+            top: 0,
+            width: 10,
+            height: 10,
+        });
+        assert!(outside.is_empty());
+        assert_eq!(fb.restore_snapshot(&outside), Ok(mxcfb_rect::invalid()));
+        let mut small = core::Framebuffer::headless(20, 20);
+        assert!(small.restore_snapshot(&snapshot).is_err());
+    }
+}
@@ -38,6 +38,11 @@ impl CompressedCanvasState {
     pub fn decompress(&self) -> Vec<u8> {
         zstd::decode_all(&*self.data).unwrap()
     }
+
+    /// Size of the compressed data in bytes
+    pub fn compressed_size(&self) -> usize {
+        self.data.len()
+    }
 }
 
 #[cfg(feature = "image")]
@@ -6,9 +6,11 @@
 
 use super::mxcfb::mxcfb_update_data;
 use crate::device;
+use crate::framebuffer::error::FramebufferError;
 use crate::framebuffer::screeninfo::{FixScreeninfo, VarScreeninfo};
+use log::warn;
 use memmap2::{MmapOptions, MmapRaw};
-use std::ffi::{c_void, CStr, CString};
+use std::ffi::{c_void, CString};
 use std::fs::OpenOptions;
 use std::io::Error as IoError;
 use std::os::unix::prelude::AsRawFd;
@@ -89,10 +91,16 @@ impl Default for SwtfbClient {
 
 impl SwtfbClient {
     pub fn new(path: impl AsRef<Path>) -> SwtfbClient {
-        assert!(
-            device::CURRENT_DEVICE.model == device::Model::Gen2,
-            "SWTFB is not supported on devices other than rM 2"
-        );
+        SwtfbClient::try_new(path).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `new`, returning an error on devices other than the rM 2 or when the
+    /// message queue of the rm2fb server can't be opened
+    pub fn try_new(path: impl AsRef<Path>) -> Result<SwtfbClient, FramebufferError> {
+        let model = device::Model::current_model()?;
+        if model != device::Model::Gen2 {
+            return Err(FramebufferError::Unsupported(model));
+        }
 
         let msqid = unsafe {
             libc::msgget(
@@ -100,13 +108,15 @@ impl SwtfbClient {
                 libc::IPC_CREAT | libc::SHM_R | libc::SHM_W,
             )
         };
-        assert!(msqid >= 0);
+        if msqid < 0 {
+            return Err(FramebufferError::Swtfb(IoError::last_os_error()));
+        }
 
-        Self {
+        Ok(Self {
             msqid,
             path: PathBuf::from(path.as_ref()),
             do_wait_ioctl: env::var("RM2FB_NO_WAIT_IOCTL").is_err(),
-        }
+        })
     }
 
     pub fn open_buffer(&self) -> Result<MmapRaw, IoError> {
@@ -161,10 +171,11 @@ impl SwtfbClient {
         let sem_name_c = CString::new(sem_name_str.as_str()).unwrap();
         let sem = unsafe { libc::sem_open(sem_name_c.as_ptr(), libc::O_CREAT, 0x644, 0) };
         if sem == libc::SEM_FAILED {
-            panic!(
-                "Opening semaphore to wait for swtfb update failed: {:?}",
-                unsafe { CStr::from_ptr(libc::strerror(*libc::__errno_location())) }
+            warn!(
+                "Opening semaphore to wait for swtfb update failed: {}",
+                IoError::last_os_error()
             );
+            return;
         }
 
         let mut timeout = libc::timespec {
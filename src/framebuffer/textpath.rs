@@ -0,0 +1,309 @@
+//! Text following a path, e.g. labels around a dial or a round widget.
+//!
+//! A `TextPath` is a polyline, built from points, a circle arc or a bezier curve.
+//! `layout` places each glyph of a string along it, turned to follow the path, and
+//! `draw` renders them, rotated, onto a framebuffer.
+
+use rusttype::{point, Scale};
+
+use crate::framebuffer::cgmath::{EuclideanSpace, InnerSpace, Point2, Vector2};
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core;
+use crate::framebuffer::draw::DEFAULT_FONT;
+use crate::framebuffer::FramebufferIO;
+
+/// Where on the path the text goes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+    Start,
+    Center,
+    End,
+}
+
+/// A glyph placed on a path
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlacedGlyph {
+    pub character: char,
+    /// The start of the glyph on the baseline
+    pub origin: Point2<f32>,
+    /// Of the baseline, clockwise from the x axis in radians
+    pub angle: f32,
+}
+
+/// The path text is laid out along, as a polyline
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextPath {
+    points: Vec<Point2<f32>>,
+    /// Distance from the start to each point
+    lengths: Vec<f32>,
+    pub align: TextAlign,
+    /// Moves the text further along the path, in pixels
+    pub offset: f32,
+}
+
+impl TextPath {
+    pub fn new(points: Vec<Point2<f32>>) -> TextPath {
+        let mut lengths = Vec::with_capacity(points.len());
+        let mut length = 0.0;
+        for (i, p) in points.iter().enumerate() {
+            if i > 0 {
+                length += (p - points[i - 1]).magnitude();
+            }
+            lengths.push(length);
+        }
+        TextPath {
+            points,
+            lengths,
+            align: TextAlign::Start,
+            offset: 0.0,
+        }
+    }
+
+    /// The arc of the circle around `center` from angle `start` to `end`, clockwise
+    /// from the x axis in radians. Going clockwise puts the text on the outside.
+    pub fn arc(center: Point2<f32>, radius: f32, start: f32, end: f32) -> TextPath {
+        let steps = ((radius * (end - start).abs()) / 4.0).ceil().max(8.0) as usize;
+        TextPath::new(
+            (0..=steps)
+                .map(|i| {
+                    let angle = start + (end - start) * i as f32 / steps as f32;
+                    Point2 {
+                        x: center.x + radius * angle.cos(),
+                        y: center.y + radius * angle.sin(),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// The quadratic bezier curve from `start` to `end` with the control point `ctrl`,
+    /// like `FramebufferDraw::draw_bezier`
+    pub fn bezier(start: Point2<f32>, ctrl: Point2<f32>, end: Point2<f32>) -> TextPath {
+        let length = (ctrl - start).magnitude() + (end - ctrl).magnitude();
+        let steps = (length / 4.0).ceil().max(8.0) as usize;
+        TextPath::new(
+            (0..=steps)
+                .map(|i| {
+                    let t = i as f32 / steps as f32;
+                    let p = start.to_vec() * (1.0 - t) * (1.0 - t)
+                        + ctrl.to_vec() * 2.0 * (1.0 - t) * t
+                        + end.to_vec() * t * t;
+                    Point2::from_vec(p)
+                })
+                .collect(),
+        )
+    }
+
+    pub fn align(mut self, align: TextAlign) -> TextPath {
+        self.align = align;
+        self
+    }
+
+    pub fn offset(mut self, offset: f32) -> TextPath {
+        self.offset = offset;
+        self
+    }
+
+    pub fn length(&self) -> f32 {
+        self.lengths.last().copied().unwrap_or(0.0)
+    }
+
+    /// The point `distance` along the path and the direction there. Continues in a
+    /// straight line beyond either end.
+    fn at(&self, distance: f32) -> Option<(Point2<f32>, Vector2<f32>)> {
+        if self.points.len() < 2 {
+            return None;
+        }
+        let segment = match self.lengths.iter().position(|&l| l > distance) {
+            Some(0) => 0,
+            Some(i) => i - 1,
+            None => self.points.len() - 2,
+        };
+        let (a, b) = (self.points[segment], self.points[segment + 1]);
+        let along = b - a;
+        let len = along.magnitude();
+        if len == 0.0 {
+            // Only repeated points
+            return Some((a, Vector2 { x: 1.0, y: 0.0 }));
+        }
+        let direction = along / len;
+        Some((
+            a + direction * (distance - self.lengths[segment]),
+            direction,
+        ))
+    }
+
+    /// Places the glyphs of `text`, `size` pixels high, along the path
+    pub fn layout(&self, text: &str, size: f32) -> Vec<PlacedGlyph> {
+        let scale = Scale::uniform(size);
+        let mut advances = Vec::new();
+        let mut width = 0.0;
+        let mut last = None;
+        for character in text.chars() {
+            let glyph = DEFAULT_FONT.glyph(character);
+            if let Some(last) = last {
+                width += DEFAULT_FONT.pair_kerning(scale, last, glyph.id());
+            }
+            last = Some(glyph.id());
+            let advance = glyph.scaled(scale).h_metrics().advance_width;
+            advances.push((character, width, advance));
+            width += advance;
+        }
+
+        let start = self.offset
+            + match self.align {
+                TextAlign::Start => 0.0,
+                TextAlign::Center => (self.length() - width) / 2.0,
+                TextAlign::End => self.length() - width,
+            };
+        advances
+            .into_iter()
+            .filter_map(|(character, at, advance)| {
+                // Turned like the path under the middle of the glyph
+                let middle = start + at + advance / 2.0;
+                let (center, direction) = self.at(middle)?;
+                Some(PlacedGlyph {
+                    character,
+                    origin: center - direction * (advance / 2.0),
+                    angle: direction.y.atan2(direction.x),
+                })
+            })
+            .collect()
+    }
+
+    /// Draws `text`, `size` pixels high, along the path. Returns the area to refresh.
+    pub fn draw(
+        &self,
+        fb: &mut core::Framebuffer,
+        text: &str,
+        size: f32,
+        col: color,
+    ) -> mxcfb_rect {
+        let scale = Scale::uniform(size);
+        let (width, height) = (fb.var_screen_info.xres, fb.var_screen_info.yres);
+        let rgb = col.to_rgb8();
+        let mut drawn: Option<mxcfb_rect> = None;
+
+        for placed in self.layout(text, size) {
+            let glyph = DEFAULT_FONT
+                .glyph(placed.character)
+                .scaled(scale)
+                .positioned(point(0.0, 0.0));
+            let bb = match glyph.pixel_bounding_box() {
+                Some(bb) => bb,
+                None => continue,
+            };
+            // Coverage of the upright glyph, relative to its origin
+            let (gw, gh) = (bb.width() as usize, bb.height() as usize);
+            let mut coverage = vec![0.0f32; gw * gh];
+            glyph.draw(|x, y, v| coverage[y as usize * gw + x as usize] = v);
+            let sample = |x: f32, y: f32| -> f32 {
+                let (x, y) = (x - bb.min.x as f32 - 0.5, y - bb.min.y as f32 - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let texel = |tx: f32, ty: f32| {
+                    if tx < 0.0 || ty < 0.0 || tx >= gw as f32 || ty >= gh as f32 {
+                        0.0
+                    } else {
+                        coverage[ty as usize * gw + tx as usize]
+                    }
+                };
+                let (fx, fy) = (x - x0, y - y0);
+                (texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx) * (1.0 - fy)
+                    + (texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx) * fy
+            };
+
+            // The rotated corners of the glyph's box give the area to fill
+            let (sin, cos) = placed.angle.sin_cos();
+            let rotate = |x: f32, y: f32| Point2 {
+                x: placed.origin.x + x * cos - y * sin,
+                y: placed.origin.y + x * sin + y * cos,
+            };
+            let corners = [
+                rotate(bb.min.x as f32, bb.min.y as f32),
+                rotate(bb.max.x as f32, bb.min.y as f32),
+                rotate(bb.min.x as f32, bb.max.y as f32),
+                rotate(bb.max.x as f32, bb.max.y as f32),
+            ];
+            let min_x = corners.iter().map(|p| p.x).fold(f32::MAX, f32::min).floor();
+            let max_x = corners.iter().map(|p| p.x).fold(f32::MIN, f32::max).ceil();
+            let min_y = corners.iter().map(|p| p.y).fold(f32::MAX, f32::min).floor();
+            let max_y = corners.iter().map(|p| p.y).fold(f32::MIN, f32::max).ceil();
+            let (left, top) = (min_x.max(0.0) as u32, min_y.max(0.0) as u32);
+            let (right, bottom) = (max_x.min(width as f32), max_y.min(height as f32));
+            if right <= left as f32 || bottom <= top as f32 {
+                continue;
+            }
+            let (right, bottom) = (right as u32, bottom as u32);
+
+            for y in top..bottom {
+                for x in left..right {
+                    // Back into the upright glyph
+                    let (dx, dy) = (
+                        x as f32 + 0.5 - placed.origin.x,
+                        y as f32 + 0.5 - placed.origin.y,
+                    );
+                    let v = sample(dx * cos + dy * sin, -dx * sin + dy * cos);
+                    if v <= 0.0 {
+                        continue;
+                    }
+                    let pos = Point2 { x, y };
+                    let under = fb.read_pixel(pos).to_rgb8();
+                    let blend =
+                        |c: usize| (f32::from(under[c]) * (1.0 - v) + f32::from(rgb[c]) * v) as u8;
+                    fb.write_pixel(
+                        pos.cast().unwrap(),
+                        color::RGB(blend(0), blend(1), blend(2)),
+                    );
+                }
+            }
+            let rect = mxcfb_rect {
+                left,
+                top,
+                width: right - left,
+                height: bottom - top,
+            };
+            drawn = Some(match drawn {
+                Some(drawn) => drawn.merge_rect(&rect),
+                None => rect,
+            });
+        }
+        drawn.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_layout_on_arc() {
+        let center = Point2 { x: 200.0, y: 200.0 };
+        // The top half of a circle, left to right
+        let path = TextPath::arc(center, 100.0, PI, 2.0 * PI).align(TextAlign::Center);
+        assert!((path.length() - 100.0 * PI).abs() < 1.0);
+
+        let glyphs = path.layout("dial", 30.0);
+        assert_eq!(glyphs.len(), 4);
+        for glyph in &glyphs {
+            // On the circle and turned along it
+            assert!(((glyph.origin - center).magnitude() - 100.0).abs() < 2.0);
+        }
+        assert!(
+            glyphs[0].angle < 0.0 && glyphs[3].angle > 0.0,
+            "{:?}",
+            glyphs
+        );
+        // Centered around the top
+        assert!(glyphs[0].origin.x < 200.0 && glyphs[3].origin.x > 200.0);
+
+        let mut fb = core::Framebuffer::headless(400, 400);
+        let rect = path.draw(&mut fb, "dial", 30.0, color::BLACK);
+        assert!(rect.top < 110 && rect.top + rect.height > 90, "{:?}", rect);
+        let dark = (rect.top..rect.top + rect.height)
+            .flat_map(|y| (rect.left..rect.left + rect.width).map(move |x| Point2 { x, y }))
+            .filter(|p| fb.read_pixel(*p).to_rgb8()[0] < 128)
+            .count();
+        assert!(dark > 50, "{}", dark);
+    }
+}
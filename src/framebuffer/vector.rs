@@ -0,0 +1,709 @@
+//! Drawing SVG documents, like the icons of a launcher.
+//!
+//! `Framebuffer::draw_svg` scales a document to fit a rectangle and draws it
+//! antialiased in shades of gray. It understands what icons are made of: paths and
+//! basic shapes in groups, with transforms, solid fills and strokes and their
+//! opacity. Gradients, text, embedded images, clipping and filters are left out.
+
+use crate::framebuffer::cgmath::{InnerSpace, Matrix3, Point2, Vector2, Vector3};
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core;
+use crate::framebuffer::graphics;
+use crate::framebuffer::{FillRule, FramebufferIO, LineCap, LineJoin};
+use crate::stroke::svg::{attribute, parse_color, parse_path_data, ParseError};
+
+/// How far flattened curves may stray from the real ones, in pixels
+const TOLERANCE: f32 = 0.2;
+
+/// Rows sampled in every pixel, for the antialiasing of edges that are close to
+/// horizontal
+const SAMPLES: usize = 4;
+
+/// How far past the corner a miter join may reach, in half widths, as in SVG
+const MITER_LIMIT: f32 = 4.0;
+
+/// Why drawing an SVG document failed
+#[derive(Debug, thiserror::Error)]
+pub enum SvgError {
+    #[error("The SVG document is not UTF-8")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("Not an SVG document")]
+    NotSvg,
+    #[error("Malformed SVG path data")]
+    Path(#[from] ParseError),
+}
+
+/// Elements that aren't drawn, along with everything in them
+const SKIPPED: &[&str] = &[
+    "clipPath",
+    "defs",
+    "desc",
+    "filter",
+    "foreignObject",
+    "image",
+    "linearGradient",
+    "marker",
+    "mask",
+    "metadata",
+    "pattern",
+    "radialGradient",
+    "script",
+    "style",
+    "symbol",
+    "text",
+    "title",
+];
+
+/// The affine transform `[a b c d e f]` of SVG
+fn affine(a: f32, b: f32, c: f32, d: f32, e: f32, f: f32) -> Matrix3<f32> {
+    Matrix3::new(a, b, 0.0, c, d, 0.0, e, f, 1.0)
+}
+
+fn apply(m: &Matrix3<f32>, p: Point2<f32>) -> Point2<f32> {
+    let p = m * Vector3 {
+        x: p.x,
+        y: p.y,
+        z: 1.0,
+    };
+    Point2 { x: p.x, y: p.y }
+}
+
+/// The numbers in `list`, separated by whitespace or commas
+fn numbers(list: &str) -> Vec<f32> {
+    list.split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(|n| n.parse().ok())
+        .collect()
+}
+
+/// The value of the `transform` attribute, e.g. `translate(10 20) rotate(45)`
+fn parse_transform(value: &str) -> Matrix3<f32> {
+    let mut transform = affine(1.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+    for part in value.split(')') {
+        let (name, args) = match part.split_once('(') {
+            Some((name, args)) => (
+                name.trim_matches(|c: char| c.is_whitespace() || c == ','),
+                numbers(args),
+            ),
+            None => continue,
+        };
+        let arg = |i: usize, default: f32| args.get(i).copied().unwrap_or(default);
+        let next = match name {
+            "matrix" if args.len() == 6 => {
+                affine(args[0], args[1], args[2], args[3], args[4], args[5])
+            }
+            "translate" => affine(1.0, 0.0, 0.0, 1.0, arg(0, 0.0), arg(1, 0.0)),
+            "scale" => affine(arg(0, 1.0), 0.0, 0.0, arg(1, arg(0, 1.0)), 0.0, 0.0),
+            "rotate" => {
+                let (sin, cos) = arg(0, 0.0).to_radians().sin_cos();
+                let (x, y) = (arg(1, 0.0), arg(2, 0.0));
+                affine(1.0, 0.0, 0.0, 1.0, x, y)
+                    * affine(cos, sin, -sin, cos, 0.0, 0.0)
+                    * affine(1.0, 0.0, 0.0, 1.0, -x, -y)
+            }
+            "skewX" => affine(1.0, 0.0, arg(0, 0.0).to_radians().tan(), 1.0, 0.0, 0.0),
+            "skewY" => affine(1.0, arg(0, 0.0).to_radians().tan(), 0.0, 1.0, 0.0, 0.0),
+            _ => continue,
+        };
+        transform = transform * next;
+    }
+    transform
+}
+
+/// A length attribute, in user units, ignoring the unit
+fn length(tag: &str, name: &str) -> Option<f32> {
+    let value = attribute(tag, name)?.trim();
+    let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%');
+    number.parse().ok()
+}
+
+/// The property `name` of the element `tag`, from its `style` attribute or else its
+/// presentation attribute
+fn property<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let styled = attribute(tag, "style").and_then(|style| {
+        style.split(';').find_map(|declaration| {
+            let (key, value) = declaration.split_once(':')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    });
+    styled.or_else(|| attribute(tag, name).map(str::trim))
+}
+
+/// A fill or stroke, `None` for `none`. References to gradients and patterns use
+/// their fallback color.
+fn parse_paint(value: &str) -> Option<color> {
+    match value {
+        "currentColor" => Some(color::BLACK),
+        _ => match value.strip_prefix("url(") {
+            Some(reference) => parse_paint(reference.split_once(')')?.1.trim()),
+            None => parse_color(value),
+        },
+    }
+}
+
+/// The properties inherited from the enclosing elements
+#[derive(Clone, Copy)]
+struct Style {
+    fill: Option<color>,
+    stroke: Option<color>,
+    width: f32,
+    opacity: f32,
+    fill_opacity: f32,
+    stroke_opacity: f32,
+    rule: FillRule,
+    cap: LineCap,
+    join: LineJoin,
+    /// From user units to framebuffer pixels
+    transform: Matrix3<f32>,
+}
+
+impl Style {
+    fn apply(&self, tag: &str) -> Style {
+        let mut style = *self;
+        let opacity = |name| property(tag, name).and_then(|v| v.parse::<f32>().ok());
+        if let Some(fill) = property(tag, "fill") {
+            style.fill = parse_paint(fill);
+        }
+        if let Some(stroke) = property(tag, "stroke") {
+            style.stroke = parse_paint(stroke);
+        }
+        if let Some(width) =
+            property(tag, "stroke-width").and_then(|v| v.trim_end_matches("px").parse().ok())
+        {
+            style.width = width;
+        }
+        // Not quite right for overlapping children of groups, which should be blended
+        // as a whole
+        if let Some(opacity) = opacity("opacity") {
+            style.opacity *= opacity.clamp(0.0, 1.0);
+        }
+        if let Some(opacity) = opacity("fill-opacity") {
+            style.fill_opacity = opacity.clamp(0.0, 1.0);
+        }
+        if let Some(opacity) = opacity("stroke-opacity") {
+            style.stroke_opacity = opacity.clamp(0.0, 1.0);
+        }
+        match property(tag, "fill-rule") {
+            Some("evenodd") => style.rule = FillRule::EvenOdd,
+            Some("nonzero") => style.rule = FillRule::NonZero,
+            _ => {}
+        }
+        match property(tag, "stroke-linecap") {
+            Some("butt") => style.cap = LineCap::Butt,
+            Some("round") => style.cap = LineCap::Round,
+            Some("square") => style.cap = LineCap::Square,
+            _ => {}
+        }
+        match property(tag, "stroke-linejoin") {
+            Some("miter") => style.join = LineJoin::Miter,
+            Some("round") => style.join = LineJoin::Round,
+            Some("bevel") => style.join = LineJoin::Bevel,
+            _ => {}
+        }
+        if let Some(transform) = attribute(tag, "transform") {
+            style.transform = style.transform * parse_transform(transform);
+        }
+        style
+    }
+
+    /// How much larger things are drawn than in user units
+    fn scale(&self) -> f32 {
+        let m = &self.transform;
+        (m.x.x * m.y.y - m.x.y * m.y.x).abs().sqrt()
+    }
+}
+
+/// The path data of a basic shape, `None` for anything else
+fn shape_path(name: &str, tag: &str) -> Option<String> {
+    let get = |n| length(tag, n).unwrap_or(0.0);
+    match name {
+        "path" => attribute(tag, "d").map(str::to_owned),
+        "rect" => {
+            let (x, y, w, h) = (get("x"), get("y"), get("width"), get("height"));
+            if w <= 0.0 || h <= 0.0 {
+                return None;
+            }
+            let (rx, ry) = match (length(tag, "rx"), length(tag, "ry")) {
+                (Some(rx), Some(ry)) => (rx, ry),
+                (Some(r), None) | (None, Some(r)) => (r, r),
+                (None, None) => (0.0, 0.0),
+            };
+            let (rx, ry) = (rx.clamp(0.0, w / 2.0), ry.clamp(0.0, h / 2.0));
+            if rx == 0.0 || ry == 0.0 {
+                return Some(format!("M{x} {y}h{w}v{h}h{}z", -w));
+            }
+            let arc = |dx: f32, dy: f32| format!("a{rx} {ry} 0 0 1 {dx} {dy}");
+            Some(format!(
+                "M{} {y}h{}{}v{}{}h{}{}v{}{}z",
+                x + rx,
+                w - 2.0 * rx,
+                arc(rx, ry),
+                h - 2.0 * ry,
+                arc(-rx, ry),
+                -(w - 2.0 * rx),
+                arc(-rx, -ry),
+                -(h - 2.0 * ry),
+                arc(rx, -ry),
+            ))
+        }
+        "circle" | "ellipse" => {
+            let (cx, cy) = (get("cx"), get("cy"));
+            let (rx, ry) = match name {
+                "circle" => (get("r"), get("r")),
+                _ => (get("rx"), get("ry")),
+            };
+            if rx <= 0.0 || ry <= 0.0 {
+                return None;
+            }
+            Some(format!(
+                "M{} {cy}A{rx} {ry} 0 0 1 {} {cy}A{rx} {ry} 0 0 1 {} {cy}z",
+                cx - rx,
+                cx + rx,
+                cx - rx
+            ))
+        }
+        "line" => Some(format!(
+            "M{} {}L{} {}",
+            get("x1"),
+            get("y1"),
+            get("x2"),
+            get("y2")
+        )),
+        "polyline" => attribute(tag, "points").map(|points| format!("M{points}")),
+        "polygon" => attribute(tag, "points").map(|points| format!("M{points}z")),
+        _ => None,
+    }
+}
+
+/// A shape to draw, in framebuffer pixels
+struct Shape {
+    subpaths: Vec<Vec<Point2<f32>>>,
+    style: Style,
+}
+
+/// The shapes of the document `svg`, scaled to fit `rect`
+fn parse(svg: &str, rect: &mxcfb_rect) -> Result<Vec<Shape>, SvgError> {
+    let mut shapes = Vec::new();
+    let mut styles: Vec<Style> = Vec::new();
+    // Depth within an element that isn't drawn
+    let mut skipping = 0;
+
+    let mut rest = svg;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").ok_or(ParseError::UnterminatedTag)?;
+            rest = &rest[end + 3..];
+            continue;
+        }
+        let end = rest.find('>').ok_or(ParseError::UnterminatedTag)?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        if tag.starts_with('/') {
+            if skipping > 0 {
+                skipping -= 1;
+            } else if styles.len() > 1 {
+                styles.pop();
+            }
+            continue;
+        }
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let hidden = property(tag, "display") == Some("none")
+            || property(tag, "visibility") == Some("hidden");
+        if skipping > 0 || SKIPPED.contains(&name) || hidden {
+            if !self_closing {
+                skipping += 1;
+            }
+            continue;
+        }
+
+        let style = match styles.last() {
+            Some(parent) => parent.apply(tag),
+            None if name == "svg" => root_style(tag, rect).apply(tag),
+            None => return Err(SvgError::NotSvg),
+        };
+        if let Some(d) = shape_path(name, tag) {
+            let tolerance = TOLERANCE / style.scale().max(f32::EPSILON);
+            let subpaths = parse_path_data(&d, tolerance)?
+                .into_iter()
+                .map(|subpath| {
+                    subpath
+                        .into_iter()
+                        .map(|p| apply(&style.transform, p))
+                        .collect()
+                })
+                .collect();
+            shapes.push(Shape { subpaths, style });
+        }
+        if !self_closing {
+            styles.push(style);
+        }
+    }
+    if styles.is_empty() {
+        return Err(SvgError::NotSvg);
+    }
+    Ok(shapes)
+}
+
+/// The defaults of SVG, with the `viewBox`, or else the size, of the root element
+/// `tag` fit into `rect`
+fn root_style(tag: &str, rect: &mxcfb_rect) -> Style {
+    let view_box = match attribute(tag, "viewBox").map(numbers).as_deref() {
+        Some(&[x, y, width, height]) => [x, y, width, height],
+        _ => [
+            0.0,
+            0.0,
+            length(tag, "width").unwrap_or(rect.width as f32),
+            length(tag, "height").unwrap_or(rect.height as f32),
+        ],
+    };
+    let [x, y, width, height] = view_box;
+    let (sx, sy) = (
+        rect.width as f32 / width.max(f32::EPSILON),
+        rect.height as f32 / height.max(f32::EPSILON),
+    );
+    // Centered, keeping the aspect ratio unless told otherwise
+    let (sx, sy) = match attribute(tag, "preserveAspectRatio") {
+        Some("none") => (sx, sy),
+        _ => (sx.min(sy), sx.min(sy)),
+    };
+    let left = rect.left as f32 + (rect.width as f32 - width * sx) / 2.0;
+    let top = rect.top as f32 + (rect.height as f32 - height * sy) / 2.0;
+    Style {
+        fill: Some(color::BLACK),
+        stroke: None,
+        width: 1.0,
+        opacity: 1.0,
+        fill_opacity: 1.0,
+        stroke_opacity: 1.0,
+        rule: FillRule::NonZero,
+        cap: LineCap::Butt,
+        join: LineJoin::Miter,
+        transform: affine(sx, 0.0, 0.0, sy, left - x * sx, top - y * sy),
+    }
+}
+
+/// `polygon` wound clockwise on the display, so that overlapping ones add up
+fn clockwise(mut polygon: Vec<Point2<f32>>) -> Vec<Point2<f32>> {
+    let area: f32 = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum();
+    if area < 0.0 {
+        polygon.reverse();
+    }
+    polygon
+}
+
+fn circle(center: Point2<f32>, radius: f32) -> Vec<Point2<f32>> {
+    let steps = (radius * 2.0).ceil().clamp(8.0, 128.0) as usize;
+    (0..steps)
+        .map(|i| {
+            let (sin, cos) = (std::f32::consts::TAU * i as f32 / steps as f32).sin_cos();
+            Point2 {
+                x: center.x + radius * cos,
+                y: center.y + radius * sin,
+            }
+        })
+        .collect()
+}
+
+/// The outline of the stroke along `points`, `width` wide, as polygons that are all
+/// wound the same way, so they can be filled as one with `FillRule::NonZero`
+fn stroke_polygons(
+    points: &[Point2<f32>],
+    width: f32,
+    cap: LineCap,
+    join: LineJoin,
+) -> Vec<Vec<Point2<f32>>> {
+    let mut points = points.to_vec();
+    points.dedup_by(|a, b| (*a - *b).magnitude2() < 1e-6);
+    let half = width / 2.0;
+    let mut polygons = Vec::new();
+    if points.len() < 2 {
+        if let (Some(point), LineCap::Round) = (points.first(), cap) {
+            polygons.push(circle(*point, half));
+        }
+        return polygons;
+    }
+    let closed = points.len() > 2 && points[0] == points[points.len() - 1];
+    let normal = |a: Point2<f32>, b: Point2<f32>| {
+        let d = (b - a).normalize();
+        Vector2 { x: -d.y, y: d.x }
+    };
+
+    let last = points.len() - 2;
+    for (i, segment) in points.windows(2).enumerate() {
+        let (mut a, mut b) = (segment[0], segment[1]);
+        if cap == LineCap::Square && !closed {
+            let direction = (b - a).normalize() * half;
+            if i == 0 {
+                a -= direction;
+            }
+            if i == last {
+                b += direction;
+            }
+        }
+        let n = normal(segment[0], segment[1]) * half;
+        polygons.push(clockwise(vec![a + n, b + n, b - n, a - n]));
+    }
+
+    let mut corners: Vec<[Point2<f32>; 3]> =
+        points.windows(3).map(|w| [w[0], w[1], w[2]]).collect();
+    if closed {
+        corners.push([points[last], points[0], points[1]]);
+    } else if cap == LineCap::Round {
+        polygons.push(circle(points[0], half));
+        polygons.push(circle(points[points.len() - 1], half));
+    }
+    for [before, at, after] in corners {
+        if join == LineJoin::Round {
+            polygons.push(circle(at, half));
+            continue;
+        }
+        let (n0, n1) = (normal(before, at), normal(at, after));
+        // The corner only needs filling on the outside of the turn
+        let side = if (at - before).perp_dot(after - at) > 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let (from, to) = (at + n0 * half * side, at + n1 * half * side);
+        let cos_half = (n0 + n1).magnitude() / 2.0;
+        if join == LineJoin::Miter && cos_half > 1.0 / MITER_LIMIT {
+            let tip = at + (n0 + n1).normalize() * (half / cos_half) * side;
+            polygons.push(clockwise(vec![at, from, tip, to]));
+        } else {
+            polygons.push(clockwise(vec![at, from, to]));
+        }
+    }
+    polygons
+}
+
+/// Adds `weight` times how much of every pixel of `row` lies between `from` and `to`
+fn add_span(row: &mut [f32], from: f32, to: f32, weight: f32) {
+    let from = from.clamp(0.0, row.len() as f32);
+    let to = to.clamp(0.0, row.len() as f32);
+    let first = from.floor() as usize;
+    for (x, pixel) in row[first..to.ceil() as usize].iter_mut().enumerate() {
+        let x = (first + x) as f32;
+        *pixel += (to.min(x + 1.0) - from.max(x)).max(0.0) * weight;
+    }
+}
+
+/// How much of every pixel in `area` the `polygons` cover with `rule`, from 0.0 to
+/// 1.0, in rows
+fn coverage(polygons: &[Vec<Point2<f32>>], rule: FillRule, area: &mxcfb_rect) -> Vec<f32> {
+    let width = area.width as usize;
+    let mut covered = vec![0.0; width * area.height as usize];
+    let edges: Vec<(Point2<f32>, Point2<f32>)> = polygons
+        .iter()
+        .flat_map(|polygon| {
+            polygon
+                .iter()
+                .copied()
+                .zip(polygon.iter().copied().cycle().skip(1))
+        })
+        .filter(|(a, b)| a.y != b.y)
+        .collect();
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    for (row, pixels) in covered.chunks_mut(width.max(1)).enumerate() {
+        for sample in 0..SAMPLES {
+            let y = area.top as f32 + row as f32 + (sample as f32 + 0.5) / SAMPLES as f32;
+            crossings.clear();
+            for (a, b) in &edges {
+                let (low, high, winding) = if a.y < b.y { (a, b, 1) } else { (b, a, -1) };
+                if y < low.y || y >= high.y {
+                    continue;
+                }
+                let x = low.x + (y - low.y) / (high.y - low.y) * (high.x - low.x);
+                crossings.push((x - area.left as f32, winding));
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                let inside = match rule {
+                    FillRule::NonZero => winding != 0,
+                    FillRule::EvenOdd => winding % 2 != 0,
+                };
+                if inside {
+                    add_span(pixels, pair[0].0, pair[1].0, 1.0 / SAMPLES as f32);
+                }
+            }
+        }
+    }
+    covered
+}
+
+impl core::Framebuffer {
+    /// Draws the SVG document `svg` scaled to fit `rect` and centered in it, in gray,
+    /// without refreshing. Returns the area to refresh.
+    pub fn draw_svg(&mut self, rect: &mxcfb_rect, svg: &[u8]) -> Result<mxcfb_rect, SvgError> {
+        let shapes = parse(std::str::from_utf8(svg)?, rect)?;
+        let screen = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: self.var_screen_info.xres,
+            height: self.var_screen_info.yres,
+        };
+        let clip = match rect.intersection(&screen) {
+            Some(clip) => clip,
+            None => return Ok(mxcfb_rect::invalid()),
+        };
+
+        let mut drawn = mxcfb_rect::invalid();
+        for shape in shapes {
+            let style = shape.style;
+            if let Some(fill) = style.fill {
+                let area = self.fill_svg(
+                    &shape.subpaths,
+                    style.rule,
+                    fill,
+                    style.opacity * style.fill_opacity,
+                    &clip,
+                );
+                drawn = drawn.merge_rect(&area);
+            }
+            if let Some(stroke) = style.stroke {
+                let width = style.width * style.scale();
+                let polygons: Vec<_> = shape
+                    .subpaths
+                    .iter()
+                    .flat_map(|subpath| stroke_polygons(subpath, width, style.cap, style.join))
+                    .collect();
+                let area = self.fill_svg(
+                    &polygons,
+                    FillRule::NonZero,
+                    stroke,
+                    style.opacity * style.stroke_opacity,
+                    &clip,
+                );
+                drawn = drawn.merge_rect(&area);
+            }
+        }
+        Ok(drawn)
+    }
+
+    /// Blends the gray of `paint` over the pixels in `clip` the `polygons` cover
+    fn fill_svg(
+        &mut self,
+        polygons: &[Vec<Point2<f32>>],
+        rule: FillRule,
+        paint: color,
+        opacity: f32,
+        clip: &mxcfb_rect,
+    ) -> mxcfb_rect {
+        let points = polygons.iter().flatten();
+        let (mut min, mut max) = (
+            Point2::new(f32::MAX, f32::MAX),
+            Point2::new(f32::MIN, f32::MIN),
+        );
+        for p in points {
+            min = Point2::new(min.x.min(p.x), min.y.min(p.y));
+            max = Point2::new(max.x.max(p.x), max.y.max(p.y));
+        }
+        if opacity <= 0.0 || max.x < min.x {
+            return mxcfb_rect::invalid();
+        }
+        let bounds = mxcfb_rect {
+            left: min.x.floor().max(0.0) as u32,
+            top: min.y.floor().max(0.0) as u32,
+            width: (max.x.ceil() - min.x.floor().max(0.0)).max(0.0) as u32,
+            height: (max.y.ceil() - min.y.floor().max(0.0)).max(0.0) as u32,
+        };
+        let area = match bounds.intersection(clip) {
+            Some(area) => area,
+            None => return mxcfb_rect::invalid(),
+        };
+
+        let gray = graphics::brightness(paint);
+        let covered = coverage(polygons, rule, &area);
+        for (i, covered) in covered.into_iter().enumerate() {
+            let alpha = covered.min(1.0) * opacity;
+            if alpha <= 0.0 {
+                continue;
+            }
+            let pos = Point2 {
+                x: area.left + (i % area.width as usize) as u32,
+                y: area.top + (i / area.width as usize) as u32,
+            };
+            let under = self.read_pixel(pos).to_rgb8();
+            let [r, g, b] =
+                under.map(|c| (f32::from(c) * (1.0 - alpha) + gray * alpha).round() as u8);
+            self.write_pixel(
+                Point2 {
+                    x: pos.x as i32,
+                    y: pos.y as i32,
+                },
+                color::RGB(r, g, b),
+            );
+        }
+        area
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn darkness(fb: &core::Framebuffer, x: u32, y: u32) -> u8 {
+        255 - fb.read_pixel(Point2 { x, y }).to_rgb8()[1]
+    }
+
+    #[test]
+    fn test_svg() {
+        let mut fb = core::Framebuffer::headless(200, 200);
+        let rect = mxcfb_rect {
+            left: 50,
+            top: 50,
+            width: 100,
+            height: 50,
+        };
+        let svg = br##"<?xml version="1.0"?>
+            <!-- An icon -->
+            <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+              <defs><linearGradient id="g"><stop offset="0"/></linearGradient></defs>
+              <g transform="translate(10 0)" fill="#808080">
+                <rect x="-10" y="0" width="10" height="10"/>
+                <circle cx="0" cy="15" r="5" style="fill: black"/>
+              </g>
+              <path d="M12 2 h6 v6 h-6 z m2 2 h2 v2 h-2 z" fill-rule="evenodd"/>
+              <line x1="10" y1="10" x2="20" y2="10" stroke="black" stroke-width="1"/>
+            </svg>"##;
+        let drawn = fb.draw_svg(&rect, svg).unwrap();
+        // 2.5 pixels per unit, centered horizontally
+        assert_eq!((drawn.left, drawn.top), (75, 50));
+        assert!(drawn.left + drawn.width <= 125 && drawn.top + drawn.height <= 100);
+        assert_eq!(darkness(&fb, 74, 60), 0);
+        assert!((darkness(&fb, 80, 60) as i32 - 127).abs() < 10);
+        assert!(darkness(&fb, 100, 87) > 250);
+        // The hole of the even-odd square and the line antialiased on half pixels
+        assert!(darkness(&fb, 108, 58) > 250);
+        assert_eq!(darkness(&fb, 113, 63), 0);
+        assert!(darkness(&fb, 115, 74) > 250 && darkness(&fb, 115, 75) > 250);
+        assert!((30..100).contains(&darkness(&fb, 115, 73)));
+        assert!((30..100).contains(&darkness(&fb, 115, 76)));
+
+        assert!(matches!(
+            fb.draw_svg(&rect, b"<html></html>"),
+            Err(SvgError::NotSvg)
+        ));
+        assert!(matches!(
+            fb.draw_svg(&rect, b"<svg><path d=\"L 1 1\"/></svg>"),
+            Err(SvgError::Path(_))
+        ));
+        assert!(matches!(
+            fb.draw_svg(&rect, b"\xff"),
+            Err(SvgError::Utf8(_))
+        ));
+    }
+}
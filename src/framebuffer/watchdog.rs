@@ -0,0 +1,296 @@
+//! Detecting refreshes the EPDC never completes.
+//!
+//! Waiting for an update marker blocks until the display controller reports the
+//! refresh as done, which it sometimes never does. With a `RefreshWatchdog` set on a
+//! framebuffer, see `Framebuffer::set_refresh_watchdog`, a wait taking longer than
+//! its timeout is logged, a recovery is attempted and the hang is reported, after
+//! which the wait gives up instead of blocking forever.
+//!
+//! The waits run one after another on a waiter thread. Once a wait is given up on,
+//! its thread is left blocking and later waits go to a fresh one, so they don't
+//! queue behind the hung refresh. Only `MAX_ABANDONED` threads are left behind like
+//! that, beyond that waits are given up on right away.
+
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+/// What to do about a hung refresh
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// Only report it
+    Nothing,
+    /// Send a full refresh of the whole display
+    FullRefresh,
+    /// Apply the screen info again before the full refresh, which reinitializes
+    /// the display controller
+    Reinit,
+}
+
+/// A refresh that didn't complete in time
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RefreshHang {
+    pub marker: u32,
+    /// Until the wait was given up on
+    pub waited: Duration,
+    /// Whether the refresh completed after the recovery
+    pub recovered: bool,
+}
+
+type HangHandler = Box<dyn Fn(&RefreshHang) + Send + Sync>;
+
+/// How many waiter threads may be blocking in waits that were given up on
+const MAX_ABANDONED: usize = 4;
+
+/// A blocking wait for a refresh, and where its result goes
+type Wait = (Box<dyn FnOnce() -> u32 + Send>, mpsc::Sender<u32>);
+
+/// A thread running the waits sent to it, until the sender is dropped
+struct Waiter {
+    jobs: mpsc::Sender<Wait>,
+    /// Alive as long as the thread is
+    alive: Weak<()>,
+}
+
+impl Waiter {
+    fn spawn() -> Waiter {
+        let (jobs, rx) = mpsc::channel::<Wait>();
+        let alive = Arc::new(());
+        let waiter = Waiter {
+            jobs,
+            alive: Arc::downgrade(&alive),
+        };
+        std::thread::spawn(move || {
+            let _alive = alive;
+            for (wait, done) in rx {
+                // Nobody listens anymore if the wait was given up on
+                let _ = done.send(wait());
+            }
+        });
+        waiter
+    }
+}
+
+#[derive(Default)]
+struct Waiters {
+    /// Started by the first wait
+    current: Option<Waiter>,
+    /// Threads left blocking in waits that were given up on
+    abandoned: Vec<Weak<()>>,
+}
+
+/// Gives up on refreshes not completing within `timeout`
+pub struct RefreshWatchdog {
+    pub timeout: Duration,
+    pub recovery: Recovery,
+    on_hang: Option<HangHandler>,
+    waiters: Mutex<Waiters>,
+}
+
+impl RefreshWatchdog {
+    pub fn new(timeout: Duration) -> RefreshWatchdog {
+        RefreshWatchdog {
+            timeout,
+            recovery: Recovery::FullRefresh,
+            on_hang: None,
+            waiters: Mutex::default(),
+        }
+    }
+
+    pub fn recovery(mut self, recovery: Recovery) -> RefreshWatchdog {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Calls `on_hang` for every hang, from the thread that was waiting
+    pub fn on_hang(
+        mut self,
+        on_hang: impl Fn(&RefreshHang) + Send + Sync + 'static,
+    ) -> RefreshWatchdog {
+        self.on_hang = Some(Box::new(on_hang));
+        self
+    }
+
+    /// Runs the blocking `wait` for `marker` on the waiter thread, giving it `timeout`
+    /// to finish. Otherwise `recover` is called with the recovery to attempt and
+    /// `wait` gets another `timeout` to finish. Returns what `wait` did, or 0 if it
+    /// didn't finish.
+    pub(crate) fn watch(
+        &self,
+        marker: u32,
+        wait: impl FnOnce() -> u32 + Send + 'static,
+        recover: impl FnOnce(Recovery),
+    ) -> u32 {
+        let started = Instant::now();
+        let (rx, thread) = match self.start(Box::new(wait)) {
+            Some(started) => started,
+            None => {
+                error!(
+                    "Not waiting for the refresh with marker {}, {} earlier refreshes hung",
+                    marker, MAX_ABANDONED
+                );
+                return 0;
+            }
+        };
+        if let Ok(collision) = rx.recv_timeout(self.timeout) {
+            return collision;
+        }
+
+        error!(
+            "Refresh with marker {} not completed after {:?}, attempting recovery: {:?}",
+            marker, self.timeout, self.recovery
+        );
+        if self.recovery != Recovery::Nothing {
+            recover(self.recovery);
+        }
+        let collision = rx.recv_timeout(self.timeout).ok();
+        let hang = RefreshHang {
+            marker,
+            waited: started.elapsed(),
+            recovered: collision.is_some(),
+        };
+        if !hang.recovered {
+            error!(
+                "Giving up on the refresh with marker {} after {:?}",
+                marker, hang.waited
+            );
+            self.abandon(thread);
+        }
+        if let Some(on_hang) = &self.on_hang {
+            on_hang(&hang);
+        }
+        collision.unwrap_or(0)
+    }
+
+    /// Hands `wait` to the waiter thread, returning where its result arrives and
+    /// the thread. None if too many threads are blocking in hung waits to start one.
+    fn start(
+        &self,
+        wait: Box<dyn FnOnce() -> u32 + Send>,
+    ) -> Option<(mpsc::Receiver<u32>, Weak<()>)> {
+        let (tx, rx) = mpsc::channel();
+        let mut waiters = self.waiters.lock().unwrap();
+        waiters.abandoned.retain(|thread| thread.strong_count() > 0);
+        let mut job = (wait, tx);
+        loop {
+            if waiters.current.is_none() && waiters.abandoned.len() >= MAX_ABANDONED {
+                return None;
+            }
+            let waiter = waiters.current.get_or_insert_with(Waiter::spawn);
+            match waiter.jobs.send(job) {
+                Ok(()) => return Some((rx, waiter.alive.clone())),
+                // The thread is gone after a wait panicked, so another one is started
+                Err(mpsc::SendError(unsent)) => {
+                    job = unsent;
+                    waiters.current = None;
+                }
+            }
+        }
+    }
+
+    /// Leaves `thread` blocking in a hung wait, later waits go to another one
+    fn abandon(&self, thread: Weak<()>) {
+        let mut waiters = self.waiters.lock().unwrap();
+        let current = waiters.current.as_ref().map(|waiter| &waiter.alive);
+        if current.is_some_and(|current| current.ptr_eq(&thread)) {
+            waiters.current = None;
+            waiters.abandoned.push(thread);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::ThreadId;
+
+    /// A wait returning 7 once `done` is signalled, recording the thread it ran on
+    fn waiting(
+        done: mpsc::Receiver<()>,
+        threads: &Arc<Mutex<Vec<ThreadId>>>,
+    ) -> impl FnOnce() -> u32 + Send + 'static {
+        let threads = threads.clone();
+        move || {
+            threads.lock().unwrap().push(std::thread::current().id());
+            let _ = done.recv();
+            7
+        }
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let hangs = Arc::new(Mutex::new(Vec::new()));
+        let reported = hangs.clone();
+        let watchdog = RefreshWatchdog::new(Duration::from_millis(100))
+            .on_hang(move |hang| reported.lock().unwrap().push(*hang));
+        let threads = Arc::new(Mutex::new(Vec::new()));
+
+        // In time
+        let recovery = std::cell::Cell::new(None);
+        let (done, signal) = mpsc::channel();
+        done.send(()).unwrap();
+        let wait = waiting(signal, &threads);
+        assert_eq!(watchdog.watch(1, wait, |r| recovery.set(Some(r))), 7);
+        assert_eq!(recovery.get(), None);
+        assert!(hangs.lock().unwrap().is_empty());
+
+        // Completing after the recovery
+        let (done, signal) = mpsc::channel();
+        let wait = waiting(signal, &threads);
+        let recover = |r| {
+            recovery.set(Some(r));
+            done.send(()).unwrap();
+        };
+        assert_eq!(watchdog.watch(2, wait, recover), 7);
+        assert_eq!(recovery.get(), Some(Recovery::FullRefresh));
+
+        // Never completing
+        let watchdog = watchdog.recovery(Recovery::Nothing);
+        recovery.set(None);
+        let (hung, signal) = mpsc::channel();
+        let wait = waiting(signal, &threads);
+        assert_eq!(watchdog.watch(3, wait, |r| recovery.set(Some(r))), 0);
+        assert_eq!(recovery.get(), None);
+
+        {
+            let hangs = hangs.lock().unwrap();
+            assert_eq!(hangs.len(), 2);
+            assert_eq!((hangs[0].marker, hangs[0].recovered), (2, true));
+            assert_eq!((hangs[1].marker, hangs[1].recovered), (3, false));
+            assert!(hangs[1].waited >= Duration::from_millis(200));
+        }
+
+        // Later waits don't queue behind the hung one, but go to a fresh thread
+        let (done, signal) = mpsc::channel();
+        done.send(()).unwrap();
+        let wait = waiting(signal, &threads);
+        assert_eq!(watchdog.watch(4, wait, |r| recovery.set(Some(r))), 7);
+        assert_eq!(hangs.lock().unwrap().len(), 2);
+        {
+            let threads = threads.lock().unwrap();
+            assert_eq!(threads.len(), 4);
+            assert!(threads[..3].iter().all(|thread| *thread == threads[0]));
+            assert_ne!(threads[3], threads[0]);
+        }
+
+        // Only so many threads are left blocking, then waits are given up on at once
+        let mut stuck = vec![hung];
+        for marker in 5..5 + MAX_ABANDONED as u32 - 1 {
+            let (hung, signal) = mpsc::channel();
+            let wait = waiting(signal, &threads);
+            assert_eq!(watchdog.watch(marker, wait, |_| ()), 0);
+            stuck.push(hung);
+        }
+        let (_done, signal) = mpsc::channel();
+        let wait = waiting(signal, &threads);
+        let given_up = Instant::now();
+        assert_eq!(watchdog.watch(9, wait, |_| ()), 0);
+        assert!(given_up.elapsed() < watchdog.timeout);
+        assert_eq!(threads.lock().unwrap().len(), 3 + MAX_ABANDONED);
+        for hung in stuck {
+            hung.send(()).unwrap();
+        }
+    }
+}
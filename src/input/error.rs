@@ -0,0 +1,31 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::input::InputDevice;
+
+/// Why finding or opening an input device failed
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error("Failed to scan {path:?}")]
+    Scan {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to find the {0:?} evdev")]
+    NotFound(InputDevice),
+    #[error("{0:?} is no device")]
+    Unknown(InputDevice),
+    #[error("Failed to open {path:?}")]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("The input devices are unavailable")]
+    Unavailable(#[source] &'static InputError),
+    #[error("Failed to set up epoll")]
+    Epoll(#[source] io::Error),
+}
@@ -1,6 +1,7 @@
 use crate::input;
 
-use input::scan::SCANNED;
+use input::scan::scanned;
+use input::InputError;
 use log::{error, info, warn};
 use std::os::unix::prelude::AsRawFd;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -49,95 +50,99 @@ impl EvDevContext {
     }
 
     /// Non-blocking function that will open the provided path and wait for more data with epoll
-    pub fn start(&mut self) {
-        let path = SCANNED.get_path(self.device);
+    pub fn start(&mut self) -> Result<(), InputError> {
+        let scanned = scanned().map_err(InputError::Unavailable)?;
+        let mut dev = scanned.get_device(self.device)?;
+        let path = scanned.get_path(self.device);
+
+        let mut v = [epoll::Event {
+            events: (epoll::Events::EPOLLET | epoll::Events::EPOLLIN | epoll::Events::EPOLLPRI)
+                .bits(),
+            data: 0,
+        }];
+        let epfd = epoll::create(false).map_err(InputError::Epoll)?;
+        if let Err(err) = epoll::ctl(
+            epfd,
+            epoll::ControlOptions::EPOLL_CTL_ADD,
+            dev.as_raw_fd(),
+            v[0],
+        ) {
+            let _ = epoll::close(epfd);
+            return Err(InputError::Epoll(err));
+        }
 
         self.started.store(true, Ordering::Relaxed);
         self.exited.store(false, Ordering::Relaxed);
         self.exit_requested.store(false, Ordering::Relaxed);
 
-        match SCANNED.get_device(self.device) {
-            Err(e) => error!("Error while reading events from epoll fd: {0}", e),
-            Ok(mut dev) => {
-                let mut v = [epoll::Event {
-                    events: (epoll::Events::EPOLLET
-                        | epoll::Events::EPOLLIN
-                        | epoll::Events::EPOLLPRI)
-                        .bits(),
-                    data: 0,
-                }];
-                let epfd = epoll::create(false).unwrap();
-                epoll::ctl(
-                    epfd,
-                    epoll::ControlOptions::EPOLL_CTL_ADD,
-                    dev.as_raw_fd(),
-                    v[0],
-                )
-                .unwrap();
+        // init callback
+        info!("Init complete for {:?}", path);
 
-                // init callback
-                info!("Init complete for {:?}", path);
+        let exit_req = Arc::clone(&self.exit_requested);
+        let exited = Arc::clone(&self.exited);
+        let device_type = self.device;
+        let state = self.state.clone();
+        let tx = self.tx.clone();
+        let _ = std::thread::spawn(move || {
+            while !exit_req.load(Ordering::Relaxed) {
+                // -1 indefinite wait but it is okay because our EPOLL FD
+                // is watching on ALL input devices at once.
+                let res = match epoll::wait(epfd, -1, &mut v[0..1]) {
+                    Ok(res) => res,
+                    Err(err) => {
+                        warn!("epoll_wait failed: {}", err);
+                        continue;
+                    }
+                };
+                if res != 1 {
+                    warn!("epoll_wait returned {0}", res);
+                }
 
-                let exit_req = Arc::clone(&self.exit_requested);
-                let exited = Arc::clone(&self.exited);
-                let device_type = self.device;
-                let state = self.state.clone();
-                let tx = self.tx.clone();
-                let _ = std::thread::spawn(move || {
-                    while !exit_req.load(Ordering::Relaxed) {
-                        // -1 indefinite wait but it is okay because our EPOLL FD
-                        // is watching on ALL input devices at once.
-                        let res = match epoll::wait(epfd, -1, &mut v[0..1]) {
-                            Ok(res) => res,
-                            Err(err) => {
-                                warn!("epoll_wait failed: {}", err);
-                                continue;
+                let events = match dev.fetch_events() {
+                    Ok(events) => events,
+                    Err(err) => {
+                        warn!("Failed to read events from {:?}: {}", device_type, err);
+                        continue;
+                    }
+                };
+                for ev in events {
+                    trace_io!(
+                        "{:?} read type={:?} code={} value={} time={:?}",
+                        device_type,
+                        ev.event_type(),
+                        ev.code(),
+                        ev.value(),
+                        ev.timestamp()
+                    );
+                    // event callback
+                    match device_type {
+                        input::InputDevice::Multitouch => {
+                            for event in input::multitouch::decode(&ev, &state) {
+                                if let Err(e) = tx.send(event) {
+                                    error!("Failed to write InputEvent into the channel: {}", e);
+                                }
                             }
-                        };
-                        if res != 1 {
-                            warn!("epoll_wait returned {0}", res);
                         }
-
-                        for ev in dev.fetch_events().unwrap() {
-                            // event callback
-                            match device_type {
-                                input::InputDevice::Multitouch => {
-                                    for event in input::multitouch::decode(&ev, &state) {
-                                        if let Err(e) = tx.send(event) {
-                                            error!(
-                                                "Failed to write InputEvent into the channel: {}",
-                                                e
-                                            );
-                                        }
-                                    }
+                        input::InputDevice::Wacom => {
+                            if let Some(event) = input::wacom::decode(&ev, &state) {
+                                if let Err(e) = tx.send(event) {
+                                    error!("Failed to write InputEvent into the channel: {}", e);
                                 }
-                                input::InputDevice::Wacom => {
-                                    if let Some(event) = input::wacom::decode(&ev, &state) {
-                                        if let Err(e) = tx.send(event) {
-                                            error!(
-                                                "Failed to write InputEvent into the channel: {}",
-                                                e
-                                            );
-                                        }
-                                    }
-                                }
-                                input::InputDevice::GPIO => {
-                                    if let Some(event) = input::gpio::decode(&ev, &state) {
-                                        if let Err(e) = tx.send(event) {
-                                            error!(
-                                                "Failed to write InputEvent into the channel: {}",
-                                                e
-                                            );
-                                        }
-                                    }
+                            }
+                        }
+                        input::InputDevice::GPIO => {
+                            if let Some(event) = input::gpio::decode(&ev, &state) {
+                                if let Err(e) = tx.send(event) {
+                                    error!("Failed to write InputEvent into the channel: {}", e);
                                 }
-                                _ => unreachable!(),
-                            };
+                            }
                         }
-                    }
-                    exited.store(true, Ordering::Relaxed);
-                });
+                        _ => unreachable!(),
+                    };
+                }
             }
-        }
+            exited.store(true, Ordering::Relaxed);
+        });
+        Ok(())
     }
 }
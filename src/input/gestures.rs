@@ -0,0 +1,403 @@
+//! Taps, swipes and pinches.
+//!
+//! A `GestureRecognizer` follows `MultitouchEvent`s and turns them into
+//! `GestureEvent`s. One finger taps, double taps, long presses and swipes; two
+//! fingers pinch and rotate. A long press happens while the finger rests, without
+//! any event to follow, so it is reported by calling `long_press` whenever
+//! `next_long_press` says so.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use cgmath::{InnerSpace, Point2};
+
+use crate::input::{kinetic, GestureEvent, MultitouchEvent, SwipeDirection};
+
+#[derive(Clone, Debug)]
+enum State {
+    Idle,
+    /// A single finger is down
+    One {
+        tracking_id: i32,
+        start: Point2<f32>,
+        since: Instant,
+        /// Further than `slop` from `start`, so neither a tap nor a long press
+        moved: bool,
+        long_pressed: bool,
+        samples: VecDeque<(Instant, Point2<f32>)>,
+    },
+    /// Two fingers are down
+    Two {
+        distance: f32,
+        angle: f32,
+        /// Pinching or rotating, once the fingers moved far enough
+        active: bool,
+        scale: f32,
+        rotation: f32,
+    },
+    /// The gesture is over, or there are too many fingers for one, until all lift
+    Done,
+}
+
+/// The angle from `from` to `to` between -PI and PI
+fn angle_between(from: f32, to: f32) -> f32 {
+    let mut angle = to - from;
+    while angle > PI {
+        angle -= 2.0 * PI;
+    }
+    while angle <= -PI {
+        angle += 2.0 * PI;
+    }
+    angle
+}
+
+/// Turns finger movements into gesture events
+#[derive(Clone, Debug)]
+pub struct GestureRecognizer {
+    /// How far in pixels a finger may move and still tap or long press, and two
+    /// fingers have to move before pinching or rotating
+    pub slop: f32,
+    /// The longest a finger may rest for a tap
+    pub tap_timeout: Duration,
+    /// The longest between two taps of a double tap
+    pub double_tap_interval: Duration,
+    /// How long a finger has to rest for a long press
+    pub long_press: Duration,
+    /// The slowest a finger may lift for a swipe, in pixels per second
+    pub min_swipe_velocity: f32,
+    fingers: BTreeMap<i32, Point2<f32>>,
+    state: State,
+    last_tap: Option<(Instant, Point2<f32>)>,
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> GestureRecognizer {
+        GestureRecognizer {
+            slop: 20.0,
+            tap_timeout: Duration::from_millis(300),
+            double_tap_interval: Duration::from_millis(400),
+            long_press: Duration::from_millis(600),
+            min_swipe_velocity: 500.0,
+            fingers: BTreeMap::new(),
+            state: State::Idle,
+            last_tap: None,
+        }
+    }
+}
+
+impl GestureRecognizer {
+    pub fn new() -> GestureRecognizer {
+        GestureRecognizer::default()
+    }
+
+    /// The distance, angle and center of the two fingers down
+    fn pair(&self) -> Option<(f32, f32, Point2<f32>)> {
+        match self.fingers.len() {
+            2 => {
+                let mut fingers = self.fingers.values();
+                let (a, b) = (fingers.next()?, fingers.next()?);
+                let apart = b - a;
+                let center = Point2 {
+                    x: (a.x + b.x) / 2.0,
+                    y: (a.y + b.y) / 2.0,
+                };
+                Some((apart.magnitude(), apart.y.atan2(apart.x), center))
+            }
+            _ => None,
+        }
+    }
+
+    /// Follows `event`, which happened at `now`. The first tap of a double tap is
+    /// reported as a `GestureEvent::Tap` as well.
+    pub fn handle(&mut self, event: &MultitouchEvent, now: Instant) -> Vec<GestureEvent> {
+        let finger = match event.finger() {
+            Some(finger) => finger,
+            None => return Vec::new(),
+        };
+        let pos = Point2 {
+            x: f32::from(finger.pos.x),
+            y: f32::from(finger.pos.y),
+        };
+        let mut events = Vec::new();
+        match event {
+            MultitouchEvent::Release { .. } => {
+                self.fingers.remove(&finger.tracking_id);
+                if let State::One {
+                    tracking_id, start, ..
+                } = self.state
+                {
+                    if tracking_id == finger.tracking_id {
+                        events.extend(self.lift(start, pos, now));
+                    }
+                }
+                if self.fingers.is_empty() {
+                    self.state = State::Idle;
+                } else {
+                    self.state = State::Done;
+                }
+                return events;
+            }
+            MultitouchEvent::Press { .. } => {
+                self.fingers.insert(finger.tracking_id, pos);
+                self.state = match (&self.state, self.pair()) {
+                    (State::Idle, _) if self.fingers.len() == 1 => State::One {
+                        tracking_id: finger.tracking_id,
+                        start: pos,
+                        since: now,
+                        moved: false,
+                        long_pressed: false,
+                        samples: VecDeque::from([(now, pos)]),
+                    },
+                    (State::One { .. }, Some((distance, angle, _))) => State::Two {
+                        distance,
+                        angle,
+                        active: false,
+                        scale: 1.0,
+                        rotation: 0.0,
+                    },
+                    _ => State::Done,
+                };
+                return events;
+            }
+            _ => {
+                self.fingers.insert(finger.tracking_id, pos);
+            }
+        }
+
+        let pair = self.pair();
+        let slop = self.slop;
+        match &mut self.state {
+            State::One {
+                tracking_id,
+                start,
+                moved,
+                samples,
+                ..
+            } if *tracking_id == finger.tracking_id => {
+                if (pos - *start).magnitude() > slop {
+                    *moved = true;
+                }
+                kinetic::sample(samples, now, pos);
+            }
+            State::Two {
+                distance: start_distance,
+                angle: last_angle,
+                active,
+                scale,
+                rotation,
+            } => {
+                let (distance, angle, center) = match pair {
+                    Some(pair) => pair,
+                    None => return events,
+                };
+                let turn = angle_between(*last_angle, angle);
+                let turned = *rotation + turn;
+                if !*active {
+                    // How far the fingers moved apart, or around their center
+                    let stretched = (distance - *start_distance).abs();
+                    let around = turned.abs() * distance / 2.0;
+                    *active = stretched >= slop || around >= slop;
+                }
+                *last_angle = angle;
+                *rotation = turned;
+                if !*active {
+                    return events;
+                }
+                let new_scale = match *start_distance > 0.0 {
+                    true => distance / *start_distance,
+                    false => 1.0,
+                };
+                if new_scale != *scale {
+                    *scale = new_scale;
+                    events.push(GestureEvent::Pinch {
+                        center,
+                        scale: new_scale,
+                    });
+                }
+                if turn != 0.0 {
+                    events.push(GestureEvent::Rotate {
+                        center,
+                        angle: turned,
+                    });
+                }
+            }
+            _ => {}
+        }
+        events
+    }
+
+    /// The tap or swipe of the single finger down at `start`, lifted at `pos`
+    fn lift(&mut self, start: Point2<f32>, pos: Point2<f32>, now: Instant) -> Vec<GestureEvent> {
+        let (since, moved, long_pressed, velocity) = match &self.state {
+            State::One {
+                since,
+                moved,
+                long_pressed,
+                samples,
+                ..
+            } => (*since, *moved, *long_pressed, kinetic::velocity(samples)),
+            _ => return Vec::new(),
+        };
+        let mut events = Vec::new();
+        if moved {
+            if velocity.magnitude() >= self.min_swipe_velocity {
+                let direction = match velocity.x.abs() >= velocity.y.abs() {
+                    true if velocity.x > 0.0 => SwipeDirection::Right,
+                    true => SwipeDirection::Left,
+                    false if velocity.y > 0.0 => SwipeDirection::Down,
+                    false => SwipeDirection::Up,
+                };
+                events.push(GestureEvent::Swipe {
+                    start,
+                    end: pos,
+                    direction,
+                    velocity,
+                });
+            }
+            self.last_tap = None;
+        } else if !long_pressed && now.duration_since(since) <= self.tap_timeout {
+            events.push(GestureEvent::Tap { position: start });
+            let double = self.last_tap.take().filter(|&(at, last)| {
+                since.duration_since(at) <= self.double_tap_interval
+                    && (start - last).magnitude() <= 2.0 * self.slop
+            });
+            match double {
+                Some(_) => events.push(GestureEvent::DoubleTap { position: start }),
+                None => self.last_tap = Some((now, start)),
+            }
+        }
+        events
+    }
+
+    /// When `long_press` has to be called next, if a finger is resting
+    pub fn next_long_press(&self) -> Option<Instant> {
+        match self.state {
+            State::One {
+                since,
+                moved: false,
+                long_pressed: false,
+                ..
+            } => Some(since + self.long_press),
+            _ => None,
+        }
+    }
+
+    /// The long press of the finger resting since `long_press` before `now`, if any
+    pub fn long_press(&mut self, now: Instant) -> Option<GestureEvent> {
+        let at = self.next_long_press()?;
+        if now < at {
+            return None;
+        }
+        match &mut self.state {
+            State::One {
+                start,
+                long_pressed,
+                ..
+            } => {
+                *long_pressed = true;
+                self.last_tap = None;
+                Some(GestureEvent::LongPress { position: *start })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{moved, press, release, touch};
+
+    #[test]
+    fn test_gestures() {
+        let mut gestures = GestureRecognizer::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let position = Point2 { x: 100.0, y: 200.0 };
+
+        // Tap, then tap again close by for a double tap
+        assert!(gestures
+            .handle(&touch(press, 1, 100, 200), at(0))
+            .is_empty());
+        assert!(gestures.next_long_press().is_some());
+        assert!(gestures
+            .handle(&touch(moved, 1, 103, 201), at(50))
+            .is_empty());
+        assert_eq!(
+            gestures.handle(&touch(release, 1, 103, 201), at(100)),
+            [GestureEvent::Tap { position }]
+        );
+        gestures.handle(&touch(press, 2, 100, 200), at(250));
+        assert_eq!(
+            gestures.handle(&touch(release, 2, 100, 200), at(300)),
+            [
+                GestureEvent::Tap { position },
+                GestureEvent::DoubleTap { position }
+            ]
+        );
+
+        // Resting for a long press, which isn't a tap after that
+        gestures.handle(&touch(press, 3, 100, 200), at(1000));
+        assert_eq!(gestures.long_press(at(1100)), None);
+        let deadline = gestures.next_long_press().unwrap();
+        assert_eq!(
+            gestures.long_press(deadline),
+            Some(GestureEvent::LongPress { position })
+        );
+        assert_eq!(gestures.next_long_press(), None);
+        assert!(gestures
+            .handle(&touch(release, 3, 100, 200), at(2000))
+            .is_empty());
+
+        // A quick swipe to the left
+        gestures.handle(&touch(press, 4, 500, 500), at(3000));
+        for step in 1..=5u16 {
+            let ms = 3000 + u64::from(step) * 10;
+            gestures.handle(&touch(moved, 4, 500 - step * 40, 505), at(ms));
+        }
+        assert_eq!(gestures.next_long_press(), None);
+        match gestures.handle(&touch(release, 4, 300, 505), at(3050))[..] {
+            [GestureEvent::Swipe {
+                direction: SwipeDirection::Left,
+                velocity,
+                end,
+                ..
+            }] => {
+                assert!(velocity.x < -3000.0, "{:?}", velocity);
+                assert_eq!(end, Point2 { x: 300.0, y: 505.0 });
+            }
+            ref other => panic!("{:?}", other),
+        }
+
+        // Spreading two fingers apart zooms in, turning them around rotates
+        gestures.handle(&touch(press, 5, 400, 500), at(4000));
+        gestures.handle(&touch(press, 6, 600, 500), at(4010));
+        assert!(gestures
+            .handle(&touch(moved, 6, 610, 500), at(4020))
+            .is_empty());
+        let events = gestures.handle(&touch(moved, 6, 800, 500), at(4030));
+        assert_eq!(
+            events,
+            [GestureEvent::Pinch {
+                center: Point2 { x: 600.0, y: 500.0 },
+                scale: 2.0
+            }]
+        );
+        let events = gestures.handle(&touch(moved, 6, 400, 900), at(4040));
+        match events[..] {
+            [GestureEvent::Rotate { angle, center }] => {
+                assert_eq!(center, Point2 { x: 400.0, y: 700.0 });
+                assert!((angle - PI / 2.0).abs() < 1e-5, "{}", angle);
+            }
+            ref other => panic!("{:?}", other),
+        }
+        // Lifting one finger ends it, without a tap of the other
+        assert!(gestures
+            .handle(&touch(release, 5, 400, 500), at(4050))
+            .is_empty());
+        assert!(gestures
+            .handle(&touch(release, 6, 400, 900), at(4060))
+            .is_empty());
+    }
+}
@@ -25,7 +25,7 @@ impl ::std::default::Default for GPIOState {
 pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<InputEvent> {
     let state = match outer_state {
         InputDeviceState::GPIOState(ref state_arc) => state_arc,
-        _ => unreachable!(),
+        _ => return Some(InputEvent::Unknown {}),
     };
     match ev.event_type().0 {
         ecodes::EV_SYN => {
@@ -54,7 +54,7 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
                     state.states[4].store(ev.value() != 0, Ordering::Relaxed);
                     PhysicalButton::WAKEUP
                 }
-                _ => return None,
+                _ => return Some(InputEvent::Unknown {}),
             };
 
             let event = if ev.value() != 0 {
@@ -70,7 +70,7 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
                 "Unknown event on PhysicalButtonHandler (type: {0:?})",
                 ev.event_type()
             );
-            None
+            Some(InputEvent::Unknown {})
         }
     }
 }
@@ -0,0 +1,428 @@
+//! Keyboards attached over USB or the pogo pins, e.g. the Type Folio.
+//!
+//! A `Keyboard` turns the raw key events of such a device into `KeyboardEvent`s: it
+//! tracks the modifiers, repeats held keys at its own pace and translates keys into
+//! characters with a `KeyboardLayout`. `start` does so on a background thread for
+//! a keyboard's evdev device, of those `find` finds.
+
+use std::collections::HashMap;
+use std::os::unix::prelude::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use evdev::Key;
+use log::warn;
+
+use crate::input::{InputError, InputEvent, KeyboardEvent, Modifiers};
+
+/// Which character each key types, with and without shift
+#[derive(Clone, Debug, Default)]
+pub struct KeyboardLayout {
+    keys: HashMap<u16, (char, char)>,
+}
+
+impl KeyboardLayout {
+    /// The US QWERTY layout
+    pub fn us() -> KeyboardLayout {
+        let rows: [(&[Key], &str, &str); 4] = [
+            (
+                &[
+                    Key::KEY_GRAVE,
+                    Key::KEY_1,
+                    Key::KEY_2,
+                    Key::KEY_3,
+                    Key::KEY_4,
+                    Key::KEY_5,
+                    Key::KEY_6,
+                    Key::KEY_7,
+                    Key::KEY_8,
+                    Key::KEY_9,
+                    Key::KEY_0,
+                    Key::KEY_MINUS,
+                    Key::KEY_EQUAL,
+                ],
+                "`1234567890-=",
+                "~!@#$%^&*()_+",
+            ),
+            (
+                &[
+                    Key::KEY_Q,
+                    Key::KEY_W,
+                    Key::KEY_E,
+                    Key::KEY_R,
+                    Key::KEY_T,
+                    Key::KEY_Y,
+                    Key::KEY_U,
+                    Key::KEY_I,
+                    Key::KEY_O,
+                    Key::KEY_P,
+                    Key::KEY_LEFTBRACE,
+                    Key::KEY_RIGHTBRACE,
+                    Key::KEY_BACKSLASH,
+                ],
+                "qwertyuiop[]\\",
+                "QWERTYUIOP{}|",
+            ),
+            (
+                &[
+                    Key::KEY_A,
+                    Key::KEY_S,
+                    Key::KEY_D,
+                    Key::KEY_F,
+                    Key::KEY_G,
+                    Key::KEY_H,
+                    Key::KEY_J,
+                    Key::KEY_K,
+                    Key::KEY_L,
+                    Key::KEY_SEMICOLON,
+                    Key::KEY_APOSTROPHE,
+                ],
+                "asdfghjkl;'",
+                "ASDFGHJKL:\"",
+            ),
+            (
+                &[
+                    Key::KEY_Z,
+                    Key::KEY_X,
+                    Key::KEY_C,
+                    Key::KEY_V,
+                    Key::KEY_B,
+                    Key::KEY_N,
+                    Key::KEY_M,
+                    Key::KEY_COMMA,
+                    Key::KEY_DOT,
+                    Key::KEY_SLASH,
+                ],
+                "zxcvbnm,./",
+                "ZXCVBNM<>?",
+            ),
+        ];
+
+        let mut layout = KeyboardLayout::default();
+        for (keys, plain, shifted) in rows {
+            for ((key, plain), shifted) in keys.iter().zip(plain.chars()).zip(shifted.chars()) {
+                layout.set(key.code(), plain, shifted);
+            }
+        }
+        layout.set(Key::KEY_SPACE.code(), ' ', ' ');
+        layout.set(Key::KEY_ENTER.code(), '\n', '\n');
+        layout.set(Key::KEY_TAB.code(), '\t', '\t');
+        layout
+    }
+
+    /// Makes `key` type `plain`, or `shifted` with shift held
+    pub fn set(&mut self, key: u16, plain: char, shifted: char) {
+        self.keys.insert(key, (plain, shifted));
+    }
+
+    /// The character `key` types with `modifiers`. Caps lock only shifts letters.
+    pub fn translate(&self, key: u16, modifiers: Modifiers) -> Option<char> {
+        let (plain, shifted) = *self.keys.get(&key)?;
+        let shift = match plain.is_alphabetic() {
+            true => modifiers.shift != modifiers.caps_lock,
+            false => modifiers.shift,
+        };
+        Some(if shift { shifted } else { plain })
+    }
+}
+
+/// How held keys repeat
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyRepeat {
+    /// Before the first repeat
+    pub delay: Duration,
+    /// Between the following ones
+    pub interval: Duration,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> KeyRepeat {
+        KeyRepeat {
+            delay: Duration::from_millis(500),
+            interval: Duration::from_millis(50),
+        }
+    }
+}
+
+pub struct Keyboard {
+    layout: KeyboardLayout,
+    repeat: Option<KeyRepeat>,
+    modifiers: Modifiers,
+    /// The key repeating and when it next does
+    held: Option<(u16, Instant)>,
+}
+
+impl Keyboard {
+    pub fn new(layout: KeyboardLayout) -> Keyboard {
+        Keyboard {
+            layout,
+            repeat: Some(KeyRepeat::default()),
+            modifiers: Modifiers::default(),
+            held: None,
+        }
+    }
+
+    /// Changes how held keys repeat, `None` to not repeat them
+    pub fn set_repeat(&mut self, repeat: Option<KeyRepeat>) {
+        self.repeat = repeat;
+        self.held = None;
+    }
+
+    pub fn set_layout(&mut self, layout: KeyboardLayout) {
+        self.layout = layout;
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Handles the evdev key event of `key` with `value`, which is 1 for presses and 0
+    /// for releases. The repeats the kernel reports as 2 are ignored in favor of our own.
+    pub fn handle(&mut self, key: u16, value: i32, now: Instant) -> Vec<KeyboardEvent> {
+        let pressed = match value {
+            0 => false,
+            1 => true,
+            _ => return Vec::new(),
+        };
+        let modifier = match Key::new(key) {
+            Key::KEY_LEFTSHIFT | Key::KEY_RIGHTSHIFT => Some(&mut self.modifiers.shift),
+            Key::KEY_LEFTCTRL | Key::KEY_RIGHTCTRL => Some(&mut self.modifiers.ctrl),
+            Key::KEY_LEFTALT | Key::KEY_RIGHTALT => Some(&mut self.modifiers.alt),
+            _ => None,
+        };
+        let is_modifier = modifier.is_some() || key == Key::KEY_CAPSLOCK.code();
+        if let Some(modifier) = modifier {
+            *modifier = pressed;
+        } else if key == Key::KEY_CAPSLOCK.code() && pressed {
+            self.modifiers.caps_lock = !self.modifiers.caps_lock;
+        }
+
+        if !pressed {
+            if matches!(self.held, Some((held, _)) if held == key) {
+                self.held = None;
+            }
+            return vec![KeyboardEvent::Release {
+                key,
+                modifiers: self.modifiers,
+            }];
+        }
+        if !is_modifier {
+            // Like most keyboards, only the last key pressed repeats
+            self.held = self.repeat.map(|repeat| (key, now + repeat.delay));
+        }
+        self.press(key, false)
+    }
+
+    /// When `repeat` has to be called next for a held key
+    pub fn next_repeat(&self) -> Option<Instant> {
+        self.held.map(|(_, at)| at)
+    }
+
+    /// The repeats of the held key due at `now`
+    pub fn repeat(&mut self, now: Instant) -> Vec<KeyboardEvent> {
+        let (key, mut at, repeat) = match (self.held, self.repeat) {
+            (Some((key, at)), Some(repeat)) => (key, at, repeat),
+            _ => return Vec::new(),
+        };
+        let interval = repeat.interval.max(Duration::from_millis(1));
+        let mut events = Vec::new();
+        while at <= now {
+            events.extend(self.press(key, true));
+            at += interval;
+        }
+        self.held = Some((key, at));
+        events
+    }
+
+    fn press(&self, key: u16, repeat: bool) -> Vec<KeyboardEvent> {
+        let mut events = vec![KeyboardEvent::Press {
+            key,
+            modifiers: self.modifiers,
+            repeat,
+        }];
+        if !self.modifiers.ctrl && !self.modifiers.alt {
+            if let Some(character) = self.layout.translate(key, self.modifiers) {
+                events.push(KeyboardEvent::Text { character });
+            }
+        }
+        events
+    }
+}
+
+/// Whether a device with `keys` is a keyboard, rather than e.g. the buttons or the pen
+fn is_keyboard(keys: &evdev::AttributeSetRef<Key>) -> bool {
+    [Key::KEY_A, Key::KEY_Z, Key::KEY_SPACE, Key::KEY_ENTER]
+        .into_iter()
+        .all(|key| keys.contains(key))
+}
+
+/// The evdev devices of the keyboards attached right now, e.g. `/dev/input/event3`
+pub fn find() -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let entries = match Path::new("/dev/input").read_dir() {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Unable to look for keyboards: {}", err);
+            return found;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_event = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("event"));
+        if !is_event {
+            continue;
+        }
+        let keyboard = evdev::Device::open(&path)
+            .ok()
+            .and_then(|dev| dev.supported_keys().map(is_keyboard))
+            .unwrap_or(false);
+        if keyboard {
+            found.push(path);
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Reads the keyboard at `path` on a background thread, sending what `keyboard` makes
+/// of it to `tx` as `InputEvent::Keyboard`. The thread ends when `tx` is disconnected
+/// or the keyboard is unplugged.
+pub fn start(
+    path: impl AsRef<Path>,
+    mut keyboard: Keyboard,
+    tx: Sender<InputEvent>,
+) -> Result<(), InputError> {
+    let path = path.as_ref();
+    let mut dev = evdev::Device::open(path).map_err(|source| InputError::Open {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let epfd = epoll::create(false).map_err(InputError::Epoll)?;
+    let readable = epoll::Event {
+        events: epoll::Events::EPOLLIN.bits(),
+        data: 0,
+    };
+    if let Err(err) = epoll::ctl(
+        epfd,
+        epoll::ControlOptions::EPOLL_CTL_ADD,
+        dev.as_raw_fd(),
+        readable,
+    ) {
+        let _ = epoll::close(epfd);
+        return Err(InputError::Epoll(err));
+    }
+
+    std::thread::spawn(move || {
+        let mut ready = [readable];
+        loop {
+            // Waits for the device, or for the next repeat of a held key
+            let timeout = keyboard.next_repeat().map_or(-1, |at| {
+                at.saturating_duration_since(Instant::now()).as_millis() as i32
+            });
+            let mut events = match epoll::wait(epfd, timeout, &mut ready) {
+                Ok(0) => Vec::new(),
+                Ok(_) => match dev.fetch_events() {
+                    Ok(fetched) => fetched
+                        .filter(|ev| ev.event_type() == evdev::EventType::KEY)
+                        .flat_map(|ev| keyboard.handle(ev.code(), ev.value(), Instant::now()))
+                        .collect(),
+                    Err(err) => {
+                        warn!("Stopped reading the keyboard: {}", err);
+                        break;
+                    }
+                },
+                Err(err) => {
+                    warn!("Failed to wait for the keyboard: {}", err);
+                    break;
+                }
+            };
+            events.extend(keyboard.repeat(Instant::now()));
+            let sent = events
+                .into_iter()
+                .all(|event| tx.send(InputEvent::Keyboard { event }).is_ok());
+            if !sent {
+                break;
+            }
+        }
+        let _ = epoll::close(epfd);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(events: &[KeyboardEvent]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                KeyboardEvent::Text { character } => Some(*character),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_is_keyboard() {
+        let keys = [
+            Key::KEY_A,
+            Key::KEY_Z,
+            Key::KEY_SPACE,
+            Key::KEY_ENTER,
+            Key::KEY_1,
+        ];
+        assert!(is_keyboard(
+            &keys.into_iter().collect::<evdev::AttributeSet<_>>()
+        ));
+        let buttons = [Key::KEY_POWER, Key::KEY_LEFT, Key::KEY_HOME, Key::KEY_RIGHT];
+        assert!(!is_keyboard(
+            &buttons.into_iter().collect::<evdev::AttributeSet<_>>()
+        ));
+    }
+
+    #[test]
+    fn test_modifiers_and_repeat() {
+        let mut keyboard = Keyboard::new(KeyboardLayout::us());
+        let start = Instant::now();
+        let mut typed = Vec::new();
+        let mut tap = |keyboard: &mut Keyboard, key: Key| {
+            typed.extend(keyboard.handle(key.code(), 1, start));
+            typed.extend(keyboard.handle(key.code(), 0, start));
+            text(&typed)
+        };
+
+        assert_eq!(tap(&mut keyboard, Key::KEY_A), "a");
+        keyboard.handle(Key::KEY_LEFTSHIFT.code(), 1, start);
+        assert_eq!(tap(&mut keyboard, Key::KEY_1), "a!");
+        keyboard.handle(Key::KEY_CAPSLOCK.code(), 1, start);
+        keyboard.handle(Key::KEY_CAPSLOCK.code(), 0, start);
+        // Caps lock and shift cancel out for letters
+        assert_eq!(tap(&mut keyboard, Key::KEY_B), "a!b");
+        keyboard.handle(Key::KEY_LEFTSHIFT.code(), 0, start);
+        assert_eq!(tap(&mut keyboard, Key::KEY_C), "a!bC");
+        keyboard.handle(Key::KEY_LEFTCTRL.code(), 1, start);
+        assert_eq!(tap(&mut keyboard, Key::KEY_V), "a!bC");
+        assert!(keyboard.modifiers().ctrl && keyboard.modifiers().caps_lock);
+        keyboard.handle(Key::KEY_LEFTCTRL.code(), 0, start);
+
+        // Held down, with the kernel's repeats ignored
+        let repeat = KeyRepeat::default();
+        assert_eq!(text(&keyboard.handle(Key::KEY_X.code(), 1, start)), "X");
+        assert!(keyboard.handle(Key::KEY_X.code(), 2, start).is_empty());
+        assert_eq!(keyboard.next_repeat(), Some(start + repeat.delay));
+        assert!(keyboard.repeat(start + repeat.delay / 2).is_empty());
+        let repeated = keyboard.repeat(start + repeat.delay + repeat.interval);
+        assert_eq!(text(&repeated), "XX");
+        assert!(matches!(
+            repeated[0],
+            KeyboardEvent::Press { repeat: true, .. }
+        ));
+        keyboard.handle(Key::KEY_X.code(), 0, start);
+        assert_eq!(keyboard.next_repeat(), None);
+    }
+}
@@ -0,0 +1,85 @@
+//! The velocity of finger movements, and the momentum they leave behind.
+//!
+//! Shared by the scroll and gesture recognizers and `ScrollView`, which all keep the
+//! recent positions of a finger to tell how fast it moved when it lifts. Scrolling
+//! then coasts on, exponentially slowing down.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use cgmath::{Point2, Vector2};
+
+/// How far back movements count towards the velocity
+pub(crate) const VELOCITY_WINDOW: Duration = Duration::from_millis(100);
+
+/// The default of how quickly momentum fades, as the fraction of the velocity lost
+/// per second on a log scale
+pub(crate) const FRICTION: f32 = 4.0;
+
+/// The default speed momentum ends below, in pixels per second
+pub(crate) const MIN_VELOCITY: f32 = 50.0;
+
+/// Adds the position `pos` at `now` to `samples`, dropping those older than
+/// `VELOCITY_WINDOW` but the last two
+pub(crate) fn sample(
+    samples: &mut VecDeque<(Instant, Point2<f32>)>,
+    now: Instant,
+    pos: Point2<f32>,
+) {
+    samples.push_back((now, pos));
+    while samples.len() > 2 && now.duration_since(samples[0].0) > VELOCITY_WINDOW {
+        samples.pop_front();
+    }
+}
+
+/// In pixels per second, over the recent positions
+pub(crate) fn velocity(samples: &VecDeque<(Instant, Point2<f32>)>) -> Vector2<f32> {
+    match (samples.front(), samples.back()) {
+        (Some(&(first, from)), Some(&(last, to))) if last > first => {
+            (to - from) / last.duration_since(first).as_secs_f32()
+        }
+        _ => Vector2 { x: 0.0, y: 0.0 },
+    }
+}
+
+/// Coasts at `velocity` for `dt` seconds, slowing down by `friction`. Returns the
+/// distance covered and the velocity left.
+pub(crate) fn coast(
+    velocity: Vector2<f32>,
+    friction: f32,
+    dt: f32,
+) -> (Vector2<f32>, Vector2<f32>) {
+    // The distance covered while exponentially slowing down over dt
+    let decay = (-friction * dt).exp();
+    let delta = match friction > 0.0 {
+        true => velocity * ((1.0 - decay) / friction),
+        false => velocity * dt,
+    };
+    (delta, velocity * decay)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_coast() {
+        let velocity = Vector2 { x: 0.0, y: 1000.0 };
+        assert_eq!(
+            coast(velocity, 0.0, 0.5),
+            (Vector2 { x: 0.0, y: 500.0 }, velocity)
+        );
+
+        // However long it coasts, it doesn't get further than velocity / friction
+        let (mut moved, mut left) = (0.0, velocity);
+        for _ in 0..100 {
+            let (delta, slower) = coast(left, FRICTION, 0.1);
+            assert!(slower.y < left.y);
+            moved += delta.y;
+            left = slower;
+        }
+        assert!((moved - 1000.0 / FRICTION).abs() < 0.1, "{}", moved);
+        let (whole, _) = coast(velocity, FRICTION, 10.0);
+        assert!((moved - whole.y).abs() < 0.1);
+    }
+}
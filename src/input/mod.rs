@@ -15,6 +15,22 @@ pub mod gpio;
 #[cfg(feature = "input")]
 pub mod multitouch;
 
+/// Contains the code to turn key events of keyboards into text
+#[cfg(feature = "input")]
+pub mod keyboard;
+
+/// Contains the code to turn two finger drags into scroll events
+pub mod scroll;
+
+/// Contains the code to turn finger movements into taps, swipes and pinches
+pub mod gestures;
+
+/// Contains the velocity of finger movements, shared by the recognizers
+pub(crate) mod kinetic;
+
+/// Contains the code to smooth the strokes of the pen
+pub mod smoothing;
+
 /// Contains the ev codes in use
 pub mod ecodes;
 
@@ -23,6 +39,12 @@ pub mod ecodes;
 #[cfg(feature = "scan")]
 pub mod scan;
 
+/// Errors of finding and opening the input devices
+#[cfg(feature = "scan")]
+pub mod error;
+#[cfg(feature = "scan")]
+pub use error::InputError;
+
 #[derive(PartialEq, Copy, Clone, Debug, Hash, Eq)]
 pub enum InputDevice {
     Wacom,
@@ -38,6 +60,8 @@ pub enum InputDeviceState {
     GPIOState(std::sync::Arc<gpio::GPIOState>),
 }
 
+#[cfg(feature = "input")]
+use crate::device::rotate::CoordinatePart;
 #[cfg(feature = "input")]
 use std::sync::Arc;
 #[cfg(feature = "input")]
@@ -73,6 +97,109 @@ impl InputDeviceState {
     }
 }
 
+/// How the raw coordinates of an input device map onto the display
+#[cfg(feature = "input")]
+pub(crate) struct Geometry {
+    /// Range of the raw axes, before rotation
+    orig_size: cgmath::Vector2<u16>,
+    placement: crate::device::InputDevicePlacement,
+    /// Range of the axes after rotation
+    size: cgmath::Vector2<u16>,
+    /// Factors from rotated device coordinates to display pixels
+    pub scale: cgmath::Vector2<f32>,
+}
+
+#[cfg(feature = "input")]
+impl Geometry {
+    pub fn new(
+        orig_size: cgmath::Vector2<u16>,
+        placement: crate::device::InputDevicePlacement,
+    ) -> Geometry {
+        let size = placement.rotation.rotated_size(&orig_size);
+        Geometry {
+            orig_size,
+            placement,
+            size,
+            scale: cgmath::Vector2 {
+                x: f32::from(crate::dimensions::DISPLAYWIDTH) / f32::from(size.x.max(1)),
+                y: f32::from(crate::dimensions::DISPLAYHEIGHT) / f32::from(size.y.max(1)),
+            },
+        }
+    }
+
+    /// Rotates and inverts a raw value of the X axis. Values outside of the device's
+    /// range, which a well behaved device never reports, are clamped to it.
+    pub fn map_x(&self, value: i32) -> CoordinatePart {
+        let x = value.clamp(0, i32::from(self.orig_size.x)) as u16;
+        self.invert(
+            self.placement
+                .rotation
+                .rotate_part(CoordinatePart::X(x), &self.orig_size),
+        )
+    }
+
+    /// Like `map_x`, for the Y axis
+    pub fn map_y(&self, value: i32) -> CoordinatePart {
+        let y = value.clamp(0, i32::from(self.orig_size.y)) as u16;
+        self.invert(
+            self.placement
+                .rotation
+                .rotate_part(CoordinatePart::Y(y), &self.orig_size),
+        )
+    }
+
+    /// The raw device position that maps to the display position `pos`
+    pub fn unmap(&self, pos: cgmath::Point2<f32>) -> cgmath::Point2<i32> {
+        use crate::device::rotate::InputDeviceRotation;
+
+        let axis = |value: f32, scale: f32, size: u16, invert: bool| {
+            let value = ((value / scale).round() as i32).clamp(0, i32::from(size));
+            match invert {
+                true => i32::from(size) - value,
+                false => value,
+            }
+        };
+        let x = axis(pos.x, self.scale.x, self.size.x, self.placement.invert_x);
+        let y = axis(pos.y, self.scale.y, self.size.y, self.placement.invert_y);
+        let (width, height) = (i32::from(self.orig_size.x), i32::from(self.orig_size.y));
+        match self.placement.rotation {
+            InputDeviceRotation::Rot0 => cgmath::Point2 { x, y },
+            InputDeviceRotation::Rot90 => cgmath::Point2 {
+                x: y,
+                y: height - x,
+            },
+            InputDeviceRotation::Rot180 => cgmath::Point2 {
+                x: width - x,
+                y: height - y,
+            },
+            InputDeviceRotation::Rot270 => cgmath::Point2 { x: width - y, y: x },
+        }
+    }
+
+    fn invert(&self, rotated: CoordinatePart) -> CoordinatePart {
+        match rotated {
+            CoordinatePart::X(x) if self.placement.invert_x => {
+                CoordinatePart::X(self.size.x.saturating_sub(x))
+            }
+            CoordinatePart::Y(y) if self.placement.invert_y => {
+                CoordinatePart::Y(self.size.y.saturating_sub(y))
+            }
+            rotated => rotated,
+        }
+    }
+}
+
+/// The raw position `device` reports for the display position `pos`, e.g. to inject
+/// synthetic events into it. `None` for devices without positions.
+#[cfg(feature = "input")]
+pub fn raw_position(device: InputDevice, pos: cgmath::Point2<f32>) -> Option<cgmath::Point2<i32>> {
+    match device {
+        InputDevice::Wacom => Some(wacom::GEOMETRY.unmap(pos)),
+        InputDevice::Multitouch => Some(multitouch::GEOMETRY.unmap(pos)),
+        InputDevice::GPIO | InputDevice::Unknown => None,
+    }
+}
+
 #[repr(u16)]
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum WacomPen {
@@ -89,6 +216,20 @@ pub enum WacomPen {
     Stylus2 = ecodes::BTN_STYLUS2,
 }
 
+impl WacomPen {
+    /// The pen key reported with the event code `code`, if any
+    pub fn from_code(code: u16) -> Option<WacomPen> {
+        match code {
+            ecodes::BTN_TOOL_PEN => Some(WacomPen::ToolPen),
+            ecodes::BTN_TOOL_RUBBER => Some(WacomPen::ToolRubber),
+            ecodes::BTN_TOUCH => Some(WacomPen::Touch),
+            ecodes::BTN_STYLUS => Some(WacomPen::Stylus),
+            ecodes::BTN_STYLUS2 => Some(WacomPen::Stylus2),
+            _ => None,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum WacomEventType {
     InstrumentChange,
@@ -107,15 +248,151 @@ pub enum WacomEvent {
         position: cgmath::Point2<f32>,
         distance: u16,
         tilt: cgmath::Vector2<u16>,
+        tool: WacomTool,
+        buttons: StylusButtons,
     },
     Draw {
         position: cgmath::Point2<f32>,
         pressure: u16,
         tilt: cgmath::Vector2<u16>,
+        tool: WacomTool,
+        buttons: StylusButtons,
     },
     Unknown,
 }
 
+/// Which end of the pen is near the display
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default, Hash)]
+pub enum WacomTool {
+    #[default]
+    Pen,
+    /// The eraser end of pens that have one, like the Marker Plus
+    Eraser,
+}
+
+/// Which of the buttons on the side of the pen are held, for pens that have them
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default, Hash)]
+pub struct StylusButtons {
+    /// `WacomPen::Stylus`
+    pub primary: bool,
+    /// `WacomPen::Stylus2`
+    pub secondary: bool,
+}
+
+impl WacomEvent {
+    /// The pressure of a `Draw` event between 0.0 and 1.0, whatever the digitizer
+    /// reports at most
+    pub fn normalized_pressure(&self) -> Option<f32> {
+        match *self {
+            WacomEvent::Draw { pressure, .. } => {
+                Some(normalize(pressure, AxisMaxima::current().wacom_pressure))
+            }
+            _ => None,
+        }
+    }
+
+    /// The tilt of a `Hover` or `Draw` event along the axes of the display, between
+    /// -1.0 and 1.0 for leaning all the way left or up to all the way right or down
+    pub fn normalized_tilt(&self) -> Option<cgmath::Vector2<f32>> {
+        let tilt = match *self {
+            WacomEvent::Hover { tilt, .. } | WacomEvent::Draw { tilt, .. } => tilt,
+            _ => return None,
+        };
+        let max = f32::from(AxisMaxima::current().wacom_tilt.max(1));
+        // Negative tilts are wrapped into the u16 by the decoder
+        let axis = |value: u16| (f32::from(value as i16) / max).clamp(-1.0, 1.0);
+        let placement = crate::device::InputDevicePlacement::wacom();
+        let mut tilt = placement.rotation.rotate_vector(cgmath::Vector2 {
+            x: axis(tilt.x),
+            y: axis(tilt.y),
+        });
+        if placement.invert_x {
+            tilt.x = -tilt.x;
+        }
+        if placement.invert_y {
+            tilt.y = -tilt.y;
+        }
+        Some(tilt)
+    }
+
+    /// Which way the pen of a `Hover` or `Draw` event leans, and how steeply
+    pub fn orientation(&self) -> Option<PenOrientation> {
+        self.normalized_tilt().map(PenOrientation::from_tilt)
+    }
+}
+
+/// Which way the pen leans and how steeply, see `WacomEvent::orientation`
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct PenOrientation {
+    /// The direction the pen leans towards on the display, in radians clockwise from
+    /// pointing right, from 0.0 up to 2 PI
+    pub azimuth: f32,
+    /// The angle between the pen and the display in radians, PI / 2 when upright
+    pub altitude: f32,
+}
+
+impl PenOrientation {
+    /// The orientation of a pen with `tilt` along the axes of the display, see
+    /// `WacomEvent::normalized_tilt`
+    pub fn from_tilt(tilt: cgmath::Vector2<f32>) -> PenOrientation {
+        use std::f32::consts::{FRAC_PI_2, PI};
+
+        let (sin_x, cos_x) = (tilt.x.clamp(-1.0, 1.0) * FRAC_PI_2).sin_cos();
+        let (sin_y, cos_y) = (tilt.y.clamp(-1.0, 1.0) * FRAC_PI_2).sin_cos();
+        // The direction of the pen from its tip, scaled to stay finite when it lies flat
+        let (x, y, z) = (sin_x * cos_y, sin_y * cos_x, cos_x * cos_y);
+        let mut azimuth = y.atan2(x);
+        if azimuth < 0.0 {
+            azimuth += 2.0 * PI;
+        }
+        PenOrientation {
+            azimuth,
+            altitude: z.atan2(x.hypot(y)),
+        }
+    }
+}
+
+/// The highest raw values the input devices report, which differ between devices
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct AxisMaxima {
+    pub wacom_pressure: u16,
+    /// Of the tilt of the pen in either direction along either axis
+    pub wacom_tilt: u16,
+    pub multitouch_pressure: u16,
+    /// Of the major axis of a touch
+    pub multitouch_size: u16,
+}
+
+impl AxisMaxima {
+    /// What both the rM1 and rM2 report
+    pub const REMARKABLE: AxisMaxima = AxisMaxima {
+        wacom_pressure: 4095,
+        wacom_tilt: 9000,
+        multitouch_pressure: 255,
+        multitouch_size: 255,
+    };
+
+    /// The maxima of the scanned input devices, or `REMARKABLE`'s if they couldn't
+    /// be scanned
+    pub fn current() -> AxisMaxima {
+        #[cfg(feature = "scan")]
+        if let Ok(devs) = scan::scanned() {
+            let or = |max: u16, fallback: u16| if max > 0 { max } else { fallback };
+            return AxisMaxima {
+                wacom_pressure: or(devs.wacom_max_pressure, 4095),
+                wacom_tilt: or(devs.wacom_max_tilt, 9000),
+                multitouch_pressure: or(devs.mt_max_pressure, 255),
+                multitouch_size: or(devs.mt_max_touch_major, 255),
+            };
+        }
+        AxisMaxima::REMARKABLE
+    }
+}
+
+fn normalize(value: u16, max: u16) -> f32 {
+    (f32::from(value) / f32::from(max.max(1))).min(1.0)
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Finger {
     pub tracking_id: i32,
@@ -123,10 +400,39 @@ pub struct Finger {
     pub pos: cgmath::Point2<u16>,
     pub(crate) pos_updated: bool, // Report motion at SYN_REPORT?
 
+    /// Raw pressure, see `normalized_pressure`
+    pub pressure: u16,
+    /// Raw length of the major axis of the contact area, see `normalized_size`
+    pub size: u16,
+
     pub(crate) last_pressed: bool,
     pub pressed: bool,
 }
 
+impl Finger {
+    /// A finger as reported at `pos`, e.g. to script input for `sim::Simulation`
+    pub fn new(tracking_id: i32, pos: cgmath::Point2<u16>, pressed: bool) -> Finger {
+        Finger {
+            tracking_id,
+            pos,
+            pressed,
+            last_pressed: pressed,
+            ..Default::default()
+        }
+    }
+
+    /// The pressure between 0.0 and 1.0, whatever the touchscreen reports at most
+    pub fn normalized_pressure(&self) -> f32 {
+        normalize(self.pressure, AxisMaxima::current().multitouch_pressure)
+    }
+
+    /// The size of the contact area between 0.0 and 1.0, whatever the touchscreen
+    /// reports at most
+    pub fn normalized_size(&self) -> f32 {
+        normalize(self.size, AxisMaxima::current().multitouch_size)
+    }
+}
+
 impl Default for Finger {
     fn default() -> Finger {
         Finger {
@@ -136,6 +442,8 @@ impl Default for Finger {
                 y: u16::MAX,
             },
             pos_updated: false,
+            pressure: 0,
+            size: 0,
             last_pressed: false,
             pressed: false,
         }
@@ -177,11 +485,156 @@ pub enum GPIOEvent {
     Unknown,
 }
 
+/// The modifiers held, or locked for caps lock, while a key is pressed
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum KeyboardEvent {
+    /// `key` is the evdev key code, `repeat` is set for presses repeated while held
+    Press {
+        key: u16,
+        modifiers: Modifiers,
+        repeat: bool,
+    },
+    Release {
+        key: u16,
+        modifiers: Modifiers,
+    },
+    /// The character typed by the press before, if the layout has one for it and
+    /// neither ctrl nor alt is held
+    Text {
+        character: char,
+    },
+}
+
+/// Scrolling with two fingers, see `scroll::ScrollRecognizer`
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ScrollEvent {
+    Begin,
+    /// The content should move by `delta` pixels. `velocity` is in pixels per second,
+    /// `momentum` is set once the fingers lifted.
+    Scroll {
+        delta: cgmath::Vector2<f32>,
+        velocity: cgmath::Vector2<f32>,
+        momentum: bool,
+    },
+    End,
+}
+
+/// A point drawn with the pen, see `smoothing::StrokeFilter`
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct SmoothedPoint {
+    /// The position of the `WacomEvent::Draw`
+    pub raw: cgmath::Point2<f32>,
+    pub position: cgmath::Point2<f32>,
+    /// Where the pen is expected to be shortly
+    pub predicted: cgmath::Point2<f32>,
+    /// In pixels per second
+    pub velocity: cgmath::Vector2<f32>,
+    pub pressure: u16,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Taps, swipes and pinches, see `gestures::GestureRecognizer`
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum GestureEvent {
+    Tap {
+        position: cgmath::Point2<f32>,
+    },
+    /// Follows the `Tap` of the second tap
+    DoubleTap {
+        position: cgmath::Point2<f32>,
+    },
+    LongPress {
+        position: cgmath::Point2<f32>,
+    },
+    /// A finger lifted from `end` while moving, `velocity` is in pixels per second
+    Swipe {
+        start: cgmath::Point2<f32>,
+        end: cgmath::Point2<f32>,
+        direction: SwipeDirection,
+        velocity: cgmath::Vector2<f32>,
+    },
+    /// Two fingers moved apart or together, `scale` is their distance relative to
+    /// when the second one touched
+    Pinch {
+        center: cgmath::Point2<f32>,
+        scale: f32,
+    },
+    /// Two fingers turned around their center by `angle` radians clockwise since the
+    /// second one touched
+    Rotate {
+        center: cgmath::Point2<f32>,
+        angle: f32,
+    },
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum InputEvent {
-    WacomEvent { event: WacomEvent },
-    MultitouchEvent { event: MultitouchEvent },
-    GPIO { event: GPIOEvent },
+    WacomEvent {
+        event: WacomEvent,
+    },
+    MultitouchEvent {
+        event: MultitouchEvent,
+    },
+    GPIO {
+        event: GPIOEvent,
+    },
+    /// The result of `ApplicationContext::recognize`
+    Recognition {
+        result: crate::recognition::Recognition,
+    },
+    /// A scratch-out gesture over the given area, see
+    /// `ApplicationContext::set_scratch_out_detector`
+    EraseRegion {
+        position: cgmath::Point2<u32>,
+        size: cgmath::Vector2<u32>,
+    },
+    /// See `keyboard::start`
+    Keyboard {
+        event: KeyboardEvent,
+    },
+    /// See `ApplicationContext::set_scroll_recognizer`
+    Scroll {
+        event: ScrollEvent,
+    },
+    /// See `ApplicationContext::set_gesture_recognizer`
+    Gesture {
+        event: GestureEvent,
+    },
+    /// Follows every `WacomEvent::Draw`, see `ApplicationContext::set_stroke_filter`
+    SmoothedDraw {
+        point: SmoothedPoint,
+    },
+    /// A refresh didn't complete in time, see `ApplicationContext::watch_refreshes`
+    RefreshHang {
+        marker: u32,
+        waited: std::time::Duration,
+        recovered: bool,
+    },
+    /// The display was fully refreshed to clear ghosting, see
+    /// `ApplicationContext::set_ghosting_policy`
+    GhostingCleared {
+        marker: u32,
+    },
+    /// A notification was posted or withdrawn, see
+    /// `ApplicationContext::listen_for_notifications`
+    Notification {
+        event: crate::notification::NotificationEvent,
+    },
     Unknown {},
 }
 
@@ -190,3 +643,338 @@ impl Default for InputEvent {
         InputEvent::Unknown {}
     }
 }
+
+#[cfg(all(test, feature = "input"))]
+mod test {
+    use super::*;
+    use crate::device::rotate::InputDeviceRotation;
+    use crate::device::InputDevicePlacement;
+    use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH};
+    use evdev::EventType;
+
+    /// Small xorshift generator, so failures reproduce without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.next() as usize % items.len()]
+        }
+
+        /// Mostly event types and codes the decoders know about, plus garbage
+        fn event(&mut self) -> evdev::InputEvent {
+            let event_type = match self.next() % 8 {
+                0 => self.next() as u16,
+                n => self
+                    .pick(&[ecodes::EV_SYN, ecodes::EV_KEY, ecodes::EV_ABS])
+                    .wrapping_add((n == 7) as u16),
+            };
+            let code = match self.next() % 4 {
+                0 => self.next() as u16,
+                _ => self.pick(&[
+                    ecodes::SYN_REPORT,
+                    ecodes::ABS_X,
+                    ecodes::ABS_Y,
+                    ecodes::ABS_PRESSURE,
+                    ecodes::ABS_DISTANCE,
+                    ecodes::ABS_TILT_X,
+                    ecodes::ABS_TILT_Y,
+                    ecodes::ABS_MT_SLOT,
+                    ecodes::ABS_MT_POSITION_X,
+                    ecodes::ABS_MT_POSITION_Y,
+                    ecodes::ABS_MT_PRESSURE,
+                    ecodes::ABS_MT_TOUCH_MAJOR,
+                    ecodes::ABS_MT_TRACKING_ID,
+                    ecodes::BTN_TOOL_PEN,
+                    ecodes::BTN_TOOL_RUBBER,
+                    ecodes::BTN_TOUCH,
+                    ecodes::BTN_STYLUS,
+                    0x142,
+                    ecodes::KEY_POWER,
+                ]),
+            };
+            let value = match self.next() % 4 {
+                0 => self.pick(&[i32::MIN, -1, 0, 1, i32::from(u16::MAX), i32::MAX]),
+                _ => (self.next() % 30000) as i32,
+            };
+            evdev::InputEvent::new(EventType(event_type), code, value)
+        }
+    }
+
+    fn geometries() -> Vec<Geometry> {
+        let rotations = || {
+            [
+                InputDeviceRotation::Rot0,
+                InputDeviceRotation::Rot90,
+                InputDeviceRotation::Rot180,
+                InputDeviceRotation::Rot270,
+            ]
+        };
+        let mut geometries = Vec::new();
+        for size in [(20966, 15725), (0, 0)] {
+            for invert in [false, true] {
+                for rotation in rotations() {
+                    let placement = InputDevicePlacement {
+                        rotation,
+                        invert_x: invert,
+                        invert_y: !invert,
+                    };
+                    geometries.push(Geometry::new(size.into(), placement));
+                }
+            }
+        }
+        geometries
+    }
+
+    fn assert_on_display(x: f32, y: f32) {
+        assert!((0.0..=f32::from(DISPLAYWIDTH)).contains(&x), "x = {}", x);
+        assert!((0.0..=f32::from(DISPLAYHEIGHT)).contains(&y), "y = {}", y);
+    }
+
+    #[test]
+    fn test_decoders_survive_garbage() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for geometry in geometries() {
+            let states = [
+                InputDeviceState::new(InputDevice::Wacom),
+                InputDeviceState::new(InputDevice::Multitouch),
+                InputDeviceState::new(InputDevice::GPIO),
+            ];
+            for _ in 0..5000 {
+                let ev = rng.event();
+                // Usually the matching state, sometimes another device's
+                let state = match rng.next() % 16 {
+                    0 => rng.pick(&[0, 1, 2]),
+                    _ => 0,
+                };
+                if let Some(InputEvent::WacomEvent {
+                    event: WacomEvent::Draw { position, .. } | WacomEvent::Hover { position, .. },
+                }) = wacom::decode_with(&ev, &states[state], &geometry)
+                {
+                    assert_on_display(position.x, position.y);
+                }
+
+                let state = match rng.next() % 16 {
+                    0 => rng.pick(&[0, 1, 2]),
+                    _ => 1,
+                };
+                for event in multitouch::decode_with(&ev, &states[state], &geometry) {
+                    if let InputEvent::MultitouchEvent { event } = event {
+                        let finger = event.finger().unwrap();
+                        assert!((0.0..=1.0).contains(&finger.normalized_pressure()));
+                        assert!((0.0..=1.0).contains(&finger.normalized_size()));
+                        let pos = finger.pos;
+                        // Unset axes stay at u16::MAX until reported
+                        if pos.x != u16::MAX && pos.y != u16::MAX {
+                            assert_on_display(f32::from(pos.x), f32::from(pos.y));
+                        }
+                    }
+                }
+
+                let state = match rng.next() % 16 {
+                    0 => rng.pick(&[0, 1, 2]),
+                    _ => 2,
+                };
+                gpio::decode(&ev, &states[state]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unmap_round_trips() {
+        for geometry in &geometries()[..8] {
+            let pos = cgmath::Point2 {
+                x: 300.0,
+                y: 1200.0,
+            };
+            let raw = geometry.unmap(pos);
+            let mut mapped = cgmath::Point2 { x: 0.0, y: 0.0 };
+            for part in [geometry.map_x(raw.x), geometry.map_y(raw.y)] {
+                match part {
+                    CoordinatePart::X(x) => mapped.x = f32::from(x) * geometry.scale.x,
+                    CoordinatePart::Y(y) => mapped.y = f32::from(y) * geometry.scale.y,
+                }
+            }
+            assert!((mapped.x - pos.x).abs() < 1.0, "{:?}", mapped);
+            assert!((mapped.y - pos.y).abs() < 1.0, "{:?}", mapped);
+        }
+    }
+
+    #[test]
+    fn test_unexpected_events_are_unknown() {
+        let geometry = &geometries()[0];
+        let wacom = InputDeviceState::new(InputDevice::Wacom);
+        let multitouch = InputDeviceState::new(InputDevice::Multitouch);
+        let gpio = InputDeviceState::new(InputDevice::GPIO);
+        let ev = |t, code, value| evdev::InputEvent::new(EventType(t), code, value);
+
+        // A key code that isn't a pen key
+        assert_eq!(
+            wacom::decode_with(&ev(ecodes::EV_KEY, 0x142, 1), &wacom, geometry),
+            Some(InputEvent::Unknown {})
+        );
+        // Events for another device's state
+        assert_eq!(
+            wacom::decode_with(&ev(ecodes::EV_SYN, 0, 0), &gpio, geometry),
+            Some(InputEvent::Unknown {})
+        );
+        // Slots out of range
+        assert_eq!(
+            multitouch::decode_with(
+                &ev(ecodes::EV_ABS, ecodes::ABS_MT_SLOT, -5),
+                &multitouch,
+                geometry
+            ),
+            vec![InputEvent::Unknown {}]
+        );
+        assert_eq!(
+            gpio::decode(&ev(ecodes::EV_KEY, ecodes::BTN_TOUCH, 1), &gpio),
+            Some(InputEvent::Unknown {})
+        );
+        assert_eq!(
+            gpio::decode(&ev(0x17, 0, 0), &gpio),
+            Some(InputEvent::Unknown {})
+        );
+    }
+
+    #[test]
+    fn test_normalized_values() {
+        let geometry = &geometries()[0];
+        let multitouch = InputDeviceState::new(InputDevice::Multitouch);
+        let ev = |t, code, value| evdev::InputEvent::new(EventType(t), code, value);
+        let maxima = AxisMaxima::current();
+
+        let mut events = Vec::new();
+        for (code, value) in [
+            (ecodes::ABS_MT_TRACKING_ID, 7),
+            (ecodes::ABS_MT_POSITION_X, 100),
+            (ecodes::ABS_MT_POSITION_Y, 100),
+            (
+                ecodes::ABS_MT_PRESSURE,
+                i32::from(maxima.multitouch_pressure / 2),
+            ),
+            (
+                ecodes::ABS_MT_TOUCH_MAJOR,
+                i32::from(maxima.multitouch_size) * 2,
+            ),
+        ] {
+            events.extend(multitouch::decode_with(
+                &ev(ecodes::EV_ABS, code, value),
+                &multitouch,
+                geometry,
+            ));
+        }
+        events.extend(multitouch::decode_with(
+            &ev(ecodes::EV_SYN, ecodes::SYN_REPORT, 0),
+            &multitouch,
+            geometry,
+        ));
+        let finger = match events.last() {
+            Some(InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Press { finger },
+            }) => *finger,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(finger.pressure, maxima.multitouch_pressure / 2);
+        assert!((finger.normalized_pressure() - 0.5).abs() < 0.01);
+        // Values beyond the maximum are capped
+        assert_eq!(finger.normalized_size(), 1.0);
+
+        let draw = WacomEvent::Draw {
+            position: (0.0, 0.0).into(),
+            pressure: maxima.wacom_pressure,
+            tilt: (0, 0).into(),
+            tool: WacomTool::Pen,
+            buttons: StylusButtons::default(),
+        };
+        assert_eq!(draw.normalized_pressure(), Some(1.0));
+        assert_eq!(WacomEvent::Unknown.normalized_pressure(), None);
+    }
+
+    #[test]
+    fn test_eraser_and_buttons() {
+        let geometry = &geometries()[0];
+        let wacom = InputDeviceState::new(InputDevice::Wacom);
+        let ev = |t, code, value| evdev::InputEvent::new(EventType(t), code, value);
+
+        for (code, value) in [
+            (ecodes::BTN_TOOL_RUBBER, 1),
+            (ecodes::BTN_STYLUS, 1),
+            (ecodes::BTN_TOUCH, 1),
+        ] {
+            wacom::decode_with(&ev(ecodes::EV_KEY, code, value), &wacom, geometry);
+        }
+        match wacom::decode_with(&ev(ecodes::EV_SYN, 0, 0), &wacom, geometry) {
+            Some(InputEvent::WacomEvent {
+                event: WacomEvent::Draw { tool, buttons, .. },
+            }) => {
+                assert_eq!(tool, WacomTool::Eraser);
+                assert!(buttons.primary && !buttons.secondary);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        for (code, value) in [
+            (ecodes::BTN_TOUCH, 0),
+            (ecodes::BTN_STYLUS, 0),
+            (ecodes::BTN_TOOL_RUBBER, 0),
+        ] {
+            wacom::decode_with(&ev(ecodes::EV_KEY, code, value), &wacom, geometry);
+        }
+        assert!(matches!(
+            wacom::decode_with(&ev(ecodes::EV_SYN, 0, 0), &wacom, geometry),
+            Some(InputEvent::WacomEvent {
+                event: WacomEvent::Hover {
+                    tool: WacomTool::Pen,
+                    buttons: StylusButtons {
+                        primary: false,
+                        secondary: false
+                    },
+                    ..
+                },
+            })
+        ));
+    }
+
+    #[test]
+    fn test_pen_orientation() {
+        use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        let upright = PenOrientation::from_tilt((0.0, 0.0).into());
+        assert!(close(upright.altitude, FRAC_PI_2), "{:?}", upright);
+
+        let right = PenOrientation::from_tilt((0.5, 0.0).into());
+        assert!(close(right.azimuth, 0.0) && close(right.altitude, FRAC_PI_4));
+        let up = PenOrientation::from_tilt((0.0, -0.5).into());
+        assert!(close(up.azimuth, 3.0 * FRAC_PI_2) && close(up.altitude, FRAC_PI_4));
+        let flat = PenOrientation::from_tilt((-1.0, 0.0).into());
+        assert!(
+            close(flat.azimuth, PI) && close(flat.altitude, 0.0),
+            "{:?}",
+            flat
+        );
+        let diagonal = PenOrientation::from_tilt((0.5, 0.5).into());
+        assert!(close(diagonal.azimuth, FRAC_PI_4));
+        assert!(diagonal.altitude < FRAC_PI_4);
+
+        // The digitizer is rotated, so its x axis is the display's y axis upwards
+        let max = AxisMaxima::current().wacom_tilt;
+        let hover = WacomEvent::Hover {
+            position: (0.0, 0.0).into(),
+            distance: 0,
+            tilt: (max, (-(max as i16)) as u16).into(),
+            tool: WacomTool::Pen,
+            buttons: StylusButtons::default(),
+        };
+        assert_eq!(hover.normalized_tilt(), Some((-1.0, -1.0).into()));
+        assert!(hover.orientation().unwrap().altitude.abs() < 1e-5);
+        assert_eq!(WacomEvent::Unknown.orientation(), None);
+    }
+}
@@ -1,21 +1,30 @@
 use super::ecodes;
 use crate::device::rotate::CoordinatePart;
 use crate::device::CURRENT_DEVICE;
-use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH, MTHEIGHT, MTWIDTH};
 use crate::input::scan::SCANNED;
-use crate::input::{Finger, InputDeviceState, InputEvent, MultitouchEvent};
+use crate::input::{
+    Finger, Geometry, InputDeviceState, InputEvent, MultitouchEvent, WacomEvent, WacomPen,
+};
 use once_cell::sync::Lazy;
 
 use evdev::InputEvent as EvInputEvent;
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use log::{debug, warn};
 use std::sync::{
     atomic::{AtomicI32, Ordering},
     Mutex,
 };
+use std::time::{Duration, Instant};
+
+pub(crate) static GEOMETRY: Lazy<Geometry> = Lazy::new(|| {
+    Geometry::new(
+        SCANNED.multitouch_orig_size,
+        CURRENT_DEVICE.get_multitouch_placement(),
+    )
+});
 
-static MT_HSCALAR: Lazy<f32> = Lazy::new(|| f32::from(DISPLAYWIDTH) / f32::from(*MTWIDTH));
-static MT_VSCALAR: Lazy<f32> = Lazy::new(|| f32::from(DISPLAYHEIGHT) / f32::from(*MTHEIGHT));
+/// Slots beyond this are ignored. The touchscreens report at most a handful.
+const MAX_SLOTS: i32 = 32;
 
 pub struct MultitouchState {
     fingers: Mutex<FxHashMap<i32 /* slot */, Finger>>,
@@ -31,10 +40,97 @@ impl ::std::default::Default for MultitouchState {
     }
 }
 
+/// Ignores the palm resting on the display while writing, see
+/// `ApplicationContext::set_palm_rejection`.
+///
+/// Touches starting while the pen is near the display, or was until `cooldown` ago,
+/// are dropped until they lift. Touches from before only lose their moves meanwhile,
+/// so that every press let through is still followed by its release.
+#[derive(Clone, Debug)]
+pub struct PalmRejection {
+    /// How long after the pen left the display touches are still rejected
+    pub cooldown: Duration,
+    pen_near: bool,
+    pen_left: Option<Instant>,
+    /// The fingers whose press was let through
+    accepted: FxHashSet<i32>,
+}
+
+impl Default for PalmRejection {
+    fn default() -> PalmRejection {
+        PalmRejection {
+            cooldown: Duration::from_millis(500),
+            pen_near: false,
+            pen_left: None,
+            accepted: FxHashSet::default(),
+        }
+    }
+}
+
+impl PalmRejection {
+    pub fn new(cooldown: Duration) -> PalmRejection {
+        PalmRejection {
+            cooldown,
+            ..PalmRejection::default()
+        }
+    }
+
+    /// Follows where the pen is by `event`, which happened at `now`
+    pub fn pen(&mut self, event: &WacomEvent, now: Instant) {
+        match *event {
+            WacomEvent::Hover { .. } | WacomEvent::Draw { .. } => self.pen_near = true,
+            WacomEvent::InstrumentChange {
+                pen: WacomPen::ToolPen | WacomPen::ToolRubber,
+                state,
+            } => {
+                if self.pen_near && !state {
+                    self.pen_left = Some(now);
+                }
+                self.pen_near = state;
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether touches are rejected at `now`
+    pub fn is_rejecting(&self, now: Instant) -> bool {
+        self.pen_near
+            || self
+                .pen_left
+                .is_some_and(|left| now.saturating_duration_since(left) < self.cooldown)
+    }
+
+    /// Whether `event`, which happened at `now`, is let through
+    pub fn allows(&mut self, event: &MultitouchEvent, now: Instant) -> bool {
+        match event {
+            MultitouchEvent::Press { finger } => {
+                let allowed = !self.is_rejecting(now);
+                if allowed {
+                    self.accepted.insert(finger.tracking_id);
+                }
+                allowed
+            }
+            MultitouchEvent::Move { finger } => {
+                self.accepted.contains(&finger.tracking_id) && !self.is_rejecting(now)
+            }
+            MultitouchEvent::Release { finger } => self.accepted.remove(&finger.tracking_id),
+            MultitouchEvent::Unknown => true,
+        }
+    }
+}
+
 pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEvent> {
+    decode_with(ev, outer_state, &GEOMETRY)
+}
+
+pub(crate) fn decode_with(
+    ev: &EvInputEvent,
+    outer_state: &InputDeviceState,
+    geometry: &Geometry,
+) -> Vec<InputEvent> {
     let state = match outer_state {
         InputDeviceState::MultitouchState(ref state_arc) => state_arc,
-        _ => unreachable!(),
+        _ => return vec![InputEvent::Unknown {}],
     };
     let mut fingers = state.fingers.lock().unwrap();
     let current_slot = state.current_slot.load(Ordering::Relaxed);
@@ -75,7 +171,7 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
                         ev.code(),
                         ev.value()
                     );
-                    vec![]
+                    vec![InputEvent::Unknown {}]
                 }
             }
         }
@@ -83,74 +179,46 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
             // Absolute
             match ev.code() {
                 ecodes::ABS_MT_SLOT => {
+                    if !(0..MAX_SLOTS).contains(&ev.value()) {
+                        warn!("Ignoring multitouch slot {}", ev.value());
+                        return vec![InputEvent::Unknown {}];
+                    }
                     state.current_slot.store(ev.value(), Ordering::Relaxed);
                     // Since only one event is processed, it isn't
                     // necessary to change the local current_slot variable.
                     vec![]
                 }
-                ecodes::ABS_MT_POSITION_X => {
-                    let placement = CURRENT_DEVICE.get_multitouch_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::X(ev.value() as u16),
-                        &SCANNED.multitouch_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *MTWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *MTHEIGHT - *y_value;
-                        }
-                    }
+                ecodes::ABS_MT_POSITION_X | ecodes::ABS_MT_POSITION_Y => {
+                    let rotated_part = match ev.code() {
+                        ecodes::ABS_MT_POSITION_X => geometry.map_x(ev.value()),
+                        _ => geometry.map_y(ev.value()),
+                    };
                     let finger: &mut Finger = fingers.entry(current_slot).or_default();
                     match rotated_part {
                         CoordinatePart::X(rotated_value) => {
-                            finger.pos.x = (f32::from(rotated_value) * *MT_HSCALAR) as u16;
+                            finger.pos.x = (f32::from(rotated_value) * geometry.scale.x) as u16;
                         }
                         CoordinatePart::Y(rotated_value) => {
-                            finger.pos.y = (f32::from(rotated_value) * *MT_VSCALAR) as u16;
-                        }
-                    }
-                    finger.pos_updated = true;
-                    vec![]
-                }
-                ecodes::ABS_MT_POSITION_Y => {
-                    let placement = CURRENT_DEVICE.get_multitouch_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::Y(ev.value() as u16),
-                        &SCANNED.multitouch_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *MTWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *MTHEIGHT - *y_value;
-                        }
-                    }
-                    let finger: &mut Finger = fingers.entry(current_slot).or_default();
-                    match rotated_part {
-                        CoordinatePart::X(rotated_value) => {
-                            finger.pos.x = (f32::from(rotated_value) * *MT_HSCALAR) as u16;
-                        }
-                        CoordinatePart::Y(rotated_value) => {
-                            finger.pos.y = (f32::from(rotated_value) * *MT_VSCALAR) as u16;
+                            finger.pos.y = (f32::from(rotated_value) * geometry.scale.y) as u16;
                         }
                     }
                     finger.pos_updated = true;
                     vec![]
                 }
                 ecodes::ABS_MT_PRESSURE => {
+                    let finger = fingers.entry(current_slot).or_default();
+                    finger.pressure = ev.value().clamp(0, i32::from(u16::MAX)) as u16;
                     if ev.value() > 0 {
                         // Pretty much always true, but who knows
-                        fingers.entry(current_slot).or_default().pressed = true;
+                        finger.pressed = true;
                     }
                     vec![]
                 }
+                ecodes::ABS_MT_TOUCH_MAJOR => {
+                    fingers.entry(current_slot).or_default().size =
+                        ev.value().clamp(0, i32::from(u16::MAX)) as u16;
+                    vec![]
+                }
                 ecodes::ABS_MT_TRACKING_ID => match ev.value() {
                     -1 => {
                         fingers.entry(current_slot).or_default().pressed = false;
@@ -161,9 +229,7 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
                         vec![]
                     }
                 },
-                ecodes::ABS_MT_ORIENTATION
-                | ecodes::ABS_MT_TOUCH_MAJOR
-                | ecodes::ABS_MT_TOUCH_MINOR => vec![], // Currently not needed
+                ecodes::ABS_MT_ORIENTATION | ecodes::ABS_MT_TOUCH_MINOR => vec![], // Currently not needed
                 // very unlikely
                 // Technically possible (but maybe not for the reMarkable):
                 // ABS_MT_DISTANCE, ABS_MT_TOOL_X, ABS_MT_TOOL_Y, ABS_MT_WIDTH_MAJOR,
@@ -175,7 +241,7 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
                         ev.code(),
                         ev.value()
                     );
-                    vec![]
+                    vec![InputEvent::Unknown {}]
                 }
             }
         }
@@ -186,7 +252,51 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
                 ev.code(),
                 ev.value()
             );
-            vec![]
+            vec![InputEvent::Unknown {}]
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{moved, press, release, touch};
+    use cgmath::{Point2, Vector2};
+
+    #[test]
+    fn test_palm_rejection() {
+        let mut palm = PalmRejection::new(Duration::from_millis(200));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let hover = WacomEvent::Hover {
+            position: Point2 { x: 10.0, y: 10.0 },
+            distance: 20,
+            tilt: Vector2 { x: 0, y: 0 },
+            tool: Default::default(),
+            buttons: Default::default(),
+        };
+        let pen_out = WacomEvent::InstrumentChange {
+            pen: WacomPen::ToolPen,
+            state: false,
+        };
+
+        // A finger from before the pen came keeps its release
+        assert!(palm.allows(&touch(press, 1, 100, 100), at(0)));
+        palm.pen(&hover, at(10));
+        assert!(palm.is_rejecting(at(10)));
+        assert!(!palm.allows(&touch(moved, 1, 100, 100), at(20)));
+        // The palm
+        assert!(!palm.allows(&touch(press, 2, 100, 100), at(30)));
+        assert!(palm.allows(&touch(release, 1, 100, 100), at(40)));
+
+        palm.pen(&pen_out, at(100));
+        assert!(!palm.allows(&touch(moved, 2, 100, 100), at(200)));
+        assert!(!palm.allows(&touch(press, 3, 100, 100), at(250)));
+        assert!(!palm.is_rejecting(at(300)));
+        // Still the palm until it lifts
+        assert!(!palm.allows(&touch(moved, 2, 100, 100), at(300)));
+        assert!(!palm.allows(&touch(release, 2, 100, 100), at(310)));
+        assert!(palm.allows(&touch(press, 4, 100, 100), at(320)));
+        assert!(palm.allows(&touch(moved, 4, 100, 100), at(330)));
+    }
+}
@@ -1,5 +1,6 @@
 use super::ecodes;
 use super::InputDevice;
+use super::InputError;
 use cgmath::Vector2;
 use log::debug;
 use once_cell::sync::Lazy;
@@ -9,8 +10,17 @@ use std::time::Duration;
 
 pub const INITIAL_DEVS_AVAILABLE_FOR: Duration = Duration::from_millis(150);
 
-/// A singleton of the EvDevsScan object
-pub static SCANNED: Lazy<EvDevs> = Lazy::new(EvDevs::new);
+static SCAN: Lazy<Result<EvDevs, InputError>> = Lazy::new(EvDevs::scan);
+
+/// A singleton of the EvDevsScan object. Panics on first use if the scan failed,
+/// see `scanned` to handle that instead.
+pub static SCANNED: Lazy<&'static EvDevs> =
+    Lazy::new(|| scanned().unwrap_or_else(|err| panic!("{}", err)));
+
+/// The result of scanning the evdev devices, which happens once
+pub fn scanned() -> Result<&'static EvDevs, &'static InputError> {
+    SCAN.as_ref()
+}
 
 /// This struct contains the results of initially scaning all evdev devices,
 /// which allows for device model independancy.
@@ -33,6 +43,13 @@ pub struct EvDevs {
     pub wacom_orig_size: Vector2<u16>,
     pub multitouch_orig_size: Vector2<u16>,
 
+    /// The highest raw values reported, see `AxisMaxima`
+    pub wacom_max_pressure: u16,
+    /// Of the tilt along the X axis, the Y axis is the same
+    pub wacom_max_tilt: u16,
+    pub mt_max_pressure: u16,
+    pub mt_max_touch_major: u16,
+
     // Those will be preserved in case they are needed fairly fast
     // to prevent any additional delay of re-opening the fds.
     // They will get removed fairly quickly though.
@@ -44,7 +61,7 @@ pub struct EvDevs {
 impl EvDevs {
     /// Scan all the evdev devices, figure out which is which
     /// and get some additional data for lazy constants.
-    fn new() -> Self {
+    pub fn scan() -> Result<Self, InputError> {
         // All of these have to be found
         let mut wacom = None;
         let mut multitouch = None;
@@ -53,15 +70,16 @@ impl EvDevs {
         // Get all /dev/input/event* file paths
         let mut event_file_paths: Vec<PathBuf> = Vec::new();
         let input_dir = Path::new("/dev/input");
-        for entry in input_dir
-            .read_dir()
-            .unwrap_or_else(|_| panic!("Failed to list {:?}", input_dir))
-        {
-            let entry = entry.unwrap();
-            let file_name = entry.file_name().as_os_str().to_str().unwrap().to_owned();
-            if !file_name.starts_with("event") {
-                continue;
-            }
+        let scan_error = |source| InputError::Scan {
+            path: input_dir.to_path_buf(),
+            source,
+        };
+        for entry in input_dir.read_dir().map_err(scan_error)? {
+            let entry = entry.map_err(scan_error)?;
+            let file_name = match entry.file_name().to_str() {
+                Some(file_name) if file_name.starts_with("event") => file_name.to_owned(),
+                _ => continue,
+            };
 
             let evdev_path = input_dir.join(&file_name);
             event_file_paths.push(evdev_path);
@@ -69,8 +87,10 @@ impl EvDevs {
 
         // Open and check capabilities of each event device
         for evdev_path in event_file_paths {
-            let dev = evdev::Device::open(&evdev_path)
-                .unwrap_or_else(|_| panic!("Failed to scan {:?}", &evdev_path));
+            let dev = evdev::Device::open(&evdev_path).map_err(|source| InputError::Scan {
+                path: evdev_path.clone(),
+                source,
+            })?;
             if dev.supported_events().contains(evdev::EventType::KEY) {
                 if dev
                     .supported_keys()
@@ -108,17 +128,24 @@ impl EvDevs {
         }
 
         // Ensure that all devices were found
-        let (wacom_path, wacom_dev) = wacom.expect("Failed to find the wacom digitizer evdev!");
+        let (wacom_path, wacom_dev) = wacom.ok_or(InputError::NotFound(InputDevice::Wacom))?;
         let (multitouch_path, multitouch_dev) =
-            multitouch.expect("Failed to find the multitouch evdev!");
-        let (gpio_path, gpio_dev) = gpio.expect("Failed to find the gpio evdev!");
+            multitouch.ok_or(InputError::NotFound(InputDevice::Multitouch))?;
+        let (gpio_path, gpio_dev) = gpio.ok_or(InputError::NotFound(InputDevice::GPIO))?;
 
         // SIZES
-        let wacom_state = wacom_dev.get_abs_state().unwrap();
+        let wacom_state = wacom_dev
+            .get_abs_state()
+            .map_err(|source| InputError::Scan {
+                path: wacom_path.clone(),
+                source,
+            })?;
         let wacom_orig_size = Vector2 {
             x: wacom_state[ecodes::ABS_X as usize].maximum as u16,
             y: wacom_state[ecodes::ABS_Y as usize].maximum as u16,
         };
+        let wacom_max_pressure = wacom_state[ecodes::ABS_PRESSURE as usize].maximum as u16;
+        let wacom_max_tilt = wacom_state[ecodes::ABS_TILT_X as usize].maximum as u16;
         // X and Y are swapped for the wacom since rM1 and probably also rM2 have it rotated
         let (wacom_width, wacom_height) = crate::device::CURRENT_DEVICE
             .get_wacom_placement()
@@ -126,11 +153,18 @@ impl EvDevs {
             .rotated_size(&wacom_orig_size)
             .into();
 
-        let mt_state = multitouch_dev.get_abs_state().unwrap();
+        let mt_state = multitouch_dev
+            .get_abs_state()
+            .map_err(|source| InputError::Scan {
+                path: multitouch_path.clone(),
+                source,
+            })?;
         let multitouch_orig_size = Vector2 {
             x: mt_state[ecodes::ABS_MT_POSITION_X as usize].maximum as u16,
             y: mt_state[ecodes::ABS_MT_POSITION_Y as usize].maximum as u16,
         };
+        let mt_max_pressure = mt_state[ecodes::ABS_MT_PRESSURE as usize].maximum as u16;
+        let mt_max_touch_major = mt_state[ecodes::ABS_MT_TOUCH_MAJOR as usize].maximum as u16;
         // Axes are swapped on the rM2 (see InputDeviceRotation for more)
         let (mt_width, mt_height) = crate::device::CURRENT_DEVICE
             .get_multitouch_placement()
@@ -156,7 +190,7 @@ impl EvDevs {
             debug!("Closed initially opened evdev fds (if not used by now).");
         });
 
-        Self {
+        Ok(Self {
             wacom_path,
             multitouch_path,
             gpio_path,
@@ -170,10 +204,15 @@ impl EvDevs {
             multitouch_orig_size,
             wacom_orig_size,
 
+            wacom_max_pressure,
+            wacom_max_tilt,
+            mt_max_pressure,
+            mt_max_touch_major,
+
             wacom_initial_dev,
             multitouch_initial_dev,
             gpio_initial_dev,
-        }
+        })
     }
 
     /// Get the path to a InputDevice
@@ -188,19 +227,24 @@ impl EvDevs {
 
     /// Get a ev device. If this is called early, it can get the device used for the initial scan.
     /// If an early device is returned, it might contain a few events from the past!
-    pub fn get_device(&self, device: InputDevice) -> Result<evdev::Device, impl std::error::Error> {
+    pub fn get_device(&self, device: InputDevice) -> Result<evdev::Device, InputError> {
         let dev_arc = match device {
             InputDevice::Wacom => self.wacom_initial_dev.clone(),
             InputDevice::Multitouch => self.multitouch_initial_dev.clone(),
             InputDevice::GPIO => self.gpio_initial_dev.clone(),
-            InputDevice::Unknown => panic!("\"InputDevice::Unkown\" is no device!"),
+            InputDevice::Unknown => return Err(InputError::Unknown(device)),
         };
 
         let mut resuable_device = dev_arc.lock().unwrap();
-        if resuable_device.is_some() {
-            Ok(resuable_device.take().unwrap())
-        } else {
-            evdev::Device::open(self.get_path(device))
+        match resuable_device.take() {
+            Some(dev) => Ok(dev),
+            None => {
+                let path = self.get_path(device);
+                evdev::Device::open(path).map_err(|source| InputError::Open {
+                    path: path.clone(),
+                    source,
+                })
+            }
         }
     }
 }
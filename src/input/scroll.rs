@@ -0,0 +1,337 @@
+//! Two finger scrolling.
+//!
+//! A `ScrollRecognizer` follows `MultitouchEvent`s and turns two fingers dragged
+//! together into `ScrollEvent`s, with the velocity of the drag. After the fingers
+//! lift, the scroll continues with decaying momentum, paced by calling `momentum`
+//! whenever `next_momentum` says so.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use cgmath::{InnerSpace, Point2, Vector2};
+
+use crate::input::{kinetic, MultitouchEvent, ScrollEvent};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Axis {
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+impl Axis {
+    fn apply(self, v: Vector2<f32>) -> Vector2<f32> {
+        match self {
+            Axis::Horizontal => Vector2 { x: v.x, y: 0.0 },
+            Axis::Vertical => Vector2 { x: 0.0, y: v.y },
+            Axis::Both => v,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum State {
+    Idle,
+    /// Two fingers are down, but haven't moved far enough together
+    Pending {
+        start: Point2<f32>,
+    },
+    Scrolling {
+        axis: Axis,
+        last: Point2<f32>,
+        /// Recent centroids, for the velocity
+        samples: VecDeque<(Instant, Point2<f32>)>,
+    },
+    Momentum {
+        velocity: Vector2<f32>,
+        last: Instant,
+    },
+}
+
+/// Turns two finger drags into scroll events
+#[derive(Clone, Debug)]
+pub struct ScrollRecognizer {
+    /// How far in pixels the fingers have to move together before scrolling starts
+    pub slop: f32,
+    /// Scroll only horizontally or vertically if the drag starts mostly that way
+    pub lock_axis: bool,
+    /// How quickly momentum fades, as the fraction of the velocity lost per second
+    /// on a log scale. 0.0 keeps scrolling forever.
+    pub friction: f32,
+    /// Momentum ends below this speed, in pixels per second
+    pub min_velocity: f32,
+    /// Between momentum scroll events
+    pub frame: Duration,
+    fingers: BTreeMap<i32, Point2<f32>>,
+    state: State,
+}
+
+impl Default for ScrollRecognizer {
+    fn default() -> ScrollRecognizer {
+        ScrollRecognizer {
+            slop: 20.0,
+            lock_axis: true,
+            friction: kinetic::FRICTION,
+            min_velocity: kinetic::MIN_VELOCITY,
+            // The display won't keep up with more anyway
+            frame: Duration::from_millis(50),
+            fingers: BTreeMap::new(),
+            state: State::Idle,
+        }
+    }
+}
+
+impl ScrollRecognizer {
+    pub fn new() -> ScrollRecognizer {
+        ScrollRecognizer::default()
+    }
+
+    /// Whether a scroll is going on, including its momentum
+    pub fn is_scrolling(&self) -> bool {
+        matches!(self.state, State::Scrolling { .. } | State::Momentum { .. })
+    }
+
+    fn centroid(&self) -> Option<Point2<f32>> {
+        match self.fingers.len() {
+            2 => {
+                let mut fingers = self.fingers.values();
+                let (a, b) = (fingers.next()?, fingers.next()?);
+                Some(Point2 {
+                    x: (a.x + b.x) / 2.0,
+                    y: (a.y + b.y) / 2.0,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Follows `event`, which happened at `now`. Touching the display stops momentum.
+    pub fn handle(&mut self, event: &MultitouchEvent, now: Instant) -> Vec<ScrollEvent> {
+        let finger = match event.finger() {
+            Some(finger) => finger,
+            None => return Vec::new(),
+        };
+        let pos = Point2 {
+            x: f32::from(finger.pos.x),
+            y: f32::from(finger.pos.y),
+        };
+        let mut events = Vec::new();
+        match event {
+            MultitouchEvent::Release { .. } => {
+                self.fingers.remove(&finger.tracking_id);
+            }
+            _ => {
+                if let (MultitouchEvent::Press { .. }, State::Momentum { .. }) =
+                    (event, &self.state)
+                {
+                    self.state = State::Idle;
+                    events.push(ScrollEvent::End);
+                }
+                self.fingers.insert(finger.tracking_id, pos);
+            }
+        }
+
+        let centroid = match self.centroid() {
+            Some(centroid) => centroid,
+            None => {
+                // Lifting a finger, or adding a third, ends the scroll
+                if let State::Scrolling { samples, .. } = &self.state {
+                    let velocity = kinetic::velocity(samples);
+                    events.extend(self.release(velocity, now));
+                } else if let State::Pending { .. } = self.state {
+                    self.state = State::Idle;
+                }
+                return events;
+            }
+        };
+
+        match &mut self.state {
+            State::Idle | State::Momentum { .. } => {
+                self.state = State::Pending { start: centroid };
+            }
+            State::Pending { start } => {
+                let moved = centroid - *start;
+                if moved.magnitude() >= self.slop {
+                    let axis = match self.lock_axis {
+                        true if moved.x.abs() > 2.0 * moved.y.abs() => Axis::Horizontal,
+                        true if moved.y.abs() > 2.0 * moved.x.abs() => Axis::Vertical,
+                        _ => Axis::Both,
+                    };
+                    let start = *start;
+                    self.state = State::Scrolling {
+                        axis,
+                        last: centroid,
+                        samples: VecDeque::from([(now, centroid)]),
+                    };
+                    events.push(ScrollEvent::Begin);
+                    events.push(ScrollEvent::Scroll {
+                        delta: axis.apply(centroid - start),
+                        velocity: Vector2 { x: 0.0, y: 0.0 },
+                        momentum: false,
+                    });
+                }
+            }
+            State::Scrolling {
+                axis,
+                last,
+                samples,
+            } => {
+                let delta = axis.apply(centroid - *last);
+                *last = centroid;
+                kinetic::sample(samples, now, centroid);
+                if delta.x != 0.0 || delta.y != 0.0 {
+                    let axis = *axis;
+                    let velocity = axis.apply(kinetic::velocity(samples));
+                    events.push(ScrollEvent::Scroll {
+                        delta,
+                        velocity,
+                        momentum: false,
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    fn release(&mut self, velocity: Vector2<f32>, now: Instant) -> Option<ScrollEvent> {
+        let axis = match self.state {
+            State::Scrolling { axis, .. } => axis,
+            _ => Axis::Both,
+        };
+        let velocity = axis.apply(velocity);
+        if velocity.magnitude() >= self.min_velocity {
+            self.state = State::Momentum {
+                velocity,
+                last: now,
+            };
+            None
+        } else {
+            self.state = State::Idle;
+            Some(ScrollEvent::End)
+        }
+    }
+
+    /// When `momentum` has to be called next, if the scroll is coasting
+    pub fn next_momentum(&self) -> Option<Instant> {
+        match self.state {
+            State::Momentum { last, .. } => Some(last + self.frame),
+            _ => None,
+        }
+    }
+
+    /// The momentum scrolling up to `now`. Ends with `ScrollEvent::End` once it has
+    /// slowed down below `min_velocity`.
+    pub fn momentum(&mut self, now: Instant) -> Option<ScrollEvent> {
+        let (velocity, last) = match &mut self.state {
+            State::Momentum { velocity, last } => (velocity, last),
+            _ => return None,
+        };
+        let dt = now.saturating_duration_since(*last).as_secs_f32();
+        *last = now;
+        let (delta, slower) = kinetic::coast(*velocity, self.friction, dt);
+        *velocity = slower;
+        if velocity.magnitude() < self.min_velocity {
+            self.state = State::Idle;
+            return Some(ScrollEvent::End);
+        }
+        Some(ScrollEvent::Scroll {
+            delta,
+            velocity: *velocity,
+            momentum: true,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{moved, press, release, touch};
+
+    #[test]
+    fn test_two_finger_scroll() {
+        let mut scroll = ScrollRecognizer::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // A single finger doesn't scroll
+        assert!(scroll.handle(&touch(press, 1, 500, 1000), at(0)).is_empty());
+        assert!(scroll.handle(&touch(moved, 1, 500, 900), at(10)).is_empty());
+        assert!(scroll.handle(&touch(press, 2, 700, 900), at(20)).is_empty());
+
+        // Both fingers moving up, slightly sideways, scroll vertically
+        let mut events = Vec::new();
+        for step in 1..=5u16 {
+            let ms = 20 + u64::from(step) * 10;
+            events.extend(scroll.handle(&touch(moved, 1, 500 + step, 900 - step * 20), at(ms)));
+            events.extend(scroll.handle(&touch(moved, 2, 700 + step, 900 - step * 20), at(ms)));
+        }
+        assert_eq!(events[0], ScrollEvent::Begin);
+        let mut total = Vector2 { x: 0.0, y: 0.0 };
+        for event in &events[1..] {
+            match event {
+                ScrollEvent::Scroll {
+                    delta,
+                    momentum: false,
+                    ..
+                } => total += *delta,
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(total, Vector2 { x: 0.0, y: -100.0 });
+        let velocity = match events.last() {
+            Some(ScrollEvent::Scroll { velocity, .. }) => *velocity,
+            other => panic!("{:?}", other),
+        };
+        assert!(velocity.y < -1000.0 && velocity.x == 0.0, "{:?}", velocity);
+
+        // Lifting a finger keeps the scroll going with momentum until it fades
+        assert!(scroll
+            .handle(&touch(release, 1, 505, 800), at(80))
+            .is_empty());
+        assert!(scroll.is_scrolling());
+        let mut last_speed = f32::MAX;
+        let end = loop {
+            let now = scroll.next_momentum().expect("momentum");
+            match scroll.momentum(now) {
+                Some(ScrollEvent::Scroll {
+                    delta,
+                    velocity,
+                    momentum: true,
+                }) => {
+                    assert!(delta.y < 0.0 && delta.x == 0.0);
+                    assert!(velocity.magnitude() < last_speed);
+                    last_speed = velocity.magnitude();
+                }
+                Some(ScrollEvent::End) => break now,
+                other => panic!("{:?}", other),
+            }
+        };
+        assert!(end < at(3000));
+        assert!(!scroll.is_scrolling());
+        assert_eq!(scroll.next_momentum(), None);
+    }
+
+    #[test]
+    fn test_touch_stops_momentum() {
+        let mut scroll = ScrollRecognizer::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        scroll.handle(&touch(press, 1, 100, 500), at(0));
+        scroll.handle(&touch(press, 2, 200, 500), at(0));
+        for step in 1..=4u16 {
+            let ms = u64::from(step) * 10;
+            scroll.handle(&touch(moved, 1, 100 + step * 30, 500), at(ms));
+            scroll.handle(&touch(moved, 2, 200 + step * 30, 500), at(ms));
+        }
+        scroll.handle(&touch(release, 1, 220, 500), at(50));
+        scroll.handle(&touch(release, 2, 320, 500), at(50));
+        assert!(scroll.next_momentum().is_some());
+
+        assert_eq!(
+            scroll.handle(&touch(press, 3, 300, 300), at(60)),
+            [ScrollEvent::End]
+        );
+        assert_eq!(scroll.next_momentum(), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! Steadier strokes.
+//!
+//! A `StrokeFilter` follows `WacomEvent`s and smooths the positions of the pen while
+//! it draws with a one euro filter: slow movements, where wobble shows, are smoothed
+//! a lot, while fast ones are followed closely so that the stroke doesn't lag
+//! behind. It also extrapolates where the pen will be shortly, so that drawing up to
+//! there hides some of the latency of the display.
+
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use cgmath::{InnerSpace, Point2, Vector2};
+
+use crate::input::{SmoothedPoint, WacomEvent, WacomPen};
+
+/// Assumed between events arriving at the same instant
+const MIN_INTERVAL: f32 = 0.001;
+
+/// How much of a new sample goes into a low pass filter with `cutoff` Hz
+fn alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+#[derive(Clone, Debug)]
+struct State {
+    position: Point2<f32>,
+    velocity: Vector2<f32>,
+    last: Instant,
+}
+
+/// Smooths the pen's strokes, see the module documentation
+#[derive(Clone, Debug)]
+pub struct StrokeFilter {
+    /// The cutoff frequency in Hz while the pen rests. Lower smooths more, but lags.
+    pub min_cutoff: f32,
+    /// How quickly the cutoff frequency rises with the speed of the pen, in Hz per
+    /// pixel per second. Higher lags less on fast strokes.
+    pub beta: f32,
+    /// The cutoff frequency in Hz for smoothing the speed of the pen
+    pub derivative_cutoff: f32,
+    /// How far ahead `SmoothedPoint::predicted` is
+    pub prediction: Duration,
+    state: Option<State>,
+}
+
+impl Default for StrokeFilter {
+    fn default() -> StrokeFilter {
+        StrokeFilter {
+            min_cutoff: 2.0,
+            beta: 0.01,
+            derivative_cutoff: 1.0,
+            prediction: Duration::from_millis(20),
+            state: None,
+        }
+    }
+}
+
+impl StrokeFilter {
+    pub fn new() -> StrokeFilter {
+        StrokeFilter::default()
+    }
+
+    /// Forgets the stroke, so that the next one starts where the pen is
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+
+    /// Follows `event`, which happened at `now`. Returns the smoothed point of a
+    /// `WacomEvent::Draw`. Lifting the pen ends the stroke.
+    pub fn filter(&mut self, event: &WacomEvent, now: Instant) -> Option<SmoothedPoint> {
+        let (raw, pressure) = match *event {
+            WacomEvent::Draw {
+                position, pressure, ..
+            } => (position, pressure),
+            WacomEvent::Hover { .. }
+            | WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: false,
+            } => {
+                self.reset();
+                return None;
+            }
+            _ => return None,
+        };
+        let state = match self.state.as_mut() {
+            Some(state) => state,
+            None => {
+                self.state = Some(State {
+                    position: raw,
+                    velocity: Vector2 { x: 0.0, y: 0.0 },
+                    last: now,
+                });
+                return Some(SmoothedPoint {
+                    raw,
+                    position: raw,
+                    predicted: raw,
+                    velocity: Vector2 { x: 0.0, y: 0.0 },
+                    pressure,
+                });
+            }
+        };
+
+        let dt = now
+            .saturating_duration_since(state.last)
+            .as_secs_f32()
+            .max(MIN_INTERVAL);
+        state.last = now;
+        let velocity = (raw - state.position) / dt;
+        state.velocity += (velocity - state.velocity) * alpha(self.derivative_cutoff, dt);
+        let cutoff = self.min_cutoff + self.beta * state.velocity.magnitude();
+        state.position += (raw - state.position) * alpha(cutoff, dt);
+
+        Some(SmoothedPoint {
+            raw,
+            position: state.position,
+            predicted: state.position + state.velocity * self.prediction.as_secs_f32(),
+            velocity: state.velocity,
+            pressure,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{StylusButtons, WacomTool};
+
+    fn draw(x: f32, y: f32) -> WacomEvent {
+        WacomEvent::Draw {
+            position: Point2 { x, y },
+            pressure: 2000,
+            tilt: Vector2 { x: 0, y: 0 },
+            tool: WacomTool::Pen,
+            buttons: StylusButtons::default(),
+        }
+    }
+
+    #[test]
+    fn test_stroke_filter() {
+        let mut filter = StrokeFilter::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // A slow horizontal stroke along y = 100, wobbling by a pixel
+        let mut raw_error = 0.0;
+        let mut smoothed_error = 0.0;
+        let mut last = None;
+        for i in 0..100u64 {
+            let wobble = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let point = filter
+                .filter(&draw(100.0 + i as f32, 100.0 + wobble), at(i * 10))
+                .unwrap();
+            assert_eq!(point.raw, Point2::new(100.0 + i as f32, 100.0 + wobble));
+            if i >= 10 {
+                raw_error += (point.raw.y - 100.0).abs();
+                smoothed_error += (point.position.y - 100.0).abs();
+            }
+            last = Some(point);
+        }
+        assert!(smoothed_error < raw_error / 4.0, "{}", smoothed_error);
+        // Predicted ahead of the smoothed point, along the stroke
+        let last = last.unwrap();
+        assert!(last.velocity.x > 50.0, "{:?}", last.velocity);
+        assert!(last.predicted.x > last.position.x);
+
+        // Lifting the pen starts the next stroke afresh
+        filter.filter(
+            &WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: false,
+            },
+            at(1000),
+        );
+        let point = filter.filter(&draw(500.0, 500.0), at(1010)).unwrap();
+        assert_eq!(point.position, Point2 { x: 500.0, y: 500.0 });
+        assert_eq!(point.predicted, point.position);
+    }
+}
@@ -2,17 +2,116 @@ use super::ecodes;
 use crate::device::rotate::CoordinatePart;
 use crate::device::CURRENT_DEVICE;
 use crate::input::scan::SCANNED;
-use crate::input::{InputDeviceState, InputEvent, WacomEvent, WacomPen};
+use crate::input::{
+    AxisMaxima, Geometry, InputDeviceState, InputEvent, StylusButtons, WacomEvent, WacomPen,
+    WacomTool,
+};
 use evdev::InputEvent as EvInputEvent;
 use log::debug;
 use once_cell::sync::Lazy;
 use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::RwLock;
 
 use crate::cgmath;
-use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH, WACOMHEIGHT, WACOMWIDTH};
 
-static WACOM_HSCALAR: Lazy<f32> = Lazy::new(|| f32::from(DISPLAYWIDTH) / f32::from(*WACOMWIDTH));
-static WACOM_VSCALAR: Lazy<f32> = Lazy::new(|| f32::from(DISPLAYHEIGHT) / f32::from(*WACOMHEIGHT));
+pub(crate) static GEOMETRY: Lazy<Geometry> = Lazy::new(|| {
+    Geometry::new(
+        SCANNED.wacom_orig_size,
+        CURRENT_DEVICE.get_wacom_placement(),
+    )
+});
+
+static PRESSURE_CURVE: Lazy<RwLock<Option<PressureCurve>>> = Lazy::new(|| RwLock::new(None));
+
+/// Evens out how the pressure of different pens and digitizers feels, applied to the
+/// pressure of every `WacomEvent::Draw` once set with `set_pressure_curve`.
+///
+/// Pressures are normalized to between 0.0 and 1.0 first, see
+/// `WacomEvent::normalized_pressure`, and scaled back to the raw range after.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PressureCurve {
+    /// Bends the pressure to `pressure.powf(gamma)`. Below 1.0 light strokes get
+    /// heavier, above they get lighter.
+    pub gamma: f32,
+    /// Pressure up to this counts as none
+    pub min: f32,
+    /// Pressure from this on counts as full
+    pub max: f32,
+    /// The pressure for evenly spaced pressures from 0.0 to 1.0, with those between
+    /// interpolated. Replaces `gamma` unless empty.
+    pub table: Vec<f32>,
+}
+
+impl Default for PressureCurve {
+    fn default() -> PressureCurve {
+        PressureCurve {
+            gamma: 1.0,
+            min: 0.0,
+            max: 1.0,
+            table: Vec::new(),
+        }
+    }
+}
+
+impl PressureCurve {
+    pub fn gamma(gamma: f32) -> PressureCurve {
+        PressureCurve {
+            gamma,
+            ..PressureCurve::default()
+        }
+    }
+
+    /// A curve through the points of `table`, see `PressureCurve::table`
+    pub fn lookup(table: Vec<f32>) -> PressureCurve {
+        PressureCurve {
+            table,
+            ..PressureCurve::default()
+        }
+    }
+
+    /// The calibrated pressure for the normalized `pressure`
+    pub fn apply(&self, pressure: f32) -> f32 {
+        let range = self.max - self.min;
+        let pressure = match range > 0.0 {
+            true => ((pressure - self.min) / range).clamp(0.0, 1.0),
+            false => f32::from(u8::from(pressure > self.min)),
+        };
+        let calibrated = match self.table.len() {
+            0 => pressure.powf(self.gamma),
+            1 => self.table[0],
+            len => {
+                let at = pressure * (len - 1) as f32;
+                let i = (at as usize).min(len - 2);
+                let t = at - i as f32;
+                self.table[i] + (self.table[i + 1] - self.table[i]) * t
+            }
+        };
+        calibrated.clamp(0.0, 1.0)
+    }
+
+    /// The calibrated raw `pressure` of a digitizer reporting up to `max`
+    pub fn apply_raw(&self, pressure: u16, max: u16) -> u16 {
+        let max = max.max(1);
+        let normalized = (f32::from(pressure) / f32::from(max)).min(1.0);
+        (self.apply(normalized) * f32::from(max)).round() as u16
+    }
+}
+
+/// Applies `curve` to the pen's pressure from now on, or stops calibrating it
+pub fn set_pressure_curve(curve: Option<PressureCurve>) {
+    *PRESSURE_CURVE.write().unwrap() = curve;
+}
+
+/// The curve set with `set_pressure_curve`
+pub fn pressure_curve() -> Option<PressureCurve> {
+    PRESSURE_CURVE.read().unwrap().clone()
+}
+
+/// The width of a stroke drawn with the normalized, and maybe calibrated,
+/// `pressure`: `min_width` without any pressure up to `max_width` at full pressure
+pub fn stroke_width(pressure: f32, min_width: f32, max_width: f32) -> f32 {
+    min_width + (max_width - min_width) * pressure.clamp(0.0, 1.0)
+}
 
 pub struct WacomState {
     last_x: AtomicU16,
@@ -22,6 +121,9 @@ pub struct WacomState {
     last_dist: AtomicU16,
     last_pressure: AtomicU16,
     last_touch_state: AtomicBool,
+    last_rubber: AtomicBool,
+    last_stylus: AtomicBool,
+    last_stylus2: AtomicBool,
 }
 
 impl ::std::default::Default for WacomState {
@@ -34,55 +136,97 @@ impl ::std::default::Default for WacomState {
             last_dist: AtomicU16::new(0),
             last_pressure: AtomicU16::new(0),
             last_touch_state: AtomicBool::new(false),
+            last_rubber: AtomicBool::new(false),
+            last_stylus: AtomicBool::new(false),
+            last_stylus2: AtomicBool::new(false),
         }
     }
 }
 
 pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<InputEvent> {
+    let mut event = decode_with(ev, outer_state, &GEOMETRY);
+    if let (
+        Some(InputEvent::WacomEvent {
+            event: WacomEvent::Draw { pressure, .. },
+        }),
+        Some(curve),
+    ) = (event.as_mut(), PRESSURE_CURVE.read().unwrap().as_ref())
+    {
+        *pressure = curve.apply_raw(*pressure, AxisMaxima::current().wacom_pressure);
+    }
+    event
+}
+
+fn unknown(ev: &EvInputEvent) -> Option<InputEvent> {
+    debug!(
+        "Unknown event for Wacom [type: {0:?} code: {1} value: {2}]",
+        ev.event_type(),
+        ev.code(),
+        ev.value()
+    );
+    Some(InputEvent::Unknown {})
+}
+
+pub(crate) fn decode_with(
+    ev: &EvInputEvent,
+    outer_state: &InputDeviceState,
+    geometry: &Geometry,
+) -> Option<InputEvent> {
     let state = match outer_state {
         InputDeviceState::WacomState(ref state_arc) => state_arc,
-        _ => unreachable!(),
+        _ => return Some(InputEvent::Unknown {}),
+    };
+    let position = || cgmath::Point2 {
+        x: f32::from(state.last_x.load(Ordering::Relaxed)) * geometry.scale.x,
+        y: f32::from(state.last_y.load(Ordering::Relaxed)) * geometry.scale.y,
+    };
+    let tilt = || cgmath::Vector2 {
+        x: state.last_xtilt.load(Ordering::Relaxed),
+        y: state.last_ytilt.load(Ordering::Relaxed),
+    };
+    let tool = || match state.last_rubber.load(Ordering::Relaxed) {
+        true => WacomTool::Eraser,
+        false => WacomTool::Pen,
+    };
+    let buttons = || StylusButtons {
+        primary: state.last_stylus.load(Ordering::Relaxed),
+        secondary: state.last_stylus2.load(Ordering::Relaxed),
     };
     match ev.event_type().0 {
         ecodes::EV_SYN => match state.last_touch_state.load(Ordering::Relaxed) {
             false => Some(InputEvent::WacomEvent {
                 event: WacomEvent::Hover {
-                    position: cgmath::Point2 {
-                        x: (f32::from(state.last_x.load(Ordering::Relaxed)) * *WACOM_HSCALAR),
-                        y: (f32::from(state.last_y.load(Ordering::Relaxed)) * *WACOM_VSCALAR),
-                    },
+                    position: position(),
                     distance: state.last_dist.load(Ordering::Relaxed),
-                    tilt: cgmath::Vector2 {
-                        x: state.last_xtilt.load(Ordering::Relaxed),
-                        y: state.last_ytilt.load(Ordering::Relaxed),
-                    },
+                    tilt: tilt(),
+                    tool: tool(),
+                    buttons: buttons(),
                 },
             }),
             true => Some(InputEvent::WacomEvent {
                 event: WacomEvent::Draw {
-                    position: cgmath::Point2 {
-                        x: (f32::from(state.last_x.load(Ordering::Relaxed)) * *WACOM_HSCALAR),
-                        y: (f32::from(state.last_y.load(Ordering::Relaxed)) * *WACOM_VSCALAR),
-                    },
+                    position: position(),
                     pressure: state.last_pressure.load(Ordering::Relaxed),
-                    tilt: cgmath::Vector2 {
-                        x: state.last_xtilt.load(Ordering::Relaxed),
-                        y: state.last_ytilt.load(Ordering::Relaxed),
-                    },
+                    tilt: tilt(),
+                    tool: tool(),
+                    buttons: buttons(),
                 },
             }),
         },
         ecodes::EV_KEY => {
             /* key (device detected - device out of range etc.) */
-            if ev.code() < WacomPen::ToolPen as u16 || ev.code() > WacomPen::Stylus2 as u16 {
-                return None;
-            }
-
-            let pen: WacomPen = unsafe { std::mem::transmute_copy(&ev.code()) };
+            let pen = match WacomPen::from_code(ev.code()) {
+                Some(pen) => pen,
+                None => return unknown(ev),
+            };
             let pen_state = ev.value() != 0;
 
-            if pen == WacomPen::Touch {
-                state.last_touch_state.store(pen_state, Ordering::Relaxed);
+            match pen {
+                WacomPen::Touch => state.last_touch_state.store(pen_state, Ordering::Relaxed),
+                WacomPen::ToolRubber => state.last_rubber.store(pen_state, Ordering::Relaxed),
+                WacomPen::Stylus => state.last_stylus.store(pen_state, Ordering::Relaxed),
+                WacomPen::Stylus2 => state.last_stylus2.store(pen_state, Ordering::Relaxed),
+                WacomPen::ToolPen => {}
             }
 
             Some(InputEvent::WacomEvent {
@@ -94,7 +238,7 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
         }
         ecodes::EV_ABS => {
             // Absolute
-            match ev.code() {
+            let rotated_part = match ev.code() {
                 ecodes::ABS_DISTANCE => {
                     // distance up to 255
                     // So we have an interesting behavior here.
@@ -109,90 +253,75 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
                             .fetch_add(ev.value() as u16, Ordering::Relaxed);
                         state.last_touch_state.store(true, Ordering::Relaxed);
                     }
+                    return None;
                 }
                 ecodes::ABS_TILT_X => {
                     // xtilt -9000 to 9000
                     state.last_xtilt.store(ev.value() as u16, Ordering::Relaxed);
+                    return None;
                 }
                 ecodes::ABS_TILT_Y => {
                     // ytilt -9000 to 9000
                     state.last_ytilt.store(ev.value() as u16, Ordering::Relaxed);
+                    return None;
                 }
                 ecodes::ABS_PRESSURE => {
                     // contact made with pressure val up to 4095
                     state
                         .last_pressure
                         .store(ev.value() as u16, Ordering::Relaxed);
+                    return None;
                 }
-                ecodes::ABS_X => {
-                    let placement = CURRENT_DEVICE.get_wacom_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::X(ev.value() as u16),
-                        &SCANNED.wacom_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *WACOMWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *WACOMHEIGHT - *y_value;
-                        }
-                    }
-                    match rotated_part {
-                        CoordinatePart::X(rotated_value) => {
-                            state.last_x.store(rotated_value, Ordering::Relaxed);
-                        }
-                        CoordinatePart::Y(rotated_value) => {
-                            state.last_y.store(rotated_value, Ordering::Relaxed);
-                        }
-                    }
-                }
-                ecodes::ABS_Y => {
-                    let placement = CURRENT_DEVICE.get_wacom_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::Y(ev.value() as u16),
-                        &SCANNED.wacom_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *WACOMWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *WACOMHEIGHT - *y_value;
-                        }
-                    }
-                    match rotated_part {
-                        CoordinatePart::X(rotated_value) => {
-                            state.last_x.store(rotated_value, Ordering::Relaxed);
-                        }
-                        CoordinatePart::Y(rotated_value) => {
-                            state.last_y.store(rotated_value, Ordering::Relaxed);
-                        }
-                    }
+                ecodes::ABS_X => geometry.map_x(ev.value()),
+                ecodes::ABS_Y => geometry.map_y(ev.value()),
+                _ => return unknown(ev),
+            };
+            match rotated_part {
+                CoordinatePart::X(rotated_value) => {
+                    state.last_x.store(rotated_value, Ordering::Relaxed);
                 }
-                _ => {
-                    debug!(
-                        "Unknown absolute event code for Wacom [type: {0:?} code: {1} value: {2}]",
-                        ev.event_type(),
-                        ev.code(),
-                        ev.value()
-                    );
+                CoordinatePart::Y(rotated_value) => {
+                    state.last_y.store(rotated_value, Ordering::Relaxed);
                 }
             }
             None
         }
-        _ => {
-            debug!(
-                "Unknown event TYPE for Wacom [type: {0:?} code: {1} value: {2}]",
-                ev.event_type(),
-                ev.code(),
-                ev.value()
-            );
-            None
+        _ => unknown(ev),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pressure_curve() {
+        let linear = PressureCurve::default();
+        for pressure in [0.0, 0.25, 1.0] {
+            assert_eq!(linear.apply(pressure), pressure);
         }
+        assert_eq!(linear.apply_raw(2000, 4095), 2000);
+
+        // Light strokes heavier
+        let soft = PressureCurve::gamma(0.5);
+        assert_eq!(soft.apply(0.25), 0.5);
+        assert_eq!(soft.apply_raw(1024, 4096), 2048);
+
+        let clamped = PressureCurve {
+            min: 0.1,
+            max: 0.9,
+            ..PressureCurve::default()
+        };
+        assert_eq!(clamped.apply(0.05), 0.0);
+        assert!((clamped.apply(0.5) - 0.5).abs() < 1e-6);
+        assert_eq!(clamped.apply(0.95), 1.0);
+
+        let table = PressureCurve::lookup(vec![0.0, 0.8, 1.0]);
+        assert_eq!(table.apply(0.5), 0.8);
+        assert!((table.apply(0.25) - 0.4).abs() < 1e-6);
+        assert_eq!(table.apply(1.0), 1.0);
+
+        assert_eq!(stroke_width(0.5, 2.0, 6.0), 4.0);
+        assert_eq!(stroke_width(1.5, 2.0, 6.0), 6.0);
     }
 }
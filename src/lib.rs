@@ -31,6 +31,26 @@ macro_rules! end_bench {
     };
 }
 
+#[cfg(feature = "trace-io")]
+#[allow(unused_macros)]
+macro_rules! trace_io {
+    ($($arg:tt)+) => {{
+        let line = format!(
+            "[{:>12.6?}] {}",
+            $crate::trace::timestamp(),
+            format_args!($($arg)+)
+        );
+        log::trace!(target: $crate::trace::TARGET, "{}", line);
+        $crate::trace::record(line);
+    }};
+}
+
+#[cfg(not(feature = "trace-io"))]
+#[allow(unused_macros)]
+macro_rules! trace_io {
+    ($($arg:tt)+) => {};
+}
+
 #[cfg(feature = "framebuffer-types")]
 #[macro_use(io, ioc, iow, iowr)]
 extern crate ioctl_gen;
@@ -59,10 +79,57 @@ pub mod input;
 /// Device dimensions.
 pub mod dimensions;
 
+/// Points and millimeters instead of pixels, for drawing, layout and input
+pub mod units;
+
+/// Vector representation of pen strokes along with import/export helpers
+#[cfg(feature = "stroke")]
+pub mod stroke;
+
+/// Pluggable handwriting recognition of strokes
+pub mod recognition;
+
+/// Text and image clipboard shared between apps
+pub mod clipboard;
+
+/// Notifications posted by background services, shown by the foreground app
+pub mod notification;
+
+/// Word prediction for typing, with a bar showing the suggestions
+pub mod prediction;
+
+/// Building blocks for shared, collaborative canvases (wire protocol etc.)
+#[cfg(feature = "canvas-protocol")]
+pub mod canvas;
+
+/// Writing notebooks of the stock reMarkable UI
+#[cfg(feature = "xochitl")]
+pub mod xochitl;
+
+/// User preferences of apps, saved in their data directory
+#[cfg(feature = "settings")]
+pub mod settings;
+
+/// Experimental compositor sharing the display between several apps
+#[cfg(feature = "compositor")]
+pub mod compositor;
+
+/// Timestamped trace logging of refresh ioctls and input device reads
+#[cfg(feature = "trace-io")]
+pub mod trace;
+
+/// Panic handler writing crash reports and showing a crash screen
+#[cfg(feature = "framebuffer-text-drawing")]
+pub mod crash;
+
 /// Simple battery and charging status provider
 #[cfg(feature = "battery")]
 pub mod battery;
 
+/// Estimated power draw and remaining runtime
+#[cfg(feature = "battery")]
+pub mod power;
+
 // TODO: Docs
 pub mod device;
 
@@ -71,7 +138,26 @@ pub mod device;
 /// to a scene after wrapping them in `UIElementWrapper`. None of these are mandatory to be used.
 /// You can choose to entirely ignore the `ApplicationContext` and `ui_extensions` and interact
 /// with the `framebuffer` and `input` devices directly.
-#[cfg(feature = "appctx")]
+#[cfg(feature = "appctx-core")]
 pub mod appctx;
-#[cfg(feature = "appctx")]
+#[cfg(feature = "appctx-core")]
 pub mod ui_extensions;
+
+/// Routing input to the handlers of regions of the screen
+#[cfg(feature = "appctx-core")]
+pub mod router;
+
+/// Deterministic, display-less runs of `ApplicationContext` applications for testing
+#[cfg(feature = "sim")]
+pub mod sim;
+
+/// Assertions comparing what was drawn against reference images
+#[cfg(all(feature = "framebuffer", feature = "image"))]
+pub mod testing;
+
+#[cfg(test)]
+mod test_util;
+
+/// Running `ApplicationContext` applications in a window on the desktop
+#[cfg(feature = "simulator")]
+pub mod simulator;
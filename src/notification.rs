@@ -0,0 +1,605 @@
+//! Notifications posted by background services and shown by the foreground app.
+//!
+//! The foreground app runs a `NotificationCenter` on a Unix socket, e.g. through
+//! `ApplicationContext::listen_for_notifications`, and shows what is posted to it as
+//! toasts or banners (see `render`). Services post with a `Notifier` and are told
+//! which action the user picked, or that the notification was dismissed:
+//!
+//! ```no_run
+//! use libremarkable::notification::{Notification, Notifier, Response, DEFAULT_SOCKET};
+//!
+//! let mut notifier = Notifier::connect(DEFAULT_SOCKET).unwrap();
+//! let id = notifier
+//!     .post(&Notification::new("Battery low").body("12% left").action("Sleep"))
+//!     .unwrap();
+//! if let Ok(Response::Action { action: 0, .. }) = notifier.responses().recv() {
+//!     // Go to sleep
+//! }
+//! ```
+//!
+//! Every message is a little endian `u32` length followed by that many bytes, the
+//! first of which tells the kind of message. Strings are sent as their length and
+//! UTF-8 bytes.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+
+/// Where the foreground app usually listens
+pub const DEFAULT_SOCKET: &str = "/run/libremarkable-notifications.sock";
+
+const MAX_MESSAGE_LEN: u32 = 64 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NotificationStyle {
+    /// A small box near the bottom of the screen
+    Toast,
+    /// A strip across the top of the screen
+    Banner,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    /// Labels of the buttons offered, reported back by index
+    pub actions: Vec<String>,
+    pub style: NotificationStyle,
+    /// How long to show it, until dismissed if `None`
+    pub timeout: Option<Duration>,
+}
+
+impl Notification {
+    /// A toast with `title`, shown until dismissed
+    pub fn new(title: &str) -> Notification {
+        Notification {
+            title: title.to_owned(),
+            body: String::new(),
+            actions: Vec::new(),
+            style: NotificationStyle::Toast,
+            timeout: None,
+        }
+    }
+
+    pub fn body(mut self, body: &str) -> Notification {
+        self.body = body.to_owned();
+        self
+    }
+
+    pub fn action(mut self, label: &str) -> Notification {
+        self.actions.push(label.to_owned());
+        self
+    }
+
+    pub fn style(mut self, style: NotificationStyle) -> Notification {
+        self.style = style;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Notification {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// What became of a posted notification, sent back to the service that posted it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// The user picked `actions[action]`
+    Action { id: u32, action: u32 },
+    /// The notification was dismissed or timed out
+    Dismissed { id: u32 },
+}
+
+/// Identifies a notification among those of all services
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NotificationKey {
+    client: u32,
+    pub id: u32,
+}
+
+/// What the foreground app is told
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotificationEvent {
+    Posted {
+        key: NotificationKey,
+        notification: Notification,
+    },
+    /// The service took the notification back, it should no longer be shown
+    Withdrawn { key: NotificationKey },
+}
+
+/// The service end
+pub struct Notifier {
+    stream: UnixStream,
+    next_id: u32,
+    responses: Receiver<Response>,
+}
+
+impl Notifier {
+    pub fn connect(socket: impl AsRef<Path>) -> io::Result<Notifier> {
+        let stream = UnixStream::connect(socket)?;
+        let mut reader = stream.try_clone()?;
+        let (tx, responses) = channel();
+        std::thread::spawn(move || {
+            while let Ok(message) = read_message(&mut reader) {
+                match decode_response(&message) {
+                    Some(response) => {
+                        if tx.send(response).is_err() {
+                            break;
+                        }
+                    }
+                    None => warn!("Malformed notification response"),
+                }
+            }
+        });
+        Ok(Notifier {
+            stream,
+            next_id: 1,
+            responses,
+        })
+    }
+
+    /// Shows `notification`, returning its id
+    pub fn post(&mut self, notification: &Notification) -> io::Result<u32> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut out = vec![1];
+        put_u32(&mut out, id);
+        out.push(match notification.style {
+            NotificationStyle::Toast => 0,
+            NotificationStyle::Banner => 1,
+        });
+        let timeout = notification
+            .timeout
+            .map_or(0, |t| t.as_millis().max(1) as u32);
+        put_u32(&mut out, timeout);
+        put_str(&mut out, &notification.title);
+        put_str(&mut out, &notification.body);
+        put_u32(&mut out, notification.actions.len() as u32);
+        for action in &notification.actions {
+            put_str(&mut out, action);
+        }
+        write_message(&self.stream, &out)?;
+        Ok(id)
+    }
+
+    /// Takes back the notification `id`
+    pub fn withdraw(&self, id: u32) -> io::Result<()> {
+        let mut out = vec![2];
+        put_u32(&mut out, id);
+        write_message(&self.stream, &out)
+    }
+
+    /// The responses to the posted notifications, as they happen
+    pub fn responses(&self) -> &Receiver<Response> {
+        &self.responses
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        // The thread reading the responses holds on to the socket as well
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// The foreground app end, receiving the notifications of all services
+pub struct NotificationCenter {
+    socket: PathBuf,
+    clients: Arc<Mutex<HashMap<u32, UnixStream>>>,
+}
+
+impl NotificationCenter {
+    /// Listens at `socket`, passing every notification posted or withdrawn to
+    /// `on_event` from a background thread
+    pub fn listen<F>(socket: impl AsRef<Path>, on_event: F) -> io::Result<NotificationCenter>
+    where
+        F: FnMut(NotificationEvent) + Send + 'static,
+    {
+        let socket = socket.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&socket)?;
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let on_event = Arc::new(Mutex::new(on_event));
+
+        let accepted = clients.clone();
+        std::thread::spawn(move || {
+            for (client, stream) in (1..).zip(listener.incoming()) {
+                let mut reader = match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+                    Ok((reader, stream)) => {
+                        accepted.lock().unwrap().insert(client, stream);
+                        reader
+                    }
+                    Err(err) => {
+                        warn!("Failed to accept a notification service: {}", err);
+                        continue;
+                    }
+                };
+                let on_event = on_event.clone();
+                let clients = accepted.clone();
+                std::thread::spawn(move || {
+                    while let Ok(message) = read_message(&mut reader) {
+                        match decode_event(client, &message) {
+                            Some(event) => (on_event.lock().unwrap())(event),
+                            None => warn!("Malformed notification from service {}", client),
+                        }
+                    }
+                    clients.lock().unwrap().remove(&client);
+                });
+            }
+        });
+        Ok(NotificationCenter { socket, clients })
+    }
+
+    /// Tells the service that posted `key` that the user picked `action`
+    pub fn invoke(&self, key: NotificationKey, action: u32) {
+        self.respond(key, Response::Action { id: key.id, action });
+    }
+
+    /// Tells the service that posted `key` that it was dismissed or timed out
+    pub fn dismiss(&self, key: NotificationKey) {
+        self.respond(key, Response::Dismissed { id: key.id });
+    }
+
+    fn respond(&self, key: NotificationKey, response: Response) {
+        let mut out = Vec::new();
+        match response {
+            Response::Action { id, action } => {
+                out.push(1);
+                put_u32(&mut out, id);
+                put_u32(&mut out, action);
+            }
+            Response::Dismissed { id } => {
+                out.push(2);
+                put_u32(&mut out, id);
+            }
+        }
+        // The service may be gone already, then there is no one left to tell
+        if let Some(stream) = self.clients.lock().unwrap().get(&key.client) {
+            let _ = write_message(stream, &out);
+        }
+    }
+}
+
+impl Drop for NotificationCenter {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn get_u32(data: &mut &[u8]) -> Option<u32> {
+    let value = data.get(..4)?.try_into().ok()?;
+    *data = &data[4..];
+    Some(u32::from_le_bytes(value))
+}
+
+fn get_str(data: &mut &[u8]) -> Option<String> {
+    let len = get_u32(data)? as usize;
+    let value = String::from_utf8(data.get(..len)?.to_vec()).ok()?;
+    *data = &data[len..];
+    Some(value)
+}
+
+fn decode_event(client: u32, message: &[u8]) -> Option<NotificationEvent> {
+    let (kind, mut data) = message.split_first()?;
+    let key = NotificationKey {
+        client,
+        id: get_u32(&mut data)?,
+    };
+    let event = match kind {
+        1 => {
+            let (style, rest) = data.split_first()?;
+            data = rest;
+            let style = match style {
+                0 => NotificationStyle::Toast,
+                1 => NotificationStyle::Banner,
+                _ => return None,
+            };
+            let timeout = match get_u32(&mut data)? {
+                0 => None,
+                ms => Some(Duration::from_millis(u64::from(ms))),
+            };
+            let title = get_str(&mut data)?;
+            let body = get_str(&mut data)?;
+            let actions = (0..get_u32(&mut data)?)
+                .map(|_| get_str(&mut data))
+                .collect::<Option<Vec<String>>>()?;
+            NotificationEvent::Posted {
+                key,
+                notification: Notification {
+                    title,
+                    body,
+                    actions,
+                    style,
+                    timeout,
+                },
+            }
+        }
+        2 => NotificationEvent::Withdrawn { key },
+        _ => return None,
+    };
+    data.is_empty().then_some(event)
+}
+
+fn decode_response(message: &[u8]) -> Option<Response> {
+    let (kind, mut data) = message.split_first()?;
+    let id = get_u32(&mut data)?;
+    let response = match kind {
+        1 => Response::Action {
+            id,
+            action: get_u32(&mut data)?,
+        },
+        2 => Response::Dismissed { id },
+        _ => return None,
+    };
+    data.is_empty().then_some(response)
+}
+
+fn write_message(mut stream: &UnixStream, message: &[u8]) -> io::Result<()> {
+    // One write, so messages of several threads can't interleave
+    let mut frame = (message.len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(message);
+    stream.write_all(&frame)
+}
+
+fn read_message(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "notification message too large",
+        ));
+    }
+    let mut message = vec![0u8; len as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+#[cfg(feature = "framebuffer-text-drawing")]
+pub use self::render::*;
+
+#[cfg(feature = "framebuffer-text-drawing")]
+mod render {
+    use super::{Notification, NotificationStyle};
+    use crate::framebuffer::cgmath;
+    use crate::framebuffer::common::{color, mxcfb_rect};
+    use crate::framebuffer::core::Framebuffer;
+    use crate::framebuffer::FramebufferDraw;
+
+    const TITLE_SIZE: f32 = 40.0;
+    const BODY_SIZE: f32 = 32.0;
+    const PADDING: u32 = 20;
+    const BUTTON_HEIGHT: u32 = 70;
+
+    /// Where a notification was drawn
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct NotificationLayout {
+        /// The whole notification, to refresh and to restore once it's gone
+        pub rect: mxcfb_rect,
+        /// The buttons of the actions, in order
+        pub actions: Vec<mxcfb_rect>,
+    }
+
+    impl NotificationLayout {
+        /// The index of the action at `point`, if any
+        pub fn action_at(&self, point: cgmath::Point2<u32>) -> Option<u32> {
+            self.actions
+                .iter()
+                .position(|button| button.contains_point(&point))
+                .map(|index| index as u32)
+        }
+    }
+
+    /// Draws `notification` in its style. The caller refreshes the returned area, and
+    /// should keep what was there before to restore it, e.g. with
+    /// `framebuffer::history::History`.
+    pub fn render(fb: &mut Framebuffer, notification: &Notification) -> NotificationLayout {
+        let (xres, yres) = (fb.var_screen_info.xres, fb.var_screen_info.yres);
+        let has_body = !notification.body.is_empty();
+        let has_actions = !notification.actions.is_empty();
+        let height = PADDING
+            + TITLE_SIZE as u32
+            + if has_body {
+                PADDING + BODY_SIZE as u32
+            } else {
+                0
+            }
+            + if has_actions {
+                PADDING + BUTTON_HEIGHT
+            } else {
+                0
+            }
+            + PADDING;
+        let rect = match notification.style {
+            NotificationStyle::Banner => mxcfb_rect {
+                left: 0,
+                top: 0,
+                width: xres,
+                height,
+            },
+            NotificationStyle::Toast => {
+                let width = xres * 2 / 3;
+                mxcfb_rect {
+                    left: (xres - width) / 2,
+                    top: yres.saturating_sub(height + yres / 10),
+                    width,
+                    height,
+                }
+            }
+        };
+
+        let top_left = cgmath::Point2 {
+            x: rect.left as i32,
+            y: rect.top as i32,
+        };
+        fb.fill_rect(top_left, rect.size(), color::WHITE);
+        fb.draw_rect(top_left, rect.size(), 3, color::BLACK);
+
+        let left = (rect.left + PADDING) as f32;
+        let mut baseline = (rect.top + PADDING) as f32 + TITLE_SIZE;
+        fb.draw_text(
+            cgmath::Point2 {
+                x: left,
+                y: baseline,
+            },
+            &notification.title,
+            TITLE_SIZE,
+            color::BLACK,
+            false,
+        );
+        if has_body {
+            baseline += PADDING as f32 + BODY_SIZE;
+            fb.draw_text(
+                cgmath::Point2 {
+                    x: left,
+                    y: baseline,
+                },
+                &notification.body,
+                BODY_SIZE,
+                color::GRAY(80),
+                false,
+            );
+        }
+
+        let mut actions = Vec::new();
+        if has_actions {
+            let count = notification.actions.len() as u32;
+            let width = (rect.width - PADDING) / count - PADDING;
+            let top = rect.top + rect.height - PADDING - BUTTON_HEIGHT;
+            for (i, label) in notification.actions.iter().enumerate() {
+                let button = mxcfb_rect {
+                    left: rect.left + PADDING + i as u32 * (width + PADDING),
+                    top,
+                    width,
+                    height: BUTTON_HEIGHT,
+                };
+                fb.draw_rect(
+                    button.top_left().cast().unwrap(),
+                    button.size(),
+                    2,
+                    color::BLACK,
+                );
+                // Centered in the button
+                let text = fb.draw_text((0.0, 0.0).into(), label, BODY_SIZE, color::BLACK, true);
+                fb.draw_text(
+                    cgmath::Point2 {
+                        x: (button.left + button.width.saturating_sub(text.width) / 2) as f32,
+                        y: (top + (BUTTON_HEIGHT + BODY_SIZE as u32) / 2) as f32 - 4.0,
+                    },
+                    label,
+                    BODY_SIZE,
+                    color::BLACK,
+                    false,
+                );
+                actions.push(button);
+            }
+        }
+        NotificationLayout { rect, actions }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let socket = std::env::temp_dir().join(format!("notification-test-{}", std::process::id()));
+        let (tx, events) = channel();
+        let center =
+            NotificationCenter::listen(&socket, move |event| tx.send(event).unwrap()).unwrap();
+
+        let mut notifier = Notifier::connect(&socket).unwrap();
+        let notification = Notification::new("Sync done")
+            .body("3 notebooks")
+            .action("Open")
+            .action("Later")
+            .style(NotificationStyle::Banner)
+            .timeout(Duration::from_secs(5));
+        let id = notifier.post(&notification).unwrap();
+        notifier.withdraw(id).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let key = match events.recv_timeout(timeout).unwrap() {
+            NotificationEvent::Posted {
+                key,
+                notification: posted,
+            } => {
+                assert_eq!(posted, notification);
+                key
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(key.id, id);
+        assert_eq!(
+            events.recv_timeout(timeout).unwrap(),
+            NotificationEvent::Withdrawn { key }
+        );
+
+        center.invoke(key, 1);
+        center.dismiss(key);
+        let responses = notifier.responses();
+        assert_eq!(
+            responses.recv_timeout(timeout).unwrap(),
+            Response::Action { id, action: 1 }
+        );
+        assert_eq!(
+            responses.recv_timeout(timeout).unwrap(),
+            Response::Dismissed { id }
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(decode_event(1, &[]), None);
+        assert_eq!(decode_event(1, &[1, 0, 0, 0, 0, 7]), None);
+        assert_eq!(decode_response(&[2, 1, 0, 0, 0, 0]), None);
+        assert_eq!(
+            decode_response(&[2, 1, 0, 0, 0]),
+            Some(Response::Dismissed { id: 1 })
+        );
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    #[test]
+    fn test_render() {
+        use crate::framebuffer::core::Framebuffer;
+
+        let mut fb = Framebuffer::headless(600, 800);
+        let toast = Notification::new("Saved").action("Undo").action("Open");
+        let layout = render(&mut fb, &toast);
+        assert_eq!(layout.rect.left, 100);
+        assert_eq!(layout.actions.len(), 2);
+        let second = layout.actions[1];
+        assert_eq!(
+            layout.action_at((second.left + 1, second.top + 1).into()),
+            Some(1)
+        );
+        assert_eq!(
+            layout.action_at((layout.rect.left + 1, layout.rect.top + 1).into()),
+            None
+        );
+
+        let banner = Notification::new("Update").style(NotificationStyle::Banner);
+        let layout = render(&mut fb, &banner);
+        assert_eq!((layout.rect.top, layout.rect.width), (0, 600));
+        assert!(layout.actions.is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+//! Estimating the power usage of the device, e.g. to tune how often an always-on
+//! dashboard refreshes.
+//!
+//! A `PowerEstimator` takes `PowerSample`s of the battery's fuel gauge, the CPU time
+//! spent and the refreshes sent, and turns each pair of them into a `PowerEstimate`
+//! of the draw over the interval in between and the runtime left at that draw. The
+//! draw is attributed to the CPU and refreshes with a `PowerModel`, which also stands
+//! in for the fuel gauge while charging.
+
+use std::time::{Duration, Instant};
+
+use crate::battery;
+
+/// Rough power draw of the parts of the device. The defaults are ballpark figures for
+/// the rM1 and rM2, measure your own device for better estimates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PowerModel {
+    /// Of an idle device with the display untouched
+    pub idle_mw: f32,
+    /// Added by a fully busy CPU
+    pub cpu_mw: f32,
+    /// Energy used refreshing a million pixels
+    pub refresh_mj_per_megapixel: f32,
+}
+
+impl Default for PowerModel {
+    fn default() -> PowerModel {
+        PowerModel {
+            idle_mw: 250.0,
+            cpu_mw: 700.0,
+            refresh_mj_per_megapixel: 120.0,
+        }
+    }
+}
+
+impl PowerModel {
+    /// The draw at `cpu_load` (0.0 to 1.0) while refreshing `megapixels_per_second`
+    pub fn power_mw(&self, cpu_load: f32, megapixels_per_second: f32) -> f32 {
+        self.idle_mw + self.cpu_mw(cpu_load) + self.refresh_mw(megapixels_per_second)
+    }
+
+    fn cpu_mw(&self, cpu_load: f32) -> f32 {
+        self.cpu_mw * cpu_load.clamp(0.0, 1.0)
+    }
+
+    fn refresh_mw(&self, megapixels_per_second: f32) -> f32 {
+        self.refresh_mj_per_megapixel * megapixels_per_second.max(0.0)
+    }
+}
+
+/// CPU time from `/proc/stat`, in clock ticks of all cores
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuTicks {
+    pub busy: u64,
+    pub total: u64,
+}
+
+impl CpuTicks {
+    pub fn read() -> Result<CpuTicks, String> {
+        let stat = std::fs::read_to_string("/proc/stat")
+            .map_err(|e| format!("Unable to read /proc/stat: {0}", e))?;
+        CpuTicks::parse(&stat).ok_or_else(|| "Unable to parse /proc/stat".to_owned())
+    }
+
+    /// From the summary line: user, nice, system, idle, iowait, irq, softirq, ...
+    fn parse(stat: &str) -> Option<CpuTicks> {
+        let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+        let ticks = line
+            .split_whitespace()
+            .skip(1)
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .ok()?;
+        let idle = ticks.get(3)? + ticks.get(4).unwrap_or(&0);
+        // Guest time is already part of user time
+        let total: u64 = ticks.iter().take(8).sum();
+        Some(CpuTicks {
+            busy: total.saturating_sub(idle),
+            total,
+        })
+    }
+}
+
+/// The state of the counters at one point in time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PowerSample {
+    pub at: Instant,
+    /// Battery voltage in µV
+    pub voltage: i32,
+    /// Battery current in µA, negative while discharging
+    pub current: i32,
+    /// Remaining charge in µAh
+    pub charge: i32,
+    pub cpu: CpuTicks,
+    /// Refreshes sent so far, see `framebuffer::refresh::activity`
+    pub refreshes: u64,
+    pub refreshed_pixels: u64,
+}
+
+impl PowerSample {
+    /// Samples the counters now
+    pub fn read() -> Result<PowerSample, String> {
+        #[cfg(feature = "framebuffer")]
+        let (refreshes, refreshed_pixels) = {
+            let activity = crate::framebuffer::refresh::activity();
+            (activity.refreshes, activity.pixels)
+        };
+        #[cfg(not(feature = "framebuffer"))]
+        let (refreshes, refreshed_pixels) = (0, 0);
+
+        Ok(PowerSample {
+            at: Instant::now(),
+            voltage: battery::voltage()?,
+            current: battery::current()?,
+            charge: battery::charge()?,
+            cpu: CpuTicks::read()?,
+            refreshes,
+            refreshed_pixels,
+        })
+    }
+
+    /// The draw in mW according to the fuel gauge, if discharging
+    fn measured_mw(&self) -> Option<f32> {
+        match self.current < 0 {
+            true => Some(self.voltage as f32 * -self.current as f32 / 1e9),
+            false => None,
+        }
+    }
+}
+
+/// The power usage over the interval between two samples
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PowerEstimate {
+    /// Total draw, smoothed over the recent samples
+    pub power_mw: f32,
+    /// Whether `power_mw` comes from the fuel gauge rather than the `PowerModel`,
+    /// which is used while charging
+    pub measured: bool,
+    /// Share of the CPU time spent busy, 0.0 to 1.0
+    pub cpu_load: f32,
+    /// Part of `power_mw` spent on the CPU
+    pub cpu_mw: f32,
+    pub refreshes_per_minute: f32,
+    /// Part of `power_mw` spent refreshing
+    pub refresh_mw: f32,
+    /// Until the battery is empty at `power_mw`
+    pub runtime: Duration,
+}
+
+/// Turns samples of the power counters into estimates
+#[derive(Clone, Debug, Default)]
+pub struct PowerEstimator {
+    pub model: PowerModel,
+    last: Option<PowerSample>,
+    power_mw: Option<f32>,
+}
+
+impl PowerEstimator {
+    /// Weight of the newest sample in the smoothed draw
+    const SMOOTHING: f32 = 0.3;
+
+    pub fn new(model: PowerModel) -> PowerEstimator {
+        PowerEstimator {
+            model,
+            ..Default::default()
+        }
+    }
+
+    /// Samples the counters now, see `update`
+    pub fn sample(&mut self) -> Result<Option<PowerEstimate>, String> {
+        Ok(self.update(PowerSample::read()?))
+    }
+
+    /// Estimates the usage since the previous sample. Returns `None` for the first
+    /// sample, or one taken at the same time as the previous.
+    pub fn update(&mut self, sample: PowerSample) -> Option<PowerEstimate> {
+        let last = self.last.replace(sample)?;
+        let seconds = sample.at.checked_duration_since(last.at)?.as_secs_f32();
+        if seconds <= 0.0 {
+            return None;
+        }
+
+        let ticks = sample.cpu.total.saturating_sub(last.cpu.total);
+        let cpu_load = match ticks {
+            0 => 0.0,
+            _ => sample.cpu.busy.saturating_sub(last.cpu.busy) as f32 / ticks as f32,
+        };
+        let refreshes = sample.refreshes.saturating_sub(last.refreshes) as f32;
+        let megapixels = sample
+            .refreshed_pixels
+            .saturating_sub(last.refreshed_pixels) as f32
+            / 1e6;
+        let modeled = self.model.power_mw(cpu_load, megapixels / seconds);
+
+        // Average the gauge over the interval, it only updates every few seconds
+        let measured = sample.measured_mw().map(|now| {
+            last.measured_mw()
+                .map_or(now, |before| (before + now) / 2.0)
+        });
+        let current = measured.unwrap_or(modeled);
+        let power_mw = match self.power_mw {
+            Some(smoothed) => smoothed + (current - smoothed) * Self::SMOOTHING,
+            None => current,
+        };
+        self.power_mw = Some(power_mw);
+
+        // Attribute the draw in proportion to what the model expects
+        let share = power_mw / modeled;
+        Some(PowerEstimate {
+            power_mw,
+            measured: measured.is_some(),
+            cpu_load,
+            cpu_mw: self.model.cpu_mw(cpu_load) * share,
+            refreshes_per_minute: refreshes * 60.0 / seconds,
+            refresh_mw: self.model.refresh_mw(megapixels / seconds) * share,
+            runtime: self.runtime_at(power_mw)?,
+        })
+    }
+
+    /// Until the battery is empty at a draw of `power_mw`, e.g. to compare refresh
+    /// strategies with `PowerModel::power_mw`. `None` before the first sample.
+    pub fn runtime_at(&self, power_mw: f32) -> Option<Duration> {
+        let last = self.last?;
+        let energy_mwh = last.charge.max(0) as f32 / 1e3 * last.voltage.max(0) as f32 / 1e6;
+        Some(Duration::from_secs_f32(
+            energy_mwh / power_mw.max(1.0) * 3600.0,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let stat = "cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 100 0 50 800 50 0 0 0 0 0\n";
+        assert_eq!(
+            CpuTicks::parse(stat),
+            Some(CpuTicks {
+                busy: 150,
+                total: 1000
+            })
+        );
+
+        let start = Instant::now();
+        let sample = PowerSample {
+            at: start,
+            voltage: 4_000_000,
+            current: -100_000,
+            charge: 1_000_000,
+            cpu: CpuTicks {
+                busy: 150,
+                total: 1000,
+            },
+            refreshes: 10,
+            refreshed_pixels: 1_000_000,
+        };
+        let mut estimator = PowerEstimator::default();
+        assert_eq!(estimator.update(sample), None);
+
+        // A quarter busy while refreshing a megapixel a second for 10s
+        let estimate = estimator
+            .update(PowerSample {
+                at: start + Duration::from_secs(10),
+                cpu: CpuTicks {
+                    busy: 400,
+                    total: 2000,
+                },
+                refreshes: 30,
+                refreshed_pixels: 11_000_000,
+                ..sample
+            })
+            .unwrap();
+        assert!(estimate.measured);
+        assert_eq!(estimate.power_mw, 400.0);
+        assert_eq!(estimate.cpu_load, 0.25);
+        assert_eq!(estimate.refreshes_per_minute, 120.0);
+        assert!(estimate.cpu_mw > 0.0 && estimate.refresh_mw > 0.0);
+        assert!(estimate.cpu_mw + estimate.refresh_mw < estimate.power_mw);
+        // 4 Wh at 400 mW
+        assert_eq!(estimate.runtime, Duration::from_secs(10 * 3600));
+
+        // While charging, the model stands in for the gauge
+        let estimate = estimator
+            .update(PowerSample {
+                at: start + Duration::from_secs(20),
+                current: 500_000,
+                ..sample
+            })
+            .unwrap();
+        assert!(!estimate.measured);
+    }
+}
@@ -0,0 +1,328 @@
+//! Word prediction, to make typing on the screen less tedious.
+//!
+//! A `Dictionary` knows how often words were used and suggests the most frequent
+//! ones starting with what was typed so far. A `Predictor` follows the characters
+//! typed, e.g. the `KeyboardEvent::Text`s of a keyboard, suggests completions of
+//! the current word and learns every finished word. With `framebuffer-text-drawing`,
+//! a `SuggestionBar` shows the suggestions above a keyboard and tells which was
+//! tapped.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// How often words were used
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dictionary {
+    /// By lowercase word
+    words: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    pub fn new() -> Dictionary {
+        Dictionary::default()
+    }
+
+    /// Reads a dictionary saved with `save`: a word and its count per line, separated
+    /// by whitespace. A missing count counts as 1, so plain word lists work as well.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Dictionary> {
+        let mut dictionary = Dictionary::new();
+        for line in std::fs::read_to_string(path)?.lines() {
+            let mut fields = line.split_whitespace();
+            if let Some(word) = fields.next() {
+                let count = fields.next().and_then(|c| c.parse().ok()).unwrap_or(1);
+                dictionary.add(word, count);
+            }
+        }
+        Ok(dictionary)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut words: Vec<_> = self.words.iter().collect();
+        words.sort();
+        let lines: String = words
+            .into_iter()
+            .map(|(word, count)| format!("{} {}\n", word, count))
+            .collect();
+        std::fs::write(path, lines)
+    }
+
+    /// Counts `word` as used `count` more times
+    pub fn add(&mut self, word: &str, count: u32) {
+        let word = word.to_lowercase();
+        if !word.is_empty() {
+            let total = self.words.entry(word).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+    }
+
+    /// Counts every word of `text` as used once more
+    pub fn learn(&mut self, text: &str) {
+        for word in text.split(|c: char| !is_word_char(c)) {
+            self.add(word, 1);
+        }
+    }
+
+    pub fn count(&self, word: &str) -> u32 {
+        self.words.get(&word.to_lowercase()).copied().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The at most `limit` most used words starting with `prefix`, other than `prefix`
+    /// itself. They follow the capitalization of `prefix`.
+    pub fn predict(&self, prefix: &str, limit: usize) -> Vec<String> {
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        let lower = prefix.to_lowercase();
+        let mut matches: Vec<(&String, u32)> = self
+            .words
+            .iter()
+            .filter(|(word, _)| word.starts_with(&lower) && **word != lower)
+            .map(|(word, count)| (word, *count))
+            .collect();
+        // Most used first, ties alphabetically so suggestions don't jump around
+        matches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(word, _)| match_case(prefix, word))
+            .collect()
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\'' || c == '-'
+}
+
+/// `word` capitalized like `prefix`: all caps, first letter only or not at all
+fn match_case(prefix: &str, word: &str) -> String {
+    let mut letters = prefix.chars().filter(|c| c.is_alphabetic());
+    match letters.next() {
+        Some(first) if first.is_uppercase() => {
+            if prefix.chars().count() > 1 && letters.all(char::is_uppercase) {
+                word.to_uppercase()
+            } else {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+        }
+        _ => word.to_owned(),
+    }
+}
+
+/// Follows what's typed to suggest completions of the current word
+#[derive(Clone, Debug, Default)]
+pub struct Predictor {
+    pub dictionary: Dictionary,
+    /// The word being typed
+    word: String,
+}
+
+impl Predictor {
+    pub fn new(dictionary: Dictionary) -> Predictor {
+        Predictor {
+            dictionary,
+            word: String::new(),
+        }
+    }
+
+    /// The part of the current word typed so far
+    pub fn word(&self) -> &str {
+        &self.word
+    }
+
+    /// Follows a typed character. Finishing a word teaches it to the dictionary.
+    pub fn push(&mut self, character: char) {
+        if is_word_char(character) {
+            self.word.push(character);
+        } else {
+            self.finish();
+        }
+    }
+
+    /// Follows the deletion of the last character typed. Characters deleted before
+    /// the current word aren't followed.
+    pub fn backspace(&mut self) {
+        self.word.pop();
+    }
+
+    /// Forgets the current word without learning it, e.g. when the cursor moved
+    pub fn reset(&mut self) {
+        self.word.clear();
+    }
+
+    pub fn suggestions(&self, limit: usize) -> Vec<String> {
+        self.dictionary.predict(&self.word, limit)
+    }
+
+    /// Completes the current word with `suggestion` and learns it. Returns what has to
+    /// be typed, replacing the `word().chars().count()` characters typed so far.
+    pub fn accept(&mut self, suggestion: &str) -> String {
+        self.dictionary.add(suggestion, 1);
+        self.word.clear();
+        format!("{} ", suggestion)
+    }
+
+    fn finish(&mut self) {
+        let word = std::mem::take(&mut self.word);
+        self.dictionary.add(&word, 1);
+    }
+}
+
+#[cfg(feature = "framebuffer-text-drawing")]
+pub use self::bar::SuggestionBar;
+
+#[cfg(feature = "framebuffer-text-drawing")]
+mod bar {
+    use crate::framebuffer::cgmath;
+    use crate::framebuffer::common::{color, mxcfb_rect};
+    use crate::framebuffer::core::Framebuffer;
+    use crate::framebuffer::FramebufferDraw;
+
+    /// A row of suggestions, e.g. above an on-screen keyboard
+    pub struct SuggestionBar {
+        pub rect: mxcfb_rect,
+        pub text_size: f32,
+        suggestions: Vec<(String, mxcfb_rect)>,
+    }
+
+    impl SuggestionBar {
+        pub fn new(rect: mxcfb_rect) -> SuggestionBar {
+            SuggestionBar {
+                rect,
+                text_size: rect.height as f32 * 0.5,
+                suggestions: Vec::new(),
+            }
+        }
+
+        /// Shows `suggestions` in equally wide slots. Returns the area to refresh.
+        pub fn draw(&mut self, fb: &mut Framebuffer, suggestions: &[String]) -> mxcfb_rect {
+            let rect = self.rect;
+            fb.fill_rect(rect.top_left().cast().unwrap(), rect.size(), color::WHITE);
+            fb.draw_line(
+                cgmath::Point2 {
+                    x: rect.left as i32,
+                    y: (rect.top + rect.height - 1) as i32,
+                },
+                cgmath::Point2 {
+                    x: (rect.left + rect.width - 1) as i32,
+                    y: (rect.top + rect.height - 1) as i32,
+                },
+                1,
+                color::BLACK,
+            );
+
+            self.suggestions.clear();
+            let width = rect.width / suggestions.len().max(1) as u32;
+            for (i, suggestion) in suggestions.iter().enumerate() {
+                let slot = mxcfb_rect {
+                    left: rect.left + i as u32 * width,
+                    top: rect.top,
+                    width,
+                    height: rect.height,
+                };
+                if i > 0 {
+                    fb.draw_line(
+                        cgmath::Point2 {
+                            x: slot.left as i32,
+                            y: (slot.top + slot.height / 4) as i32,
+                        },
+                        cgmath::Point2 {
+                            x: slot.left as i32,
+                            y: (slot.top + slot.height * 3 / 4) as i32,
+                        },
+                        1,
+                        color::GRAY(128),
+                    );
+                }
+                let size = self.text_size;
+                let text = fb.draw_text((0.0, 0.0).into(), suggestion, size, color::BLACK, true);
+                fb.draw_text(
+                    cgmath::Point2 {
+                        x: (slot.left + slot.width.saturating_sub(text.width) / 2) as f32,
+                        y: (slot.top + slot.height / 2) as f32 + size / 3.0,
+                    },
+                    suggestion,
+                    size,
+                    color::BLACK,
+                    false,
+                );
+                self.suggestions.push((suggestion.clone(), slot));
+            }
+            rect
+        }
+
+        /// The suggestion drawn at `point`, if any
+        pub fn suggestion_at(&self, point: cgmath::Point2<u32>) -> Option<&str> {
+            self.suggestions
+                .iter()
+                .find(|(_, slot)| slot.contains_point(&point))
+                .map(|(suggestion, _)| suggestion.as_str())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prediction() {
+        let mut dictionary = Dictionary::new();
+        dictionary.learn("the then there there, them. The other");
+        assert_eq!(dictionary.count("THE"), 2);
+        assert_eq!(dictionary.predict("th", 2), ["the", "there"]);
+        assert_eq!(dictionary.predict("The", 3), ["There", "Them", "Then"]);
+        assert_eq!(dictionary.predict("THE", 1), ["THERE"]);
+        assert!(dictionary.predict("x", 3).is_empty());
+
+        let mut predictor = Predictor::new(dictionary);
+        "oth".chars().for_each(|c| predictor.push(c));
+        assert_eq!(predictor.suggestions(3), ["other"]);
+        predictor.backspace();
+        assert_eq!(predictor.word(), "ot");
+        assert_eq!(predictor.accept("other"), "other ");
+        assert_eq!(predictor.dictionary.count("other"), 2);
+
+        // Learns from what's typed
+        "otter ".chars().for_each(|c| predictor.push(c));
+        "ott".chars().for_each(|c| predictor.push(c));
+        assert_eq!(predictor.suggestions(3), ["otter"]);
+
+        let path = std::env::temp_dir().join(format!("prediction-test-{}", std::process::id()));
+        predictor.dictionary.save(&path).unwrap();
+        assert_eq!(Dictionary::load(&path).unwrap(), predictor.dictionary);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    #[test]
+    fn test_suggestion_bar() {
+        use crate::framebuffer::common::mxcfb_rect;
+        use crate::framebuffer::core::Framebuffer;
+
+        let mut fb = Framebuffer::headless(300, 200);
+        let rect = mxcfb_rect {
+            left: 0,
+            top: 100,
+            width: 300,
+            height: 50,
+        };
+        let mut bar = SuggestionBar::new(rect);
+        let drawn = bar.draw(&mut fb, &["one".to_owned(), "two".to_owned()]);
+        assert_eq!(drawn, rect);
+        assert_eq!(bar.suggestion_at((200, 120).into()), Some("two"));
+        assert_eq!(bar.suggestion_at((200, 20).into()), None);
+    }
+}
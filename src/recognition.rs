@@ -0,0 +1,193 @@
+//! Handwriting recognition of finished strokes.
+//!
+//! The crate doesn't ship a recognition engine. Anything implementing `Recognizer`
+//! can be plugged in, including plain closures, and `CommandRecognizer` hands the
+//! strokes to an external program, e.g. a client of an on-device daemon or of a
+//! network service.
+//!
+//! With an `ApplicationContext`, set the engine with `set_recognizer` and call
+//! `recognize` once a word or line is complete. The result is delivered to the event
+//! loop as `InputEvent::Recognition`.
+
+/// A possible reading of the handwriting
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub text: String,
+    /// How likely the engine considers this candidate, in the range `0.0..=1.0`
+    pub confidence: f32,
+}
+
+impl Candidate {
+    pub fn new(text: &str, confidence: f32) -> Candidate {
+        Candidate {
+            text: text.to_owned(),
+            confidence,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum RecognitionError {
+    #[error("failed to run the recognition engine: {0}")]
+    Engine(String),
+    #[error("malformed response from the recognition engine: {0}")]
+    Response(String),
+}
+
+/// The outcome of a recognition request
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recognition {
+    /// The id returned when the request was made
+    pub request: u64,
+    /// The candidates, best first
+    pub result: Result<Vec<Candidate>, RecognitionError>,
+}
+
+#[cfg(feature = "stroke")]
+pub use self::recognizer::*;
+
+#[cfg(feature = "stroke")]
+mod recognizer {
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+
+    use super::{Candidate, RecognitionError};
+    use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH};
+    use crate::framebuffer::cgmath;
+    use crate::stroke::{svg, Stroke};
+
+    /// A handwriting recognition engine
+    pub trait Recognizer: Send {
+        /// Reads the text written by `strokes`. Candidates are returned best first.
+        fn recognize(&mut self, strokes: &[Stroke]) -> Result<Vec<Candidate>, RecognitionError>;
+    }
+
+    impl<F> Recognizer for F
+    where
+        F: FnMut(&[Stroke]) -> Result<Vec<Candidate>, RecognitionError> + Send,
+    {
+        fn recognize(&mut self, strokes: &[Stroke]) -> Result<Vec<Candidate>, RecognitionError> {
+            self(strokes)
+        }
+    }
+
+    /// Runs an external program for every request.
+    ///
+    /// The strokes are written to its stdin as an SVG document (see `stroke::svg`).
+    /// It has to print one candidate per line, best first, as the confidence and the
+    /// text separated by a tab, and exit successfully.
+    pub struct CommandRecognizer {
+        program: String,
+        args: Vec<String>,
+        /// Size of the SVG documents, the display by default
+        pub size: cgmath::Vector2<u32>,
+    }
+
+    impl CommandRecognizer {
+        pub fn new(program: &str) -> CommandRecognizer {
+            CommandRecognizer {
+                program: program.to_owned(),
+                args: Vec::new(),
+                size: cgmath::Vector2 {
+                    x: u32::from(DISPLAYWIDTH),
+                    y: u32::from(DISPLAYHEIGHT),
+                },
+            }
+        }
+
+        pub fn arg(mut self, arg: &str) -> CommandRecognizer {
+            self.args.push(arg.to_owned());
+            self
+        }
+    }
+
+    impl Recognizer for CommandRecognizer {
+        fn recognize(&mut self, strokes: &[Stroke]) -> Result<Vec<Candidate>, RecognitionError> {
+            let engine_error = |err: std::io::Error| RecognitionError::Engine(err.to_string());
+            let mut child = Command::new(&self.program)
+                .args(&self.args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(engine_error)?;
+
+            // Written from another thread so a large document can't block on a full
+            // pipe while the engine is writing its output. Dropping stdin closes it,
+            // which tells the engine the document is complete.
+            let mut stdin = child.stdin.take().unwrap();
+            let document = svg::to_svg(strokes, self.size);
+            let writer = std::thread::spawn(move || stdin.write_all(document.as_bytes()));
+            let mut output = String::new();
+            let read = child.stdout.take().unwrap().read_to_string(&mut output);
+            let status = child.wait().map_err(engine_error)?;
+            if !status.success() {
+                return Err(RecognitionError::Engine(format!(
+                    "{} exited with {}",
+                    self.program, status
+                )));
+            }
+            writer.join().unwrap().map_err(engine_error)?;
+            read.map_err(engine_error)?;
+            parse_candidates(&output)
+        }
+    }
+
+    /// Parses the output of a `CommandRecognizer` engine
+    pub(crate) fn parse_candidates(output: &str) -> Result<Vec<Candidate>, RecognitionError> {
+        output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (confidence, text) = line
+                    .split_once('\t')
+                    .ok_or_else(|| RecognitionError::Response(line.to_owned()))?;
+                let confidence = confidence
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|_| RecognitionError::Response(line.to_owned()))?;
+                Ok(Candidate::new(text, confidence.clamp(0.0, 1.0)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "stroke"))]
+mod test {
+    use super::*;
+    use crate::stroke::{Stroke, StrokePoint};
+
+    #[test]
+    fn test_parse_candidates() {
+        assert_eq!(
+            parse_candidates("0.9\thello\n0.25\thallo\n\n").unwrap(),
+            vec![Candidate::new("hello", 0.9), Candidate::new("hallo", 0.25)]
+        );
+        assert_eq!(
+            parse_candidates("hello"),
+            Err(RecognitionError::Response("hello".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_command_recognizer() {
+        let stroke = Stroke::new(
+            vec![StrokePoint::new(10.0, 10.0), StrokePoint::new(20.0, 20.0)],
+            2.0,
+            crate::framebuffer::common::color::BLACK,
+        );
+        // Answers with the number of paths in the document it was sent
+        let mut engine = CommandRecognizer::new("sh")
+            .arg("-c")
+            .arg(r#"printf '1\t%s\n' "$(grep -c '<path')""#);
+        assert_eq!(
+            engine.recognize(&[stroke.clone(), stroke]).unwrap(),
+            vec![Candidate::new("2", 1.0)]
+        );
+
+        let mut failing = CommandRecognizer::new("false");
+        assert!(matches!(
+            failing.recognize(&[]),
+            Err(RecognitionError::Engine(_))
+        ));
+    }
+}
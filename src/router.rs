@@ -0,0 +1,284 @@
+//! Sending input to the part of the screen it is meant for.
+//!
+//! Apps register regions with the `InputRouter` of their `ApplicationContext`, each
+//! with a handler and a z-order. The event loop passes pen and touch events to the
+//! handler of the topmost region under them, and everything else, like input outside
+//! of all regions, to its callback as before:
+//!
+//! ```no_run
+//! # use libremarkable::appctx::ApplicationContext;
+//! # use libremarkable::framebuffer::common::mxcfb_rect;
+//! let mut app = ApplicationContext::default();
+//! let canvas = mxcfb_rect { left: 0, top: 100, width: 1404, height: 1772 };
+//! app.input_router().add_region(canvas, 0, |_app, _event| {
+//!     // Draw
+//! });
+//! let toolbar = mxcfb_rect { left: 0, top: 0, width: 1404, height: 100 };
+//! app.input_router().add_region(toolbar, 1, |_app, _event| {
+//!     // Pick a tool
+//! });
+//! ```
+//!
+//! A drag belongs to the region it started in: once the pen touches the display, it
+//! stays with that region until it lifts, and so does every finger until it lifts,
+//! even when they move out of it.
+
+use std::sync::{Arc, Mutex};
+
+use fxhash::FxHashMap;
+
+use crate::appctx::ApplicationContext;
+use crate::framebuffer::cgmath::Point2;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::input::{InputEvent, MultitouchEvent, WacomEvent, WacomPen};
+
+/// Handles the input routed to a region
+pub type RegionHandler = Arc<Mutex<dyn FnMut(&mut ApplicationContext<'_>, InputEvent) + Send>>;
+
+/// Identifies a region of an `InputRouter`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RegionId(u64);
+
+enum Shape {
+    Rect(mxcfb_rect),
+    Custom(Box<dyn Fn(Point2<f32>) -> bool + Send>),
+}
+
+impl Shape {
+    fn contains(&self, pos: Point2<f32>) -> bool {
+        match self {
+            Shape::Rect(rect) => {
+                pos.x >= rect.left as f32
+                    && pos.y >= rect.top as f32
+                    && pos.x < (rect.left + rect.width) as f32
+                    && pos.y < (rect.top + rect.height) as f32
+            }
+            Shape::Custom(contains) => contains(pos),
+        }
+    }
+}
+
+struct Region {
+    id: RegionId,
+    z: i32,
+    shape: Shape,
+    handler: RegionHandler,
+}
+
+/// Finds the region input is for, see the module documentation
+#[derive(Default)]
+pub struct InputRouter {
+    /// From the bottom to the top
+    regions: Vec<Region>,
+    next_id: u64,
+    pen_down: bool,
+    /// The region under the pen, or holding it while it touches the display
+    pen: Option<RegionId>,
+    /// The region holding each finger, by its tracking id
+    fingers: FxHashMap<i32, Option<RegionId>>,
+}
+
+impl InputRouter {
+    pub fn new() -> InputRouter {
+        InputRouter::default()
+    }
+
+    fn add(&mut self, shape: Shape, z: i32, handler: RegionHandler) -> RegionId {
+        let id = RegionId(self.next_id);
+        self.next_id += 1;
+        // Above those with the same z-order added before
+        let at = self.regions.partition_point(|region| region.z <= z);
+        self.regions.insert(
+            at,
+            Region {
+                id,
+                z,
+                shape,
+                handler,
+            },
+        );
+        id
+    }
+
+    /// Routes the input on `rect` to `handler`, unless a region with a higher `z` is
+    /// on top. Of regions with the same `z`, the one added last is on top.
+    pub fn add_region(
+        &mut self,
+        rect: mxcfb_rect,
+        z: i32,
+        handler: impl FnMut(&mut ApplicationContext<'_>, InputEvent) + Send + 'static,
+    ) -> RegionId {
+        self.add(Shape::Rect(rect), z, Arc::new(Mutex::new(handler)))
+    }
+
+    /// Like `add_region`, for the positions `contains` is true for
+    pub fn add_custom_region(
+        &mut self,
+        contains: impl Fn(Point2<f32>) -> bool + Send + 'static,
+        z: i32,
+        handler: impl FnMut(&mut ApplicationContext<'_>, InputEvent) + Send + 'static,
+    ) -> RegionId {
+        self.add(
+            Shape::Custom(Box::new(contains)),
+            z,
+            Arc::new(Mutex::new(handler)),
+        )
+    }
+
+    /// Moves the region `id` to `rect`. Returns whether there is such a region.
+    pub fn set_rect(&mut self, id: RegionId, rect: mxcfb_rect) -> bool {
+        match self.regions.iter_mut().find(|region| region.id == id) {
+            Some(region) => {
+                region.shape = Shape::Rect(rect);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the region `id`. Input it holds goes to the callback from now on.
+    pub fn remove(&mut self, id: RegionId) -> bool {
+        let len = self.regions.len();
+        self.regions.retain(|region| region.id != id);
+        self.regions.len() != len
+    }
+
+    /// The topmost region at `pos`
+    pub fn region_at(&self, pos: Point2<f32>) -> Option<RegionId> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.shape.contains(pos))
+            .map(|region| region.id)
+    }
+
+    /// The region `event` is for, following the pen and the fingers along
+    pub fn route(&mut self, event: &InputEvent) -> Option<RegionId> {
+        let target = match event {
+            InputEvent::WacomEvent { event } => match *event {
+                WacomEvent::Hover { position, .. } => {
+                    self.pen_down = false;
+                    self.pen = self.region_at(position);
+                    self.pen
+                }
+                WacomEvent::Draw { position, .. } => {
+                    if !self.pen_down {
+                        self.pen_down = true;
+                        self.pen = self.region_at(position);
+                    }
+                    self.pen
+                }
+                WacomEvent::InstrumentChange {
+                    pen: WacomPen::Touch,
+                    state,
+                } => {
+                    // Touching down is left to the first `Draw`, where the pen is
+                    self.pen_down &= state;
+                    self.pen
+                }
+                WacomEvent::InstrumentChange { .. } => self.pen,
+                WacomEvent::Unknown => None,
+            },
+            InputEvent::MultitouchEvent { event } => match event {
+                MultitouchEvent::Press { finger } => {
+                    let target = self.region_at(finger.pos.cast().unwrap());
+                    self.fingers.insert(finger.tracking_id, target);
+                    target
+                }
+                MultitouchEvent::Move { finger } => match self.fingers.get(&finger.tracking_id) {
+                    Some(target) => *target,
+                    None => self.region_at(finger.pos.cast().unwrap()),
+                },
+                MultitouchEvent::Release { finger } => {
+                    self.fingers.remove(&finger.tracking_id).flatten()
+                }
+                MultitouchEvent::Unknown => None,
+            },
+            _ => None,
+        };
+        // Removed regions don't hold on to anything
+        target.filter(|id| self.regions.iter().any(|region| region.id == *id))
+    }
+
+    /// The handler of `event`'s region, if it is for one
+    pub(crate) fn handler(&mut self, event: &InputEvent) -> Option<RegionHandler> {
+        let id = self.route(event)?;
+        self.regions
+            .iter()
+            .find(|region| region.id == id)
+            .map(|region| region.handler.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{Finger, StylusButtons, WacomTool};
+    use crate::test_util::{moved, press, rect, release, touch};
+
+    fn pen(event: WacomEvent) -> InputEvent {
+        InputEvent::WacomEvent { event }
+    }
+
+    fn draw(x: f32, y: f32) -> InputEvent {
+        pen(WacomEvent::Draw {
+            position: Point2 { x, y },
+            pressure: 2000,
+            tilt: (0, 0).into(),
+            tool: WacomTool::Pen,
+            buttons: StylusButtons::default(),
+        })
+    }
+
+    fn finger(event: fn(Finger) -> MultitouchEvent, id: i32, x: u16, y: u16) -> InputEvent {
+        InputEvent::MultitouchEvent {
+            event: touch(event, id, x, y),
+        }
+    }
+
+    #[test]
+    fn test_input_router() {
+        let mut router = InputRouter::new();
+        let canvas = router.add_region(rect(0, 0, 100, 100), 0, |_, _| {});
+        let button = router.add_region(rect(10, 10, 20, 20), 1, |_, _| {});
+        // Added later, but below
+        let circle = router.add_custom_region(
+            |pos| (pos.x - 50.0).powi(2) + (pos.y - 50.0).powi(2) < 100.0,
+            -1,
+            |_, _| {},
+        );
+        assert_eq!(router.region_at(Point2 { x: 15.0, y: 15.0 }), Some(button));
+        assert_eq!(router.region_at(Point2 { x: 50.0, y: 50.0 }), Some(canvas));
+        assert_eq!(router.region_at(Point2 { x: 150.0, y: 50.0 }), None);
+        assert!(router.remove(canvas));
+        assert_eq!(router.region_at(Point2 { x: 50.0, y: 50.0 }), Some(circle));
+        let canvas = router.add_region(rect(0, 0, 100, 100), 0, |_, _| {});
+
+        // A stroke starting on the canvas stays there, across the button
+        assert_eq!(router.route(&draw(50.0, 50.0)), Some(canvas));
+        assert_eq!(router.route(&draw(15.0, 15.0)), Some(canvas));
+        assert_eq!(router.route(&draw(500.0, 15.0)), Some(canvas));
+        let lift = pen(WacomEvent::InstrumentChange {
+            pen: WacomPen::Touch,
+            state: false,
+        });
+        assert_eq!(router.route(&lift), Some(canvas));
+        assert_eq!(router.route(&draw(15.0, 15.0)), Some(button));
+        router.route(&lift);
+
+        // So do fingers, each on its own
+        assert_eq!(router.route(&finger(press, 1, 15, 15)), Some(button));
+        assert_eq!(router.route(&finger(press, 2, 200, 200)), None);
+        assert_eq!(router.route(&finger(moved, 1, 60, 60)), Some(button));
+        assert_eq!(router.route(&finger(moved, 2, 60, 60)), None);
+        assert_eq!(router.route(&finger(release, 1, 60, 60)), Some(button));
+        assert_eq!(router.route(&finger(release, 2, 60, 60)), None);
+
+        // Input held by a removed region goes to the callback
+        assert_eq!(router.route(&finger(press, 3, 15, 15)), Some(button));
+        router.remove(button);
+        assert_eq!(router.route(&finger(moved, 3, 15, 15)), None);
+        assert!(router.set_rect(canvas, rect(0, 0, 10, 10)));
+        assert_eq!(router.route(&finger(press, 4, 5, 5)), Some(canvas));
+    }
+}
@@ -0,0 +1,212 @@
+//! A standard place for apps to keep user preferences.
+//!
+//! An app defines its own settings type, e.g. with the orientation, theme and
+//! pressure curve the user picked, deriving `Serialize`, `Deserialize` and
+//! `Default`. `Settings::open` loads it from `settings.json` in the app's data
+//! directory, see `data_dir`, falling back to the defaults. Changes made with
+//! `Settings::update` are written back right away and passed to the watchers, as are
+//! changes to the file by someone else, once `Settings::reload` notices them.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Name of the settings file in the data directory
+pub const FILE_NAME: &str = "settings.json";
+
+/// Why loading or saving settings failed
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("Failed to access the settings file")]
+    Io(#[from] io::Error),
+    #[error("Malformed settings file")]
+    Json(#[from] serde_json::Error),
+    #[error("Unable to find a data directory, neither XDG_DATA_HOME nor HOME is set")]
+    NoDataDir,
+}
+
+/// Where the app called `app` keeps its data: `$XDG_DATA_HOME/<app>`, or
+/// `~/.local/share/<app>` like xochitl, which is `/home/root/.local/share/<app>` on
+/// the device
+pub fn data_dir(app: &str) -> Result<PathBuf, SettingsError> {
+    let base = match (std::env::var_os("XDG_DATA_HOME"), std::env::var_os("HOME")) {
+        (Some(data), _) if !data.is_empty() => PathBuf::from(data),
+        (_, Some(home)) if !home.is_empty() => Path::new(&home).join(".local/share"),
+        _ => return Err(SettingsError::NoDataDir),
+    };
+    Ok(base.join(app))
+}
+
+type Watcher<T> = Box<dyn FnMut(&T) + Send>;
+
+/// The settings of type `T`, kept in sync with a file
+pub struct Settings<T> {
+    path: PathBuf,
+    value: T,
+    /// Of the file when it was last read or written
+    modified: Option<SystemTime>,
+    watchers: Vec<Watcher<T>>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> Settings<T> {
+    /// The settings of the app called `app`, see `data_dir`
+    pub fn open(app: &str) -> Result<Settings<T>, SettingsError> {
+        Settings::open_file(data_dir(app)?.join(FILE_NAME))
+    }
+
+    /// The settings in the file at `path`, or the defaults if there is none yet. The
+    /// file is only created once they change.
+    pub fn open_file(path: impl AsRef<Path>) -> Result<Settings<T>, SettingsError> {
+        let path = path.as_ref().to_path_buf();
+        let (value, modified) = match Settings::read(&path)? {
+            Some((value, modified)) => (value, modified),
+            None => (T::default(), None),
+        };
+        Ok(Settings {
+            path,
+            value,
+            modified,
+            watchers: Vec::new(),
+        })
+    }
+
+    fn read(path: &Path) -> Result<Option<(T, Option<SystemTime>)>, SettingsError> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        Ok(Some((serde_json::from_slice(&data)?, modified)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Calls `watcher` with the new settings after every change
+    pub fn watch(&mut self, watcher: impl FnMut(&T) + Send + 'static) {
+        self.watchers.push(Box::new(watcher));
+    }
+
+    fn notify(&mut self) {
+        for watcher in &mut self.watchers {
+            watcher(&self.value);
+        }
+    }
+
+    /// Changes the settings with `change`, saves them and notifies the watchers. The
+    /// change is kept even if saving fails.
+    pub fn update(&mut self, change: impl FnOnce(&mut T)) -> Result<(), SettingsError> {
+        change(&mut self.value);
+        let saved = self.save();
+        self.notify();
+        saved
+    }
+
+    /// Writes the settings to the file, creating its directory if needed
+    pub fn save(&mut self) -> Result<(), SettingsError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec_pretty(&self.value)?;
+        // Written next to it and renamed, so a crash never leaves half of it
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, data)?;
+        fs::rename(&partial, &self.path)?;
+        self.modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+
+    /// Reads the file again if it was modified since it was last read or written,
+    /// notifying the watchers. Returns whether it was. A removed file keeps the
+    /// current settings.
+    pub fn reload(&mut self) -> Result<bool, SettingsError> {
+        let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => Some(modified),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        if modified == self.modified {
+            return Ok(false);
+        }
+        match Settings::read(&self.path)? {
+            Some((value, modified)) => {
+                self.value = value;
+                self.modified = modified;
+                self.notify();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(default)]
+    struct Preferences {
+        orientation: String,
+        dark: bool,
+        pressure_curve: [f32; 2],
+    }
+
+    impl Default for Preferences {
+        fn default() -> Preferences {
+            Preferences {
+                orientation: "portrait".to_owned(),
+                dark: false,
+                pressure_curve: [0.0, 1.0],
+            }
+        }
+    }
+
+    #[test]
+    fn test_settings() {
+        let dir =
+            std::env::temp_dir().join(format!("libremarkable-settings-{}", std::process::id()));
+        let path = dir.join("app").join(FILE_NAME);
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut settings = Settings::<Preferences>::open_file(&path).unwrap();
+        assert_eq!(settings.get(), &Preferences::default());
+        assert!(!path.exists());
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let watched = seen.clone();
+        settings.watch(move |prefs: &Preferences| watched.lock().unwrap().push(prefs.dark));
+        settings.update(|prefs| prefs.dark = true).unwrap();
+        assert!(!settings.reload().unwrap());
+
+        let reopened = Settings::<Preferences>::open_file(&path).unwrap();
+        assert_eq!(reopened.get(), settings.get());
+
+        // Changed by someone else, with a field missing
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(&path, r#"{ "orientation": "landscape" }"#).unwrap();
+        assert!(settings.reload().unwrap());
+        assert_eq!(settings.get().orientation, "landscape");
+        assert!(!settings.get().dark);
+        assert_eq!(*seen.lock().unwrap(), [true, false]);
+
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            Settings::<Preferences>::open_file(&path),
+            Err(SettingsError::Json(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1097 @@
+//! Deterministic simulation of `ApplicationContext` applications.
+//!
+//! A `Simulation` runs an application on a headless framebuffer against a virtual
+//! clock. Input events, timers and checkpoints are scheduled at virtual times and
+//! processed strictly in order, refreshes complete immediately, and the screen is
+//! captured at every checkpoint. The same script therefore always produces the same
+//! frames, which can be compared against reference images with
+//! `framebuffer::golden`:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use libremarkable::sim::Simulation;
+//! # use libremarkable::input::{Finger, InputEvent, MultitouchEvent};
+//! let mut sim = Simulation::new(1404, 1872);
+//! let finger = Finger::new(1, (100, 200).into(), true);
+//! sim.input_at(
+//!     Duration::from_millis(10),
+//!     InputEvent::MultitouchEvent {
+//!         event: MultitouchEvent::Press { finger },
+//!     },
+//! );
+//! sim.checkpoint_at(Duration::from_millis(20), "pressed");
+//! let frames = sim.run(|app, event| { /* the application's input handler */ });
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::appctx::ApplicationContext;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::mxcfb::mxcfb_update_data;
+use crate::framebuffer::FramebufferIO;
+use crate::input::InputEvent;
+
+/// Time as seen by a simulated application. Clones share the same time.
+#[derive(Clone, Default)]
+pub struct VirtualClock(Arc<Mutex<Duration>>);
+
+impl VirtualClock {
+    /// Time elapsed since the start of the simulation
+    pub fn now(&self) -> Duration {
+        *self.0.lock().unwrap()
+    }
+
+    fn set(&self, now: Duration) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+/// A function run at a virtual time
+pub type Timer = Box<dyn FnOnce(&mut ApplicationContext<'_>) + Send>;
+
+enum Action {
+    Input(InputEvent),
+    Timer(Timer),
+    Checkpoint(String),
+}
+
+struct Scheduled {
+    at: Duration,
+    seq: u64,
+    action: Action,
+}
+
+// Ordered so that `BinaryHeap` pops the earliest action first, and actions scheduled
+// for the same time in the order they were scheduled.
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+#[derive(Default)]
+struct Queue {
+    heap: BinaryHeap<Scheduled>,
+    seq: u64,
+}
+
+/// Handle to schedule actions into a running simulation, for example from timers or
+/// the input handler. Times in the past are processed right away, in order.
+#[derive(Clone)]
+pub struct Scheduler {
+    clock: VirtualClock,
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl Scheduler {
+    fn push(&self, at: Duration, action: Action) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.seq += 1;
+        let seq = queue.seq;
+        queue.heap.push(Scheduled { at, seq, action });
+    }
+
+    fn pop_until(&self, until: Option<Duration>) -> Option<Scheduled> {
+        let mut queue = self.queue.lock().unwrap();
+        match (queue.heap.peek(), until) {
+            (Some(next), Some(until)) if next.at > until => None,
+            _ => queue.heap.pop(),
+        }
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Delivers `event` to the application at `at`
+    pub fn input_at(&self, at: Duration, event: InputEvent) {
+        self.push(at, Action::Input(event));
+    }
+
+    /// Delivers timed events, e.g. a recorded input script, to the application
+    pub fn script(&self, events: impl IntoIterator<Item = (Duration, InputEvent)>) {
+        for (at, event) in events {
+            self.input_at(at, event);
+        }
+    }
+
+    /// Runs `timer` at `at`
+    pub fn timer_at<T>(&self, at: Duration, timer: T)
+    where
+        T: FnOnce(&mut ApplicationContext<'_>) + Send + 'static,
+    {
+        self.push(at, Action::Timer(Box::new(timer)));
+    }
+
+    /// Runs `timer` once `delay` has passed on the virtual clock
+    pub fn timer_after<T>(&self, delay: Duration, timer: T)
+    where
+        T: FnOnce(&mut ApplicationContext<'_>) + Send + 'static,
+    {
+        self.timer_at(self.clock.now() + delay, timer);
+    }
+
+    /// Captures the screen at `at` as a frame called `name`
+    pub fn checkpoint_at(&self, at: Duration, name: &str) {
+        self.push(at, Action::Checkpoint(name.to_owned()));
+    }
+}
+
+/// The screen contents at a checkpoint
+pub struct Frame {
+    pub name: String,
+    pub at: Duration,
+    pub width: u32,
+    pub height: u32,
+    /// Of the framebuffer's native format
+    pub bits_per_pixel: u32,
+    /// The whole screen in the framebuffer's native format
+    pub pixels: Vec<u8>,
+    /// Refreshes requested since the previous checkpoint
+    pub refreshes: Vec<mxcfb_update_data>,
+}
+
+impl Frame {
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> image::RgbImage {
+        crate::framebuffer::golden::from_native(
+            self.width,
+            self.height,
+            self.bits_per_pixel,
+            &self.pixels,
+        )
+    }
+}
+
+pub struct Simulation {
+    app: ApplicationContext<'static>,
+    scheduler: Scheduler,
+    frames: Vec<Frame>,
+}
+
+impl Simulation {
+    /// Simulates an application on a headless screen of the given size
+    pub fn new(width: u32, height: u32) -> Simulation {
+        Simulation::with_context(ApplicationContext::headless(width, height))
+    }
+
+    /// Simulates an application around an existing context. Its framebuffer should be
+    /// headless for refreshes to complete deterministically.
+    pub fn with_context(app: ApplicationContext<'static>) -> Simulation {
+        Simulation {
+            app,
+            scheduler: Scheduler {
+                clock: VirtualClock::default(),
+                queue: Arc::default(),
+            },
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn app(&mut self) -> &mut ApplicationContext<'static> {
+        &mut self.app
+    }
+
+    /// A handle to schedule further actions, e.g. from inside timers
+    pub fn scheduler(&self) -> Scheduler {
+        self.scheduler.clone()
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        &self.scheduler.clock
+    }
+
+    pub fn input_at(&self, at: Duration, event: InputEvent) {
+        self.scheduler.input_at(at, event);
+    }
+
+    pub fn script(&self, events: impl IntoIterator<Item = (Duration, InputEvent)>) {
+        self.scheduler.script(events);
+    }
+
+    pub fn timer_at<T>(&self, at: Duration, timer: T)
+    where
+        T: FnOnce(&mut ApplicationContext<'_>) + Send + 'static,
+    {
+        self.scheduler.timer_at(at, timer);
+    }
+
+    pub fn checkpoint_at(&self, at: Duration, name: &str) {
+        self.scheduler.checkpoint_at(at, name);
+    }
+
+    /// Frames captured so far
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn frame(&self, name: &str) -> Option<&Frame> {
+        self.frames.iter().find(|frame| frame.name == name)
+    }
+
+    /// Processes everything scheduled, passing input events to `callback` like
+    /// `ApplicationContext::start_event_loop` does.
+    pub fn run<F: FnMut(&mut ApplicationContext<'_>, InputEvent)>(
+        &mut self,
+        callback: F,
+    ) -> &[Frame] {
+        self.process(None, callback);
+        &self.frames
+    }
+
+    /// Processes everything scheduled up to and including `until`, then sets the clock
+    /// to `until`.
+    pub fn run_until<F: FnMut(&mut ApplicationContext<'_>, InputEvent)>(
+        &mut self,
+        until: Duration,
+        callback: F,
+    ) -> &[Frame] {
+        self.process(Some(until), callback);
+        if self.clock().now() < until {
+            self.clock().set(until);
+        }
+        &self.frames
+    }
+
+    fn process<F: FnMut(&mut ApplicationContext<'_>, InputEvent)>(
+        &mut self,
+        until: Option<Duration>,
+        mut callback: F,
+    ) {
+        while let Some(next) = self.scheduler.pop_until(until) {
+            // Never move time backwards when something was scheduled in the past
+            if next.at > self.clock().now() {
+                self.clock().set(next.at);
+            }
+            match next.action {
+                Action::Input(event) => self.app.dispatch_event(event, &mut callback),
+                Action::Timer(timer) => timer(&mut self.app),
+                Action::Checkpoint(name) => self.capture(name),
+            }
+        }
+    }
+
+    fn capture(&mut self, name: String) {
+        let fb = self.app.get_framebuffer_ref();
+        let (width, height) = (fb.var_screen_info.xres, fb.var_screen_info.yres);
+        let pixels = fb
+            .dump_region(mxcfb_rect {
+                top: 0,
+                left: 0,
+                width,
+                height,
+            })
+            .expect("the whole screen is always in bounds");
+        self.frames.push(Frame {
+            name,
+            at: self.clock().now(),
+            width,
+            height,
+            bits_per_pixel: fb.var_screen_info.bits_per_pixel,
+            pixels,
+            refreshes: fb.take_refreshes(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::Point2;
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::common::{display_temp, dither_mode, waveform_mode};
+    use crate::framebuffer::{FramebufferDraw, FramebufferRefresh, PartialRefreshMode};
+    use crate::input::{Finger, MultitouchEvent};
+    use crate::test_util;
+
+    fn press(x: u16, y: u16) -> InputEvent {
+        let finger = Finger::new(1, Point2 { x, y }, true);
+        InputEvent::MultitouchEvent {
+            event: MultitouchEvent::Press { finger },
+        }
+    }
+
+    fn pixel(frame: &Frame, x: u32, y: u32) -> &[u8] {
+        let bytespp = frame.bits_per_pixel / 8;
+        let i = ((y * frame.width + x) * bytespp) as usize;
+        &frame.pixels[i..i + bytespp as usize]
+    }
+
+    #[test]
+    fn test_deterministic_run() {
+        let mut sim = Simulation::new(40, 40);
+        let scheduler = sim.scheduler();
+        sim.input_at(Duration::from_millis(30), press(10, 10));
+        sim.checkpoint_at(Duration::from_millis(20), "before");
+        sim.checkpoint_at(Duration::from_millis(100), "after");
+
+        let frames = sim.run(move |app, event| {
+            if let InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Press { finger },
+            } = event
+            {
+                let fb = app.get_framebuffer_ref();
+                let rect = fb.fill_circle(finger.pos.cast().unwrap(), 2, color::BLACK);
+                fb.partial_refresh(
+                    &rect,
+                    PartialRefreshMode::Async,
+                    waveform_mode::WAVEFORM_MODE_DU,
+                    display_temp::TEMP_USE_REMARKABLE_DRAW,
+                    dither_mode::EPDC_FLAG_EXP1,
+                    0,
+                    false,
+                );
+                // Erased by a timer half a virtual second later
+                scheduler.timer_after(Duration::from_millis(500), move |app| {
+                    app.get_framebuffer_ref().fill_rect(
+                        Point2 { x: 0, y: 0 },
+                        (40, 40).into(),
+                        color::WHITE,
+                    );
+                });
+            }
+        });
+
+        assert_eq!(frames.len(), 2);
+        let white = pixel(&frames[0], 10, 10).to_vec();
+        assert_ne!(pixel(&frames[1], 10, 10), white);
+        assert_eq!(frames[1].at, Duration::from_millis(100));
+        assert_eq!(frames[0].refreshes.len(), 0);
+        assert_eq!(frames[1].refreshes.len(), 1);
+
+        sim.checkpoint_at(Duration::from_secs(1), "erased");
+        sim.run(|_, _| {});
+        assert_eq!(sim.clock().now(), Duration::from_secs(1));
+        assert_eq!(
+            pixel(sim.frame("erased").unwrap(), 10, 10),
+            white.as_slice()
+        );
+    }
+
+    #[cfg(feature = "stroke")]
+    #[test]
+    fn test_scratch_out_event() {
+        use crate::input::{WacomEvent, WacomPen};
+        use crate::stroke::scratch::ScratchOutDetector;
+
+        let mut sim = Simulation::new(400, 400);
+        sim.app()
+            .set_scratch_out_detector(Some(ScratchOutDetector::default()));
+        let draw = |x: f32, y: f32| InputEvent::WacomEvent {
+            event: WacomEvent::Draw {
+                position: Point2 { x, y },
+                pressure: 2000,
+                tilt: (0, 0).into(),
+                tool: Default::default(),
+                buttons: Default::default(),
+            },
+        };
+        let mut events = Vec::new();
+        for pass in 0..6 {
+            for step in 0..=10 {
+                let x = match pass % 2 {
+                    0 => 100.0 + step as f32 * 20.0,
+                    _ => 300.0 - step as f32 * 20.0,
+                };
+                events.push(draw(x, 100.0 + pass as f32 * 5.0 + step as f32 * 0.5));
+            }
+        }
+        events.push(InputEvent::WacomEvent {
+            event: WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: false,
+            },
+        });
+        sim.script(
+            events
+                .into_iter()
+                .enumerate()
+                .map(|(i, event)| (Duration::from_millis(i as u64 * 10), event)),
+        );
+
+        let mut erased = Vec::new();
+        sim.run(|_, event| {
+            if let InputEvent::EraseRegion { position, size } = event {
+                erased.push(mxcfb_rect::from(position, size));
+            }
+        });
+        assert_eq!(erased.len(), 1);
+        assert!(erased[0].contains_point(&Point2 { x: 200, y: 110 }));
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    #[test]
+    fn test_focus() {
+        use crate::framebuffer::cgmath::Vector2;
+        use crate::input::{KeyboardEvent, Modifiers};
+        use crate::ui_extensions::element::{UIElement, UIElementHandle, UIElementWrapper};
+        use crate::ui_extensions::text_input::TextInput;
+        use evdev::Key;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static BLURRED: AtomicUsize = AtomicUsize::new(0);
+        fn on_blur(_: &mut ApplicationContext<'_>, _: UIElementHandle) {
+            BLURRED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut sim = Simulation::new(400, 400);
+        for (name, y) in [("first", 10), ("second", 100)] {
+            sim.app().add_element(
+                name,
+                UIElementWrapper {
+                    position: Point2 { x: 10, y },
+                    onblur: Some(on_blur),
+                    inner: UIElement::TextInput {
+                        input: TextInput::new(Vector2 { x: 300, y: 60 }, 30.0),
+                    },
+                    ..Default::default()
+                },
+            );
+        }
+        sim.app().draw_elements();
+        assert_eq!(sim.app().focus(), None);
+        assert!(!sim.app().set_focus(Some("missing")));
+
+        let text = |character| InputEvent::Keyboard {
+            event: KeyboardEvent::Text { character },
+        };
+        let tab = InputEvent::Keyboard {
+            event: KeyboardEvent::Press {
+                key: Key::KEY_TAB.code(),
+                modifiers: Modifiers::default(),
+                repeat: false,
+            },
+        };
+        sim.script([
+            (Duration::from_millis(10), press(50, 130)),
+            (Duration::from_millis(20), text('a')),
+            (Duration::from_millis(30), tab),
+            (Duration::from_millis(40), text('b')),
+        ]);
+        // Typing into the focused field uses up the keyboard events
+        let mut passed = Vec::new();
+        sim.run(|_, event| {
+            if let InputEvent::Keyboard { event } = event {
+                passed.push(event);
+            }
+        });
+        assert!(passed.is_empty(), "{:?}", passed);
+        assert_eq!(sim.app().focus(), Some("first"));
+        assert_eq!(BLURRED.load(Ordering::Relaxed), 1);
+
+        let value = |app: &mut ApplicationContext<'_>, name| match app
+            .get_element_by_name(name)
+            .unwrap()
+            .read()
+            .inner
+        {
+            UIElement::TextInput { ref input } => input.text().to_owned(),
+            _ => unreachable!(),
+        };
+        assert_eq!(value(sim.app(), "first"), "b");
+        assert_eq!(value(sim.app(), "second"), "a");
+
+        sim.app().remove_element("first");
+        assert_eq!(sim.app().focus(), None);
+        assert!(!sim
+            .app()
+            .type_into_focus(&KeyboardEvent::Text { character: 'c' }));
+    }
+
+    #[test]
+    fn test_scroll_view() {
+        use crate::framebuffer::cgmath::Vector2;
+        use crate::ui_extensions::element::{UIElement, UIElementWrapper};
+        use crate::ui_extensions::scroll_view::ScrollView;
+
+        let mut sim = Simulation::new(400, 400);
+        let mut view = ScrollView::new(Vector2 { x: 200, y: 200 }, Vector2 { x: 200, y: 1000 });
+        // Redrawn on every move and without momentum, as this runs in no time
+        view.frame = Duration::ZERO;
+        view.min_velocity = f32::INFINITY;
+        sim.app().add_element(
+            "view",
+            UIElementWrapper {
+                position: Point2 { x: 100, y: 100 },
+                inner: UIElement::ScrollView { view },
+                ..Default::default()
+            },
+        );
+        for (name, y) in [("top", 10), ("bottom", 300)] {
+            sim.app().add_element(
+                name,
+                UIElementWrapper {
+                    inner: UIElement::Region {
+                        size: Vector2 { x: 50, y: 50 },
+                        border_color: color::BLACK,
+                        border_px: 2,
+                    },
+                    ..Default::default()
+                },
+            );
+            assert!(sim
+                .app()
+                .add_scroll_child("view", name, Point2 { x: 10, y }));
+        }
+        sim.app().draw_elements();
+        let drawn = |app: &mut ApplicationContext<'_>, name| {
+            app.get_element_by_name(name)
+                .unwrap()
+                .read()
+                .last_drawn_rect
+        };
+        assert_eq!(
+            drawn(sim.app(), "top"),
+            Some(mxcfb_rect::from(
+                Point2 { x: 110, y: 110 },
+                Vector2 { x: 50, y: 50 }
+            ))
+        );
+        assert_eq!(drawn(sim.app(), "bottom"), None);
+
+        let touch = |event: fn(Finger) -> MultitouchEvent, y| InputEvent::MultitouchEvent {
+            event: test_util::touch(event, 1, 150, y),
+        };
+        sim.script([
+            (Duration::from_millis(0), touch(test_util::press, 290)),
+            (Duration::from_millis(10), touch(test_util::moved, 200)),
+            (Duration::from_millis(1000), touch(test_util::moved, 120)),
+            (Duration::from_millis(2000), touch(test_util::release, 120)),
+        ]);
+        sim.run(|_, _| {});
+        // Scrolled by 170, the top one is out of view and the bottom one shows
+        assert_eq!(drawn(sim.app(), "top"), None);
+        assert_eq!(
+            drawn(sim.app(), "bottom"),
+            Some(mxcfb_rect::from(
+                Point2 { x: 110, y: 230 },
+                Vector2 { x: 50, y: 50 }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_list_view() {
+        use crate::framebuffer::cgmath::Vector2;
+        use crate::ui_extensions::element::{UIElement, UIElementHandle, UIElementWrapper};
+        use crate::ui_extensions::list_view::ListView;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static SELECTED: AtomicUsize = AtomicUsize::new(0);
+        fn select(_: &mut ApplicationContext<'_>, _: UIElementHandle, index: usize) {
+            SELECTED.store(index, Ordering::Relaxed);
+        }
+
+        let mut sim = Simulation::new(400, 400);
+        let mut list = ListView::new(Vector2 { x: 200, y: 200 }, 5000, 50, |_, row| {
+            row.inner = UIElement::Region {
+                size: Vector2 { x: 100, y: 40 },
+                border_color: color::BLACK,
+                border_px: 2,
+            };
+        });
+        list.on_select = Some(select);
+        list.scroll_to_index(1000);
+        sim.app().add_element(
+            "list",
+            UIElementWrapper {
+                position: Point2 { x: 100, y: 100 },
+                inner: UIElement::ListView { list },
+                ..Default::default()
+            },
+        );
+        sim.app().draw_elements();
+
+        let touch = |event: fn(Finger) -> MultitouchEvent| InputEvent::MultitouchEvent {
+            event: test_util::touch(event, 1, 150, 220),
+        };
+        sim.script([
+            (Duration::from_millis(0), touch(test_util::press)),
+            (Duration::from_millis(50), touch(test_util::release)),
+        ]);
+        sim.run(|_, _| {});
+        // The third row in view
+        assert_eq!(SELECTED.load(Ordering::Relaxed), 1002);
+    }
+
+    #[test]
+    fn test_controls() {
+        use crate::framebuffer::cgmath::Vector2;
+        use crate::ui_extensions::element::{
+            Control, UIElement, UIElementHandle, UIElementWrapper,
+        };
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CHANGES: AtomicUsize = AtomicUsize::new(0);
+        fn changed(_: &mut ApplicationContext<'_>, _: UIElementHandle, checked: bool) {
+            CHANGES.fetch_add(if checked { 10 } else { 1 }, Ordering::Relaxed);
+        }
+
+        let mut sim = Simulation::new(400, 400);
+        for (name, y) in [("pen", 10), ("marker", 70)] {
+            let mut radio = Control::radio(name, "tool", Vector2 { x: 200, y: 50 });
+            radio.checked = name == "pen";
+            radio.on_change = Some(changed);
+            sim.app().add_element(
+                name,
+                UIElementWrapper {
+                    position: Point2 { x: 10, y },
+                    inner: UIElement::Control { control: radio },
+                    ..Default::default()
+                },
+            );
+        }
+        sim.app().draw_elements();
+        let touch = |event: fn(Finger) -> MultitouchEvent| InputEvent::MultitouchEvent {
+            event: test_util::touch(event, 1, 50, 90),
+        };
+        sim.script([
+            (Duration::from_millis(0), touch(test_util::press)),
+            (Duration::from_millis(50), touch(test_util::release)),
+        ]);
+        sim.run(|_, _| {});
+        let checked = |app: &mut ApplicationContext<'_>, name| match app
+            .get_element_by_name(name)
+            .unwrap()
+            .read()
+            .inner
+        {
+            UIElement::Control { ref control } => control.checked,
+            _ => unreachable!(),
+        };
+        assert!(checked(sim.app(), "marker"));
+        assert!(!checked(sim.app(), "pen"));
+        // Checking one and unchecking the other
+        assert_eq!(CHANGES.load(Ordering::Relaxed), 11);
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    #[test]
+    fn test_dialog() {
+        use crate::ui_extensions::dialog::DialogSpec;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static ANSWER: AtomicUsize = AtomicUsize::new(0);
+        fn answered(_: &mut ApplicationContext<'_>, index: usize) {
+            ANSWER.store(index + 1, Ordering::Relaxed);
+        }
+
+        let mut sim = Simulation::new(600, 600);
+        let spec = DialogSpec::new("Quit?", "Unsaved changes are lost.")
+            .button("Stay")
+            .button("Quit")
+            .on_result(answered);
+        let result = sim.app().show_dialog(spec);
+        let tap = |event: fn(Finger) -> MultitouchEvent, x, y| InputEvent::MultitouchEvent {
+            event: test_util::touch(event, 1, x, y),
+        };
+        // The first tap misses the dialog, and doesn't get to the app either
+        let (x, y) = (450, 400);
+        sim.script([
+            (Duration::from_millis(0), tap(test_util::press, 5, 5)),
+            (Duration::from_millis(10), tap(test_util::release, 5, 5)),
+            (Duration::from_millis(100), tap(test_util::press, x, y)),
+            (Duration::from_millis(110), tap(test_util::release, x, y)),
+        ]);
+        let reached = Arc::new(Mutex::new(0));
+        let counted = reached.clone();
+        sim.run(move |_, _| *counted.lock().unwrap() += 1);
+        assert_eq!(*reached.lock().unwrap(), 0);
+        assert_eq!(result.result(), Some(1));
+        assert_eq!(ANSWER.load(Ordering::Relaxed), 2);
+        // What it covered is back
+        let fb = sim.app().get_framebuffer_ref();
+        assert_eq!(
+            fb.read_pixel(Point2 { x: 300, y: 300 }).as_native(),
+            color::WHITE.as_native()
+        );
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    #[test]
+    fn test_menus() {
+        use crate::input::{GestureEvent, KeyboardEvent, Modifiers};
+        use crate::ui_extensions::element::{UIElement, UIElementWrapper};
+        use crate::ui_extensions::menu::{Menu, MenuBar};
+        use evdev::Key;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static PICKED: AtomicUsize = AtomicUsize::new(0);
+        fn open(_: &mut ApplicationContext<'_>) {
+            PICKED.store(1, Ordering::Relaxed);
+        }
+        fn copy(_: &mut ApplicationContext<'_>) {
+            PICKED.store(2, Ordering::Relaxed);
+        }
+        fn delete(_: &mut ApplicationContext<'_>) {
+            PICKED.store(3, Ordering::Relaxed);
+        }
+
+        let mut sim = Simulation::new(600, 600);
+        let bar = MenuBar::new((600, 60).into())
+            .menu(
+                "File",
+                Menu::new().disabled("Save").separator().item("Open", open),
+            )
+            .menu("Edit", Menu::new().item("Copy", copy));
+        sim.app().add_element(
+            "bar",
+            UIElementWrapper {
+                inner: UIElement::MenuBar { bar },
+                ..Default::default()
+            },
+        );
+        sim.app()
+            .set_context_menu("bar", Some(Menu::new().item("Delete", delete)));
+        sim.app().draw_elements();
+
+        let touch = |event: fn(Finger) -> MultitouchEvent, y| InputEvent::MultitouchEvent {
+            event: test_util::touch(event, 1, 30, y),
+        };
+        let key = |key: Key| InputEvent::Keyboard {
+            event: KeyboardEvent::Press {
+                key: key.code(),
+                modifiers: Modifiers::default(),
+                repeat: false,
+            },
+        };
+        let reached = Arc::new(Mutex::new(0));
+        let counted = reached.clone();
+        let run = |sim: &mut Simulation, events: Vec<InputEvent>| {
+            sim.script(
+                events
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, event)| (Duration::from_millis(i as u64 * 10), event)),
+            );
+            let counted = counted.clone();
+            sim.run(move |_, _| *counted.lock().unwrap() += 1);
+        };
+
+        // Pressing "File" opens it, sliding onto the disabled item and lifting there
+        // doesn't pick anything
+        run(
+            &mut sim,
+            vec![
+                touch(test_util::press, 30),
+                touch(test_util::moved, 80),
+                touch(test_util::release, 80),
+            ],
+        );
+        assert!(sim.app().shown_menu().is_some());
+        assert_eq!(PICKED.load(Ordering::Relaxed), 0);
+        let pixel = |sim: &mut Simulation, x, y| {
+            let fb = sim.app().get_framebuffer_ref();
+            fb.read_pixel(Point2 { x, y }).as_native()
+        };
+        // Down skips the separator, right goes to "Edit", enter picks "Copy"
+        run(&mut sim, vec![key(Key::KEY_DOWN)]);
+        // Below the bar, "Save" and the separator
+        assert_eq!(pixel(&mut sim, 10, 140), color::BLACK.as_native());
+        run(&mut sim, vec![key(Key::KEY_RIGHT), key(Key::KEY_ENTER)]);
+        assert!(sim.app().shown_menu().is_none());
+        assert_eq!(PICKED.load(Ordering::Relaxed), 2);
+
+        // A long press on the bar opens its context menu, a tap elsewhere closes it
+        let long_press = InputEvent::Gesture {
+            event: GestureEvent::LongPress {
+                position: (300.0, 30.0).into(),
+            },
+        };
+        run(&mut sim, vec![long_press]);
+        assert!(sim.app().shown_menu().is_some());
+        // Its border, below and to the right of the finger
+        assert_eq!(pixel(&mut sim, 301, 31), color::BLACK.as_native());
+        run(
+            &mut sim,
+            vec![touch(test_util::press, 500), touch(test_util::release, 500)],
+        );
+        assert!(sim.app().shown_menu().is_none());
+        assert_eq!(PICKED.load(Ordering::Relaxed), 2);
+        // Only the release after the menu closed got to the app
+        assert_eq!(*reached.lock().unwrap(), 1);
+        assert_eq!(pixel(&mut sim, 301, 100), color::WHITE.as_native());
+    }
+
+    #[test]
+    fn test_navigator() {
+        use crate::framebuffer::cgmath::Vector2;
+        use crate::ui_extensions::element::{UIElement, UIElementHandle, UIElementWrapper};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLICKS: AtomicUsize = AtomicUsize::new(0);
+        static ROUTED: AtomicUsize = AtomicUsize::new(0);
+        fn clicked(_: &mut ApplicationContext<'_>, _: UIElementHandle) {
+            CLICKS.fetch_add(1, Ordering::Relaxed);
+        }
+        fn region(x: i32, y: i32) -> UIElementWrapper {
+            UIElementWrapper {
+                position: Point2 { x, y },
+                inner: UIElement::Region {
+                    size: Vector2 { x: 50, y: 50 },
+                    border_color: color::BLACK,
+                    border_px: 2,
+                },
+                ..Default::default()
+            }
+        }
+        fn home(app: &mut ApplicationContext<'_>) {
+            let button = UIElementWrapper {
+                onclick: Some(clicked),
+                ..region(10, 10)
+            };
+            app.add_element("button", button);
+            let bottom = mxcfb_rect::from(Point2 { x: 0, y: 300 }, (400, 100).into());
+            app.input_router().add_region(bottom, 0, |_, _| {
+                ROUTED.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        fn detail(app: &mut ApplicationContext<'_>) {
+            app.add_element("panel", region(200, 200));
+        }
+        fn settings(app: &mut ApplicationContext<'_>) {
+            app.add_element("panel", region(100, 100));
+        }
+
+        let mut sim = Simulation::new(400, 400);
+        let pixel = |sim: &mut Simulation, x, y| {
+            let fb = sim.app().get_framebuffer_ref();
+            fb.read_pixel(Point2 { x, y }).as_native()
+        };
+        // Each a new gesture, for the active regions to notice
+        let taps = std::cell::Cell::new(1);
+        let tap = |sim: &mut Simulation, y| {
+            taps.set(taps.get() + 1);
+            let finger = Finger::new(taps.get(), Point2 { x: 30, y }, true);
+            let event = InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Press { finger },
+            };
+            let at = sim.clock().now();
+            sim.script([(at, event)]);
+            let reached = Arc::new(Mutex::new(0));
+            let counted = reached.clone();
+            sim.run(move |_, _| *counted.lock().unwrap() += 1);
+            let reached = *reached.lock().unwrap();
+            reached
+        };
+        sim.app().switch_tab("home", home);
+        sim.app().push_screen("detail", detail);
+        let navigator = sim.app().navigator();
+        assert_eq!(navigator.current(), Some("detail"));
+        assert_eq!(navigator.below().collect::<Vec<_>>(), [Some("home")]);
+        assert!(sim.app().get_element_by_name("button").is_none());
+        assert_eq!(pixel(&mut sim, 10, 10), color::WHITE.as_native());
+        assert_eq!(pixel(&mut sim, 200, 200), color::BLACK.as_native());
+        // Nothing of the screen below takes input
+        assert_eq!(tap(&mut sim, 30), 1);
+        assert_eq!(tap(&mut sim, 350), 1);
+        assert_eq!(CLICKS.load(Ordering::Relaxed), 0);
+        assert_eq!(ROUTED.load(Ordering::Relaxed), 0);
+
+        sim.app().get_framebuffer_ref().take_refreshes();
+        assert!(sim.app().pop_screen());
+        assert!(!sim.app().pop_screen());
+        assert!(sim.app().get_element_by_name("panel").is_none());
+        assert_eq!(pixel(&mut sim, 10, 10), color::BLACK.as_native());
+        assert_eq!(pixel(&mut sim, 200, 200), color::WHITE.as_native());
+        let fb = sim.app().get_framebuffer_ref();
+        let refreshes = fb.take_refreshes();
+        assert_eq!(refreshes.len(), 1);
+        assert_eq!(refreshes[0].update_region, sim.app().screen_rect());
+        tap(&mut sim, 30);
+        assert_eq!(CLICKS.load(Ordering::Relaxed), 1);
+        assert_eq!(tap(&mut sim, 350), 0);
+        assert_eq!(ROUTED.load(Ordering::Relaxed), 1);
+
+        // Tabs come back as they were left, without being built again
+        sim.app().push_screen("detail", detail);
+        sim.app().switch_tab("settings", settings);
+        assert_eq!(pixel(&mut sim, 100, 100), color::BLACK.as_native());
+        assert!(!sim.app().navigator().can_pop());
+        sim.app().switch_tab("home", |_| unreachable!());
+        assert_eq!(sim.app().navigator().current(), Some("detail"));
+        assert!(sim.app().navigator().has_tab("settings"));
+        assert_eq!(pixel(&mut sim, 200, 200), color::BLACK.as_native());
+        assert!(sim.app().pop_screen());
+        assert_eq!(sim.app().navigator().current(), Some("home"));
+    }
+
+    #[test]
+    fn test_navigator_first_tab() {
+        use crate::framebuffer::cgmath::Vector2;
+        use crate::ui_extensions::element::{UIElement, UIElementWrapper};
+
+        fn region(app: &mut ApplicationContext<'_>, name: &str) {
+            let element = UIElementWrapper {
+                inner: UIElement::Region {
+                    size: Vector2 { x: 50, y: 50 },
+                    border_color: color::BLACK,
+                    border_px: 2,
+                },
+                ..Default::default()
+            };
+            app.add_element(name, element);
+        }
+
+        let mut sim = Simulation::new(200, 200);
+        region(sim.app(), "splash");
+        sim.app()
+            .push_screen("welcome", |app| region(app, "welcome"));
+        assert_eq!(sim.app().navigator().current(), Some("welcome"));
+        assert_eq!(sim.app().navigator().below().collect::<Vec<_>>(), [None]);
+        assert_eq!(sim.app().navigator().tab(), None);
+
+        // The screens shown before the first tab don't belong to any
+        sim.app().switch_tab("home", |app| region(app, "home"));
+        let navigator = sim.app().navigator();
+        assert_eq!(navigator.tab(), Some("home"));
+        assert_eq!(navigator.current(), Some("home"));
+        assert!(!navigator.can_pop());
+        assert!(!sim.app().pop_screen());
+        assert!(sim.app().get_element_by_name("welcome").is_none());
+        assert!(sim.app().get_element_by_name("splash").is_none());
+
+        // Nor come back with it
+        sim.app()
+            .switch_tab("settings", |app| region(app, "settings"));
+        assert!(sim.app().navigator().has_tab("home"));
+        assert!(!sim.app().navigator().has_tab("welcome"));
+        sim.app().switch_tab("home", |_| unreachable!());
+        assert!(!sim.app().navigator().can_pop());
+        assert!(sim.app().get_element_by_name("home").is_some());
+        assert!(sim.app().get_element_by_name("splash").is_none());
+    }
+
+    #[cfg(feature = "stroke")]
+    #[test]
+    fn test_canvas() {
+        use crate::framebuffer::cgmath::Vector2;
+        use crate::framebuffer::FramebufferIO;
+        use crate::input::{WacomEvent, WacomPen};
+        use crate::ui_extensions::canvas::CanvasWidget;
+        use crate::ui_extensions::element::{UIElement, UIElementHandle, UIElementWrapper};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CHANGES: AtomicUsize = AtomicUsize::new(0);
+        fn changed(_: &mut ApplicationContext<'_>, _: UIElementHandle) {
+            CHANGES.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut sim = Simulation::new(300, 300);
+        let mut canvas = CanvasWidget::new(Vector2 { x: 200, y: 200 });
+        canvas.width = 8.0;
+        canvas.on_change = Some(changed);
+        sim.app().add_element(
+            "canvas",
+            UIElementWrapper {
+                position: Point2 { x: 50, y: 50 },
+                inner: UIElement::Canvas { canvas },
+                ..Default::default()
+            },
+        );
+        sim.app().draw_elements();
+        let draw = |x: f32, y: f32| InputEvent::WacomEvent {
+            event: WacomEvent::Draw {
+                position: Point2 { x, y },
+                pressure: 2000,
+                tilt: (0, 0).into(),
+                tool: Default::default(),
+                buttons: Default::default(),
+            },
+        };
+        let lift = InputEvent::WacomEvent {
+            event: WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: false,
+            },
+        };
+        sim.script(
+            [draw(100.0, 150.0), draw(200.0, 150.0), lift]
+                .into_iter()
+                .enumerate()
+                .map(|(i, event)| (Duration::from_millis(i as u64 * 10), event)),
+        );
+        sim.run(|_, _| {});
+        assert_eq!(CHANGES.load(Ordering::Relaxed), 1);
+        let dark = |app: &mut ApplicationContext<'_>| {
+            let fb = app.get_framebuffer_ref();
+            fb.read_pixel(Point2 { x: 150, y: 150 }).to_rgb8()[1] < 128
+        };
+        assert!(dark(sim.app()));
+        let strokes = sim.app().update_canvas("canvas", |c| c.strokes().len());
+        assert_eq!(strokes, Some(1));
+
+        assert_eq!(sim.app().update_canvas("canvas", |c| c.undo()), Some(true));
+        assert_eq!(CHANGES.load(Ordering::Relaxed), 2);
+        assert!(!dark(sim.app()));
+        let refreshes = sim.app().get_framebuffer_ref().take_refreshes();
+        assert!(refreshes
+            .last()
+            .unwrap()
+            .update_region
+            .contains_point(&Point2 { x: 150, y: 150 }));
+    }
+
+    #[test]
+    fn test_handle_event() {
+        use crate::ui_extensions::element::{UIElement, UIElementHandle, UIElementWrapper};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLICKS: AtomicUsize = AtomicUsize::new(0);
+        static ROUTED: AtomicUsize = AtomicUsize::new(0);
+        fn clicked(_: &mut ApplicationContext<'_>, _: UIElementHandle) {
+            CLICKS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut sim = Simulation::new(400, 400);
+        let app = sim.app();
+        app.add_element(
+            "button",
+            UIElementWrapper {
+                position: Point2 { x: 10, y: 10 },
+                onclick: Some(clicked),
+                inner: UIElement::Region {
+                    size: (50, 50).into(),
+                    border_color: color::BLACK,
+                    border_px: 2,
+                },
+                ..Default::default()
+            },
+        );
+        app.draw_elements();
+        let all = mxcfb_rect::from(Point2 { x: 0, y: 0 }, (400, 400).into());
+        app.input_router().add_region(all, 0, |_, _| {
+            ROUTED.fetch_add(1, Ordering::Relaxed);
+        });
+        let touch = |id, x| {
+            let finger = Finger::new(id, Point2 { x, y: 30 }, true);
+            InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Move { finger },
+            }
+        };
+        // Once per gesture, like the event loop
+        app.handle_event(touch(2, 30));
+        app.handle_event(touch(2, 31));
+        assert_eq!(CLICKS.load(Ordering::Relaxed), 1);
+        app.handle_event(touch(3, 30));
+        assert_eq!(CLICKS.load(Ordering::Relaxed), 2);
+        assert_eq!(ROUTED.load(Ordering::Relaxed), 3);
+    }
+}
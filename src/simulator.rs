@@ -0,0 +1,283 @@
+//! Running apps in a window on the desktop.
+//!
+//! `ApplicationContext::simulator` creates a context whose framebuffer is shown in a
+//! window, with every refresh showing up there right away. The mouse is the pen:
+//! moving it hovers, dragging with the left button draws. The arrow keys left and
+//! right, `Home` and `P` press the left, right and middle button and the power
+//! button. Nothing is rendered like on an e-ink display, waveforms are ignored.
+//!
+//! The window runs on a thread of its own, which not every platform allows, e.g.
+//! macOS. Without a display, like over SSH, there is no window and the app runs as if
+//! it were headless.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+
+use crate::appctx::ApplicationContext;
+use crate::framebuffer::backend::{FramebufferBackend, Pixels};
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::color;
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::mxcfb::mxcfb_update_data;
+use crate::input::{
+    AxisMaxima, GPIOEvent, InputEvent, PhysicalButton, StylusButtons, WacomEvent, WacomPen,
+    WacomTool,
+};
+
+/// How far above the display a hovering mouse is, in the digitizer's raw units
+const HOVER_DISTANCE: u16 = 20;
+
+/// What the window shows, one `0RGB` pixel each
+struct Screen {
+    width: usize,
+    pixels: Mutex<Vec<u32>>,
+    changed: AtomicBool,
+}
+
+/// Copies refreshed regions into the `Screen`
+struct WindowBackend {
+    screen: Arc<Screen>,
+}
+
+impl FramebufferBackend for WindowBackend {
+    fn send_update(&self, pixels: &Pixels<'_>, update: &mxcfb_update_data) -> bool {
+        let rect = update.update_region;
+        let region = pixels.region(rect);
+        let bytespp = (pixels.bits_per_pixel / 8) as usize;
+        let mut screen = self.screen.pixels.lock().unwrap();
+        for (i, pixel) in region.chunks_exact(bytespp).enumerate() {
+            let [r, g, b] = match *pixel {
+                [b, g, r, _] => [r, g, b],
+                [c1, c2] => color::NATIVE_COMPONENTS(c1, c2).to_rgb8(),
+                _ => return false,
+            };
+            let x = rect.left as usize + i % rect.width as usize;
+            let y = rect.top as usize + i / rect.width as usize;
+            screen[y * self.screen.width + x] =
+                u32::from(r) << 16 | u32::from(g) << 8 | u32::from(b);
+        }
+        self.screen.changed.store(true, Ordering::Release);
+        true
+    }
+}
+
+/// Turns the mouse into the events of a pen
+#[derive(Default)]
+struct MousePen {
+    near: bool,
+    down: bool,
+    last: Option<(f32, f32)>,
+}
+
+impl MousePen {
+    /// The events for the mouse at `pos`, `None` when it left the window, with the
+    /// left button `down` or not
+    fn update(&mut self, pos: Option<(f32, f32)>, down: bool) -> Vec<WacomEvent> {
+        let mut events = Vec::new();
+        let pos = match pos {
+            Some(pos) => pos,
+            None => {
+                if self.down {
+                    self.down = false;
+                    events.push(WacomEvent::InstrumentChange {
+                        pen: WacomPen::Touch,
+                        state: false,
+                    });
+                }
+                if self.near {
+                    self.near = false;
+                    events.push(WacomEvent::InstrumentChange {
+                        pen: WacomPen::ToolPen,
+                        state: false,
+                    });
+                }
+                self.last = None;
+                return events;
+            }
+        };
+        if !self.near {
+            self.near = true;
+            events.push(WacomEvent::InstrumentChange {
+                pen: WacomPen::ToolPen,
+                state: true,
+            });
+        }
+        if down != self.down {
+            self.down = down;
+            events.push(WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: down,
+            });
+        } else if self.last == Some(pos) {
+            return events;
+        }
+        self.last = Some(pos);
+        let position = cgmath::Point2 { x: pos.0, y: pos.1 };
+        let tilt = cgmath::Vector2 { x: 0, y: 0 };
+        events.push(if down {
+            WacomEvent::Draw {
+                position,
+                pressure: AxisMaxima::current().wacom_pressure,
+                tilt,
+                tool: WacomTool::Pen,
+                buttons: StylusButtons::default(),
+            }
+        } else {
+            WacomEvent::Hover {
+                position,
+                distance: HOVER_DISTANCE,
+                tilt,
+                tool: WacomTool::Pen,
+                buttons: StylusButtons::default(),
+            }
+        });
+        events
+    }
+}
+
+/// The button a key stands for
+fn button(key: Key) -> Option<PhysicalButton> {
+    match key {
+        Key::Left => Some(PhysicalButton::LEFT),
+        Key::Home => Some(PhysicalButton::MIDDLE),
+        Key::Right => Some(PhysicalButton::RIGHT),
+        Key::P => Some(PhysicalButton::POWER),
+        _ => None,
+    }
+}
+
+/// Shows `screen` until the window is closed, sending its input to `input_tx`
+fn run_window(screen: Arc<Screen>, height: usize, input_tx: Sender<InputEvent>) {
+    let options = WindowOptions {
+        scale: Scale::FitScreen,
+        ..WindowOptions::default()
+    };
+    let mut window = match Window::new("libremarkable", screen.width, height, options) {
+        Ok(window) => window,
+        Err(err) => {
+            warn!("Unable to open the simulator window: {}", err);
+            return;
+        }
+    };
+    window.set_target_fps(60);
+    let mut pen = MousePen::default();
+    while window.is_open() {
+        if screen.changed.swap(false, Ordering::Acquire) {
+            let pixels = screen.pixels.lock().unwrap().clone();
+            if let Err(err) = window.update_with_buffer(&pixels, screen.width, height) {
+                warn!("Unable to update the simulator window: {}", err);
+            }
+        } else {
+            window.update();
+        }
+
+        let pos = window.get_mouse_pos(MouseMode::Discard);
+        let events = pen
+            .update(pos, window.get_mouse_down(MouseButton::Left))
+            .into_iter()
+            .map(|event| InputEvent::WacomEvent { event });
+        let pressed = window
+            .get_keys_pressed(KeyRepeat::No)
+            .into_iter()
+            .filter_map(button)
+            .map(|button| GPIOEvent::Press { button });
+        let released = window
+            .get_keys_released()
+            .into_iter()
+            .filter_map(button)
+            .map(|button| GPIOEvent::Unpress { button });
+        let buttons = pressed
+            .chain(released)
+            .map(|event| InputEvent::GPIO { event });
+        for event in events.chain(buttons) {
+            if input_tx.send(event).is_err() {
+                // The context is gone
+                return;
+            }
+        }
+    }
+}
+
+impl ApplicationContext<'static> {
+    /// Creates a context shown in a window of `width` by `height` pixels, scaled down
+    /// to fit the desktop, see `simulator`
+    pub fn simulator(width: u32, height: u32) -> ApplicationContext<'static> {
+        let screen = Arc::new(Screen {
+            width: width as usize,
+            pixels: Mutex::new(vec![0x00ff_ffff; (width * height) as usize]),
+            changed: AtomicBool::new(true),
+        });
+        let backend = WindowBackend {
+            screen: screen.clone(),
+        };
+        let app =
+            ApplicationContext::with_framebuffer(Framebuffer::with_backend(width, height, backend));
+        let input_tx = app.input_sender();
+        std::thread::spawn(move || run_window(screen, height as usize, input_tx));
+        app
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::{Point2, Vector2};
+    use crate::framebuffer::common::mxcfb_rect;
+    use crate::framebuffer::{FramebufferDraw, PartialRefreshMode};
+
+    #[test]
+    fn test_simulator() {
+        let screen = Arc::new(Screen {
+            width: 20,
+            pixels: Mutex::new(vec![0; 20 * 10]),
+            changed: AtomicBool::new(false),
+        });
+        let mut fb = Framebuffer::with_backend(
+            20,
+            10,
+            WindowBackend {
+                screen: screen.clone(),
+            },
+        );
+        fb.fill_rect(
+            Point2 { x: 5, y: 5 },
+            Vector2 { x: 2, y: 2 },
+            color::RGB(0, 0, 255),
+        );
+        let rect = mxcfb_rect {
+            left: 4,
+            top: 4,
+            width: 4,
+            height: 4,
+        };
+        fb.refresh(&rect, PartialRefreshMode::Async);
+        assert!(screen.changed.load(Ordering::Acquire));
+        let pixels = screen.pixels.lock().unwrap();
+        assert_eq!(pixels[5 * 20 + 6], 0x0000_00ff);
+        assert_eq!(pixels[4 * 20 + 4], 0x00ff_ffff);
+        // Not refreshed
+        assert_eq!(pixels[0], 0);
+
+        let mut pen = MousePen::default();
+        let near = WacomEvent::InstrumentChange {
+            pen: WacomPen::ToolPen,
+            state: true,
+        };
+        assert!(matches!(
+            pen.update(Some((1.0, 2.0)), false)[..],
+            [ref event, WacomEvent::Hover { .. }] if *event == near
+        ));
+        assert!(pen.update(Some((1.0, 2.0)), false).is_empty());
+        assert!(matches!(
+            pen.update(Some((3.0, 2.0)), true)[..],
+            [WacomEvent::InstrumentChange { state: true, .. }, WacomEvent::Draw { position, .. }]
+                if position == Point2 { x: 3.0, y: 2.0 }
+        ));
+        assert_eq!(pen.update(None, true).len(), 2);
+        assert_eq!(button(Key::P), Some(PhysicalButton::POWER));
+    }
+}
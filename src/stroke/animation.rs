@@ -0,0 +1,482 @@
+//! Keyframed playback of strokes.
+//!
+//! An `Animation` is a set of strokes along with keyframes describing how they are
+//! transformed (moved, scaled, rotated) and how much of them is revealed over time.
+//! A `Player` turns an animation into frames while keeping the peculiarities of
+//! e-ink in mind: frames are rate limited, only the damaged region is redrawn and
+//! refreshed, identical frames are skipped and the last frame is cleaned up with a
+//! high fidelity waveform to get rid of the ghosting left behind by `DU` updates.
+
+use std::time::Duration;
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect, waveform_mode};
+use crate::stroke::Stroke;
+
+#[cfg(feature = "framebuffer-drawing")]
+use crate::framebuffer::common::{display_temp, dither_mode};
+#[cfg(feature = "framebuffer-drawing")]
+use crate::framebuffer::core;
+#[cfg(feature = "framebuffer-drawing")]
+use crate::framebuffer::FramebufferDraw;
+#[cfg(feature = "framebuffer-drawing")]
+use crate::framebuffer::{FramebufferRefresh, PartialRefreshMode};
+
+/// Interpolation used between two keyframes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Jumps to the next keyframe once it is reached
+    Step,
+}
+
+impl Easing {
+    /// Maps the linear progress `t` (`0.0..=1.0`) onto the eased progress
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+/// An affine transform made up of a scale and rotation around the animation's
+/// pivot followed by a translation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: cgmath::Vector2<f32>,
+    pub scale: f32,
+    /// Clockwise rotation in radians
+    pub rotation: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: cgmath::Vector2 { x: 0.0, y: 0.0 },
+        scale: 1.0,
+        rotation: 0.0,
+    };
+
+    pub fn translate(x: f32, y: f32) -> Transform {
+        Transform {
+            translation: cgmath::Vector2 { x, y },
+            ..Transform::IDENTITY
+        }
+    }
+
+    /// Applies the transform to `p`, scaling and rotating around `pivot`
+    pub fn apply(&self, p: cgmath::Point2<f32>, pivot: cgmath::Point2<f32>) -> cgmath::Point2<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = (p.x - pivot.x) * self.scale;
+        let y = (p.y - pivot.y) * self.scale;
+        cgmath::Point2 {
+            x: pivot.x + x * cos - y * sin + self.translation.x,
+            y: pivot.y + x * sin + y * cos + self.translation.y,
+        }
+    }
+
+    /// Linear interpolation between `self` (at `t == 0`) and `other` (at `t == 1`)
+    pub fn interpolate(&self, other: &Transform, t: f32) -> Transform {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Transform {
+            translation: cgmath::Vector2 {
+                x: lerp(self.translation.x, other.translation.x),
+                y: lerp(self.translation.y, other.translation.y),
+            },
+            scale: lerp(self.scale, other.scale),
+            rotation: lerp(self.rotation, other.rotation),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    /// Offset from the start of the animation
+    pub time: Duration,
+    pub transform: Transform,
+    /// Fraction of the strokes' points that are visible, in `0.0..=1.0`.
+    /// Animating it from 0 to 1 makes the strokes appear as if being written.
+    pub reveal: f32,
+    /// Easing used for the transition from the previous keyframe into this one
+    pub easing: Easing,
+}
+
+impl Keyframe {
+    pub fn new(time: Duration, transform: Transform) -> Keyframe {
+        Keyframe {
+            time,
+            transform,
+            reveal: 1.0,
+            easing: Easing::Linear,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    pub strokes: Vec<Stroke>,
+    /// Keyframes sorted by their time
+    keyframes: Vec<Keyframe>,
+    /// Point around which the strokes are scaled and rotated
+    pub pivot: cgmath::Point2<f32>,
+    /// Whether the animation starts over once the last keyframe is reached
+    pub looping: bool,
+}
+
+impl Animation {
+    /// Creates a still animation of `strokes`, pivoting around the center of their
+    /// bounding box. Use `add_keyframe` to make them move.
+    pub fn new(strokes: Vec<Stroke>) -> Animation {
+        let bounds = strokes.iter().fold(mxcfb_rect::invalid(), |r, s| {
+            r.merge_rect(&s.bounding_rect())
+        });
+        let pivot = if bounds.width == 0 || bounds.height == 0 {
+            cgmath::Point2 { x: 0.0, y: 0.0 }
+        } else {
+            cgmath::Point2 {
+                x: bounds.left as f32 + bounds.width as f32 / 2.0,
+                y: bounds.top as f32 + bounds.height as f32 / 2.0,
+            }
+        };
+        Animation {
+            strokes,
+            keyframes: Vec::new(),
+            pivot,
+            looping: false,
+        }
+    }
+
+    /// Adds a keyframe, replacing any existing keyframe at the same time
+    pub fn add_keyframe(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .binary_search_by(|k| k.time.cmp(&keyframe.time))
+        {
+            Ok(i) => self.keyframes[i] = keyframe,
+            Err(i) => self.keyframes.insert(i, keyframe),
+        }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> Duration {
+        self.keyframes.last().map(|k| k.time).unwrap_or_default()
+    }
+
+    /// Whether a non looping animation has reached its last keyframe at `elapsed`
+    pub fn is_finished(&self, elapsed: Duration) -> bool {
+        !self.looping && elapsed >= self.duration()
+    }
+
+    /// The interpolated transform and reveal fraction at `elapsed`
+    pub fn state_at(&self, elapsed: Duration) -> (Transform, f32) {
+        let elapsed = match self.duration() {
+            d if self.looping && !d.is_zero() => {
+                Duration::from_nanos((elapsed.as_nanos() % d.as_nanos()) as u64)
+            }
+            _ => elapsed,
+        };
+        let next = self.keyframes.iter().position(|k| k.time > elapsed);
+        match next {
+            None => self
+                .keyframes
+                .last()
+                .map(|k| (k.transform, k.reveal))
+                .unwrap_or((Transform::IDENTITY, 1.0)),
+            Some(0) => {
+                let first = &self.keyframes[0];
+                (first.transform, first.reveal)
+            }
+            Some(i) => {
+                let (from, to) = (&self.keyframes[i - 1], &self.keyframes[i]);
+                let span = (to.time - from.time).as_secs_f32();
+                let t = to.easing.apply((elapsed - from.time).as_secs_f32() / span);
+                (
+                    from.transform.interpolate(&to.transform, t),
+                    from.reveal + (to.reveal - from.reveal) * t,
+                )
+            }
+        }
+    }
+
+    /// The strokes as they should be displayed at `elapsed`
+    pub fn frame_at(&self, elapsed: Duration) -> Vec<Stroke> {
+        let (transform, reveal) = self.state_at(elapsed);
+        let total: usize = self.strokes.iter().map(|s| s.points.len()).sum();
+        let mut remaining = (total as f32 * reveal.clamp(0.0, 1.0)).round() as usize;
+
+        let mut frame = Vec::with_capacity(self.strokes.len());
+        for stroke in &self.strokes {
+            if remaining == 0 {
+                break;
+            }
+            let count = remaining.min(stroke.points.len());
+            remaining -= count;
+            let points = stroke.points[..count]
+                .iter()
+                .map(|p| {
+                    let mut p = *p;
+                    p.position = transform.apply(p.position, self.pivot);
+                    p
+                })
+                .collect();
+            frame.push(Stroke::new(
+                points,
+                stroke.width * transform.scale,
+                stroke.color,
+            ));
+        }
+        frame
+    }
+}
+
+/// Drives an `Animation` onto the display.
+///
+/// The player is clocked by the caller: ask it `time_until_next_frame` and call
+/// `render` (or `render_and_refresh`) once that much time has passed. `play` does
+/// all of that in a blocking loop.
+pub struct Player {
+    pub animation: Animation,
+    /// Frames are never drawn more often than this. Each frame costs at least one
+    /// refresh, so going much below the duration of a `DU` update only queues up work
+    /// for the EPDC.
+    pub min_frame_interval: Duration,
+    /// Waveform used for the intermediate frames
+    pub waveform: waveform_mode,
+    /// Color the previous frame is erased with
+    pub background: color,
+    last_frame: Option<Duration>,
+    #[cfg(feature = "framebuffer-drawing")]
+    last_state: Option<(Transform, f32)>,
+    last_rect: mxcfb_rect,
+    finished: bool,
+}
+
+impl Player {
+    pub fn new(animation: Animation) -> Player {
+        Player {
+            animation,
+            min_frame_interval: Duration::from_millis(100),
+            waveform: waveform_mode::WAVEFORM_MODE_DU,
+            background: color::WHITE,
+            last_frame: None,
+            #[cfg(feature = "framebuffer-drawing")]
+            last_state: None,
+            last_rect: mxcfb_rect::invalid(),
+            finished: false,
+        }
+    }
+
+    /// Whether the last frame of a non looping animation has been rendered
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// How long to wait from `elapsed` on before the next frame is due. Returns
+    /// `None` once the animation is finished.
+    pub fn time_until_next_frame(&self, elapsed: Duration) -> Option<Duration> {
+        if self.finished {
+            return None;
+        }
+        Some(match self.last_frame {
+            None => Duration::ZERO,
+            Some(last) => (last + self.min_frame_interval).saturating_sub(elapsed),
+        })
+    }
+
+    /// Region covered by the most recently rendered frame
+    pub fn last_rect(&self) -> mxcfb_rect {
+        self.last_rect
+    }
+
+    /// Erases the previous frame and draws the one at `elapsed`. Returns the region
+    /// that needs to be refreshed, or `None` if the frame isn't due yet or is
+    /// identical to the one already on screen. The last frame always returns its
+    /// region, for `render_and_refresh` to clean it up.
+    #[cfg(feature = "framebuffer-drawing")]
+    pub fn render<F: FramebufferDraw + ?Sized>(
+        &mut self,
+        fb: &mut F,
+        elapsed: Duration,
+    ) -> Option<mxcfb_rect> {
+        if self.time_until_next_frame(elapsed) != Some(Duration::ZERO) {
+            return None;
+        }
+        self.last_frame = Some(elapsed);
+        let finishing = !self.finished && self.animation.is_finished(elapsed);
+        self.finished = self.animation.is_finished(elapsed);
+
+        let state = self.animation.state_at(elapsed);
+        if self.last_state == Some(state) {
+            // Still damaged for the cleanup refresh of the last frame
+            let shown = self.last_rect;
+            return Some(shown).filter(|_| finishing && shown.width != 0 && shown.height != 0);
+        }
+        self.last_state = Some(state);
+
+        let previous = self.last_rect;
+        if previous.width != 0 && previous.height != 0 {
+            fb.fill_rect(
+                cgmath::Point2 {
+                    x: previous.left as i32,
+                    y: previous.top as i32,
+                },
+                previous.size(),
+                self.background,
+            );
+        }
+        self.last_rect = self
+            .animation
+            .frame_at(elapsed)
+            .iter()
+            .fold(mxcfb_rect::invalid(), |r, s| r.merge_rect(&s.draw(fb)));
+
+        let damage = previous.merge_rect(&self.last_rect);
+        if damage.width == 0 || damage.height == 0 {
+            None
+        } else {
+            Some(damage)
+        }
+    }
+
+    /// Renders the frame at `elapsed` and refreshes the damaged region. Returns the
+    /// marker of the refresh, if one was issued.
+    ///
+    /// The last frame of the animation is refreshed with `GC16` to clean up the
+    /// ghosting accumulated by the intermediate frames.
+    #[cfg(feature = "framebuffer-drawing")]
+    pub fn render_and_refresh(
+        &mut self,
+        fb: &mut core::Framebuffer,
+        elapsed: Duration,
+    ) -> Option<u32> {
+        let damage = self.render(fb, elapsed)?;
+        let waveform = if self.finished {
+            waveform_mode::WAVEFORM_MODE_GC16
+        } else {
+            self.waveform
+        };
+        Some(fb.partial_refresh(
+            &damage,
+            PartialRefreshMode::Async,
+            waveform,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            false,
+        ))
+    }
+
+    /// Plays the whole animation, blocking until it is finished. Each refresh is
+    /// waited for before the next frame is drawn so updates never pile up.
+    ///
+    /// Looping animations never finish, run them on their own thread or drive
+    /// them with `render_and_refresh` instead.
+    #[cfg(feature = "framebuffer-drawing")]
+    pub fn play(&mut self, fb: &mut core::Framebuffer) {
+        let start = std::time::Instant::now();
+        while let Some(wait) = self.time_until_next_frame(start.elapsed()) {
+            std::thread::sleep(wait);
+            if let Some(marker) = self.render_and_refresh(fb, start.elapsed()) {
+                fb.wait_refresh_complete(marker);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stroke::StrokePoint;
+
+    fn line() -> Stroke {
+        Stroke::new(
+            (0..10).map(|i| StrokePoint::new(i as f32, 0.0)).collect(),
+            2.0,
+            color::BLACK,
+        )
+    }
+
+    #[test]
+    fn test_keyframe_interpolation() {
+        let mut anim = Animation::new(vec![line()]);
+        anim.pivot = cgmath::Point2 { x: 0.0, y: 0.0 };
+        anim.add_keyframe(Keyframe {
+            reveal: 0.0,
+            ..Keyframe::new(Duration::ZERO, Transform::IDENTITY)
+        });
+        anim.add_keyframe(Keyframe::new(
+            Duration::from_secs(1),
+            Transform::translate(100.0, 50.0),
+        ));
+
+        let (transform, reveal) = anim.state_at(Duration::from_millis(500));
+        assert_eq!(transform.translation, cgmath::Vector2 { x: 50.0, y: 25.0 });
+        assert_eq!(reveal, 0.5);
+
+        let frame = anim.frame_at(Duration::from_millis(500));
+        assert_eq!(frame[0].points.len(), 5);
+        assert_eq!(
+            frame[0].points[1].position,
+            cgmath::Point2 { x: 51.0, y: 25.0 }
+        );
+
+        assert!(anim.frame_at(Duration::ZERO).is_empty());
+        assert_eq!(anim.frame_at(Duration::from_secs(2))[0].points.len(), 10);
+        assert!(anim.is_finished(Duration::from_secs(1)));
+
+        anim.looping = true;
+        assert_eq!(
+            anim.state_at(Duration::from_millis(1500)),
+            anim.state_at(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn test_player_frame_pacing() {
+        let mut anim = Animation::new(vec![line()]);
+        anim.add_keyframe(Keyframe::new(Duration::from_secs(1), Transform::IDENTITY));
+        let mut player = Player::new(anim);
+        assert_eq!(
+            player.time_until_next_frame(Duration::from_millis(30)),
+            Some(Duration::ZERO)
+        );
+        player.last_frame = Some(Duration::from_millis(30));
+        assert_eq!(
+            player.time_until_next_frame(Duration::from_millis(50)),
+            Some(Duration::from_millis(80))
+        );
+        player.finished = true;
+        assert_eq!(player.time_until_next_frame(Duration::from_secs(2)), None);
+    }
+
+    #[cfg(feature = "framebuffer-drawing")]
+    #[test]
+    fn test_player_last_frame() {
+        let mut anim = Animation::new(vec![line()]);
+        anim.add_keyframe(Keyframe::new(Duration::ZERO, Transform::IDENTITY));
+        anim.add_keyframe(Keyframe::new(Duration::from_secs(1), Transform::IDENTITY));
+        let mut player = Player::new(anim);
+        let mut fb = core::Framebuffer::headless(100, 100);
+        let first = player.render(&mut fb, Duration::ZERO).unwrap();
+        // Unchanged since, but the last frame
+        assert_eq!(player.render(&mut fb, Duration::from_secs(2)), Some(first));
+        assert_eq!(player.time_until_next_frame(Duration::from_secs(3)), None);
+    }
+}
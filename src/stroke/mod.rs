@@ -0,0 +1,319 @@
+//! A minimal vector representation of pen input.
+//!
+//! A `Stroke` is what is left of a pen gesture once it is finished: the sampled
+//! points along with the color and nominal width it was drawn with. Strokes are
+//! independent of the framebuffer, so they can be exported, re-rendered at a
+//! different scale or sent over the network.
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+
+#[cfg(feature = "framebuffer-drawing")]
+use crate::framebuffer::FramebufferDraw;
+#[cfg(feature = "input-types")]
+use crate::input::{WacomEvent, WacomPen};
+
+/// Keyframed playback of strokes
+pub mod animation;
+/// Adding strokes to PDF documents as ink annotations
+pub mod pdf;
+/// Detecting the scratch-out gesture
+pub mod scratch;
+/// Converting strokes to and from SVG documents
+pub mod svg;
+
+/// Maximum raw pressure reported by the digitizer on both the rM1 and rM2, see
+/// `AxisMaxima::current` for that of the device in use
+pub const WACOM_MAX_PRESSURE: f32 = 4095.0;
+
+/// A single sample of a stroke.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrokePoint {
+    /// Position in framebuffer coordinates
+    pub position: cgmath::Point2<f32>,
+    /// Normalized pressure in the range `0.0..=1.0`
+    pub pressure: f32,
+    /// Tilt of the pen as reported by the digitizer (signed raw units)
+    pub tilt: cgmath::Vector2<f32>,
+}
+
+impl StrokePoint {
+    /// A point with full pressure and no tilt, useful for strokes that weren't
+    /// captured with the pen (finger input, imported data etc.)
+    pub fn new(x: f32, y: f32) -> StrokePoint {
+        StrokePoint {
+            position: cgmath::Point2 { x, y },
+            pressure: 1.0,
+            tilt: cgmath::Vector2 { x: 0.0, y: 0.0 },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stroke {
+    pub points: Vec<StrokePoint>,
+    /// Width of the stroke at full pressure
+    pub width: f32,
+    pub color: color,
+}
+
+impl Stroke {
+    pub fn new(points: Vec<StrokePoint>, width: f32, color: color) -> Stroke {
+        Stroke {
+            points,
+            width,
+            color,
+        }
+    }
+
+    /// The width of the stroke at the `i`th point, taking its pressure into account
+    pub fn width_at(&self, i: usize) -> f32 {
+        self.width * self.points[i].pressure.clamp(0.0, 1.0)
+    }
+
+    /// Whether all points of the stroke are drawn with the same width
+    pub fn has_uniform_width(&self) -> bool {
+        match self.points.first() {
+            Some(first) => self
+                .points
+                .iter()
+                .all(|p| (p.pressure - first.pressure).abs() < f32::EPSILON),
+            None => true,
+        }
+    }
+
+    /// The area covered by the stroke, including its width
+    pub fn bounding_rect(&self) -> mxcfb_rect {
+        if self.points.is_empty() {
+            return mxcfb_rect::invalid();
+        }
+        let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+        let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+        for p in &self.points {
+            min_x = min_x.min(p.position.x);
+            min_y = min_y.min(p.position.y);
+            max_x = max_x.max(p.position.x);
+            max_y = max_y.max(p.position.y);
+        }
+        let margin = (self.width / 2.0).ceil();
+        let left = (min_x - margin).floor().max(0.0);
+        let top = (min_y - margin).floor().max(0.0);
+        mxcfb_rect {
+            left: left as u32,
+            top: top as u32,
+            width: ((max_x + margin).ceil() - left).max(0.0) as u32,
+            height: ((max_y + margin).ceil() - top).max(0.0) as u32,
+        }
+    }
+
+    /// Whether the stroke passes within `radius` of `point`, counting its width
+    pub fn hit_test(&self, point: cgmath::Point2<f32>, radius: f32) -> bool {
+        match self.points.len() {
+            0 => false,
+            1 => {
+                distance_to_segment(point, self.points[0].position, self.points[0].position)
+                    <= radius + self.width_at(0) / 2.0
+            }
+            _ => self.points.windows(2).enumerate().any(|(i, pair)| {
+                let reach = radius + self.width_at(i).max(self.width_at(i + 1)) / 2.0;
+                distance_to_segment(point, pair[0].position, pair[1].position) <= reach
+            }),
+        }
+    }
+
+    /// Whether at least half of the points of the stroke are inside `polygon`, e.g. a
+    /// lasso drawn around it
+    pub fn is_within(&self, polygon: &[cgmath::Point2<f32>]) -> bool {
+        let inside = self
+            .points
+            .iter()
+            .filter(|p| polygon_contains(polygon, p.position))
+            .count();
+        !self.points.is_empty() && inside * 2 >= self.points.len()
+    }
+
+    /// The stroke moved, scaled and rotated by `transform`, widths included
+    pub fn transformed(&self, transform: &StrokeTransform) -> Stroke {
+        let points = self
+            .points
+            .iter()
+            .map(|p| StrokePoint {
+                position: transform.apply(p.position),
+                tilt: transform.rotate(p.tilt),
+                ..*p
+            })
+            .collect();
+        Stroke::new(points, self.width * transform.scale, self.color)
+    }
+
+    /// Draws the stroke onto `fb` without refreshing. Returns the affected region.
+    #[cfg(feature = "framebuffer-drawing")]
+    pub fn draw<F: FramebufferDraw + ?Sized>(&self, fb: &mut F) -> mxcfb_rect {
+        let to_i32 = |p: cgmath::Point2<f32>| cgmath::Point2 {
+            x: p.x.round() as i32,
+            y: p.y.round() as i32,
+        };
+        match self.points.len() {
+            0 => mxcfb_rect::invalid(),
+            1 => fb.fill_circle(
+                to_i32(self.points[0].position),
+                (self.width_at(0) / 2.0).round().max(1.0) as u32,
+                self.color,
+            ),
+            _ => {
+                let mut rect = mxcfb_rect::invalid();
+                for (i, pair) in self.points.windows(2).enumerate() {
+                    let width = (self.width_at(i) + self.width_at(i + 1)) / 2.0;
+                    rect = rect.merge_rect(&fb.draw_line(
+                        to_i32(pair[0].position),
+                        to_i32(pair[1].position),
+                        width.round().max(1.0) as u32,
+                        self.color,
+                    ));
+                }
+                rect
+            }
+        }
+    }
+}
+
+/// How far `point` is from the line segment between `start` and `end`
+pub fn distance_to_segment(
+    point: cgmath::Point2<f32>,
+    start: cgmath::Point2<f32>,
+    end: cgmath::Point2<f32>,
+) -> f32 {
+    use cgmath::InnerSpace;
+    let along = end - start;
+    let length = along.magnitude2();
+    let t = match length > 0.0 {
+        true => ((point - start).dot(along) / length).clamp(0.0, 1.0),
+        false => 0.0,
+    };
+    (point - (start + along * t)).magnitude()
+}
+
+/// Whether `point` is inside `polygon`, closed from its last corner back to its first.
+/// Where it overlaps itself counts as inside every other time.
+pub fn polygon_contains(polygon: &[cgmath::Point2<f32>], point: cgmath::Point2<f32>) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Moves, scales and rotates strokes, see `Stroke::transformed`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrokeTransform {
+    /// What is scaled and rotated about
+    pub center: cgmath::Point2<f32>,
+    /// Radians clockwise, as y goes down
+    pub rotation: f32,
+    pub scale: f32,
+    /// Applied last
+    pub translation: cgmath::Vector2<f32>,
+}
+
+impl StrokeTransform {
+    /// Leaving strokes as they are, until changed
+    pub fn identity(center: cgmath::Point2<f32>) -> StrokeTransform {
+        StrokeTransform {
+            center,
+            rotation: 0.0,
+            scale: 1.0,
+            translation: cgmath::Vector2 { x: 0.0, y: 0.0 },
+        }
+    }
+
+    pub fn apply(&self, point: cgmath::Point2<f32>) -> cgmath::Point2<f32> {
+        self.center + self.rotate(point - self.center) * self.scale + self.translation
+    }
+
+    /// Turns `v` by `rotation`, without scaling it
+    pub fn rotate(&self, v: cgmath::Vector2<f32>) -> cgmath::Vector2<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        cgmath::Vector2 {
+            x: v.x * cos - v.y * sin,
+            y: v.x * sin + v.y * cos,
+        }
+    }
+}
+
+/// Accumulates points into a `Stroke`.
+///
+/// Feed it the `WacomEvent`s you receive through `handle_wacom_event` and it will hand
+/// you a finished `Stroke` once the pen is lifted off the display.
+#[derive(Clone, Debug)]
+pub struct StrokeBuilder {
+    pub width: f32,
+    pub color: color,
+    points: Vec<StrokePoint>,
+}
+
+impl StrokeBuilder {
+    pub fn new(width: f32, color: color) -> StrokeBuilder {
+        StrokeBuilder {
+            width,
+            color,
+            points: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, point: StrokePoint) {
+        self.points.push(point);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// The points of the stroke currently being built
+    pub fn points(&self) -> &[StrokePoint] {
+        &self.points
+    }
+
+    /// Returns the stroke built so far and starts a new one. Returns `None` if no
+    /// points were recorded.
+    pub fn finish(&mut self) -> Option<Stroke> {
+        if self.points.is_empty() {
+            return None;
+        }
+        Some(Stroke::new(
+            std::mem::take(&mut self.points),
+            self.width,
+            self.color,
+        ))
+    }
+
+    /// Records `Draw` events and returns the finished stroke once the pen stops
+    /// touching the display.
+    #[cfg(feature = "input-types")]
+    pub fn handle_wacom_event(&mut self, event: &WacomEvent) -> Option<Stroke> {
+        match *event {
+            WacomEvent::Draw { position, tilt, .. } => {
+                self.push(StrokePoint {
+                    position,
+                    pressure: event.normalized_pressure().unwrap_or_default(),
+                    // Negative tilt values get wrapped into the u16 by the decoder
+                    tilt: cgmath::Vector2 {
+                        x: f32::from(tilt.x as i16),
+                        y: f32::from(tilt.y as i16),
+                    },
+                });
+                None
+            }
+            WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: false,
+            }
+            | WacomEvent::Hover { .. } => self.finish(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,754 @@
+//! Strokes as ink annotations of PDF documents.
+//!
+//! Strokes drawn over the pages of a PDF are collected per page in an
+//! `AnnotationLayer` and appended to the document as an incremental update, which
+//! leaves the original bytes untouched. Every stroke becomes an ink annotation with
+//! an appearance stream, so other viewers show it and can edit or remove it like
+//! their own annotations.
+//!
+//! Only documents with classic cross-reference tables can be annotated. Those that
+//! keep their objects in compressed object streams, as many PDF 1.5 writers do, are
+//! rejected with `PdfError::Unsupported`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::Stroke;
+
+/// Why annotating a PDF failed
+#[derive(Debug, thiserror::Error)]
+pub enum PdfError {
+    #[error("Failed to access the PDF")]
+    Io(#[from] io::Error),
+    #[error("Malformed PDF: {0}")]
+    Malformed(&'static str),
+    #[error("Unsupported PDF: {0}")]
+    Unsupported(&'static str),
+    #[error("The PDF has no page {0}")]
+    NoSuchPage(usize),
+}
+
+/// The strokes drawn over the pages of a document
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AnnotationLayer {
+    pages: BTreeMap<usize, Vec<Stroke>>,
+}
+
+impl AnnotationLayer {
+    pub fn new() -> AnnotationLayer {
+        AnnotationLayer::default()
+    }
+
+    /// Adds `stroke` to the page with index `page`. Its coordinates are PDF points
+    /// from the top left of the page, as in a rendering of the page at a scale of 1.
+    pub fn add(&mut self, page: usize, stroke: Stroke) {
+        self.pages.entry(page).or_default().push(stroke);
+    }
+
+    pub fn strokes(&self, page: usize) -> &[Stroke] {
+        self.pages.get(&page).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.values().all(Vec::is_empty)
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    /// The incremental update adding the strokes to `pdf` as ink annotations, to be
+    /// appended to it
+    pub fn update(&self, pdf: &[u8]) -> Result<Vec<u8>, PdfError> {
+        let document = Document::parse(pdf)?;
+        let pages = document.pages()?;
+        let mut next = document.size;
+        let mut objects: Vec<(u32, u16, Vec<u8>)> = Vec::new();
+
+        for (&index, strokes) in self.pages.iter().filter(|(_, s)| !s.is_empty()) {
+            let page = pages.get(index).ok_or(PdfError::NoSuchPage(index))?;
+            let mut annots = match page.dict.get("Annots") {
+                Some(Object::Array(annots)) => annots.clone(),
+                Some(Object::Ref(num, gen)) => match document.object(*num, *gen)? {
+                    Object::Array(annots) => annots,
+                    _ => return Err(PdfError::Malformed("annotations aren't an array")),
+                },
+                _ => Vec::new(),
+            };
+            for stroke in strokes.iter().filter(|s| !s.points.is_empty()) {
+                let (annot, appearance) = (next, next + 1);
+                next += 2;
+                let (dict, stream) = ink_annotation(stroke, page, appearance);
+                objects.push((annot, 0, dict.into_bytes()));
+                objects.push((appearance, 0, stream));
+                annots.push(Object::Ref(annot, 0));
+            }
+            let mut dict = page.dict.clone();
+            dict.set("Annots", Object::Array(annots));
+            let mut bytes = Vec::new();
+            Object::Dict(dict).write(&mut bytes);
+            objects.push((page.num, page.gen, bytes));
+        }
+        if objects.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // A file not ending in a newline would run into the first object
+        let mut out = Vec::new();
+        if !pdf.ends_with(b"\n") && !pdf.ends_with(b"\r") {
+            out.push(b'\n');
+        }
+        let mut offsets = Vec::new();
+        for (num, gen, body) in &objects {
+            offsets.push((*num, *gen, pdf.len() + out.len()));
+            writeln!(out, "{} {} obj", num, gen)?;
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        offsets.sort_unstable();
+        let xref = pdf.len() + out.len();
+        out.extend_from_slice(b"xref\n");
+        for (num, gen, offset) in offsets {
+            write!(out, "{} 1\n{:010} {:05} n\r\n", num, offset, gen)?;
+        }
+
+        let mut trailer = Dict::default();
+        trailer.set("Size", Object::Number(f64::from(next)));
+        for key in ["Root", "Info", "ID"] {
+            if let Some(value) = document.trailer.get(key) {
+                trailer.set(key, value.clone());
+            }
+        }
+        trailer.set("Prev", Object::Number(document.startxref as f64));
+        out.extend_from_slice(b"trailer\n");
+        Object::Dict(trailer).write(&mut out);
+        write!(out, "\nstartxref\n{}\n%%EOF\n", xref)?;
+        Ok(out)
+    }
+
+    /// Appends the strokes to the PDF at `path` as ink annotations
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), PdfError> {
+        let path = path.as_ref();
+        let update = self.update(&std::fs::read(path)?)?;
+        let mut file = std::fs::OpenOptions::new().append(true).open(path)?;
+        file.write_all(&update)?;
+        Ok(())
+    }
+}
+
+/// The annotation dictionary of `stroke`, and the appearance stream object numbered
+/// `appearance` drawing it
+fn ink_annotation(stroke: &Stroke, page: &Page, appearance: u32) -> (String, Vec<u8>) {
+    let [left, _, _, top] = page.media_box;
+    let points: Vec<(f32, f32)> = stroke
+        .points
+        .iter()
+        .map(|p| (left + p.position.x, top - p.position.y))
+        .collect();
+    let margin = stroke.width / 2.0 + 1.0;
+    let min = |f: fn(&(f32, f32)) -> f32| points.iter().map(f).fold(f32::MAX, f32::min);
+    let max = |f: fn(&(f32, f32)) -> f32| points.iter().map(f).fold(f32::MIN, f32::max);
+    let rect = [
+        min(|p| p.0) - margin,
+        min(|p| p.1) - margin,
+        max(|p| p.0) + margin,
+        max(|p| p.1) + margin,
+    ];
+    let [r, g, b] = stroke.color.to_rgb8().map(|c| f32::from(c) / 255.0);
+
+    let mut ink = String::new();
+    let mut path = String::new();
+    for (i, (x, y)) in points.iter().enumerate() {
+        let _ = write!(ink, "{} {} ", number(*x), number(*y));
+        let op = if i == 0 { "m" } else { "l" };
+        let _ = writeln!(path, "{} {} {}", number(*x), number(*y), op);
+    }
+    if points.len() == 1 {
+        // A dot, drawn as a line of no length with round caps
+        let (x, y) = points[0];
+        let _ = writeln!(path, "{} {} l", number(x), number(y));
+    }
+    let rect = rect.map(number).join(" ");
+    let color = [r, g, b].map(number).join(" ");
+    let width = number(stroke.width);
+
+    let dict = format!(
+        "<< /Type /Annot /Subtype /Ink /Rect [{rect}] /InkList [[{ink}]] /C [{color}] \
+         /BS << /W {width} >> /F 4 /P {} {} R /AP << /N {appearance} 0 R >> >>",
+        page.num,
+        page.gen,
+        ink = ink.trim_end(),
+    );
+    let content = format!("{color} RG {width} w 1 J 1 j\n{path}S\n");
+    let mut stream = format!(
+        "<< /Type /XObject /Subtype /Form /BBox [{rect}] /Length {} >>\nstream\n",
+        content.len()
+    )
+    .into_bytes();
+    stream.extend_from_slice(content.as_bytes());
+    stream.extend_from_slice(b"\nendstream");
+    (dict, stream)
+}
+
+/// `value` without exponent, which PDF doesn't know, and few decimals
+fn number(value: f32) -> String {
+    let text = format!("{:.3}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    match text {
+        "-0" => "0".to_owned(),
+        text => text.to_owned(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Object {
+    Null,
+    Bool(bool),
+    Number(f64),
+    /// Without the slash, escapes left as they are
+    Name(String),
+    /// As written, delimiters included
+    String(Vec<u8>),
+    Array(Vec<Object>),
+    Dict(Dict),
+    Ref(u32, u16),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Dict(Vec<(String, Object)>);
+
+impl Dict {
+    fn get(&self, key: &str) -> Option<&Object> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn set(&mut self, key: &str, value: Object) {
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.0.push((key.to_owned(), value)),
+        }
+    }
+}
+
+impl Object {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Object::Null => out.extend_from_slice(b"null"),
+            Object::Bool(value) => out.extend_from_slice(value.to_string().as_bytes()),
+            // Offsets and sizes can be too large for the precision of `number`
+            Object::Number(value) if value.fract() == 0.0 => {
+                out.extend_from_slice((*value as i64).to_string().as_bytes())
+            }
+            Object::Number(value) => out.extend_from_slice(number(*value as f32).as_bytes()),
+            Object::Name(name) => {
+                out.push(b'/');
+                out.extend_from_slice(name.as_bytes());
+            }
+            Object::String(raw) => out.extend_from_slice(raw),
+            Object::Array(items) => {
+                out.push(b'[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b' ');
+                    }
+                    item.write(out);
+                }
+                out.push(b']');
+            }
+            Object::Dict(dict) => {
+                out.extend_from_slice(b"<<");
+                for (key, value) in &dict.0 {
+                    out.push(b'/');
+                    out.extend_from_slice(key.as_bytes());
+                    out.push(b' ');
+                    value.write(out);
+                }
+                out.extend_from_slice(b">>");
+            }
+            Object::Ref(num, gen) => out.extend_from_slice(format!("{} {} R", num, gen).as_bytes()),
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Object::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the objects of a PDF, as far as annotating it needs to
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Of the arrays and dictionaries being read
+    depth: usize,
+}
+
+fn is_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'\x0c' | b'\0')
+}
+
+fn is_delimiter(c: u8) -> bool {
+    matches!(
+        c,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+const MALFORMED: PdfError = PdfError::Malformed("unexpected token");
+
+/// How deeply arrays and dictionaries may be nested
+const MAX_DEPTH: usize = 64;
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Parser<'a> {
+        Parser {
+            data,
+            pos,
+            depth: 0,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.data.get(self.pos) {
+            if c == b'%' {
+                while !matches!(self.data.get(self.pos), None | Some(b'\n' | b'\r')) {
+                    self.pos += 1;
+                }
+            } else if is_whitespace(c) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// A keyword or number
+    fn word(&mut self) -> &'a [u8] {
+        self.skip_whitespace();
+        let start = self.pos;
+        while let Some(&c) = self.data.get(self.pos) {
+            if is_whitespace(c) || is_delimiter(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn expect(&mut self, keyword: &[u8]) -> Result<(), PdfError> {
+        match self.word() == keyword {
+            true => Ok(()),
+            false => Err(MALFORMED),
+        }
+    }
+
+    fn integer<T: std::str::FromStr>(&mut self) -> Result<T, PdfError> {
+        std::str::from_utf8(self.word())
+            .ok()
+            .and_then(|word| word.parse().ok())
+            .ok_or(MALFORMED)
+    }
+
+    fn object(&mut self) -> Result<Object, PdfError> {
+        if self.depth == MAX_DEPTH {
+            return Err(PdfError::Malformed("objects nested too deeply"));
+        }
+        self.depth += 1;
+        let object = self.value();
+        self.depth -= 1;
+        object
+    }
+
+    /// The object at the current position, see `object`
+    fn value(&mut self) -> Result<Object, PdfError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match self.data.get(self.pos).ok_or(MALFORMED)? {
+            b'/' => {
+                self.pos += 1;
+                let name = self.word();
+                Ok(Object::Name(String::from_utf8_lossy(name).into_owned()))
+            }
+            b'(' => {
+                let mut depth = 0;
+                while let Some(&c) = self.data.get(self.pos) {
+                    self.pos += 1;
+                    match c {
+                        b'\\' => self.pos += 1,
+                        b'(' => depth += 1,
+                        b')' if depth == 1 => {
+                            return Ok(Object::String(self.data[start..self.pos].to_vec()))
+                        }
+                        b')' => depth -= 1,
+                        _ => {}
+                    }
+                }
+                Err(PdfError::Malformed("unterminated string"))
+            }
+            b'<' if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                let mut dict = Dict::default();
+                loop {
+                    self.skip_whitespace();
+                    if self.data[self.pos..].starts_with(b">>") {
+                        self.pos += 2;
+                        return Ok(Object::Dict(dict));
+                    }
+                    match self.object()? {
+                        Object::Name(key) => {
+                            let value = self.object()?;
+                            dict.0.push((key, value));
+                        }
+                        _ => return Err(PdfError::Malformed("dictionary key isn't a name")),
+                    }
+                }
+            }
+            b'<' => {
+                let end = self.data[start..]
+                    .iter()
+                    .position(|&c| c == b'>')
+                    .ok_or(PdfError::Malformed("unterminated string"))?;
+                self.pos = start + end + 1;
+                Ok(Object::String(self.data[start..self.pos].to_vec()))
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.data.get(self.pos) == Some(&b']') {
+                        self.pos += 1;
+                        return Ok(Object::Array(items));
+                    }
+                    items.push(self.object()?);
+                }
+            }
+            _ => {
+                let word = self.word();
+                match word {
+                    b"null" => return Ok(Object::Null),
+                    b"true" => return Ok(Object::Bool(true)),
+                    b"false" => return Ok(Object::Bool(false)),
+                    _ => {}
+                }
+                let value: f64 = std::str::from_utf8(word)
+                    .ok()
+                    .and_then(|w| w.parse().ok())
+                    .ok_or(MALFORMED)?;
+                // Two integers followed by R are a reference
+                let after = self.pos;
+                if word.iter().all(u8::is_ascii_digit) {
+                    let gen = self.word();
+                    if !gen.is_empty() && gen.iter().all(u8::is_ascii_digit) && self.word() == b"R"
+                    {
+                        let gen = std::str::from_utf8(gen)
+                            .unwrap()
+                            .parse()
+                            .map_err(|_| MALFORMED)?;
+                        return Ok(Object::Ref(value as u32, gen));
+                    }
+                }
+                self.pos = after;
+                Ok(Object::Number(value))
+            }
+        }
+    }
+}
+
+struct Page {
+    num: u32,
+    gen: u16,
+    dict: Dict,
+    /// Left, bottom, right and top
+    media_box: [f32; 4],
+}
+
+struct Document<'a> {
+    data: &'a [u8],
+    /// Offsets of the objects by number, with their generation
+    offsets: HashMap<u32, (usize, u16)>,
+    trailer: Dict,
+    startxref: usize,
+    size: u32,
+}
+
+impl<'a> Document<'a> {
+    fn parse(data: &'a [u8]) -> Result<Document<'a>, PdfError> {
+        let tail = &data[data.len().saturating_sub(1024)..];
+        let at = tail
+            .windows(9)
+            .rposition(|w| w == b"startxref")
+            .ok_or(PdfError::Malformed("no startxref"))?;
+        let mut parser = Parser::new(tail, at + 9);
+        let startxref: usize = parser.integer()?;
+
+        let mut document = Document {
+            data,
+            offsets: HashMap::new(),
+            trailer: Dict::default(),
+            startxref,
+            size: 0,
+        };
+        // The newest sections come first and override the older ones they point to
+        let mut section = Some(startxref);
+        let mut seen = Vec::new();
+        while let Some(offset) = section.take() {
+            if seen.contains(&offset) || offset >= data.len() {
+                return Err(PdfError::Malformed("broken cross-reference chain"));
+            }
+            seen.push(offset);
+            let trailer = document.read_xref(offset)?;
+            if document.trailer.0.is_empty() {
+                document.trailer = trailer.clone();
+            }
+            section = trailer
+                .get("Prev")
+                .and_then(Object::number)
+                .map(|p| p as usize);
+        }
+        document.size = document
+            .trailer
+            .get("Size")
+            .and_then(Object::number)
+            .ok_or(PdfError::Malformed("no size in the trailer"))? as u32;
+        Ok(document)
+    }
+
+    /// Reads the cross-reference section at `offset`, returning its trailer
+    fn read_xref(&mut self, offset: usize) -> Result<Dict, PdfError> {
+        let mut parser = Parser::new(self.data, offset);
+        if parser.word() != b"xref" {
+            return Err(PdfError::Unsupported("cross-reference streams"));
+        }
+        loop {
+            let save = parser.pos;
+            if parser.word() == b"trailer" {
+                break;
+            }
+            parser.pos = save;
+            let first: u32 = parser.integer()?;
+            let count: u32 = parser.integer()?;
+            let end = first
+                .checked_add(count)
+                .ok_or(PdfError::Malformed("cross-reference section"))?;
+            for num in first..end {
+                let offset: usize = parser.integer()?;
+                let gen: u16 = parser.integer()?;
+                let used = parser.word() == b"n";
+                if used {
+                    self.offsets.entry(num).or_insert((offset, gen));
+                }
+            }
+        }
+        match parser.object()? {
+            Object::Dict(trailer) => Ok(trailer),
+            _ => Err(PdfError::Malformed("trailer isn't a dictionary")),
+        }
+    }
+
+    fn object(&self, num: u32, gen: u16) -> Result<Object, PdfError> {
+        let &(offset, found) = self
+            .offsets
+            .get(&num)
+            .ok_or(PdfError::Unsupported("objects in object streams"))?;
+        if offset >= self.data.len() {
+            return Err(PdfError::Malformed("object past the end of the file"));
+        }
+        let mut parser = Parser::new(self.data, offset);
+        if parser.integer::<u32>()? != num || parser.integer::<u16>()? != gen || found != gen {
+            return Err(PdfError::Malformed("cross-reference table is off"));
+        }
+        parser.expect(b"obj")?;
+        parser.object()
+    }
+
+    fn resolve(&self, object: &Object) -> Result<Object, PdfError> {
+        match object {
+            Object::Ref(num, gen) => self.object(*num, *gen),
+            other => Ok(other.clone()),
+        }
+    }
+
+    fn dict(&self, object: &Object) -> Result<Dict, PdfError> {
+        match self.resolve(object)? {
+            Object::Dict(dict) => Ok(dict),
+            _ => Err(PdfError::Malformed("expected a dictionary")),
+        }
+    }
+
+    /// The pages in order, walking the page tree
+    fn pages(&self) -> Result<Vec<Page>, PdfError> {
+        let root = self
+            .trailer
+            .get("Root")
+            .ok_or(PdfError::Malformed("no catalog"))?;
+        let tree = self
+            .dict(root)?
+            .get("Pages")
+            .cloned()
+            .ok_or(PdfError::Malformed("no page tree"))?;
+        let mut pages = Vec::new();
+        // Nodes to visit, last first, with the media box inherited by them
+        let mut stack = vec![(tree, None)];
+        let mut visited = 0;
+        while let Some((node, inherited)) = stack.pop() {
+            let (num, gen) = match node {
+                Object::Ref(num, gen) => (num, gen),
+                _ => return Err(PdfError::Malformed("page tree node isn't a reference")),
+            };
+            visited += 1;
+            if visited > self.offsets.len() {
+                return Err(PdfError::Malformed("page tree has a cycle"));
+            }
+            let dict = self.object(num, gen).and_then(|o| self.dict(&o))?;
+            let media_box = match dict.get("MediaBox") {
+                Some(media_box) => match self.resolve(media_box)? {
+                    Object::Array(values) if values.len() == 4 => {
+                        let mut rect = [0.0; 4];
+                        for (value, item) in rect.iter_mut().zip(&values) {
+                            *value = self.resolve(item)?.number().ok_or(MALFORMED)? as f32;
+                        }
+                        Some(rect)
+                    }
+                    _ => return Err(PdfError::Malformed("media box")),
+                },
+                None => inherited,
+            };
+            match dict.get("Type") {
+                Some(Object::Name(kind)) if kind == "Pages" => {
+                    let kids = match dict.get("Kids").map(|k| self.resolve(k)).transpose()? {
+                        Some(Object::Array(kids)) => kids,
+                        _ => return Err(PdfError::Malformed("page tree node without kids")),
+                    };
+                    stack.extend(kids.into_iter().rev().map(|kid| (kid, media_box)));
+                }
+                _ => pages.push(Page {
+                    num,
+                    gen,
+                    dict,
+                    // US Letter, the default of the PDF specification
+                    media_box: media_box.unwrap_or([0.0, 0.0, 612.0, 792.0]),
+                }),
+            }
+        }
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::common::color;
+    use crate::stroke::StrokePoint;
+
+    /// A document with two pages of 200 by 100 points, inheriting their media box
+    fn two_pages() -> Vec<u8> {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>",
+            "<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 /MediaBox [0 0 200 100] >>",
+            "<< /Type /Page /Parent 2 0 R /Annots [5 0 R] >>",
+            "<< /Type /Page /Parent 2 0 R /Contents 6 0 R >>",
+            "<< /Type /Annot /Subtype /Text /Rect [0 0 10 10] /Contents (a (nested) \\) note) >>",
+            "<< /Length 0 >>\nstream\n\nendstream",
+        ];
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(b"xref\n0 7\n0000000000 65535 f\r\n");
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size 7 /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                xref
+            )
+            .as_bytes(),
+        );
+        pdf
+    }
+
+    #[test]
+    fn test_ink_annotations() {
+        let mut pdf = two_pages();
+        let mut layer = AnnotationLayer::new();
+        let stroke = |points: &[(f32, f32)]| {
+            let points = points
+                .iter()
+                .map(|&(x, y)| StrokePoint::new(x, y))
+                .collect();
+            Stroke::new(points, 2.0, color::BLACK)
+        };
+        layer.add(0, stroke(&[(10.0, 10.0), (50.0, 20.0)]));
+        layer.add(1, stroke(&[(30.0, 40.0)]));
+        let update = layer.update(&pdf).unwrap();
+        pdf.extend_from_slice(&update);
+
+        // Annotating the result again builds on the update
+        let document = Document::parse(&pdf).unwrap();
+        assert_eq!(document.size, 11);
+        let pages = document.pages().unwrap();
+        assert_eq!(pages.len(), 2);
+        let annots = match pages[0].dict.get("Annots") {
+            Some(Object::Array(annots)) => annots.clone(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(annots.len(), 2);
+        let ink = document.dict(&annots[1]).unwrap();
+        assert_eq!(ink.get("Subtype"), Some(&Object::Name("Ink".to_owned())));
+        // The y axis points up in PDFs
+        let ink_list = Object::Array(vec![Object::Array(
+            [10.0, 90.0, 50.0, 80.0].map(Object::Number).to_vec(),
+        )]);
+        assert_eq!(ink.get("InkList"), Some(&ink_list));
+        let note = document.dict(&annots[0]).unwrap();
+        assert_eq!(
+            note.get("Contents"),
+            Some(&Object::String(b"(a (nested) \\) note)".to_vec()))
+        );
+        assert!(matches!(pages[1].dict.get("Annots"), Some(Object::Array(a)) if a.len() == 1));
+        assert!(layer.update(&pdf).is_ok());
+
+        layer.add(2, stroke(&[(0.0, 0.0)]));
+        assert!(matches!(layer.update(&pdf), Err(PdfError::NoSuchPage(2))));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let pdf = two_pages();
+        let malformed = |pdf: &[u8]| match AnnotationLayer::new().update(pdf) {
+            Err(PdfError::Malformed(reason)) => reason,
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        };
+
+        // An object offset past the end of the file
+        let xref = pdf.windows(5).position(|w| w == b"xref\n").unwrap();
+        let at = xref + "xref\n0 7\n0000000000 65535 f\r\n".len();
+        let mut broken = pdf.clone();
+        broken[at..at + 10].copy_from_slice(b"9999999999");
+        assert_eq!(malformed(&broken), "object past the end of the file");
+
+        // A section of objects beyond the highest object number
+        let mut broken = pdf.clone();
+        let section = xref + "xref\n".len();
+        broken.splice(section..section + 3, b"4294967295 7".iter().copied());
+        assert_eq!(malformed(&broken), "cross-reference section");
+
+        let nested = [b"[".repeat(100_000), b"]".repeat(100_000)].concat();
+        let mut parser = Parser::new(&nested, 0);
+        assert!(matches!(
+            parser.object(),
+            Err(PdfError::Malformed("objects nested too deeply"))
+        ));
+        let nested = [b"[".repeat(MAX_DEPTH), b"]".repeat(MAX_DEPTH)].concat();
+        assert!(Parser::new(&nested, 0).object().is_ok());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::stroke::Stroke;
+
+/// Recognizes the zig-zag "scratch-out" gesture used to delete what's under it.
+///
+/// A stroke is a scratch-out when it goes back and forth along one axis several
+/// times, each pass long compared to how far the stroke drifts across that axis.
+/// This keeps handwriting like "mmm" or "www" from being mistaken for one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScratchOutDetector {
+    /// Direction changes needed, e.g. 4 for five passes
+    pub min_reversals: usize,
+    /// Minimum length of a pass in pixels, smaller wiggles are ignored
+    pub min_pass: f32,
+    /// How many times longer the passes have to be in total than the stroke drifts
+    /// across the axis
+    pub min_aspect: f32,
+}
+
+impl Default for ScratchOutDetector {
+    fn default() -> ScratchOutDetector {
+        ScratchOutDetector {
+            min_reversals: 4,
+            min_pass: 60.0,
+            min_aspect: 5.0,
+        }
+    }
+}
+
+impl ScratchOutDetector {
+    /// The area scratched out by `stroke`, if it is a scratch-out
+    pub fn detect(&self, stroke: &Stroke) -> Option<mxcfb_rect> {
+        let horizontal = self.is_zig_zag(stroke, |p| (p.x, p.y));
+        let vertical = self.is_zig_zag(stroke, |p| (p.y, p.x));
+        match horizontal || vertical {
+            true => Some(stroke.bounding_rect()),
+            false => None,
+        }
+    }
+
+    /// Whether `stroke` goes back and forth along the first coordinate of `axes`
+    fn is_zig_zag<F>(&self, stroke: &Stroke, axes: F) -> bool
+    where
+        F: Fn(cgmath::Point2<f32>) -> (f32, f32),
+    {
+        let mut points = stroke.points.iter().map(|p| axes(p.position));
+        let first = match points.next() {
+            Some(first) => first,
+            None => return false,
+        };
+
+        // Start and extreme point of the current pass, and the direction it goes in
+        let mut start = first;
+        let mut extreme = first;
+        let mut direction = 0.0f32;
+        let mut passes = Vec::new();
+        for p in points {
+            let advance = p.0 - extreme.0;
+            if direction == 0.0 {
+                if advance.abs() >= self.min_pass {
+                    direction = advance.signum();
+                    extreme = p;
+                }
+            } else if advance * direction > 0.0 {
+                extreme = p;
+            } else if advance.abs() >= self.min_pass {
+                // Turned around far enough to count as the next pass
+                passes.push((start, extreme));
+                start = extreme;
+                extreme = p;
+                direction = -direction;
+            }
+        }
+        if direction != 0.0 {
+            passes.push((start, extreme));
+        }
+
+        if passes.len() <= self.min_reversals {
+            return false;
+        }
+        let along: f32 = passes.iter().map(|(a, b)| (b.0 - a.0).abs()).sum();
+        let drift = (passes[passes.len() - 1].1 .1 - passes[0].0 .1).abs();
+        along >= self.min_aspect * drift
+    }
+}
+
+/// The strokes that are mostly inside `region`, at least `min_share` of their points,
+/// e.g. to delete those under a scratch-out
+pub fn covered_strokes(region: &mxcfb_rect, strokes: &[Stroke], min_share: f32) -> Vec<usize> {
+    let inside = |p: cgmath::Point2<f32>| {
+        p.x >= region.left as f32
+            && p.y >= region.top as f32
+            && p.x <= (region.left + region.width) as f32
+            && p.y <= (region.top + region.height) as f32
+    };
+    strokes
+        .iter()
+        .enumerate()
+        .filter(|(_, stroke)| {
+            let covered = stroke.points.iter().filter(|p| inside(p.position)).count();
+            !stroke.points.is_empty() && covered as f32 >= min_share * stroke.points.len() as f32
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::common::color;
+    use crate::stroke::StrokePoint;
+
+    fn stroke(points: &[(f32, f32)]) -> Stroke {
+        let points = points
+            .iter()
+            .map(|&(x, y)| StrokePoint::new(x, y))
+            .collect();
+        Stroke::new(points, 2.0, color::BLACK)
+    }
+
+    /// Samples straight lines between the `corners` every few pixels
+    fn polyline(corners: &[(f32, f32)]) -> Stroke {
+        let mut points = vec![corners[0]];
+        for pair in corners.windows(2) {
+            for i in 1..=10 {
+                let t = i as f32 / 10.0;
+                points.push((
+                    pair[0].0 + (pair[1].0 - pair[0].0) * t,
+                    pair[0].1 + (pair[1].1 - pair[0].1) * t,
+                ));
+            }
+        }
+        stroke(&points)
+    }
+
+    #[test]
+    fn test_scratch_out() {
+        let detector = ScratchOutDetector::default();
+        let scratch = polyline(&[
+            (100.0, 100.0),
+            (300.0, 105.0),
+            (105.0, 112.0),
+            (298.0, 120.0),
+            (102.0, 126.0),
+            (301.0, 131.0),
+        ]);
+        let rect = detector.detect(&scratch).unwrap();
+        assert!(rect.contains_point(&cgmath::Point2 { x: 200, y: 115 }));
+
+        // The same zig-zag vertically
+        let vertical = polyline(&[
+            (100.0, 100.0),
+            (105.0, 300.0),
+            (112.0, 105.0),
+            (120.0, 298.0),
+            (126.0, 102.0),
+            (131.0, 301.0),
+        ]);
+        assert!(detector.detect(&vertical).is_some());
+
+        // Too few passes, a plain line, and handwriting like "www"
+        let underline = polyline(&[(100.0, 100.0), (300.0, 100.0), (100.0, 100.0)]);
+        assert!(detector.detect(&underline).is_none());
+        assert!(detector
+            .detect(&polyline(&[(0.0, 0.0), (500.0, 10.0)]))
+            .is_none());
+        let www = polyline(&[
+            (0.0, 0.0),
+            (10.0, 40.0),
+            (20.0, 0.0),
+            (30.0, 40.0),
+            (40.0, 0.0),
+            (50.0, 40.0),
+            (60.0, 0.0),
+            (70.0, 40.0),
+            (80.0, 0.0),
+        ]);
+        assert!(detector.detect(&www).is_none());
+        assert!(detector.detect(&stroke(&[])).is_none());
+    }
+
+    #[test]
+    fn test_covered_strokes() {
+        let region = mxcfb_rect {
+            left: 100,
+            top: 100,
+            width: 200,
+            height: 50,
+        };
+        let strokes = [
+            stroke(&[(120.0, 110.0), (200.0, 120.0)]),
+            stroke(&[(120.0, 110.0), (500.0, 500.0), (600.0, 600.0)]),
+            stroke(&[]),
+        ];
+        assert_eq!(covered_strokes(&region, &strokes, 0.8), vec![0]);
+    }
+}
@@ -0,0 +1,748 @@
+use std::fmt::Write as _;
+use std::io;
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::cgmath::{EuclideanSpace, InnerSpace};
+use crate::framebuffer::common::color;
+use crate::stroke::{Stroke, StrokePoint};
+
+/// Default flattening tolerance in pixels used by `parse_svg`
+pub const DEFAULT_TOLERANCE: f32 = 0.5;
+
+/// Differentiate between the reasons why importing SVG data can fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A character that isn't valid in path data was found at the given byte offset
+    UnexpectedCharacter(usize, char),
+    /// A command was missing some of its arguments
+    MissingArguments(char),
+    /// Path data has to start with a moveto command
+    MissingMoveTo,
+    /// A tag was opened but never closed
+    UnterminatedTag,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedCharacter(ofst, c) => {
+                write!(f, "Unexpected character '{}' at offset {}", c, ofst)
+            }
+            ParseError::MissingArguments(cmd) => {
+                write!(f, "Missing arguments for path command '{}'", cmd)
+            }
+            ParseError::MissingMoveTo => write!(f, "Path data must begin with a moveto"),
+            ParseError::UnterminatedTag => write!(f, "Unterminated tag"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Formats `c` as an SVG hex color (`#rrggbb`)
+fn hex_color(c: color) -> String {
+    let [r, g, b] = c.to_rgb8();
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Appends the SVG elements for a single stroke to `out`.
+///
+/// Strokes drawn with a constant pressure become a single `<path>`. Pressure
+/// sensitive strokes are split into one segment per pair of points so that each
+/// segment can carry its own `stroke-width`.
+fn write_stroke(out: &mut String, stroke: &Stroke) {
+    let col = hex_color(stroke.color);
+    match stroke.points.len() {
+        0 => {}
+        1 => {
+            let p = stroke.points[0].position;
+            let _ = writeln!(
+                out,
+                r#"  <circle cx="{:.2}" cy="{:.2}" r="{:.2}" fill="{col}"/>"#,
+                p.x,
+                p.y,
+                stroke.width_at(0) / 2.0
+            );
+        }
+        _ if stroke.has_uniform_width() => {
+            let mut d = String::new();
+            for (i, p) in stroke.points.iter().enumerate() {
+                let cmd = if i == 0 { 'M' } else { 'L' };
+                let _ = write!(d, "{cmd}{:.2} {:.2} ", p.position.x, p.position.y);
+            }
+            let _ = writeln!(
+                out,
+                r#"  <path d="{}" fill="none" stroke="{col}" stroke-width="{:.2}" stroke-linecap="round" stroke-linejoin="round"/>"#,
+                d.trim_end(),
+                stroke.width_at(0)
+            );
+        }
+        _ => {
+            let _ = writeln!(
+                out,
+                r#"  <g fill="none" stroke="{col}" stroke-linecap="round">"#
+            );
+            for (i, pair) in stroke.points.windows(2).enumerate() {
+                let width = (stroke.width_at(i) + stroke.width_at(i + 1)) / 2.0;
+                let _ = writeln!(
+                    out,
+                    r#"    <line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke-width="{:.2}"/>"#,
+                    pair[0].position.x,
+                    pair[0].position.y,
+                    pair[1].position.x,
+                    pair[1].position.y,
+                    width
+                );
+            }
+            let _ = writeln!(out, "  </g>");
+        }
+    }
+}
+
+/// Renders `strokes` into a standalone SVG document of the given `size`
+/// (typically the size of the framebuffer the strokes were captured on).
+pub fn to_svg(strokes: &[Stroke], size: cgmath::Vector2<u32>) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = size.x,
+        h = size.y
+    );
+    for stroke in strokes {
+        write_stroke(&mut out, stroke);
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Same as `to_svg` but writes the document into `writer`
+pub fn write_svg<W: io::Write>(
+    writer: &mut W,
+    strokes: &[Stroke],
+    size: cgmath::Vector2<u32>,
+) -> io::Result<()> {
+    writer.write_all(to_svg(strokes, size).as_bytes())
+}
+
+/// Splits path data into command letters and numbers
+struct PathTokenizer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+enum PathToken {
+    Command(char),
+    Number(f32),
+}
+
+impl<'a> PathTokenizer<'a> {
+    fn skip_separators(&mut self) {
+        while self.pos < self.data.len()
+            && (self.data[self.pos].is_ascii_whitespace() || self.data[self.pos] == b',')
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// The character starting at the byte offset `pos`
+    fn char_at(&self, pos: usize) -> char {
+        std::str::from_utf8(&self.data[pos..])
+            .ok()
+            .and_then(|rest| rest.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+
+    /// The next token and its byte offset
+    fn next_token(&mut self) -> Result<Option<(usize, PathToken)>, ParseError> {
+        self.skip_separators();
+        let start = self.pos;
+        let c = match self.data.get(start) {
+            None => return Ok(None),
+            Some(c) => *c,
+        };
+        if c.is_ascii_alphabetic() && c != b'e' && c != b'E' {
+            self.pos += 1;
+            return Ok(Some((start, PathToken::Command(c as char))));
+        }
+
+        // Numbers may directly follow each other as in "10-5" or ".5.5"
+        let mut end = start;
+        if matches!(self.data[end], b'+' | b'-') {
+            end += 1;
+        }
+        let mut seen_dot = false;
+        let mut seen_digit = false;
+        while end < self.data.len() {
+            match self.data[end] {
+                b'0'..=b'9' => seen_digit = true,
+                b'.' if !seen_dot => seen_dot = true,
+                b'e' | b'E' if seen_digit => {
+                    end += 1;
+                    if end < self.data.len() && matches!(self.data[end], b'+' | b'-') {
+                        end += 1;
+                    }
+                    while end < self.data.len() && self.data[end].is_ascii_digit() {
+                        end += 1;
+                    }
+                    break;
+                }
+                _ => break,
+            }
+            end += 1;
+        }
+        if !seen_digit {
+            return Err(ParseError::UnexpectedCharacter(start, self.char_at(start)));
+        }
+        self.pos = end;
+        let text = std::str::from_utf8(&self.data[start..end]).unwrap_or_default();
+        text.parse::<f32>()
+            .map(|n| Some((start, PathToken::Number(n))))
+            .map_err(|_| ParseError::UnexpectedCharacter(start, self.char_at(start)))
+    }
+}
+
+/// Number of line segments needed to approximate a curve whose control polygon
+/// has the given length within `tolerance`
+fn segment_count(control_length: f32, tolerance: f32) -> usize {
+    ((control_length / tolerance.max(0.01)).sqrt().ceil() as usize).clamp(1, 256)
+}
+
+/// Flattens a single elliptical arc (SVG endpoint parameterization) into `out`.
+#[allow(clippy::too_many_arguments)]
+fn flatten_arc(
+    out: &mut Vec<cgmath::Point2<f32>>,
+    from: cgmath::Point2<f32>,
+    mut rx: f32,
+    mut ry: f32,
+    x_axis_rotation: f32,
+    large_arc: bool,
+    sweep: bool,
+    to: cgmath::Point2<f32>,
+    tolerance: f32,
+) {
+    rx = rx.abs();
+    ry = ry.abs();
+    if rx == 0.0 || ry == 0.0 || from == to {
+        out.push(to);
+        return;
+    }
+    // See https://www.w3.org/TR/SVG/implnote.html#ArcConversionEndpointToCenter
+    let (sin_phi, cos_phi) = x_axis_rotation.to_radians().sin_cos();
+    let dx = (from.x - to.x) / 2.0;
+    let dy = (from.y - to.y) / 2.0;
+    let x1 = cos_phi * dx + sin_phi * dy;
+    let y1 = -sin_phi * dx + cos_phi * dy;
+
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let num = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let den = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let mut coef = (num / den).max(0.0).sqrt();
+    if large_arc == sweep {
+        coef = -coef;
+    }
+    let cx1 = coef * rx * y1 / ry;
+    let cy1 = -coef * ry * x1 / rx;
+    let cx = cos_phi * cx1 - sin_phi * cy1 + (from.x + to.x) / 2.0;
+    let cy = sin_phi * cx1 + cos_phi * cy1 + (from.y + to.y) / 2.0;
+
+    let angle = |ux: f32, uy: f32, vx: f32, vy: f32| {
+        let sign = if ux * vy - uy * vx < 0.0 { -1.0 } else { 1.0 };
+        let dot = (ux * vx + uy * vy) / ((ux * ux + uy * uy).sqrt() * (vx * vx + vy * vy).sqrt());
+        sign * dot.clamp(-1.0, 1.0).acos()
+    };
+    let theta1 = angle(1.0, 0.0, (x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut delta = angle(
+        (x1 - cx1) / rx,
+        (y1 - cy1) / ry,
+        (-x1 - cx1) / rx,
+        (-y1 - cy1) / ry,
+    );
+    if !sweep && delta > 0.0 {
+        delta -= std::f32::consts::TAU;
+    } else if sweep && delta < 0.0 {
+        delta += std::f32::consts::TAU;
+    }
+
+    let steps = segment_count(rx.max(ry) * delta.abs(), tolerance);
+    for i in 1..=steps {
+        let theta = theta1 + delta * (i as f32 / steps as f32);
+        let (sin_t, cos_t) = theta.sin_cos();
+        out.push(cgmath::Point2 {
+            x: cx + rx * cos_t * cos_phi - ry * sin_t * sin_phi,
+            y: cy + rx * cos_t * sin_phi + ry * sin_t * cos_phi,
+        });
+    }
+}
+
+/// Parses SVG path data (the `d` attribute of a `<path>`) and flattens it into
+/// polylines, one per subpath. Curves and arcs are approximated with line segments
+/// that deviate no more than roughly `tolerance` pixels from the original curve.
+pub fn parse_path_data(
+    d: &str,
+    tolerance: f32,
+) -> Result<Vec<Vec<cgmath::Point2<f32>>>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut tokenizer = PathTokenizer {
+        data: d.as_bytes(),
+        pos: 0,
+    };
+    while let Some(token) = tokenizer.next_token()? {
+        tokens.push(token);
+    }
+
+    let mut subpaths: Vec<Vec<cgmath::Point2<f32>>> = Vec::new();
+    let mut current: Vec<cgmath::Point2<f32>> = Vec::new();
+    let mut pos = cgmath::Point2 { x: 0.0, y: 0.0 };
+    let mut subpath_start = pos;
+    // Reflected control points for the S and T shorthands
+    let mut last_cubic_ctrl: Option<cgmath::Point2<f32>> = None;
+    let mut last_quad_ctrl: Option<cgmath::Point2<f32>> = None;
+
+    let mut i = 0;
+    let mut cmd: Option<char> = None;
+    // Byte offset of the command
+    let mut cmd_offset = 0;
+    while i < tokens.len() {
+        if let (offset, PathToken::Command(c)) = tokens[i] {
+            if cmd.is_none() && c != 'M' && c != 'm' {
+                return Err(ParseError::MissingMoveTo);
+            }
+            cmd = Some(c);
+            cmd_offset = offset;
+            i += 1;
+            if c == 'Z' || c == 'z' {
+                if !current.is_empty() {
+                    current.push(subpath_start);
+                    subpaths.push(std::mem::take(&mut current));
+                }
+                pos = subpath_start;
+                last_cubic_ctrl = None;
+                last_quad_ctrl = None;
+                continue;
+            }
+        }
+        let c = cmd.ok_or(ParseError::MissingMoveTo)?;
+        if c == 'Z' || c == 'z' {
+            // Numbers following a closepath without a new command
+            return Err(ParseError::MissingArguments(c));
+        }
+        let argc = match c.to_ascii_uppercase() {
+            'M' | 'L' | 'T' => 2,
+            'H' | 'V' => 1,
+            'S' | 'Q' => 4,
+            'C' => 6,
+            'A' => 7,
+            _ => return Err(ParseError::UnexpectedCharacter(cmd_offset, c)),
+        };
+        let mut args = [0.0f32; 7];
+        for arg in args.iter_mut().take(argc) {
+            match tokens.get(i) {
+                Some((_, PathToken::Number(n))) => *arg = *n,
+                _ => return Err(ParseError::MissingArguments(c)),
+            }
+            i += 1;
+        }
+        let relative = c.is_ascii_lowercase();
+        let abs = |x: f32, y: f32| {
+            if relative {
+                cgmath::Point2 {
+                    x: pos.x + x,
+                    y: pos.y + y,
+                }
+            } else {
+                cgmath::Point2 { x, y }
+            }
+        };
+
+        let mut cubic_ctrl = None;
+        let mut quad_ctrl = None;
+        match c.to_ascii_uppercase() {
+            'M' => {
+                if current.len() > 1 {
+                    subpaths.push(std::mem::take(&mut current));
+                }
+                current.clear();
+                pos = abs(args[0], args[1]);
+                subpath_start = pos;
+                current.push(pos);
+                // Subsequent pairs are implicit lineto commands
+                cmd = Some(if relative { 'l' } else { 'L' });
+            }
+            'L' => {
+                pos = abs(args[0], args[1]);
+                current.push(pos);
+            }
+            'H' => {
+                pos.x = if relative { pos.x + args[0] } else { args[0] };
+                current.push(pos);
+            }
+            'V' => {
+                pos.y = if relative { pos.y + args[0] } else { args[0] };
+                current.push(pos);
+            }
+            'C' | 'S' => {
+                let (c1, c2, end) = if c.eq_ignore_ascii_case(&'C') {
+                    (
+                        abs(args[0], args[1]),
+                        abs(args[2], args[3]),
+                        abs(args[4], args[5]),
+                    )
+                } else {
+                    let c1 = match last_cubic_ctrl {
+                        Some(prev) => pos + (pos - prev),
+                        None => pos,
+                    };
+                    (c1, abs(args[0], args[1]), abs(args[2], args[3]))
+                };
+                let length =
+                    (c1 - pos).magnitude() + (c2 - c1).magnitude() + (end - c2).magnitude();
+                let steps = segment_count(length, tolerance);
+                for s in 1..=steps {
+                    let t = s as f32 / steps as f32;
+                    let mt = 1.0 - t;
+                    current.push(cgmath::Point2::from_vec(
+                        pos.to_vec() * (mt * mt * mt)
+                            + c1.to_vec() * (3.0 * mt * mt * t)
+                            + c2.to_vec() * (3.0 * mt * t * t)
+                            + end.to_vec() * (t * t * t),
+                    ));
+                }
+                cubic_ctrl = Some(c2);
+                pos = end;
+            }
+            'Q' | 'T' => {
+                let (ctrl, end) = if c.eq_ignore_ascii_case(&'Q') {
+                    (abs(args[0], args[1]), abs(args[2], args[3]))
+                } else {
+                    let ctrl = match last_quad_ctrl {
+                        Some(prev) => pos + (pos - prev),
+                        None => pos,
+                    };
+                    (ctrl, abs(args[0], args[1]))
+                };
+                let length = (ctrl - pos).magnitude() + (end - ctrl).magnitude();
+                let steps = segment_count(length, tolerance);
+                for s in 1..=steps {
+                    let t = s as f32 / steps as f32;
+                    let mt = 1.0 - t;
+                    current.push(cgmath::Point2::from_vec(
+                        pos.to_vec() * (mt * mt)
+                            + ctrl.to_vec() * (2.0 * mt * t)
+                            + end.to_vec() * (t * t),
+                    ));
+                }
+                quad_ctrl = Some(ctrl);
+                pos = end;
+            }
+            'A' => {
+                let end = abs(args[5], args[6]);
+                flatten_arc(
+                    &mut current,
+                    pos,
+                    args[0],
+                    args[1],
+                    args[2],
+                    args[3] != 0.0,
+                    args[4] != 0.0,
+                    end,
+                    tolerance,
+                );
+                pos = end;
+            }
+            _ => unreachable!(),
+        }
+        last_cubic_ctrl = cubic_ctrl;
+        last_quad_ctrl = quad_ctrl;
+    }
+    if current.len() > 1 {
+        subpaths.push(current);
+    }
+    Ok(subpaths)
+}
+
+/// Parses a CSS color as used in SVG presentation attributes. Returns `None`
+/// for `none` and for anything that isn't understood.
+pub(crate) fn parse_color(value: &str) -> Option<color> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        let digits: Vec<u8> = hex
+            .chars()
+            .filter_map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect();
+        return match digits.len() {
+            3 => Some(color::RGB(digits[0] * 17, digits[1] * 17, digits[2] * 17)),
+            6 => Some(color::RGB(
+                digits[0] * 16 + digits[1],
+                digits[2] * 16 + digits[3],
+                digits[4] * 16 + digits[5],
+            )),
+            _ => None,
+        };
+    }
+    if let Some(args) = value
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let parts: Vec<u8> = args
+            .split(',')
+            .filter_map(|p| p.trim().parse::<u8>().ok())
+            .collect();
+        return match parts[..] {
+            [r, g, b] => Some(color::RGB(r, g, b)),
+            _ => None,
+        };
+    }
+    match value {
+        "black" => Some(color::BLACK),
+        "white" => Some(color::WHITE),
+        "red" => Some(color::RED),
+        "green" | "lime" => Some(color::GREEN),
+        "blue" => Some(color::BLUE),
+        "gray" | "grey" => Some(color::RGB(128, 128, 128)),
+        _ => None,
+    }
+}
+
+/// Returns the value of the attribute `name` inside the contents of a tag
+pub(crate) fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(idx) = rest.find(name) {
+        let before = rest[..idx].chars().last();
+        let after = rest[idx + name.len()..].trim_start();
+        rest = &rest[idx + name.len()..];
+        if !matches!(before, Some(c) if c.is_whitespace()) {
+            continue;
+        }
+        if let Some(value) = after.strip_prefix('=') {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            if quote != '"' && quote != '\'' {
+                continue;
+            }
+            let end = value[1..].find(quote)?;
+            return Some(&value[1..end + 1]);
+        }
+    }
+    None
+}
+
+/// Presentation attributes that are inherited from enclosing `<g>` elements
+#[derive(Clone, Copy)]
+struct Style {
+    stroke: Option<color>,
+    fill: Option<color>,
+    width: f32,
+}
+
+impl Style {
+    fn apply(&self, tag: &str) -> Style {
+        let mut style = *self;
+        if let Some(v) = attribute(tag, "stroke") {
+            style.stroke = parse_color(v);
+        }
+        if let Some(v) = attribute(tag, "fill") {
+            style.fill = parse_color(v);
+        }
+        if let Some(w) = attribute(tag, "stroke-width")
+            .and_then(|v| v.trim_end_matches("px").parse::<f32>().ok())
+        {
+            style.width = w;
+        }
+        style
+    }
+
+    /// Filled shapes without a stroke are imported as their outline
+    fn color(&self) -> color {
+        self.stroke.or(self.fill).unwrap_or(color::BLACK)
+    }
+}
+
+/// Imports the `<path>`, `<line>` and `<polyline>` elements of an SVG document as
+/// strokes. Stroke color and width (including those inherited from `<g>` elements)
+/// are preserved, transforms and CSS styling are not applied.
+pub fn parse_svg(svg: &str) -> Result<Vec<Stroke>, ParseError> {
+    parse_svg_with_tolerance(svg, DEFAULT_TOLERANCE)
+}
+
+/// Same as `parse_svg` with a custom flattening `tolerance` in pixels
+pub fn parse_svg_with_tolerance(svg: &str, tolerance: f32) -> Result<Vec<Stroke>, ParseError> {
+    let mut strokes = Vec::new();
+    let mut styles = vec![Style {
+        stroke: None,
+        fill: Some(color::BLACK),
+        width: 1.0,
+    }];
+
+    let mut rest = svg;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").ok_or(ParseError::UnterminatedTag)?;
+            rest = &rest[end + 3..];
+            continue;
+        }
+        let end = rest.find('>').ok_or(ParseError::UnterminatedTag)?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let parent = *styles.last().unwrap();
+
+        if tag.starts_with('/') {
+            if name == "g" && styles.len() > 1 {
+                styles.pop();
+            }
+            continue;
+        }
+
+        let polylines = match name {
+            "g" => {
+                if !self_closing {
+                    styles.push(parent.apply(tag));
+                }
+                continue;
+            }
+            "path" => match attribute(tag, "d") {
+                Some(d) => parse_path_data(d, tolerance)?,
+                None => continue,
+            },
+            "line" => {
+                let coord = |n| {
+                    attribute(tag, n)
+                        .and_then(|v| v.parse::<f32>().ok())
+                        .unwrap_or(0.0)
+                };
+                vec![vec![
+                    cgmath::Point2 {
+                        x: coord("x1"),
+                        y: coord("y1"),
+                    },
+                    cgmath::Point2 {
+                        x: coord("x2"),
+                        y: coord("y2"),
+                    },
+                ]]
+            }
+            "polyline" | "polygon" => {
+                let d = match attribute(tag, "points") {
+                    Some(points) if name == "polygon" => format!("M{points}Z"),
+                    Some(points) => format!("M{points}"),
+                    None => continue,
+                };
+                parse_path_data(&d, tolerance)?
+            }
+            _ => continue,
+        };
+
+        let style = parent.apply(tag);
+        for polyline in polylines {
+            let points = polyline
+                .into_iter()
+                .map(|p| StrokePoint::new(p.x, p.y))
+                .collect();
+            strokes.push(Stroke::new(points, style.width, style.color()));
+        }
+    }
+    Ok(strokes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stroke::StrokePoint;
+
+    #[test]
+    fn test_uniform_stroke_is_single_path() {
+        let stroke = Stroke::new(
+            vec![StrokePoint::new(10.0, 10.0), StrokePoint::new(20.0, 30.0)],
+            4.0,
+            color::BLACK,
+        );
+        let svg = to_svg(&[stroke], cgmath::Vector2 { x: 1404, y: 1872 });
+        assert!(svg.contains(r#"width="1404" height="1872""#));
+        assert!(svg.contains(r#"d="M10.00 10.00 L20.00 30.00""#));
+        assert!(svg.contains(r##"stroke="#000000" stroke-width="4.00""##));
+    }
+
+    #[test]
+    fn test_pressure_sensitive_stroke_keeps_widths() {
+        let mut points = vec![StrokePoint::new(0.0, 0.0), StrokePoint::new(10.0, 0.0)];
+        points[1].pressure = 0.5;
+        let svg = to_svg(
+            &[Stroke::new(points, 4.0, color::RED)],
+            cgmath::Vector2 { x: 100, y: 100 },
+        );
+        assert!(svg.contains(r##"stroke="#ff0000""##));
+        assert!(svg.contains(r#"stroke-width="3.00""#));
+    }
+
+    #[test]
+    fn test_parse_path_data_commands() {
+        let paths = parse_path_data("M10,10 h10 v-10 L0 0z m5 5 l1-1", 0.5).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(
+            paths[0],
+            vec![
+                cgmath::Point2 { x: 10.0, y: 10.0 },
+                cgmath::Point2 { x: 20.0, y: 10.0 },
+                cgmath::Point2 { x: 20.0, y: 0.0 },
+                cgmath::Point2 { x: 0.0, y: 0.0 },
+                cgmath::Point2 { x: 10.0, y: 10.0 },
+            ]
+        );
+        assert_eq!(paths[1][1], cgmath::Point2 { x: 16.0, y: 14.0 });
+
+        let curve = parse_path_data("M0 0 C 0 100 100 100 100 0", 0.5).unwrap();
+        assert!(curve[0].len() > 8);
+        assert_eq!(
+            *curve[0].last().unwrap(),
+            cgmath::Point2 { x: 100.0, y: 0.0 }
+        );
+
+        assert_eq!(
+            parse_path_data("L 10 10", 0.5),
+            Err(ParseError::MissingMoveTo)
+        );
+        // Byte offsets into the path data
+        assert_eq!(
+            parse_path_data("M 0 0 L 10 10 X 5", 0.5),
+            Err(ParseError::UnexpectedCharacter(14, 'X'))
+        );
+        assert_eq!(
+            parse_path_data("M 0 0 L 1é 2", 0.5),
+            Err(ParseError::UnexpectedCharacter(9, 'é'))
+        );
+    }
+
+    #[test]
+    fn test_svg_round_trip() {
+        let stroke = Stroke::new(
+            vec![
+                StrokePoint::new(1.0, 2.0),
+                StrokePoint::new(3.0, 4.0),
+                StrokePoint::new(5.0, 7.0),
+            ],
+            6.0,
+            color::BLACK,
+        );
+        let svg = to_svg(
+            std::slice::from_ref(&stroke),
+            cgmath::Vector2 { x: 100, y: 100 },
+        );
+        let imported = parse_svg(&svg).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].points, stroke.points);
+        assert_eq!(imported[0].width, stroke.width);
+        assert_eq!(imported[0].color.to_rgb8(), [0, 0, 0]);
+    }
+}
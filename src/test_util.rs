@@ -0,0 +1,42 @@
+//! Helpers shared by the tests of the crate.
+
+#[cfg(feature = "framebuffer-types")]
+use crate::framebuffer::common::mxcfb_rect;
+#[cfg(feature = "input-types")]
+use crate::input::{Finger, MultitouchEvent};
+
+#[cfg(feature = "framebuffer-types")]
+pub(crate) fn rect(left: u32, top: u32, width: u32, height: u32) -> mxcfb_rect {
+    mxcfb_rect {
+        left,
+        top,
+        width,
+        height,
+    }
+}
+
+/// The finger `id` at `x`, `y`, e.g. `touch(press, 1, 10, 20)`
+#[cfg(feature = "input-types")]
+pub(crate) fn touch(
+    event: fn(Finger) -> MultitouchEvent,
+    id: i32,
+    x: u16,
+    y: u16,
+) -> MultitouchEvent {
+    event(Finger::new(id, cgmath::Point2 { x, y }, true))
+}
+
+#[cfg(feature = "input-types")]
+pub(crate) fn press(finger: Finger) -> MultitouchEvent {
+    MultitouchEvent::Press { finger }
+}
+
+#[cfg(feature = "input-types")]
+pub(crate) fn moved(finger: Finger) -> MultitouchEvent {
+    MultitouchEvent::Move { finger }
+}
+
+#[cfg(feature = "input-types")]
+pub(crate) fn release(finger: Finger) -> MultitouchEvent {
+    MultitouchEvent::Release { finger }
+}
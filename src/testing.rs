@@ -0,0 +1,91 @@
+//! Assertions for rendering tests.
+//!
+//! `assert_matches_png` is the short form of `framebuffer::golden` for a single
+//! reference image, compared with `golden::perceptual_compare` so that antialiasing
+//! moving by a pixel doesn't fail a test while a stroke that went missing does:
+//!
+//! ```no_run
+//! # use libremarkable::framebuffer::common::{color, mxcfb_rect};
+//! # use libremarkable::framebuffer::core::Framebuffer;
+//! # use libremarkable::framebuffer::FramebufferDraw;
+//! # use libremarkable::testing::assert_matches_png;
+//! let mut fb = Framebuffer::headless(200, 100);
+//! fb.draw_text((10.0, 60.0).into(), "Hello", 40.0, color::BLACK, false);
+//! let region = mxcfb_rect { left: 0, top: 0, width: 200, height: 100 };
+//! assert_matches_png(&fb, region, "tests/golden/hello.png", 16);
+//! ```
+//!
+//! Missing references are created, like all others are updated, by running the tests
+//! with `LIBREMARKABLE_BLESS=1`. On a mismatch the actual image and the diff are
+//! written next to the temporary artifacts of `Golden`.
+
+use std::path::Path;
+
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::golden::Golden;
+use crate::framebuffer::FramebufferIO;
+
+/// Panics unless `region` of `fb` looks like the PNG at `path`, with brightness
+/// differing by up to `tolerance`, see the module documentation
+pub fn assert_matches_png<F: FramebufferIO + ?Sized>(
+    fb: &F,
+    region: mxcfb_rect,
+    path: impl AsRef<Path>,
+    tolerance: u8,
+) {
+    let path = path.as_ref();
+    let name = match path.file_stem() {
+        Some(name) => name.to_string_lossy(),
+        None => panic!("{} is not an image file", path.display()),
+    };
+    let mut golden = Golden::new(path.parent().unwrap_or_else(|| Path::new(".")));
+    golden.tolerance = tolerance;
+    golden.perceptual = true;
+    golden.assert_matches(&name, fb, region);
+}
+
+#[cfg(all(test, feature = "framebuffer-drawing"))]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::{Point2, Vector2};
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::core::Framebuffer;
+    use crate::framebuffer::golden::capture;
+    use crate::framebuffer::FramebufferDraw;
+
+    #[test]
+    fn test_assert_matches_png() {
+        let mut fb = Framebuffer::headless(40, 20);
+        fb.fill_rect(
+            Point2 { x: 10, y: 5 },
+            Vector2 { x: 20, y: 10 },
+            color::BLACK,
+        );
+        let region = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 40,
+            height: 20,
+        };
+        let dir =
+            std::env::temp_dir().join(format!("libremarkable-testing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("box.png");
+        capture(&fb, region).unwrap().save(&path).unwrap();
+
+        // A pixel wider
+        fb.fill_rect(
+            Point2 { x: 30, y: 5 },
+            Vector2 { x: 1, y: 10 },
+            color::BLACK,
+        );
+        assert_matches_png(&fb, region, &path, 8);
+
+        fb.fill_rect(Point2 { x: 0, y: 0 }, Vector2 { x: 5, y: 5 }, color::BLACK);
+        let failed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            assert_matches_png(&fb, region, &path, 8)
+        }));
+        assert!(failed.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+//! Trace logging of the ioctls and device reads behind refreshes and input.
+//!
+//! With the `trace-io` feature enabled, every refresh request, wait for refresh
+//! completion and evdev event read is logged at trace level with the target
+//! `TARGET`, prefixed with the time since the first traced operation. Enable it with
+//! e.g. `RUST_LOG=libremarkable::trace=trace` when using `env_logger`. The most
+//! recent records are kept in memory as well, see `recent`, for crash reports.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+/// Log target of all trace records
+pub const TARGET: &str = "libremarkable::trace";
+
+/// How many records `recent` returns at most
+pub const RECENT_CAPACITY: usize = 200;
+
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+static RECENT: Lazy<Mutex<VecDeque<String>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)));
+
+/// Time since the first traced operation
+pub fn timestamp() -> Duration {
+    START.elapsed()
+}
+
+pub(crate) fn record(line: String) {
+    // A panic while holding the lock shouldn't lose the trace of what led to it
+    let mut recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+    if recent.len() == RECENT_CAPACITY {
+        recent.pop_front();
+    }
+    recent.push_back(line);
+}
+
+/// The most recent trace records, oldest first
+pub fn recent() -> Vec<String> {
+    let recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+    recent.iter().cloned().collect()
+}
@@ -0,0 +1,1130 @@
+//! A drawing area keeping what is drawn on it as strokes.
+//!
+//! A `CanvasWidget` turns the pen input over it into `Stroke`s, inking them as they
+//! are drawn and rendering them with a brush of `framebuffer::brush` once the pen
+//! lifts. The eraser end of the pen, or any pen while `erasing` is set, erases the
+//! strokes it touches as a whole. Adding and erasing strokes can be undone and redone,
+//! and the strokes can be written to and read back from a compact binary format with
+//! `write_strokes` and `read_strokes`.
+//!
+//! While `lasso` is set, the pen draws a lasso instead, selecting the strokes mostly
+//! within it. The selection is framed with handles: dragging inside the frame moves
+//! the strokes selected, dragging a corner scales them and dragging the handle above
+//! rotates them. They are shown transformed as the pen moves, and only changed for
+//! good, as one change that can be undone, once it lifts. Transformed strokes keep
+//! their ids, and so their place among the others.
+//!
+//! Strokes are kept in the coordinates of the canvas, with its top left at the
+//! origin, so they stay where they are on it when the canvas moves. As the
+//! `UIElement::Canvas` element, the `ApplicationContext` passes it the pen input and
+//! refreshes what it draws, and `ApplicationContext::update_canvas` redraws it after
+//! it was changed otherwise, e.g. by `undo`.
+
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+use crate::framebuffer::brush::{Brush, ChiselBrush, RoundBrush, TexturedBrush};
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::{FramebufferDraw, StrokeStyle};
+use crate::input::{WacomEvent, WacomPen, WacomTool};
+use crate::stroke::{Stroke, StrokeBuilder, StrokePoint, StrokeTransform};
+use crate::ui_extensions::element::ActiveRegionFunction;
+
+const STROKES_HEADER: &[u8; 16] = b"libremarkable\0sl";
+const STROKES_VERSION: u16 = 1;
+
+/// Between the strokes selected and their frame
+const SELECTION_MARGIN: u32 = 8;
+/// Of the squares at the corners of the frame, and how close to them the pen grabs them
+const HANDLE_SIZE: u32 = 16;
+/// From the top of the frame to the handle rotating the selection
+const ROTATE_HANDLE_DISTANCE: u32 = 40;
+/// How far strokes can be scaled down, so they don't vanish
+const MIN_SCALE: f32 = 0.05;
+
+/// Which brush of `framebuffer::brush` a stroke is rendered with, at its width and
+/// color
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum BrushKind {
+    /// `RoundBrush`
+    #[default]
+    Round,
+    /// `ChiselBrush`, with its tip at `angle` radians clockwise from the x axis
+    Chisel { thickness: f32, angle: f32 },
+    /// `TexturedBrush::pencil`
+    Pencil,
+}
+
+impl BrushKind {
+    pub fn brush(self, size: f32, color: color) -> Box<dyn Brush> {
+        match self {
+            BrushKind::Round => Box::new(RoundBrush { size, color }),
+            BrushKind::Chisel { thickness, angle } => Box::new(ChiselBrush {
+                size,
+                thickness,
+                angle,
+                color,
+            }),
+            BrushKind::Pencil => Box::new(TexturedBrush::pencil(size, color)),
+        }
+    }
+}
+
+/// Identifies a stroke on a canvas. Later strokes have larger ids, and are drawn over
+/// earlier ones.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StrokeId(u64);
+
+/// A stroke on a canvas, in its coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanvasStroke {
+    pub id: StrokeId,
+    pub stroke: Stroke,
+    pub brush: BrushKind,
+}
+
+impl CanvasStroke {
+    /// Renders the stroke with the canvas' top left at `origin`
+    fn draw(&self, fb: &mut Framebuffer, origin: cgmath::Vector2<f32>) -> mxcfb_rect {
+        let points: Vec<_> = self
+            .stroke
+            .points
+            .iter()
+            .map(|point| StrokePoint {
+                position: point.position + origin,
+                ..*point
+            })
+            .collect();
+        let brush = self.brush.brush(self.stroke.width, self.stroke.color);
+        fb.draw_stroke(&points, brush.as_ref())
+    }
+
+    /// The same stroke moved, scaled and rotated by `transform`, with the brush turned
+    /// and sized along
+    pub fn transformed(&self, transform: &StrokeTransform) -> CanvasStroke {
+        let brush = match self.brush {
+            BrushKind::Chisel { thickness, angle } => BrushKind::Chisel {
+                thickness: thickness * transform.scale,
+                angle: angle + transform.rotation,
+            },
+            brush => brush,
+        };
+        CanvasStroke {
+            id: self.id,
+            stroke: self.stroke.transformed(transform),
+            brush,
+        }
+    }
+}
+
+/// Strokes taken off and put on a canvas at once, undone by the reverse
+#[derive(Clone, Debug, Default)]
+struct Change {
+    removed: Vec<CanvasStroke>,
+    added: Vec<CanvasStroke>,
+}
+
+impl Change {
+    fn reverse(self) -> Change {
+        Change {
+            removed: self.added,
+            added: self.removed,
+        }
+    }
+}
+
+/// What part of the frame around the selection the pen holds
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Handle {
+    Move,
+    /// A corner, scaling about the opposite one
+    Scale,
+    /// Above the frame, rotating about its center
+    Rotate,
+}
+
+impl Handle {
+    /// `transform` with the pen dragging the handle from `start` to `to`
+    fn drag(
+        self,
+        start: cgmath::Point2<f32>,
+        to: cgmath::Point2<f32>,
+        transform: StrokeTransform,
+    ) -> StrokeTransform {
+        use cgmath::InnerSpace;
+        let (from, to) = (start - transform.center, to - transform.center);
+        match self {
+            Handle::Move => StrokeTransform {
+                translation: to - from,
+                ..transform
+            },
+            Handle::Scale => StrokeTransform {
+                scale: (to.magnitude() / from.magnitude().max(1.0)).max(MIN_SCALE),
+                ..transform
+            },
+            Handle::Rotate => StrokeTransform {
+                rotation: to.y.atan2(to.x) - from.y.atan2(from.x),
+                ..transform
+            },
+        }
+    }
+}
+
+/// What the pen is doing on a canvas
+#[derive(Clone, Debug)]
+enum Capture {
+    Drawing(StrokeBuilder),
+    /// Where the eraser was last, and the strokes erased so far
+    Erasing(cgmath::Point2<f32>, Vec<CanvasStroke>),
+    /// The corners of the lasso so far
+    Lasso(Vec<cgmath::Point2<f32>>),
+    /// The selection, shown transformed by `transform` until the pen lifts
+    Transforming {
+        handle: Handle,
+        start: cgmath::Point2<f32>,
+        transform: StrokeTransform,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct CanvasWidget {
+    pub size: cgmath::Vector2<u32>,
+    /// Of the strokes drawn from now on
+    pub brush: BrushKind,
+    pub width: f32,
+    pub color: color,
+    /// Whether the pen erases strokes, like its eraser end always does
+    pub erasing: bool,
+    /// How close to a stroke the eraser erases it
+    pub eraser_width: f32,
+    /// Whether the pen draws a lasso around the strokes to select
+    pub lasso: bool,
+    /// How many changes can be undone
+    pub undo_limit: usize,
+    /// Called when strokes were added or erased, including by undoing
+    pub on_change: Option<ActiveRegionFunction>,
+    /// From the bottom up, ordered by id
+    strokes: Vec<CanvasStroke>,
+    /// Ordered by id
+    selection: Vec<StrokeId>,
+    next_id: u64,
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    capture: Option<Capture>,
+    /// Where to draw the strokes again, in the canvas' coordinates
+    damage: Option<mxcfb_rect>,
+    changed: bool,
+}
+
+impl CanvasWidget {
+    pub fn new(size: cgmath::Vector2<u32>) -> CanvasWidget {
+        CanvasWidget {
+            size,
+            brush: BrushKind::Round,
+            width: 4.0,
+            color: color::BLACK,
+            erasing: false,
+            eraser_width: 20.0,
+            lasso: false,
+            undo_limit: 100,
+            on_change: None,
+            strokes: Vec::new(),
+            selection: Vec::new(),
+            next_id: 0,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            capture: None,
+            damage: None,
+            changed: false,
+        }
+    }
+
+    /// From the bottom up
+    pub fn strokes(&self) -> &[CanvasStroke] {
+        &self.strokes
+    }
+
+    pub fn stroke(&self, id: StrokeId) -> Option<&CanvasStroke> {
+        let index = self.strokes.binary_search_by_key(&id, |s| s.id).ok()?;
+        Some(&self.strokes[index])
+    }
+
+    /// Adds `strokes` over the others as one change that can be undone. Returns their
+    /// ids.
+    pub fn add_strokes(&mut self, strokes: Vec<(Stroke, BrushKind)>) -> Vec<StrokeId> {
+        let added: Vec<_> = strokes
+            .into_iter()
+            .map(|(stroke, brush)| self.new_stroke(stroke, brush))
+            .collect();
+        let ids = added.iter().map(|s| s.id).collect();
+        self.record(Change {
+            removed: Vec::new(),
+            added,
+        });
+        ids
+    }
+
+    /// Erases the strokes `ids` as one change that can be undone. Returns whether any
+    /// of them were there.
+    pub fn erase(&mut self, ids: &[StrokeId]) -> bool {
+        let removed: Vec<_> = ids
+            .iter()
+            .filter_map(|id| self.stroke(*id))
+            .cloned()
+            .collect();
+        if removed.is_empty() {
+            return false;
+        }
+        self.record(Change {
+            removed,
+            added: Vec::new(),
+        });
+        true
+    }
+
+    /// Erases all the strokes as one change that can be undone
+    pub fn clear(&mut self) -> bool {
+        let ids: Vec<_> = self.strokes.iter().map(|s| s.id).collect();
+        self.erase(&ids)
+    }
+
+    /// Replaces the strokes with `strokes`, e.g. those of `read_strokes`, forgetting
+    /// what could be undone
+    pub fn set_strokes(&mut self, strokes: Vec<(Stroke, BrushKind)>) {
+        self.capture = None;
+        self.selection.clear();
+        self.undo.clear();
+        self.redo.clear();
+        self.strokes = strokes
+            .into_iter()
+            .map(|(stroke, brush)| self.new_stroke(stroke, brush))
+            .collect();
+        self.damage = Some(self.bounds());
+        self.changed = true;
+    }
+
+    fn new_stroke(&mut self, stroke: Stroke, brush: BrushKind) -> CanvasStroke {
+        let id = StrokeId(self.next_id);
+        self.next_id += 1;
+        CanvasStroke { id, stroke, brush }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Reverts the last change. Returns whether there was one.
+    pub fn undo(&mut self) -> bool {
+        let change = match self.undo.pop() {
+            Some(change) => change.reverse(),
+            None => return false,
+        };
+        self.apply(&change);
+        self.redo.push(change);
+        true
+    }
+
+    /// Makes the last change undone again. Returns whether there was one.
+    pub fn redo(&mut self) -> bool {
+        let change = match self.redo.pop() {
+            Some(change) => change.reverse(),
+            None => return false,
+        };
+        self.apply(&change);
+        self.push_undo(change);
+        true
+    }
+
+    /// Applies `change` as a new one that can be undone
+    fn record(&mut self, change: Change) {
+        self.apply(&change);
+        self.redo.clear();
+        self.push_undo(change);
+    }
+
+    fn push_undo(&mut self, change: Change) {
+        self.undo.push(change);
+        let excess = self.undo.len().saturating_sub(self.undo_limit);
+        self.undo.drain(..excess);
+    }
+
+    fn apply(&mut self, change: &Change) {
+        self.damage_selection();
+        for stroke in &change.removed {
+            if let Ok(index) = self.strokes.binary_search_by_key(&stroke.id, |s| s.id) {
+                self.strokes.remove(index);
+            }
+            self.damage(stroke.stroke.bounding_rect());
+        }
+        for stroke in &change.added {
+            match self.strokes.binary_search_by_key(&stroke.id, |s| s.id) {
+                Ok(index) => self.strokes[index] = stroke.clone(),
+                Err(index) => self.strokes.insert(index, stroke.clone()),
+            }
+            self.damage(stroke.stroke.bounding_rect());
+        }
+        let strokes = &self.strokes;
+        self.selection
+            .retain(|id| strokes.binary_search_by_key(id, |s| s.id).is_ok());
+        self.damage_selection();
+        self.changed = true;
+    }
+
+    /// The strokes selected, ordered by id
+    pub fn selection(&self) -> &[StrokeId] {
+        &self.selection
+    }
+
+    fn is_selected(&self, id: StrokeId) -> bool {
+        self.selection.binary_search(&id).is_ok()
+    }
+
+    /// Selects the strokes `ids` that are there, instead of those selected before
+    pub fn select(&mut self, ids: &[StrokeId]) {
+        self.damage_selection();
+        self.selection = ids
+            .iter()
+            .copied()
+            .filter(|id| self.stroke(*id).is_some())
+            .collect();
+        self.selection.sort_unstable();
+        self.selection.dedup();
+        self.damage_selection();
+    }
+
+    /// Returns whether anything was selected
+    pub fn clear_selection(&mut self) -> bool {
+        if self.selection.is_empty() {
+            return false;
+        }
+        self.select(&[]);
+        true
+    }
+
+    /// Moves, scales and rotates the strokes selected as one change that can be
+    /// undone. Returns whether any were selected.
+    pub fn transform_selection(&mut self, transform: &StrokeTransform) -> bool {
+        let removed: Vec<_> = self
+            .selection
+            .iter()
+            .filter_map(|id| self.stroke(*id))
+            .cloned()
+            .collect();
+        if removed.is_empty() {
+            return false;
+        }
+        let added = removed.iter().map(|s| s.transformed(transform)).collect();
+        self.record(Change { removed, added });
+        true
+    }
+
+    /// How the selection is shown transformed while the pen drags it
+    fn preview(&self) -> Option<&StrokeTransform> {
+        match self.capture {
+            Some(Capture::Transforming { ref transform, .. }) => Some(transform),
+            _ => None,
+        }
+    }
+
+    /// `stroke` as it is shown
+    fn shown<'a>(&self, stroke: &'a CanvasStroke) -> Cow<'a, CanvasStroke> {
+        match self.preview() {
+            Some(transform) if self.is_selected(stroke.id) => {
+                Cow::Owned(stroke.transformed(transform))
+            }
+            _ => Cow::Borrowed(stroke),
+        }
+    }
+
+    /// The area covered by the strokes selected, as they are shown
+    pub fn selection_rect(&self) -> Option<mxcfb_rect> {
+        self.selection
+            .iter()
+            .filter_map(|id| self.stroke(*id))
+            .map(|stroke| self.shown(stroke).stroke.bounding_rect())
+            .reduce(|rect, other| rect.merge_rect(&other))
+    }
+
+    /// The frame drawn around the strokes selected
+    fn selection_frame(&self) -> Option<mxcfb_rect> {
+        let m = SELECTION_MARGIN;
+        Some(grow(self.selection_rect()?, m, m, m, m))
+    }
+
+    /// What is drawn for the selection, its frame and handles
+    fn selection_extent(&self) -> Option<mxcfb_rect> {
+        let h = HANDLE_SIZE / 2 + 1;
+        Some(grow(
+            self.selection_frame()?,
+            h,
+            ROTATE_HANDLE_DISTANCE + h,
+            h,
+            h,
+        ))
+    }
+
+    fn damage_selection(&mut self) {
+        if let Some(extent) = self.selection_extent() {
+            self.damage(extent);
+        }
+    }
+
+    /// The handle of the frame around the selection at `point`, with what it scales or
+    /// rotates about
+    fn grab(&self, point: cgmath::Point2<f32>) -> Option<(Handle, cgmath::Point2<f32>)> {
+        use cgmath::MetricSpace;
+        let frame = self.selection_frame()?;
+        let (left, top) = (frame.left as f32, frame.top as f32);
+        let (right, bottom) = (left + frame.width as f32, top + frame.height as f32);
+        let center = cgmath::Point2 {
+            x: (left + right) / 2.0,
+            y: (top + bottom) / 2.0,
+        };
+        let reach = HANDLE_SIZE as f32;
+        let rotate = cgmath::Point2 {
+            x: center.x,
+            y: top - ROTATE_HANDLE_DISTANCE as f32,
+        };
+        if point.distance(rotate) <= reach {
+            return Some((Handle::Rotate, center));
+        }
+        let corners = [(left, top), (right, top), (right, bottom), (left, bottom)];
+        for (i, (x, y)) in corners.iter().enumerate() {
+            if point.distance(cgmath::Point2 { x: *x, y: *y }) <= reach {
+                let (x, y) = corners[(i + 2) % 4];
+                return Some((Handle::Scale, cgmath::Point2 { x, y }));
+            }
+        }
+        let inside = (left..=right).contains(&point.x) && (top..=bottom).contains(&point.y);
+        inside.then_some((Handle::Move, center))
+    }
+
+    fn bounds(&self) -> mxcfb_rect {
+        mxcfb_rect::from(cgmath::Point2 { x: 0, y: 0 }, self.size)
+    }
+
+    /// Has the strokes over `rect`, in the canvas' coordinates, drawn again by
+    /// `draw_damage`
+    pub fn damage(&mut self, rect: mxcfb_rect) {
+        let rect = match rect.intersection(&self.bounds()) {
+            Some(rect) => rect,
+            None => return,
+        };
+        self.damage = Some(match self.damage {
+            Some(damage) => damage.merge_rect(&rect),
+            None => rect,
+        });
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        self.damage.is_some()
+    }
+
+    /// Whether strokes were added or erased since the last call
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Whether the pen is drawing, erasing, selecting or transforming on it
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Follows the pen over the canvas drawn at `rect`, inking the stroke or lasso it
+    /// draws onto `fb` as it goes. Returns the area inked, to refresh quickly. The
+    /// finished stroke, the strokes erased and the selection transformed are left to
+    /// `draw_damage`.
+    pub fn handle_wacom(
+        &mut self,
+        fb: &mut Framebuffer,
+        event: &WacomEvent,
+        rect: mxcfb_rect,
+    ) -> Option<mxcfb_rect> {
+        let origin = cgmath::Vector2 {
+            x: rect.left as f32,
+            y: rect.top as f32,
+        };
+        match *event {
+            WacomEvent::Draw {
+                position,
+                pressure,
+                tilt,
+                tool,
+                buttons,
+            } => {
+                let local = position - origin;
+                if self.capture.is_none() {
+                    let inside = position.x >= 0.0
+                        && position.y >= 0.0
+                        && rect.contains_point(&position.cast().unwrap());
+                    if !inside {
+                        return None;
+                    }
+                    let erasing = self.erasing || tool == WacomTool::Eraser;
+                    let capture = match self.grab(local) {
+                        Some((handle, center)) if !erasing => Capture::Transforming {
+                            handle,
+                            start: local,
+                            transform: StrokeTransform::identity(center),
+                        },
+                        _ if erasing => Capture::Erasing(local, Vec::new()),
+                        _ if self.lasso => Capture::Lasso(vec![local]),
+                        _ => Capture::Drawing(StrokeBuilder::new(self.width, self.color)),
+                    };
+                    if !matches!(capture, Capture::Transforming { .. }) {
+                        self.clear_selection();
+                    }
+                    self.capture = Some(capture);
+                }
+                if let Some(Capture::Transforming {
+                    handle,
+                    start,
+                    transform,
+                }) = self.capture
+                {
+                    self.damage_selection();
+                    self.capture = Some(Capture::Transforming {
+                        handle,
+                        start,
+                        transform: handle.drag(start, local, transform),
+                    });
+                    self.damage_selection();
+                    return None;
+                }
+                match self.capture {
+                    Some(Capture::Drawing(ref mut builder)) => {
+                        builder.handle_wacom_event(&WacomEvent::Draw {
+                            position: local,
+                            pressure,
+                            tilt,
+                            tool,
+                            buttons,
+                        });
+                        // Only the last segment, what came before is already inked
+                        let points = builder.points();
+                        let ink = CanvasStroke {
+                            id: StrokeId(self.next_id),
+                            stroke: Stroke::new(
+                                points[points.len().saturating_sub(2)..].to_vec(),
+                                self.width,
+                                self.color,
+                            ),
+                            brush: self.brush,
+                        };
+                        fb.push_clip(rect);
+                        let inked = ink.draw(fb, origin);
+                        fb.pop_clip();
+                        inked.intersection(&rect)
+                    }
+                    Some(Capture::Erasing(ref mut last, ref mut erased)) => {
+                        let from = std::mem::replace(last, local);
+                        let radius = self.eraser_width / 2.0;
+                        let (hit, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.strokes)
+                            .into_iter()
+                            .partition(|s| erases(&s.stroke, from, local, radius));
+                        self.strokes = kept;
+                        let rects: Vec<_> = hit.iter().map(|s| s.stroke.bounding_rect()).collect();
+                        erased.extend(hit);
+                        for rect in rects {
+                            self.damage(rect);
+                        }
+                        None
+                    }
+                    Some(Capture::Lasso(ref mut corners)) => {
+                        let last = *corners.last().unwrap();
+                        corners.push(local);
+                        fb.push_clip(rect);
+                        let inked =
+                            fb.draw_line_aa(last + origin, position, 2.0, color::GRAY(0x80));
+                        fb.pop_clip();
+                        inked.intersection(&rect)
+                    }
+                    _ => None,
+                }
+            }
+            WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: false,
+            }
+            | WacomEvent::Hover { .. } => {
+                // Where the selection is shown until the change
+                self.damage_selection();
+                match self.capture.take() {
+                    Some(Capture::Drawing(mut builder)) => {
+                        if let Some(stroke) = builder.finish() {
+                            let added = vec![self.new_stroke(stroke, self.brush)];
+                            self.record(Change {
+                                removed: Vec::new(),
+                                added,
+                            });
+                        }
+                    }
+                    Some(Capture::Erasing(_, erased)) if !erased.is_empty() => {
+                        // Taken off already, as the eraser went over them
+                        self.redo.clear();
+                        self.push_undo(Change {
+                            removed: erased,
+                            added: Vec::new(),
+                        });
+                        self.changed = true;
+                    }
+                    Some(Capture::Lasso(corners)) => {
+                        let ids: Vec<_> = self
+                            .strokes
+                            .iter()
+                            .filter(|s| s.stroke.is_within(&corners))
+                            .map(|s| s.id)
+                            .collect();
+                        // Wiping the lasso
+                        let points = corners.iter().map(|p| StrokePoint::new(p.x, p.y)).collect();
+                        self.damage(Stroke::new(points, 4.0, color::BLACK).bounding_rect());
+                        self.select(&ids);
+                    }
+                    Some(Capture::Transforming { transform, .. })
+                        if transform != StrokeTransform::identity(transform.center) =>
+                    {
+                        self.transform_selection(&transform);
+                    }
+                    _ => {}
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Renders the strokes over `area`, in the canvas' coordinates, on white with the
+    /// canvas at `position`. Returns the area drawn on the display.
+    fn render(
+        &self,
+        fb: &mut Framebuffer,
+        position: cgmath::Point2<i32>,
+        area: mxcfb_rect,
+    ) -> mxcfb_rect {
+        let screen = mxcfb_rect {
+            left: (position.x + area.left as i32).max(0) as u32,
+            top: (position.y + area.top as i32).max(0) as u32,
+            ..area
+        };
+        let origin = cgmath::Vector2 {
+            x: position.x as f32,
+            y: position.y as f32,
+        };
+        fb.push_clip(screen);
+        fb.fill_rect(
+            screen.top_left().cast().unwrap(),
+            screen.size(),
+            color::WHITE,
+        );
+        for stroke in &self.strokes {
+            let stroke = self.shown(stroke);
+            if stroke.stroke.bounding_rect().intersection(&area).is_some() {
+                stroke.draw(fb, origin);
+            }
+        }
+        if let Some(frame) = self.selection_frame() {
+            draw_frame(fb, frame, position);
+        }
+        fb.pop_clip();
+        screen
+    }
+
+    pub fn draw(&mut self, fb: &mut Framebuffer, position: cgmath::Point2<i32>) -> mxcfb_rect {
+        self.damage = None;
+        self.render(fb, position, self.bounds())
+    }
+
+    /// Draws the strokes again where they changed, with the canvas at `position`.
+    /// Returns the area drawn on the display, if any.
+    pub fn draw_damage(
+        &mut self,
+        fb: &mut Framebuffer,
+        position: cgmath::Point2<i32>,
+    ) -> Option<mxcfb_rect> {
+        let damage = self.damage.take()?;
+        Some(self.render(fb, position, damage))
+    }
+
+    /// Writes the strokes with `write_strokes`
+    pub fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write_strokes(out, &self.strokes)
+    }
+
+    /// Replaces the strokes with those read by `read_strokes`
+    pub fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let strokes = read_strokes(input)?;
+        self.set_strokes(strokes);
+        Ok(())
+    }
+}
+
+/// `rect` made larger by the margins, as far as it can go up and left
+fn grow(rect: mxcfb_rect, left: u32, top: u32, right: u32, bottom: u32) -> mxcfb_rect {
+    let (x, y) = (rect.left.saturating_sub(left), rect.top.saturating_sub(top));
+    mxcfb_rect {
+        left: x,
+        top: y,
+        width: rect.left + rect.width + right - x,
+        height: rect.top + rect.height + bottom - y,
+    }
+}
+
+/// Draws the dashed frame around a selection at `frame` with its handles, with the
+/// canvas at `position`
+fn draw_frame(fb: &mut Framebuffer, frame: mxcfb_rect, position: cgmath::Point2<i32>) {
+    let pos = position + cgmath::Vector2::new(frame.left as i32, frame.top as i32);
+    let size = frame.size();
+    fb.draw_rect_styled(pos, size, 1, color::BLACK, &StrokeStyle::dashed(&[6]));
+    let top = pos + cgmath::Vector2::new(size.x as i32 / 2, 0);
+    let rotate = top - cgmath::Vector2::new(0, ROTATE_HANDLE_DISTANCE as i32);
+    fb.draw_line(top, rotate, 1, color::BLACK);
+    let h = HANDLE_SIZE / 2;
+    fb.fill_circle(rotate, h, color::WHITE);
+    fb.draw_circle(rotate, h, color::BLACK);
+    let (w, h) = (size.x as i32, size.y as i32);
+    for corner in [(0, 0), (w, 0), (w, h), (0, h)] {
+        let half = (HANDLE_SIZE / 2) as i32;
+        let at = pos + cgmath::Vector2::new(corner.0 - half, corner.1 - half);
+        let size = cgmath::Vector2::new(HANDLE_SIZE, HANDLE_SIZE);
+        fb.fill_rect(at, size, color::WHITE);
+        fb.draw_rect(at, size, 2, color::BLACK);
+    }
+}
+
+/// Writes `strokes` with their brushes, in a binary format of their own. Colors are
+/// kept as the shade of gray or RGB they are drawn with.
+pub fn write_strokes<W: Write>(out: &mut W, strokes: &[CanvasStroke]) -> io::Result<()> {
+    let mut data = STROKES_HEADER.to_vec();
+    data.extend_from_slice(&STROKES_VERSION.to_le_bytes());
+    let word = |data: &mut Vec<u8>, bytes: [u8; 4]| data.extend_from_slice(&bytes);
+    word(&mut data, (strokes.len() as u32).to_le_bytes());
+    for CanvasStroke { stroke, brush, .. } in strokes {
+        match *brush {
+            BrushKind::Round => data.push(0),
+            BrushKind::Chisel { thickness, angle } => {
+                data.push(1);
+                word(&mut data, thickness.to_le_bytes());
+                word(&mut data, angle.to_le_bytes());
+            }
+            BrushKind::Pencil => data.push(2),
+        }
+        let [b, g, r, _] = stroke.color.to_xrgb8888();
+        data.extend_from_slice(&[r, g, b]);
+        word(&mut data, stroke.width.to_le_bytes());
+        word(&mut data, (stroke.points.len() as u32).to_le_bytes());
+        for point in &stroke.points {
+            word(&mut data, point.position.x.to_le_bytes());
+            word(&mut data, point.position.y.to_le_bytes());
+            word(&mut data, point.pressure.to_le_bytes());
+            word(&mut data, point.tilt.x.to_le_bytes());
+            word(&mut data, point.tilt.y.to_le_bytes());
+        }
+    }
+    out.write_all(&data)
+}
+
+/// Reads the strokes written by `write_strokes`, in the same order
+pub fn read_strokes<R: Read>(input: &mut R) -> io::Result<Vec<(Stroke, BrushKind)>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+    if &read_bytes::<16>(input)? != STROKES_HEADER {
+        return Err(invalid("Not a list of strokes"));
+    }
+    if u16::from_le_bytes(read_bytes(input)?) != STROKES_VERSION {
+        return Err(invalid("Unsupported version of the list of strokes"));
+    }
+    let float = |input: &mut R| read_bytes(input).map(f32::from_le_bytes);
+    let count = u32::from_le_bytes(read_bytes(input)?);
+    let mut strokes = Vec::new();
+    for _ in 0..count {
+        let brush = match read_bytes::<1>(input)? {
+            [0] => BrushKind::Round,
+            [1] => BrushKind::Chisel {
+                thickness: float(input)?,
+                angle: float(input)?,
+            },
+            [2] => BrushKind::Pencil,
+            _ => return Err(invalid("Unknown brush")),
+        };
+        let color = match read_bytes(input)? {
+            [0, 0, 0] => color::BLACK,
+            [255, 255, 255] => color::WHITE,
+            [r, g, b] if r == g && g == b => color::GRAY(255 - r),
+            [r, g, b] => color::RGB(r, g, b),
+        };
+        let width = float(input)?;
+        let mut points = Vec::new();
+        for _ in 0..u32::from_le_bytes(read_bytes(input)?) {
+            points.push(StrokePoint {
+                position: cgmath::Point2 {
+                    x: float(input)?,
+                    y: float(input)?,
+                },
+                pressure: float(input)?,
+                tilt: cgmath::Vector2 {
+                    x: float(input)?,
+                    y: float(input)?,
+                },
+            });
+        }
+        strokes.push((Stroke::new(points, width, color), brush));
+    }
+    Ok(strokes)
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Whether the eraser moving from `from` to `to` goes within `radius` of `stroke`
+fn erases(
+    stroke: &Stroke,
+    from: cgmath::Point2<f32>,
+    to: cgmath::Point2<f32>,
+    radius: f32,
+) -> bool {
+    use cgmath::MetricSpace;
+    // Often enough along the way not to skip over a stroke
+    let steps = (from.distance(to) / radius.max(1.0)).ceil().max(1.0) as usize;
+    (0..=steps).any(|step| {
+        let point = from + (to - from) * (step as f32 / steps as f32);
+        stroke.hit_test(point, radius)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::FramebufferIO;
+    use crate::input::StylusButtons;
+
+    fn draw(x: f32, y: f32, tool: WacomTool) -> WacomEvent {
+        WacomEvent::Draw {
+            position: cgmath::Point2 { x, y },
+            pressure: 2000,
+            tilt: cgmath::Vector2 { x: 0, y: 0 },
+            tool,
+            buttons: StylusButtons::default(),
+        }
+    }
+
+    const LIFT: WacomEvent = WacomEvent::InstrumentChange {
+        pen: WacomPen::Touch,
+        state: false,
+    };
+
+    #[test]
+    fn test_canvas() {
+        let mut fb = Framebuffer::headless(200, 200);
+        let mut canvas = CanvasWidget::new(cgmath::Vector2 { x: 100, y: 100 });
+        let position = cgmath::Point2 { x: 50, y: 50 };
+        let rect = canvas.draw(&mut fb, position);
+        assert_eq!(rect.top_left(), cgmath::Point2 { x: 50, y: 50 });
+        canvas.width = 8.0;
+        // Drawn over, the edges of strokes are blended in
+        let black =
+            |fb: &Framebuffer, x, y| fb.read_pixel(cgmath::Point2 { x, y }).to_rgb8()[1] < 128;
+
+        // Starting outside of it draws nothing
+        assert_eq!(
+            canvas.handle_wacom(&mut fb, &draw(10.0, 10.0, WacomTool::Pen), rect),
+            None
+        );
+        assert!(!canvas.is_capturing());
+
+        canvas.handle_wacom(&mut fb, &draw(60.0, 100.0, WacomTool::Pen), rect);
+        let inked = canvas.handle_wacom(&mut fb, &draw(140.0, 100.0, WacomTool::Pen), rect);
+        assert!(inked.is_some());
+        assert!(black(&fb, 100, 100));
+        assert!(canvas.strokes().is_empty());
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        assert_eq!(canvas.strokes().len(), 1);
+        assert_eq!(canvas.strokes()[0].stroke.points[0].position.x, 10.0);
+        assert!(canvas.take_changed() && !canvas.take_changed());
+        assert!(canvas.draw_damage(&mut fb, position).is_some());
+        assert!(black(&fb, 100, 100));
+
+        // Across the first stroke, then erased by the eraser end of the pen
+        canvas.handle_wacom(&mut fb, &draw(100.0, 60.0, WacomTool::Pen), rect);
+        canvas.handle_wacom(&mut fb, &draw(100.0, 140.0, WacomTool::Pen), rect);
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        canvas.draw_damage(&mut fb, position);
+        assert_eq!(canvas.strokes().len(), 2);
+        assert!(canvas.strokes()[0].id < canvas.strokes()[1].id);
+        assert!(black(&fb, 100, 70));
+        canvas.handle_wacom(&mut fb, &draw(80.0, 60.0, WacomTool::Eraser), rect);
+        canvas.handle_wacom(&mut fb, &draw(130.0, 80.0, WacomTool::Eraser), rect);
+        assert_eq!(canvas.strokes().len(), 1);
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        let damage = canvas.draw_damage(&mut fb, position).unwrap();
+        assert!(damage.contains_point(&cgmath::Point2 { x: 100, y: 70 }));
+        assert!(!black(&fb, 100, 70));
+        assert!(black(&fb, 70, 100));
+
+        assert!(canvas.undo());
+        assert_eq!(canvas.strokes().len(), 2);
+        canvas.draw_damage(&mut fb, position);
+        assert!(black(&fb, 100, 70));
+        assert!(canvas.undo() && canvas.undo() && !canvas.undo());
+        assert!(canvas.strokes().is_empty());
+        canvas.draw_damage(&mut fb, position);
+        assert!(!black(&fb, 70, 100));
+        assert!(canvas.redo() && canvas.can_redo());
+        assert_eq!(canvas.strokes().len(), 1);
+
+        // A new change can't be redone after
+        assert!(canvas.clear());
+        assert!(!canvas.can_redo());
+        assert!(canvas.strokes().is_empty());
+        assert!(!canvas.clear());
+        canvas.undo_limit = 1;
+        canvas.erasing = true;
+        let ids = canvas.add_strokes(vec![(
+            Stroke::new(vec![StrokePoint::new(5.0, 5.0)], 4.0, color::BLACK),
+            BrushKind::Round,
+        )]);
+        canvas.handle_wacom(&mut fb, &draw(56.0, 56.0, WacomTool::Pen), rect);
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        assert!(canvas.stroke(ids[0]).is_none());
+        assert!(canvas.undo() && !canvas.undo());
+        assert!(canvas.stroke(ids[0]).is_some());
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut fb = Framebuffer::headless(300, 300);
+        let mut canvas = CanvasWidget::new(cgmath::Vector2 { x: 200, y: 200 });
+        let position = cgmath::Point2 { x: 0, y: 0 };
+        let rect = canvas.draw(&mut fb, position);
+        let line = |x0, x1| {
+            let points = vec![StrokePoint::new(x0, 100.0), StrokePoint::new(x1, 100.0)];
+            (Stroke::new(points, 4.0, color::BLACK), BrushKind::Round)
+        };
+        let ids = canvas.add_strokes(vec![line(20.0, 60.0), line(120.0, 160.0)]);
+        let (a, b) = (ids[0], ids[1]);
+        let c = canvas.add_strokes(vec![line(130.0, 150.0)])[0];
+        canvas.draw_damage(&mut fb, position);
+        let dark =
+            |fb: &Framebuffer, x, y| fb.read_pixel(cgmath::Point2 { x, y }).to_rgb8()[1] < 128;
+        let drag = |canvas: &mut CanvasWidget, fb: &mut Framebuffer, points: &[(f32, f32)]| {
+            for (x, y) in points {
+                canvas.handle_wacom(fb, &draw(*x, *y, WacomTool::Pen), rect);
+            }
+        };
+
+        canvas.lasso = true;
+        let lasso = [(10.0, 80.0), (70.0, 80.0), (70.0, 120.0), (10.0, 120.0)];
+        drag(&mut canvas, &mut fb, &lasso);
+        assert!(dark(&fb, 70, 100));
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        assert_eq!(canvas.selection(), [a]);
+        let frame = canvas.draw_damage(&mut fb, position).unwrap();
+        assert!(frame.contains_point(&cgmath::Point2 { x: 70, y: 100 }));
+        // The lasso wiped, the frame around the stroke drawn with a handle at its corner
+        assert!(!dark(&fb, 70, 100));
+        assert!(dark(&fb, 2, 90) && !dark(&fb, 10, 90));
+
+        // Moving it, shown as the pen moves and changed once it lifts
+        drag(&mut canvas, &mut fb, &[(40.0, 100.0), (40.0, 150.0)]);
+        assert_eq!(canvas.stroke(a).unwrap().stroke.points[0].position.y, 100.0);
+        let damage = canvas.draw_damage(&mut fb, position).unwrap();
+        assert!(damage.contains_point(&cgmath::Point2 { x: 40, y: 100 }));
+        assert!(damage.contains_point(&cgmath::Point2 { x: 40, y: 150 }));
+        assert!(dark(&fb, 40, 150) && !dark(&fb, 40, 100));
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        assert!(canvas.take_changed());
+        let moved = canvas.stroke(a).unwrap().stroke.points[0].position;
+        assert_eq!(moved, cgmath::Point2 { x: 20.0, y: 150.0 });
+        let order: Vec<_> = canvas.strokes().iter().map(|s| s.id).collect();
+        assert_eq!(order, [a, b, c]);
+        assert_eq!(canvas.selection(), [a]);
+
+        assert!(canvas.undo());
+        assert_eq!(canvas.stroke(a).unwrap().stroke.points[0].position.y, 100.0);
+        canvas.draw_damage(&mut fb, position);
+        assert!(dark(&fb, 40, 100) && !dark(&fb, 40, 150));
+        assert!(canvas.redo());
+
+        // Twice as large from the bottom right corner, about the top left one
+        let corner = canvas.selection_frame().unwrap();
+        assert_eq!((corner.left, corner.top), (10, 140));
+        drag(&mut canvas, &mut fb, &[(70.0, 160.0), (130.0, 180.0)]);
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        let scaled = &canvas.stroke(a).unwrap().stroke;
+        assert_eq!(scaled.width, 8.0);
+        assert_eq!(
+            scaled.points[0].position,
+            cgmath::Point2 { x: 30.0, y: 160.0 }
+        );
+        assert_eq!(
+            scaled.points[1].position,
+            cgmath::Point2 { x: 110.0, y: 160.0 }
+        );
+
+        // Turned around by the handle above the frame
+        let frame = canvas.selection_frame().unwrap();
+        let center = (frame.left + frame.width / 2) as f32;
+        let handle = frame.top as f32 - ROTATE_HANDLE_DISTANCE as f32;
+        drag(&mut canvas, &mut fb, &[(center, handle), (center, 190.0)]);
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        let turned = canvas.stroke(a).unwrap().stroke.points[0].position;
+        assert!((turned.x - 110.0).abs() < 0.01 && (turned.y - 160.0).abs() < 0.01);
+
+        // Starting elsewhere drops the selection
+        assert!(canvas.transform_selection(&StrokeTransform::identity(turned)));
+        drag(&mut canvas, &mut fb, &[(180.0, 20.0)]);
+        assert!(canvas.selection().is_empty());
+        canvas.handle_wacom(&mut fb, &LIFT, rect);
+        canvas.select(&[c, a, c]);
+        assert_eq!(canvas.selection(), [a, c]);
+        assert!(canvas.erase(&[a]));
+        assert_eq!(canvas.selection(), [c]);
+    }
+
+    #[test]
+    fn test_write_strokes() {
+        let mut canvas = CanvasWidget::new(cgmath::Vector2 { x: 100, y: 100 });
+        let mut point = StrokePoint::new(10.0, 20.0);
+        point.pressure = 0.5;
+        point.tilt = cgmath::Vector2 { x: -3.0, y: 4.0 };
+        let strokes = vec![
+            (
+                Stroke::new(vec![point, StrokePoint::new(30.0, 40.0)], 3.0, color::BLACK),
+                BrushKind::Pencil,
+            ),
+            (
+                Stroke::new(vec![point], 8.0, color::GRAY(0x40)),
+                BrushKind::Chisel {
+                    thickness: 2.0,
+                    angle: 0.5,
+                },
+            ),
+            (
+                Stroke::new(Vec::new(), 1.0, color::RGB(10, 20, 30)),
+                BrushKind::Round,
+            ),
+        ];
+        canvas.add_strokes(strokes.clone());
+        let mut data = Vec::new();
+        canvas.save(&mut data).unwrap();
+        assert_eq!(read_strokes(&mut &data[..]).unwrap(), strokes);
+
+        let mut loaded = CanvasWidget::new(cgmath::Vector2 { x: 100, y: 100 });
+        loaded.load(&mut &data[..]).unwrap();
+        assert_eq!(loaded.strokes(), canvas.strokes());
+        assert!(!loaded.can_undo());
+
+        let err = read_strokes(&mut &data[..data.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        data[0] = b'L';
+        let err = read_strokes(&mut &data[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
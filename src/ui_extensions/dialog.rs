@@ -0,0 +1,277 @@
+//! Dialogs asking to confirm or choose, and toasts telling something in passing.
+//!
+//! `ApplicationContext::show_dialog` shows a `DialogSpec` over the app as a modal
+//! overlay, which keeps input from the app until one of its buttons is tapped. The
+//! index of that button goes to the `on_result` handler of the spec, and completes
+//! the `DialogResult` returned for apps that would rather await it.
+//! `ApplicationContext::show_toast` shows a line of text near the bottom of the
+//! display for a while. Both put back what they covered once they are gone.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::appctx::ApplicationContext;
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::draw::TextSpan;
+use crate::framebuffer::{CornerRadii, FramebufferDraw};
+use crate::ui_extensions::overlay::OverlayId;
+
+/// Called with the index of the button tapped to close a dialog
+pub type DialogFunction = fn(&mut ApplicationContext<'_>, usize);
+
+/// Between and around the parts of a dialog
+const PADDING: u32 = 30;
+const BORDER: u32 = 4;
+
+/// What a dialog says and which buttons close it
+#[derive(Clone, Debug)]
+pub struct DialogSpec {
+    pub title: String,
+    pub body: String,
+    /// From left to right. A dialog without any has an "OK" one.
+    pub buttons: Vec<String>,
+    /// At most, it is narrower on a smaller display
+    pub width: u32,
+    /// Of the body, the title is a bit larger
+    pub scale: f32,
+    pub on_result: Option<DialogFunction>,
+}
+
+impl DialogSpec {
+    pub fn new(title: &str, body: &str) -> DialogSpec {
+        DialogSpec {
+            title: title.to_owned(),
+            body: body.to_owned(),
+            buttons: Vec::new(),
+            width: 900,
+            scale: 36.0,
+            on_result: None,
+        }
+    }
+
+    pub fn button(mut self, label: &str) -> DialogSpec {
+        self.buttons.push(label.to_owned());
+        self
+    }
+
+    pub fn on_result(mut self, on_result: DialogFunction) -> DialogSpec {
+        self.on_result = Some(on_result);
+        self
+    }
+
+    fn labels(&self) -> Vec<String> {
+        match self.buttons.is_empty() {
+            true => vec!["OK".to_owned()],
+            false => self.buttons.clone(),
+        }
+    }
+
+    /// Where the dialog goes, centered on `screen`, and where its parts go on it
+    pub(crate) fn layout(&self, fb: &mut Framebuffer, screen: mxcfb_rect) -> DialogLayout {
+        let width = self.width.min(screen.width.saturating_sub(2 * PADDING));
+        let inner = width.saturating_sub(2 * PADDING) as f32;
+        let origin = cgmath::Point2 { x: 0.0, y: 0.0 };
+        let title = [TextSpan::new(&self.title, self.scale * 1.3).bold()];
+        let title_height = fb.draw_spans(origin, inner, &title, true).height;
+        let body = [TextSpan::new(&self.body, self.scale)];
+        let body_height = fb.draw_spans(origin, inner, &body, true).height;
+        let button_height = (self.scale * 2.2) as u32;
+        let height = 4 * PADDING + title_height + body_height + button_height;
+        let rect = mxcfb_rect {
+            left: screen.left + screen.width.saturating_sub(width) / 2,
+            top: screen.top + screen.height.saturating_sub(height) / 2,
+            width,
+            height,
+        };
+
+        let labels = self.labels();
+        let count = labels.len() as u32;
+        let gap = PADDING / 2;
+        let button_width = (width - 2 * PADDING).saturating_sub(gap * (count - 1)) / count;
+        let buttons = (0..count)
+            .map(|i| mxcfb_rect {
+                left: rect.left + PADDING + i * (button_width + gap),
+                top: rect.top + rect.height - PADDING - button_height,
+                width: button_width,
+                height: button_height,
+            })
+            .collect();
+        DialogLayout {
+            rect,
+            title_top: rect.top + PADDING,
+            body_top: rect.top + 2 * PADDING + title_height,
+            buttons,
+        }
+    }
+
+    /// Draws the dialog as laid out in `layout`
+    pub(crate) fn draw(&self, fb: &mut Framebuffer, layout: &DialogLayout) {
+        let rect = layout.rect;
+        let pos = rect.top_left().cast().unwrap();
+        fb.fill_rect(pos, rect.size(), color::WHITE);
+        fb.draw_rect(pos, rect.size(), BORDER, color::BLACK);
+        let inner = rect.width.saturating_sub(2 * PADDING) as f32;
+        let left = (rect.left + PADDING) as f32;
+        let title = [TextSpan::new(&self.title, self.scale * 1.3).bold()];
+        fb.draw_spans((left, layout.title_top as f32).into(), inner, &title, false);
+        let body = [TextSpan::new(&self.body, self.scale)];
+        fb.draw_spans((left, layout.body_top as f32).into(), inner, &body, false);
+        for (label, button) in self.labels().iter().zip(&layout.buttons) {
+            fb.draw_rounded_rect(
+                button.top_left().cast().unwrap(),
+                button.size(),
+                CornerRadii::uniform(button.height / 4),
+                BORDER,
+                color::BLACK,
+            );
+            draw_centered(fb, label, self.scale, *button, color::BLACK);
+        }
+    }
+}
+
+/// Where a dialog and its parts are on the display
+#[derive(Clone, Debug)]
+pub(crate) struct DialogLayout {
+    pub rect: mxcfb_rect,
+    title_top: u32,
+    body_top: u32,
+    pub buttons: Vec<mxcfb_rect>,
+}
+
+/// Draws a line of `text` in the middle of `area`
+fn draw_centered(fb: &mut Framebuffer, text: &str, size: f32, area: mxcfb_rect, c: color) {
+    let measured = fb.draw_text(cgmath::Point2 { x: 0.0, y: size }, text, size, c, true);
+    let pos = cgmath::Point2 {
+        x: area.left as f32 + (area.width as f32 - measured.width as f32) / 2.0,
+        // The baseline, so that the letters without descenders are centered
+        y: area.top as f32 + (area.height as f32 + size * 0.6) / 2.0,
+    };
+    fb.draw_text(pos, text, size, c, false);
+}
+
+/// Where a toast of `text` in `size` goes on `screen`, near the bottom
+pub(crate) fn toast_rect(
+    fb: &mut Framebuffer,
+    text: &str,
+    size: f32,
+    screen: mxcfb_rect,
+) -> mxcfb_rect {
+    let measured = fb.draw_text(
+        cgmath::Point2 { x: 0.0, y: size },
+        text,
+        size,
+        color::BLACK,
+        true,
+    );
+    let width = (measured.width + 2 * PADDING).min(screen.width);
+    let height = (size * 2.0) as u32;
+    mxcfb_rect {
+        left: screen.left + (screen.width - width) / 2,
+        top: (screen.top + screen.height).saturating_sub(height + 4 * PADDING),
+        width,
+        height,
+    }
+}
+
+/// Draws a toast of `text` in `size` at `rect`, white on black
+pub(crate) fn draw_toast(fb: &mut Framebuffer, text: &str, size: f32, rect: mxcfb_rect) {
+    fb.fill_rounded_rect(
+        rect.top_left().cast().unwrap(),
+        rect.size(),
+        CornerRadii::uniform(rect.height / 2),
+        color::BLACK,
+    );
+    draw_centered(fb, text, size, rect, color::WHITE);
+}
+
+#[derive(Default)]
+struct State {
+    result: Option<usize>,
+    waker: Option<Waker>,
+}
+
+/// Completes with the index of the button tapped to close a dialog
+#[derive(Clone, Default)]
+pub struct DialogResult {
+    state: Arc<Mutex<State>>,
+}
+
+impl DialogResult {
+    /// The index of the button tapped, if the dialog was closed already
+    pub fn result(&self) -> Option<usize> {
+        self.state.lock().unwrap().result
+    }
+
+    pub(crate) fn complete(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(index);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for DialogResult {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<usize> {
+        let mut state = self.state.lock().unwrap();
+        match state.result {
+            Some(index) => Poll::Ready(index),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A dialog on the display, waiting for one of its buttons to be tapped
+pub(crate) struct ShownDialog {
+    pub overlay: OverlayId,
+    pub rect: mxcfb_rect,
+    pub buttons: Vec<mxcfb_rect>,
+    pub on_result: Option<DialogFunction>,
+    pub result: DialogResult,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::FramebufferIO;
+
+    #[test]
+    fn test_dialog_layout() {
+        let mut fb = Framebuffer::headless(1000, 1000);
+        let screen = mxcfb_rect::from(cgmath::Point2 { x: 0, y: 0 }, (1000, 1000).into());
+        let spec = DialogSpec::new("Delete?", "The notebook will be gone for good.")
+            .button("Cancel")
+            .button("Delete");
+        let layout = spec.layout(&mut fb, screen);
+        assert_eq!(layout.rect.width, 900);
+        assert_eq!(layout.rect.left, 50);
+        // Side by side at the bottom, within the dialog
+        let [cancel, delete] = layout.buttons[..] else {
+            panic!("{:?}", layout.buttons);
+        };
+        assert_eq!(cancel.top, delete.top);
+        assert!(cancel.left + cancel.width < delete.left);
+        assert!(layout.rect.contains_rect(&delete));
+        spec.draw(&mut fb, &layout);
+        let corner = layout.rect.top_left();
+        assert_eq!(fb.read_pixel(corner).as_native(), color::BLACK.as_native());
+
+        // Without buttons, there is one to close it anyway
+        let layout = DialogSpec::new("Saved", "").layout(&mut fb, screen);
+        assert_eq!(layout.buttons.len(), 1);
+
+        let result = DialogResult::default();
+        assert_eq!(result.result(), None);
+        result.clone().complete(1);
+        assert_eq!(result.result(), Some(1));
+    }
+}
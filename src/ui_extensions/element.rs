@@ -4,14 +4,29 @@ use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use crate::framebuffer::cgmath;
 use crate::framebuffer::common;
 use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::CornerRadii;
 use crate::framebuffer::FramebufferDraw;
 use crate::framebuffer::FramebufferRefresh;
 use crate::framebuffer::PartialRefreshMode;
 
 use crate::appctx;
+use crate::input::MultitouchEvent;
+#[cfg(feature = "stroke")]
+use crate::ui_extensions::canvas::CanvasWidget;
+use crate::ui_extensions::list_view::ListView;
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::ui_extensions::menu::MenuBar;
+use crate::ui_extensions::scroll_view::ScrollView;
+use crate::ui_extensions::slider::{Slider, Stepper};
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::ui_extensions::text_input::TextInput;
 
 pub type ActiveRegionFunction = fn(&mut appctx::ApplicationContext<'_>, UIElementHandle);
 
+/// Called with the element and whether it is checked now
+pub type ToggleFunction = fn(&mut appctx::ApplicationContext<'_>, UIElementHandle, bool);
+
 #[derive(Clone)]
 pub struct ActiveRegionHandler {
     pub handler: ActiveRegionFunction,
@@ -42,6 +57,13 @@ pub struct UIElementWrapper {
     pub last_drawn_rect: Option<common::mxcfb_rect>,
     pub onclick: Option<ActiveRegionFunction>,
     pub inner: UIElement,
+    /// Keeps the element from drawing outside of it, e.g. text too long to fit
+    pub clip: Option<common::mxcfb_rect>,
+    /// Whether a tap or tab can move the focus to the element, which `TextInput`s
+    /// always can, see `ApplicationContext::set_focus`
+    pub focusable: bool,
+    pub onfocus: Option<ActiveRegionFunction>,
+    pub onblur: Option<ActiveRegionFunction>,
 }
 
 impl Default for UIElementWrapper {
@@ -52,6 +74,10 @@ impl Default for UIElementWrapper {
             last_drawn_rect: Option::default(),
             onclick: Option::default(),
             inner: UIElement::default(),
+            clip: Option::default(),
+            focusable: false,
+            onfocus: Option::default(),
+            onblur: Option::default(),
         }
     }
 }
@@ -73,6 +99,7 @@ impl Eq for UIElementWrapper {}
 
 #[derive(Clone, Default, Debug)]
 pub enum UIElement {
+    #[cfg(feature = "framebuffer-text-drawing")]
     Text {
         text: String,
         scale: f32,
@@ -81,15 +108,298 @@ pub enum UIElement {
     },
     #[cfg(feature = "image")]
     Image { img: image::DynamicImage },
+    /// A single line text field, see `ApplicationContext::type_into`
+    #[cfg(feature = "framebuffer-text-drawing")]
+    TextInput { input: TextInput },
     Region {
         size: cgmath::Vector2<u32>,
         border_color: color,
         border_px: u32,
     },
+    /// Shows other elements on a scrollable area, see
+    /// `ApplicationContext::add_scroll_child`
+    ScrollView { view: ScrollView },
+    /// Scrollable rows made up as they come into view
+    ListView { list: ListView },
+    /// A button or something to check, following the fingers itself
+    Control { control: Control },
+    /// Dragged to pick a number, see `ApplicationContext::display_slider`
+    Slider { slider: Slider },
+    /// Tapped to count up or down
+    Stepper { stepper: Stepper },
+    /// Opens its menus when their titles are tapped, see `ApplicationContext::open_menu`
+    #[cfg(feature = "framebuffer-text-drawing")]
+    MenuBar { bar: MenuBar },
+    /// Drawn on with the pen, see `ApplicationContext::update_canvas`
+    #[cfg(feature = "stroke")]
+    Canvas { canvas: CanvasWidget },
     #[default]
     Unspecified,
 }
 
+/// How thick the lines of controls are
+const CONTROL_BORDER: u32 = 3;
+
+/// What a `Control` is and does when tapped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControlKind {
+    /// Calls `on_press`
+    Button,
+    /// A switch, turned on and off
+    Toggle,
+    /// A box, checked and unchecked
+    Checkbox,
+    /// A circle, checked and unchecking the others of its group
+    Radio,
+}
+
+/// What a touch did to a `Control`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControlEvent {
+    /// A finger came down on it
+    Pressed,
+    /// The finger slid off or lifted elsewhere
+    Cancelled,
+    /// The finger lifted on it, without changing whether it is checked
+    Activated,
+    /// The finger lifted on it, checking or unchecking it
+    Changed(bool),
+}
+
+/// The state of a `UIElement::Control`, see `ApplicationContext::display_control`
+#[derive(Clone, Debug)]
+pub struct Control {
+    pub kind: ControlKind,
+    pub label: String,
+    pub size: cgmath::Vector2<u32>,
+    /// Of the label
+    pub scale: f32,
+    pub checked: bool,
+    /// Radio buttons of the same group uncheck each other
+    pub group: Option<String>,
+    pub on_press: Option<ActiveRegionFunction>,
+    /// Called when it is checked or unchecked, by a tap or by checking another radio
+    /// button of its group
+    pub on_change: Option<ToggleFunction>,
+    /// The finger holding it down
+    pressed: Option<i32>,
+}
+
+impl Control {
+    fn new(kind: ControlKind, label: &str, size: cgmath::Vector2<u32>) -> Control {
+        Control {
+            kind,
+            label: label.to_owned(),
+            size,
+            scale: size.y as f32 * 0.5,
+            checked: false,
+            group: None,
+            on_press: None,
+            on_change: None,
+            pressed: None,
+        }
+    }
+
+    pub fn button(label: &str, size: cgmath::Vector2<u32>) -> Control {
+        Control::new(ControlKind::Button, label, size)
+    }
+
+    pub fn toggle(label: &str, size: cgmath::Vector2<u32>) -> Control {
+        Control::new(ControlKind::Toggle, label, size)
+    }
+
+    pub fn checkbox(label: &str, size: cgmath::Vector2<u32>) -> Control {
+        Control::new(ControlKind::Checkbox, label, size)
+    }
+
+    pub fn radio(label: &str, group: &str, size: cgmath::Vector2<u32>) -> Control {
+        Control {
+            group: Some(group.to_owned()),
+            ..Control::new(ControlKind::Radio, label, size)
+        }
+    }
+
+    /// Whether a finger is holding it down
+    pub fn is_pressed(&self) -> bool {
+        self.pressed.is_some()
+    }
+
+    /// Follows a touch on the control drawn at `rect`, pressing it, and checking or
+    /// unchecking it once the finger lifts on it
+    pub fn handle_multitouch(
+        &mut self,
+        event: &MultitouchEvent,
+        rect: mxcfb_rect,
+    ) -> Option<ControlEvent> {
+        let finger = event.finger()?;
+        let inside = rect.contains_point(&finger.pos.cast().unwrap());
+        let holding = self.pressed == Some(finger.tracking_id);
+        match event {
+            MultitouchEvent::Press { .. } if self.pressed.is_none() && inside => {
+                self.pressed = Some(finger.tracking_id);
+                Some(ControlEvent::Pressed)
+            }
+            MultitouchEvent::Move { .. } if holding && !inside => {
+                self.pressed = None;
+                Some(ControlEvent::Cancelled)
+            }
+            MultitouchEvent::Release { .. } if holding => {
+                self.pressed = None;
+                Some(match inside {
+                    true => self.activate(),
+                    false => ControlEvent::Cancelled,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Does what a tap does
+    pub fn activate(&mut self) -> ControlEvent {
+        let checked = match self.kind {
+            ControlKind::Button => return ControlEvent::Activated,
+            ControlKind::Toggle | ControlKind::Checkbox => !self.checked,
+            ControlKind::Radio => true,
+        };
+        if checked == self.checked {
+            return ControlEvent::Activated;
+        }
+        self.checked = checked;
+        ControlEvent::Changed(checked)
+    }
+
+    pub fn draw(&self, fb: &mut Framebuffer, position: cgmath::Point2<i32>) -> mxcfb_rect {
+        let rect = mxcfb_rect::from(position.cast().unwrap(), self.size);
+        let size = self.size;
+        fb.fill_rect(position, size, color::WHITE);
+        // Pressed buttons turn black, the marks of other controls get bolder
+        let border = match self.is_pressed() {
+            true => CONTROL_BORDER * 2,
+            false => CONTROL_BORDER,
+        };
+        if self.kind == ControlKind::Button {
+            let foreground = match self.is_pressed() {
+                true => {
+                    fb.fill_rect(position, size, color::BLACK);
+                    color::WHITE
+                }
+                false => color::BLACK,
+            };
+            fb.draw_rect(position, size, CONTROL_BORDER, color::BLACK);
+            self.draw_label(fb, rect, true, foreground);
+            return rect;
+        }
+
+        let side = (size.y * 3 / 5).max(1);
+        let pos = cgmath::Point2 {
+            x: position.x + (size.y - side) as i32 / 2,
+            y: position.y + (size.y - side) as i32 / 2,
+        };
+        let radius = side / 2;
+        let center = pos
+            + cgmath::Vector2 {
+                x: radius as i32,
+                y: radius as i32,
+            };
+        let width = match self.kind {
+            ControlKind::Toggle => {
+                let track = cgmath::Vector2 {
+                    x: side * 2,
+                    y: side,
+                };
+                let radii = CornerRadii::uniform(radius);
+                // On is black with the knob on the right
+                let knob = match self.checked {
+                    true => {
+                        fb.fill_rounded_rect(pos, track, radii, color::BLACK);
+                        center
+                            + cgmath::Vector2 {
+                                x: side as i32,
+                                y: 0,
+                            }
+                    }
+                    false => {
+                        fb.draw_rounded_rect(pos, track, radii, border, color::BLACK);
+                        center
+                    }
+                };
+                let inset = radius.saturating_sub(border);
+                fb.fill_circle(knob, inset, color::WHITE);
+                fb.draw_circle(knob, inset, color::BLACK);
+                side * 2
+            }
+            ControlKind::Checkbox => {
+                fb.draw_rect(
+                    pos,
+                    cgmath::Vector2 { x: side, y: side },
+                    border,
+                    color::BLACK,
+                );
+                if self.checked {
+                    let inset = side / 4;
+                    fb.fill_rect(
+                        pos + cgmath::Vector2 {
+                            x: inset as i32,
+                            y: inset as i32,
+                        },
+                        cgmath::Vector2 {
+                            x: side - 2 * inset,
+                            y: side - 2 * inset,
+                        },
+                        color::BLACK,
+                    );
+                }
+                side
+            }
+            ControlKind::Radio => {
+                fb.fill_circle(center, radius, color::BLACK);
+                fb.fill_circle(center, radius.saturating_sub(border), color::WHITE);
+                if self.checked {
+                    fb.fill_circle(center, radius / 2, color::BLACK);
+                }
+                side
+            }
+            ControlKind::Button => unreachable!(),
+        };
+        // The label follows the mark
+        let left = (pos.x - position.x) as u32 * 2 + width;
+        let label = mxcfb_rect {
+            left: rect.left + left,
+            width: rect.width.saturating_sub(left),
+            ..rect
+        };
+        self.draw_label(fb, label, false, color::BLACK);
+        rect
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn draw_label(&self, fb: &mut Framebuffer, area: mxcfb_rect, centered: bool, c: color) {
+        let size = self.scale;
+        let measured = fb.draw_text(
+            cgmath::Point2 { x: 0.0, y: size },
+            &self.label,
+            size,
+            c,
+            true,
+        );
+        let left = match centered {
+            true => (area.width as f32 - measured.width as f32) / 2.0,
+            false => 0.0,
+        };
+        let pos = cgmath::Point2 {
+            x: area.left as f32 + left,
+            // The baseline, so that the letters without descenders are centered
+            y: area.top as f32 + (area.height as f32 + size * 0.6) / 2.0,
+        };
+        fb.push_clip(area);
+        fb.draw_text(pos, &self.label, size, c, false);
+        fb.pop_clip();
+    }
+
+    #[cfg(not(feature = "framebuffer-text-drawing"))]
+    fn draw_label(&self, _fb: &mut Framebuffer, _area: mxcfb_rect, _centered: bool, _c: color) {}
+}
+
 impl UIElementHandle {
     pub fn read(&self) -> RwLockReadGuard<'_, UIElementWrapper> {
         self.0.read().unwrap()
@@ -105,6 +415,91 @@ impl UIElementHandle {
 }
 
 impl UIElementWrapper {
+    /// How large the element is by itself, as far as that is known before it is drawn
+    pub fn natural_size(&self) -> Option<cgmath::Vector2<u32>> {
+        match self.inner {
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::TextInput { ref input } => Some(input.size),
+            #[cfg(feature = "image")]
+            UIElement::Image { ref img } => Some(image::GenericImageView::dimensions(img).into()),
+            UIElement::Region { size, .. } => Some(size),
+            UIElement::ScrollView { ref view } => Some(view.size),
+            UIElement::ListView { ref list } => Some(list.size()),
+            UIElement::Control { ref control } => Some(control.size),
+            UIElement::Slider { ref slider } => Some(slider.size),
+            UIElement::Stepper { ref stepper } => Some(stepper.size),
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::MenuBar { ref bar } => Some(bar.size),
+            #[cfg(feature = "stroke")]
+            UIElement::Canvas { ref canvas } => Some(canvas.size),
+            _ => self.last_drawn_rect.map(|rect| rect.size()),
+        }
+    }
+
+    /// Moves the element to `rect`, resizing those that have a size of their own
+    pub fn place(&mut self, rect: mxcfb_rect) {
+        let baseline = match self.inner {
+            // Text is drawn from its baseline
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::Text { scale, .. } => match self.last_drawn_rect {
+                Some(drawn) => self.position.y - drawn.top as i32,
+                None => scale.round() as i32,
+            },
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::TextInput { ref mut input } => {
+                input.size = rect.size();
+                0
+            }
+            UIElement::Region { ref mut size, .. } => {
+                *size = rect.size();
+                0
+            }
+            UIElement::ScrollView { ref mut view } => {
+                view.size = rect.size();
+                0
+            }
+            UIElement::ListView { ref mut list } => {
+                list.set_size(rect.size());
+                0
+            }
+            UIElement::Control { ref mut control } => {
+                control.size = rect.size();
+                0
+            }
+            UIElement::Slider { ref mut slider } => {
+                slider.size = rect.size();
+                0
+            }
+            UIElement::Stepper { ref mut stepper } => {
+                stepper.size = rect.size();
+                0
+            }
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::MenuBar { ref mut bar } => {
+                bar.size = rect.size();
+                0
+            }
+            #[cfg(feature = "stroke")]
+            UIElement::Canvas { ref mut canvas } => {
+                canvas.size = rect.size();
+                0
+            }
+            _ => 0,
+        };
+        self.position = cgmath::Point2 {
+            x: rect.left as i32,
+            y: rect.top as i32 + baseline,
+        };
+    }
+
+    pub fn is_focusable(&self) -> bool {
+        match self.inner {
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::TextInput { .. } => true,
+            _ => self.focusable,
+        }
+    }
+
     pub fn draw(
         &mut self,
         app: &mut appctx::ApplicationContext<'_>,
@@ -112,6 +507,9 @@ impl UIElementWrapper {
     ) {
         let refresh = self.refresh;
         let framebuffer = app.get_framebuffer_ref();
+        if let Some(clip) = self.clip {
+            framebuffer.push_clip(clip);
+        }
 
         let old_filled_rect = match self.last_drawn_rect {
             Some(rect) => {
@@ -143,6 +541,7 @@ impl UIElementWrapper {
         // TODO: Move this to inside the app and then have it call the UIElement's draw
         // TODO: Also perhaps make border_padding configurable
         let rect = match self.inner {
+            #[cfg(feature = "framebuffer-text-drawing")]
             UIElement::Text {
                 ref text,
                 scale,
@@ -161,6 +560,31 @@ impl UIElementWrapper {
             UIElement::Image { ref img } => {
                 app.display_image(img, self.position.cast().unwrap(), refresh)
             }
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::TextInput { ref mut input } => {
+                app.display_text_input(input, self.position, refresh)
+            }
+            UIElement::ScrollView { ref mut view } => {
+                app.display_scroll_view(view, self.position, refresh)
+            }
+            UIElement::ListView { ref mut list } => {
+                app.display_list_view(list, self.position, refresh)
+            }
+            UIElement::Control { ref control } => {
+                app.display_control(control, self.position, refresh)
+            }
+            UIElement::Slider { ref mut slider } => {
+                app.display_slider(slider, self.position, refresh)
+            }
+            UIElement::Stepper { ref mut stepper } => {
+                app.display_stepper(stepper, self.position, refresh)
+            }
+            #[cfg(feature = "framebuffer-text-drawing")]
+            UIElement::MenuBar { ref bar } => app.display_menu_bar(bar, self.position, refresh),
+            #[cfg(feature = "stroke")]
+            UIElement::Canvas { ref mut canvas } => {
+                app.display_canvas(canvas, self.position, refresh)
+            }
             UIElement::Region {
                 size,
                 border_color,
@@ -172,7 +596,19 @@ impl UIElementWrapper {
                 border_color,
                 refresh,
             ),
-            UIElement::Unspecified => return,
+            UIElement::Unspecified => {
+                if self.clip.is_some() {
+                    framebuffer.pop_clip();
+                }
+                return;
+            }
+        };
+        let rect = match self.clip {
+            Some(clip) => {
+                framebuffer.pop_clip();
+                rect.intersection(&clip).unwrap_or_else(mxcfb_rect::invalid)
+            }
+            None => rect,
         };
 
         // If no changes, no need to change the active region
@@ -220,3 +656,75 @@ impl UIElementWrapper {
         self.last_drawn_rect = Some(rect);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::FramebufferIO;
+    use crate::test_util::{moved, press, release, touch};
+
+    #[test]
+    fn test_controls() {
+        let rect = mxcfb_rect {
+            left: 10,
+            top: 10,
+            width: 200,
+            height: 50,
+        };
+        let mut button = Control::button("OK", rect.size());
+        assert_eq!(
+            button.handle_multitouch(&touch(press, 1, 300, 20), rect),
+            None
+        );
+        assert_eq!(
+            button.handle_multitouch(&touch(press, 2, 20, 20), rect),
+            Some(ControlEvent::Pressed)
+        );
+        // Other fingers don't count while one holds it down
+        assert_eq!(
+            button.handle_multitouch(&touch(release, 1, 20, 20), rect),
+            None
+        );
+        assert!(button.is_pressed());
+        assert_eq!(
+            button.handle_multitouch(&touch(release, 2, 20, 20), rect),
+            Some(ControlEvent::Activated)
+        );
+
+        // Sliding off cancels
+        let mut checkbox = Control::checkbox("Sync", rect.size());
+        checkbox.handle_multitouch(&touch(press, 3, 20, 20), rect);
+        assert_eq!(
+            checkbox.handle_multitouch(&touch(moved, 3, 20, 100), rect),
+            Some(ControlEvent::Cancelled)
+        );
+        assert_eq!(
+            checkbox.handle_multitouch(&touch(release, 3, 20, 20), rect),
+            None
+        );
+        assert!(!checkbox.checked);
+        checkbox.handle_multitouch(&touch(press, 4, 20, 20), rect);
+        assert_eq!(
+            checkbox.handle_multitouch(&touch(release, 4, 25, 25), rect),
+            Some(ControlEvent::Changed(true))
+        );
+        assert_eq!(checkbox.activate(), ControlEvent::Changed(false));
+
+        // Checked radio buttons stay checked
+        let mut radio = Control::radio("Pen", "tool", rect.size());
+        assert_eq!(radio.activate(), ControlEvent::Changed(true));
+        assert_eq!(radio.activate(), ControlEvent::Activated);
+
+        let mut fb = Framebuffer::headless(300, 100);
+        let mut toggle = Control::toggle("Wi-Fi", rect.size());
+        toggle.activate();
+        for control in [&button, &checkbox, &radio, &toggle] {
+            assert_eq!(control.draw(&mut fb, rect.top_left().cast().unwrap()), rect);
+        }
+        // The knob of a toggle that is on is on the right of its black track
+        assert_eq!(
+            fb.read_pixel(cgmath::Point2 { x: 30, y: 35 }).as_native(),
+            color::BLACK.as_native()
+        );
+    }
+}
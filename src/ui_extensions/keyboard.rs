@@ -0,0 +1,541 @@
+//! A keyboard drawn on the display, for devices without a hardware one.
+//!
+//! `OnScreenKeyboard` has a QWERTY, a numeric and a symbols layout and turns taps on
+//! its keys into the same `KeyboardEvent`s a hardware keyboard sends, so that text
+//! fields don't have to care where their input comes from. Characters are typed as
+//! `KeyboardEvent::Text`, while space, backspace and enter are also pressed and
+//! released with their evdev key codes. `ApplicationContext::type_into_focus` types
+//! them into the focused element.
+//!
+//! Shift shifts the next letter, tapping it again locks it like caps lock and a
+//! third tap releases it. Held keys repeat as long as the finger stays on them.
+
+use std::time::{Duration, Instant};
+
+use evdev::Key;
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::FramebufferDraw;
+use crate::input::keyboard::KeyRepeat;
+use crate::input::{KeyboardEvent, Modifiers, MultitouchEvent};
+
+/// Around each key
+const KEY_GAP: u32 = 6;
+const KEY_BORDER: u32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnScreenLayout {
+    Qwerty,
+    Numeric,
+    Symbols,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SoftKey {
+    Char(char),
+    Space,
+    Backspace,
+    Enter,
+    Shift,
+    /// Switches to another layout
+    Layout(OnScreenLayout),
+}
+
+impl SoftKey {
+    /// Relative to that of a character
+    fn width(self) -> f32 {
+        match self {
+            SoftKey::Char(_) => 1.0,
+            SoftKey::Space => 4.0,
+            SoftKey::Enter => 2.0,
+            SoftKey::Backspace | SoftKey::Shift | SoftKey::Layout(_) => 1.5,
+        }
+    }
+
+    /// The evdev key code pressed and released along, if any
+    fn code(self) -> Option<u16> {
+        match self {
+            SoftKey::Space => Some(Key::KEY_SPACE.code()),
+            SoftKey::Backspace => Some(Key::KEY_BACKSPACE.code()),
+            SoftKey::Enter => Some(Key::KEY_ENTER.code()),
+            _ => None,
+        }
+    }
+
+    fn repeats(self) -> bool {
+        matches!(self, SoftKey::Char(_) | SoftKey::Space | SoftKey::Backspace)
+    }
+
+    fn label(self, shifted: bool) -> String {
+        match self {
+            SoftKey::Char(c) if shifted => c.to_uppercase().collect(),
+            SoftKey::Char(c) => c.to_string(),
+            SoftKey::Space => "space".to_owned(),
+            SoftKey::Backspace => "del".to_owned(),
+            SoftKey::Enter => "enter".to_owned(),
+            SoftKey::Shift => "shift".to_owned(),
+            SoftKey::Layout(OnScreenLayout::Qwerty) => "abc".to_owned(),
+            SoftKey::Layout(OnScreenLayout::Numeric) => "123".to_owned(),
+            SoftKey::Layout(OnScreenLayout::Symbols) => "#+=".to_owned(),
+        }
+    }
+}
+
+impl OnScreenLayout {
+    /// The keys from the top row to the bottom one
+    pub fn rows(self) -> Vec<Vec<SoftKey>> {
+        let chars = |row: &str| row.chars().map(SoftKey::Char).collect::<Vec<_>>();
+        let with = |before: &[SoftKey], row: &str, after: &[SoftKey]| {
+            let mut keys = before.to_vec();
+            keys.extend(chars(row));
+            keys.extend_from_slice(after);
+            keys
+        };
+        let bottom = |layout| {
+            vec![
+                SoftKey::Layout(layout),
+                SoftKey::Char(','),
+                SoftKey::Space,
+                SoftKey::Char('.'),
+                SoftKey::Enter,
+            ]
+        };
+        match self {
+            OnScreenLayout::Qwerty => vec![
+                chars("qwertyuiop"),
+                chars("asdfghjkl"),
+                with(&[SoftKey::Shift], "zxcvbnm", &[SoftKey::Backspace]),
+                bottom(OnScreenLayout::Symbols),
+            ],
+            OnScreenLayout::Symbols => vec![
+                chars("1234567890"),
+                chars("@#$%&*-+()"),
+                with(
+                    &[SoftKey::Layout(OnScreenLayout::Numeric)],
+                    "!\"':;/?",
+                    &[SoftKey::Backspace],
+                ),
+                bottom(OnScreenLayout::Qwerty),
+            ],
+            OnScreenLayout::Numeric => vec![
+                chars("123"),
+                chars("456"),
+                chars("789"),
+                with(&[SoftKey::Char('.')], "0", &[SoftKey::Backspace]),
+                vec![SoftKey::Layout(OnScreenLayout::Qwerty), SoftKey::Enter],
+            ],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Shift {
+    Off,
+    /// For the next letter
+    Once,
+    Locked,
+}
+
+/// The key a finger holds down
+#[derive(Copy, Clone, Debug)]
+struct Held {
+    finger: i32,
+    key: SoftKey,
+    /// When it repeats next, `None` once the finger left it
+    next: Option<Instant>,
+}
+
+pub struct OnScreenKeyboard {
+    /// Where on the screen the keyboard is
+    pub rect: mxcfb_rect,
+    /// How held keys repeat, `None` to not repeat them
+    pub repeat: Option<KeyRepeat>,
+    layout: OnScreenLayout,
+    shift: Shift,
+    held: Option<Held>,
+    changed: bool,
+}
+
+impl OnScreenKeyboard {
+    pub fn new(rect: mxcfb_rect) -> OnScreenKeyboard {
+        OnScreenKeyboard {
+            rect,
+            repeat: Some(KeyRepeat::default()),
+            layout: OnScreenLayout::Qwerty,
+            shift: Shift::Off,
+            held: None,
+            changed: true,
+        }
+    }
+
+    pub fn layout(&self) -> OnScreenLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: OnScreenLayout) {
+        self.layout = layout;
+        self.shift = Shift::Off;
+        self.changed = true;
+    }
+
+    /// Shift is held while it is on for the next letter
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.shift == Shift::Once,
+            caps_lock: self.shift == Shift::Locked,
+            ..Modifiers::default()
+        }
+    }
+
+    /// Whether the keys look different than when they were last drawn, e.g. after
+    /// switching the layout
+    pub fn needs_redraw(&self) -> bool {
+        self.changed
+    }
+
+    /// Every key of the current layout and where it is
+    pub fn keys(&self) -> Vec<(SoftKey, mxcfb_rect)> {
+        let rows = self.layout.rows();
+        let units = rows
+            .iter()
+            .map(|row| row.iter().map(|key| key.width()).sum::<f32>())
+            .fold(1.0, f32::max);
+        let unit = self.rect.width as f32 / units;
+        let height = self.rect.height / rows.len() as u32;
+
+        let mut keys = Vec::new();
+        for (i, row) in rows.into_iter().enumerate() {
+            let width: f32 = row.iter().map(|key| key.width()).sum();
+            // Shorter rows are centered
+            let mut x = self.rect.left as f32 + (units - width) * unit / 2.0;
+            for key in row {
+                let left = x.round() as u32;
+                x += key.width() * unit;
+                let rect = mxcfb_rect {
+                    left,
+                    top: self.rect.top + i as u32 * height,
+                    width: x.round() as u32 - left,
+                    height,
+                };
+                keys.push((key, rect));
+            }
+        }
+        keys
+    }
+
+    /// The key at `pos`
+    pub fn key_at(&self, pos: cgmath::Point2<u16>) -> Option<SoftKey> {
+        let pos = pos.cast().unwrap();
+        self.keys()
+            .into_iter()
+            .find(|(_, rect)| rect.contains_point(&pos))
+            .map(|(key, _)| key)
+    }
+
+    /// Handles a touch, returning what it typed. Taps on keys type right away.
+    pub fn handle_multitouch(
+        &mut self,
+        event: &MultitouchEvent,
+        now: Instant,
+    ) -> Vec<KeyboardEvent> {
+        match event {
+            MultitouchEvent::Press { finger } => {
+                let mut events = self.lift();
+                if let Some(key) = self.key_at(finger.pos) {
+                    events.extend(self.press(key, false));
+                    let next = match self.repeat {
+                        Some(repeat) if key.repeats() => Some(now + repeat.delay),
+                        _ => None,
+                    };
+                    self.held = Some(Held {
+                        finger: finger.tracking_id,
+                        key,
+                        next,
+                    });
+                }
+                events
+            }
+            MultitouchEvent::Move { finger } => {
+                let key_at = self.key_at(finger.pos);
+                if let Some(held) = self.held.as_mut() {
+                    if held.finger == finger.tracking_id && key_at != Some(held.key) {
+                        held.next = None;
+                    }
+                }
+                Vec::new()
+            }
+            MultitouchEvent::Release { finger } => match self.held {
+                Some(held) if held.finger == finger.tracking_id => self.lift(),
+                _ => Vec::new(),
+            },
+            MultitouchEvent::Unknown => Vec::new(),
+        }
+    }
+
+    /// When `repeat` has to be called next for a held key
+    pub fn next_repeat(&self) -> Option<Instant> {
+        self.held.and_then(|held| held.next)
+    }
+
+    /// The repeats of the held key due at `now`
+    pub fn repeat(&mut self, now: Instant) -> Vec<KeyboardEvent> {
+        let (held, mut at, repeat) = match (self.held, self.next_repeat(), self.repeat) {
+            (Some(held), Some(at), Some(repeat)) => (held, at, repeat),
+            _ => return Vec::new(),
+        };
+        let interval = repeat.interval.max(Duration::from_millis(1));
+        let mut events = Vec::new();
+        while at <= now {
+            events.extend(self.press(held.key, true));
+            at += interval;
+        }
+        self.held = Some(Held {
+            next: Some(at),
+            ..held
+        });
+        events
+    }
+
+    /// Releases the held key
+    fn lift(&mut self) -> Vec<KeyboardEvent> {
+        match self.held.take().and_then(|held| held.key.code()) {
+            Some(key) => vec![KeyboardEvent::Release {
+                key,
+                modifiers: self.modifiers(),
+            }],
+            None => Vec::new(),
+        }
+    }
+
+    fn press(&mut self, key: SoftKey, repeat: bool) -> Vec<KeyboardEvent> {
+        let modifiers = self.modifiers();
+        let mut events = Vec::new();
+        if let Some(key) = key.code() {
+            events.push(KeyboardEvent::Press {
+                key,
+                modifiers,
+                repeat,
+            });
+        }
+        match key {
+            SoftKey::Char(c) => {
+                let shifted = modifiers.shift || modifiers.caps_lock;
+                let mut upper = c.to_uppercase();
+                let character = match (shifted, upper.next(), upper.next()) {
+                    (true, Some(upper), None) => upper,
+                    _ => c,
+                };
+                events.push(KeyboardEvent::Text { character });
+                if self.shift == Shift::Once && c.is_alphabetic() {
+                    self.shift = Shift::Off;
+                    self.changed = true;
+                }
+            }
+            SoftKey::Space => events.push(KeyboardEvent::Text { character: ' ' }),
+            SoftKey::Enter => events.push(KeyboardEvent::Text { character: '\n' }),
+            SoftKey::Backspace => {}
+            SoftKey::Shift => {
+                self.shift = match self.shift {
+                    Shift::Off => Shift::Once,
+                    Shift::Once => Shift::Locked,
+                    Shift::Locked => Shift::Off,
+                };
+                self.changed = true;
+            }
+            SoftKey::Layout(layout) => self.set_layout(layout),
+        }
+        events
+    }
+
+    /// Draws the keys of the current layout. Returns the area to refresh.
+    pub fn draw(&mut self, fb: &mut Framebuffer) -> mxcfb_rect {
+        self.changed = false;
+        let rect = self.rect;
+        fb.fill_rect(rect.top_left().cast().unwrap(), rect.size(), color::WHITE);
+        fb.fill_rect(
+            rect.top_left().cast().unwrap(),
+            cgmath::Vector2 {
+                x: rect.width,
+                y: KEY_BORDER.min(rect.height),
+            },
+            color::BLACK,
+        );
+
+        let shifted = self.shift != Shift::Off;
+        for (key, area) in self.keys() {
+            if area.width <= 2 * KEY_GAP || area.height <= 2 * KEY_GAP {
+                continue;
+            }
+            let pos = cgmath::Point2 {
+                x: (area.left + KEY_GAP) as i32,
+                y: (area.top + KEY_GAP) as i32,
+            };
+            let size = cgmath::Vector2 {
+                x: area.width - 2 * KEY_GAP,
+                y: area.height - 2 * KEY_GAP,
+            };
+            // Locked shift is black, shift for the next letter has a thicker border
+            let (border, foreground) = match (key, self.shift) {
+                (SoftKey::Shift, Shift::Locked) => {
+                    fb.fill_rect(pos, size, color::BLACK);
+                    (KEY_BORDER, color::WHITE)
+                }
+                (SoftKey::Shift, Shift::Once) => (KEY_BORDER * 3, color::BLACK),
+                _ => (KEY_BORDER, color::BLACK),
+            };
+            fb.draw_rect(pos, size, border, color::BLACK);
+            self.draw_label(fb, &key.label(shifted), area, foreground);
+        }
+        rect
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn draw_label(&self, fb: &mut Framebuffer, label: &str, area: mxcfb_rect, c: color) {
+        let size = (area.height as f32 * 0.4).min(area.width as f32 * 0.5);
+        let measured = fb.draw_text(cgmath::Point2 { x: 0.0, y: size }, label, size, c, true);
+        let pos = cgmath::Point2 {
+            x: area.left as f32 + (area.width as f32 - measured.width as f32) / 2.0,
+            // The baseline, so that the letters without descenders are centered
+            y: area.top as f32 + (area.height as f32 + size * 0.6) / 2.0,
+        };
+        fb.draw_text(pos, label, size, c, false);
+    }
+
+    #[cfg(not(feature = "framebuffer-text-drawing"))]
+    fn draw_label(&self, _fb: &mut Framebuffer, _label: &str, _area: mxcfb_rect, _c: color) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::Finger;
+
+    fn touch(keyboard: &mut OnScreenKeyboard, key: SoftKey) -> Vec<KeyboardEvent> {
+        let (_, rect) = keyboard
+            .keys()
+            .into_iter()
+            .find(|(k, _)| *k == key)
+            .unwrap();
+        let pos = cgmath::Point2 {
+            x: (rect.left + rect.width / 2) as u16,
+            y: (rect.top + rect.height / 2) as u16,
+        };
+        let now = Instant::now();
+        let finger = Finger::new(1, pos, true);
+        let mut events = keyboard.handle_multitouch(&MultitouchEvent::Press { finger }, now);
+        let finger = Finger::new(1, pos, false);
+        events.extend(keyboard.handle_multitouch(&MultitouchEvent::Release { finger }, now));
+        events
+    }
+
+    fn text(events: &[KeyboardEvent]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                KeyboardEvent::Text { character } => Some(*character),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_on_screen_keyboard() {
+        let mut keyboard = OnScreenKeyboard::new(mxcfb_rect {
+            left: 0,
+            top: 1000,
+            width: 1000,
+            height: 400,
+        });
+        // The keys of each row are next to each other and within the keyboard
+        for layout in [
+            OnScreenLayout::Qwerty,
+            OnScreenLayout::Numeric,
+            OnScreenLayout::Symbols,
+        ] {
+            keyboard.set_layout(layout);
+            for (_, rect) in keyboard.keys() {
+                assert!(keyboard.rect.contains_rect(&rect), "{:?}", rect);
+            }
+        }
+        keyboard.set_layout(OnScreenLayout::Qwerty);
+        assert_eq!(
+            keyboard.key_at(cgmath::Point2 { x: 10, y: 1010 }),
+            Some(SoftKey::Char('q'))
+        );
+        assert_eq!(keyboard.key_at(cgmath::Point2 { x: 10, y: 10 }), None);
+
+        // Shift is for the next letter, unless locked
+        assert_eq!(text(&touch(&mut keyboard, SoftKey::Char('h'))), "h");
+        touch(&mut keyboard, SoftKey::Shift);
+        assert!(keyboard.modifiers().shift);
+        assert_eq!(text(&touch(&mut keyboard, SoftKey::Char('h'))), "H");
+        assert_eq!(text(&touch(&mut keyboard, SoftKey::Char('i'))), "i");
+        touch(&mut keyboard, SoftKey::Shift);
+        touch(&mut keyboard, SoftKey::Shift);
+        assert!(keyboard.modifiers().caps_lock);
+        assert_eq!(text(&touch(&mut keyboard, SoftKey::Char('o'))), "O");
+        assert_eq!(text(&touch(&mut keyboard, SoftKey::Char('k'))), "K");
+        touch(&mut keyboard, SoftKey::Shift);
+        assert_eq!(keyboard.modifiers(), Modifiers::default());
+
+        // Keys with a key code are pressed and released
+        let enter = Key::KEY_ENTER.code();
+        assert!(matches!(
+            touch(&mut keyboard, SoftKey::Enter)[..],
+            [
+                KeyboardEvent::Press { key, repeat: false, .. },
+                KeyboardEvent::Text { character: '\n' },
+                KeyboardEvent::Release { key: released, .. },
+            ] if key == enter && released == enter
+        ));
+
+        // Switching layouts
+        touch(&mut keyboard, SoftKey::Layout(OnScreenLayout::Symbols));
+        assert_eq!(keyboard.layout(), OnScreenLayout::Symbols);
+        assert!(keyboard.needs_redraw());
+        assert_eq!(text(&touch(&mut keyboard, SoftKey::Char('7'))), "7");
+
+        // Held keys repeat while the finger stays on them
+        keyboard.repeat = Some(KeyRepeat {
+            delay: Duration::from_millis(500),
+            interval: Duration::from_millis(100),
+        });
+        let start = Instant::now();
+        let (_, rect) = keyboard
+            .keys()
+            .into_iter()
+            .find(|(key, _)| *key == SoftKey::Backspace)
+            .unwrap();
+        let pos = cgmath::Point2 {
+            x: (rect.left + 5) as u16,
+            y: (rect.top + 5) as u16,
+        };
+        let finger = Finger::new(2, pos, true);
+        assert_eq!(
+            keyboard
+                .handle_multitouch(&MultitouchEvent::Press { finger }, start)
+                .len(),
+            1
+        );
+        assert_eq!(
+            keyboard.next_repeat(),
+            Some(start + Duration::from_millis(500))
+        );
+        let repeats = keyboard.repeat(start + Duration::from_millis(750));
+        assert_eq!(repeats.len(), 3);
+        assert!(matches!(
+            repeats[0],
+            KeyboardEvent::Press { repeat: true, .. }
+        ));
+        let finger = Finger::new(2, cgmath::Point2 { x: 0, y: 0 }, true);
+        keyboard.handle_multitouch(&MultitouchEvent::Move { finger }, start);
+        assert_eq!(keyboard.next_repeat(), None);
+        let finger = Finger::new(2, cgmath::Point2 { x: 0, y: 0 }, false);
+        assert!(matches!(
+            keyboard.handle_multitouch(&MultitouchEvent::Release { finger }, start)[..],
+            [KeyboardEvent::Release { .. }]
+        ));
+    }
+}
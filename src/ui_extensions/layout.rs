@@ -0,0 +1,452 @@
+//! Arranging elements in rows and columns instead of at fixed positions.
+//!
+//! A `LayoutNode` tree is laid out like a single line CSS flexbox: the children of a
+//! row or a column are placed one after the other along it, space left over is shared
+//! by those that `grow` and missing space is taken from those that `shrink`. Leaves
+//! name the `UIElementWrapper`s they place:
+//!
+//! ```no_run
+//! # use libremarkable::appctx::ApplicationContext;
+//! # use libremarkable::ui_extensions::layout::{Edges, LayoutNode};
+//! let mut app = ApplicationContext::default();
+//! let layout = LayoutNode::column(vec![
+//!     LayoutNode::element("toolbar").height(100),
+//!     LayoutNode::element("canvas").grow(1.0),
+//! ])
+//! .padding(Edges::all(20));
+//! app.set_layout(Some(layout));
+//! ```
+//!
+//! `ApplicationContext::set_layout` moves the elements into place right away and again
+//! whenever the screen is rotated, `relayout` after their content changed.
+
+use std::collections::HashMap;
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::mxcfb_rect;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Row,
+    Column,
+}
+
+/// How children are placed across a row or a column
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+    /// Filling it, unless they have a size across it
+    #[default]
+    Stretch,
+}
+
+/// How the space left over along a row or a column is placed around the children
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Justify {
+    #[default]
+    Start,
+    Center,
+    End,
+    SpaceBetween,
+    SpaceAround,
+}
+
+/// Space around the four edges of a node
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct Edges {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Edges {
+    pub fn all(space: u32) -> Edges {
+        Edges::symmetric(space, space)
+    }
+
+    pub fn symmetric(vertical: u32, horizontal: u32) -> Edges {
+        Edges {
+            top: vertical,
+            right: horizontal,
+            bottom: vertical,
+            left: horizontal,
+        }
+    }
+
+    fn size(&self) -> cgmath::Vector2<f32> {
+        cgmath::Vector2 {
+            x: (self.left + self.right) as f32,
+            y: (self.top + self.bottom) as f32,
+        }
+    }
+}
+
+/// A row, a column or an element in a layout, see the module documentation
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutNode {
+    /// The element placed in the node
+    pub name: Option<String>,
+    pub direction: Direction,
+    pub justify: Justify,
+    pub align: Align,
+    /// Overrides the `align` of the parent
+    pub align_self: Option<Align>,
+    /// The share of the space left over the node takes along its parent
+    pub grow: f32,
+    /// The share of the missing space the node gives up, relative to its size
+    pub shrink: f32,
+    /// Including the padding, the natural size otherwise
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub padding: Edges,
+    pub margin: Edges,
+    /// Between children
+    pub gap: u32,
+    pub children: Vec<LayoutNode>,
+}
+
+impl Default for LayoutNode {
+    fn default() -> LayoutNode {
+        LayoutNode {
+            name: None,
+            direction: Direction::default(),
+            justify: Justify::default(),
+            align: Align::default(),
+            align_self: None,
+            grow: 0.0,
+            shrink: 1.0,
+            width: None,
+            height: None,
+            padding: Edges::default(),
+            margin: Edges::default(),
+            gap: 0,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Along the direction of a row or a column, and across it
+fn main(v: cgmath::Vector2<f32>, direction: Direction) -> f32 {
+    match direction {
+        Direction::Row => v.x,
+        Direction::Column => v.y,
+    }
+}
+
+fn cross(v: cgmath::Vector2<f32>, direction: Direction) -> f32 {
+    match direction {
+        Direction::Row => v.y,
+        Direction::Column => v.x,
+    }
+}
+
+fn vector(main: f32, cross: f32, direction: Direction) -> cgmath::Vector2<f32> {
+    match direction {
+        Direction::Row => cgmath::Vector2 { x: main, y: cross },
+        Direction::Column => cgmath::Vector2 { x: cross, y: main },
+    }
+}
+
+impl LayoutNode {
+    pub fn row(children: Vec<LayoutNode>) -> LayoutNode {
+        LayoutNode {
+            direction: Direction::Row,
+            children,
+            ..LayoutNode::default()
+        }
+    }
+
+    pub fn column(children: Vec<LayoutNode>) -> LayoutNode {
+        LayoutNode {
+            direction: Direction::Column,
+            children,
+            ..LayoutNode::default()
+        }
+    }
+
+    /// A leaf placing the element `name`
+    pub fn element(name: &str) -> LayoutNode {
+        LayoutNode {
+            name: Some(name.to_owned()),
+            ..LayoutNode::default()
+        }
+    }
+
+    pub fn grow(mut self, grow: f32) -> LayoutNode {
+        self.grow = grow;
+        self
+    }
+
+    pub fn shrink(mut self, shrink: f32) -> LayoutNode {
+        self.shrink = shrink;
+        self
+    }
+
+    pub fn width(mut self, width: u32) -> LayoutNode {
+        self.width = Some(width);
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> LayoutNode {
+        self.height = Some(height);
+        self
+    }
+
+    pub fn padding(mut self, padding: Edges) -> LayoutNode {
+        self.padding = padding;
+        self
+    }
+
+    pub fn margin(mut self, margin: Edges) -> LayoutNode {
+        self.margin = margin;
+        self
+    }
+
+    pub fn gap(mut self, gap: u32) -> LayoutNode {
+        self.gap = gap;
+        self
+    }
+
+    pub fn justify(mut self, justify: Justify) -> LayoutNode {
+        self.justify = justify;
+        self
+    }
+
+    pub fn align(mut self, align: Align) -> LayoutNode {
+        self.align = align;
+        self
+    }
+
+    pub fn align_self(mut self, align: Align) -> LayoutNode {
+        self.align_self = Some(align);
+        self
+    }
+
+    /// Names the node, so that its rectangle is among those `compute` returns
+    pub fn name(mut self, name: &str) -> LayoutNode {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// The size the node takes unless stretched, grown or shrunk, without its margin.
+    /// `size_of` is the natural size of the element of a leaf.
+    fn natural<F>(&self, size_of: &F) -> cgmath::Vector2<f32>
+    where
+        F: Fn(&str) -> Option<cgmath::Vector2<u32>>,
+    {
+        let content = if self.children.is_empty() {
+            self.name
+                .as_deref()
+                .and_then(size_of)
+                .map_or(cgmath::Vector2 { x: 0.0, y: 0.0 }, |size| {
+                    size.cast().unwrap()
+                })
+                + self.padding.size()
+        } else {
+            let direction = self.direction;
+            let (mut along, mut across) = (0.0f32, 0.0f32);
+            for child in &self.children {
+                let size = child.natural(size_of) + child.margin.size();
+                along += main(size, direction);
+                across = across.max(cross(size, direction));
+            }
+            along += (self.gap * (self.children.len() as u32 - 1)) as f32;
+            vector(along, across, direction) + self.padding.size()
+        };
+        cgmath::Vector2 {
+            x: self.width.map_or(content.x, |width| width as f32),
+            y: self.height.map_or(content.y, |height| height as f32),
+        }
+    }
+
+    /// Lays the tree out on `area`, returning where each named node goes. `size_of`
+    /// is the natural size of the element of a leaf, if it has one.
+    pub fn compute<F>(&self, area: mxcfb_rect, size_of: F) -> HashMap<String, mxcfb_rect>
+    where
+        F: Fn(&str) -> Option<cgmath::Vector2<u32>>,
+    {
+        let mut rects = HashMap::new();
+        let origin = cgmath::Point2 {
+            x: area.left as f32,
+            y: area.top as f32,
+        };
+        self.place(origin, area.size().cast().unwrap(), &size_of, &mut rects);
+        rects
+    }
+
+    fn place<F>(
+        &self,
+        origin: cgmath::Point2<f32>,
+        size: cgmath::Vector2<f32>,
+        size_of: &F,
+        rects: &mut HashMap<String, mxcfb_rect>,
+    ) where
+        F: Fn(&str) -> Option<cgmath::Vector2<u32>>,
+    {
+        if let Some(ref name) = self.name {
+            let left = origin.x.round().max(0.0) as u32;
+            let top = origin.y.round().max(0.0) as u32;
+            rects.insert(
+                name.clone(),
+                mxcfb_rect {
+                    left,
+                    top,
+                    width: ((origin.x + size.x).round().max(0.0) as u32).saturating_sub(left),
+                    height: ((origin.y + size.y).round().max(0.0) as u32).saturating_sub(top),
+                },
+            );
+        }
+        if self.children.is_empty() {
+            return;
+        }
+
+        let direction = self.direction;
+        let inner_origin = origin
+            + cgmath::Vector2 {
+                x: self.padding.left as f32,
+                y: self.padding.top as f32,
+            };
+        let inner = size - self.padding.size();
+        let (length, thickness) = (main(inner, direction).max(0.0), cross(inner, direction));
+
+        // The sizes along the direction, grown or shrunk to fit
+        let naturals: Vec<_> = self
+            .children
+            .iter()
+            .map(|child| child.natural(size_of))
+            .collect();
+        let mut sizes: Vec<f32> = naturals.iter().map(|size| main(*size, direction)).collect();
+        let used: f32 = self
+            .children
+            .iter()
+            .zip(&sizes)
+            .map(|(child, size)| size + main(child.margin.size(), direction))
+            .sum::<f32>()
+            + (self.gap * (self.children.len() as u32 - 1)) as f32;
+        let mut free = length - used;
+        let grow: f32 = self.children.iter().map(|child| child.grow).sum();
+        let shrink: f32 = self
+            .children
+            .iter()
+            .zip(&sizes)
+            .map(|(child, size)| child.shrink * size)
+            .sum();
+        if free > 0.0 && grow > 0.0 {
+            for (child, size) in self.children.iter().zip(sizes.iter_mut()) {
+                *size += free * child.grow / grow;
+            }
+            free = 0.0;
+        } else if free < 0.0 && shrink > 0.0 {
+            for (child, size) in self.children.iter().zip(sizes.iter_mut()) {
+                *size = (*size + free * child.shrink * *size / shrink).max(0.0);
+            }
+            free = 0.0;
+        }
+        let free = free.max(0.0);
+
+        let count = self.children.len() as f32;
+        let (mut along, spacing) = match self.justify {
+            Justify::Start => (0.0, 0.0),
+            Justify::Center => (free / 2.0, 0.0),
+            Justify::End => (free, 0.0),
+            Justify::SpaceBetween if count > 1.0 => (0.0, free / (count - 1.0)),
+            Justify::SpaceBetween => (0.0, 0.0),
+            Justify::SpaceAround => (free / count / 2.0, free / count),
+        };
+        for ((child, natural), size) in self.children.iter().zip(naturals).zip(sizes) {
+            let margin = child.margin;
+            let (before, after) = match direction {
+                Direction::Row => (margin.left, margin.right),
+                Direction::Column => (margin.top, margin.bottom),
+            };
+            let (above, below) = match direction {
+                Direction::Row => (margin.top, margin.bottom),
+                Direction::Column => (margin.left, margin.right),
+            };
+            let room = (thickness - (above + below) as f32).max(0.0);
+            let fixed = match direction {
+                Direction::Row => child.height,
+                Direction::Column => child.width,
+            };
+            let align = child.align_self.unwrap_or(self.align);
+            let across = match (align, fixed) {
+                (Align::Stretch, None) => room,
+                _ => cross(natural, direction).min(room),
+            };
+            let offset = above as f32
+                + match align {
+                    Align::Start | Align::Stretch => 0.0,
+                    Align::Center => (room - across) / 2.0,
+                    Align::End => room - across,
+                };
+
+            along += before as f32;
+            child.place(
+                inner_origin + vector(along, offset, direction),
+                vector(size, across, direction),
+                size_of,
+                rects,
+            );
+            along += size + after as f32 + self.gap as f32 + spacing;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::rect;
+
+    #[test]
+    fn test_layout() {
+        let area = rect(0, 0, 1000, 800);
+        let layout = LayoutNode::column(vec![
+            LayoutNode::element("toolbar").height(100),
+            LayoutNode::row(vec![
+                LayoutNode::element("sidebar").width(200),
+                LayoutNode::element("canvas").grow(1.0),
+            ])
+            .gap(10)
+            .grow(1.0),
+            LayoutNode::row(vec![
+                LayoutNode::element("ok"),
+                LayoutNode::element("cancel").margin(Edges::all(5)),
+            ])
+            .name("buttons")
+            .justify(Justify::End)
+            .align(Align::Center),
+        ])
+        .padding(Edges::all(20));
+        let size_of = |name: &str| match name {
+            "ok" | "cancel" => Some(cgmath::Vector2 { x: 100, y: 40 }),
+            _ => None,
+        };
+        let rects = layout.compute(area, size_of);
+        assert_eq!(rects["toolbar"], rect(20, 20, 960, 100));
+        // The buttons take their natural height, the rest goes to the middle row
+        assert_eq!(rects["buttons"], rect(20, 730, 960, 50));
+        assert_eq!(rects["sidebar"], rect(20, 120, 200, 610));
+        assert_eq!(rects["canvas"], rect(230, 120, 750, 610));
+        assert_eq!(rects["cancel"], rect(875, 735, 100, 40));
+        assert_eq!(rects["ok"], rect(770, 735, 100, 40));
+
+        // Rotated, the same layout fits the new shape
+        let rects = layout.compute(rect(0, 0, 800, 1000), size_of);
+        assert_eq!(rects["canvas"], rect(230, 120, 550, 810));
+
+        // Too little space is taken from those that shrink, by their size
+        let row = LayoutNode::row(vec![
+            LayoutNode::element("a").width(300),
+            LayoutNode::element("b").width(100),
+            LayoutNode::element("c").width(100).shrink(0.0),
+        ]);
+        let rects = row.compute(rect(0, 0, 300, 10), |_| None);
+        assert_eq!(rects["a"].width, 150);
+        assert_eq!(rects["b"].width, 50);
+        assert_eq!(rects["c"], rect(200, 0, 100, 10));
+    }
+}
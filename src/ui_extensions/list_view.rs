@@ -0,0 +1,409 @@
+//! A list of any number of rows, of which only those in view exist.
+//!
+//! A `ListView` is the state of a `UIElement::ListView`. Instead of an element per
+//! item, it has its `provider` fill in a row for an item once that row scrolls into
+//! view, reusing the rows that scrolled out of view for it. A list of thousands of
+//! files this way costs about as much as a screenful of them. It scrolls like the
+//! `ScrollView` it is built on, and tapping or holding a row calls `on_select` or
+//! `on_long_press` with the index of its item.
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::appctx::ApplicationContext;
+use crate::framebuffer::cgmath;
+use crate::framebuffer::cgmath::{EuclideanSpace, InnerSpace};
+use crate::framebuffer::common::mxcfb_rect;
+use crate::input::MultitouchEvent;
+use crate::ui_extensions::element::{UIConstraintRefresh, UIElementHandle, UIElementWrapper};
+use crate::ui_extensions::scroll_view::ScrollView;
+
+/// Fills in the row for the item at an index. The row may still show the item it was
+/// used for before, and its position is relative to its top left corner.
+pub type RowProvider = Arc<dyn Fn(usize, &mut UIElementWrapper) + Send + Sync>;
+
+/// Called with the list and the index of the item selected
+pub type ListSelectFunction = fn(&mut ApplicationContext<'_>, UIElementHandle, usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ListEvent {
+    Tap(usize),
+    LongPress(usize),
+}
+
+#[derive(Clone)]
+struct Row {
+    /// Of the item shown, none if the row is free to be reused
+    index: Option<usize>,
+    element: UIElementWrapper,
+    /// Where the provider put the element on the row
+    offset: cgmath::Vector2<i32>,
+}
+
+#[derive(Clone, Debug)]
+struct Press {
+    finger: i32,
+    start: cgmath::Point2<f32>,
+    index: usize,
+    at: Instant,
+    /// Set once it was reported as a long press
+    held: bool,
+}
+
+#[derive(Clone)]
+pub struct ListView {
+    pub row_height: u32,
+    pub provider: RowProvider,
+    pub on_select: Option<ListSelectFunction>,
+    pub on_long_press: Option<ListSelectFunction>,
+    /// How long a row has to be held for a long press
+    pub hold: Duration,
+    view: ScrollView,
+    len: usize,
+    rows: Vec<Row>,
+    press: Option<Press>,
+    events: Vec<ListEvent>,
+}
+
+impl std::fmt::Debug for ListView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ListView")
+            .field("row_height", &self.row_height)
+            .field("len", &self.len)
+            .field("view", &self.view)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ListView {
+    /// A list of `len` items in rows of `row_height`, filled in by `provider`
+    pub fn new(
+        size: cgmath::Vector2<u32>,
+        len: usize,
+        row_height: u32,
+        provider: impl Fn(usize, &mut UIElementWrapper) + Send + Sync + 'static,
+    ) -> ListView {
+        let mut list = ListView {
+            row_height,
+            provider: Arc::new(provider),
+            on_select: None,
+            on_long_press: None,
+            hold: Duration::from_millis(500),
+            view: ScrollView::new(size, size),
+            len,
+            rows: Vec::new(),
+            press: None,
+            events: Vec::new(),
+        };
+        list.set_len(len);
+        list
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Changes the number of items, keeping the rows of those still there
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len;
+        self.view.content_size = cgmath::Vector2 {
+            x: self.view.size.x,
+            y: (len as u64 * u64::from(self.row_height)).min(u64::from(u32::MAX)) as u32,
+        };
+        self.view.scroll_to(self.view.offset());
+        for row in &mut self.rows {
+            if row.index.is_some_and(|index| index >= len) {
+                row.index = None;
+            }
+        }
+    }
+
+    /// Has the provider fill in every row again when it is next drawn, e.g. after
+    /// the items changed
+    pub fn invalidate(&mut self) {
+        for row in &mut self.rows {
+            row.index = None;
+        }
+    }
+
+    pub fn size(&self) -> cgmath::Vector2<u32> {
+        self.view.size
+    }
+
+    pub fn set_size(&mut self, size: cgmath::Vector2<u32>) {
+        self.view.size = size;
+        self.set_len(self.len);
+    }
+
+    /// The scrolling, whose `mode`, `friction` and the like can be changed
+    pub fn view(&self) -> &ScrollView {
+        &self.view
+    }
+
+    pub fn view_mut(&mut self) -> &mut ScrollView {
+        &mut self.view
+    }
+
+    /// Scrolls the item at `index` to the top, as far as the list goes
+    pub fn scroll_to_index(&mut self, index: usize) {
+        self.view.scroll_to(cgmath::Vector2 {
+            x: 0.0,
+            y: index as f32 * self.row_height as f32,
+        });
+    }
+
+    /// The items in view
+    pub fn visible(&self) -> Range<usize> {
+        if self.row_height == 0 {
+            return 0..0;
+        }
+        let top = self.view.offset().y.round() as usize;
+        let height = self.row_height as usize;
+        let first = top / height;
+        let end = (top + self.view.size.y as usize).div_ceil(height);
+        first.min(self.len)..end.min(self.len)
+    }
+
+    /// The item under `pos` on the list as it was last drawn
+    pub fn index_at(&self, pos: cgmath::Point2<u16>) -> Option<usize> {
+        let rect = self.view.rect()?;
+        if !rect.contains_point(&pos.cast().unwrap()) || self.row_height == 0 {
+            return None;
+        }
+        let y = u32::from(pos.y) - rect.top + self.view.offset().y.round() as u32;
+        Some((y / self.row_height) as usize).filter(|index| *index < self.len)
+    }
+
+    /// Whether the list moved since it was last drawn
+    pub fn needs_redraw(&self) -> bool {
+        self.view.needs_redraw()
+    }
+
+    /// Follows a touch at `now`, scrolling the list or selecting a row. Returns
+    /// whether the list should be redrawn, the rows selected are left for
+    /// `take_events`.
+    pub fn handle_multitouch(&mut self, event: &MultitouchEvent, now: Instant) -> bool {
+        // Touching a coasting list only stops it
+        let coasting = self.view.next_momentum().is_some();
+        let redraw = self.view.handle_multitouch(event, now);
+        let finger = match event.finger() {
+            Some(finger) => finger,
+            None => return redraw,
+        };
+        let pos = finger.pos.cast().unwrap();
+        match event {
+            MultitouchEvent::Press { .. } => {
+                if self.press.is_none() && !coasting {
+                    self.press = self.index_at(finger.pos).map(|index| Press {
+                        finger: finger.tracking_id,
+                        start: pos,
+                        index,
+                        at: now,
+                        held: false,
+                    });
+                }
+            }
+            MultitouchEvent::Move { .. } => {
+                let scrolled = self.press.as_ref().is_some_and(|press| {
+                    press.finger == finger.tracking_id
+                        && (pos - press.start).magnitude() >= self.view.slop
+                });
+                if scrolled {
+                    self.press = None;
+                }
+            }
+            MultitouchEvent::Release { .. } => match self.press.take() {
+                Some(press) if press.finger == finger.tracking_id => {
+                    if !press.held {
+                        self.events.push(ListEvent::Tap(press.index));
+                    }
+                }
+                press => self.press = press,
+            },
+            MultitouchEvent::Unknown => {}
+        }
+        redraw
+    }
+
+    /// When `long_press` has to be called next, if a row is being held
+    pub fn next_long_press(&self) -> Option<Instant> {
+        self.press
+            .as_ref()
+            .filter(|press| !press.held)
+            .map(|press| press.at + self.hold)
+    }
+
+    /// Reports the row held since `hold` before `now` as long pressed. Returns
+    /// whether there was one.
+    pub fn long_press(&mut self, now: Instant) -> bool {
+        let hold = self.hold;
+        match self.press.as_mut() {
+            Some(press) if !press.held && now >= press.at + hold => {
+                press.held = true;
+                self.events.push(ListEvent::LongPress(press.index));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// When `momentum` has to be called next, if the list is coasting
+    pub fn next_momentum(&self) -> Option<Instant> {
+        self.view.next_momentum()
+    }
+
+    /// Coasts up to `now`. Returns whether the list should be redrawn.
+    pub fn momentum(&mut self, now: Instant) -> bool {
+        self.view.momentum(now)
+    }
+
+    /// The rows selected since this was last called
+    pub fn take_events(&mut self) -> Vec<ListEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// The rows in view for the list at `rect`, each filled in and placed on it.
+    /// Rows scrolled out of view are reused for those scrolled into it.
+    pub(crate) fn rows_in_view(
+        &mut self,
+        rect: mxcfb_rect,
+    ) -> impl Iterator<Item = &mut UIElementWrapper> + '_ {
+        let visible = self.visible();
+        for row in &mut self.rows {
+            if row.index.is_some_and(|index| !visible.contains(&index)) {
+                row.index = None;
+            }
+        }
+        for index in visible {
+            if self.rows.iter().any(|row| row.index == Some(index)) {
+                continue;
+            }
+            let at = match self.rows.iter().position(|row| row.index.is_none()) {
+                Some(at) => at,
+                None => {
+                    self.rows.push(Row {
+                        index: None,
+                        element: UIElementWrapper::default(),
+                        offset: cgmath::Vector2 { x: 0, y: 0 },
+                    });
+                    self.rows.len() - 1
+                }
+            };
+            let row = &mut self.rows[at];
+            row.element.position = cgmath::Point2::origin();
+            (self.provider)(index, &mut row.element);
+            row.offset = row.element.position.to_vec();
+            row.index = Some(index);
+        }
+
+        let height = self.row_height as i32;
+        let scrolled = self.view.offset().y.round() as i32;
+        let bottom = (rect.top + rect.height) as i32;
+        self.rows.iter_mut().filter_map(move |row| {
+            let index = row.index?;
+            let top = rect.top as i32 + index as i32 * height - scrolled;
+            let clip_top = top.max(rect.top as i32);
+            let element = &mut row.element;
+            element.position = cgmath::Point2 {
+                x: rect.left as i32,
+                y: top,
+            } + row.offset;
+            element.clip = Some(mxcfb_rect {
+                left: rect.left,
+                top: clip_top as u32,
+                width: rect.width,
+                height: ((top + height).min(bottom) - clip_top).max(0) as u32,
+            });
+            // The list was cleared already and is refreshed as a whole
+            element.refresh = UIConstraintRefresh::NoRefresh;
+            element.last_drawn_rect = None;
+            Some(element)
+        })
+    }
+
+    /// Remembers that the list was drawn at `rect`
+    pub(crate) fn drawn(&mut self, rect: mxcfb_rect) {
+        self.view.drawn(rect);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{moved, press, release, touch};
+    use crate::ui_extensions::element::UIElement;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_list_view() {
+        let rect = mxcfb_rect {
+            left: 100,
+            top: 100,
+            width: 400,
+            height: 300,
+        };
+        let filled = Arc::new(AtomicUsize::new(0));
+        let counter = filled.clone();
+        let mut list = ListView::new(rect.size(), 5000, 100, move |index, row| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            row.position = cgmath::Point2 { x: 10, y: 5 };
+            row.inner = UIElement::Region {
+                size: cgmath::Vector2 {
+                    x: index as u32 % 300,
+                    y: 90,
+                },
+                border_color: crate::framebuffer::common::color::BLACK,
+                border_px: 1,
+            };
+        });
+        assert_eq!(list.visible(), 0..3);
+        let positions: Vec<_> = list.rows_in_view(rect).map(|row| row.position).collect();
+        assert_eq!(positions.len(), 3);
+        assert!(positions.contains(&cgmath::Point2 { x: 110, y: 305 }));
+        list.drawn(rect);
+        assert_eq!(filled.load(Ordering::Relaxed), 3);
+
+        // Rows still in view are kept, those scrolled out of it are reused
+        list.view_mut()
+            .scroll_to(cgmath::Vector2 { x: 0.0, y: 150.0 });
+        assert_eq!(list.visible(), 1..5);
+        assert_eq!(list.rows_in_view(rect).count(), 4);
+        assert_eq!(filled.load(Ordering::Relaxed), 5);
+        list.scroll_to_index(4000);
+        assert_eq!(list.rows_in_view(rect).count(), 3);
+        assert_eq!(list.rows.len(), 4);
+        list.drawn(rect);
+        let clips: Vec<_> = list.rows_in_view(rect).map(|row| row.clip).collect();
+        assert!(clips.iter().all(|clip| clip.unwrap().height == 100));
+
+        // Taps select, holding selects once, and scrolling none
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        list.handle_multitouch(&touch(press, 1, 200, 150), at(0));
+        list.handle_multitouch(&touch(release, 1, 200, 150), at(50));
+        assert_eq!(list.next_long_press(), None);
+        list.handle_multitouch(&touch(press, 1, 200, 250), at(100));
+        assert_eq!(list.next_long_press(), Some(at(600)));
+        assert!(!list.long_press(at(500)));
+        assert!(list.long_press(at(600)));
+        list.handle_multitouch(&touch(release, 1, 200, 250), at(700));
+        list.handle_multitouch(&touch(press, 1, 200, 350), at(800));
+        list.handle_multitouch(&touch(moved, 1, 200, 250), at(900));
+        list.handle_multitouch(&touch(release, 1, 200, 250), at(2000));
+        assert_eq!(
+            list.take_events(),
+            vec![ListEvent::Tap(4000), ListEvent::LongPress(4001)]
+        );
+        assert_eq!(list.index_at(cgmath::Point2 { x: 200, y: 150 }), Some(4001));
+
+        // Fewer items keep the list within them
+        list.set_len(10);
+        assert_eq!(list.visible(), 7..10);
+        assert!(list.take_events().is_empty());
+    }
+}
@@ -75,6 +75,7 @@ pub fn lua_refresh(
     }
 }
 
+#[cfg(feature = "framebuffer-text-drawing")]
 pub fn lua_draw_text(
     y: hlua::AnyLuaValue,
     x: hlua::AnyLuaValue,
@@ -0,0 +1,445 @@
+//! Drop-down menus of a menu bar, and context menus opened by a long press.
+//!
+//! A `Menu` is a list of items to pick from, some of them disabled, split up by
+//! separators. `ApplicationContext::show_menu` shows one as an overlay next to some
+//! area, placed so that it stays on the display. A `MenuBar` element opens its menus
+//! below their titles when those are tapped, and `ApplicationContext::set_context_menu`
+//! opens one where an element is long pressed. While a menu is shown, touches pick
+//! its items, the arrow keys move between them and enter picks the one highlighted.
+//! A tap anywhere else or escape closes it.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::appctx::ApplicationContext;
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::FramebufferDraw;
+use crate::ui_extensions::overlay::OverlayId;
+
+/// Called when an item of a menu is picked
+pub type MenuFunction = fn(&mut ApplicationContext<'_>);
+
+/// Around the labels of items and titles
+const PADDING: u32 = 20;
+const BORDER: u32 = 3;
+/// How tall a separator is, its line is in the middle
+const SEPARATOR: u32 = 16;
+
+#[derive(Clone, Debug)]
+pub enum MenuItem {
+    Action {
+        label: String,
+        /// Disabled items are grayed out, and can't be picked or highlighted
+        enabled: bool,
+        on_select: Option<MenuFunction>,
+    },
+    /// A line between groups of items
+    Separator,
+}
+
+impl MenuItem {
+    fn label(&self) -> Option<&str> {
+        match self {
+            MenuItem::Action { label, .. } => Some(label),
+            MenuItem::Separator => None,
+        }
+    }
+
+    /// Whether it can be picked
+    pub fn is_enabled(&self) -> bool {
+        matches!(self, MenuItem::Action { enabled: true, .. })
+    }
+}
+
+/// Items to pick from, from top to bottom
+#[derive(Clone, Debug)]
+pub struct Menu {
+    pub items: Vec<MenuItem>,
+    /// Of the labels
+    pub scale: f32,
+    /// At least, it is wider when the labels need it
+    pub min_width: u32,
+}
+
+impl Default for Menu {
+    fn default() -> Menu {
+        Menu {
+            items: Vec::new(),
+            scale: 32.0,
+            min_width: 250,
+        }
+    }
+}
+
+impl Menu {
+    pub fn new() -> Menu {
+        Menu::default()
+    }
+
+    pub fn item(mut self, label: &str, on_select: MenuFunction) -> Menu {
+        self.items.push(MenuItem::Action {
+            label: label.to_owned(),
+            enabled: true,
+            on_select: Some(on_select),
+        });
+        self
+    }
+
+    /// Adds an item that is shown, but can't be picked
+    pub fn disabled(mut self, label: &str) -> Menu {
+        self.items.push(MenuItem::Action {
+            label: label.to_owned(),
+            enabled: false,
+            on_select: None,
+        });
+        self
+    }
+
+    pub fn separator(mut self) -> Menu {
+        self.items.push(MenuItem::Separator);
+        self
+    }
+
+    /// The enabled item after `from`, or before it when not `forward`, wrapping
+    /// around. Without `from`, the first or last enabled one.
+    pub fn next_enabled(&self, from: Option<usize>, forward: bool) -> Option<usize> {
+        let len = self.items.len();
+        let start = match (from, forward) {
+            (Some(from), true) => from + 1,
+            (Some(from), false) => from + len - 1,
+            (None, true) => 0,
+            (None, false) => len.checked_sub(1)?,
+        };
+        (0..len)
+            .map(|i| match forward {
+                true => (start + i) % len,
+                false => (start + len - i) % len,
+            })
+            .find(|&i| self.items[i].is_enabled())
+    }
+
+    /// Where the menu goes next to `anchor` on `screen`, and where its items go on it
+    pub(crate) fn layout(
+        &self,
+        fb: &mut Framebuffer,
+        anchor: mxcfb_rect,
+        screen: mxcfb_rect,
+    ) -> MenuLayout {
+        let row = self.row_height();
+        let widest = self
+            .items
+            .iter()
+            .filter_map(MenuItem::label)
+            .map(|label| measure(fb, label, self.scale))
+            .max()
+            .unwrap_or(0);
+        let height = self
+            .items
+            .iter()
+            .map(|item| match item {
+                MenuItem::Action { .. } => row,
+                MenuItem::Separator => SEPARATOR,
+            })
+            .sum::<u32>()
+            + 2 * BORDER;
+        let size = cgmath::Vector2 {
+            x: self.min_width.max(widest + 2 * PADDING),
+            y: height,
+        };
+        let rect = place(size, anchor, screen);
+        let mut top = rect.top + BORDER;
+        let rows = self
+            .items
+            .iter()
+            .map(|item| {
+                let height = match item {
+                    MenuItem::Action { .. } => row,
+                    MenuItem::Separator => SEPARATOR,
+                };
+                let row = mxcfb_rect {
+                    left: rect.left + BORDER,
+                    top,
+                    width: rect.width.saturating_sub(2 * BORDER),
+                    height,
+                };
+                top += height;
+                row
+            })
+            .collect();
+        MenuLayout { rect, rows }
+    }
+
+    fn row_height(&self) -> u32 {
+        (self.scale * 1.8) as u32
+    }
+
+    /// Draws the menu as laid out in `layout`, with the item `highlighted` inverted
+    pub(crate) fn draw(
+        &self,
+        fb: &mut Framebuffer,
+        layout: &MenuLayout,
+        highlighted: Option<usize>,
+    ) {
+        let rect = layout.rect;
+        let pos = rect.top_left().cast().unwrap();
+        fb.fill_rect(pos, rect.size(), color::WHITE);
+        fb.draw_rect(pos, rect.size(), BORDER, color::BLACK);
+        for (i, (item, row)) in self.items.iter().zip(&layout.rows).enumerate() {
+            match item {
+                MenuItem::Action { label, enabled, .. } => {
+                    let foreground = match (highlighted == Some(i), enabled) {
+                        (true, _) => {
+                            fb.fill_rect(row.top_left().cast().unwrap(), row.size(), color::BLACK);
+                            color::WHITE
+                        }
+                        (false, true) => color::BLACK,
+                        (false, false) => color::GRAY(0x80),
+                    };
+                    draw_label(fb, label, self.scale, *row, foreground);
+                }
+                MenuItem::Separator => fb.fill_rect(
+                    cgmath::Point2 {
+                        x: (row.left + PADDING / 2) as i32,
+                        y: (row.top + row.height / 2 - 1) as i32,
+                    },
+                    cgmath::Vector2 {
+                        x: row.width.saturating_sub(PADDING),
+                        y: 2,
+                    },
+                    color::GRAY(0x80),
+                ),
+            }
+        }
+    }
+}
+
+/// Where a menu and its items are on the display
+#[derive(Clone, Debug)]
+pub(crate) struct MenuLayout {
+    pub rect: mxcfb_rect,
+    pub rows: Vec<mxcfb_rect>,
+}
+
+impl MenuLayout {
+    /// The item at `pos`, whether or not it can be picked
+    pub fn item_at(&self, pos: cgmath::Point2<u32>) -> Option<usize> {
+        self.rows.iter().position(|row| row.contains_point(&pos))
+    }
+}
+
+/// Where a popup of `size` goes next to `anchor` without leaving `screen`: below it
+/// and starting at its left edge, or above it when there is no room below. It moves
+/// left when there is no room to the right, and up when there is none above either.
+pub fn place(size: cgmath::Vector2<u32>, anchor: mxcfb_rect, screen: mxcfb_rect) -> mxcfb_rect {
+    let width = size.x.min(screen.width);
+    let height = size.y.min(screen.height);
+    let (right, bottom) = (screen.left + screen.width, screen.top + screen.height);
+    let left = anchor.left.clamp(screen.left, right - width);
+    let below = anchor.top + anchor.height;
+    let top = if below + height <= bottom {
+        below.max(screen.top)
+    } else if anchor.top >= screen.top + height {
+        anchor.top - height
+    } else {
+        bottom - height
+    };
+    mxcfb_rect {
+        left,
+        top,
+        width,
+        height,
+    }
+}
+
+/// The titles of menus in a row, opening them below when tapped
+#[derive(Clone, Debug)]
+pub struct MenuBar {
+    pub menus: Vec<(String, Menu)>,
+    pub size: cgmath::Vector2<u32>,
+    /// Of the titles
+    pub scale: f32,
+    open: Option<usize>,
+}
+
+impl MenuBar {
+    pub fn new(size: cgmath::Vector2<u32>) -> MenuBar {
+        MenuBar {
+            menus: Vec::new(),
+            size,
+            scale: 32.0,
+            open: None,
+        }
+    }
+
+    pub fn menu(mut self, title: &str, menu: Menu) -> MenuBar {
+        self.menus.push((title.to_owned(), menu));
+        self
+    }
+
+    /// The menu shown below its title, if any
+    pub fn open_menu(&self) -> Option<usize> {
+        self.open
+    }
+
+    pub(crate) fn set_open(&mut self, open: Option<usize>) {
+        self.open = open;
+    }
+
+    /// Where the titles are when the bar is drawn at `rect`, from left to right
+    pub fn title_rects(&self, fb: &mut Framebuffer, rect: mxcfb_rect) -> Vec<mxcfb_rect> {
+        let mut left = rect.left;
+        self.menus
+            .iter()
+            .map(|(title, _)| {
+                let width = measure(fb, title, self.scale) + 2 * PADDING;
+                let title = mxcfb_rect {
+                    left,
+                    top: rect.top,
+                    width,
+                    height: rect.height,
+                };
+                left += width;
+                title
+            })
+            .collect()
+    }
+
+    pub fn draw(&self, fb: &mut Framebuffer, position: cgmath::Point2<i32>) -> mxcfb_rect {
+        let rect = mxcfb_rect::from(position.cast().unwrap(), self.size);
+        fb.fill_rect(position, self.size, color::WHITE);
+        let line = cgmath::Point2 {
+            x: position.x,
+            y: position.y + self.size.y as i32 - BORDER as i32,
+        };
+        fb.fill_rect(line, (self.size.x, BORDER).into(), color::BLACK);
+        for (i, title) in self.title_rects(fb, rect).into_iter().enumerate() {
+            let foreground = match self.open == Some(i) {
+                true => {
+                    fb.fill_rect(title.top_left().cast().unwrap(), title.size(), color::BLACK);
+                    color::WHITE
+                }
+                false => color::BLACK,
+            };
+            let label = mxcfb_rect {
+                left: title.left + PADDING,
+                ..title
+            };
+            draw_label(fb, &self.menus[i].0, self.scale, label, foreground);
+        }
+        rect
+    }
+}
+
+/// How wide `text` is in `size`
+fn measure(fb: &mut Framebuffer, text: &str, size: f32) -> u32 {
+    let origin = cgmath::Point2 { x: 0.0, y: size };
+    fb.draw_text(origin, text, size, color::BLACK, true).width
+}
+
+/// Draws a line of `text` at the left of `area`, centered vertically
+fn draw_label(fb: &mut Framebuffer, text: &str, size: f32, area: mxcfb_rect, c: color) {
+    let pos = cgmath::Point2 {
+        x: (area.left + PADDING) as f32,
+        // The baseline, so that the letters without descenders are centered
+        y: area.top as f32 + (area.height as f32 + size * 0.6) / 2.0,
+    };
+    fb.draw_text(pos, text, size, c, false);
+}
+
+/// A menu on the display, with the item highlighted by a finger or the arrow keys
+pub(crate) struct ShownMenu {
+    pub overlay: OverlayId,
+    pub menu: Menu,
+    pub layout: MenuLayout,
+    pub highlighted: Rc<Cell<Option<usize>>>,
+    /// The `MenuBar` element it was opened from and its index there
+    pub bar: Option<(String, usize)>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::FramebufferIO;
+    use crate::test_util::rect;
+
+    #[test]
+    fn test_place() {
+        let screen = rect(0, 0, 1000, 1000);
+        let size = cgmath::Vector2 { x: 300, y: 400 };
+        // Below and to the right, when there's room
+        assert_eq!(
+            place(size, rect(100, 0, 80, 50), screen),
+            rect(100, 50, 300, 400)
+        );
+        // Moved left at the right edge, and above near the bottom
+        assert_eq!(
+            place(size, rect(900, 800, 80, 50), screen),
+            rect(700, 400, 300, 400)
+        );
+        // Up against the bottom with no room on either side
+        assert_eq!(
+            place(size, rect(0, 300, 0, 0), rect(0, 0, 1000, 600)),
+            rect(0, 200, 300, 400)
+        );
+        // Never larger than the screen
+        assert_eq!(
+            place((2000, 50).into(), rect(10, 10, 0, 0), screen),
+            rect(0, 10, 1000, 50)
+        );
+    }
+
+    #[test]
+    fn test_menu() {
+        fn nothing(_: &mut ApplicationContext<'_>) {}
+        let menu = Menu::new()
+            .disabled("Undo")
+            .item("Cut", nothing)
+            .separator()
+            .item("Paste", nothing);
+        assert_eq!(menu.next_enabled(None, true), Some(1));
+        assert_eq!(menu.next_enabled(Some(1), true), Some(3));
+        assert_eq!(menu.next_enabled(Some(3), true), Some(1));
+        assert_eq!(menu.next_enabled(None, false), Some(3));
+        assert_eq!(menu.next_enabled(Some(1), false), Some(3));
+        assert_eq!(Menu::new().next_enabled(None, true), None);
+
+        let mut fb = Framebuffer::headless(500, 500);
+        let layout = menu.layout(&mut fb, rect(400, 450, 0, 0), rect(0, 0, 500, 500));
+        let row = menu.row_height();
+        assert_eq!(layout.rect.height, 3 * row + SEPARATOR + 2 * BORDER);
+        // Moved to stay on the screen
+        assert_eq!(layout.rect.left + layout.rect.width, 500);
+        assert_eq!(layout.rect.top + layout.rect.height, 450);
+        let separator = layout.rows[2];
+        assert_eq!(separator.height, SEPARATOR);
+        let below = cgmath::Point2 {
+            x: separator.left + 10,
+            y: separator.top + SEPARATOR + 5,
+        };
+        assert_eq!(layout.item_at(below), Some(3));
+
+        menu.draw(&mut fb, &layout, Some(1));
+        let highlighted = layout.rows[1].top_left() + cgmath::Vector2 { x: 2, y: 2 };
+        assert_eq!(
+            fb.read_pixel(highlighted).as_native(),
+            color::BLACK.as_native()
+        );
+        let other = layout.rows[3].top_left() + cgmath::Vector2 { x: 2, y: 2 };
+        assert_eq!(fb.read_pixel(other).as_native(), color::WHITE.as_native());
+    }
+
+    #[test]
+    fn test_menu_bar() {
+        let mut fb = Framebuffer::headless(500, 100);
+        let bar = MenuBar::new((500, 60).into())
+            .menu("File", Menu::new())
+            .menu("Edit", Menu::new());
+        let rects = bar.title_rects(&mut fb, rect(0, 0, 500, 60));
+        assert_eq!(rects[0].left, 0);
+        assert_eq!(rects[1].left, rects[0].width);
+        assert!(rects.iter().all(|title| title.height == 60));
+        assert_eq!(bar.draw(&mut fb, (0, 0).into()), rect(0, 0, 500, 60));
+    }
+}
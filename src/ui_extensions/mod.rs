@@ -7,3 +7,43 @@ pub mod luaext;
 /// `ApplicationContext` and `ui_extensions` and choose to interact with the `framebuffer`
 /// and `input` devices directly.
 pub mod element;
+
+/// Popups, menus and toasts that restore what they covered when dismissed
+pub mod overlay;
+
+/// A paged document viewer with pinch zoom and page caching
+pub mod viewer;
+
+/// A keyboard drawn on the display, typing like a hardware one
+pub mod keyboard;
+
+/// A single line text field, typed into with any keyboard
+#[cfg(feature = "framebuffer-text-drawing")]
+pub mod text_input;
+
+/// Rows and columns of elements, laid out like a flexbox
+pub mod layout;
+
+/// A scrollable area showing other elements, with kinetic scrolling
+pub mod scroll_view;
+
+/// A scrollable list of any length, making up only the rows in view
+pub mod list_view;
+
+/// Sliders and steppers for picking a number within a range
+pub mod slider;
+
+/// Modal dialogs and toasts shown over the app
+#[cfg(feature = "framebuffer-text-drawing")]
+pub mod dialog;
+
+/// A menu bar with drop-down menus, and context menus
+#[cfg(feature = "framebuffer-text-drawing")]
+pub mod menu;
+
+/// Screens of an app, pushed, popped and switched between as tabs
+pub mod navigator;
+
+/// A drawing area keeping what is drawn on it as strokes, with undo and selection
+#[cfg(feature = "stroke")]
+pub mod canvas;
@@ -0,0 +1,86 @@
+//! Screens of an app, shown one at a time.
+//!
+//! Each screen owns its UI elements, active regions, `InputRouter` regions, focus,
+//! layout and context menus. `ApplicationContext::push_screen` shows a new screen over
+//! the one shown, which is kept as it is until `ApplicationContext::pop_screen` goes
+//! back to it. `ApplicationContext::switch_tab` swaps in another stack of screens,
+//! keeping the one shown to switch back to. Either way, the screen shown is drawn
+//! afresh and refreshed as a whole, without anything left over from the one before.
+//!
+//! ```no_run
+//! # use libremarkable::appctx::ApplicationContext;
+//! fn library(app: &mut ApplicationContext<'_>) {
+//!     // app.add_element(...) for the screen
+//! }
+//! fn settings(app: &mut ApplicationContext<'_>) {}
+//!
+//! let mut app = ApplicationContext::default();
+//! app.switch_tab("library", library);
+//! app.push_screen("settings", settings);
+//! assert_eq!(app.navigator().current(), Some("settings"));
+//! app.pop_screen();
+//! ```
+
+use std::collections::HashMap;
+
+use aabb_quadtree::QuadTree;
+
+use crate::appctx::ApplicationContext;
+use crate::router::InputRouter;
+use crate::ui_extensions::element::{ActiveRegionHandler, UIElementHandle};
+use crate::ui_extensions::layout::LayoutNode;
+#[cfg(feature = "framebuffer-text-drawing")]
+use crate::ui_extensions::menu::Menu;
+
+/// Adds the elements and regions of a screen as it is shown for the first time
+pub type ScreenFunction = fn(&mut ApplicationContext<'_>);
+
+/// What a screen is made of, kept aside while another one is shown
+pub(crate) struct Screen {
+    /// `None` for the one shown before any was pushed or switched to
+    pub name: Option<String>,
+    pub ui_elements: HashMap<String, UIElementHandle>,
+    pub active_regions: QuadTree<ActiveRegionHandler>,
+    pub router: InputRouter,
+    pub focus: Option<String>,
+    pub layout: Option<LayoutNode>,
+    #[cfg(feature = "framebuffer-text-drawing")]
+    pub context_menus: HashMap<String, Menu>,
+}
+
+/// Where an app is among its screens and tabs
+#[derive(Default)]
+pub struct Navigator {
+    pub(crate) current: Option<String>,
+    /// The screens of the tab shown below the current one, from the bottom up
+    pub(crate) below: Vec<Screen>,
+    pub(crate) tab: Option<String>,
+    /// The screens of the other tabs, from the bottom up
+    pub(crate) tabs: HashMap<String, Vec<Screen>>,
+}
+
+impl Navigator {
+    /// The name of the screen shown
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// The names of the screens `pop_screen` goes back to, from the bottom up
+    pub fn below(&self) -> impl Iterator<Item = Option<&str>> {
+        self.below.iter().map(|screen| screen.name.as_deref())
+    }
+
+    pub fn can_pop(&self) -> bool {
+        !self.below.is_empty()
+    }
+
+    /// The name of the tab shown
+    pub fn tab(&self) -> Option<&str> {
+        self.tab.as_deref()
+    }
+
+    /// Whether the tab `name` was shown before, and is kept to switch back to
+    pub fn has_tab(&self, name: &str) -> bool {
+        self.tab.as_deref() == Some(name) || self.tabs.contains_key(name)
+    }
+}
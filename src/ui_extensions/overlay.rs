@@ -0,0 +1,303 @@
+//! Popups, menus and toasts drawn over an app.
+//!
+//! An `OverlayManager` snapshots what an overlay covers before drawing it, and puts
+//! that back and refreshes it when the overlay is dismissed, so the app underneath
+//! doesn't need to redraw. Overlays stack in the order they are shown. Dismissing one
+//! below others redraws those above it, so they stay intact.
+
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::snapshot::RegionSnapshot;
+use crate::framebuffer::PartialRefreshMode;
+
+/// Identifies an overlay shown by an `OverlayManager`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OverlayId(u32);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverlayKind {
+    /// Takes all input until dismissed, see `OverlayManager::blocks`
+    Modal,
+    /// Dismissed by a tap outside of it
+    Menu,
+    /// Dismissed by `OverlayManager::expire` once shown for `duration`
+    Toast { duration: Duration },
+}
+
+type DrawOverlay = Box<dyn Fn(&mut Framebuffer, mxcfb_rect)>;
+
+struct Overlay {
+    id: OverlayId,
+    kind: OverlayKind,
+    rect: mxcfb_rect,
+    shown: Instant,
+    background: RegionSnapshot,
+    draw: DrawOverlay,
+}
+
+impl Overlay {
+    /// Draws it, clipped to its rect
+    fn paint(&self, fb: &mut Framebuffer) {
+        fb.push_clip(self.rect);
+        (self.draw)(fb, self.rect);
+        fb.pop_clip();
+    }
+
+    fn hide(&self, fb: &mut Framebuffer) {
+        if let Err(err) = fb.restore_snapshot(&self.background) {
+            warn!("Failed to restore the background of an overlay: {}", err);
+        }
+    }
+}
+
+/// The overlays shown, from the bottom up
+#[derive(Default)]
+pub struct OverlayManager {
+    overlays: Vec<Overlay>,
+    next_id: u32,
+}
+
+impl OverlayManager {
+    pub fn new() -> OverlayManager {
+        OverlayManager::default()
+    }
+
+    /// Shows an overlay over `rect` drawn by `draw`, which gets the framebuffer
+    /// clipped to it and may be called again to redraw it
+    pub fn show(
+        &mut self,
+        fb: &mut Framebuffer,
+        kind: OverlayKind,
+        rect: mxcfb_rect,
+        draw: impl Fn(&mut Framebuffer, mxcfb_rect) + 'static,
+    ) -> OverlayId {
+        let id = OverlayId(self.next_id);
+        self.next_id += 1;
+        let overlay = Overlay {
+            id,
+            kind,
+            rect,
+            shown: Instant::now(),
+            background: fb.snapshot_region(rect),
+            draw: Box::new(draw),
+        };
+        overlay.paint(fb);
+        fb.refresh(&rect, PartialRefreshMode::Async);
+        self.overlays.push(overlay);
+        id
+    }
+
+    /// Removes the overlay `id`, restoring what it covered. Returns whether it was
+    /// shown.
+    pub fn dismiss(&mut self, fb: &mut Framebuffer, id: OverlayId) -> bool {
+        let index = match self.overlays.iter().position(|o| o.id == id) {
+            Some(index) => index,
+            None => return false,
+        };
+        // The ones above were drawn over its contents and have to go first
+        for overlay in self.overlays[index..].iter().rev() {
+            overlay.hide(fb);
+        }
+        let dismissed = self.overlays.remove(index);
+        let mut changed = dismissed.rect;
+        for overlay in &mut self.overlays[index..] {
+            overlay.background = fb.snapshot_region(overlay.rect);
+            overlay.paint(fb);
+            changed = changed.merge_rect(&overlay.rect);
+        }
+        fb.refresh(&changed, PartialRefreshMode::Async);
+        true
+    }
+
+    /// Draws the overlay `id` again, e.g. after what its `draw` shows changed, and the
+    /// ones above it over that. Returns whether it was shown.
+    pub fn redraw(&mut self, fb: &mut Framebuffer, id: OverlayId) -> bool {
+        let index = match self.overlays.iter().position(|o| o.id == id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let mut changed = self.overlays[index].rect;
+        for overlay in &self.overlays[index..] {
+            overlay.paint(fb);
+            changed = changed.merge_rect(&overlay.rect);
+        }
+        fb.refresh(&changed, PartialRefreshMode::Async);
+        true
+    }
+
+    /// Draws all the overlays again over what was drawn under them since, e.g. a whole
+    /// new screen, which is what they cover from now on. Doesn't refresh.
+    pub fn repaint(&mut self, fb: &mut Framebuffer) {
+        for overlay in &mut self.overlays {
+            overlay.background = fb.snapshot_region(overlay.rect);
+            overlay.paint(fb);
+        }
+    }
+
+    /// Dismisses the topmost overlay, if any
+    pub fn dismiss_top(&mut self, fb: &mut Framebuffer) -> Option<OverlayId> {
+        let id = self.overlays.last()?.id;
+        self.dismiss(fb, id);
+        Some(id)
+    }
+
+    /// Dismisses the toasts shown for their duration at `now`. Returns them.
+    pub fn expire(&mut self, fb: &mut Framebuffer, now: Instant) -> Vec<OverlayId> {
+        let expired: Vec<_> = self
+            .overlays
+            .iter()
+            .filter(|o| match o.kind {
+                OverlayKind::Toast { duration } => now.duration_since(o.shown) >= duration,
+                _ => false,
+            })
+            .map(|o| o.id)
+            .collect();
+        for id in &expired {
+            self.dismiss(fb, *id);
+        }
+        expired
+    }
+
+    /// When the first of the toasts shown is due to be dismissed by `expire`
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.overlays
+            .iter()
+            .filter_map(|o| match o.kind {
+                OverlayKind::Toast { duration } => Some(o.shown + duration),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// The topmost overlay at `pos`. A tap anywhere else dismisses the menus.
+    pub fn handle_tap(
+        &mut self,
+        fb: &mut Framebuffer,
+        pos: cgmath::Point2<u32>,
+    ) -> Option<OverlayId> {
+        if let Some(id) = self.at(pos) {
+            return Some(id);
+        }
+        let menus: Vec<_> = self
+            .overlays
+            .iter()
+            .filter(|o| o.kind == OverlayKind::Menu)
+            .map(|o| o.id)
+            .collect();
+        for id in menus {
+            self.dismiss(fb, id);
+        }
+        None
+    }
+
+    /// The topmost overlay at `pos`
+    pub fn at(&self, pos: cgmath::Point2<u32>) -> Option<OverlayId> {
+        self.overlays
+            .iter()
+            .rev()
+            .find(|o| o.rect.contains_point(&pos))
+            .map(|o| o.id)
+    }
+
+    /// Whether input at `pos` is kept from the app by a modal overlay
+    pub fn blocks(&self, pos: cgmath::Point2<u32>) -> bool {
+        self.overlays.iter().any(|o| o.kind == OverlayKind::Modal) && self.at(pos).is_none()
+    }
+
+    pub fn is_shown(&self, id: OverlayId) -> bool {
+        self.overlays.iter().any(|o| o.id == id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.overlays.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::cgmath::{Point2, Vector2};
+    use crate::framebuffer::common::color;
+    use crate::framebuffer::{FramebufferDraw, FramebufferIO};
+    use crate::test_util::rect;
+
+    fn filled(c: color) -> impl Fn(&mut Framebuffer, mxcfb_rect) {
+        // Larger than the overlay, which clips it
+        move |fb, _| {
+            fb.fill_rect(Point2 { x: 0, y: 0 }, Vector2 { x: 100, y: 100 }, c);
+        }
+    }
+
+    #[test]
+    fn test_overlays() {
+        let mut fb = Framebuffer::headless(100, 100);
+        fb.fill_rect(
+            Point2 { x: 0, y: 0 },
+            Vector2 { x: 100, y: 100 },
+            color::GRAY(0x80),
+        );
+        let pixel = |fb: &Framebuffer, x, y| fb.read_pixel(Point2 { x, y }).to_rgb8();
+        let (gray, black) = (color::GRAY(0x80).to_rgb8(), color::BLACK.to_rgb8());
+
+        let mut overlays = OverlayManager::new();
+        let menu = overlays.show(
+            &mut fb,
+            OverlayKind::Menu,
+            rect(10, 10, 30, 30),
+            filled(color::BLACK),
+        );
+        let toast = overlays.show(
+            &mut fb,
+            OverlayKind::Toast {
+                duration: Duration::from_secs(2),
+            },
+            rect(30, 30, 30, 10),
+            filled(color::WHITE),
+        );
+        assert_eq!(pixel(&fb, 15, 15), black);
+        assert_eq!(pixel(&fb, 35, 35), color::WHITE.to_rgb8());
+        assert_eq!(pixel(&fb, 50, 50), gray);
+        assert_eq!(overlays.at(Point2 { x: 35, y: 35 }), Some(toast));
+        assert_eq!(overlays.at(Point2 { x: 15, y: 15 }), Some(menu));
+        assert!(!overlays.blocks(Point2 { x: 90, y: 90 }));
+
+        // The menu goes from under the toast, which stays
+        assert_eq!(overlays.handle_tap(&mut fb, Point2 { x: 90, y: 90 }), None);
+        assert!(!overlays.is_shown(menu));
+        assert_eq!(pixel(&fb, 15, 15), gray);
+        assert_eq!(pixel(&fb, 35, 35), color::WHITE.to_rgb8());
+        let refreshes = fb.take_refreshes();
+        assert_eq!(
+            refreshes.last().unwrap().update_region,
+            rect(10, 10, 50, 30)
+        );
+
+        assert!(overlays.expire(&mut fb, Instant::now()).is_empty());
+        assert!(overlays.next_expiry().unwrap() > Instant::now());
+        let later = Instant::now() + Duration::from_secs(3);
+        assert_eq!(overlays.expire(&mut fb, later), [toast]);
+        assert!(overlays.is_empty());
+        assert_eq!(pixel(&fb, 35, 35), gray);
+
+        let modal = overlays.show(
+            &mut fb,
+            OverlayKind::Modal,
+            rect(0, 0, 50, 50),
+            filled(color::BLACK),
+        );
+        assert!(overlays.blocks(Point2 { x: 90, y: 90 }));
+        assert_eq!(overlays.handle_tap(&mut fb, Point2 { x: 90, y: 90 }), None);
+        assert!(overlays.is_shown(modal));
+        assert!(overlays.redraw(&mut fb, modal));
+        assert_eq!(pixel(&fb, 0, 0), black);
+        assert_eq!(overlays.dismiss_top(&mut fb), Some(modal));
+        assert!(!overlays.dismiss(&mut fb, modal));
+        assert!(!overlays.redraw(&mut fb, modal));
+        assert_eq!(pixel(&fb, 0, 0), gray);
+    }
+}
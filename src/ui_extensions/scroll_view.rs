@@ -0,0 +1,365 @@
+//! A scrollable container for content larger than the screen.
+//!
+//! A `ScrollView` is the state of a `UIElement::ScrollView`: it shows part of a larger
+//! content area, on which other elements are placed by name. Dragging a finger on it
+//! scrolls the content, flinging it keeps scrolling with decaying momentum. Since the
+//! display can't keep up with every move, the content is redrawn at most once per
+//! `frame` while it follows the finger, and only the area of the view is refreshed.
+//! In `ScrollMode::Paged` it instead jumps by a whole page once the finger lifts,
+//! which is kinder to e-ink.
+//!
+//! The event loop passes touch input to every scroll view and redraws those that
+//! scrolled, see `ApplicationContext::add_scroll_child`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::cgmath::InnerSpace;
+use crate::framebuffer::common::mxcfb_rect;
+use crate::input::{kinetic, MultitouchEvent};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum ScrollMode {
+    /// Following the finger, coasting after a fling
+    #[default]
+    Continuous,
+    /// A page at a time, once the finger lifts
+    Paged,
+}
+
+#[derive(Clone, Debug)]
+struct Drag {
+    finger: i32,
+    start: cgmath::Point2<f32>,
+    last: cgmath::Point2<f32>,
+    /// Set once the finger moved further than `slop`
+    scrolling: bool,
+    samples: VecDeque<(Instant, cgmath::Point2<f32>)>,
+}
+
+impl Drag {
+    fn sample(&mut self, now: Instant, pos: cgmath::Point2<f32>) {
+        kinetic::sample(&mut self.samples, now, pos);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScrollView {
+    /// Of the visible part
+    pub size: cgmath::Vector2<u32>,
+    /// Of everything that can be scrolled into view
+    pub content_size: cgmath::Vector2<u32>,
+    pub mode: ScrollMode,
+    /// How far in pixels a finger has to move before the view scrolls
+    pub slop: f32,
+    /// How quickly momentum fades, as the fraction of the velocity lost per second
+    /// on a log scale
+    pub friction: f32,
+    /// Momentum ends below this speed, and a faster fling turns the page, in pixels
+    /// per second
+    pub min_velocity: f32,
+    /// Between redraws while scrolling
+    pub frame: Duration,
+    /// The elements shown and where they are on the content
+    children: Vec<(String, cgmath::Point2<i32>)>,
+    offset: cgmath::Vector2<f32>,
+    /// The offset last drawn
+    shown: cgmath::Vector2<f32>,
+    last_frame: Option<Instant>,
+    /// Where the view was last drawn
+    rect: Option<mxcfb_rect>,
+    drag: Option<Drag>,
+    momentum: Option<(cgmath::Vector2<f32>, Instant)>,
+}
+
+impl ScrollView {
+    pub fn new(size: cgmath::Vector2<u32>, content_size: cgmath::Vector2<u32>) -> ScrollView {
+        ScrollView {
+            size,
+            content_size,
+            mode: ScrollMode::default(),
+            slop: 20.0,
+            friction: kinetic::FRICTION,
+            min_velocity: kinetic::MIN_VELOCITY,
+            frame: Duration::from_millis(100),
+            children: Vec::new(),
+            offset: cgmath::Vector2 { x: 0.0, y: 0.0 },
+            shown: cgmath::Vector2 { x: 0.0, y: 0.0 },
+            last_frame: None,
+            rect: None,
+            drag: None,
+            momentum: None,
+        }
+    }
+
+    /// Shows the element `name` at `position` on the content, instead of wherever it
+    /// is on its own
+    pub fn add_child(&mut self, name: &str, position: cgmath::Point2<i32>) {
+        self.remove_child(name);
+        self.children.push((name.to_owned(), position));
+    }
+
+    pub fn remove_child(&mut self, name: &str) -> bool {
+        let len = self.children.len();
+        self.children.retain(|(child, _)| child != name);
+        self.children.len() != len
+    }
+
+    /// The elements shown and where they are on the content
+    pub fn children(&self) -> &[(String, cgmath::Point2<i32>)] {
+        &self.children
+    }
+
+    /// How far the content is scrolled
+    pub fn offset(&self) -> cgmath::Vector2<f32> {
+        self.offset
+    }
+
+    fn max_offset(&self) -> cgmath::Vector2<f32> {
+        cgmath::Vector2 {
+            x: self.content_size.x.saturating_sub(self.size.x) as f32,
+            y: self.content_size.y.saturating_sub(self.size.y) as f32,
+        }
+    }
+
+    /// Scrolls to `offset`, as far as the content goes
+    pub fn scroll_to(&mut self, offset: cgmath::Vector2<f32>) {
+        let max = self.max_offset();
+        self.offset = cgmath::Vector2 {
+            x: offset.x.clamp(0.0, max.x),
+            y: offset.y.clamp(0.0, max.y),
+        };
+    }
+
+    /// Whether the content moved since it was last drawn
+    pub fn needs_redraw(&self) -> bool {
+        self.offset != self.shown
+    }
+
+    /// Whether to redraw at `now`, at most once per `frame` unless `now` is the end
+    /// of the scroll
+    fn frame_due(&mut self, now: Instant, last: bool) -> bool {
+        let due = self.needs_redraw()
+            && (last || self.last_frame.is_none_or(|at| now >= at + self.frame));
+        if due {
+            self.last_frame = Some(now);
+        }
+        due
+    }
+
+    /// Follows a touch at `now`, scrolling if it drags the content of the view as it
+    /// was last drawn. Returns whether the view should be redrawn.
+    pub fn handle_multitouch(&mut self, event: &MultitouchEvent, now: Instant) -> bool {
+        let finger = match event.finger() {
+            Some(finger) => finger,
+            None => return false,
+        };
+        let pos = finger.pos.cast().unwrap();
+        match event {
+            MultitouchEvent::Press { .. } => {
+                let inside = self
+                    .rect
+                    .is_some_and(|rect| rect.contains_point(&finger.pos.cast().unwrap()));
+                if inside && self.drag.is_none() {
+                    // Touching stops the momentum
+                    self.momentum = None;
+                    self.drag = Some(Drag {
+                        finger: finger.tracking_id,
+                        start: pos,
+                        last: pos,
+                        scrolling: false,
+                        samples: VecDeque::from([(now, pos)]),
+                    });
+                }
+                false
+            }
+            MultitouchEvent::Move { .. } => {
+                let slop = self.slop;
+                let drag = match self.drag.as_mut() {
+                    Some(drag) if drag.finger == finger.tracking_id => drag,
+                    _ => return false,
+                };
+                drag.scrolling |= (pos - drag.start).magnitude() >= slop;
+                drag.sample(now, pos);
+                if !drag.scrolling || self.mode == ScrollMode::Paged {
+                    return false;
+                }
+                let delta = pos - drag.last;
+                drag.last = pos;
+                self.scroll_to(self.offset - delta);
+                self.frame_due(now, false)
+            }
+            MultitouchEvent::Release { .. } => {
+                let mut drag = match self.drag.take() {
+                    Some(drag) if drag.finger == finger.tracking_id => drag,
+                    drag => {
+                        self.drag = drag;
+                        return false;
+                    }
+                };
+                if !drag.scrolling {
+                    return false;
+                }
+                // Resting before lifting is no fling
+                drag.sample(now, pos);
+                let velocity = kinetic::velocity(&drag.samples);
+                match self.mode {
+                    ScrollMode::Continuous => {
+                        if velocity.magnitude() >= self.min_velocity {
+                            self.momentum = Some((velocity, now));
+                        }
+                    }
+                    ScrollMode::Paged => self.turn_page(pos - drag.start, velocity),
+                }
+                self.frame_due(now, self.momentum.is_none())
+            }
+            MultitouchEvent::Unknown => false,
+        }
+    }
+
+    /// Scrolls by a page against the drag `moved`, along its longer axis
+    fn turn_page(&mut self, moved: cgmath::Vector2<f32>, velocity: cgmath::Vector2<f32>) {
+        let (moved, speed, page, offset, vertical) = match moved.y.abs() >= moved.x.abs() {
+            true => (moved.y, velocity.y, self.size.y, self.offset.y, true),
+            false => (moved.x, velocity.x, self.size.x, self.offset.x, false),
+        };
+        if page == 0 || moved.abs() < self.slop * 2.0 && speed.abs() < self.min_velocity {
+            return;
+        }
+        let page = page as f32;
+        let current = (offset / page).round();
+        let target = match moved < 0.0 {
+            true => (current + 1.0) * page,
+            false => (current - 1.0) * page,
+        };
+        self.scroll_to(match vertical {
+            true => cgmath::Vector2 {
+                x: self.offset.x,
+                y: target,
+            },
+            false => cgmath::Vector2 {
+                x: target,
+                y: self.offset.y,
+            },
+        });
+    }
+
+    /// When `momentum` has to be called next, if the content is coasting
+    pub fn next_momentum(&self) -> Option<Instant> {
+        self.momentum.map(|(_, last)| last + self.frame)
+    }
+
+    /// Coasts up to `now`. Returns whether the view should be redrawn.
+    pub fn momentum(&mut self, now: Instant) -> bool {
+        let (velocity, last) = match self.momentum {
+            Some(momentum) => momentum,
+            None => return false,
+        };
+        let dt = now.saturating_duration_since(last).as_secs_f32();
+        let (delta, velocity) = kinetic::coast(velocity, self.friction, dt);
+        let before = self.offset;
+        self.scroll_to(self.offset - delta);
+        // Stopping at the end of the content, too
+        let done = velocity.magnitude() < self.min_velocity || self.offset == before;
+        self.momentum = match done {
+            true => None,
+            false => Some((velocity, now)),
+        };
+        self.frame_due(now, done)
+    }
+
+    /// Where the child at `position` on the content is on the display, for the view
+    /// at `rect`
+    pub(crate) fn child_position(
+        &self,
+        rect: mxcfb_rect,
+        position: cgmath::Point2<i32>,
+    ) -> cgmath::Point2<i32> {
+        cgmath::Point2 {
+            x: rect.left as i32 + position.x - self.offset.x.round() as i32,
+            y: rect.top as i32 + position.y - self.offset.y.round() as i32,
+        }
+    }
+
+    /// Where the view was last drawn
+    pub(crate) fn rect(&self) -> Option<mxcfb_rect> {
+        self.rect
+    }
+
+    /// Remembers that the view was drawn at `rect`
+    pub(crate) fn drawn(&mut self, rect: mxcfb_rect) {
+        self.rect = Some(rect);
+        self.shown = self.offset;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{moved, press, release, touch};
+
+    #[test]
+    fn test_scroll_view() {
+        let rect = mxcfb_rect {
+            left: 100,
+            top: 100,
+            width: 400,
+            height: 300,
+        };
+        let mut view = ScrollView::new(rect.size(), cgmath::Vector2 { x: 400, y: 3000 });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Not drawn yet, so nothing to drag
+        view.handle_multitouch(&touch(press, 1, 200, 300), start);
+        assert!(!view.handle_multitouch(&touch(moved, 1, 200, 200), at(10)));
+        view.handle_multitouch(&touch(release, 1, 200, 200), at(20));
+        view.drawn(rect);
+
+        // Small moves are taps, the rest is redrawn at most once per frame
+        assert!(!view.handle_multitouch(&touch(press, 1, 200, 300), at(0)));
+        assert!(!view.handle_multitouch(&touch(moved, 1, 200, 290), at(10)));
+        assert!(view.handle_multitouch(&touch(moved, 1, 200, 250), at(20)));
+        assert_eq!(view.offset(), cgmath::Vector2 { x: 0.0, y: 50.0 });
+        view.drawn(rect);
+        assert!(!view.handle_multitouch(&touch(moved, 1, 200, 240), at(30)));
+        // A slow release shows where the finger stopped
+        assert!(view.handle_multitouch(&touch(release, 1, 200, 240), at(500)));
+        assert_eq!(view.next_momentum(), None);
+        view.drawn(rect);
+        assert_eq!(
+            view.child_position(rect, cgmath::Point2 { x: 10, y: 100 }),
+            cgmath::Point2 { x: 110, y: 140 }
+        );
+
+        // A fling coasts until it slows down
+        view.handle_multitouch(&touch(press, 1, 200, 350), at(1000));
+        view.handle_multitouch(&touch(moved, 1, 200, 300), at(1020));
+        view.handle_multitouch(&touch(moved, 1, 200, 200), at(1040));
+        assert!(!view.handle_multitouch(&touch(release, 1, 200, 200), at(1050)));
+        let mut frames = 0;
+        while let Some(next) = view.next_momentum() {
+            if view.momentum(next) {
+                view.drawn(rect);
+                frames += 1;
+            }
+        }
+        assert!(frames > 1);
+        assert!(view.offset().y > 500.0, "{:?}", view.offset());
+        assert!(!view.needs_redraw());
+
+        // Pages jump once the finger lifts, and not past the end
+        view.mode = ScrollMode::Paged;
+        view.scroll_to(cgmath::Vector2 { x: 0.0, y: 2400.0 });
+        view.handle_multitouch(&touch(press, 1, 200, 350), at(2000));
+        assert!(!view.handle_multitouch(&touch(moved, 1, 200, 250), at(2100)));
+        assert!(view.handle_multitouch(&touch(release, 1, 200, 250), at(2200)));
+        assert_eq!(view.offset().y, 2700.0);
+        view.drawn(rect);
+        view.handle_multitouch(&touch(press, 1, 200, 150), at(3000));
+        view.handle_multitouch(&touch(moved, 1, 200, 250), at(3100));
+        view.handle_multitouch(&touch(release, 1, 200, 250), at(3200));
+        assert_eq!(view.offset().y, 2400.0);
+    }
+}
@@ -0,0 +1,563 @@
+//! Picking a number within a range, like a brush size or a setting.
+//!
+//! A `Slider` is dragged along its track with a finger or the pen, a `Stepper` is
+//! tapped to count up or down. Both keep to their `min`, `max` and `step`, and a
+//! slider also snaps to the values in `snap_points` when dragged close to them. As
+//! the display can't keep up with every move of a drag, a slider is redrawn at most
+//! once per `frame` while it follows it, and once more when it is let go.
+
+use std::time::{Duration, Instant};
+
+use crate::appctx::ApplicationContext;
+use crate::framebuffer::cgmath;
+use crate::framebuffer::cgmath::InnerSpace;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::FramebufferDraw;
+use crate::input::{MultitouchEvent, WacomEvent, WacomPen};
+use crate::ui_extensions::element::UIElementHandle;
+
+/// Called with the element and its value, after it changed
+pub type ValueFunction = fn(&mut ApplicationContext<'_>, UIElementHandle, f32);
+
+/// How thick the lines of sliders and steppers are
+const LINE: u32 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Orientation {
+    /// With `min` on the left
+    #[default]
+    Horizontal,
+    /// With `min` at the bottom
+    Vertical,
+}
+
+/// Keeps `value` within `min` and `max`, on a multiple of `step` from `min`
+fn constrain(value: f32, min: f32, max: f32, step: f32) -> f32 {
+    let value = match step > 0.0 {
+        true => min + ((value - min) / step).round() * step,
+        false => value,
+    };
+    value.clamp(min, max.max(min))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Grab {
+    Finger(i32),
+    Pen,
+}
+
+#[derive(Clone, Debug)]
+pub struct Slider {
+    pub size: cgmath::Vector2<u32>,
+    pub orientation: Orientation,
+    pub min: f32,
+    pub max: f32,
+    /// Between the values it can take, any value if 0
+    pub step: f32,
+    /// Values a drag snaps to when it comes this close to them, in pixels
+    pub snap_points: Vec<f32>,
+    pub snap: f32,
+    /// Between redraws while dragging
+    pub frame: Duration,
+    pub on_change: Option<ValueFunction>,
+    value: f32,
+    /// The value last drawn
+    shown: f32,
+    grab: Option<Grab>,
+    /// Whether it was grabbed or let go since it was last drawn
+    regrabbed: bool,
+    last_frame: Option<Instant>,
+}
+
+impl Slider {
+    pub fn new(size: cgmath::Vector2<u32>, orientation: Orientation, min: f32, max: f32) -> Slider {
+        Slider {
+            size,
+            orientation,
+            min,
+            max,
+            step: 0.0,
+            snap_points: Vec::new(),
+            snap: 20.0,
+            frame: Duration::from_millis(150),
+            on_change: None,
+            value: min,
+            shown: min,
+            grab: None,
+            regrabbed: false,
+            last_frame: None,
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Sets the value, as close to `value` as `min`, `max` and `step` allow
+    pub fn set_value(&mut self, value: f32) {
+        self.value = constrain(value, self.min, self.max, self.step);
+    }
+
+    /// Whether a finger or the pen is dragging it
+    pub fn is_grabbed(&self) -> bool {
+        self.grab.is_some()
+    }
+
+    /// The value last drawn
+    pub(crate) fn shown(&self) -> f32 {
+        self.shown
+    }
+
+    fn radius(&self) -> f32 {
+        let across = match self.orientation {
+            Orientation::Horizontal => self.size.y,
+            Orientation::Vertical => self.size.x,
+        };
+        across as f32 * 0.3
+    }
+
+    /// From where the thumb is at `min` to where it is at `max`, for the slider at
+    /// `rect`
+    fn track(&self, rect: mxcfb_rect) -> (cgmath::Point2<f32>, cgmath::Point2<f32>) {
+        let r = self.radius();
+        let (left, top) = (rect.left as f32, rect.top as f32);
+        let (right, bottom) = (left + rect.width as f32, top + rect.height as f32);
+        match self.orientation {
+            Orientation::Horizontal => {
+                let y = (top + bottom) / 2.0;
+                ((left + r, y).into(), (right - r, y).into())
+            }
+            Orientation::Vertical => {
+                let x = (left + right) / 2.0;
+                ((x, bottom - r).into(), (x, top + r).into())
+            }
+        }
+    }
+
+    /// Where the thumb is for `value` on the slider at `rect`
+    fn point_of(&self, value: f32, rect: mxcfb_rect) -> cgmath::Point2<f32> {
+        let (start, end) = self.track(rect);
+        let range = self.max - self.min;
+        let fraction = match range > 0.0 {
+            true => ((value - self.min) / range).clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        start + (end - start) * fraction
+    }
+
+    /// The value for the thumb at `pos` on the slider at `rect`, snapped
+    pub fn value_at(&self, pos: cgmath::Point2<f32>, rect: mxcfb_rect) -> f32 {
+        let (start, end) = self.track(rect);
+        let track = end - start;
+        let fraction = match track.magnitude2() > 0.0 {
+            true => ((pos - start).dot(track) / track.magnitude2()).clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        let value = self.min + fraction * (self.max - self.min);
+        let point = self.point_of(value, rect);
+        let snapped = self
+            .snap_points
+            .iter()
+            .map(|snap| (*snap, (self.point_of(*snap, rect) - point).magnitude()))
+            .filter(|(_, distance)| *distance <= self.snap)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match snapped {
+            Some((snap, _)) => snap.clamp(self.min, self.max.max(self.min)),
+            None => constrain(value, self.min, self.max, self.step),
+        }
+    }
+
+    /// Whether to redraw at `now`, at most once per `frame` unless `now` is the start
+    /// or the end of the drag
+    fn frame_due(&mut self, now: Instant, last: bool) -> bool {
+        let due = (self.value != self.shown || self.regrabbed)
+            && (last || self.last_frame.is_none_or(|at| now >= at + self.frame));
+        if due {
+            self.last_frame = Some(now);
+        }
+        due
+    }
+
+    /// Moves the thumb to `pos` if it is held by `grab`, or grabs it there if
+    /// nothing holds it and `pos` is on the slider at `rect`
+    fn drag(&mut self, grab: Grab, pos: cgmath::Point2<f32>, rect: mxcfb_rect) -> bool {
+        match self.grab {
+            Some(held) if held != grab => return false,
+            Some(_) => {}
+            None if rect.contains_point(&pos.cast().unwrap()) => {
+                self.grab = Some(grab);
+                self.regrabbed = true;
+            }
+            None => return false,
+        }
+        self.value = self.value_at(pos, rect);
+        true
+    }
+
+    fn release(&mut self, grab: Grab, now: Instant) -> bool {
+        if self.grab != Some(grab) {
+            return false;
+        }
+        self.grab = None;
+        self.regrabbed = true;
+        self.frame_due(now, true)
+    }
+
+    /// Follows a touch at `now` on the slider at `rect`. Returns whether the slider
+    /// should be redrawn.
+    pub fn handle_multitouch(
+        &mut self,
+        event: &MultitouchEvent,
+        rect: mxcfb_rect,
+        now: Instant,
+    ) -> bool {
+        let finger = match event.finger() {
+            Some(finger) => finger,
+            None => return false,
+        };
+        let grab = Grab::Finger(finger.tracking_id);
+        let pos = finger.pos.cast().unwrap();
+        match event {
+            MultitouchEvent::Press { .. } | MultitouchEvent::Move { .. } => {
+                let grabbing = self.grab.is_none();
+                self.drag(grab, pos, rect) && self.frame_due(now, grabbing)
+            }
+            MultitouchEvent::Release { .. } => self.release(grab, now),
+            MultitouchEvent::Unknown => false,
+        }
+    }
+
+    /// Follows the pen at `now` on the slider at `rect`, like `handle_multitouch`
+    pub fn handle_wacom(&mut self, event: &WacomEvent, rect: mxcfb_rect, now: Instant) -> bool {
+        match *event {
+            WacomEvent::Draw { position, .. } => {
+                let grabbing = self.grab.is_none();
+                self.drag(Grab::Pen, position, rect) && self.frame_due(now, grabbing)
+            }
+            WacomEvent::InstrumentChange {
+                pen: WacomPen::Touch,
+                state: false,
+            }
+            | WacomEvent::Hover { .. } => self.release(Grab::Pen, now),
+            _ => false,
+        }
+    }
+
+    pub fn draw(&mut self, fb: &mut Framebuffer, position: cgmath::Point2<i32>) -> mxcfb_rect {
+        self.shown = self.value;
+        self.regrabbed = false;
+        let rect = mxcfb_rect::from(position.cast().unwrap(), self.size);
+        fb.fill_rect(position, self.size, color::WHITE);
+        let (start, end) = self.track(rect);
+        let thumb = self.point_of(self.value, rect);
+        let r = self.radius();
+        let line =
+            |fb: &mut Framebuffer, from: cgmath::Point2<f32>, to: cgmath::Point2<f32>, width| {
+                fb.draw_line(
+                    from.cast().unwrap(),
+                    to.cast().unwrap(),
+                    width,
+                    color::BLACK,
+                );
+            };
+        // Thin past the thumb, thick up to it
+        line(fb, thumb, end, LINE);
+        line(fb, start, thumb, LINE * 3);
+        let across = match self.orientation {
+            Orientation::Horizontal => cgmath::Vector2 { x: 0.0, y: r },
+            Orientation::Vertical => cgmath::Vector2 { x: r, y: 0.0 },
+        };
+        for snap in &self.snap_points {
+            let point = self.point_of(*snap, rect);
+            line(fb, point - across, point + across, LINE);
+        }
+        // Hollow while dragged
+        let center = thumb.cast().unwrap();
+        fb.fill_circle(center, r as u32, color::BLACK);
+        if self.is_grabbed() {
+            fb.fill_circle(center, (r as u32).saturating_sub(LINE * 2), color::WHITE);
+        }
+        rect
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Stepper {
+    pub size: cgmath::Vector2<u32>,
+    pub orientation: Orientation,
+    pub min: f32,
+    pub max: f32,
+    /// What a tap adds or takes away
+    pub step: f32,
+    /// Of the value shown
+    pub scale: f32,
+    pub on_change: Option<ValueFunction>,
+    value: f32,
+    shown: f32,
+    /// The finger on a button, and whether that is the one counting up
+    pressed: Option<(i32, bool)>,
+}
+
+impl Stepper {
+    pub fn new(
+        size: cgmath::Vector2<u32>,
+        orientation: Orientation,
+        min: f32,
+        max: f32,
+        step: f32,
+    ) -> Stepper {
+        Stepper {
+            size,
+            orientation,
+            min,
+            max,
+            step,
+            scale: size.x.min(size.y) as f32 * 0.5,
+            on_change: None,
+            value: min,
+            shown: min,
+            pressed: None,
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Sets the value, as close to `value` as `min`, `max` and `step` allow
+    pub fn set_value(&mut self, value: f32) {
+        self.value = constrain(value, self.min, self.max, self.step);
+    }
+
+    /// The value last drawn
+    pub(crate) fn shown(&self) -> f32 {
+        self.shown
+    }
+
+    /// The buttons counting down and up, for the stepper at `rect`
+    fn buttons(&self, rect: mxcfb_rect) -> (mxcfb_rect, mxcfb_rect) {
+        match self.orientation {
+            Orientation::Horizontal => {
+                let side = rect.height.min(rect.width / 2);
+                let down = mxcfb_rect {
+                    width: side,
+                    ..rect
+                };
+                let up = mxcfb_rect {
+                    left: rect.left + rect.width - side,
+                    width: side,
+                    ..rect
+                };
+                (down, up)
+            }
+            Orientation::Vertical => {
+                let side = rect.width.min(rect.height / 2);
+                let up = mxcfb_rect {
+                    height: side,
+                    ..rect
+                };
+                let down = mxcfb_rect {
+                    top: rect.top + rect.height - side,
+                    height: side,
+                    ..rect
+                };
+                (down, up)
+            }
+        }
+    }
+
+    /// Follows a touch on the stepper at `rect`, counting once a finger lifts on a
+    /// button. Returns whether the stepper should be redrawn.
+    pub fn handle_multitouch(&mut self, event: &MultitouchEvent, rect: mxcfb_rect) -> bool {
+        let finger = match event.finger() {
+            Some(finger) => finger,
+            None => return false,
+        };
+        let (down, up) = self.buttons(rect);
+        let pos = finger.pos.cast().unwrap();
+        let on = |up_pressed| match up_pressed {
+            true => up.contains_point(&pos),
+            false => down.contains_point(&pos),
+        };
+        match (event, self.pressed) {
+            (MultitouchEvent::Press { .. }, None) => {
+                self.pressed = match (down.contains_point(&pos), up.contains_point(&pos)) {
+                    (true, _) => Some((finger.tracking_id, false)),
+                    (_, true) => Some((finger.tracking_id, true)),
+                    _ => None,
+                };
+                self.pressed.is_some()
+            }
+            (MultitouchEvent::Move { .. }, Some((id, up_pressed)))
+                if id == finger.tracking_id && !on(up_pressed) =>
+            {
+                self.pressed = None;
+                true
+            }
+            (MultitouchEvent::Release { .. }, Some((id, up_pressed)))
+                if id == finger.tracking_id =>
+            {
+                self.pressed = None;
+                if on(up_pressed) {
+                    let step = if up_pressed { self.step } else { -self.step };
+                    self.set_value(self.value + step);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn draw(&mut self, fb: &mut Framebuffer, position: cgmath::Point2<i32>) -> mxcfb_rect {
+        self.shown = self.value;
+        let rect = mxcfb_rect::from(position.cast().unwrap(), self.size);
+        fb.fill_rect(position, self.size, color::WHITE);
+        let (down, up) = self.buttons(rect);
+        for (button, counts_up) in [(down, false), (up, true)] {
+            let pos = button.top_left().cast().unwrap();
+            // Pressed buttons turn black
+            let foreground = match self.pressed {
+                Some((_, pressed)) if pressed == counts_up => {
+                    fb.fill_rect(pos, button.size(), color::BLACK);
+                    color::WHITE
+                }
+                _ => color::BLACK,
+            };
+            fb.draw_rect(pos, button.size(), LINE, color::BLACK);
+            let center = cgmath::Point2 {
+                x: (button.left + button.width / 2) as i32,
+                y: (button.top + button.height / 2) as i32,
+            };
+            let arm = (button.width.min(button.height) / 4) as i32;
+            fb.draw_line(
+                center - cgmath::Vector2 { x: arm, y: 0 },
+                center + cgmath::Vector2 { x: arm, y: 0 },
+                LINE,
+                foreground,
+            );
+            if counts_up {
+                fb.draw_line(
+                    center - cgmath::Vector2 { x: 0, y: arm },
+                    center + cgmath::Vector2 { x: 0, y: arm },
+                    LINE,
+                    foreground,
+                );
+            }
+        }
+        self.draw_value(fb, rect);
+        rect
+    }
+
+    #[cfg(feature = "framebuffer-text-drawing")]
+    fn draw_value(&self, fb: &mut Framebuffer, rect: mxcfb_rect) {
+        // As many decimals as the step has
+        let decimals = match self.step > 0.0 && self.step.fract() != 0.0 {
+            true => (-self.step.fract().log10()).ceil().max(0.0) as usize,
+            false => 0,
+        };
+        let text = format!("{:.*}", decimals, self.value);
+        let size = self.scale;
+        let measured = fb.draw_text(
+            cgmath::Point2 { x: 0.0, y: size },
+            &text,
+            size,
+            color::BLACK,
+            true,
+        );
+        let pos = cgmath::Point2 {
+            x: rect.left as f32 + (rect.width as f32 - measured.width as f32) / 2.0,
+            // The baseline, so that the digits are centered
+            y: rect.top as f32 + (rect.height as f32 + size * 0.6) / 2.0,
+        };
+        fb.draw_text(pos, &text, size, color::BLACK, false);
+    }
+
+    #[cfg(not(feature = "framebuffer-text-drawing"))]
+    fn draw_value(&self, _fb: &mut Framebuffer, _rect: mxcfb_rect) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{moved, press, release, touch};
+
+    #[test]
+    fn test_slider() {
+        // The track goes from 30 to 330
+        let rect = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 360,
+            height: 100,
+        };
+        let mut slider = Slider::new(rect.size(), Orientation::Horizontal, 0.0, 100.0);
+        slider.step = 5.0;
+        slider.snap_points = vec![42.0];
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(!slider.handle_multitouch(&touch(press, 1, 200, 200), rect, start));
+        assert!(slider.handle_multitouch(&touch(press, 1, 90, 50), rect, at(0)));
+        assert_eq!(slider.value(), 20.0);
+        let mut fb = Framebuffer::headless(400, 100);
+        slider.draw(&mut fb, rect.top_left().cast().unwrap());
+        // Redrawn at most once a frame, stepping or snapping
+        assert!(!slider.handle_multitouch(&touch(moved, 1, 100, 50), rect, at(50)));
+        assert_eq!(slider.value(), 25.0);
+        assert!(slider.handle_multitouch(&touch(moved, 1, 160, 50), rect, at(200)));
+        assert_eq!(slider.value(), 42.0);
+        slider.draw(&mut fb, rect.top_left().cast().unwrap());
+        // Let go, it is drawn once more
+        assert!(slider.handle_multitouch(&touch(release, 1, 160, 50), rect, at(210)));
+        assert!(!slider.is_grabbed());
+
+        // The pen drags it, too, up to the end
+        let pen = |x| WacomEvent::Draw {
+            position: cgmath::Point2 { x, y: 50.0 },
+            pressure: 1000,
+            tilt: (0, 0).into(),
+            tool: crate::input::WacomTool::Pen,
+            buttons: Default::default(),
+        };
+        assert!(slider.handle_wacom(&pen(300.0), rect, at(300)));
+        slider.handle_wacom(&pen(500.0), rect, at(500));
+        assert_eq!(slider.value(), 100.0);
+
+        let mut vertical = Slider::new(
+            cgmath::Vector2 { x: 100, y: 360 },
+            Orientation::Vertical,
+            1.0,
+            11.0,
+        );
+        let rect = mxcfb_rect::from(cgmath::Point2 { x: 0, y: 0 }, vertical.size);
+        assert_eq!(vertical.value_at((50.0, 30.0).into(), rect), 11.0);
+        assert_eq!(vertical.value_at((50.0, 180.0).into(), rect), 6.0);
+        vertical.set_value(20.0);
+        assert_eq!(vertical.value(), 11.0);
+    }
+
+    #[test]
+    fn test_stepper() {
+        let rect = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 300,
+            height: 100,
+        };
+        let mut stepper = Stepper::new(rect.size(), Orientation::Horizontal, 0.0, 1.0, 0.25);
+        assert!(stepper.handle_multitouch(&touch(press, 1, 250, 50), rect));
+        assert!(stepper.handle_multitouch(&touch(release, 1, 250, 50), rect));
+        assert_eq!(stepper.value(), 0.25);
+        // Sliding off a button doesn't count
+        stepper.handle_multitouch(&touch(press, 1, 250, 50), rect);
+        assert!(stepper.handle_multitouch(&touch(moved, 1, 150, 50), rect));
+        assert!(!stepper.handle_multitouch(&touch(release, 1, 150, 50), rect));
+        assert_eq!(stepper.value(), 0.25);
+        for _ in 0..3 {
+            stepper.handle_multitouch(&touch(press, 1, 50, 50), rect);
+            stepper.handle_multitouch(&touch(release, 1, 50, 50), rect);
+        }
+        assert_eq!(stepper.value(), 0.0);
+    }
+}
@@ -0,0 +1,450 @@
+//! A single line text field.
+//!
+//! `TextInput` is the state of a `UIElement::TextInput`: the text, the cursor and the
+//! selection, edited by `KeyboardEvent`s from a hardware keyboard or an
+//! `OnScreenKeyboard` alike. Text too long for the field scrolls horizontally to keep
+//! the cursor in view, and taps place it with `place_cursor`.
+//! `ApplicationContext::type_into` passes keyboard events to a field by the name of
+//! its element, redraws it and calls its `on_change` and `on_submit` handlers.
+
+use std::ops::Range;
+
+use evdev::Key;
+use rusttype::{point, Scale};
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::FramebufferDraw;
+use crate::input::KeyboardEvent;
+use crate::ui_extensions::element::ActiveRegionFunction;
+
+/// Between the border and the text
+const PADDING: u32 = 8;
+const BORDER: u32 = 2;
+const CURSOR_WIDTH: u32 = 2;
+
+/// What a keyboard event did to a `TextInput`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextInputEvent {
+    /// The cursor or the selection moved, the text is the same
+    Moved,
+    Changed,
+    /// Enter was pressed
+    Submitted,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextInput {
+    /// Of the field, including its border
+    pub size: cgmath::Vector2<u32>,
+    pub scale: f32,
+    /// Called after the text changed
+    pub on_change: Option<ActiveRegionFunction>,
+    /// Called when enter is pressed
+    pub on_submit: Option<ActiveRegionFunction>,
+    text: String,
+    /// A byte offset into `text`, on a character boundary like `anchor`
+    cursor: usize,
+    /// Where the selection started, it ends at the cursor
+    anchor: Option<usize>,
+    /// How far the text is scrolled to the left, in pixels
+    scroll: f32,
+    focused: bool,
+}
+
+impl TextInput {
+    pub fn new(size: cgmath::Vector2<u32>, scale: f32) -> TextInput {
+        TextInput {
+            size,
+            scale,
+            on_change: None,
+            on_submit: None,
+            text: String::new(),
+            cursor: 0,
+            anchor: None,
+            scroll: 0.0,
+            focused: false,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text, with the cursor at its end
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_owned();
+        self.cursor = self.text.len();
+        self.anchor = None;
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Focused fields have a thicker border and show the cursor
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// The byte offset of the cursor
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Moves the cursor to the character boundary at or before `cursor`, dropping the
+    /// selection
+    pub fn set_cursor(&mut self, cursor: usize) {
+        self.cursor = self.floor(cursor);
+        self.anchor = None;
+    }
+
+    /// The byte range selected, if any
+    pub fn selection(&self) -> Option<Range<usize>> {
+        let anchor = self.anchor?;
+        match anchor.cmp(&self.cursor) {
+            std::cmp::Ordering::Less => Some(anchor..self.cursor),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some(self.cursor..anchor),
+        }
+    }
+
+    /// Selects `range`, with the cursor at its end
+    pub fn select(&mut self, range: Range<usize>) {
+        self.anchor = Some(self.floor(range.start));
+        self.cursor = self.floor(range.end);
+    }
+
+    pub fn select_all(&mut self) {
+        self.select(0..self.text.len());
+    }
+
+    pub fn selected_text(&self) -> &str {
+        self.selection().map_or("", |range| &self.text[range])
+    }
+
+    fn floor(&self, mut offset: usize) -> usize {
+        offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    fn previous(&self, offset: usize) -> usize {
+        self.text[..offset]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next(&self, offset: usize) -> usize {
+        self.text[offset..]
+            .chars()
+            .next()
+            .map_or(offset, |c| offset + c.len_utf8())
+    }
+
+    /// Removes the selection, returning whether there was one
+    fn delete_selection(&mut self) -> bool {
+        let range = match self.selection() {
+            Some(range) => range,
+            None => {
+                self.anchor = None;
+                return false;
+            }
+        };
+        self.cursor = range.start;
+        self.text.replace_range(range, "");
+        self.anchor = None;
+        true
+    }
+
+    /// Replaces the selection, if any, with `text`
+    pub fn insert(&mut self, text: &str) {
+        self.delete_selection();
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Deletes the selection or the character before the cursor, like backspace.
+    /// Returns whether anything was deleted.
+    pub fn delete_backward(&mut self) -> bool {
+        if self.delete_selection() {
+            return true;
+        }
+        if self.cursor == 0 {
+            return false;
+        }
+        let start = self.previous(self.cursor);
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+        true
+    }
+
+    /// Deletes the selection or the character after the cursor. Returns whether
+    /// anything was deleted.
+    pub fn delete_forward(&mut self) -> bool {
+        if self.delete_selection() {
+            return true;
+        }
+        let end = self.next(self.cursor);
+        if end == self.cursor {
+            return false;
+        }
+        self.text.replace_range(self.cursor..end, "");
+        true
+    }
+
+    /// Moves the cursor to `to`, extending the selection if `select` is set
+    fn move_to(&mut self, to: usize, select: bool) {
+        if select {
+            self.anchor.get_or_insert(self.cursor);
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = to;
+    }
+
+    /// Edits the text as `event` says. Returns what that did, `None` if it was ignored.
+    pub fn handle_key(&mut self, event: &KeyboardEvent) -> Option<TextInputEvent> {
+        match *event {
+            KeyboardEvent::Text { character: '\n' } => Some(TextInputEvent::Submitted),
+            KeyboardEvent::Text { character } if !character.is_control() => {
+                self.insert(character.encode_utf8(&mut [0; 4]));
+                Some(TextInputEvent::Changed)
+            }
+            KeyboardEvent::Press { key, modifiers, .. } => {
+                let select = modifiers.shift;
+                let (to, changed) = match Key::new(key) {
+                    Key::KEY_BACKSPACE => (None, self.delete_backward()),
+                    Key::KEY_DELETE => (None, self.delete_forward()),
+                    Key::KEY_A if modifiers.ctrl => {
+                        self.select_all();
+                        return Some(TextInputEvent::Moved);
+                    }
+                    Key::KEY_LEFT => match self.selection() {
+                        Some(range) if !select => (Some(range.start), false),
+                        _ => (Some(self.previous(self.cursor)), false),
+                    },
+                    Key::KEY_RIGHT => match self.selection() {
+                        Some(range) if !select => (Some(range.end), false),
+                        _ => (Some(self.next(self.cursor)), false),
+                    },
+                    Key::KEY_HOME => (Some(0), false),
+                    Key::KEY_END => (Some(self.text.len()), false),
+                    _ => return None,
+                };
+                if let Some(to) = to {
+                    self.move_to(to, select);
+                    Some(TextInputEvent::Moved)
+                } else if changed {
+                    Some(TextInputEvent::Changed)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// How wide the first `len` bytes of the text are
+    fn measure(&self, fb: &Framebuffer, len: usize) -> f32 {
+        fb.fonts()
+            .layout(
+                &self.text[..len],
+                Scale::uniform(self.scale),
+                point(0.0, 0.0),
+            )
+            .last()
+            .map_or(0.0, |g| {
+                g.position().x + g.unpositioned().h_metrics().advance_width
+            })
+    }
+
+    /// Moves the cursor to the character boundary closest to a tap `x` pixels right of
+    /// the left edge of the field, as it was last drawn
+    pub fn place_cursor(&mut self, fb: &Framebuffer, x: f32) {
+        let x = x - (BORDER + PADDING) as f32 + self.scroll;
+        let boundaries = self
+            .text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(self.text.len()));
+        let closest = boundaries.min_by(|&a, &b| {
+            let a = (self.measure(fb, a) - x).abs();
+            let b = (self.measure(fb, b) - x).abs();
+            a.total_cmp(&b)
+        });
+        self.set_cursor(closest.unwrap_or(0));
+    }
+
+    /// Draws the field with its top left corner at `position`. Returns the area drawn.
+    pub fn draw(&mut self, fb: &mut Framebuffer, position: cgmath::Point2<i32>) -> mxcfb_rect {
+        let rect = mxcfb_rect::from(position.cast().unwrap(), self.size);
+        fb.fill_rect(position, self.size, color::WHITE);
+        let border = if self.focused { BORDER * 2 } else { BORDER };
+        fb.draw_rect(position, self.size, border, color::BLACK);
+        let inset = BORDER + PADDING;
+        if self.size.x <= 2 * inset || self.size.y <= 2 * inset {
+            return rect;
+        }
+        let inner = mxcfb_rect {
+            left: rect.left + inset,
+            top: rect.top + inset,
+            width: rect.width - 2 * inset,
+            height: rect.height - 2 * inset,
+        };
+
+        // Scroll just enough to show the cursor, and no further than the end
+        let cursor = self.measure(fb, self.cursor);
+        let total = self.measure(fb, self.text.len());
+        let width = (inner.width - CURSOR_WIDTH) as f32;
+        self.scroll = self
+            .scroll
+            .min(cursor)
+            .max(cursor - width)
+            .min((total - width).max(0.0));
+
+        fb.push_clip(inner);
+        let x = |offset: f32| inner.left as f32 - self.scroll + offset;
+        let baseline = inner.top as f32 + (inner.height as f32 + self.scale * 0.6) / 2.0;
+        fb.draw_text(
+            cgmath::Point2 {
+                x: x(0.0),
+                y: baseline,
+            },
+            &self.text,
+            self.scale,
+            color::BLACK,
+            false,
+        );
+        match self.selection() {
+            Some(range) => {
+                let start = self.measure(fb, range.start);
+                let end = self.measure(fb, range.end);
+                fb.fill_rect(
+                    cgmath::Point2 {
+                        x: x(start) as i32,
+                        y: inner.top as i32,
+                    },
+                    cgmath::Vector2 {
+                        x: (end - start).ceil() as u32,
+                        y: inner.height,
+                    },
+                    color::BLACK,
+                );
+                fb.draw_text(
+                    cgmath::Point2 {
+                        x: x(start),
+                        y: baseline,
+                    },
+                    &self.text[range],
+                    self.scale,
+                    color::WHITE,
+                    false,
+                );
+            }
+            None if self.focused => fb.fill_rect(
+                cgmath::Point2 {
+                    x: x(cursor) as i32,
+                    y: inner.top as i32,
+                },
+                cgmath::Vector2 {
+                    x: CURSOR_WIDTH,
+                    y: inner.height,
+                },
+                color::BLACK,
+            ),
+            None => {}
+        }
+        fb.pop_clip();
+        rect
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::Modifiers;
+
+    fn press(input: &mut TextInput, key: Key, modifiers: Modifiers) -> Option<TextInputEvent> {
+        input.handle_key(&KeyboardEvent::Press {
+            key: key.code(),
+            modifiers,
+            repeat: false,
+        })
+    }
+
+    #[test]
+    fn test_text_input() {
+        let mut input = TextInput::new(cgmath::Vector2 { x: 200, y: 60 }, 30.0);
+        for character in "héllo".chars() {
+            assert_eq!(
+                input.handle_key(&KeyboardEvent::Text { character }),
+                Some(TextInputEvent::Changed)
+            );
+        }
+        assert_eq!(input.text(), "héllo");
+        assert_eq!(input.cursor(), "héllo".len());
+
+        let none = Modifiers::default();
+        let shift = Modifiers {
+            shift: true,
+            ..none
+        };
+        // Selecting "llo" and typing over it
+        press(&mut input, Key::KEY_LEFT, shift);
+        press(&mut input, Key::KEY_LEFT, shift);
+        press(&mut input, Key::KEY_LEFT, shift);
+        assert_eq!(input.selected_text(), "llo");
+        input.handle_key(&KeyboardEvent::Text { character: 'y' });
+        assert_eq!(input.text(), "héy");
+
+        // Backspace over the multibyte character
+        press(&mut input, Key::KEY_LEFT, none);
+        assert_eq!(
+            press(&mut input, Key::KEY_BACKSPACE, none),
+            Some(TextInputEvent::Changed)
+        );
+        assert_eq!(input.text(), "hy");
+        press(&mut input, Key::KEY_HOME, none);
+        assert_eq!(press(&mut input, Key::KEY_BACKSPACE, none), None);
+        press(&mut input, Key::KEY_DELETE, none);
+        assert_eq!(input.text(), "y");
+
+        let ctrl = Modifiers { ctrl: true, ..none };
+        input.set_text("hello world");
+        press(&mut input, Key::KEY_A, ctrl);
+        assert_eq!(input.selected_text(), "hello world");
+        press(&mut input, Key::KEY_BACKSPACE, none);
+        assert_eq!(input.text(), "");
+        assert_eq!(
+            input.handle_key(&KeyboardEvent::Text { character: '\n' }),
+            Some(TextInputEvent::Submitted)
+        );
+        assert_eq!(
+            input.handle_key(&KeyboardEvent::Text { character: '\t' }),
+            None
+        );
+
+        // Text too long for the field scrolls to keep the cursor in view
+        let mut fb = Framebuffer::headless(300, 100);
+        input.set_text(&"m".repeat(40));
+        let rect = input.draw(&mut fb, cgmath::Point2 { x: 10, y: 10 });
+        assert_eq!(rect.width, 200);
+        assert!(input.scroll > 0.0);
+        press(&mut input, Key::KEY_HOME, none);
+        input.draw(&mut fb, cgmath::Point2 { x: 10, y: 10 });
+        assert_eq!(input.scroll, 0.0);
+
+        // Tapping places the cursor between the characters
+        input.set_text("mmmm");
+        let m = input.measure(&fb, 1);
+        input.place_cursor(&fb, (BORDER + PADDING) as f32 + m * 2.2);
+        assert_eq!(input.cursor(), 2);
+        input.place_cursor(&fb, 0.0);
+        assert_eq!(input.cursor(), 0);
+    }
+}
@@ -0,0 +1,448 @@
+//! A paged document viewer, for anything that can render its pages as pictures.
+//!
+//! PDF, EPUB or image sequence readers implement `PageSource` and leave paging,
+//! zooming and caching to `DocumentViewer`. Swiping turns the page, pinching zooms
+//! and dragging moves a zoomed in page around. Gestures are applied once the fingers
+//! are lifted, so the page is re-rendered once at the new scale instead of the
+//! display trying to keep up with every move.
+
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::framebuffer::cgmath;
+use crate::framebuffer::cgmath::InnerSpace;
+use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::{FramebufferDraw, FramebufferIO};
+use crate::input::MultitouchEvent;
+
+/// Height of the progress bar at the bottom of the viewer
+const PROGRESS_HEIGHT: u32 = 6;
+/// How far a finger has to travel sideways to turn the page
+const SWIPE_DISTANCE: f32 = 150.0;
+
+/// A page rendered in grayscale, 0 being black
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderedPage {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row, one byte per pixel
+    pub pixels: Vec<u8>,
+}
+
+/// The pages of a document
+pub trait PageSource {
+    fn page_count(&self) -> usize;
+    /// The size of `page` at a scale of 1
+    fn page_size(&self, page: usize) -> cgmath::Vector2<f32>;
+    /// Renders `page` at `scale`, `None` if that failed
+    fn render(&mut self, page: usize, scale: f32) -> Option<RenderedPage>;
+}
+
+struct CacheEntry {
+    page: RenderedPage,
+    last_used: u64,
+}
+
+/// What the fingers on the viewer did so far
+#[derive(Default)]
+struct Gesture {
+    fingers: HashMap<i32, cgmath::Point2<f32>>,
+    /// Where the first finger went down
+    start: Option<cgmath::Point2<f32>>,
+    /// Centroid and spread of the fingers when the second went down, and their
+    /// spread when last seen
+    pinch: Option<(cgmath::Point2<f32>, f32, f32)>,
+}
+
+impl Gesture {
+    fn spread(&self) -> Option<(cgmath::Point2<f32>, f32)> {
+        let mut ids: Vec<_> = self.fingers.keys().copied().collect();
+        ids.sort_unstable();
+        match ids[..] {
+            [a, b, ..] => {
+                let (pa, pb) = (self.fingers[&a], self.fingers[&b]);
+                let centroid = cgmath::Point2 {
+                    x: (pa.x + pb.x) / 2.0,
+                    y: (pa.y + pb.y) / 2.0,
+                };
+                Some((centroid, (pb - pa).magnitude()))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct DocumentViewer<S: PageSource> {
+    source: S,
+    /// Where on the screen the viewer is
+    pub rect: mxcfb_rect,
+    page: usize,
+    /// 1 fits the page into the viewer
+    zoom: f32,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// Top left of the shown part of a page larger than the viewer, in rendered pixels
+    offset: cgmath::Vector2<f32>,
+    cache: HashMap<(usize, u32), CacheEntry>,
+    cached: usize,
+    clock: u64,
+    /// Maximum number of bytes of rendered pages kept
+    pub cache_budget: usize,
+    gesture: Gesture,
+}
+
+impl<S: PageSource> DocumentViewer<S> {
+    pub fn new(source: S, rect: mxcfb_rect) -> DocumentViewer<S> {
+        DocumentViewer {
+            source,
+            rect,
+            page: 0,
+            zoom: 1.0,
+            min_zoom: 1.0,
+            max_zoom: 4.0,
+            offset: cgmath::Vector2 { x: 0.0, y: 0.0 },
+            cache: HashMap::new(),
+            cached: 0,
+            clock: 0,
+            cache_budget: 16 * 1024 * 1024,
+            gesture: Gesture::default(),
+        }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.source.page_count()
+    }
+
+    /// Shows `page`, at the zoom of the current one. Returns false if there is no such
+    /// page or it is shown already.
+    pub fn go_to(&mut self, page: usize) -> bool {
+        if page >= self.page_count() || page == self.page {
+            return false;
+        }
+        self.page = page;
+        self.offset = cgmath::Vector2 { x: 0.0, y: 0.0 };
+        true
+    }
+
+    pub fn next_page(&mut self) -> bool {
+        self.go_to(self.page + 1)
+    }
+
+    pub fn previous_page(&mut self) -> bool {
+        self.page > 0 && self.go_to(self.page - 1)
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Zooms keeping `anchor`, a screen position, in place. Returns false if the zoom
+    /// didn't change.
+    pub fn set_zoom(&mut self, zoom: f32, anchor: cgmath::Point2<f32>) -> bool {
+        let zoom = zoom.clamp(self.min_zoom, self.max_zoom);
+        if zoom == self.zoom {
+            return false;
+        }
+        let ratio = zoom / self.zoom;
+        let anchor = cgmath::Vector2 {
+            x: anchor.x - self.rect.left as f32,
+            y: anchor.y - self.rect.top as f32,
+        };
+        self.offset = (self.offset + anchor) * ratio - anchor;
+        self.zoom = zoom;
+        true
+    }
+
+    /// Follows the fingers on the viewer. Returns true once a gesture changed the page
+    /// or zoom, and the viewer needs to be drawn again.
+    pub fn handle_multitouch(&mut self, event: &MultitouchEvent) -> bool {
+        let finger = match event.finger() {
+            Some(finger) => *finger,
+            None => return false,
+        };
+        let pos = cgmath::Point2 {
+            x: f32::from(finger.pos.x),
+            y: f32::from(finger.pos.y),
+        };
+        let gesture = &mut self.gesture;
+        match event {
+            MultitouchEvent::Press { .. } => {
+                gesture.fingers.insert(finger.tracking_id, pos);
+                gesture.start.get_or_insert(pos);
+                if gesture.pinch.is_none() {
+                    if let Some((centroid, spread)) = gesture.spread() {
+                        gesture.pinch = Some((centroid, spread, spread));
+                    }
+                }
+                false
+            }
+            MultitouchEvent::Move { .. } => {
+                gesture.fingers.insert(finger.tracking_id, pos);
+                let spread = gesture.spread();
+                if let (Some(pinch), Some((_, spread))) = (gesture.pinch.as_mut(), spread) {
+                    pinch.2 = spread;
+                }
+                false
+            }
+            MultitouchEvent::Release { .. } => {
+                gesture.fingers.remove(&finger.tracking_id);
+                if !gesture.fingers.is_empty() {
+                    return false;
+                }
+                let start = gesture.start.take();
+                match gesture.pinch.take() {
+                    Some((centroid, from, to)) if from > 0.0 => {
+                        self.set_zoom(self.zoom * to / from, centroid)
+                    }
+                    Some(_) => false,
+                    None => self.swipe(pos - start.unwrap_or(pos)),
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn swipe(&mut self, delta: cgmath::Vector2<f32>) -> bool {
+        if self.zoom > 1.0 {
+            self.offset -= delta;
+            return delta.magnitude() > 0.0;
+        }
+        if delta.x.abs() < SWIPE_DISTANCE || delta.x.abs() < delta.y.abs() {
+            return false;
+        }
+        match delta.x < 0.0 {
+            true => self.next_page(),
+            false => self.previous_page(),
+        }
+    }
+
+    /// The scale the current page is rendered at
+    fn scale(&self, page: usize) -> f32 {
+        let size = self.source.page_size(page);
+        let fit = (self.rect.width as f32 / size.x.max(1.0))
+            .min(self.rect.height.saturating_sub(PROGRESS_HEIGHT) as f32 / size.y.max(1.0));
+        fit * self.zoom
+    }
+
+    /// Renders `page` into the cache unless it is there already
+    fn render(&mut self, page: usize) -> Option<(usize, u32)> {
+        let scale = self.scale(page);
+        let key = (page, (scale * 1000.0).round() as u32);
+        self.clock += 1;
+        if let Some(entry) = self.cache.get_mut(&key) {
+            entry.last_used = self.clock;
+            return Some(key);
+        }
+        let rendered = match self.source.render(page, scale) {
+            Some(rendered)
+                if rendered.pixels.len() == (rendered.width * rendered.height) as usize =>
+            {
+                rendered
+            }
+            Some(_) => {
+                warn!("Page {} was rendered with the wrong number of pixels", page);
+                return None;
+            }
+            None => {
+                warn!("Failed to render page {}", page);
+                return None;
+            }
+        };
+        self.cached += rendered.pixels.len();
+        self.cache.insert(
+            key,
+            CacheEntry {
+                page: rendered,
+                last_used: self.clock,
+            },
+        );
+        // Least recently used first, never the page just rendered
+        while self.cached > self.cache_budget && self.cache.len() > 1 {
+            let oldest = *self
+                .cache
+                .iter()
+                .filter(|(k, _)| **k != key)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .unwrap()
+                .0;
+            let evicted = self.cache.remove(&oldest).unwrap();
+            self.cached -= evicted.page.pixels.len();
+        }
+        Some(key)
+    }
+
+    /// Renders the pages next to the current one at its zoom, so turning to them is
+    /// quick. Best called while the app is idle.
+    pub fn prefetch(&mut self) {
+        for page in [self.page + 1, self.page.wrapping_sub(1)] {
+            if page < self.page_count() {
+                self.render(page);
+            }
+        }
+    }
+
+    /// Draws the current page and the progress bar. Returns the area to refresh.
+    pub fn draw(&mut self, fb: &mut Framebuffer) -> mxcfb_rect {
+        let rect = self.rect;
+        let area = mxcfb_rect {
+            height: rect.height.saturating_sub(PROGRESS_HEIGHT),
+            ..rect
+        };
+        fb.fill_rect(rect.top_left().cast().unwrap(), rect.size(), color::WHITE);
+
+        if let Some(key) = self.render(self.page) {
+            let page = &self.cache[&key].page;
+            // Pages smaller than the viewer are centered, larger ones show `offset`
+            let place = |size: u32, space: u32, offset: &mut f32| {
+                if size <= space {
+                    *offset = 0.0;
+                    ((space - size) / 2, 0, size)
+                } else {
+                    *offset = offset.clamp(0.0, (size - space) as f32);
+                    (0, offset.round() as u32, space)
+                }
+            };
+            let (x, from_x, width) = place(page.width, area.width, &mut self.offset.x);
+            let (y, from_y, height) = place(page.height, area.height, &mut self.offset.y);
+
+            let mut pixels = Vec::with_capacity((width * height * 2) as usize);
+            for row in from_y..from_y + height {
+                let start = (row * page.width + from_x) as usize;
+                for &luma in &page.pixels[start..start + width as usize] {
+                    pixels.extend_from_slice(&color::RGB(luma, luma, luma).as_native());
+                }
+            }
+            let shown = mxcfb_rect {
+                left: area.left + x,
+                top: area.top + y,
+                width,
+                height,
+            };
+            if let Err(err) = fb.restore_region(shown, &pixels) {
+                warn!("Failed to draw page {}: {}", self.page, err);
+            }
+        }
+
+        let count = self.page_count().max(1) as u32;
+        let progress = rect.width * (self.page as u32 + 1) / count;
+        fb.fill_rect(
+            cgmath::Point2 {
+                x: rect.left as i32,
+                y: (rect.top + area.height) as i32,
+            },
+            cgmath::Vector2 {
+                x: progress,
+                y: PROGRESS_HEIGHT.min(rect.height),
+            },
+            color::BLACK,
+        );
+        rect
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::Finger;
+
+    /// Pages as light as their number is high, counting what it renders
+    struct Pages {
+        renders: usize,
+    }
+
+    impl PageSource for Pages {
+        fn page_count(&self) -> usize {
+            3
+        }
+
+        fn page_size(&self, _page: usize) -> cgmath::Vector2<f32> {
+            (50.0, 100.0).into()
+        }
+
+        fn render(&mut self, page: usize, scale: f32) -> Option<RenderedPage> {
+            self.renders += 1;
+            let (width, height) = ((50.0 * scale) as u32, (100.0 * scale) as u32);
+            Some(RenderedPage {
+                width,
+                height,
+                pixels: vec![page as u8 * 100; (width * height) as usize],
+            })
+        }
+    }
+
+    /// A finger's id, and where it goes down and moves to
+    type Touch = (i32, (u16, u16), (u16, u16));
+
+    fn touch(viewer: &mut DocumentViewer<Pages>, fingers: &[Touch]) {
+        let finger = |id, pos| Finger::new(id, pos, true);
+        for &(id, from, _) in fingers {
+            let finger = finger(id, from.into());
+            assert!(!viewer.handle_multitouch(&MultitouchEvent::Press { finger }));
+        }
+        for &(id, _, to) in fingers {
+            let finger = finger(id, to.into());
+            assert!(!viewer.handle_multitouch(&MultitouchEvent::Move { finger }));
+        }
+    }
+
+    fn release(viewer: &mut DocumentViewer<Pages>, id: i32, pos: (u16, u16)) -> bool {
+        let finger = Finger::new(id, pos.into(), false);
+        viewer.handle_multitouch(&MultitouchEvent::Release { finger })
+    }
+
+    #[test]
+    fn test_viewer() {
+        let mut fb = Framebuffer::headless(300, 300);
+        let rect = mxcfb_rect {
+            left: 0,
+            top: 0,
+            width: 200,
+            height: 200 + PROGRESS_HEIGHT,
+        };
+        let mut viewer = DocumentViewer::new(Pages { renders: 0 }, rect);
+        viewer.draw(&mut fb);
+        // Fit to 100x200 and centered
+        let inside = fb.read_pixel((100, 100).into()).as_native();
+        assert_eq!(inside, color::BLACK.as_native());
+        assert_eq!(
+            fb.read_pixel((20, 100).into()).as_native(),
+            color::WHITE.as_native()
+        );
+
+        // Swiping to the left turns the page, which was prefetched
+        viewer.prefetch();
+        assert_eq!(viewer.source().renders, 2);
+        touch(&mut viewer, &[(1, (250, 100), (60, 110))]);
+        assert!(release(&mut viewer, 1, (60, 110)));
+        assert_eq!(viewer.page(), 1);
+        viewer.draw(&mut fb);
+        assert_eq!(viewer.source().renders, 2);
+        assert_ne!(fb.read_pixel((100, 100).into()).as_native(), inside);
+
+        // Pinching out twice as far zooms in, with a single render
+        touch(
+            &mut viewer,
+            &[(1, (80, 100), (60, 100)), (2, (120, 100), (140, 100))],
+        );
+        assert!(!release(&mut viewer, 1, (60, 100)));
+        assert!(release(&mut viewer, 2, (140, 100)));
+        assert_eq!(viewer.zoom(), 2.0);
+        viewer.draw(&mut fb);
+        assert_eq!(viewer.source().renders, 3);
+
+        // Dragging a zoomed in page doesn't turn it
+        touch(&mut viewer, &[(3, (250, 100), (50, 100))]);
+        assert!(release(&mut viewer, 3, (50, 100)));
+        assert_eq!(viewer.page(), 1);
+    }
+}
@@ -0,0 +1,277 @@
+//! Physical units, so things keep their size on displays of other resolutions.
+//!
+//! `Units` converts points (1/72 inch) and millimeters to display pixels and back,
+//! for the DPI of the device in use. Layout can be done in points, input positions
+//! converted to points, and with `framebuffer-drawing`, `LogicalDraw` draws in points
+//! onto any `FramebufferDraw`. Everything else stays in pixels, so this is optional.
+
+use cgmath::{Point2, Vector2};
+use once_cell::sync::Lazy;
+
+pub const POINTS_PER_INCH: f32 = 72.0;
+pub const MILLIMETERS_PER_INCH: f32 = 25.4;
+
+/// The DPI of the rM1 and rM2, used when the model can't be determined
+pub const DEFAULT_DPI: f32 = 226.0;
+
+static CURRENT: Lazy<Units> = Lazy::new(|| {
+    Units::new(
+        crate::device::Model::current_model()
+            .map(|model| model.dpi())
+            .unwrap_or(DEFAULT_DPI),
+    )
+});
+
+/// A length in any unit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Length {
+    Px(f32),
+    Pt(f32),
+    Mm(f32),
+}
+
+/// Converts between physical units and the pixels of a display
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Units {
+    pub dpi: f32,
+}
+
+impl Default for Units {
+    fn default() -> Units {
+        Units::current()
+    }
+}
+
+impl Units {
+    pub const fn new(dpi: f32) -> Units {
+        Units { dpi }
+    }
+
+    /// The units of the display of the device in use
+    pub fn current() -> Units {
+        *CURRENT
+    }
+
+    /// `length` in pixels
+    pub fn px(&self, length: Length) -> f32 {
+        match length {
+            Length::Px(px) => px,
+            Length::Pt(pt) => self.pt(pt),
+            Length::Mm(mm) => self.mm(mm),
+        }
+    }
+
+    /// `pt` points in pixels
+    pub fn pt(&self, pt: f32) -> f32 {
+        pt * self.dpi / POINTS_PER_INCH
+    }
+
+    /// `mm` millimeters in pixels
+    pub fn mm(&self, mm: f32) -> f32 {
+        mm * self.dpi / MILLIMETERS_PER_INCH
+    }
+
+    /// `px` pixels in points
+    pub fn to_pt(&self, px: f32) -> f32 {
+        px * POINTS_PER_INCH / self.dpi
+    }
+
+    /// `px` pixels in millimeters
+    pub fn to_mm(&self, px: f32) -> f32 {
+        px * MILLIMETERS_PER_INCH / self.dpi
+    }
+
+    /// A position in points as the nearest pixel
+    pub fn point(&self, pt: Point2<f32>) -> Point2<i32> {
+        Point2 {
+            x: self.pt(pt.x).round() as i32,
+            y: self.pt(pt.y).round() as i32,
+        }
+    }
+
+    /// A size in points as whole pixels
+    pub fn size(&self, pt: Vector2<f32>) -> Vector2<u32> {
+        Vector2 {
+            x: self.pt(pt.x).round().max(0.0) as u32,
+            y: self.pt(pt.y).round().max(0.0) as u32,
+        }
+    }
+
+    /// A position in pixels, e.g. of input, in points
+    pub fn point_to_pt(&self, px: Point2<f32>) -> Point2<f32> {
+        Point2 {
+            x: self.to_pt(px.x),
+            y: self.to_pt(px.y),
+        }
+    }
+
+    /// The area in pixels covered by `size` points at `pos` points
+    #[cfg(feature = "framebuffer-types")]
+    pub fn rect(
+        &self,
+        pos: Point2<f32>,
+        size: Vector2<f32>,
+    ) -> crate::framebuffer::common::mxcfb_rect {
+        let pos = self.point(pos);
+        crate::framebuffer::common::mxcfb_rect::from(
+            Point2 {
+                x: pos.x.max(0) as u32,
+                y: pos.y.max(0) as u32,
+            },
+            self.size(size),
+        )
+    }
+
+    /// Where the pen or finger of `event` is, in points
+    #[cfg(feature = "input-types")]
+    pub fn input_position(&self, event: &crate::input::InputEvent) -> Option<Point2<f32>> {
+        use crate::input::{InputEvent, WacomEvent};
+
+        let px = match event {
+            InputEvent::WacomEvent {
+                event: WacomEvent::Draw { position, .. } | WacomEvent::Hover { position, .. },
+            } => *position,
+            InputEvent::MultitouchEvent { event } => event.finger()?.pos.cast()?,
+            _ => return None,
+        };
+        Some(self.point_to_pt(px))
+    }
+
+    /// Draws onto `fb` in points
+    #[cfg(feature = "framebuffer-drawing")]
+    pub fn draw<'a, F: crate::framebuffer::FramebufferDraw + ?Sized>(
+        &self,
+        fb: &'a mut F,
+    ) -> LogicalDraw<'a, F> {
+        LogicalDraw { fb, units: *self }
+    }
+}
+
+#[cfg(feature = "framebuffer-drawing")]
+pub use self::draw::LogicalDraw;
+
+#[cfg(feature = "framebuffer-drawing")]
+mod draw {
+    use super::Units;
+    use crate::framebuffer::common::{color, mxcfb_rect};
+    use crate::framebuffer::FramebufferDraw;
+    use cgmath::{Point2, Vector2};
+
+    /// Drawing with positions and sizes in points. The returned areas to refresh are
+    /// in pixels, like everything passed to the framebuffer.
+    pub struct LogicalDraw<'a, F: FramebufferDraw + ?Sized> {
+        pub fb: &'a mut F,
+        pub units: Units,
+    }
+
+    impl<F: FramebufferDraw + ?Sized> LogicalDraw<'_, F> {
+        /// Lines stay at least a pixel wide
+        fn width(&self, pt: f32) -> u32 {
+            self.units.pt(pt).round().max(1.0) as u32
+        }
+
+        pub fn draw_line(
+            &mut self,
+            start: Point2<f32>,
+            end: Point2<f32>,
+            width: f32,
+            c: color,
+        ) -> mxcfb_rect {
+            let width = self.width(width);
+            let (start, end) = (self.units.point(start), self.units.point(end));
+            self.fb.draw_line(start, end, width, c)
+        }
+
+        pub fn draw_circle(&mut self, pos: Point2<f32>, rad: f32, c: color) -> mxcfb_rect {
+            let rad = self.width(rad);
+            let pos = self.units.point(pos);
+            self.fb.draw_circle(pos, rad, c)
+        }
+
+        pub fn fill_circle(&mut self, pos: Point2<f32>, rad: f32, c: color) -> mxcfb_rect {
+            let rad = self.width(rad);
+            let pos = self.units.point(pos);
+            self.fb.fill_circle(pos, rad, c)
+        }
+
+        pub fn draw_rect(
+            &mut self,
+            pos: Point2<f32>,
+            size: Vector2<f32>,
+            border: f32,
+            c: color,
+        ) -> mxcfb_rect {
+            let rect = self.units.rect(pos, size);
+            let border = self.width(border);
+            self.fb
+                .draw_rect(self.units.point(pos), self.units.size(size), border, c);
+            rect
+        }
+
+        pub fn fill_rect(&mut self, pos: Point2<f32>, size: Vector2<f32>, c: color) -> mxcfb_rect {
+            let rect = self.units.rect(pos, size);
+            self.fb
+                .fill_rect(self.units.point(pos), self.units.size(size), c);
+            rect
+        }
+
+        /// Draws `text` with its baseline starting at `pos`, `size` points high
+        #[cfg(feature = "framebuffer-text-drawing")]
+        pub fn draw_text(
+            &mut self,
+            pos: Point2<f32>,
+            text: &str,
+            size: f32,
+            c: color,
+            dryrun: bool,
+        ) -> mxcfb_rect {
+            let pos = Point2 {
+                x: self.units.pt(pos.x),
+                y: self.units.pt(pos.y),
+            };
+            let size = self.units.pt(size);
+            self.fb.draw_text(pos, text, size, c, dryrun)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let units = Units::new(144.0);
+        assert_eq!(units.pt(72.0), 144.0);
+        assert_eq!(units.px(Length::Mm(25.4)), 144.0);
+        assert_eq!(units.px(Length::Px(3.0)), 3.0);
+        assert_eq!(units.to_pt(units.pt(12.5)), 12.5);
+        assert!((units.to_mm(units.mm(7.0)) - 7.0).abs() < 1e-4);
+        assert_eq!(units.point((10.0, -0.3).into()), Point2 { x: 20, y: -1 });
+        assert_eq!(units.size((0.7, -2.0).into()), Vector2 { x: 1, y: 0 });
+
+        // The same physical size is more pixels at a higher DPI
+        let denser = Units::new(288.0);
+        assert_eq!(denser.mm(10.0), units.mm(10.0) * 2.0);
+    }
+
+    #[cfg(feature = "framebuffer-drawing")]
+    #[test]
+    fn test_logical_draw() {
+        use crate::framebuffer::common::color;
+        use crate::framebuffer::core::Framebuffer;
+        use crate::framebuffer::FramebufferIO;
+
+        let mut fb = Framebuffer::headless(200, 200);
+        let units = Units::new(144.0);
+        let rect =
+            units
+                .draw(&mut fb)
+                .fill_rect((10.0, 20.0).into(), (5.0, 5.0).into(), color::BLACK);
+        assert_eq!(rect, units.rect((10.0, 20.0).into(), (5.0, 5.0).into()));
+        assert_eq!((rect.left, rect.top, rect.width), (20, 40, 10));
+        let black = color::BLACK.as_native();
+        assert_eq!(fb.read_pixel((25, 45).into()).as_native(), black);
+        assert_ne!(fb.read_pixel((15, 45).into()).as_native(), black);
+    }
+}
@@ -0,0 +1,369 @@
+//! Notebooks of xochitl, the stock reMarkable UI.
+//!
+//! xochitl keeps every document as a set of files named after its id in
+//! `DEFAULT_DIR`: `<id>.metadata` and `<id>.content` in JSON, and a directory
+//! `<id>` with a `.rm` file of strokes per page. With the `image` feature, page
+//! thumbnails are written to `<id>.thumbnails` as well.
+//!
+//! xochitl only reads the store when it starts, so it has to be restarted to show
+//! what was written, e.g. with `systemctl restart xochitl`. Pages are written in
+//! version 5 of the `.rm` format, which all releases since 2.0 read.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde_json::{json, Value};
+
+use crate::stroke::Stroke;
+
+/// Where xochitl keeps its documents
+pub const DEFAULT_DIR: &str = "/home/root/.local/share/remarkable/xochitl";
+
+const LINES_HEADER: &[u8; 43] = b"reMarkable .lines file, version=5          ";
+/// The fineliner of the firmware 2.x pens, drawing strokes as they are
+const BRUSH_FINELINER: i32 = 17;
+/// The medium size of the pens
+const BRUSH_SIZE: f32 = 2.0;
+
+/// Why reading or writing a notebook failed
+#[derive(Debug, thiserror::Error)]
+pub enum XochitlError {
+    #[error("Failed to access the document store")]
+    Io(#[from] io::Error),
+    #[error("Malformed document {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("There is no notebook {0}")]
+    NotFound(String),
+    #[cfg(feature = "image")]
+    #[error("Failed to write a thumbnail")]
+    Thumbnail(#[from] image::ImageError),
+}
+
+/// Writes `strokes` as a single layer in the `.rm` format
+pub fn write_lines<W: Write>(out: &mut W, strokes: &[Stroke]) -> io::Result<()> {
+    let mut data = LINES_HEADER.to_vec();
+    let word = |data: &mut Vec<u8>, bytes: [u8; 4]| data.extend_from_slice(&bytes);
+    word(&mut data, 1i32.to_le_bytes());
+    word(&mut data, (strokes.len() as i32).to_le_bytes());
+    for stroke in strokes {
+        // Black, gray and white are all the pens know
+        let [r, g, b] = stroke.color.to_rgb8();
+        let luma = (u32::from(r) * 3 + u32::from(g) * 6 + u32::from(b)) / 10;
+        let color = match luma {
+            0..=84 => 0i32,
+            85..=169 => 1,
+            _ => 2,
+        };
+        word(&mut data, BRUSH_FINELINER.to_le_bytes());
+        word(&mut data, color.to_le_bytes());
+        word(&mut data, 0i32.to_le_bytes());
+        word(&mut data, BRUSH_SIZE.to_le_bytes());
+        word(&mut data, 0f32.to_le_bytes());
+        word(&mut data, (stroke.points.len() as i32).to_le_bytes());
+        for (i, point) in stroke.points.iter().enumerate() {
+            let direction = match stroke.points.get(i + 1) {
+                Some(next) => {
+                    (next.position.y - point.position.y).atan2(next.position.x - point.position.x)
+                }
+                None => 0.0,
+            };
+            word(&mut data, point.position.x.to_le_bytes());
+            word(&mut data, point.position.y.to_le_bytes());
+            word(&mut data, 0f32.to_le_bytes());
+            word(&mut data, direction.to_le_bytes());
+            word(&mut data, stroke.width_at(i).to_le_bytes());
+            word(&mut data, point.pressure.to_le_bytes());
+        }
+    }
+    out.write_all(&data)
+}
+
+/// A random version 4 UUID, which xochitl uses as ids
+fn new_id() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// Milliseconds since the epoch, as a string like xochitl writes them
+fn now() -> String {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis())
+        .to_string()
+}
+
+/// The documents of xochitl
+pub struct DocumentStore {
+    dir: PathBuf,
+}
+
+impl Default for DocumentStore {
+    fn default() -> DocumentStore {
+        DocumentStore::new(DEFAULT_DIR)
+    }
+}
+
+impl DocumentStore {
+    /// The store in `dir`
+    pub fn new(dir: impl AsRef<Path>) -> DocumentStore {
+        DocumentStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, id: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, extension))
+    }
+
+    fn read_json(&self, id: &str, extension: &str) -> Result<Value, XochitlError> {
+        match fs::read(self.path(id, extension)) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(XochitlError::NotFound(id.to_owned()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Written next to it and renamed, so xochitl never sees half of it
+    fn write_file(&self, path: PathBuf, data: &[u8]) -> io::Result<()> {
+        let partial = path.with_extension("partial");
+        fs::write(&partial, data)?;
+        fs::rename(&partial, path)
+    }
+
+    fn write_json(&self, id: &str, extension: &str, value: &Value) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(value).map_err(io::Error::from)?;
+        self.write_file(self.path(id, extension), &data)
+    }
+
+    /// The id of the notebook shown as `name`, if there is one that isn't deleted
+    pub fn find(&self, name: &str) -> Result<Option<String>, XochitlError> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let id = match (path.extension(), path.file_stem()) {
+                (Some(ext), Some(id)) if ext == "metadata" => id.to_string_lossy().into_owned(),
+                _ => continue,
+            };
+            let metadata = self.read_json(&id, "metadata")?;
+            if metadata["visibleName"] == name
+                && metadata["type"] == "DocumentType"
+                && metadata["deleted"] != true
+            {
+                let content = self.read_json(&id, "content")?;
+                if content["fileType"] == "notebook" {
+                    return Ok(Some(id));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Creates a notebook shown as `name` at the top level, with a page per entry of
+    /// `pages`. Returns its id.
+    pub fn create_notebook(
+        &self,
+        name: &str,
+        pages: &[Vec<Stroke>],
+    ) -> Result<String, XochitlError> {
+        let id = new_id()?;
+        fs::create_dir_all(self.dir.join(&id))?;
+        let mut page_ids = Vec::new();
+        for strokes in pages {
+            page_ids.push(self.write_page(&id, strokes)?);
+        }
+        let content = json!({
+            "fileType": "notebook",
+            "orientation": "portrait",
+            "pageCount": page_ids.len(),
+            "pages": page_ids,
+        });
+        self.write_json(&id, "content", &content)?;
+        // Written last, xochitl ignores documents without it
+        let metadata = json!({
+            "deleted": false,
+            "lastModified": now(),
+            "metadatamodified": false,
+            "modified": false,
+            "parent": "",
+            "pinned": false,
+            "synced": false,
+            "type": "DocumentType",
+            "version": 0,
+            "visibleName": name,
+        });
+        self.write_json(&id, "metadata", &metadata)?;
+        Ok(id)
+    }
+
+    /// Adds a page with `strokes` at the end of the notebook `id`. Returns the id of
+    /// the page.
+    pub fn append_page(&self, id: &str, strokes: &[Stroke]) -> Result<String, XochitlError> {
+        let mut content = self.read_json(id, "content")?;
+        if content["fileType"] != "notebook" {
+            return Err(XochitlError::NotFound(id.to_owned()));
+        }
+        fs::create_dir_all(self.dir.join(id))?;
+        let page = self.write_page(id, strokes)?;
+
+        match content.get_mut("pages").and_then(Value::as_array_mut) {
+            Some(pages) => pages.push(json!(page)),
+            None => content["pages"] = json!([page]),
+        }
+        // Newer releases order pages by `cPages`, with ids that sort like the pages
+        if let Some(pages) = content
+            .pointer_mut("/cPages/pages")
+            .and_then(Value::as_array_mut)
+        {
+            let last = pages
+                .iter()
+                .filter_map(|p| p.pointer("/idx/value").and_then(Value::as_str))
+                .max()
+                .unwrap_or("a")
+                .to_owned();
+            pages.push(json!({
+                "id": page,
+                "idx": { "timestamp": "1:2", "value": format!("{}a", last) },
+            }));
+        }
+        let count = content["pages"].as_array().map_or(0, Vec::len);
+        content["pageCount"] = json!(count);
+        self.write_json(id, "content", &content)?;
+
+        let mut metadata = self.read_json(id, "metadata")?;
+        metadata["lastModified"] = json!(now());
+        metadata["metadatamodified"] = json!(true);
+        metadata["modified"] = json!(true);
+        self.write_json(id, "metadata", &metadata)?;
+        Ok(page)
+    }
+
+    fn write_page(&self, id: &str, strokes: &[Stroke]) -> Result<String, XochitlError> {
+        let page = new_id()?;
+        let dir = self.dir.join(id);
+        let mut lines = Vec::new();
+        write_lines(&mut lines, strokes)?;
+        self.write_file(dir.join(format!("{}.rm", page)), &lines)?;
+        let layers = json!({ "layers": [{ "name": "Layer 1" }] });
+        let layers = serde_json::to_vec(&layers)?;
+        self.write_file(dir.join(format!("{}-metadata.json", page)), &layers)?;
+        #[cfg(feature = "image")]
+        {
+            let thumbnails = self.dir.join(format!("{}.thumbnails", id));
+            fs::create_dir_all(&thumbnails)?;
+            thumbnail(strokes).save(thumbnails.join(format!("{}.png", page)))?;
+        }
+        Ok(page)
+    }
+}
+
+/// The page scaled down to the size of xochitl's thumbnails
+#[cfg(feature = "image")]
+fn thumbnail(strokes: &[Stroke]) -> image::GrayImage {
+    use crate::dimensions::DISPLAYWIDTH;
+
+    let (width, height) = (280u32, 374u32);
+    let scale = width as f32 / f32::from(DISPLAYWIDTH);
+    let mut image = image::GrayImage::from_pixel(width, height, image::Luma([255]));
+    let mut dot = |x: f32, y: f32, radius: f32, luma: u8| {
+        let r = radius.ceil() as i32;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let (px, py) = ((x + dx as f32) as i32, (y + dy as f32) as i32);
+                let inside = (dx * dx + dy * dy) as f32 <= radius * radius + 0.5;
+                if inside && px >= 0 && py >= 0 && (px as u32) < width && (py as u32) < height {
+                    image.put_pixel(px as u32, py as u32, image::Luma([luma]));
+                }
+            }
+        }
+    };
+    for stroke in strokes {
+        let [r, g, b] = stroke.color.to_rgb8();
+        let luma = ((u32::from(r) * 3 + u32::from(g) * 6 + u32::from(b)) / 10) as u8;
+        for (i, pair) in stroke.points.windows(2).enumerate() {
+            let (from, to) = (pair[0].position * scale, pair[1].position * scale);
+            let radius = (stroke.width_at(i) * scale / 2.0).max(0.5);
+            let steps = ((to - from).x.abs().max((to - from).y.abs()) * 2.0)
+                .ceil()
+                .max(1.0);
+            for step in 0..=steps as u32 {
+                let t = step as f32 / steps;
+                dot(
+                    from.x + (to.x - from.x) * t,
+                    from.y + (to.y - from.y) * t,
+                    radius,
+                    luma,
+                );
+            }
+        }
+        if let [point] = stroke.points.as_slice() {
+            let radius = (stroke.width_at(0) * scale / 2.0).max(0.5);
+            dot(
+                point.position.x * scale,
+                point.position.y * scale,
+                radius,
+                luma,
+            );
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::common::color;
+    use crate::stroke::StrokePoint;
+
+    #[test]
+    fn test_notebook() {
+        let dir = std::env::temp_dir().join(format!("xochitl-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = DocumentStore::new(&dir);
+        let stroke = Stroke::new(
+            vec![
+                StrokePoint::new(100.0, 100.0),
+                StrokePoint::new(300.0, 400.0),
+            ],
+            4.0,
+            color::BLACK,
+        );
+
+        let id = store
+            .create_notebook("Sketches", &[vec![stroke.clone()]])
+            .unwrap();
+        assert_eq!(store.find("Sketches").unwrap().as_deref(), Some(&id[..]));
+        assert_eq!(store.find("Other").unwrap(), None);
+        let page = store.append_page(&id, &[stroke.clone(), stroke]).unwrap();
+
+        let content = store.read_json(&id, "content").unwrap();
+        assert_eq!(content["pageCount"], 2);
+        assert_eq!(content["pages"][1], page.as_str());
+        assert_eq!(store.read_json(&id, "metadata").unwrap()["modified"], true);
+        let lines = fs::read(dir.join(&id).join(format!("{}.rm", page))).unwrap();
+        assert!(lines.starts_with(LINES_HEADER));
+        // Layers and lines, then per line 6 words and 2 points of 6 words
+        assert_eq!(lines.len(), 43 + 8 + 2 * (24 + 2 * 24));
+        #[cfg(feature = "image")]
+        assert!(dir.join(format!("{}.thumbnails/{}.png", id, page)).exists());
+
+        assert!(matches!(
+            store.append_page("missing", &[]),
+            Err(XochitlError::NotFound(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
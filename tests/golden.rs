@@ -0,0 +1,72 @@
+//! Renders drawing primitives into a headless framebuffer and compares them against
+//! the reference images in `tests/golden`. Run with `LIBREMARKABLE_BLESS=1` to update
+//! the references after an intended change.
+#![cfg(all(feature = "framebuffer-text-drawing", feature = "image"))]
+
+use libremarkable::framebuffer::cgmath::{Point2, Vector2};
+use libremarkable::framebuffer::common::{color, mxcfb_rect};
+use libremarkable::framebuffer::core::Framebuffer;
+use libremarkable::framebuffer::golden::Golden;
+use libremarkable::framebuffer::FramebufferDraw;
+
+const SIZE: u32 = 200;
+
+fn golden() -> Golden {
+    Golden::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden"))
+}
+
+fn region() -> mxcfb_rect {
+    mxcfb_rect {
+        top: 0,
+        left: 0,
+        width: SIZE,
+        height: SIZE,
+    }
+}
+
+#[test]
+fn primitives() {
+    let mut fb = Framebuffer::headless(SIZE, SIZE);
+    fb.draw_line(Point2::new(10, 10), Point2::new(190, 60), 3, color::BLACK);
+    fb.draw_circle(Point2::new(60, 120), 40, color::BLACK);
+    fb.fill_circle(Point2::new(150, 120), 25, color::GRAY(0x80));
+    fb.draw_rect(Point2::new(20, 165), Vector2::new(160, 25), 2, color::BLACK);
+    fb.draw_polygon(
+        &[
+            Point2::new(100, 80),
+            Point2::new(130, 100),
+            Point2::new(110, 150),
+        ],
+        true,
+        color::BLACK,
+    );
+    fb.draw_bezier(
+        Point2::new(20.0, 100.0),
+        Point2::new(100.0, 0.0),
+        Point2::new(180.0, 100.0),
+        2.0,
+        50,
+        color::BLACK,
+    );
+    golden().assert_matches("primitives", &fb, region());
+}
+
+#[test]
+fn text() {
+    let mut fb = Framebuffer::headless(SIZE, SIZE);
+    fb.draw_text(
+        Point2::new(10.0, 60.0),
+        "libremarkable",
+        30.0,
+        color::BLACK,
+        false,
+    );
+    fb.draw_text(
+        Point2::new(10.0, 150.0),
+        "Ag 123",
+        60.0,
+        color::BLACK,
+        false,
+    );
+    golden().assert_matches("text", &fb, region());
+}